@@ -0,0 +1,100 @@
+// Converting a full FIFO drain (1024 bytes, 73 frames) at once versus one sample at a time, the way `GY521::read` does it.
+// Run with `cargo bench --bench fifo`
+#![feature(test)]
+
+extern crate test;
+
+use njord::gy521::fifo::{BatchConverter, FifoLayout, Scaling};
+use njord::gy521::SensorSample;
+use njord::math::{Mat3, Vec3D};
+use test::{black_box, Bencher};
+
+const FIFO_SIZE: usize = 1024; // [bytes]
+
+fn fifo() -> Vec<u8> {
+    (0..FIFO_SIZE).map(|n| (n * 37 % 251) as u8).collect()
+}
+
+fn scalings() -> (Scaling, Scaling) {
+    let matrix = Mat3::from_rows(
+        &Vec3D::new(1.01, 0.002, -0.001),
+        &Vec3D::new(0.0, 0.99, 0.003),
+        &Vec3D::new(0.001, 0.0, 1.02),
+    );
+    let accelerometer = Scaling {
+        scale_factor: 16_384.0,
+        matrix,
+        offset: Vec3D::new(0.01, -0.02, 0.03),
+    };
+    let gyroscope = Scaling {
+        scale_factor: 131.0,
+        matrix,
+        offset: Vec3D::new(-1.2, 0.4, 0.7),
+    };
+    (accelerometer, gyroscope)
+}
+
+// One frame per burst read, as in `GY521::read_raw` and `GY521::read`
+fn convert_each(
+    bytes: &[u8],
+    accelerometer: &Scaling,
+    gyroscope: &Scaling,
+) -> Vec<SensorSample<Vec3D, f64>> {
+    let frame_size = FifoLayout::ALL.frame_size();
+    let mut samples = Vec::new();
+    for frame in bytes.chunks_exact(frame_size) {
+        let data = frame.to_vec();
+        let word = |index: usize| i16::from_be_bytes([data[2 * index], data[2 * index + 1]]);
+        let acceleration = Vec3D::new(word(0), word(1), word(2));
+        let temperature = word(3) as f64 / 340.0 + 36.53;
+        let angular_velocity = Vec3D::new(word(4), word(5), word(6));
+        samples.push(SensorSample::new(
+            accelerometer.matrix * (acceleration / accelerometer.scale_factor)
+                + accelerometer.offset,
+            gyroscope.matrix * (angular_velocity / gyroscope.scale_factor) + gyroscope.offset,
+            Some(temperature),
+        ));
+    }
+    samples
+}
+
+#[bench]
+fn one_at_a_time(bencher: &mut Bencher) {
+    let bytes = fifo();
+    let (accelerometer, gyroscope) = scalings();
+    bencher.iter(|| convert_each(black_box(&bytes), &accelerometer, &gyroscope));
+}
+
+#[bench]
+fn batch(bencher: &mut Bencher) {
+    let bytes = fifo();
+    let (accelerometer, gyroscope) = scalings();
+    let mut converter =
+        BatchConverter::new(FifoLayout::ALL, &accelerometer, &gyroscope, 340.0, 36.53);
+    let mut samples = Vec::with_capacity(FIFO_SIZE);
+    bencher.iter(|| {
+        samples.clear();
+        converter.convert(black_box(&bytes), &mut samples)
+    });
+}
+
+#[test]
+fn batch_matches_one_at_a_time() {
+    let bytes = fifo();
+    let (accelerometer, gyroscope) = scalings();
+    let mut converter =
+        BatchConverter::new(FifoLayout::ALL, &accelerometer, &gyroscope, 340.0, 36.53);
+    let mut samples = Vec::new();
+    converter.convert(&bytes, &mut samples);
+    let reference = convert_each(&bytes, &accelerometer, &gyroscope);
+    assert_eq!(samples.len(), reference.len());
+    for (sample, reference) in samples.iter().zip(&reference) {
+        assert!(sample
+            .acceleration()
+            .approx_eq(&reference.acceleration(), 1e-12));
+        assert!(sample
+            .angular_velocity()
+            .approx_eq(&reference.angular_velocity(), 1e-9));
+        assert!((sample.temperature().unwrap() - reference.temperature().unwrap()).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,15 @@
+// Generates the gRPC service and client from proto/njord.proto, with the grpc feature
+fn main() -> std::io::Result<()> {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "grpc")]
+    {
+        // The protoc in PROTOC, if set, or else the one bundled for the machine building, so that none needs to be installed
+        if std::env::var_os("PROTOC").is_none() {
+            if let Ok(protoc) = protoc_bin_vendored::protoc_bin_path() {
+                std::env::set_var("PROTOC", protoc);
+            }
+        }
+        tonic_build::compile_protos("proto/njord.proto")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,431 @@
+// Outputs driven by the crate, e.g., a rudder servo for a heading-hold controller, or the ESC of a thruster.
+// Servos and ESCs take a pulse every 20 ms or so, whose width, usually from 1 ms to 2 ms, sets the position or throttle. The pulses are held to limits, whatever is commanded, and fall back to a failsafe when commands stop coming, e.g., because the controller hung.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use rppal::{
+    gpio::OutputPin,
+    pwm::{Channel, Polarity, Pwm},
+};
+
+pub mod buzzer;
+pub mod led;
+
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(20); // Longest the failsafe is late, and the watchdog takes to notice being stopped
+
+/// Where the pulses come from
+pub enum PwmOutput {
+    Hardware(Pwm), // PWM0 on GPIO 12 or 18, or PWM1 on GPIO 13 or 19, once enabled in /boot/config.txt, e.g., with dtoverlay=pwm
+    Software(OutputPin), // Any pin, timed by a thread. Its jitter of some 10 µs makes servos twitch, so hardware PWM is the better choice where available
+}
+
+impl PwmOutput {
+    /// Hardware PWM on {channel}, without pulses until set
+    pub fn hardware(channel: Channel) -> Result<Self> {
+        let pwm = Pwm::with_period(
+            channel,
+            Duration::from_millis(20),
+            Duration::ZERO,
+            Polarity::Normal,
+            false,
+        )
+        .context("Unable to set up hardware PWM.")?;
+        Ok(Self::Hardware(pwm))
+    }
+
+    /// Pulses of {pulse_width} every {period}
+    pub fn set(&mut self, period: Duration, pulse_width: Duration) -> Result<()> {
+        match self {
+            Self::Hardware(pwm) => {
+                // The pulse can't be wider than the period, at any point in between
+                if pulse_width > pwm.period()? {
+                    pwm.set_period(period)?;
+                    pwm.set_pulse_width(pulse_width)?;
+                } else {
+                    pwm.set_pulse_width(pulse_width)?;
+                    pwm.set_period(period)?;
+                }
+                pwm.enable()?;
+            }
+            Self::Software(pin) => pin.set_pwm(period, pulse_width)?,
+        }
+        Ok(())
+    }
+
+    /// No more pulses, with the output low. Servos go limp, and ESCs stop the motor
+    pub fn stop(&mut self) -> Result<()> {
+        match self {
+            Self::Hardware(pwm) => pwm.disable()?,
+            Self::Software(pin) => {
+                pin.clear_pwm()?;
+                pin.set_low();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Narrowest and widest pulse an output is ever set to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PulseLimits {
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl PulseLimits {
+    pub fn clamp(&self, pulse_width: Duration) -> Duration {
+        pulse_width.clamp(self.min, self.max)
+    }
+}
+
+impl Default for PulseLimits {
+    /// 1 ms to 2 ms, which every servo and ESC takes. Many servos go further, but how far differs, and running into the end stops can break them
+    fn default() -> Self {
+        Self {
+            min: Duration::from_micros(1000),
+            max: Duration::from_micros(2000),
+        }
+    }
+}
+
+/// What happens when no command comes within the timeout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Failsafe {
+    Hold, // The last pulse width
+    #[default]
+    Neutral, // Center for servos, zero throttle for ESCs
+    Off,  // No more pulses
+}
+
+/// How a `Servo` or an `Esc` is driven
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServoConfig {
+    pub period: Duration, // 20 ms (50 Hz) for analog servos. Digital servos and most ESCs take down to 2.5 ms (400 Hz)
+    pub limits: PulseLimits,
+    pub neutral: Duration, // Pulse width of the center position, or of zero throttle for ESCs
+    pub timeout: Option<Duration>, // Longest time between commands before the failsafe kicks in. None never does
+    pub failsafe: Failsafe,
+}
+
+impl ServoConfig {
+    /// 50 Hz, 1 ms to 2 ms around 1.5 ms, back to center after 1 s without commands
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 50 Hz, 1 ms to 2 ms, with zero throttle at 1 ms, after 0.5 s without commands
+    pub fn esc() -> Self {
+        Self {
+            neutral: Duration::from_micros(1000),
+            timeout: Some(Duration::from_millis(500)),
+            ..Self::default()
+        }
+    }
+
+    // Pulse width for {position}, from -1 at the narrowest pulse, through 0 at neutral, to 1 at the widest, within the limits
+    fn pulse_width(&self, position: f64) -> Duration {
+        let position = position.clamp(-1.0, 1.0);
+        let neutral = self.limits.clamp(self.neutral);
+        let pulse_width = if position >= 0.0 {
+            neutral + (self.limits.max - neutral).mul_f64(position)
+        } else {
+            neutral - (neutral - self.limits.min).mul_f64(-position)
+        };
+        self.limits.clamp(pulse_width)
+    }
+
+    // Pulse width for {throttle}, from 0 at neutral to 1 at the widest pulse
+    fn throttle_pulse_width(&self, throttle: f64) -> Duration {
+        self.pulse_width(throttle.clamp(0.0, 1.0))
+    }
+}
+
+impl Default for ServoConfig {
+    fn default() -> Self {
+        Self {
+            period: Duration::from_millis(20),
+            limits: PulseLimits::default(),
+            neutral: Duration::from_micros(1500),
+            timeout: Some(Duration::from_secs(1)),
+            failsafe: Failsafe::Neutral,
+        }
+    }
+}
+
+/// How an output has done so far
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OutputStatus {
+    pub pulse_width: Option<Duration>, // None without pulses
+    pub failsafe_active: bool,         // Until the next command
+    pub failsafes: usize,              // Times the failsafe kicked in
+}
+
+struct Shared {
+    output: PwmOutput,
+    status: OutputStatus,
+    last_command: Option<Instant>, // None until the first command, before which there is nothing to fail
+}
+
+// Whether the failsafe is due at {now}, for the latest command at {last_command}
+fn failsafe_due(last_command: Option<Instant>, now: Instant, timeout: Option<Duration>) -> bool {
+    match (last_command, timeout) {
+        (Some(last_command), Some(timeout)) => {
+            now.saturating_duration_since(last_command) > timeout
+        }
+        _ => false,
+    }
+}
+
+// The output of a `Servo` or an `Esc`, with a watchdog thread applying the failsafe
+struct Driver {
+    config: ServoConfig,
+    shared: Arc<Mutex<Shared>>,
+    running: Arc<AtomicBool>,
+    watchdog: Option<JoinHandle<()>>,
+}
+
+impl Driver {
+    fn new(output: PwmOutput, config: ServoConfig, name: &str) -> Result<Self> {
+        anyhow::ensure!(
+            config.limits.min <= config.limits.max && config.limits.max < config.period,
+            "Pulse width limits need to be in order, and within the period."
+        );
+        let shared = Arc::new(Mutex::new(Shared {
+            output,
+            status: OutputStatus::default(),
+            last_command: None,
+        }));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let watchdog = match config.timeout {
+            Some(_) => {
+                let (shared, running) = (shared.clone(), running.clone());
+                let watchdog = std::thread::Builder::new()
+                    .name(format!("{name}-watchdog"))
+                    .spawn(move || {
+                        while running.load(Ordering::Relaxed) {
+                            std::thread::sleep(WATCHDOG_INTERVAL);
+                            let mut shared = shared.lock().unwrap();
+                            if shared.status.failsafe_active
+                                || !failsafe_due(
+                                    shared.last_command,
+                                    Instant::now(),
+                                    config.timeout,
+                                )
+                            {
+                                continue;
+                            }
+                            // Tried again on the next round if it fails
+                            let result = match config.failsafe {
+                                Failsafe::Hold => Ok(()),
+                                Failsafe::Neutral => shared
+                                    .output
+                                    .set(config.period, config.limits.clamp(config.neutral)),
+                                Failsafe::Off => shared.output.stop(),
+                            };
+                            if result.is_ok() {
+                                shared.status.pulse_width = match config.failsafe {
+                                    Failsafe::Hold => shared.status.pulse_width,
+                                    Failsafe::Neutral => Some(config.limits.clamp(config.neutral)),
+                                    Failsafe::Off => None,
+                                };
+                                shared.status.failsafe_active = true;
+                                shared.status.failsafes += 1;
+                            }
+                        }
+                    })
+                    .with_context(|| format!("Unable to start {name} watchdog."))?;
+                Some(watchdog)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            config,
+            shared,
+            running,
+            watchdog,
+        })
+    }
+
+    // Sets the output to {pulse_width}, held to the limits
+    fn command(&self, pulse_width: Duration) -> Result<()> {
+        let pulse_width = self.config.limits.clamp(pulse_width);
+        let mut shared = self.shared.lock().unwrap();
+        shared
+            .output
+            .set(self.config.period, pulse_width)
+            .context("Unable to set pulse width.")?;
+        shared.status.pulse_width = Some(pulse_width);
+        shared.status.failsafe_active = false;
+        shared.last_command = Some(Instant::now());
+        Ok(())
+    }
+
+    // No pulses, and no failsafe until the next command
+    fn stop(&self) -> Result<()> {
+        let mut shared = self.shared.lock().unwrap();
+        shared.output.stop().context("Unable to stop pulses.")?;
+        shared.status.pulse_width = None;
+        shared.last_command = None;
+        Ok(())
+    }
+
+    fn status(&self) -> OutputStatus {
+        self.shared.lock().unwrap().status
+    }
+}
+
+impl Drop for Driver {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(watchdog) = self.watchdog.take() {
+            let _ = watchdog.join();
+        }
+        let _ = self.shared.lock().unwrap().output.stop();
+    }
+}
+
+/// A servo, e.g., moving the rudder, set by position from -1 to 1. There are no pulses until the first command, and none once dropped
+pub struct Servo {
+    driver: Driver,
+}
+
+impl Servo {
+    pub fn new(output: PwmOutput, config: ServoConfig) -> Result<Self> {
+        Ok(Self {
+            driver: Driver::new(output, config, "servo")?,
+        })
+    }
+
+    pub fn config(&self) -> &ServoConfig {
+        &self.driver.config
+    }
+
+    /// Moves to {position}, from -1 at the narrowest pulse, through 0 at neutral, to 1 at the widest
+    pub fn set_position(&self, position: f64) -> Result<()> {
+        self.driver
+            .command(self.driver.config.pulse_width(position))
+    }
+
+    /// Moves to where {pulse_width} sets it, held to the limits
+    pub fn set_pulse_width(&self, pulse_width: Duration) -> Result<()> {
+        self.driver.command(pulse_width)
+    }
+
+    /// Lets the servo go limp
+    pub fn stop(&self) -> Result<()> {
+        self.driver.stop()
+    }
+
+    pub fn status(&self) -> OutputStatus {
+        self.driver.status()
+    }
+}
+
+/// The electronic speed controller of a motor, set by throttle from 0 to 1. It doesn't run the motor before `arm`
+pub struct Esc {
+    driver: Driver,
+    armed: bool,
+}
+
+impl Esc {
+    /// The failsafe can't be `Failsafe::Hold`, which would keep the motor running
+    pub fn new(output: PwmOutput, config: ServoConfig) -> Result<Self> {
+        anyhow::ensure!(
+            config.failsafe != Failsafe::Hold,
+            "An ESC can't hold its throttle as failsafe."
+        );
+        Ok(Self {
+            driver: Driver::new(output, config, "esc")?,
+            armed: false,
+        })
+    }
+
+    pub fn config(&self) -> &ServoConfig {
+        &self.driver.config
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.armed
+    }
+
+    /// Holds zero throttle for {duration}, which ESCs need to see before they run the motor, usually for 1 s to 3 s after power-up, and blocks meanwhile
+    pub fn arm(&mut self, duration: Duration) -> Result<()> {
+        let start = Instant::now();
+        // Repeated, so that the failsafe doesn't kick in with a short timeout
+        while start.elapsed() < duration {
+            self.driver.command(self.driver.config.neutral)?;
+            std::thread::sleep(WATCHDOG_INTERVAL.min(duration.saturating_sub(start.elapsed())));
+        }
+        self.driver.command(self.driver.config.neutral)?;
+        self.armed = true;
+        Ok(())
+    }
+
+    /// Sets zero throttle, and takes no other until armed again
+    pub fn disarm(&mut self) -> Result<()> {
+        self.armed = false;
+        self.driver.command(self.driver.config.neutral)
+    }
+
+    /// Runs the motor at {throttle}, from 0 at neutral to 1 at the widest pulse
+    pub fn set_throttle(&self, throttle: f64) -> Result<()> {
+        anyhow::ensure!(self.armed, "ESC needs to be armed first.");
+        self.driver
+            .command(self.driver.config.throttle_pulse_width(throttle))
+    }
+
+    /// Stops the pulses, which ESCs take as loss of signal, stopping the motor. It needs to be armed again afterwards
+    pub fn stop(&mut self) -> Result<()> {
+        self.armed = false;
+        self.driver.stop()
+    }
+
+    pub fn status(&self) -> OutputStatus {
+        self.driver.status()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pulse_width() {
+        let micros = Duration::from_micros;
+        let config = ServoConfig {
+            limits: PulseLimits {
+                min: micros(1100),
+                max: micros(1900),
+            },
+            ..ServoConfig::new()
+        };
+        assert_eq!(config.pulse_width(0.0), micros(1500));
+        assert_eq!(config.pulse_width(0.5), micros(1700));
+        assert_eq!(config.pulse_width(-0.25), micros(1400));
+        assert_eq!(config.pulse_width(-3.0), micros(1100));
+        assert_eq!(config.limits.clamp(micros(2500)), micros(1900));
+
+        // Off center neutral, and throttle never below it
+        let config = ServoConfig::esc();
+        assert_eq!(config.throttle_pulse_width(0.0), micros(1000));
+        assert_eq!(config.throttle_pulse_width(0.3), micros(1300));
+        assert_eq!(config.throttle_pulse_width(-1.0), micros(1000));
+
+        let start = Instant::now();
+        let timeout = Some(Duration::from_millis(500));
+        assert!(!failsafe_due(None, start + micros(10_000_000), timeout));
+        assert!(!failsafe_due(Some(start), start + micros(400_000), timeout));
+        assert!(failsafe_due(Some(start), start + micros(600_000), timeout));
+        assert!(!failsafe_due(Some(start), start + micros(600_000), None));
+    }
+}
@@ -0,0 +1,320 @@
+// A buzzer sounding alarms raised from anywhere in the application, e.g., heel beyond a limit, the anchor dragging, or the sensor failing.
+// Several alarms can be raised at once, and the buzzer sounds the one with the highest priority, until it is cleared or its duration is up.
+
+use std::{
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use rppal::gpio::OutputPin;
+
+use super::PwmOutput;
+
+/// What makes the sound
+pub enum BuzzerOutput {
+    Active(OutputPin), // A buzzer with its own oscillator, sounding while the pin is high
+    Tone {
+        output: PwmOutput, // A passive buzzer or a speaker, driven with a square wave
+        frequency: f64, // [Hz] Of the tone, e.g., 2700 Hz, where many piezo buzzers are the loudest
+    },
+}
+
+impl BuzzerOutput {
+    fn set(&mut self, on: bool) -> Result<()> {
+        match self {
+            Self::Active(pin) => {
+                if on {
+                    pin.set_high();
+                } else {
+                    pin.set_low();
+                }
+            }
+            Self::Tone { output, frequency } => {
+                if on {
+                    let period = Duration::from_secs_f64(1.0 / *frequency);
+                    output.set(period, period / 2)?;
+                } else {
+                    output.stop()?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How an alarm sounds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BeepPattern {
+    Continuous,
+    Intermittent(Duration), // Beeping on and off with the given period
+    Pulses(u8), // {count} short beeps, then a pause, over and over, telling alarms apart by ear
+}
+
+impl BeepPattern {
+    /// The steps of the pattern, each a level (true for sounding) and how long it lasts, repeated over and over
+    pub fn steps(&self) -> Vec<(bool, Duration)> {
+        let millis = Duration::from_millis;
+        match *self {
+            Self::Continuous => vec![(true, Duration::from_secs(1))],
+            Self::Intermittent(period) => vec![(true, period / 2), (false, period - period / 2)],
+            Self::Pulses(count) => {
+                let mut steps =
+                    [(true, millis(100)), (false, millis(100))].repeat(count.max(1) as usize);
+                if let Some(last) = steps.last_mut() {
+                    last.1 = millis(1000);
+                }
+                steps
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+/// An alarm to sound
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alarm {
+    pub name: String, // E.g., "heel". Raising an alarm of the same name again replaces it
+    pub pattern: BeepPattern,
+    pub priority: Priority,
+    pub duration: Option<Duration>, // None sounds until cleared
+}
+
+impl Alarm {
+    /// Sounds until cleared
+    pub fn new(name: &str, pattern: BeepPattern, priority: Priority) -> Self {
+        Self {
+            name: name.to_string(),
+            pattern,
+            priority,
+            duration: None,
+        }
+    }
+}
+
+// Alarms raised and not cleared yet
+#[derive(Debug, Default)]
+struct AlarmQueue {
+    alarms: Vec<(Alarm, Option<Instant>, u64)>, // With when it ends, and in order of raising
+    sequence: u64,
+}
+
+impl AlarmQueue {
+    fn raise(&mut self, alarm: Alarm, now: Instant) {
+        self.clear(&alarm.name);
+        let end = alarm
+            .duration
+            .and_then(|duration| now.checked_add(duration));
+        self.alarms.push((alarm, end, self.sequence));
+        self.sequence += 1;
+    }
+
+    fn clear(&mut self, name: &str) {
+        self.alarms.retain(|(alarm, _, _)| alarm.name != name);
+    }
+
+    // Drops the alarms whose duration is up by {now}, and returns when the next one ends
+    fn expire(&mut self, now: Instant) -> Option<Instant> {
+        self.alarms
+            .retain(|(_, end, _)| end.is_none_or(|end| end > now));
+        self.alarms.iter().filter_map(|(_, end, _)| *end).min()
+    }
+
+    // The alarm to sound, the one of the highest priority, and of those the one raised last, with its sequence number
+    fn current(&self) -> Option<(&Alarm, u64)> {
+        self.alarms
+            .iter()
+            .max_by_key(|(alarm, _, sequence)| (alarm.priority, *sequence))
+            .map(|(alarm, _, sequence)| (alarm, *sequence))
+    }
+}
+
+enum Command {
+    Raise(Alarm),
+    Clear(String),
+    ClearAll,
+    Stop,
+}
+
+/// Raises and clears alarms of a `Buzzer`, from any thread
+#[derive(Clone)]
+pub struct AlarmSender(Sender<Command>);
+
+impl AlarmSender {
+    pub fn raise(&self, alarm: Alarm) -> Result<()> {
+        self.send(Command::Raise(alarm))
+    }
+
+    /// Clears the alarm named {name}, if it is raised
+    pub fn clear(&self, name: &str) -> Result<()> {
+        self.send(Command::Clear(name.to_string()))
+    }
+
+    pub fn clear_all(&self) -> Result<()> {
+        self.send(Command::ClearAll)
+    }
+
+    fn send(&self, command: Command) -> Result<()> {
+        self.0
+            .send(command)
+            .context("The buzzer doesn't sound alarms anymore.")
+    }
+}
+
+/// Sounds the alarms raised with its `AlarmSender`s on its own thread. It is silent once dropped
+pub struct Buzzer {
+    sender: Option<AlarmSender>,
+    player: Option<JoinHandle<()>>,
+}
+
+impl Buzzer {
+    pub fn new(output: BuzzerOutput) -> Result<Self> {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let player = std::thread::Builder::new()
+            .name("buzzer".to_string())
+            .spawn(move || play(output, receiver))
+            .context("Unable to start buzzer.")?;
+        Ok(Self {
+            sender: Some(AlarmSender(sender)),
+            player: Some(player),
+        })
+    }
+
+    /// For handing to the subsystems raising alarms
+    pub fn sender(&self) -> AlarmSender {
+        self.sender.clone().unwrap()
+    }
+
+    pub fn raise(&self, alarm: Alarm) -> Result<()> {
+        self.sender().raise(alarm)
+    }
+
+    pub fn clear(&self, name: &str) -> Result<()> {
+        self.sender().clear(name)
+    }
+}
+
+impl Drop for Buzzer {
+    fn drop(&mut self) {
+        // Other senders may still be around, so the player is told to stop, rather than waiting for all senders to be gone
+        if let Some(sender) = self.sender.take() {
+            let _ = sender.send(Command::Stop);
+        }
+        if let Some(player) = self.player.take() {
+            let _ = player.join();
+        }
+    }
+}
+
+// The alarm being sounded
+struct Playing {
+    sequence: u64, // Of the alarm in the queue
+    steps: Vec<(bool, Duration)>,
+    step: usize,
+    step_end: Instant,
+}
+
+// Sounds the current alarm of those raised over {receiver}, step by step, until told to stop, or all senders are gone
+fn play(mut output: BuzzerOutput, receiver: Receiver<Command>) {
+    let mut queue = AlarmQueue::default();
+    let mut playing: Option<Playing> = None;
+    loop {
+        let now = Instant::now();
+        let next_end = queue.expire(now);
+        match (queue.current(), &mut playing) {
+            (Some((_, sequence)), Some(playing)) if playing.sequence == sequence => {
+                if now >= playing.step_end {
+                    playing.step = (playing.step + 1) % playing.steps.len();
+                    let (level, duration) = playing.steps[playing.step];
+                    let _ = output.set(level);
+                    playing.step_end = now + duration;
+                }
+            }
+            // Another alarm, or the first one, starts from the beginning of its pattern
+            (Some((alarm, sequence)), _) => {
+                let steps = alarm.pattern.steps();
+                let (level, duration) = steps[0];
+                let _ = output.set(level);
+                playing = Some(Playing {
+                    sequence,
+                    steps,
+                    step: 0,
+                    step_end: now + duration,
+                });
+            }
+            (None, _) => {
+                if playing.take().is_some() {
+                    let _ = output.set(false);
+                }
+            }
+        }
+
+        let wake = [playing.as_ref().map(|playing| playing.step_end), next_end]
+            .into_iter()
+            .flatten()
+            .min();
+        let command = match wake {
+            Some(wake) => receiver.recv_timeout(wake.saturating_duration_since(now)),
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match command {
+            Ok(Command::Raise(alarm)) => queue.raise(alarm, Instant::now()),
+            Ok(Command::Clear(name)) => queue.clear(&name),
+            Ok(Command::ClearAll) => queue.alarms.clear(),
+            Ok(Command::Stop) | Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+    }
+    let _ = output.set(false);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alarm_queue() {
+        let start = Instant::now();
+        let mut queue = AlarmQueue::default();
+        assert!(queue.current().is_none());
+
+        let heel = Alarm::new("heel", BeepPattern::Pulses(2), Priority::High);
+        let sensor = Alarm {
+            duration: Some(Duration::from_secs(10)),
+            ..Alarm::new("sensor", BeepPattern::Pulses(3), Priority::Medium)
+        };
+        queue.raise(sensor.clone(), start);
+        queue.raise(heel.clone(), start);
+        assert_eq!(queue.current().unwrap().0, &heel);
+        // Raised again, it doesn't pile up
+        queue.raise(heel.clone(), start);
+        assert_eq!(queue.alarms.len(), 2);
+
+        // Highest priority first, and of the same priority, the latest
+        let anchor = Alarm::new("anchor", BeepPattern::Continuous, Priority::High);
+        queue.raise(anchor.clone(), start);
+        assert_eq!(queue.current().unwrap().0, &anchor);
+        queue.clear("anchor");
+        queue.clear("heel");
+        assert_eq!(queue.current().unwrap().0, &sensor);
+
+        assert_eq!(
+            queue.expire(start + Duration::from_secs(5)),
+            Some(start + Duration::from_secs(10))
+        );
+        assert_eq!(queue.expire(start + Duration::from_secs(10)), None);
+        assert!(queue.current().is_none());
+
+        let steps = BeepPattern::Pulses(2).steps();
+        assert_eq!(steps.len(), 4);
+        assert_eq!(steps[3], (false, Duration::from_millis(1000)));
+    }
+}
@@ -0,0 +1,150 @@
+// An LED telling what the application is up to, blinking in patterns on its own thread, so that the sampling loop only says when the state changes.
+
+use std::{thread::JoinHandle, time::Duration};
+
+use anyhow::{Context, Result};
+use crossbeam_channel::{RecvTimeoutError, Sender};
+use rppal::gpio::OutputPin;
+
+/// How the LED blinks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Pattern {
+    #[default]
+    Off,
+    On,
+    Heartbeat,       // A short flash every second: Running fine
+    Calibrating,     // Even blinking at 4 Hz: Keep the sensor still
+    ErrorCode(u8), // {code} flashes, then a pause, over and over, e.g., 2 for failing reads. 0 is taken as 1
+    Blink(Duration), // Even blinking with the given period
+}
+
+impl Pattern {
+    /// The steps of the pattern, each a level (true for on) and how long it lasts, repeated over and over. Empty for a steady level
+    pub fn steps(&self) -> Vec<(bool, Duration)> {
+        let millis = Duration::from_millis;
+        match *self {
+            Self::Off | Self::On => Vec::new(),
+            Self::Heartbeat => vec![(true, millis(100)), (false, millis(900))],
+            Self::Calibrating => Self::Blink(millis(250)).steps(),
+            Self::ErrorCode(code) => {
+                let mut steps =
+                    [(true, millis(200)), (false, millis(300))].repeat(code.max(1) as usize);
+                // Long enough to tell where the code starts
+                if let Some(last) = steps.last_mut() {
+                    last.1 = millis(1500);
+                }
+                steps
+            }
+            Self::Blink(period) => vec![(true, period / 2), (false, period - period / 2)],
+        }
+    }
+
+    // Level of the steady patterns
+    fn level(&self) -> bool {
+        *self == Self::On
+    }
+}
+
+/// An LED on {pin}, active high, blinking the latest `Pattern` set. It is off once dropped
+pub struct StatusLed {
+    pattern: Pattern,
+    sender: Option<Sender<Pattern>>,
+    blinker: Option<JoinHandle<()>>,
+}
+
+impl StatusLed {
+    pub fn new(mut pin: OutputPin, pattern: Pattern) -> Result<Self> {
+        let (sender, receiver) = crossbeam_channel::unbounded::<Pattern>();
+        let blinker = std::thread::Builder::new()
+            .name("status-led".to_string())
+            .spawn(move || {
+                let mut current = pattern;
+                'patterns: loop {
+                    let steps = current.steps();
+                    if steps.is_empty() {
+                        write(&mut pin, current.level());
+                        match receiver.recv() {
+                            Ok(pattern) => current = pattern,
+                            Err(_) => break,
+                        }
+                        continue;
+                    }
+                    // Waiting for the next pattern times the steps, so that a new one shows right away
+                    for (level, duration) in steps.iter().cycle() {
+                        write(&mut pin, *level);
+                        match receiver.recv_timeout(*duration) {
+                            Ok(pattern) => {
+                                current = pattern;
+                                continue 'patterns;
+                            }
+                            Err(RecvTimeoutError::Timeout) => {}
+                            Err(RecvTimeoutError::Disconnected) => break 'patterns,
+                        }
+                    }
+                }
+                pin.set_low();
+            })
+            .context("Unable to start status LED.")?;
+
+        Ok(Self {
+            pattern,
+            sender: Some(sender),
+            blinker: Some(blinker),
+        })
+    }
+
+    pub fn pattern(&self) -> Pattern {
+        self.pattern
+    }
+
+    /// Switches to {pattern}, starting from its beginning, unless it is the current one already, so that this can be called on every round of a loop
+    pub fn set(&mut self, pattern: Pattern) {
+        if pattern == self.pattern {
+            return;
+        }
+        self.pattern = pattern;
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(pattern);
+        }
+    }
+}
+
+fn write(pin: &mut OutputPin, on: bool) {
+    if on {
+        pin.set_high();
+    } else {
+        pin.set_low();
+    }
+}
+
+impl Drop for StatusLed {
+    fn drop(&mut self) {
+        // Without a sender, the blinker turns the LED off and ends
+        self.sender.take();
+        if let Some(blinker) = self.blinker.take() {
+            let _ = blinker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_steps() {
+        let millis = Duration::from_millis;
+        assert!(Pattern::On.steps().is_empty());
+        assert_eq!(
+            Pattern::Blink(millis(500)).steps(),
+            [(true, millis(250)), (false, millis(250))]
+        );
+
+        let steps = Pattern::ErrorCode(3).steps();
+        assert_eq!(steps.len(), 6);
+        assert_eq!(steps.iter().filter(|(level, _)| *level).count(), 3);
+        assert_eq!(steps[3], (false, millis(300)));
+        assert_eq!(steps[5], (false, millis(1500)));
+        assert_eq!(Pattern::ErrorCode(0).steps().len(), 2);
+    }
+}
@@ -0,0 +1,255 @@
+// Anchoring the monotonic instants that samples are taken at to wall-clock time, so that recordings of several devices can be lined up.
+// The Pi has no real-time clock, so its wall clock only becomes trustworthy once NTP has synchronized it, which may well happen in the middle of a recording.
+
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{Context, Result};
+
+use crate::logging::Timebase;
+
+pub mod ds3231;
+
+/// A monotonic instant, and the wall-clock time at that instant
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Anchor {
+    pub instant: Instant,
+    pub system_time: SystemTime,
+}
+
+impl Anchor {
+    pub fn now() -> Self {
+        Self {
+            instant: Instant::now(),
+            system_time: SystemTime::now(),
+        }
+    }
+
+    /// Wall-clock time at {instant}, assuming both clocks run at the same rate from here. Works for instants before the anchor as well
+    pub fn system_time(&self, instant: Instant) -> SystemTime {
+        match instant.checked_duration_since(self.instant) {
+            Some(elapsed) => self.system_time + elapsed,
+            None => self.system_time - self.instant.duration_since(instant),
+        }
+    }
+}
+
+/// A jump of the wall clock against the monotonic clock, e.g., when NTP first synchronizes after booting, or someone sets the time
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ClockStep {
+    pub time: f64,   // [s] Since the start of the recording, by the monotonic clock
+    pub offset: f64, // [s] Wall-clock time after the step, minus what it would have been without. Positive if the wall clock jumped ahead
+}
+
+/// How often `ClockSync` checks the wall clock, and what counts as a step
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockSyncConfig {
+    pub interval: Duration,       // Between checks
+    pub step_threshold: Duration, // Smaller differences are taken as NTP slewing the clock, and only move the anchor
+}
+
+impl Default for ClockSyncConfig {
+    /// Checks every 10 s, and counts anything beyond 10 ms as a step
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(10),
+            step_threshold: Duration::from_millis(10),
+        }
+    }
+}
+
+/// Keeps the monotonic clock anchored to the wall clock over a recording, by checking the wall clock every {interval}, and notes steps of the wall clock.
+/// `Timebase` maps instants to wall-clock time by the start of the recording alone, which is off by however much the wall clock was off at the start. This follows the wall clock instead, so sample times of several devices synchronized by NTP line up
+pub struct ClockSync {
+    timebase: Timebase,
+    config: ClockSyncConfig,
+    anchor: Anchor, // Latest
+    steps: Vec<ClockStep>,
+}
+
+impl ClockSync {
+    /// Anchored at the start of {timebase}
+    pub fn new(timebase: Timebase, config: ClockSyncConfig) -> Self {
+        Self {
+            timebase,
+            config,
+            anchor: Anchor {
+                instant: timebase.start,
+                system_time: timebase.start_time,
+            },
+            steps: Vec::new(),
+        }
+    }
+
+    /// Checks the wall clock if {interval} has passed since the latest check. Returns the step, if the wall clock stepped meanwhile
+    pub fn poll(&mut self) -> Option<ClockStep> {
+        if self.anchor.instant.elapsed() < self.config.interval {
+            return None;
+        }
+        self.update(Anchor::now())
+    }
+
+    /// Moves the anchor to {anchor}, e.g., from `Anchor::now`. Returns the step, if the wall clock stepped since the previous anchor
+    pub fn update(&mut self, anchor: Anchor) -> Option<ClockStep> {
+        let expected = self.anchor.system_time(anchor.instant);
+        let offset = seconds_between(expected, anchor.system_time);
+        self.anchor = anchor;
+        if offset.abs() < self.config.step_threshold.as_secs_f64() {
+            return None;
+        }
+        let step = ClockStep {
+            time: self.timebase.elapsed(anchor.instant).as_secs_f64(),
+            offset,
+        };
+        self.steps.push(step);
+        Some(step)
+    }
+
+    pub fn anchor(&self) -> Anchor {
+        self.anchor
+    }
+
+    /// Wall-clock time of {instant}, by the latest anchor. Instants from before a step are mapped as if the wall clock had been right all along
+    pub fn system_time(&self, instant: Instant) -> SystemTime {
+        self.anchor.system_time(instant)
+    }
+
+    /// Where the wall clock of the start of the recording would have been, had it been right all along. Sums up the steps so far
+    pub fn corrected_start_time(&self) -> SystemTime {
+        self.system_time(self.timebase.start)
+    }
+
+    /// All steps so far, in order
+    pub fn steps(&self) -> &[ClockStep] {
+        &self.steps
+    }
+}
+
+/// [s] From {earlier} to {later}, negative if {later} is in fact earlier
+pub fn seconds_between(earlier: SystemTime, later: SystemTime) -> f64 {
+    match later.duration_since(earlier) {
+        Ok(duration) => duration.as_secs_f64(),
+        Err(error) => -error.duration().as_secs_f64(),
+    }
+}
+
+/// Wall-clock time of {time} [s] after midnight UTC on {date}, as (year, month, day), from 1970 on. See http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+pub fn from_civil((year, month, day): (u16, u8, u8), time: f64) -> SystemTime {
+    let (year, month, day) = (year as u64, month as u64, day as u64);
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    SystemTime::UNIX_EPOCH + Duration::from_secs(days * 86_400) + Duration::from_secs_f64(time)
+}
+
+/// Date, as (year, month, day), and time [s] after midnight UTC of {system_time}, from 1970 on. The reverse of `from_civil`. See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+pub fn to_civil(system_time: SystemTime) -> ((u16, u8, u8), f64) {
+    let since_epoch = system_time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let days = since_epoch.as_secs() / 86_400;
+    let time = since_epoch.as_secs_f64() - (days * 86_400) as f64;
+
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153; // From March on
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as u64;
+    ((year as u16, month as u8, day as u8), time)
+}
+
+/// UTC in ISO 8601 with milliseconds, e.g., "2022-03-14T09:10:13.250Z", as JSON APIs like Signal K expect
+pub fn iso_timestamp(time: SystemTime) -> String {
+    let ((year, month, day), _) = to_civil(time);
+    let since_epoch = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let (seconds, milliseconds) = (since_epoch.as_secs() % 86_400, since_epoch.subsec_millis());
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{milliseconds:03}Z",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Whether systemd reports the wall clock as synchronized by NTP. Errors where `timedatectl` isn't available
+pub fn ntp_synchronized() -> Result<bool> {
+    let output = std::process::Command::new("timedatectl")
+        .args(["show", "--property=NTPSynchronized", "--value"])
+        .output()
+        .context("Unable to run timedatectl.")?;
+    anyhow::ensure!(
+        output.status.success(),
+        "timedatectl failed: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    Ok(String::from_utf8_lossy(&output.stdout).trim() == "yes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_steps() {
+        let timebase = Timebase::now();
+        let mut sync = ClockSync::new(timebase, ClockSyncConfig::default());
+        let at = |seconds: f64, wall_clock: f64| Anchor {
+            instant: timebase.start + Duration::from_secs_f64(seconds),
+            system_time: timebase.start_time + Duration::from_secs_f64(wall_clock),
+        };
+
+        // Slewing by a few milliseconds only moves the anchor
+        assert_eq!(sync.update(at(10.0, 10.005)), None);
+        assert_eq!(
+            sync.system_time(timebase.start + Duration::from_secs(20)),
+            timebase.start_time + Duration::from_secs_f64(20.005)
+        );
+
+        // NTP sets the clock back by 2 s
+        let step = sync.update(at(20.0, 18.005)).unwrap();
+        assert_eq!(step.time, 20.0);
+        assert!((step.offset - -2.0).abs() < 1e-9);
+        assert_eq!(sync.steps(), [step]);
+        assert!(
+            (seconds_between(timebase.start_time, sync.corrected_start_time()) - -1.995).abs()
+                < 1e-9
+        );
+    }
+
+    #[test]
+    fn test_civil() {
+        let time = from_civil((2022, 3, 14), 9.0 * 3600.0 + 26.0 * 60.0 + 53.5);
+        assert_eq!(
+            time.duration_since(SystemTime::UNIX_EPOCH).unwrap(),
+            Duration::from_secs_f64(1_647_250_013.5)
+        );
+        assert_eq!(to_civil(time), ((2022, 3, 14), 34_013.5));
+        // Leap days, and the turn of the year
+        for date in [
+            (2024, 2, 29),
+            (2024, 3, 1),
+            (1999, 12, 31),
+            (2000, 1, 1),
+            (1970, 1, 1),
+        ] {
+            assert_eq!(to_civil(from_civil(date, 0.0)), (date, 0.0));
+        }
+        assert_eq!(
+            iso_timestamp(from_civil((2022, 3, 14), 33_013.25)),
+            "2022-03-14T09:10:13.250Z"
+        );
+    }
+}
@@ -0,0 +1,213 @@
+// Maxim DS3231 real-time clock, keeping the time on its battery while the Pi is off, so that recordings made without network, and thus without NTP, still get the right wall-clock time.
+// Register map: https://datasheets.maximintegrated.com/en/ds/DS3231.pdf
+// Its address, 0x68, is the one of the GY-521 with AD0 low, so the GY-521 needs AD0 high, for 0x69, to share the bus with it.
+
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use rppal::i2c::I2c;
+
+use crate::utilites;
+
+pub const I2C_ADDRESS: u16 = 0x68; // Fixed
+
+const TIME: u8 = 0x00; // Seven bytes: seconds, minutes, hours, day of the week, day of the month, month and century, year
+const CONTROL: u8 = 0x0E;
+const STATUS: u8 = 0x0F;
+const TEMPERATURE: u8 = 0x11; // Two bytes
+
+// Status register bits
+const OSF: u8 = 1 << 7; // The oscillator stopped at some point, e.g., with a flat battery, so the time is wrong. Cleared by setting the time
+
+/// Which clock was set from which by `DS3231::synchronize`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Synchronization {
+    RtcFromSystem,
+    SystemFromRtc { offset: f64 }, // [s] That the wall clock was set by. Positive if it was behind
+}
+
+/// The real-time clock, keeping UTC
+#[non_exhaustive]
+pub struct DS3231 {
+    pub i2c_address: u16,
+    pub retry_policy: utilites::RetryPolicy, // Applied to all register reads and writes
+}
+
+impl DS3231 {
+    pub fn new(i2c_address: u16) -> Self {
+        Self {
+            i2c_address,
+            retry_policy: Default::default(),
+        }
+    }
+
+    fn read_registers(&self, i2c: &I2c, address: u8, buffer: &mut [u8]) -> Result<()> {
+        self.retry_policy
+            .run(|| i2c.block_read(address, buffer))
+            .with_context(|| format!("Unable to read registers from {:#04x}.", address))
+    }
+
+    fn write_register(&self, i2c: &I2c, address: u8, value: u8) -> Result<()> {
+        self.retry_policy
+            .run(|| i2c.smbus_write_byte(address, value))
+            .with_context(|| format!("Unable to write register {:#04x}.", address))
+    }
+
+    /// Addresses the clock on {i2c}, and checks that it is there
+    pub fn initialize(&mut self, i2c: &mut I2c) -> Result<()> {
+        i2c.set_slave_address(self.i2c_address)?;
+        // Nothing to set up, since it runs by itself, so this only checks that something answers
+        self.read_registers(i2c, CONTROL, &mut [0u8; 1])
+            .context("No DS3231 found.")
+    }
+
+    /// Whether the time is trustworthy, i.e., the oscillator didn't stop since the time was set
+    pub fn time_valid(&self, i2c: &I2c) -> Result<bool> {
+        let mut status = [0u8; 1];
+        self.read_registers(i2c, STATUS, &mut status)?;
+        Ok(status[0] & OSF == 0)
+    }
+
+    /// Current UTC, to the second. Errors if the time isn't trustworthy, see `time_valid`
+    pub fn time(&self, i2c: &I2c) -> Result<SystemTime> {
+        anyhow::ensure!(
+            self.time_valid(i2c)?,
+            "The clock stopped since it was set, e.g., due to a flat battery. Set it again."
+        );
+        let mut registers = [0u8; 7];
+        self.read_registers(i2c, TIME, &mut registers)?;
+        parse_time(&registers)
+    }
+
+    /// Sets the clock to {time} [UTC], truncated to the second, which marks it trustworthy again
+    pub fn set_time(&self, i2c: &I2c, time: SystemTime) -> Result<()> {
+        let registers = encode_time(time);
+        self.retry_policy
+            .run(|| i2c.block_write(TIME, &registers))
+            .context("Unable to write time registers.")?;
+        let mut status = [0u8; 1];
+        self.read_registers(i2c, STATUS, &mut status)?;
+        self.write_register(i2c, STATUS, status[0] & !OSF)
+    }
+
+    /// [degree C] Of the chip, measured every 64 s to compensate the oscillator, in steps of 0.25 °C
+    pub fn temperature(&self, i2c: &I2c) -> Result<f64> {
+        let mut registers = [0u8; 2];
+        self.read_registers(i2c, TEMPERATURE, &mut registers)?;
+        // Most significant byte first, with the fraction in the two upper bits of the second
+        Ok(i16::from_be_bytes(registers) as f64 / 256.0)
+    }
+
+    /// Sets the system's wall clock from the real-time clock, unless the wall clock is {ntp_synchronized}, in which case it sets the real-time clock from the wall clock instead, keeping it right for the next start without network.
+    /// Setting the wall clock needs root. Call this before `logging::Timebase::now`, so that the recording starts at the right time
+    pub fn synchronize(&self, i2c: &I2c, ntp_synchronized: bool) -> Result<Synchronization> {
+        if ntp_synchronized {
+            self.set_time(i2c, SystemTime::now())?;
+            return Ok(Synchronization::RtcFromSystem);
+        }
+        let time = self.time(i2c)?;
+        let offset = super::seconds_between(SystemTime::now(), time);
+        set_system_time(time)?;
+        Ok(Synchronization::SystemFromRtc { offset })
+    }
+}
+
+impl Default for DS3231 {
+    fn default() -> Self {
+        Self::new(I2C_ADDRESS)
+    }
+}
+
+/// Sets the system's wall clock to {time}, with `date`, which needs root
+pub fn set_system_time(time: SystemTime) -> Result<()> {
+    let seconds = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .context("Time is before 1970.")?
+        .as_secs();
+    let output = std::process::Command::new("date")
+        .args(["--utc", &format!("--set=@{seconds}")])
+        .output()
+        .context("Unable to run date.")?;
+    anyhow::ensure!(
+        output.status.success(),
+        "Unable to set the wall clock: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    Ok(())
+}
+
+fn bcd(value: u8) -> u8 {
+    (value & 0x0F) + 10 * (value >> 4)
+}
+
+fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+// Time of the seven time registers
+fn parse_time(registers: &[u8; 7]) -> Result<SystemTime> {
+    let seconds = bcd(registers[0] & 0x7F) as f64;
+    let minutes = bcd(registers[1] & 0x7F) as f64;
+    let hours = if registers[2] & (1 << 6) != 0 {
+        // 12 hour mode, with bit 5 set for PM
+        let hours = bcd(registers[2] & 0x1F) % 12;
+        hours + 12 * ((registers[2] >> 5) & 1)
+    } else {
+        bcd(registers[2] & 0x3F)
+    } as f64;
+    let day = bcd(registers[4] & 0x3F);
+    let month = bcd(registers[5] & 0x1F);
+    let century = (registers[5] >> 7) as u16;
+    let year = 2000 + 100 * century + bcd(registers[6]) as u16;
+    anyhow::ensure!(
+        (1..=12).contains(&month) && (1..=31).contains(&day) && hours < 24.0,
+        "Invalid time in registers: {registers:02x?}"
+    );
+    Ok(super::from_civil(
+        (year, month, day),
+        hours * 3600.0 + minutes * 60.0 + seconds,
+    ))
+}
+
+// The seven time registers for {time}, in 24 hour mode
+fn encode_time(time: SystemTime) -> [u8; 7] {
+    let ((year, month, day), seconds) = super::to_civil(time);
+    let seconds = seconds as u32;
+    let days = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 86_400;
+    let day_of_week = ((days + 3) % 7 + 1) as u8; // 1 for Monday. 1970-01-01 was a Thursday
+    let century = ((year.saturating_sub(2000) / 100) as u8) << 7;
+    [
+        to_bcd((seconds % 60) as u8),
+        to_bcd((seconds / 60 % 60) as u8),
+        to_bcd((seconds / 3600) as u8),
+        day_of_week,
+        to_bcd(day),
+        century | to_bcd(month),
+        to_bcd((year % 100) as u8),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time() {
+        // Monday, 2022-03-14 09:26:53 UTC
+        let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_647_250_013);
+        let registers = [0x53, 0x26, 0x09, 0x01, 0x14, 0x03, 0x22];
+        assert_eq!(encode_time(time), registers);
+        assert_eq!(parse_time(&registers).unwrap(), time);
+        // 9 PM in 12 hour mode
+        let registers = [0x53, 0x26, 0x69, 0x01, 0x14, 0x03, 0x22];
+        assert_eq!(
+            parse_time(&registers).unwrap(),
+            time + std::time::Duration::from_secs(12 * 3600)
+        );
+        assert!(parse_time(&[0, 0, 0, 1, 0x14, 0x13, 0x22]).is_err());
+    }
+}
@@ -0,0 +1,253 @@
+// A small OLED showing the attitude and how the recording goes, for a headless box in the cockpit, where a look beats logging in over SSH.
+// Drawing and sending a frame takes tens of milliseconds on the I2C bus, so `Display` does it on its own thread, with the latest status.
+
+pub mod ssd1306;
+
+use std::{
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use crossbeam_channel::{RecvTimeoutError, Sender};
+use rppal::i2c::I2c;
+
+use crate::telemetry::Attitude;
+
+pub const WIDTH: usize = 128; // [pixel]
+pub const HEIGHT: usize = 64; // [pixel]
+
+const GLYPH_WIDTH: usize = 5; // [pixel] Plus one of space between characters
+const GLYPH_HEIGHT: usize = 7; // [pixel] Plus one of space between lines
+
+// 5x7 glyphs from space to Z, column by column, with the top row in bit 0
+#[rustfmt::skip]
+const FONT: [[u8; GLYPH_WIDTH]; 59] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], [0x00, 0x00, 0x5F, 0x00, 0x00], [0x00, 0x07, 0x00, 0x07, 0x00], // Space ! "
+    [0x14, 0x7F, 0x14, 0x7F, 0x14], [0x24, 0x2A, 0x7F, 0x2A, 0x12], [0x23, 0x13, 0x08, 0x64, 0x62], // # $ %
+    [0x36, 0x49, 0x56, 0x20, 0x50], [0x00, 0x05, 0x03, 0x00, 0x00], [0x00, 0x1C, 0x22, 0x41, 0x00], // & ' (
+    [0x00, 0x41, 0x22, 0x1C, 0x00], [0x08, 0x2A, 0x1C, 0x2A, 0x08], [0x08, 0x08, 0x3E, 0x08, 0x08], // ) * +
+    [0x00, 0x50, 0x30, 0x00, 0x00], [0x08, 0x08, 0x08, 0x08, 0x08], [0x00, 0x60, 0x60, 0x00, 0x00], // , - .
+    [0x20, 0x10, 0x08, 0x04, 0x02], [0x3E, 0x51, 0x49, 0x45, 0x3E], [0x00, 0x42, 0x7F, 0x40, 0x00], // / 0 1
+    [0x42, 0x61, 0x51, 0x49, 0x46], [0x21, 0x41, 0x45, 0x4B, 0x31], [0x18, 0x14, 0x12, 0x7F, 0x10], // 2 3 4
+    [0x27, 0x45, 0x45, 0x45, 0x39], [0x3C, 0x4A, 0x49, 0x49, 0x30], [0x01, 0x71, 0x09, 0x05, 0x03], // 5 6 7
+    [0x36, 0x49, 0x49, 0x49, 0x36], [0x06, 0x49, 0x49, 0x29, 0x1E], [0x00, 0x36, 0x36, 0x00, 0x00], // 8 9 :
+    [0x00, 0x56, 0x36, 0x00, 0x00], [0x08, 0x14, 0x22, 0x41, 0x00], [0x14, 0x14, 0x14, 0x14, 0x14], // ; < =
+    [0x00, 0x41, 0x22, 0x14, 0x08], [0x02, 0x01, 0x51, 0x09, 0x06], [0x32, 0x49, 0x79, 0x41, 0x3E], // > ? @
+    [0x7E, 0x11, 0x11, 0x11, 0x7E], [0x7F, 0x49, 0x49, 0x49, 0x36], [0x3E, 0x41, 0x41, 0x41, 0x22], // A B C
+    [0x7F, 0x41, 0x41, 0x22, 0x1C], [0x7F, 0x49, 0x49, 0x49, 0x41], [0x7F, 0x09, 0x09, 0x09, 0x01], // D E F
+    [0x3E, 0x41, 0x49, 0x49, 0x7A], [0x7F, 0x08, 0x08, 0x08, 0x7F], [0x00, 0x41, 0x7F, 0x41, 0x00], // G H I
+    [0x20, 0x40, 0x41, 0x3F, 0x01], [0x7F, 0x08, 0x14, 0x22, 0x41], [0x7F, 0x40, 0x40, 0x40, 0x40], // J K L
+    [0x7F, 0x02, 0x0C, 0x02, 0x7F], [0x7F, 0x04, 0x08, 0x10, 0x7F], [0x3E, 0x41, 0x41, 0x41, 0x3E], // M N O
+    [0x7F, 0x09, 0x09, 0x09, 0x06], [0x3E, 0x41, 0x51, 0x21, 0x5E], [0x7F, 0x09, 0x19, 0x29, 0x46], // P Q R
+    [0x46, 0x49, 0x49, 0x49, 0x31], [0x01, 0x01, 0x7F, 0x01, 0x01], [0x3F, 0x40, 0x40, 0x40, 0x3F], // S T U
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], [0x3F, 0x40, 0x38, 0x40, 0x3F], [0x63, 0x14, 0x08, 0x14, 0x63], // V W X
+    [0x07, 0x08, 0x70, 0x08, 0x07], [0x61, 0x51, 0x49, 0x45, 0x43],                                 // Y Z
+];
+const DEGREE: [u8; GLYPH_WIDTH] = [0x00, 0x06, 0x09, 0x09, 0x06];
+
+// Columns of {character}. Lower case is shown as upper case, and anything else the font lacks as '?'
+fn glyph(character: char) -> [u8; GLYPH_WIDTH] {
+    match character.to_ascii_uppercase() {
+        '°' => DEGREE,
+        character @ ' '..='Z' => FONT[character as usize - ' ' as usize],
+        _ => glyph('?'),
+    }
+}
+
+/// Pixels of the display, laid out as the SSD1306 takes them: Pages of 8 rows, each a byte per column, with the top row in bit 0
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameBuffer([u8; WIDTH * HEIGHT / 8]);
+
+impl FrameBuffer {
+    /// All dark
+    pub fn new() -> Self {
+        Self([0; WIDTH * HEIGHT / 8])
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn clear(&mut self) {
+        self.0.fill(0);
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        x < WIDTH && y < HEIGHT && self.0[y / 8 * WIDTH + x] & (1 << (y % 8)) != 0
+    }
+
+    /// Lights the pixel at {x}, {y}, counted from the top left, or darkens it. Pixels beyond the display are left out
+    pub fn set_pixel(&mut self, x: usize, y: usize, on: bool) {
+        if x >= WIDTH || y >= HEIGHT {
+            return;
+        }
+        let byte = &mut self.0[y / 8 * WIDTH + x];
+        if on {
+            *byte |= 1 << (y % 8);
+        } else {
+            *byte &= !(1 << (y % 8));
+        }
+    }
+
+    /// Draws {text} with its top left corner at {x}, {y}, each pixel of the font {scale} pixels wide and high, so characters take 6 {scale} by 8 {scale} pixels. Returns the x after the text
+    pub fn draw_text(&mut self, x: usize, y: usize, text: &str, scale: usize) -> usize {
+        let mut x = x;
+        for character in text.chars() {
+            for (column, bits) in glyph(character).iter().enumerate() {
+                for row in 0..GLYPH_HEIGHT {
+                    if bits & (1 << row) == 0 {
+                        continue;
+                    }
+                    for dx in 0..scale {
+                        for dy in 0..scale {
+                            self.set_pixel(x + column * scale + dx, y + row * scale + dy, true);
+                        }
+                    }
+                }
+            }
+            x += (GLYPH_WIDTH + 1) * scale;
+        }
+        x
+    }
+}
+
+impl Default for FrameBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What the display shows
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DisplayStatus {
+    pub attitude: Option<Attitude>, // None before the first sample
+    pub samples: usize,             // Stored so far
+    pub errors: usize,
+    pub recording: bool,
+}
+
+/// Lays {status} out on a frame: Roll and pitch large, for reading them from the helm, and the rest below
+pub fn render(status: &DisplayStatus) -> FrameBuffer {
+    let mut frame = FrameBuffer::new();
+    let angle = |angle: Option<f64>| match angle {
+        Some(angle) => format!("{angle:6.1}°"),
+        None => "    --".to_string(),
+    };
+    let attitude = status.attitude;
+    frame.draw_text(0, 0, "R", 2);
+    frame.draw_text(24, 0, &angle(attitude.map(|attitude| attitude.roll)), 2);
+    frame.draw_text(0, 18, "P", 2);
+    frame.draw_text(24, 18, &angle(attitude.map(|attitude| attitude.pitch)), 2);
+    let heading = attitude
+        .and_then(|attitude| attitude.yaw)
+        .map(crate::math::angle::wrap_to_360);
+    frame.draw_text(0, 36, &format!("HDG {}", angle(heading)), 1);
+    frame.draw_text(0, 46, &format!("SAMPLES {}", status.samples), 1);
+    let state = if status.recording { "REC" } else { "PAUSED" };
+    frame.draw_text(0, 56, &format!("ERRORS {} {state:>6}", status.errors), 1);
+    frame
+}
+
+/// Shows the latest `DisplayStatus` on an SSD1306, redrawn at most every {refresh} on its own thread. It is blank once dropped
+pub struct Display {
+    sender: Option<Sender<DisplayStatus>>,
+    drawer: Option<JoinHandle<()>>,
+}
+
+impl Display {
+    /// Takes over {driver}, initialized, with its own {i2c}
+    pub fn new(driver: ssd1306::SSD1306, i2c: I2c, refresh: Duration) -> Result<Self> {
+        let (sender, receiver) = crossbeam_channel::unbounded::<DisplayStatus>();
+        let drawer = std::thread::Builder::new()
+            .name("display".to_string())
+            .spawn(move || {
+                let mut last_draw: Option<Instant> = None;
+                let mut pending: Option<DisplayStatus> = None;
+                loop {
+                    let wait = last_draw.map_or(Duration::ZERO, |last_draw| {
+                        (last_draw + refresh).saturating_duration_since(Instant::now())
+                    });
+                    // Waiting for the first status to draw, or for the refresh period to pass with one pending, taking in newer ones meanwhile
+                    let status = match pending {
+                        None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                        Some(_) => receiver.recv_timeout(wait),
+                    };
+                    match status {
+                        Ok(status) => {
+                            pending = Some(status);
+                            if !wait.is_zero() {
+                                continue;
+                            }
+                        }
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                    if let Some(status) = pending.take() {
+                        // A display coming loose shouldn't stop anything else, and the next frame tries again
+                        let _ = driver.flush(&i2c, &render(&status));
+                        last_draw = Some(Instant::now());
+                    }
+                }
+                let _ = driver.flush(&i2c, &FrameBuffer::new());
+            })
+            .context("Unable to start display.")?;
+        Ok(Self {
+            sender: Some(sender),
+            drawer: Some(drawer),
+        })
+    }
+
+    /// Shows {status} with the next refresh. Cheap enough for every round of the sampling loop
+    pub fn show(&self, status: DisplayStatus) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(status);
+        }
+    }
+}
+
+impl Drop for Display {
+    fn drop(&mut self) {
+        // Without a sender, the drawer blanks the display and ends
+        self.sender.take();
+        if let Some(drawer) = self.drawer.take() {
+            let _ = drawer.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut frame = FrameBuffer::new();
+        // '1' at its natural size: The stem in the middle column, and the foot along the bottom row
+        assert_eq!(frame.draw_text(0, 0, "1", 1), 6);
+        assert!((0..7).all(|y| frame.pixel(2, y)));
+        assert!((1..4).all(|x| frame.pixel(x, 6)));
+        assert!(!frame.pixel(0, 0) && !frame.pixel(4, 0));
+        assert_eq!(frame.bytes()[2], 0x7F);
+
+        // Doubled, each pixel becomes 2x2, and off the display is left out
+        frame.clear();
+        assert_eq!(frame.draw_text(120, 60, "1", 2), 132);
+        assert!(frame.pixel(124, 60) && frame.pixel(125, 63));
+        assert_eq!(glyph('a'), glyph('A'));
+        assert_eq!(glyph('~'), glyph('?'));
+
+        let frame = render(&DisplayStatus {
+            attitude: Some(Attitude {
+                time: 0.0,
+                roll: -12.3,
+                pitch: 4.5,
+                yaw: Some(-90.0),
+            }),
+            samples: 42,
+            errors: 0,
+            recording: true,
+        });
+        assert_ne!(frame, FrameBuffer::new());
+    }
+}
@@ -0,0 +1,126 @@
+// Solomon Systech SSD1306 driver of the common 128x64 monochrome OLEDs on the I2C bus.
+// Command reference: https://cdn-shop.adafruit.com/datasheets/SSD1306.pdf
+
+use anyhow::{Context, Result};
+use rppal::i2c::I2c;
+
+use super::{FrameBuffer, HEIGHT, WIDTH};
+use crate::utilites;
+
+pub const I2C_ADDRESS: u16 = 0x3C; // 0x3D on boards with the address jumper moved
+
+// Control bytes, preceding what follows
+const COMMANDS: u8 = 0x00;
+const DATA: u8 = 0x40;
+
+const CHUNK: usize = 32; // Bytes of data per write, the most an I2C block write takes
+
+// Commands
+const DISPLAY_OFF: u8 = 0xAE;
+const DISPLAY_ON: u8 = 0xAF;
+const SET_CONTRAST: u8 = 0x81;
+const SET_COLUMN_ADDRESS: u8 = 0x21;
+const SET_PAGE_ADDRESS: u8 = 0x22;
+const SEGMENT_REMAP: u8 = 0xA0; // | 1 maps column 127 to SEG0
+const COM_SCAN: u8 = 0xC0; // | 0x08 scans from COM63 to COM0
+
+// Commands with their arguments, setting the display up
+const SETUP: [&[u8]; 12] = [
+    &[0xD5, 0x80], // Clock divide ratio and oscillator frequency, as after reset
+    &[0xA8, HEIGHT as u8 - 1], // Multiplex ratio
+    &[0xD3, 0x00], // No display offset
+    &[0x40],       // Start line 0
+    &[0x8D, 0x14], // Charge pump on
+    &[0x20, 0x00], // Horizontal addressing, wrapping from page to page
+    &[0xDA, 0x12], // Alternative COM pin configuration, for 64 rows
+    &[0xD9, 0xF1], // Pre-charge period, for the charge pump
+    &[0xDB, 0x40], // VCOMH deselect level
+    &[0xA4],       // Show the RAM
+    &[0xA6],       // Not inverted
+    &[0x2E],       // No scrolling
+];
+
+/// The display. It shares the bus with the GY-521, but needs its own `I2c`, since that is set to the GY-521's address once
+#[non_exhaustive]
+pub struct SSD1306 {
+    pub i2c_address: u16,
+    pub contrast: u8,
+    pub rotated: bool, // By 180 degrees, for a display mounted upside down
+    pub retry_policy: utilites::RetryPolicy, // Applied to all writes
+}
+
+impl SSD1306 {
+    pub fn new(i2c_address: u16) -> Self {
+        Self {
+            i2c_address,
+            contrast: 0xCF,
+            rotated: false,
+            retry_policy: Default::default(),
+        }
+    }
+
+    fn command(&self, i2c: &I2c, commands: &[u8]) -> Result<()> {
+        self.retry_policy
+            .run(|| i2c.block_write(COMMANDS, commands))
+            .with_context(|| format!("Unable to send display commands {commands:02x?}."))
+    }
+
+    /// Sets the display up for the internal charge pump, clears it, and switches it on
+    pub fn initialize(&mut self, i2c: &mut I2c) -> Result<()> {
+        i2c.set_slave_address(self.i2c_address)?;
+        self.command(i2c, &[DISPLAY_OFF])?;
+        for command in SETUP {
+            self.command(i2c, command)?;
+        }
+        self.set_rotation(i2c, self.rotated)?;
+        self.set_contrast(i2c, self.contrast)?;
+        self.flush(i2c, &FrameBuffer::new())?;
+        self.command(i2c, &[DISPLAY_ON])
+    }
+
+    /// Flips the display by 180 degrees, or back
+    pub fn set_rotation(&mut self, i2c: &I2c, rotated: bool) -> Result<()> {
+        self.rotated = rotated;
+        match rotated {
+            false => self.command(i2c, &[SEGMENT_REMAP | 1, COM_SCAN | 0x08]),
+            true => self.command(i2c, &[SEGMENT_REMAP, COM_SCAN]),
+        }
+    }
+
+    pub fn set_contrast(&mut self, i2c: &I2c, contrast: u8) -> Result<()> {
+        self.contrast = contrast;
+        self.command(i2c, &[SET_CONTRAST, contrast])
+    }
+
+    /// Switches the display off, keeping what it shows, or on again. Off, it draws next to nothing
+    pub fn set_on(&self, i2c: &I2c, on: bool) -> Result<()> {
+        self.command(i2c, &[if on { DISPLAY_ON } else { DISPLAY_OFF }])
+    }
+
+    /// Shows {frame}
+    pub fn flush(&self, i2c: &I2c, frame: &FrameBuffer) -> Result<()> {
+        self.command(
+            i2c,
+            &[
+                SET_COLUMN_ADDRESS,
+                0,
+                WIDTH as u8 - 1,
+                SET_PAGE_ADDRESS,
+                0,
+                (HEIGHT / 8) as u8 - 1,
+            ],
+        )?;
+        for chunk in frame.bytes().chunks(CHUNK) {
+            self.retry_policy
+                .run(|| i2c.block_write(DATA, chunk))
+                .context("Unable to write display data.")?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for SSD1306 {
+    fn default() -> Self {
+        Self::new(I2C_ADDRESS)
+    }
+}
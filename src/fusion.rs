@@ -0,0 +1,154 @@
+// Attitude estimation from gyroscope and accelerometer samples.
+// Angular velocities are expected in [degree/s], accelerations in [g], like `GY521::read` provides them.
+// Angles are in [rad], following the conventions of `math::EulerAngles`.
+
+use crate::{
+    gy521::SensorSample,
+    math::{angle, EulerAngles, Quaternion, RotationOrder, Vec3D},
+};
+
+mod complementary;
+mod dead_reckoning;
+mod ekf;
+mod gravity;
+mod madgwick;
+mod mahony;
+
+pub use complementary::ComplementaryFilter;
+pub use dead_reckoning::{DeadReckoning, Drift, ZeroVelocityDetector};
+pub use ekf::ExtendedKalmanFilter;
+pub use gravity::{GravityRemoval, LinearAcceleration};
+pub use madgwick::Madgwick;
+pub use mahony::Mahony;
+
+/// Common interface of the attitude filters, so that downstream code can swap algorithms.
+/// The orientation rotates vectors from the sensor frame into the earth frame, whose z axis points up.
+pub trait AttitudeFilter {
+    /// Updates the estimate with a sample taken {sample_period} [s] after the previous one, and returns the new orientation.
+    fn update_attitude(
+        &mut self,
+        sample: &SensorSample<Vec3D, f64>,
+        sample_period: f64,
+    ) -> Quaternion;
+
+    fn orientation(&self) -> Quaternion;
+
+    /// Starts over, as if no sample had been seen yet
+    fn reset(&mut self);
+
+    fn euler_angles(&self, order: RotationOrder) -> EulerAngles {
+        EulerAngles::from_quaternion(&self.orientation(), order)
+    }
+}
+
+/// Roll and pitch [rad] of a sensor at rest, from the direction of gravity in {acceleration}.
+/// The accelerometer reads +1 g on an axis pointing up. Yaw is unobservable from gravity.
+/// Roll covers the full circle (-pi, pi], so upside down is a roll of pi, not a mirrored pitch. Pitch stays within [-pi/2, pi/2], matching the ZYX order of `EulerAngles`.
+/// Only the direction matters, so the magnitude of {acceleration} needn't be 1 g.
+/// Near vertical (pitch close to +-pi/2), y and z both approach zero, so roll is dominated by noise, and at exactly vertical it is undefined (reported as 0). This is gimbal lock, not a bug: Use a quaternion based filter if the sensor can point straight up or down.
+pub fn accelerometer_tilt(acceleration: &Vec3D) -> (f64, f64) {
+    let roll = acceleration.y.atan2(acceleration.z);
+    let pitch = (-acceleration.x).atan2((acceleration.y.powi(2) + acceleration.z.powi(2)).sqrt());
+    (roll, pitch)
+}
+
+/// Compass heading [rad] of the sensor's x axis, clockwise from true north in [0, 2 pi), e.g., 90 degrees when pointing east.
+/// {magnetometer} and {acceleration} are taken at the same time, in the sensor frame. The magnetometer's unit doesn't matter, but hard and soft iron effects need to be calibrated out already.
+/// The magnetic field is rotated back to horizontal with the tilt from `accelerometer_tilt`, so the sensor needn't be level, but it should be at rest, and the same singularity near vertical applies.
+/// {declination} [rad] is the angle from true north to magnetic north, positive towards east. It depends on location and year, so look it up, e.g., at https://www.ngdc.noaa.gov/geomag/calculators/magcalc.shtml
+pub fn tilt_compensated_heading(
+    magnetometer: &Vec3D,
+    acceleration: &Vec3D,
+    declination: f64,
+) -> f64 {
+    let (roll, pitch) = accelerometer_tilt(acceleration);
+    let horizontal = Quaternion::from_euler_angles(roll, pitch, 0.0).rotate(magnetometer);
+    // In North-West-Up, y points west, so the field appears on +y when the sensor turns east
+    let magnetic_heading = horizontal.y.atan2(horizontal.x);
+    angle::wrap_to_two_pi(magnetic_heading + declination)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub(super) fn sample(acceleration: Vec3D, angular_velocity: Vec3D) -> SensorSample<Vec3D, f64> {
+        SensorSample::new(acceleration, angular_velocity, None)
+    }
+
+    // Reading of a sensor at rest in {orientation}: The earth's up direction, seen from the sensor
+    pub(super) fn gravity(orientation: &Quaternion) -> Vec3D {
+        orientation.conjugate().rotate(&Vec3D::new(0, 0, 1))
+    }
+
+    #[test]
+    fn test_accelerometer_tilt() {
+        for (roll, pitch) in [(30, 20), (-45, 60), (150, -30), (-170, 80), (180, 0)] {
+            let (roll, pitch) = (f64::from(roll).to_radians(), f64::from(pitch).to_radians());
+            let orientation = Quaternion::from_euler_angles(roll, pitch, 1.0);
+            // At rest, the sensor measures up, rotated into its own frame. Scaled, since only the direction counts
+            let acceleration = orientation.conjugate().rotate(&Vec3D::new(0, 0, 1)) * 0.9;
+            let sample = SensorSample::new(acceleration, Vec3D::default(), None);
+            let (estimated_roll, estimated_pitch) = sample.tilt();
+            // Roll wraps around at pi
+            assert!(
+                (estimated_roll - roll).sin().abs() < 1e-9 && (estimated_roll - roll).cos() > 0.0
+            );
+            assert!((estimated_pitch - pitch).abs() < 1e-9);
+        }
+
+        // Straight up: Roll is undefined, but the result is finite
+        let (roll, pitch) = accelerometer_tilt(&Vec3D::new(-1, 0, 0));
+        assert_eq!(roll, 0.0);
+        assert!((pitch - std::f64::consts::FRAC_PI_2).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_tilt_compensated_heading() {
+        // Northern hemisphere: The field points north and down
+        let field = Vec3D::new(0.2, 0.0, -0.45); // [G]
+        let declination = 3_f64.to_radians();
+        for (roll, pitch, heading) in [(0, 0, 0), (0, 0, 90), (20, -30, 135), (-40, 15, 250)] {
+            let heading = f64::from(heading).to_radians();
+            // Yaw counts counterclockwise, heading clockwise
+            let orientation = Quaternion::from_euler_angles(
+                f64::from(roll).to_radians(),
+                f64::from(pitch).to_radians(),
+                -heading,
+            );
+            let magnetometer = orientation.conjugate().rotate(&field);
+            let acceleration = orientation.conjugate().rotate(&Vec3D::new(0, 0, 1));
+            let estimate = tilt_compensated_heading(&magnetometer, &acceleration, declination);
+            assert!((estimate - (heading + declination)).abs() < 1e-9);
+        }
+
+        // Wraps around north
+        let heading = tilt_compensated_heading(&Vec3D::new(1, 0, 0), &Vec3D::new(0, 0, 1), -0.1);
+        assert!((heading - (2.0 * std::f64::consts::PI - 0.1)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_attitude_filters() {
+        let roll = 30_f64.to_radians();
+        let sample = SensorSample::new(
+            Vec3D::new(0.0, roll.sin(), roll.cos()),
+            Vec3D::default(),
+            None,
+        );
+        let mut filters: Vec<Box<dyn AttitudeFilter>> = vec![
+            Box::new(ComplementaryFilter::new(0.98, 0.01)),
+            Box::new(ExtendedKalmanFilter::new(0.1, 0.02, 0.01)),
+            Box::new(Madgwick::new(0.1, 0.01)),
+            Box::new(Mahony::new(1.0, 0.0, 0.01)),
+        ];
+        for filter in &mut filters {
+            for _ in 0..2000 {
+                filter.update_attitude(&sample, 0.01);
+            }
+            assert!((filter.euler_angles(RotationOrder::ZYX).roll - roll).abs() < 1e-2);
+
+            filter.reset();
+            assert_eq!(filter.euler_angles(RotationOrder::ZYX).roll, 0.0);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use std::f64::consts::PI;
+
+use crate::{
+    gy521::SensorSample,
+    math::{angle, EulerAngles, Quaternion, RotationOrder, Vec3D},
+};
+
+/// Fuses the accelerometer's tilt with integrated gyroscope rates into roll and pitch.
+/// The gyroscope is trusted in the short run, the accelerometer (which is noisy and disturbed by motion, but doesn't drift) in the long run.
+pub struct ComplementaryFilter {
+    pub alpha: f64, // [1] Weight of the gyroscope in each update. The rest goes to the accelerometer
+    pub sample_period: f64, // [s] Used by `update`
+    pub roll: f64,  // [rad]
+    pub pitch: f64, // [rad]
+    initialized: bool, // The first sample takes the accelerometer's tilt as it is
+}
+
+impl ComplementaryFilter {
+    pub fn new(alpha: f64, sample_period: f64) -> Self {
+        Self {
+            alpha,
+            sample_period,
+            roll: 0.0,
+            pitch: 0.0,
+            initialized: false,
+        }
+    }
+
+    /// Chooses alpha, such that disturbances of the accelerometer shorter than {time_constant} [s] are mostly ignored.
+    pub fn from_time_constant(time_constant: f64, sample_period: f64) -> Self {
+        Self::new(
+            time_constant / (time_constant + sample_period),
+            sample_period,
+        )
+    }
+
+    /// Updates the estimate with a sample taken one `sample_period` after the previous one.
+    pub fn update(&mut self, sample: &SensorSample<Vec3D, f64>) -> EulerAngles {
+        self.update_with_period(sample, self.sample_period)
+    }
+
+    /// Updates the estimate with a sample taken {sample_period} [s] after the previous one, for irregularly spaced samples.
+    pub fn update_with_period(
+        &mut self,
+        sample: &SensorSample<Vec3D, f64>,
+        sample_period: f64,
+    ) -> EulerAngles {
+        let (accelerometer_roll, accelerometer_pitch) =
+            super::accelerometer_tilt(&sample.acceleration());
+
+        if !self.initialized {
+            self.roll = accelerometer_roll;
+            self.pitch = accelerometer_pitch;
+            self.initialized = true;
+            return self.angles();
+        }
+
+        // Body rates to Euler angle rates
+        let rate = sample.angular_velocity() * (PI / 180.0);
+        let (sin_roll, cos_roll) = self.roll.sin_cos();
+        let roll_rate = rate.x + (rate.y * sin_roll + rate.z * cos_roll) * self.pitch.tan();
+        let pitch_rate = rate.y * cos_roll - rate.z * sin_roll;
+
+        let gyroscope_roll = self.roll + roll_rate * sample_period;
+        let gyroscope_pitch = self.pitch + pitch_rate * sample_period;
+
+        // Roll wraps around at +-180 degrees. Blending across the wrap would average towards 0
+        let roll_difference = angle::wrap_to_pi(accelerometer_roll - gyroscope_roll);
+        self.roll = angle::wrap_to_pi(gyroscope_roll + (1.0 - self.alpha) * roll_difference);
+        self.pitch = self.alpha * gyroscope_pitch + (1.0 - self.alpha) * accelerometer_pitch;
+        self.angles()
+    }
+
+    /// Current estimate. Yaw is always 0, as it can't be observed from the accelerometer
+    pub fn angles(&self) -> EulerAngles {
+        EulerAngles::new(self.roll, self.pitch, 0.0, RotationOrder::ZYX)
+    }
+
+    /// Starts over from the next sample's accelerometer tilt
+    pub fn reset(&mut self) {
+        self.roll = 0.0;
+        self.pitch = 0.0;
+        self.initialized = false;
+    }
+}
+
+impl super::AttitudeFilter for ComplementaryFilter {
+    fn update_attitude(
+        &mut self,
+        sample: &SensorSample<Vec3D, f64>,
+        sample_period: f64,
+    ) -> Quaternion {
+        self.update_with_period(sample, sample_period)
+            .to_quaternion()
+    }
+
+    fn orientation(&self) -> Quaternion {
+        self.angles().to_quaternion()
+    }
+
+    fn reset(&mut self) {
+        ComplementaryFilter::reset(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fusion::tests::sample;
+
+    #[test]
+    fn test_accelerometer_tilt() {
+        let roll = 20_f64.to_radians();
+        let mut filter = ComplementaryFilter::new(0.98, 0.01);
+        // The first sample initializes from the accelerometer
+        let angles = filter.update(&sample(
+            Vec3D::new(0.0, roll.sin(), roll.cos()),
+            Vec3D::default(),
+        ));
+        assert!((angles.roll - roll).abs() < 1e-9);
+        assert!(angles.pitch.abs() < 1e-9);
+
+        // A wrong estimate converges towards the accelerometer's tilt
+        filter.roll = 0.0;
+        for _ in 0..1000 {
+            filter.update(&sample(
+                Vec3D::new(0.0, roll.sin(), roll.cos()),
+                Vec3D::default(),
+            ));
+        }
+        assert!((filter.roll - roll).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_gyroscope_integration() {
+        let mut filter = ComplementaryFilter::new(1.0, 0.01); // Gyroscope only
+        filter.update(&sample(Vec3D::new(0, 0, 1), Vec3D::default()));
+        // Pitching at 10 degree/s for one second
+        for _ in 0..100 {
+            filter.update(&sample(Vec3D::new(0, 0, 1), Vec3D::new(0, 10, 0)));
+        }
+        assert!((filter.pitch - 10_f64.to_radians()).abs() < 1e-9);
+        assert!(filter.roll.abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_roll_wrap() {
+        let mut filter = ComplementaryFilter::from_time_constant(0.5, 0.01);
+        // Upside down, with the accelerometer's roll flipping between +-180 degrees
+        for i in 0..1000 {
+            let y = if i % 2 == 0 { 1e-3 } else { -1e-3 };
+            filter.update(&sample(Vec3D::new(0.0, y, -1.0), Vec3D::default()));
+        }
+        assert!((filter.roll.abs() - PI).abs() < 1e-2);
+    }
+}
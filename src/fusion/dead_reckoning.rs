@@ -0,0 +1,232 @@
+use crate::{
+    gy521::SensorSample,
+    math::{Matrix, Vec3D},
+};
+
+use super::LinearAcceleration;
+
+/// Decides when the sensor stands still, so that the integrated velocity can be reset (zero velocity update, ZUPT).
+pub struct ZeroVelocityDetector {
+    pub acceleration_tolerance: f64, // [g] Largest deviation of the acceleration's magnitude from 1 g
+    pub angular_velocity_threshold: f64, // [degree/s] Largest angular velocity
+    pub samples: usize, // Consecutive still samples required, so that the sensor isn't just passing through a still moment
+    still: usize,
+}
+
+impl ZeroVelocityDetector {
+    pub fn new(samples: usize) -> Self {
+        Self {
+            acceleration_tolerance: 0.02,
+            angular_velocity_threshold: 1.0,
+            samples,
+            still: 0,
+        }
+    }
+
+    /// True once the last `samples` samples, including {sample}, were all still
+    pub fn update(&mut self, sample: &SensorSample<Vec3D, f64>) -> bool {
+        let still = (sample.acceleration().norm() - 1.0).abs() <= self.acceleration_tolerance
+            && sample.angular_velocity().norm() <= self.angular_velocity_threshold;
+        self.still = if still { self.still + 1 } else { 0 };
+        self.still >= self.samples
+    }
+
+    /// Forgets the still samples so far
+    pub fn reset(&mut self) {
+        self.still = 0;
+    }
+}
+
+/// Estimated error of the dead reckoning [1 sigma, per axis]
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct Drift {
+    pub velocity: f64,            // [m/s]
+    pub position: f64,            // [m]
+    pub since_zero_velocity: f64, // [s] Time since the last zero velocity update, or since the start
+}
+
+/// Integrates linear acceleration in the earth frame to velocity and position.
+/// Errors grow quickly (position error with time cubed for a constant bias), so this is only good for short maneuvers, or with frequent zero velocity updates.
+pub struct DeadReckoning {
+    pub velocity: Vec3D,                             // [m/s]
+    pub position: Vec3D,                             // [m]
+    pub acceleration_noise: f64, // [m/s^2] Standard deviation of the white noise of the linear acceleration
+    pub acceleration_bias: f64, // [m/s^2] Expected residual bias of the linear acceleration, e.g., from calibration or attitude errors
+    pub zero_velocity: Option<ZeroVelocityDetector>, // None disables zero velocity updates
+    pub zero_velocity_updates: usize,
+    previous_acceleration: Option<Vec3D>, // [m/s^2]
+    covariance: Matrix<2, 2>, // Of (position, velocity) due to noise, equal for each axis
+    bias_error: (f64, f64),   // (position [m], velocity [m/s]) due to the bias
+    since_zero_velocity: f64, // [s]
+}
+
+impl DeadReckoning {
+    pub fn new(acceleration_noise: f64, acceleration_bias: f64) -> Self {
+        Self {
+            velocity: Vec3D::default(),
+            position: Vec3D::default(),
+            acceleration_noise,
+            acceleration_bias,
+            zero_velocity: None,
+            zero_velocity_updates: 0,
+            previous_acceleration: None,
+            covariance: Matrix::ZERO,
+            bias_error: (0.0, 0.0),
+            since_zero_velocity: 0.0,
+        }
+    }
+
+    /// Enables zero velocity updates after {samples} consecutive still samples.
+    pub fn enable_zero_velocity_updates(&mut self, samples: usize) {
+        self.zero_velocity = Some(ZeroVelocityDetector::new(samples));
+    }
+
+    /// Integrates the {linear} acceleration of a {sample} taken {sample_period} [s] after the previous one. The sample itself is only used for detecting standstill.
+    pub fn update(
+        &mut self,
+        sample: &SensorSample<Vec3D, f64>,
+        linear: &LinearAcceleration,
+        sample_period: f64,
+    ) {
+        let acceleration = linear.earth_si();
+        if self
+            .zero_velocity
+            .as_mut()
+            .is_some_and(|detector| detector.update(sample))
+        {
+            self.zero_velocity_update();
+            self.previous_acceleration = Some(acceleration);
+            return;
+        }
+
+        // The first sample only marks the start
+        let previous_acceleration = match self.previous_acceleration.replace(acceleration) {
+            Some(previous_acceleration) => previous_acceleration,
+            None => return,
+        };
+
+        // Trapezoidal rule, which is exact for constant and linearly changing accelerations
+        let previous_velocity = self.velocity;
+        self.velocity += (previous_acceleration + acceleration) * (0.5 * sample_period);
+        self.position += (previous_velocity + self.velocity) * (0.5 * sample_period);
+
+        let transition = Matrix::new([[1.0, sample_period], [0.0, 1.0]]);
+        let noise = (self.acceleration_noise * sample_period).powi(2);
+        self.covariance = transition * self.covariance * transition.transpose()
+            + Matrix::new([[0.0, 0.0], [0.0, noise]]);
+        let (position_error, velocity_error) = self.bias_error;
+        let velocity_error_next = velocity_error + self.acceleration_bias * sample_period;
+        self.bias_error = (
+            position_error + (velocity_error + velocity_error_next) * (0.5 * sample_period),
+            velocity_error_next,
+        );
+        self.since_zero_velocity += sample_period;
+    }
+
+    /// Sets the velocity to zero, e.g., when standstill is known from elsewhere. The position's error remains.
+    pub fn zero_velocity_update(&mut self) {
+        self.velocity = Vec3D::default();
+        self.covariance[(0, 1)] = 0.0;
+        self.covariance[(1, 0)] = 0.0;
+        self.covariance[(1, 1)] = 0.0;
+        self.bias_error.1 = 0.0;
+        self.since_zero_velocity = 0.0;
+        self.zero_velocity_updates += 1;
+    }
+
+    pub fn drift(&self) -> Drift {
+        let (position_error, velocity_error) = self.bias_error;
+        Drift {
+            velocity: self.covariance[(1, 1)].sqrt() + velocity_error.abs(),
+            position: self.covariance[(0, 0)].sqrt() + position_error.abs(),
+            since_zero_velocity: self.since_zero_velocity,
+        }
+    }
+
+    /// Starts over at the origin, at rest
+    pub fn reset(&mut self) {
+        self.velocity = Vec3D::default();
+        self.position = Vec3D::default();
+        self.zero_velocity_updates = 0;
+        self.previous_acceleration = None;
+        self.covariance = Matrix::ZERO;
+        self.bias_error = (0.0, 0.0);
+        self.since_zero_velocity = 0.0;
+        if let Some(detector) = &mut self.zero_velocity {
+            detector.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{gy521, math::Quaternion};
+
+    // Sample and its linear acceleration for a level sensor, accelerating with {acceleration} [m/s^2]
+    fn level(acceleration: Vec3D) -> (SensorSample<Vec3D, f64>, LinearAcceleration) {
+        let measured = acceleration / gy521::g + Vec3D::new(0, 0, 1);
+        (
+            SensorSample::new(measured, Vec3D::default(), None),
+            LinearAcceleration::new(&measured, &Quaternion::IDENTITY),
+        )
+    }
+
+    #[test]
+    fn test_constant_acceleration() {
+        let mut integrator = DeadReckoning::new(0.01, 0.0);
+        let (sample, linear) = level(Vec3D::new(1, 0, 0));
+        for _ in 0..=100 {
+            integrator.update(&sample, &linear, 0.01);
+        }
+        assert!(integrator.velocity.approx_eq(&Vec3D::new(1, 0, 0), 1e-9));
+        assert!(integrator.position.approx_eq(&Vec3D::new(0.5, 0, 0), 1e-9));
+    }
+
+    #[test]
+    fn test_zero_velocity_update() {
+        let mut integrator = DeadReckoning::new(0.05, 0.01);
+        integrator.enable_zero_velocity_updates(10);
+
+        let (moving, moving_linear) = level(Vec3D::new(2, 0, 0));
+        for _ in 0..50 {
+            integrator.update(&moving, &moving_linear, 0.01);
+        }
+        let drift = integrator.drift();
+        assert!(integrator.velocity.x > 0.9);
+        assert!(drift.velocity > 0.0 && drift.position > 0.0);
+        assert_eq!(integrator.zero_velocity_updates, 0);
+
+        let (still, still_linear) = level(Vec3D::default());
+        for _ in 0..10 {
+            integrator.update(&still, &still_linear, 0.01);
+        }
+        assert_eq!(integrator.zero_velocity_updates, 1);
+        assert_eq!(integrator.velocity, Vec3D::default());
+        assert_eq!(integrator.drift().velocity, 0.0);
+        assert!(integrator.drift().position >= drift.position);
+        assert_eq!(integrator.drift().since_zero_velocity, 0.0);
+
+        // Still samples from before a reset don't count towards the next update
+        for _ in 0..9 {
+            integrator.update(&still, &still_linear, 0.01);
+        }
+        integrator.reset();
+        integrator.update(&still, &still_linear, 0.01);
+        assert_eq!(integrator.zero_velocity_updates, 0);
+    }
+
+    #[test]
+    fn test_drift_growth() {
+        // A constant bias b gives a velocity error of b t and a position error of b t^2 / 2
+        let mut integrator = DeadReckoning::new(0.0, 0.1);
+        let (sample, linear) = level(Vec3D::default());
+        for _ in 0..=200 {
+            integrator.update(&sample, &linear, 0.01);
+        }
+        let drift = integrator.drift();
+        assert!((drift.velocity - 0.2).abs() < 1e-9);
+        assert!((drift.position - 0.2).abs() < 1e-9);
+        assert!((drift.since_zero_velocity - 2.0).abs() < 1e-9);
+    }
+}
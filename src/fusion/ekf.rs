@@ -0,0 +1,290 @@
+use std::{f64::consts::PI, time::Instant};
+
+use crate::{
+    gy521::SensorSample,
+    math::{KalmanFilter, Matrix, Quaternion, Vec3D},
+};
+
+/// Extended Kalman filter for the orientation and the gyroscope's bias. Frames are the same as for `Madgwick`.
+/// The quaternion is kept outside of the filter, which only tracks small errors of it (error state or multiplicative EKF). This keeps the covariance free of the quaternion's unit norm constraint.
+/// Error states: [attitude error about the sensor axes [rad], bias error [rad/s]]
+pub struct ExtendedKalmanFilter {
+    pub gyroscope_noise: f64, // [degree/s] Standard deviation of the gyroscope's white noise
+    pub gyroscope_bias_drift: f64, // [degree/s/sqrt(s)] How quickly the bias wanders (rate random walk)
+    pub accelerometer_noise: f64,  // [g] Standard deviation of the accelerometer's white noise
+    pub acceleration_sensitivity: f64, // [1] How quickly trust in the accelerometer drops, when its magnitude deviates from 1 g due to motion. 0 disables the adaptation
+    pub sample_period: f64,            // [s] Used by `update` and for the first `update_at`
+    pub orientation: Quaternion,
+    bias: Vec3D, // [rad/s]
+    filter: KalmanFilter<6, 3>,
+    previous: Option<Instant>, // Instant of the previous sample given to `update_at`
+    initialized: bool,         // The first sample takes the accelerometer's tilt as it is
+}
+
+impl ExtendedKalmanFilter {
+    const INITIAL_ATTITUDE_VARIANCE: f64 = 0.01; // [rad^2]
+    const INITIAL_BIAS_VARIANCE: f64 = 3e-4; // [(rad/s)^2] About (1 degree/s)^2
+
+    /// Noise figures as found with `NoiseCharacterization`, or on the data sheet.
+    pub fn new(gyroscope_noise: f64, accelerometer_noise: f64, sample_period: f64) -> Self {
+        Self {
+            gyroscope_noise,
+            gyroscope_bias_drift: 0.01,
+            accelerometer_noise,
+            acceleration_sensitivity: 1.0,
+            sample_period,
+            orientation: Quaternion::IDENTITY,
+            bias: Vec3D::default(),
+            filter: KalmanFilter::new(
+                [0.0; 6],
+                initial_covariance(),
+                Matrix::identity(),
+                Matrix::ZERO,
+                Matrix::ZERO,
+                Matrix::ZERO,
+            ),
+            previous: None,
+            initialized: false,
+        }
+    }
+
+    /// Current estimate of the gyroscope's bias [degree/s]. Bias about the vertical axis is unobservable from gravity and stays near its initial value.
+    pub fn gyroscope_bias(&self) -> Vec3D {
+        self.bias * 180.0 / PI
+    }
+
+    /// Standard deviation of the attitude estimate about the sensor axes [rad]
+    pub fn attitude_uncertainty(&self) -> Vec3D {
+        let covariance = &self.filter.covariance;
+        Vec3D::new(
+            covariance[(0, 0)].sqrt(),
+            covariance[(1, 1)].sqrt(),
+            covariance[(2, 2)].sqrt(),
+        )
+    }
+
+    /// Updates the orientation with a sample taken one `sample_period` after the previous one.
+    pub fn update(&mut self, sample: &SensorSample<Vec3D, f64>) -> Quaternion {
+        self.update_with_period(sample, self.sample_period)
+    }
+
+    /// Updates the orientation with a sample taken at {instant}, e.g., as returned by `GY521::wait_for_sample`.
+    /// The time since the previous sample is used for propagation, so late or missed samples are handled gracefully.
+    pub fn update_at(&mut self, sample: &SensorSample<Vec3D, f64>, instant: Instant) -> Quaternion {
+        let sample_period = self.previous.map_or(self.sample_period, |previous| {
+            instant.saturating_duration_since(previous).as_secs_f64()
+        });
+        self.previous = Some(instant);
+        self.update_with_period(sample, sample_period)
+    }
+
+    /// Updates the orientation with a sample taken {sample_period} [s] after the previous one.
+    pub fn update_with_period(
+        &mut self,
+        sample: &SensorSample<Vec3D, f64>,
+        sample_period: f64,
+    ) -> Quaternion {
+        let acceleration = sample.acceleration();
+        if !self.initialized {
+            if acceleration.norm() > Vec3D::THRESHOLD {
+                let (roll, pitch) = super::accelerometer_tilt(&acceleration);
+                self.orientation = Quaternion::from_euler_angles(roll, pitch, 0.0);
+            }
+            self.initialized = true;
+            return self.orientation;
+        }
+
+        self.predict(sample.angular_velocity() * (PI / 180.0), sample_period);
+        if acceleration.norm() > Vec3D::THRESHOLD {
+            self.correct(&acceleration);
+        }
+        self.orientation
+    }
+
+    fn predict(&mut self, angular_velocity: Vec3D, sample_period: f64) {
+        let rate = angular_velocity - self.bias;
+        self.orientation =
+            (self.orientation * rotation_vector(&(rate * sample_period))).normalized();
+
+        // Error dynamics: attitude' = -[rate x] attitude - bias, bias' = 0
+        let mut transition = Matrix::<6, 6>::identity();
+        let rotation = skew(&(rate * -sample_period));
+        for row in 0..3 {
+            for column in 0..3 {
+                transition[(row, column)] += rotation[(row, column)];
+            }
+            transition[(row, row + 3)] = -sample_period;
+        }
+
+        let angle_variance = (self.gyroscope_noise.to_radians() * sample_period).powi(2);
+        let bias_variance = self.gyroscope_bias_drift.to_radians().powi(2) * sample_period;
+        self.filter.transition = transition;
+        self.filter.process_noise = Matrix::from_diagonal([
+            angle_variance,
+            angle_variance,
+            angle_variance,
+            bias_variance,
+            bias_variance,
+            bias_variance,
+        ]);
+        self.filter.predict();
+    }
+
+    fn correct(&mut self, acceleration: &Vec3D) {
+        // Direction of gravity (up) in the sensor frame. A small attitude error e changes it by gravity x e
+        let gravity = self.orientation.conjugate().rotate(&Vec3D::new(0, 0, 1));
+        let mut observation = Matrix::<3, 6>::ZERO;
+        let jacobian = skew(&gravity);
+        for row in 0..3 {
+            for column in 0..3 {
+                observation[(row, column)] = jacobian[(row, column)];
+            }
+        }
+
+        // Motion shows as a magnitude different from 1 g. The accelerometer's direction is trusted less, the larger the difference
+        let deviation = (acceleration.norm() - 1.0).abs();
+        let variance =
+            self.accelerometer_noise.powi(2) + (self.acceleration_sensitivity * deviation).powi(2);
+        let innovation = acceleration.normalized() - gravity;
+
+        // The error state is zero after each correction, so the innovation is the measurement of the error
+        if self
+            .filter
+            .update_with(
+                innovation.to_array(),
+                &observation,
+                &Matrix::from_diagonal([variance; 3]),
+            )
+            .is_err()
+        {
+            return;
+        }
+
+        let [roll, pitch, yaw, bias_x, bias_y, bias_z] = self.filter.state();
+        self.orientation =
+            (self.orientation * rotation_vector(&Vec3D::new(roll, pitch, yaw))).normalized();
+        self.bias += Vec3D::new(bias_x, bias_y, bias_z);
+        self.filter.state = Matrix::ZERO;
+    }
+
+    /// Forgets the orientation, the bias estimate, and the timing of the previous sample
+    pub fn reset(&mut self) {
+        self.orientation = Quaternion::IDENTITY;
+        self.bias = Vec3D::default();
+        self.filter.state = Matrix::ZERO;
+        self.filter.covariance = initial_covariance();
+        self.previous = None;
+        self.initialized = false;
+    }
+}
+
+impl super::AttitudeFilter for ExtendedKalmanFilter {
+    fn update_attitude(
+        &mut self,
+        sample: &SensorSample<Vec3D, f64>,
+        sample_period: f64,
+    ) -> Quaternion {
+        self.update_with_period(sample, sample_period)
+    }
+
+    fn orientation(&self) -> Quaternion {
+        self.orientation
+    }
+
+    fn reset(&mut self) {
+        ExtendedKalmanFilter::reset(self)
+    }
+}
+
+fn initial_covariance() -> Matrix<6, 6> {
+    let (attitude, bias) = (
+        ExtendedKalmanFilter::INITIAL_ATTITUDE_VARIANCE,
+        ExtendedKalmanFilter::INITIAL_BIAS_VARIANCE,
+    );
+    Matrix::from_diagonal([attitude, attitude, attitude, bias, bias, bias])
+}
+
+// Matrix of the cross product: skew(a) * b = a x b
+fn skew(vector: &Vec3D) -> Matrix<3, 3> {
+    Matrix::new([
+        [0.0, -vector.z, vector.y],
+        [vector.z, 0.0, -vector.x],
+        [-vector.y, vector.x, 0.0],
+    ])
+}
+
+// Rotation about {vector} by its length [rad]
+fn rotation_vector(vector: &Vec3D) -> Quaternion {
+    let angle = vector.norm();
+    if angle < Vec3D::THRESHOLD {
+        return Quaternion::new(1.0, vector.x / 2.0, vector.y / 2.0, vector.z / 2.0).normalized();
+    }
+    Quaternion::from_axis_angle(vector, angle)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::fusion::tests::{gravity, sample};
+
+    #[test]
+    fn test_tilt_convergence() {
+        let reference = Quaternion::from_euler_angles(0.5, -0.35, 0.0);
+        let mut filter = ExtendedKalmanFilter::new(0.1, 0.02, 0.01);
+        filter.update(&sample(Vec3D::new(0, 0, 1), Vec3D::default())); // Starts level
+        for _ in 0..1000 {
+            filter.update(&sample(gravity(&reference), Vec3D::default()));
+        }
+        let (roll, pitch, _) = filter.orientation.to_euler_angles();
+        assert!((roll - 0.5).abs() < 1e-4);
+        assert!((pitch + 0.35).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_bias_estimation() {
+        let bias = Vec3D::new(1.0, -0.5, 0.0); // [degree/s]
+        let mut filter = ExtendedKalmanFilter::new(0.1, 0.02, 0.01);
+        for _ in 0..6000 {
+            filter.update(&sample(Vec3D::new(0, 0, 1), bias));
+        }
+        assert!((filter.gyroscope_bias() - bias).norm() < 0.02);
+        let (roll, pitch, _) = filter.orientation.to_euler_angles();
+        assert!(roll.abs() < 1e-3 && pitch.abs() < 1e-3);
+        assert!(filter.attitude_uncertainty().x < 0.01);
+    }
+
+    #[test]
+    fn test_sustained_acceleration() {
+        // Level, but accelerating forward at 0.5 g for two seconds. The accelerometer suggests a pitch of about -27 degrees
+        let run = |acceleration_sensitivity| {
+            let mut filter = ExtendedKalmanFilter::new(0.1, 0.02, 0.01);
+            filter.acceleration_sensitivity = acceleration_sensitivity;
+            for _ in 0..1000 {
+                filter.update(&sample(Vec3D::new(0, 0, 1), Vec3D::default()));
+            }
+            for _ in 0..200 {
+                filter.update(&sample(Vec3D::new(0.5, 0.0, 1.0), Vec3D::default()));
+            }
+            filter.orientation.to_euler_angles().1.abs()
+        };
+        let (adaptive, fixed) = (run(1.0), run(0.0));
+        assert!(adaptive < fixed / 2.0);
+    }
+
+    #[test]
+    fn test_irregular_timestamps() {
+        // Turning about the vertical axis at 90 degree/s for one second, with jittery and missed samples
+        let turning = sample(Vec3D::new(0, 0, 1), Vec3D::new(0, 0, 90));
+        let mut filter = ExtendedKalmanFilter::new(0.1, 0.02, 0.01);
+        let mut instant = Instant::now();
+        filter.update_at(&turning, instant);
+        for milliseconds in [5, 15, 10, 20].repeat(20) {
+            instant += Duration::from_millis(milliseconds);
+            filter.update_at(&turning, instant);
+        }
+        let yaw = filter.orientation.to_euler_angles().2;
+        assert!((yaw - PI / 2.0).abs() < 1e-6);
+    }
+}
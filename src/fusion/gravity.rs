@@ -0,0 +1,85 @@
+use crate::{
+    gy521::{self, SensorSample},
+    math::{Quaternion, Vec3D},
+};
+
+/// Acceleration due to motion alone, i.e., with gravity removed [g]
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct LinearAcceleration {
+    pub sensor: Vec3D, // In the sensor frame
+    pub earth: Vec3D,  // In the earth frame, whose z axis points up. For heave, z is what matters
+}
+
+impl LinearAcceleration {
+    /// Removes gravity from an {acceleration} [g] measured in the sensor frame, with the sensor's {orientation} (sensor to earth frame).
+    /// An accelerometer at rest reads +1 g upwards, so that's what gets subtracted.
+    pub fn new(acceleration: &Vec3D, orientation: &Quaternion) -> Self {
+        let sensor = *acceleration - orientation.conjugate().rotate(&Vec3D::new(0, 0, 1));
+        Self {
+            sensor,
+            earth: orientation.rotate(&sensor),
+        }
+    }
+
+    /// Earth frame acceleration [m/s^2]
+    pub fn earth_si(&self) -> Vec3D {
+        self.earth * gy521::g
+    }
+}
+
+/// Processing stage that runs an attitude filter and removes gravity from each sample with the resulting orientation.
+pub struct GravityRemoval<F: super::AttitudeFilter> {
+    pub filter: F,
+}
+
+impl<F: super::AttitudeFilter> GravityRemoval<F> {
+    pub fn new(filter: F) -> Self {
+        Self { filter }
+    }
+
+    /// Updates the filter with a sample taken {sample_period} [s] after the previous one, and returns the sample's linear acceleration.
+    pub fn update(
+        &mut self,
+        sample: &SensorSample<Vec3D, f64>,
+        sample_period: f64,
+    ) -> LinearAcceleration {
+        let orientation = self.filter.update_attitude(sample, sample_period);
+        LinearAcceleration::new(&sample.acceleration(), &orientation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fusion::Mahony;
+
+    #[test]
+    fn test_at_rest() {
+        let orientation = Quaternion::from_euler_angles(0.4, -0.7, 1.2);
+        let gravity = orientation.conjugate().rotate(&Vec3D::new(0, 0, 1));
+        let linear = LinearAcceleration::new(&gravity, &orientation);
+        assert!(linear.sensor.norm() < 1e-12);
+        assert!(linear.earth.norm() < 1e-12);
+    }
+
+    #[test]
+    fn test_heave() {
+        // Rolled by 20 degrees and accelerating upwards with 0.1 g
+        let orientation = Quaternion::from_euler_angles(20_f64.to_radians(), 0.0, 0.0);
+        let acceleration = orientation.conjugate().rotate(&Vec3D::new(0.0, 0.0, 1.1));
+        let linear = LinearAcceleration::new(&acceleration, &orientation);
+        assert!(linear.earth.approx_eq(&Vec3D::new(0.0, 0.0, 0.1), 1e-12));
+        assert!((linear.earth_si().z - 0.1 * gy521::g).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_stage() {
+        let mut stage = GravityRemoval::new(Mahony::new(1.0, 0.0, 0.01));
+        let sample = SensorSample::new(Vec3D::new(0, 0, 1), Vec3D::default(), None);
+        let linear = (0..100)
+            .map(|_| stage.update(&sample, 0.01))
+            .last()
+            .unwrap();
+        assert!(linear.sensor.norm() < 1e-9);
+    }
+}
@@ -0,0 +1,239 @@
+use crate::{
+    gy521::SensorSample,
+    math::{Quaternion, Vec3D},
+};
+
+/// Madgwick's gradient descent orientation filter, with optional magnetometer input.
+/// See: https://x-io.co.uk/open-source-imu-and-ahrs-algorithms/
+/// The orientation rotates vectors from the sensor frame into the earth frame. The earth frame's z axis points up, and with a magnetometer, its x axis points to magnetic north.
+pub struct Madgwick {
+    pub beta: f64, // [rad/s] Step size of the gradient descent. Larger values trust the accelerometer (and magnetometer) more
+    pub sample_period: f64, // [s] Used by `update` and `update_with_magnetometer`
+    pub orientation: Quaternion,
+}
+
+impl Madgwick {
+    pub fn new(beta: f64, sample_period: f64) -> Self {
+        Self {
+            beta,
+            sample_period,
+            orientation: Quaternion::IDENTITY,
+        }
+    }
+
+    /// Chooses beta from the gyroscope's measurement error {gyroscope_error} [degree/s], as suggested in the paper.
+    pub fn from_gyroscope_error(gyroscope_error: f64, sample_period: f64) -> Self {
+        Self::new(
+            (3.0_f64 / 4.0).sqrt() * gyroscope_error.to_radians(),
+            sample_period,
+        )
+    }
+
+    /// Updates the orientation with a sample taken one `sample_period` after the previous one.
+    pub fn update(&mut self, sample: &SensorSample<Vec3D, f64>) -> Quaternion {
+        self.update_with_period(sample, None, self.sample_period)
+    }
+
+    /// Same as `update`, but also corrects the heading with a {magnetometer} reading, taken in the sensor frame (any unit).
+    pub fn update_with_magnetometer(
+        &mut self,
+        sample: &SensorSample<Vec3D, f64>,
+        magnetometer: &Vec3D,
+    ) -> Quaternion {
+        self.update_with_period(sample, Some(magnetometer), self.sample_period)
+    }
+
+    /// Updates the orientation with a sample taken {sample_period} [s] after the previous one.
+    pub fn update_with_period(
+        &mut self,
+        sample: &SensorSample<Vec3D, f64>,
+        magnetometer: Option<&Vec3D>,
+        sample_period: f64,
+    ) -> Quaternion {
+        let q = self.orientation;
+        let rate = sample.angular_velocity() * std::f64::consts::PI / 180.0;
+
+        // Rate of change from the gyroscope: 1/2 q * (0, rate)
+        let mut derivative = as_array(&(q * Quaternion::new(0.0, rate.x, rate.y, rate.z)))
+            .map(|component| 0.5 * component);
+
+        // A free falling accelerometer (or a dead magnetometer) can't be normalized, so it gets ignored
+        let acceleration = sample.acceleration();
+        if acceleration.norm() > Vec3D::THRESHOLD {
+            let mut gradient = gravity_gradient(&q, &acceleration.normalized());
+            if let Some(magnetometer) = magnetometer.filter(|field| field.norm() > Vec3D::THRESHOLD)
+            {
+                let field_gradient = field_gradient(&q, &magnetometer.normalized());
+                for (component, field_component) in gradient.iter_mut().zip(field_gradient) {
+                    *component += field_component;
+                }
+            }
+
+            let norm = gradient
+                .iter()
+                .map(|component| component.powi(2))
+                .sum::<f64>()
+                .sqrt();
+            if norm > Vec3D::THRESHOLD {
+                for (component, gradient_component) in derivative.iter_mut().zip(gradient) {
+                    *component -= self.beta * gradient_component / norm;
+                }
+            }
+        }
+
+        let [w, x, y, z] = as_array(&q);
+        self.orientation = Quaternion::new(
+            w + derivative[0] * sample_period,
+            x + derivative[1] * sample_period,
+            y + derivative[2] * sample_period,
+            z + derivative[3] * sample_period,
+        )
+        .normalized();
+        self.orientation
+    }
+}
+
+impl super::AttitudeFilter for Madgwick {
+    fn update_attitude(
+        &mut self,
+        sample: &SensorSample<Vec3D, f64>,
+        sample_period: f64,
+    ) -> Quaternion {
+        self.update_with_period(sample, None, sample_period)
+    }
+
+    fn orientation(&self) -> Quaternion {
+        self.orientation
+    }
+
+    fn reset(&mut self) {
+        self.orientation = Quaternion::IDENTITY;
+    }
+}
+
+fn as_array(q: &Quaternion) -> [f64; 4] {
+    [q.w, q.x, q.y, q.z]
+}
+
+// J^T * f of the objective function f = (direction of gravity in the sensor frame) - {acceleration}. Equations 25 and 26 of the paper
+fn gravity_gradient(q: &Quaternion, acceleration: &Vec3D) -> [f64; 4] {
+    let [w, x, y, z] = as_array(q);
+    let f = [
+        2.0 * (x * z - w * y) - acceleration.x,
+        2.0 * (w * x + y * z) - acceleration.y,
+        2.0 * (0.5 - x * x - y * y) - acceleration.z,
+    ];
+    let jacobian = [
+        [-2.0 * y, 2.0 * z, -2.0 * w, 2.0 * x],
+        [2.0 * x, 2.0 * w, 2.0 * z, 2.0 * y],
+        [0.0, -4.0 * x, -4.0 * y, 0.0],
+    ];
+    transposed_product(&jacobian, &f)
+}
+
+// J^T * f of the objective function f = (direction of the earth's field in the sensor frame) - {magnetometer}. Equations 29 and 30 of the paper
+fn field_gradient(q: &Quaternion, magnetometer: &Vec3D) -> [f64; 4] {
+    // The earth's field has no east/west component by definition of the earth frame. Its horizontal and vertical parts follow from the current estimate
+    let field = q.rotate(magnetometer);
+    let (bx, bz) = ((field.x.powi(2) + field.y.powi(2)).sqrt(), field.z);
+
+    let [w, x, y, z] = as_array(q);
+    let f = [
+        2.0 * bx * (0.5 - y * y - z * z) + 2.0 * bz * (x * z - w * y) - magnetometer.x,
+        2.0 * bx * (x * y - w * z) + 2.0 * bz * (w * x + y * z) - magnetometer.y,
+        2.0 * bx * (w * y + x * z) + 2.0 * bz * (0.5 - x * x - y * y) - magnetometer.z,
+    ];
+    let jacobian = [
+        [
+            -2.0 * bz * y,
+            2.0 * bz * z,
+            -4.0 * bx * y - 2.0 * bz * w,
+            -4.0 * bx * z + 2.0 * bz * x,
+        ],
+        [
+            -2.0 * bx * z + 2.0 * bz * x,
+            2.0 * bx * y + 2.0 * bz * w,
+            2.0 * bx * x + 2.0 * bz * z,
+            -2.0 * bx * w + 2.0 * bz * y,
+        ],
+        [
+            2.0 * bx * y,
+            2.0 * bx * z - 4.0 * bz * x,
+            2.0 * bx * w - 4.0 * bz * y,
+            2.0 * bx * x,
+        ],
+    ];
+    transposed_product(&jacobian, &f)
+}
+
+fn transposed_product(jacobian: &[[f64; 4]; 3], f: &[f64; 3]) -> [f64; 4] {
+    [0, 1, 2, 3].map(|column| (0..3).map(|row| jacobian[row][column] * f[row]).sum())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fusion::tests::{gravity, sample};
+    use crate::math::EulerAngles;
+
+    #[test]
+    fn test_level_at_rest() {
+        let mut filter = Madgwick::new(0.1, 0.01);
+        for _ in 0..100 {
+            filter.update(&sample(Vec3D::new(0, 0, 1), Vec3D::default()));
+        }
+        assert!((filter.orientation.dot(&Quaternion::IDENTITY).abs() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_tilt_convergence() {
+        let reference = Quaternion::from_euler_angles(0.3, -0.2, 0.0);
+        let mut filter = Madgwick::new(0.5, 0.01);
+        // The normalized gradient steps make the estimate oscillate by about beta * sample_period. A small beta settles it
+        for beta in [0.5, 0.01] {
+            filter.beta = beta;
+            for _ in 0..2000 {
+                filter.update(&sample(gravity(&reference), Vec3D::default()));
+            }
+        }
+        let (roll, pitch, _) = filter.orientation.to_euler_angles();
+        assert!((roll - 0.3).abs() < 1e-3);
+        assert!((pitch + 0.2).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_gyroscope_integration() {
+        // Gyroscope only, turning about the vertical axis at 90 degree/s for one second
+        let mut filter = Madgwick::new(0.0, 0.001);
+        for _ in 0..1000 {
+            filter.update(&sample(Vec3D::new(0, 0, 1), Vec3D::new(0, 0, 90)));
+        }
+        let reference = Quaternion::from_axis_angle(&Vec3D::new(0, 0, 1), 90_f64.to_radians());
+        assert!((filter.orientation.dot(&reference).abs() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_magnetometer_heading() {
+        // Field pointing north and 60 degrees down, like in northern Europe
+        let inclination = 60_f64.to_radians();
+        let field = Vec3D::new(inclination.cos(), 0.0, -inclination.sin());
+        let reference =
+            EulerAngles::from_degrees(10.0, 5.0, 30.0, Default::default()).to_quaternion();
+
+        let mut filter = Madgwick::new(0.5, 0.01);
+        for beta in [0.5, 0.01] {
+            filter.beta = beta;
+            for _ in 0..5000 {
+                filter.update_with_magnetometer(
+                    &sample(gravity(&reference), Vec3D::default()),
+                    &reference.conjugate().rotate(&field),
+                );
+            }
+        }
+        let angles = EulerAngles::from_quaternion(&filter.orientation, Default::default());
+        let (roll, pitch, yaw) = angles.to_degrees();
+        assert!((roll - 10.0).abs() < 0.1);
+        assert!((pitch - 5.0).abs() < 0.1);
+        assert!((yaw - 30.0).abs() < 0.1);
+    }
+}
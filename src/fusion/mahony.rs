@@ -0,0 +1,162 @@
+use crate::{
+    gy521::SensorSample,
+    math::{Quaternion, Vec3D},
+};
+
+/// Mahony's nonlinear complementary filter on the rotation group, with optional magnetometer input.
+/// The integral term estimates the gyroscope's bias online. Frames are the same as for `Madgwick`.
+/// See: https://hal.archives-ouvertes.fr/hal-00488376/document
+pub struct Mahony {
+    pub proportional_gain: f64, // [rad/s] How strongly the accelerometer (and magnetometer) pull on the estimate
+    pub integral_gain: f64, // [rad/s^2] How quickly the bias estimate follows. 0 disables bias estimation
+    pub sample_period: f64, // [s] Used by `update` and `update_with_magnetometer`
+    pub orientation: Quaternion,
+    integral: Vec3D, // [rad/s] Accumulated correction, i.e., the negative gyroscope bias
+}
+
+impl Mahony {
+    pub fn new(proportional_gain: f64, integral_gain: f64, sample_period: f64) -> Self {
+        Self {
+            proportional_gain,
+            integral_gain,
+            sample_period,
+            orientation: Quaternion::IDENTITY,
+            integral: Vec3D::default(),
+        }
+    }
+
+    /// Current estimate of the gyroscope's bias [degree/s]. Bias about the vertical axis is only observable with a magnetometer.
+    pub fn gyroscope_bias(&self) -> Vec3D {
+        -self.integral * 180.0 / std::f64::consts::PI
+    }
+
+    /// Updates the orientation with a sample taken one `sample_period` after the previous one.
+    pub fn update(&mut self, sample: &SensorSample<Vec3D, f64>) -> Quaternion {
+        self.update_with_period(sample, None, self.sample_period)
+    }
+
+    /// Same as `update`, but also corrects the heading with a {magnetometer} reading, taken in the sensor frame (any unit).
+    pub fn update_with_magnetometer(
+        &mut self,
+        sample: &SensorSample<Vec3D, f64>,
+        magnetometer: &Vec3D,
+    ) -> Quaternion {
+        self.update_with_period(sample, Some(magnetometer), self.sample_period)
+    }
+
+    /// Updates the orientation with a sample taken {sample_period} [s] after the previous one.
+    pub fn update_with_period(
+        &mut self,
+        sample: &SensorSample<Vec3D, f64>,
+        magnetometer: Option<&Vec3D>,
+        sample_period: f64,
+    ) -> Quaternion {
+        let q = self.orientation;
+        let mut rate = sample.angular_velocity() * std::f64::consts::PI / 180.0;
+
+        // Errors are the rotations from the measured to the estimated directions of gravity and the earth's field
+        let acceleration = sample.acceleration();
+        if acceleration.norm() > Vec3D::THRESHOLD {
+            let estimated_gravity = q.conjugate().rotate(&Vec3D::new(0, 0, 1));
+            let mut error = acceleration.normalized().cross_product(&estimated_gravity);
+
+            if let Some(magnetometer) = magnetometer.filter(|field| field.norm() > Vec3D::THRESHOLD)
+            {
+                let magnetometer = magnetometer.normalized();
+                // The earth's field has no east/west component by definition of the earth frame
+                let field = q.rotate(&magnetometer);
+                let reference =
+                    Vec3D::new((field.x.powi(2) + field.y.powi(2)).sqrt(), 0.0, field.z);
+                error += magnetometer.cross_product(&q.conjugate().rotate(&reference));
+            }
+
+            if self.integral_gain > 0.0 {
+                self.integral += error * (self.integral_gain * sample_period);
+                rate += self.integral;
+            }
+            rate += error * self.proportional_gain;
+        }
+
+        let derivative = q * Quaternion::new(0.0, rate.x, rate.y, rate.z);
+        self.orientation = Quaternion::new(
+            q.w + 0.5 * derivative.w * sample_period,
+            q.x + 0.5 * derivative.x * sample_period,
+            q.y + 0.5 * derivative.y * sample_period,
+            q.z + 0.5 * derivative.z * sample_period,
+        )
+        .normalized();
+        self.orientation
+    }
+
+    /// Forgets the orientation and the bias estimate
+    pub fn reset(&mut self) {
+        self.orientation = Quaternion::IDENTITY;
+        self.integral = Vec3D::default();
+    }
+}
+
+impl super::AttitudeFilter for Mahony {
+    fn update_attitude(
+        &mut self,
+        sample: &SensorSample<Vec3D, f64>,
+        sample_period: f64,
+    ) -> Quaternion {
+        self.update_with_period(sample, None, sample_period)
+    }
+
+    fn orientation(&self) -> Quaternion {
+        self.orientation
+    }
+
+    fn reset(&mut self) {
+        Mahony::reset(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fusion::tests::{gravity, sample};
+    use crate::math::EulerAngles;
+
+    #[test]
+    fn test_tilt_convergence() {
+        let reference = Quaternion::from_euler_angles(-0.4, 0.25, 0.0);
+        let mut filter = Mahony::new(1.0, 0.0, 0.01);
+        for _ in 0..2000 {
+            filter.update(&sample(gravity(&reference), Vec3D::default()));
+        }
+        let (roll, pitch, _) = filter.orientation.to_euler_angles();
+        assert!((roll + 0.4).abs() < 1e-6);
+        assert!((pitch - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_bias_estimation() {
+        let bias = Vec3D::new(1.0, -0.5, 0.0); // [degree/s]
+        let mut filter = Mahony::new(1.0, 0.3, 0.01);
+        for _ in 0..10_000 {
+            filter.update(&sample(Vec3D::new(0, 0, 1), bias));
+        }
+        assert!((filter.gyroscope_bias() - bias).norm() < 1e-3);
+        let (roll, pitch, _) = filter.orientation.to_euler_angles();
+        assert!(roll.abs() < 1e-4 && pitch.abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_magnetometer_heading() {
+        let inclination = 60_f64.to_radians();
+        let field = Vec3D::new(inclination.cos(), 0.0, -inclination.sin());
+        let reference =
+            EulerAngles::from_degrees(-15.0, 20.0, -120.0, Default::default()).to_quaternion();
+
+        let mut filter = Mahony::new(5.0, 0.0, 0.01);
+        for _ in 0..5000 {
+            filter.update_with_magnetometer(
+                &sample(gravity(&reference), Vec3D::default()),
+                &reference.conjugate().rotate(&field),
+            );
+        }
+        assert!((filter.orientation.dot(&reference).abs() - 1.0).abs() < 1e-9);
+    }
+}
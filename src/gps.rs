@@ -0,0 +1,529 @@
+// Position, speed, and course from a GPS receiver, which sends NMEA 0183 sentences over a serial port, e.g., a u-blox NEO-6M on the UART of the Pi.
+// Receivers send a burst of sentences per fix, once a second by default. RMC, GGA, and VTG are parsed, and merged into one `Fix` per burst. Others, like GSA and GSV, are skipped.
+
+use std::{
+    io::Read,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{ensure, Context, Result};
+
+use crate::{clock, gy521::SampleSource};
+
+const KNOTS: f64 = 1852.0 / 3600.0; // [m/s]
+const READ_TIMEOUT: Duration = Duration::from_millis(100); // Of single reads of the UART, so that `next_sample` can keep its timeout
+
+/// [degree] Positive towards north and east
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Position {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// GGA fix quality
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum FixQuality {
+    #[default]
+    Invalid,
+    Gps,
+    Differential, // E.g., with SBAS corrections
+    Pps,
+    RealTimeKinematic,
+    FloatRealTimeKinematic,
+    Estimated, // Dead reckoning
+    Manual,
+    Simulation,
+}
+
+impl FixQuality {
+    pub fn from_gga(quality: u8) -> Option<Self> {
+        Some(match quality {
+            0 => Self::Invalid,
+            1 => Self::Gps,
+            2 => Self::Differential,
+            3 => Self::Pps,
+            4 => Self::RealTimeKinematic,
+            5 => Self::FloatRealTimeKinematic,
+            6 => Self::Estimated,
+            7 => Self::Manual,
+            8 => Self::Simulation,
+            _ => return None,
+        })
+    }
+}
+
+/// Recommended minimum data
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rmc {
+    pub time: Option<f64>, // [s] Since midnight UTC
+    pub valid: bool,
+    pub position: Option<Position>,
+    pub speed: Option<f64>,              // [m/s] Over ground
+    pub course: Option<f64>,             // [degree] Over ground, clockwise from true north
+    pub date: Option<(u16, u8, u8)>,     // UTC (year, month, day)
+    pub magnetic_variation: Option<f64>, // [degree] Positive towards east
+}
+
+/// Fix data
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gga {
+    pub time: Option<f64>, // [s] Since midnight UTC
+    pub position: Option<Position>,
+    pub quality: FixQuality,
+    pub satellites: Option<u8>,        // In use
+    pub hdop: Option<f64>,             // Horizontal dilution of precision
+    pub altitude: Option<f64>,         // [m] Above mean sea level
+    pub geoid_separation: Option<f64>, // [m] Of mean sea level above the WGS 84 ellipsoid
+}
+
+/// Course and speed over ground
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vtg {
+    pub course: Option<f64>,          // [degree] Clockwise from true north
+    pub magnetic_course: Option<f64>, // [degree] Clockwise from magnetic north
+    pub speed: Option<f64>,           // [m/s]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sentence {
+    Rmc(Rmc),
+    Gga(Gga),
+    Vtg(Vtg),
+}
+
+impl Sentence {
+    /// [s] Since midnight UTC. VTG has none
+    pub fn time(&self) -> Option<f64> {
+        match self {
+            Self::Rmc(rmc) => rmc.time,
+            Self::Gga(gga) => gga.time,
+            Self::Vtg(_) => None,
+        }
+    }
+}
+
+/// Parses {line}, e.g., "$GPRMC,...*6A", from any talker, e.g., GP for GPS only, or GN for several satellite systems. Ok(None) for other sentences. Errors for broken sentences, including wrong checksums
+pub fn parse(line: &str) -> Result<Option<Sentence>> {
+    let line = line.trim_end();
+    let body = line
+        .strip_prefix('$')
+        .with_context(|| format!("NMEA sentence doesn't start with $: {line}"))?;
+    let (body, checksum) = body
+        .rsplit_once('*')
+        .with_context(|| format!("NMEA sentence without checksum: {line}"))?;
+    let expected = u8::from_str_radix(checksum, 16)
+        .with_context(|| format!("Invalid NMEA checksum: {line}"))?;
+    let actual = body.bytes().fold(0, |sum, byte| sum ^ byte);
+    ensure!(
+        actual == expected,
+        "NMEA checksum is {actual:02X} instead of {expected:02X}: {line}"
+    );
+
+    let fields = body.split(',').collect::<Vec<_>>();
+    ensure!(
+        fields[0].len() == 5 && fields[0].is_ascii(),
+        "Invalid NMEA address: {line}"
+    );
+    // Missing trailing fields are treated like empty ones
+    let field = |index: usize| fields.get(index).copied().unwrap_or("");
+    let parse = || -> Result<Option<Sentence>> {
+        Ok(Some(match &fields[0][2..] {
+            "RMC" => Sentence::Rmc(Rmc {
+                time: time(field(1))?,
+                valid: field(2) == "A",
+                position: position(field(3), field(4), field(5), field(6))?,
+                speed: number(field(7))?.map(|speed| speed * KNOTS),
+                course: number(field(8))?,
+                date: date(field(9))?,
+                magnetic_variation: number(field(10))?.map(|variation| match field(11) {
+                    "W" => -variation,
+                    _ => variation,
+                }),
+            }),
+            "GGA" => Sentence::Gga(Gga {
+                time: time(field(1))?,
+                position: position(field(2), field(3), field(4), field(5))?,
+                quality: match number(field(6))? {
+                    Some(quality) => {
+                        FixQuality::from_gga(quality as u8).context("Invalid fix quality.")?
+                    }
+                    None => FixQuality::Invalid,
+                },
+                satellites: number(field(7))?.map(|satellites| satellites as u8),
+                hdop: number(field(8))?,
+                altitude: number(field(9))?,
+                geoid_separation: number(field(11))?,
+            }),
+            "VTG" => Sentence::Vtg(Vtg {
+                course: number(field(1))?,
+                magnetic_course: number(field(3))?,
+                // Knots, or km/h if those are missing
+                speed: match number(field(5))? {
+                    Some(speed) => Some(speed * KNOTS),
+                    None => number(field(7))?.map(|speed| speed / 3.6),
+                },
+            }),
+            _ => return Ok(None),
+        }))
+    };
+    parse().with_context(|| format!("Invalid NMEA sentence: {line}"))
+}
+
+// None for empty fields, which is how receivers leave out what they don't know yet
+fn number(field: &str) -> Result<Option<f64>> {
+    if field.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(field.parse()?))
+}
+
+// [s] Since midnight, from "hhmmss.ss"
+fn time(field: &str) -> Result<Option<f64>> {
+    if field.is_empty() {
+        return Ok(None);
+    }
+    ensure!(field.len() >= 6 && field.is_ascii(), "Invalid time.");
+    let hours: f64 = field[0..2].parse()?;
+    let minutes: f64 = field[2..4].parse()?;
+    let seconds: f64 = field[4..].parse()?;
+    Ok(Some(hours * 3600.0 + minutes * 60.0 + seconds))
+}
+
+// (year, month, day), from "ddmmyy"
+fn date(field: &str) -> Result<Option<(u16, u8, u8)>> {
+    if field.is_empty() {
+        return Ok(None);
+    }
+    ensure!(field.len() == 6 && field.is_ascii(), "Invalid date.");
+    let day = field[0..2].parse()?;
+    let month = field[2..4].parse()?;
+    let year: u16 = field[4..6].parse()?;
+    // Two digits only. GPS started in 1980
+    let year = if year < 80 { 2000 + year } else { 1900 + year };
+    Ok(Some((year, month, day)))
+}
+
+// From "ddmm.mmmm" and "N" or "S", and "dddmm.mmmm" and "E" or "W"
+fn position(
+    latitude: &str,
+    north_south: &str,
+    longitude: &str,
+    east_west: &str,
+) -> Result<Option<Position>> {
+    let degrees = |field: &str, negative: bool| -> Result<Option<f64>> {
+        Ok(number(field)?.map(|value| {
+            let degrees = (value / 100.0).trunc() + (value % 100.0) / 60.0;
+            if negative {
+                -degrees
+            } else {
+                degrees
+            }
+        }))
+    };
+    Ok(
+        match (
+            degrees(latitude, north_south == "S")?,
+            degrees(longitude, east_west == "W")?,
+        ) {
+            (Some(latitude), Some(longitude)) => Some(Position {
+                latitude,
+                longitude,
+            }),
+            _ => None,
+        },
+    )
+}
+
+/// What the receiver knows at one instant, merged from the sentences it sent about it
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Fix {
+    pub time: Option<SystemTime>, // UTC, by the receiver. Needs the date from an RMC sentence of this or an earlier fix
+    pub position: Option<Position>,
+    pub altitude: Option<f64>, // [m] Above mean sea level
+    pub speed: Option<f64>,    // [m/s] Over ground
+    pub course: Option<f64>,   // [degree] Over ground, clockwise from true north
+    pub quality: FixQuality,
+    pub satellites: Option<u8>, // In use
+    pub hdop: Option<f64>, // Horizontal dilution of precision. Times a few meters is roughly the error of the position
+}
+
+impl Fix {
+    /// Whether the receiver knows its position
+    pub fn is_valid(&self) -> bool {
+        self.position.is_some() && self.quality != FixQuality::Invalid
+    }
+}
+
+// Sentences of one burst, until they are complete
+struct Epoch {
+    time: Option<f64>, // [s] Since midnight UTC
+    fix: Fix,
+    instant: Instant, // Of the first sentence
+    rmc: bool,
+    gga: bool,
+    emitted: bool,
+}
+
+/// Reads sentences from a GPS receiver and merges them into fixes, as a `SampleSource`. Reads from the UART with `open`, or from anything else, e.g., a file of recorded sentences.
+/// A fix is complete once both RMC and GGA of the same time are in, or otherwise when the next one starts. The instant of a fix is when its first sentence arrived, which is a few hundred milliseconds after `Fix::time` on most receivers
+pub struct GpsReceiver<R: Read> {
+    reader: R,
+    buffer: Vec<u8>, // Bytes of the line that is being read
+    epoch: Option<Epoch>,
+    date: Option<(u16, u8, u8)>, // Latest
+    pub errors: usize, // Broken sentences so far, e.g., from noise on the line. They are skipped
+    finished: bool,
+}
+
+/// Reads from a UART, with reads that time out reported as `std::io::ErrorKind::TimedOut`, so that they aren't taken for the end of the data
+pub struct SerialPort(rppal::uart::Uart);
+
+impl Read for SerialPort {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        match self.0.read(buffer) {
+            Ok(0) => Err(std::io::ErrorKind::TimedOut.into()),
+            Ok(count) => Ok(count),
+            Err(error) => Err(std::io::Error::other(error)),
+        }
+    }
+}
+
+impl GpsReceiver<SerialPort> {
+    /// Reads from the UART at {path}, e.g., "/dev/serial0" for the one on GPIO 14 and 15, at {baud_rate}, 9600 for most receivers, with 8 data bits, no parity, and 1 stop bit
+    pub fn open(path: &str, baud_rate: u32) -> Result<Self> {
+        let mut uart =
+            rppal::uart::Uart::with_path(path, baud_rate, rppal::uart::Parity::None, 8, 1)
+                .with_context(|| format!("Unable to open {path}."))?;
+        uart.set_read_mode(0, READ_TIMEOUT)?;
+        Ok(Self::new(SerialPort(uart)))
+    }
+}
+
+impl<R: Read> GpsReceiver<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffer: Vec::new(),
+            epoch: None,
+            date: None,
+            errors: 0,
+            finished: false,
+        }
+    }
+
+    /// Merges {sentence}, received at {instant}, into the current fix. Returns the fix that this completes, if any
+    pub fn add(&mut self, sentence: Sentence, instant: Instant) -> Option<(Fix, Instant)> {
+        let mut completed = None;
+        let starts_epoch = match (&self.epoch, sentence.time()) {
+            (None, _) => true,
+            (Some(epoch), Some(time)) => epoch.time.is_some_and(|epoch| epoch != time),
+            (Some(_), None) => false,
+        };
+        if starts_epoch {
+            if let Some(epoch) = self.epoch.take().filter(|epoch| !epoch.emitted) {
+                completed = Some((epoch.fix, epoch.instant));
+            }
+            self.epoch = Some(Epoch {
+                time: sentence.time(),
+                fix: Fix::default(),
+                instant,
+                rmc: false,
+                gga: false,
+                emitted: false,
+            });
+        }
+        let epoch = self.epoch.as_mut()?;
+        // Stragglers of a fix that is out already
+        if epoch.emitted {
+            return completed;
+        }
+        epoch.time = epoch.time.or(sentence.time());
+        let fix = &mut epoch.fix;
+        match sentence {
+            Sentence::Rmc(rmc) => {
+                epoch.rmc = true;
+                self.date = rmc.date.or(self.date);
+                fix.position = fix.position.or(rmc.position.filter(|_| rmc.valid));
+                fix.speed = rmc.speed.or(fix.speed);
+                fix.course = rmc.course.or(fix.course);
+            }
+            Sentence::Gga(gga) => {
+                epoch.gga = true;
+                fix.position = gga.position.or(fix.position);
+                fix.altitude = gga.altitude;
+                fix.quality = gga.quality;
+                fix.satellites = gga.satellites;
+                fix.hdop = gga.hdop;
+            }
+            Sentence::Vtg(vtg) => {
+                fix.speed = fix.speed.or(vtg.speed);
+                fix.course = fix.course.or(vtg.course);
+            }
+        }
+        // Receivers that send RMC only have no fix quality
+        if !epoch.gga && fix.position.is_some() {
+            fix.quality = FixQuality::Gps;
+        }
+        if let (Some(date), Some(time)) = (self.date, epoch.time) {
+            fix.time = Some(clock::from_civil(date, time));
+        }
+        if epoch.rmc && epoch.gga {
+            epoch.emitted = true;
+            return Some((epoch.fix, epoch.instant));
+        }
+        completed
+    }
+
+    // Next complete line, without the line break, or None if there is none yet
+    fn next_line(&mut self) -> Option<String> {
+        let end = self.buffer.iter().position(|byte| *byte == b'\n')?;
+        let line = self.buffer.drain(..=end).collect::<Vec<_>>();
+        Some(String::from_utf8_lossy(&line).trim().to_string())
+    }
+}
+
+impl<R: Read> SampleSource<Fix> for GpsReceiver<R> {
+    fn next_sample(&mut self, timeout: Option<Duration>) -> (Result<Option<Fix>>, Instant) {
+        let start = Instant::now();
+        let mut chunk = [0u8; 256];
+        loop {
+            while let Some(line) = self.next_line() {
+                if line.is_empty() {
+                    continue;
+                }
+                match parse(&line) {
+                    Ok(Some(sentence)) => {
+                        if let Some((fix, instant)) = self.add(sentence, Instant::now()) {
+                            return (Ok(Some(fix)), instant);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(_) => self.errors += 1,
+                }
+            }
+            if self.finished {
+                // The last fix is as complete as it gets
+                let epoch = self.epoch.take().filter(|epoch| !epoch.emitted);
+                return (Ok(epoch.map(|epoch| epoch.fix)), Instant::now());
+            }
+            if timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+                return (Ok(None), Instant::now());
+            }
+            match self.reader.read(&mut chunk) {
+                Ok(0) => {
+                    self.finished = true;
+                    // A last line without line break
+                    self.buffer.push(b'\n');
+                }
+                Ok(count) => self.buffer.extend_from_slice(&chunk[..count]),
+                Err(error)
+                    if matches!(
+                        error.kind(),
+                        std::io::ErrorKind::TimedOut
+                            | std::io::ErrorKind::WouldBlock
+                            | std::io::ErrorKind::Interrupted
+                    ) => {}
+                Err(error) => {
+                    return (
+                        Err(error).context("Unable to read from GPS receiver."),
+                        Instant::now(),
+                    )
+                }
+            }
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished && self.epoch.as_ref().is_none_or(|epoch| epoch.emitted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let Some(Sentence::Rmc(rmc)) =
+            parse("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A").unwrap()
+        else {
+            panic!("Not RMC.");
+        };
+        assert_eq!(rmc.time, Some(12.0 * 3600.0 + 35.0 * 60.0 + 19.0));
+        assert!(rmc.valid);
+        let position = rmc.position.unwrap();
+        assert!((position.latitude - 48.1173).abs() < 1e-9);
+        assert!((position.longitude - (11.0 + 31.0 / 60.0)).abs() < 1e-9);
+        assert!((rmc.speed.unwrap() - 22.4 * KNOTS).abs() < 1e-9);
+        assert_eq!(rmc.course, Some(84.4));
+        assert_eq!(rmc.date, Some((1994, 3, 23)));
+        assert_eq!(rmc.magnetic_variation, Some(-3.1));
+
+        let Some(Sentence::Gga(gga)) =
+            parse("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47\r\n").unwrap()
+        else {
+            panic!("Not GGA.");
+        };
+        assert_eq!(gga.quality, FixQuality::Gps);
+        assert_eq!(gga.satellites, Some(8));
+        assert_eq!(gga.altitude, Some(545.4));
+        assert_eq!(gga.geoid_separation, Some(46.9));
+
+        let Some(Sentence::Vtg(vtg)) = parse("$GPVTG,054.7,T,034.4,M,005.5,N,010.2,K*48").unwrap()
+        else {
+            panic!("Not VTG.");
+        };
+        assert_eq!(vtg.course, Some(54.7));
+        assert!((vtg.speed.unwrap() - 5.5 * KNOTS).abs() < 1e-9);
+
+        // Before the receiver has a fix
+        let Some(Sentence::Gga(gga)) = parse("$GNGGA,,,,,,0,00,99.99,,,,,,*56").unwrap() else {
+            panic!("Not GGA.");
+        };
+        assert_eq!(gga.position, None);
+        assert_eq!(gga.quality, FixQuality::Invalid);
+
+        assert!(parse("$GPGSA,A,3,04,05,,09,12,,,24,,,,,2.5,1.3,2.1*39")
+            .unwrap()
+            .is_none());
+        assert!(parse("$GPVTG,054.7,T,034.4,M,005.5,N,010.2,K*49").is_err());
+        assert!(parse("GPVTG,054.7,T").is_err());
+    }
+
+    #[test]
+    fn test_receiver() {
+        let sentences = concat!(
+            "$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A\r\n",
+            "$GPVTG,054.7,T,034.4,M,005.5,N,010.2,K*48\r\n",
+            "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47\r\n",
+            "$GPGSA,A,3,04,05,,09,12,,,24,,,,,2.5,1.3,2.1*39\r\n",
+            "$GPRMC,123520,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*60\r\n",
+            "$GPGGA,123520,4807.0,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*42\r\n",
+            "$GPRMC,123521,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*61",
+        );
+        let mut receiver = GpsReceiver::new(sentences.as_bytes());
+
+        let (fix, _) = receiver.next_sample(None);
+        let fix = fix.unwrap().unwrap();
+        assert!(fix.is_valid());
+        assert_eq!(fix.satellites, Some(8));
+        assert!((fix.speed.unwrap() - 22.4 * KNOTS).abs() < 1e-9);
+        // 1994-03-23 12:35:19 UTC
+        assert_eq!(
+            fix.time,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(764_426_119))
+        );
+
+        // The GGA sentence has a wrong checksum, so the fix is only complete at the end
+        let (fix, _) = receiver.next_sample(None);
+        let fix = fix.unwrap().unwrap();
+        assert_eq!(fix.satellites, None);
+        assert_eq!(fix.quality, FixQuality::Gps);
+        assert_eq!(receiver.errors, 1);
+        assert!(!receiver.is_finished());
+        let (fix, _) = receiver.next_sample(None);
+        assert!(fix.unwrap().unwrap().time.is_some());
+        assert!(receiver.is_finished());
+        assert!(receiver.next_sample(None).0.unwrap().is_none());
+    }
+}
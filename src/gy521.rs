@@ -6,35 +6,63 @@ use std::{
 use anyhow::{Context, Result};
 use rppal::i2c::I2c;
 
-use crate::{math::Vec3D, utilites};
+use crate::{
+    math::{Mat3, Scalar, Vec3D},
+    sensors::{
+        transport::{Interface, Transport},
+        ImuSensor, Sensor,
+    },
+    units, utilites,
+};
+
+pub mod calibration;
+pub mod fifo;
+pub mod outlier;
+
+const USER_CTRL: u8 = 0x6A; // Same address on all variants
+const I2C_IF_DIS: u8 = 1 << 4; // In USER_CTRL. Only on variants with SPI, e.g., the MPU-9250
 
 #[allow(non_upper_case_globals)]
-const g: f64 = 9.80665; // [m/s^2] | Don't know which value of g the sensor has been calibrated with, so I'm using standard gravity: https://en.wikipedia.org/wiki/Gravity_of_Earth
+pub const g: f64 = 9.80665; // [m/s^2] | Don't know which value of g the sensor has been calibrated with, so I'm using standard gravity: https://en.wikipedia.org/wiki/Gravity_of_Earth
 
-#[derive(Debug, serde::Serialize, Default, Clone, Copy)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Default, Clone, Copy)]
 pub struct SensorSample<V, T> {
     acceleration: V,
     angular_velocity: V,
-    temperature: T,
+    temperature: Option<T>, // None if the thermometer is disabled
+    outlier: bool,          // Flagged by outlier rejection. See `GY521::enable_outlier_rejection`
+}
+
+/// Readings in counts, as the sensor reports them, before scaling and calibration. Recording these allows redoing the calibration later
+pub type RawSample = SensorSample<[i16; 3], i16>;
+
+// A missing temperature acts as the additive identity, so that sums can start from SensorSample::default()
+fn add_temperatures<T: Add<Output = T>>(lhs: Option<T>, rhs: Option<T>) -> Option<T> {
+    match (lhs, rhs) {
+        (Some(lhs), Some(rhs)) => Some(lhs + rhs),
+        (temperature, None) | (None, temperature) => temperature,
+    }
 }
 
 impl<V: Add<Output = V>, T: Add<Output = T>> Add<SensorSample<V, T>> for SensorSample<V, T> {
     type Output = Self;
 
     fn add(self, rhs: SensorSample<V, T>) -> Self::Output {
-        Self::new(
-            self.acceleration + rhs.acceleration,
-            self.angular_velocity + rhs.angular_velocity,
-            self.temperature + rhs.temperature,
-        )
+        Self {
+            acceleration: self.acceleration + rhs.acceleration,
+            angular_velocity: self.angular_velocity + rhs.angular_velocity,
+            temperature: add_temperatures(self.temperature, rhs.temperature),
+            outlier: self.outlier || rhs.outlier,
+        }
     }
 }
 
-impl<V: AddAssign, T: AddAssign> AddAssign<SensorSample<V, T>> for SensorSample<V, T> {
+impl<V: AddAssign, T: Add<Output = T>> AddAssign<SensorSample<V, T>> for SensorSample<V, T> {
     fn add_assign(&mut self, rhs: SensorSample<V, T>) {
         self.acceleration += rhs.acceleration;
         self.angular_velocity += rhs.angular_velocity;
-        self.temperature += rhs.temperature;
+        self.temperature = add_temperatures(self.temperature.take(), rhs.temperature);
+        self.outlier |= rhs.outlier;
     }
 }
 
@@ -42,11 +70,12 @@ impl<V: Neg<Output = V>, T: Neg<Output = T>> Neg for SensorSample<V, T> {
     type Output = Self;
 
     fn neg(self) -> Self::Output {
-        Self::new(
-            -self.acceleration,
-            -self.angular_velocity,
-            -self.temperature,
-        )
+        Self {
+            acceleration: -self.acceleration,
+            angular_velocity: -self.angular_velocity,
+            temperature: self.temperature.map(|temperature| -temperature),
+            outlier: self.outlier,
+        }
     }
 }
 
@@ -56,20 +85,74 @@ impl<R: Into<f64> + Copy, V: Div<f64, Output = V>, T: Div<f64, Output = T>> Div<
     type Output = Self;
 
     fn div(self, rhs: R) -> Self::Output {
-        Self::new(
-            self.acceleration / rhs.into(),
-            self.angular_velocity / rhs.into(),
-            self.temperature / rhs.into(),
-        )
+        Self {
+            acceleration: self.acceleration / rhs.into(),
+            angular_velocity: self.angular_velocity / rhs.into(),
+            temperature: self.temperature.map(|temperature| temperature / rhs.into()),
+            outlier: self.outlier,
+        }
     }
 }
 
 impl<V, T> SensorSample<V, T> {
-    pub fn new(acceleration: V, angular_velocity: V, temperature: T) -> Self {
+    pub fn new(acceleration: V, angular_velocity: V, temperature: Option<T>) -> Self {
         Self {
             acceleration,
             angular_velocity,
             temperature,
+            outlier: false,
+        }
+    }
+
+    /// True if outlier rejection flagged this sample. Depending on `OutlierAction`, its values are either the original or replaced ones
+    pub fn is_outlier(&self) -> bool {
+        self.outlier
+    }
+}
+
+impl<V: Copy, T: Copy> SensorSample<V, T> {
+    pub fn acceleration(&self) -> V {
+        self.acceleration
+    }
+
+    pub fn angular_velocity(&self) -> V {
+        self.angular_velocity
+    }
+
+    pub fn temperature(&self) -> Option<T> {
+        self.temperature
+    }
+}
+
+impl SensorSample<Vec3D, f64> {
+    /// Roll and pitch [rad] from the accelerometer alone. Only meaningful while the sensor isn't accelerating. See `fusion::accelerometer_tilt`
+    pub fn tilt(&self) -> (f64, f64) {
+        crate::fusion::accelerometer_tilt(&self.acceleration)
+    }
+
+    pub fn acceleration_gs(&self) -> units::Gs<Vec3D> {
+        units::Gs(self.acceleration)
+    }
+
+    pub fn angular_velocity_degrees(&self) -> units::DegreesPerSecond<Vec3D> {
+        units::DegreesPerSecond(self.angular_velocity)
+    }
+
+    pub fn temperature_celsius(&self) -> Option<units::Celsius> {
+        self.temperature.map(units::Celsius)
+    }
+}
+
+impl<S: Scalar> SensorSample<Vec3D<S>, S> {
+    /// Converts the number type, e.g., to `SensorSample<Vec3D<f32>, f32>` to save memory
+    pub fn cast<U: Scalar>(&self) -> SensorSample<Vec3D<U>, U> {
+        SensorSample {
+            acceleration: self.acceleration.cast(),
+            angular_velocity: self.angular_velocity.cast(),
+            temperature: self
+                .temperature
+                .map(|temperature| U::from_f64(temperature.to_f64())),
+            outlier: self.outlier,
         }
     }
 }
@@ -81,6 +164,7 @@ pub struct InterruptStatus {
     pub data_ready: bool, // true: Data ready interrupt (occurs when a write operation to all sensor registers has been completed) has caused interrupt
 }
 
+#[derive(Default)]
 pub struct InterruptConfiguration {
     pub level: bool,                // false: Active high | true: Active low
     pub open: bool,                 // false: Push-pull | true: open drain
@@ -95,23 +179,72 @@ pub struct InterruptConfiguration {
     pub interrupt_pin: Option<rppal::gpio::InputPin>,
 }
 
-#[allow(clippy::derivable_impls)]
-impl Default for InterruptConfiguration {
-    fn default() -> Self {
+/// Instrumentation of data ready interrupts: Records how far each interrupt arrives from when it is expected according to the sample rate.
+/// The sensor's clock and the Raspberry Pi's clock drift apart over time, so the expected instant is counted from the previous interrupt, not from the first one.
+pub struct InterruptTiming {
+    period: f64, // [s]
+    previous: Option<Instant>,
+    deviations: utilites::Memory<f64>, // [s] Positive: Later than expected
+    missed: usize, // Interrupts that never arrived, judging by the time between two interrupts
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+pub struct JitterStatistics {
+    pub count: usize, // All intervals between two recorded interrupts, i.e., one less than the interrupts, while the deviation statistics only cover the latest ones
+    pub missed: usize,
+    // Deviation from expected interrupt instant [s]
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+}
+
+impl InterruptTiming {
+    /// Keeps the deviations of the latest {capacity} interrupts.
+    pub fn new(sample_rate: f64, capacity: usize) -> Self {
         Self {
-            level: false,
-            open: false,
-            launch: false,
-            clear: false,
-            fsync_level: false,
-            fsync_interrupt: false,
-            i2c_bypass: false,
-            fifo_buffer_overflow: false,
-            i2c_master_interrupt: false,
-            data_ready: false,
-            interrupt_pin: None,
+            period: 1.0 / sample_rate,
+            previous: None,
+            deviations: utilites::Memory::with_capacity(capacity),
+            missed: 0,
         }
     }
+
+    pub fn record(&mut self, instant: Instant) {
+        if let Some(previous) = self.previous {
+            let interval = instant.duration_since(previous).as_secs_f64();
+            let periods = (interval / self.period).round().max(1.0);
+            self.missed += periods as usize - 1;
+            self.deviations.push(interval - periods * self.period);
+        }
+        self.previous = Some(instant);
+    }
+
+    /// Deviation that {percentile} percent of the recorded deviations are less than or equal to (nearest-rank method).
+    pub fn percentile(&self, percentile: f64) -> Option<f64> {
+        let mut deviations = self.deviations.iter().copied().collect::<Vec<_>>();
+        deviations.sort_by(f64::total_cmp);
+        let rank = (percentile / 100.0 * deviations.len() as f64).ceil() as usize;
+        deviations
+            .get(rank.clamp(1, deviations.len().max(1)) - 1)
+            .copied()
+    }
+
+    pub fn statistics(&self) -> Option<JitterStatistics> {
+        let deviations = &self.deviations;
+        (!deviations.is_empty()).then(|| JitterStatistics {
+            count: self.deviations.count(),
+            missed: self.missed,
+            min: deviations.iter().copied().fold(f64::INFINITY, f64::min),
+            max: deviations.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            mean: deviations.iter().sum::<f64>() / deviations.len() as f64,
+            p50: self.percentile(50.0).unwrap(),
+            p95: self.percentile(95.0).unwrap(),
+            p99: self.percentile(99.0).unwrap(),
+        })
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -132,7 +265,7 @@ impl Default for ExternalFrameSynchronization {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum Filter {
     // Configuration of Digital Low Pass Filter (DLPF) (register 25)
     Disabled = 0,           // DLPF disabled. Gyroscope Output Rate: 8kHz
@@ -157,7 +290,7 @@ pub struct Configuration {
     pub filter: Filter,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[allow(dead_code)]
 pub enum WakeFrequency {
     Freq1_25Hz = 0,
@@ -166,6 +299,7 @@ pub enum WakeFrequency {
     Freq40Hz = 3,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[allow(dead_code)]
 pub enum PowerMode {
     Active,
@@ -180,6 +314,60 @@ impl Default for PowerMode {
     }
 }
 
+/// The power state the sensor is currently in, as far as the driver knows.
+/// Unlike `PowerMode`, which is what we ask for, this is what we have successfully told the sensor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PowerState {
+    Active,
+    Cycle(WakeFrequency),
+    Sleep,
+    Resetting, // Reset requested, but the sensor has not yet reported it as finished
+}
+
+impl PowerState {
+    /// Transitions that may be requested from outside the driver.
+    /// Resetting -> Sleep happens on its own once the sensor reports the reset as finished, so it is not listed here.
+    fn can_transition_to(&self, next: PowerState) -> bool {
+        match (self, next) {
+            (PowerState::Resetting, PowerState::Resetting) => true, // Retrying a reset that didn't finish
+            (PowerState::Resetting, _) => false,
+            (PowerState::Active | PowerState::Cycle(_) | PowerState::Sleep, _) => true,
+        }
+    }
+
+    fn ensure_transition(&self, next: PowerState) -> Result<()> {
+        anyhow::ensure!(
+            self.can_transition_to(next),
+            "Cannot change power state from {:?} to {:?}.",
+            self,
+            next
+        );
+        Ok(())
+    }
+
+    /// Only an awake sensor updates its data registers and generates data ready interrupts.
+    fn ensure_sampling(&self, operation: &str) -> Result<()> {
+        anyhow::ensure!(
+            matches!(self, PowerState::Active | PowerState::Cycle(_)),
+            "Cannot {} while the sensor is in the {:?} power state.",
+            operation,
+            self
+        );
+        Ok(())
+    }
+}
+
+impl From<PowerMode> for PowerState {
+    fn from(mode: PowerMode) -> Self {
+        match mode {
+            PowerMode::Active => Self::Active,
+            PowerMode::Cycle(wake_frequency) => Self::Cycle(wake_frequency),
+            PowerMode::Reset => Self::Resetting,
+            PowerMode::Sleep => Self::Sleep,
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 pub enum ClockSource {
     InternalOscillator8MHz = 0,
@@ -225,6 +413,153 @@ impl Default for PowerSettings {
     }
 }
 
+/// Members of the MPU family that fit on GY-521-style breakout boards. They share the data register layout, but differ in identity, temperature scaling, and some configuration registers.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Variant {
+    #[default]
+    MPU6050,
+    MPU6500,
+    MPU9250, // MPU-6500 with an AK8963 magnetometer in the same package
+}
+
+impl Variant {
+    /// Value of the WHO_AM_I register (117)
+    pub fn who_am_i(&self) -> u8 {
+        match self {
+            Variant::MPU6050 => 0x68,
+            Variant::MPU6500 => 0x70,
+            Variant::MPU9250 => 0x71,
+        }
+    }
+
+    pub fn from_who_am_i(who_am_i: u8) -> Option<Self> {
+        [Variant::MPU6050, Variant::MPU6500, Variant::MPU9250]
+            .into_iter()
+            .find(|variant| variant.who_am_i() == who_am_i)
+    }
+
+    pub fn register_map(&self) -> RegisterMap {
+        let thermometer = match self {
+            Variant::MPU6050 => ThermometerConfiguration::MPU6050,
+            Variant::MPU6500 | Variant::MPU9250 => ThermometerConfiguration::MPU6500,
+        };
+
+        RegisterMap {
+            name: format!("{:?}", self),
+            accepted_who_am_i: vec![self.who_am_i()],
+            who_am_i: 0x75,
+            pwr_mgmt_1: 0x6B,
+            pwr_mgmt_2: 0x6C,
+            int_pin_cfg: 0x37,
+            int_enable: 0x38,
+            int_status: 0x3A,
+            smplrt_div: 0x19,
+            config: 0x1A,
+            // The MPU-6050 shares the low pass filter setting between accelerometer and gyroscope
+            accel_config_2: (*self != Variant::MPU6050).then_some(0x1D),
+            accelerometer: 0x3B..=0x40,
+            thermometer: 0x41..=0x42,
+            gyroscope: 0x43..=0x48,
+            thermometer_sensitivity: thermometer.sensitivity,
+            thermometer_offset_celcius: thermometer.offset_celcius,
+            fifo_size: match self {
+                Variant::MPU6050 => 1024,
+                Variant::MPU6500 | Variant::MPU9250 => 4096,
+            },
+            magnetometer: *self == Variant::MPU9250,
+        }
+    }
+}
+
+/// Complete description of a sensor's registers and conversion constants.
+/// Built-in variants are available through `Variant::register_map`, while clone chips can be described in a YAML file:
+///
+/// ```yaml
+/// name: Clone with odd WHO_AM_I
+/// accepted_who_am_i: [0x72, 0x98]
+/// who_am_i: 0x75
+/// pwr_mgmt_1: 0x6B
+/// # ...
+/// accelerometer: {start: 0x3B, end: 0x40}
+/// thermometer_sensitivity: 340.0
+/// thermometer_offset_celcius: 35.0
+/// ```
+///
+/// The easiest way to create such a file is to save the register map of the closest built-in variant and edit it.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RegisterMap {
+    pub name: String,
+    pub accepted_who_am_i: Vec<u8>, // Clones don't necessarily report the same value as the original
+    // Register addresses
+    pub who_am_i: u8,
+    pub pwr_mgmt_1: u8,
+    pub pwr_mgmt_2: u8,
+    pub int_pin_cfg: u8,
+    pub int_enable: u8,
+    pub int_status: u8,
+    #[serde(default = "default_smplrt_div")] // Register maps saved before it was added
+    pub smplrt_div: u8,
+    pub config: u8,
+    pub accel_config_2: Option<u8>, // Only present if the accelerometer has its own low pass filter setting
+    pub accelerometer: RangeInclusive<u8>,
+    pub thermometer: RangeInclusive<u8>,
+    pub gyroscope: RangeInclusive<u8>,
+    // Conversion constants
+    pub thermometer_sensitivity: f64,    // [LSB/(degree C)]
+    pub thermometer_offset_celcius: f64, // [degree C]
+    // Capabilities
+    pub fifo_size: usize, // [bytes]
+    pub magnetometer: bool,
+}
+
+impl RegisterMap {
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let file = std::fs::File::open(path.as_ref()).with_context(|| {
+            format!(
+                "Unable to open register map file: {}",
+                path.as_ref().display()
+            )
+        })?;
+        let register_map: Self =
+            serde_yaml::from_reader(file).context("Unable to parse register map.")?;
+        DataRegisters::try_from(&register_map)?; // Catch broken data register ranges on load, instead of on first use
+        Ok(register_map)
+    }
+
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        let file = std::fs::File::create(path.as_ref()).with_context(|| {
+            format!(
+                "Unable to create register map file: {}",
+                path.as_ref().display()
+            )
+        })?;
+        serde_yaml::to_writer(file, self).context("Unable to write register map.")
+    }
+
+    pub fn has_accelerometer_filter(&self) -> bool {
+        self.accel_config_2.is_some()
+    }
+
+    fn thermometer_configuration(&self) -> ThermometerConfiguration {
+        ThermometerConfiguration {
+            sensitivity: self.thermometer_sensitivity,
+            offset_celcius: self.thermometer_offset_celcius,
+            ..Default::default()
+        }
+    }
+}
+
+// The same on all built-in variants
+fn default_smplrt_div() -> u8 {
+    0x19
+}
+
+impl Default for RegisterMap {
+    fn default() -> Self {
+        Variant::default().register_map()
+    }
+}
+
 struct Register {
     address: u8,
     value: u8,
@@ -242,17 +577,24 @@ pub struct SettingsRegisters {
     int_pin_cfg: Register,
     int_enable: Register,
     int_status: Register,
-    config: Register, // Filter configuration
+    smplrt_div: Register,             // Sample rate divider
+    config: Register,                 // Filter configuration
+    accel_config_2: Option<Register>, // Accelerometer filter configuration. Only present if the accelerometer has its own filter
+    who_am_i: Register,
 }
 
 impl SettingsRegisters {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         pwr_mgmt_1: Register,
         pwr_mgmt_2: Register,
         int_pin_cfg: Register,
         int_enable: Register,
         int_status: Register,
+        smplrt_div: Register,
         config: Register,
+        accel_config_2: Option<Register>,
+        who_am_i: Register,
     ) -> Self {
         Self {
             pwr_mgmt_1,
@@ -260,24 +602,52 @@ impl SettingsRegisters {
             int_pin_cfg,
             int_enable,
             int_status,
+            smplrt_div,
             config,
+            accel_config_2,
+            who_am_i,
+        }
+    }
+
+    // A device reset restores the power-on defaults: Everything 0, except for the sleep bit in pwr_mgmt_1
+    fn reset_values(&mut self, pwr_mgmt_1: u8) {
+        self.pwr_mgmt_1.value = pwr_mgmt_1;
+        self.pwr_mgmt_2.value = 0;
+        self.int_pin_cfg.value = 0;
+        self.int_enable.value = 0;
+        self.int_status.value = 0;
+        self.smplrt_div.value = 0;
+        self.config.value = 0;
+        if let Some(accel_config_2) = &mut self.accel_config_2 {
+            accel_config_2.value = 0;
         }
     }
 }
 
-impl Default for SettingsRegisters {
-    fn default() -> Self {
+impl From<&RegisterMap> for SettingsRegisters {
+    fn from(register_map: &RegisterMap) -> Self {
         SettingsRegisters::new(
-            Register::new(0x6B, 0),
-            Register::new(0x6C, 0),
-            Register::new(0x37, 0),
-            Register::new(0x38, 0),
-            Register::new(0x3A, 0),
-            Register::new(0x1A, 0),
+            Register::new(register_map.pwr_mgmt_1, 0),
+            Register::new(register_map.pwr_mgmt_2, 0),
+            Register::new(register_map.int_pin_cfg, 0),
+            Register::new(register_map.int_enable, 0),
+            Register::new(register_map.int_status, 0),
+            Register::new(register_map.smplrt_div, 0),
+            Register::new(register_map.config, 0),
+            register_map
+                .accel_config_2
+                .map(|address| Register::new(address, 0)),
+            Register::new(register_map.who_am_i, 0),
         )
     }
 }
 
+impl Default for SettingsRegisters {
+    fn default() -> Self {
+        SettingsRegisters::from(&RegisterMap::default())
+    }
+}
+
 pub struct DataRegisters {
     accelerometer: RangeInclusive<u8>,
     thermometer: RangeInclusive<u8>,
@@ -314,19 +684,30 @@ impl DataRegisters {
     }
 }
 
+impl TryFrom<&RegisterMap> for DataRegisters {
+    type Error = anyhow::Error;
+
+    fn try_from(register_map: &RegisterMap) -> Result<Self> {
+        DataRegisters::new(
+            register_map.accelerometer.clone(),
+            register_map.thermometer.clone(),
+            register_map.gyroscope.clone(),
+        )
+    }
+}
+
 impl Default for DataRegisters {
     fn default() -> Self {
-        DataRegisters::new(0x3B..=0x40, 0x41..=0x42, 0x43..=0x48)
-            .expect("Unable to create data registers.")
+        DataRegisters::try_from(&RegisterMap::default()).expect("Unable to create data registers.")
     }
 }
 
 pub struct GyroscopeConfiguration {
-    #[allow(dead_code)]
     range: RangeInclusive<isize>, // Full-Scale Range [degree/s]
-    scale_factor: f64,         // Sensitivity Scale Factor [LSB/(degree/s)]
-    output_rate: f64,          // [Hz]
-    calibration_offset: Vec3D, // [egree/s]
+    scale_factor: f64,            // Sensitivity Scale Factor [LSB/(degree/s)]
+    output_rate: f64,             // [Hz]
+    calibration_offset: Vec3D,    // [egree/s]
+    calibration_matrix: Mat3,     // Scale and misalignment, applied before the offset
 }
 
 #[allow(dead_code)]
@@ -340,6 +721,7 @@ impl GyroscopeConfiguration {
             y: 0.0,
             z: 0.0,
         },
+        calibration_matrix: Mat3::IDENTITY,
     };
     pub const B: Self = Self {
         range: -500..=500,
@@ -350,6 +732,7 @@ impl GyroscopeConfiguration {
             y: 0.0,
             z: 0.0,
         },
+        calibration_matrix: Mat3::IDENTITY,
     };
     pub const C: Self = Self {
         range: -1000..=1000,
@@ -360,6 +743,7 @@ impl GyroscopeConfiguration {
             y: 0.0,
             z: 0.0,
         },
+        calibration_matrix: Mat3::IDENTITY,
     };
     pub const D: Self = Self {
         range: -2000..=2000,
@@ -370,7 +754,13 @@ impl GyroscopeConfiguration {
             y: 0.0,
             z: 0.0,
         },
+        calibration_matrix: Mat3::IDENTITY,
     };
+
+    /// Full-scale range [degree/s]
+    pub fn range(&self) -> RangeInclusive<isize> {
+        self.range.clone()
+    }
 }
 
 impl Default for GyroscopeConfiguration {
@@ -380,11 +770,11 @@ impl Default for GyroscopeConfiguration {
 }
 
 pub struct AccelerometerConfiguration {
-    #[allow(dead_code)]
     range: RangeInclusive<isize>, // Full-Scale Range [g]
-    scale_factor: usize,       // Sensitivity Scale Factor [LSB/g]
-    output_rate: f64,          // [Hz]
-    calibration_offset: Vec3D, // [g]
+    scale_factor: usize,          // Sensitivity Scale Factor [LSB/g]
+    output_rate: f64,             // [Hz]
+    calibration_offset: Vec3D,    // [g]
+    calibration_matrix: Mat3,     // Scale and misalignment, applied before the offset
 }
 
 #[allow(dead_code)]
@@ -398,6 +788,7 @@ impl AccelerometerConfiguration {
             y: 0.0,
             z: 0.0,
         },
+        calibration_matrix: Mat3::IDENTITY,
     };
     pub const B: Self = Self {
         range: -4..=4,
@@ -408,6 +799,7 @@ impl AccelerometerConfiguration {
             y: 0.0,
             z: 0.0,
         },
+        calibration_matrix: Mat3::IDENTITY,
     };
     pub const C: Self = Self {
         range: -8..=8,
@@ -418,6 +810,7 @@ impl AccelerometerConfiguration {
             y: 0.0,
             z: 0.0,
         },
+        calibration_matrix: Mat3::IDENTITY,
     };
     pub const D: Self = Self {
         range: -16..=16,
@@ -428,7 +821,13 @@ impl AccelerometerConfiguration {
             y: 0.0,
             z: 0.0,
         },
+        calibration_matrix: Mat3::IDENTITY,
     };
+
+    /// Full-scale range [g]
+    pub fn range(&self) -> RangeInclusive<isize> {
+        self.range.clone()
+    }
 }
 
 impl Default for AccelerometerConfiguration {
@@ -440,32 +839,45 @@ impl Default for AccelerometerConfiguration {
 pub struct ThermometerConfiguration {
     #[allow(dead_code)]
     range: RangeInclusive<isize>, // [degree C]
-    #[allow(dead_code)]
-    sensitivity: usize, // [LSB/(degree C)]
+    sensitivity: f64, // [LSB/(degree C)]
     #[allow(dead_code)]
     offset: isize, // [LSB]
-    offset_celcius: f64,     // [degree C]
+    offset_celcius: f64, // [degree C]
     calibration_offset: f64, // [degree C]
 }
 
+impl ThermometerConfiguration {
+    pub const MPU6050: Self = Self {
+        range: -40..=85,
+        sensitivity: 340.0,
+        offset: -521,
+        offset_celcius: 36.53, // See section 4.18 in revision 4.2 of register map
+        calibration_offset: 0.0,
+    };
+    // Also used by the MPU-9250, which contains an MPU-6500. See section 4.23 in revision 1.4 of the MPU-6500 register map
+    pub const MPU6500: Self = Self {
+        range: -40..=85,
+        sensitivity: 333.87,
+        offset: 0,
+        offset_celcius: 21.0,
+        calibration_offset: 0.0,
+    };
+}
+
 impl Default for ThermometerConfiguration {
     fn default() -> Self {
-        Self {
-            range: -40..=85,
-            sensitivity: 340,
-            offset: -521,
-            offset_celcius: 36.53, // See section 4.18 in revision 4.2 of register map
-            calibration_offset: 0.0,
-        }
+        Self::MPU6050
     }
 }
 
 // Not splitting up into individual sensors for gyroscope and accelerometer, since data needs to be read in one go (burst reading) for all sensors, to ensure that data is from the same sampling instance. See: https://stackoverflow.com/questions/65117246/mpu-6050-burst-read-auto-increment
 #[non_exhaustive]
 pub struct GY521 {
+    pub register_map: RegisterMap,
     pub acceleration: Vec3D,
     pub angular_velocity: Vec3D,
-    pub temperature: f64,
+    pub temperature: Option<f64>, // None if the thermometer is disabled
+    pub raw: Option<RawSample>, // Readings that the latest values were calibrated from. None until the first successful read
     pub data_registers: DataRegisters,
     pub settings_registers: SettingsRegisters,
     pub power_settings: PowerSettings,
@@ -480,22 +892,29 @@ pub struct GY521 {
     pub sample_rate_divider: u8, // Register 25: Used for determining sample rate: How often sensor samples should be output to the data registers, FIFO, or DMP. With a sample rate above the accelerometer output rate, the same accelerometer data will be output multiple times
     pub sample_rate: f64,        // [Hz]
     pub interrupt_configuration: InterruptConfiguration,
+    pub interrupt_timing: Option<InterruptTiming>, // Only recorded if Some. See `enable_interrupt_timing`
+    pub fifo_overflows: usize, // Reported in the interrupt status so far. The FIFO only overflows while it is enabled and not read fast enough
+    pub retry_policy: utilites::RetryPolicy, // Applied to all register reads and writes
+    pub bias_estimator: Option<calibration::BiasEstimator>, // Only estimated if Some. See `enable_bias_estimation`
+    pub outlier_rejection: Option<outlier::OutlierRejection>, // Only checked if Some. See `enable_outlier_rejection`
+    pub fifo: Option<fifo::Fifo>, // Only drained if Some. See `enable_fifo`
+    power_state: PowerState, // Private, so that it only changes along with the sensor's actual state
 }
 
 impl GY521 {
+    /// Data registers, settings registers, and thermometer constants are taken from {register_map}.
+    /// The thermometer starts out without a calibration offset. See `set_thermometer_offset`
     pub fn new(
-        data_registers: DataRegisters,
-        settings_registers: SettingsRegisters,
+        register_map: RegisterMap,
         power_settings: PowerSettings,
         i2c_address: u16,
         i2c_data_access_rate: f64,
         mut gyroscope_configuration: GyroscopeConfiguration,
         accelerometer_configuration: AccelerometerConfiguration,
-        thermometer_configuration: ThermometerConfiguration,
         configuration: Configuration,
         sample_rate_divider: u8,
         interrupt_configuration: InterruptConfiguration,
-    ) -> Self {
+    ) -> Result<Self> {
         gyroscope_configuration.output_rate = match configuration.filter {
             Filter::BwAc260HzBwGy256Hz | Filter::BwAc5HzBwGy5Hz => 8e3,
             _ => 1e3,
@@ -503,27 +922,82 @@ impl GY521 {
 
         let sample_rate = gyroscope_configuration.output_rate / (1.0 + sample_rate_divider as f64);
 
-        Self {
-            data_registers,
-            settings_registers,
+        Ok(Self {
+            data_registers: DataRegisters::try_from(&register_map)?,
+            settings_registers: SettingsRegisters::from(&register_map),
+            thermometer_configuration: register_map.thermometer_configuration(),
+            register_map,
             power_settings,
             i2c_address,
             i2c_data_access_rate,
             gyroscope_configuration,
             accelerometer_configuration,
-            thermometer_configuration,
             configuration,
             sample_rate_divider,
             sample_rate,
             interrupt_configuration,
+            interrupt_timing: None,
+            fifo_overflows: 0,
+            retry_policy: Default::default(),
+            bias_estimator: None,
+            outlier_rejection: None,
+            fifo: None,
             acceleration: Default::default(),
             angular_velocity: Default::default(),
             temperature: Default::default(),
-        }
+            raw: None,
+            power_state: PowerState::Sleep, // The sensor starts up in sleep mode. See section 4.28 in revision 4.2 of register map
+        })
+    }
+
+    pub fn power_state(&self) -> PowerState {
+        self.power_state
+    }
+
+    /// Sets the offset added to every temperature reading [degree C], e.g., found by comparing with a reference thermometer.
+    pub fn set_thermometer_offset(&mut self, calibration_offset: f64) {
+        self.thermometer_configuration.calibration_offset = calibration_offset;
+    }
+
+    /// Starts recording the timing of the latest {capacity} data ready interrupts.
+    pub fn enable_interrupt_timing(&mut self, capacity: usize) {
+        self.interrupt_timing = Some(InterruptTiming::new(self.sample_rate, capacity));
+    }
+
+    /// Starts refining the gyroscope offset whenever {window_size} consecutive samples show the sensor at rest.
+    pub fn enable_bias_estimation(&mut self, window_size: usize) {
+        self.bias_estimator = Some(calibration::BiasEstimator::new(window_size));
+    }
+
+    /// Starts checking each sample against the median of the latest {window_size} samples, flagging glitches. See `outlier::OutlierRejection`
+    pub fn enable_outlier_rejection(&mut self, window_size: usize) {
+        self.outlier_rejection = Some(outlier::OutlierRejection::new(window_size));
+    }
+
+    fn write_register(&self, bus: &impl Transport, address: u8, value: u8) -> Result<()> {
+        self.retry_policy
+            .run(|| bus.write_register(address, value))
+            .with_context(|| format!("Unable to write register {:#04x}.", address))
+    }
+
+    fn read_register(&self, bus: &impl Transport, address: u8) -> Result<u8> {
+        Ok(self.retry_policy.run(|| bus.read_register(address))?)
+    }
+
+    /// Identifies the sensor at {i2c_address} by its WHO_AM_I register.
+    pub fn detect_variant(bus: &mut impl Transport, i2c_address: u16) -> Result<Variant> {
+        bus.select(i2c_address)?;
+        let who_am_i = bus
+            .read_register(SettingsRegisters::default().who_am_i.address)
+            .context("Unable to read WHO_AM_I register.")?;
+        Variant::from_who_am_i(who_am_i)
+            .with_context(|| format!("Unknown WHO_AM_I value: {:#04x}", who_am_i))
     }
 
     // Raw acceleration, temperature, and angular velocity readings shifted to be signed integer values
-    fn read_raw(&self, i2c: &I2c) -> Result<SensorSample<[i16; 3], i16>> {
+    fn read_raw(&self, bus: &impl Transport) -> Result<RawSample> {
+        self.power_state.ensure_sampling("read sensor data")?;
+
         fn concat_bytes(low: u8, high: u8) -> u16 {
             low as u16 | ((high as u16) << 8)
         }
@@ -536,8 +1010,10 @@ impl GY521 {
             }
         }
 
+        // The thermometer registers sit between the accelerometer and gyroscope registers. Leaving them out would mean splitting the burst read, risking data from different sampling instances. So they are always read, but only parsed if the thermometer is active
         let mut data = vec![0u8; self.data_registers.data_range.len()];
-        i2c.block_read(*self.data_registers.data_range.start(), &mut data)?;
+        self.retry_policy
+            .run(|| bus.read_registers(*self.data_registers.data_range.start(), &mut data))?;
 
         let acceleration = &data[*self.data_registers.accelerometer.start() as usize
             ..=*self.data_registers.accelerometer.end() as usize];
@@ -547,9 +1023,11 @@ impl GY521 {
             shift_to_signed(concat_bytes(acceleration[5], acceleration[4])),
         ];
 
-        let temperature = &data[*self.data_registers.thermometer.start() as usize
-            ..=*self.data_registers.thermometer.end() as usize];
-        let temperature = shift_to_signed(concat_bytes(temperature[1], temperature[0]));
+        let temperature = self.power_settings.thermometer_active.then(|| {
+            let temperature = &data[*self.data_registers.thermometer.start() as usize
+                ..=*self.data_registers.thermometer.end() as usize];
+            shift_to_signed(concat_bytes(temperature[1], temperature[0]))
+        });
 
         let angular_velocity = &data[*self.data_registers.gyroscope.start() as usize
             ..=*self.data_registers.gyroscope.end() as usize];
@@ -567,14 +1045,17 @@ impl GY521 {
     }
 
     // Reads (acceleration, temperature, angular_velocity)
-    pub fn read(&mut self, i2c: &I2c) -> Result<SensorSample<Vec3D, f64>> {
-        let sample = self.read_raw(i2c)?;
+    pub fn read(&mut self, bus: &impl Transport) -> Result<SensorSample<Vec3D, f64>> {
+        self.raw = None;
+        let sample = self.read_raw(bus)?;
+        self.raw = Some(sample);
         let acceleration = Vec3D::new(
             sample.acceleration[0],
             sample.acceleration[1],
             sample.acceleration[2],
         );
-        self.acceleration = acceleration / self.accelerometer_configuration.scale_factor as f64
+        self.acceleration = self.accelerometer_configuration.calibration_matrix
+            * (acceleration / self.accelerometer_configuration.scale_factor as f64)
             + self.accelerometer_configuration.calibration_offset;
 
         let angular_velocity = Vec3D::new(
@@ -582,34 +1063,99 @@ impl GY521 {
             sample.angular_velocity[1],
             sample.angular_velocity[2],
         );
-        self.angular_velocity = angular_velocity / self.gyroscope_configuration.scale_factor as f64
+        self.angular_velocity = self.gyroscope_configuration.calibration_matrix
+            * (angular_velocity / self.gyroscope_configuration.scale_factor as f64)
             + self.gyroscope_configuration.calibration_offset;
 
-        self.temperature = sample.temperature as f64 / self.thermometer_configuration.sensitivity as f64
+        // Before the bias estimator, so that glitches don't end up in the offset. Flagged samples are kept from it entirely
+        let outlier = self
+            .outlier_rejection
+            .as_mut()
+            .is_some_and(|outlier_rejection| {
+                outlier_rejection.check(&mut self.acceleration, &mut self.angular_velocity)
+            });
+
+        if let (false, Some(bias_estimator)) = (outlier, &mut self.bias_estimator) {
+            if let Some(correction) =
+                bias_estimator.update(self.acceleration, self.angular_velocity)
+            {
+                self.gyroscope_configuration.calibration_offset += correction;
+            }
+        }
+
+        self.temperature = sample.temperature.map(|temperature| {
+            temperature as f64 / self.thermometer_configuration.sensitivity
             + self.thermometer_configuration.offset_celcius // See section 4.18 in revision 4.2 of register map
-            + self.thermometer_configuration.calibration_offset;
+            + self.thermometer_configuration.calibration_offset
+        });
 
-        Ok(SensorSample::new(
-            self.acceleration,
-            self.angular_velocity,
-            self.temperature,
-        ))
+        let mut sample =
+            SensorSample::new(self.acceleration, self.angular_velocity, self.temperature);
+        sample.outlier = outlier;
+        Ok(sample)
+    }
+
+    /// Like `read`, along with the raw readings that the sample was calibrated from. These stay as read, even if outlier rejection replaces the calibrated values
+    pub fn read_with_raw(
+        &mut self,
+        bus: &impl Transport,
+    ) -> Result<(RawSample, SensorSample<Vec3D, f64>)> {
+        let sample = self.read(bus)?;
+        let raw = self
+            .raw
+            .context("Raw readings are missing after reading.")?;
+        Ok((raw, sample))
     }
 
-    pub fn initialize(&mut self, i2c: &mut I2c) -> Result<()> {
-        i2c.set_slave_address(self.i2c_address)?;
+    pub fn initialize(&mut self, bus: &mut impl Transport) -> Result<()> {
+        bus.select(self.i2c_address)?;
+
+        // Make sure that we are talking to the sensor we think we are talking to, since register contents differ between variants
+        let who_am_i = self
+            .read_register(bus, self.settings_registers.who_am_i.address)
+            .context("Unable to read WHO_AM_I register.")?;
+        self.register_map
+            .accepted_who_am_i
+            .contains(&who_am_i)
+            .then_some(())
+            .with_context(|| {
+                format!(
+                    "Expected WHO_AM_I value in {:#04x?} for {}, but the sensor reported {:#04x} ({}).",
+                    self.register_map.accepted_who_am_i,
+                    self.register_map.name,
+                    who_am_i,
+                    Variant::from_who_am_i(who_am_i)
+                        .map_or("unknown variant".to_string(), |variant| format!("{:?}", variant))
+                )
+            })?;
+        self.settings_registers.who_am_i.value = who_am_i;
+
+        // A reset wipes all registers, so it has to happen before the rest of the configuration is written. The sensor is asleep afterwards
+        if self.power_settings.mode == PowerMode::Reset {
+            self.reset(bus)?;
+        }
+        // The sensor switches to I2C for good on anything that looks like I2C traffic, e.g., on a shared chip select line, unless that is disabled
+        if bus.interface() == Interface::Spi {
+            self.write_register(bus, USER_CTRL, I2C_IF_DIS)
+                .context("Unable to disable I2C interface.")?;
+        }
+        let power_state = match self.power_settings.mode {
+            PowerMode::Reset => PowerState::Sleep,
+            mode => PowerState::from(mode),
+        };
+        self.power_state.ensure_transition(power_state)?;
 
         // Set power settings
         let mut pwr_mgmt_1 = 0u8; // First power management register
         let mut pwr_mgmt_2 = 0u8; // Second power management register
-        match self.power_settings.mode {
-            PowerMode::Active => pwr_mgmt_1 = 0,
-            PowerMode::Cycle(wake_up_frequency) => {
+        match power_state {
+            PowerState::Active => pwr_mgmt_1 = 0,
+            PowerState::Cycle(wake_up_frequency) => {
                 pwr_mgmt_1 |= 1 << 5;
                 pwr_mgmt_2 |= (wake_up_frequency as u8) << 6;
             }
-            PowerMode::Reset => pwr_mgmt_1 |= 1 << 7,
-            PowerMode::Sleep => pwr_mgmt_1 |= 1 << 6,
+            PowerState::Sleep => pwr_mgmt_1 |= 1 << 6,
+            PowerState::Resetting => unreachable!("Reset is handled above"),
         }
 
         if !self.power_settings.thermometer_active {
@@ -627,10 +1173,11 @@ impl GY521 {
         pwr_mgmt_2 |= (!self.power_settings.gyroscope_z_active as u8) << 0;
 
         // Updating stored configuration only after successfully sending commands to sensor
-        i2c.smbus_write_byte(self.settings_registers.pwr_mgmt_1.address, pwr_mgmt_1)?;
+        self.write_register(bus, self.settings_registers.pwr_mgmt_1.address, pwr_mgmt_1)?;
         self.settings_registers.pwr_mgmt_1.value = pwr_mgmt_1;
-        i2c.smbus_write_byte(self.settings_registers.pwr_mgmt_2.address, pwr_mgmt_2)?;
+        self.write_register(bus, self.settings_registers.pwr_mgmt_2.address, pwr_mgmt_2)?;
         self.settings_registers.pwr_mgmt_2.value = pwr_mgmt_2;
+        self.power_state = power_state;
 
         // Set interrupt settings
         if let Some(interrupt_pin) = &mut self.interrupt_configuration.interrupt_pin {
@@ -657,58 +1204,79 @@ impl GY521 {
             int_enable |= (self.interrupt_configuration.i2c_master_interrupt as u8) << 3;
             int_enable |= (self.interrupt_configuration.data_ready as u8) << 0;
 
-            i2c.smbus_write_byte(self.settings_registers.int_pin_cfg.address, int_pin_cfg)?;
+            self.write_register(
+                bus,
+                self.settings_registers.int_pin_cfg.address,
+                int_pin_cfg,
+            )?;
             self.settings_registers.int_pin_cfg.value = int_pin_cfg;
-            i2c.smbus_write_byte(self.settings_registers.int_enable.address, int_enable)?;
+            self.write_register(bus, self.settings_registers.int_enable.address, int_enable)?;
             self.settings_registers.int_enable.value = int_enable;
         }
 
+        self.write_register(
+            bus,
+            self.settings_registers.smplrt_div.address,
+            self.sample_rate_divider,
+        )?;
+        self.settings_registers.smplrt_div.value = self.sample_rate_divider;
+
         // Set filter settings
         let mut config = 0u8;
         config |= (self.configuration.filter as u8) << 0;
         config |= (self.configuration.external_frame_synchronization as u8) << 3;
-        i2c.smbus_write_byte(self.settings_registers.config.address, config)?;
+        self.write_register(bus, self.settings_registers.config.address, config)?;
         self.settings_registers.config.value = config;
 
+        if let Some(address) = self
+            .settings_registers
+            .accel_config_2
+            .as_ref()
+            .map(|register| register.address)
+        {
+            // Bandwidths of the accelerometer filter settings roughly match those of the shared filter on the MPU-6050
+            let accel_config_2 = self.configuration.filter as u8;
+            self.write_register(bus, address, accel_config_2)?;
+            self.settings_registers.accel_config_2 = Some(Register::new(address, accel_config_2));
+        }
+
         Ok(())
     }
 
     /// Calculates calibration coefficients for acceleration and angular velocity based on {sample_size} samples.
     /// Samples for a duration of {sampling_duration}, attempting to sample with a period of {sampling_period}.
     /// Only the last {sample_size} samples are used.
-    /// Attempts to execute {status_action} once every {status_period}.
+    /// Fails once {cancellation} is cancelled, leaving the calibration as it was. {observer} is kept up to date on the way.
     ///
     /// Gyroscope output is expected to be 0 degrees/s for all axes under steady conditions.
     /// Accelerometer output is exepcted to be 0g for the x-, and y-axes, and 1g for the z-axis.
     /// The thermometer is not calibrated, because that can not be done simply by letting the sensor sit around in peace like for the other sensors.
-    pub fn calibrate<F>(
+    /// Samples deviating more than `CalibrationReport::OUTLIER_THRESHOLD` standard deviations from the rest are discarded.
+    /// Returns the resulting calibration together with a report on the collected samples, so it can be saved and applied again later on.
+    pub fn calibrate(
         &mut self,
         sample_size: usize,
         sampling_period: std::time::Duration,
         calibration_duration: std::time::Duration,
-        i2c: &mut I2c,
-        kill_signal: &crossbeam_channel::Receiver<()>,
-        status_period: std::time::Duration,
-        mut status_action: F,
-    ) where
-        F: FnMut(),
-    {
+        bus: &mut impl Transport,
+        cancellation: &utilites::CancellationToken,
+        observer: &mut impl calibration::CalibrationObserver,
+    ) -> Result<calibration::CalibrationData> {
+        self.power_state.ensure_sampling("calibrate")?;
+
         // 1.: Collect data for a while
         let interrupt_timeout = std::time::Duration::from_secs_f64(1.5 / self.sample_rate); // Timeout of more than one sampling period (in case of minor delay?), but less than two sampling periods
 
-        let mut samples = utilites::Memory::new(sample_size);
-        let mut errors = utilites::Memory::new(sample_size);
+        let mut samples = utilites::Memory::with_capacity(sample_size);
 
         let mut sample_count = 0;
-        let mut status_count = 0;
 
         let clock = Instant::now();
         loop {
-            if kill_signal.try_recv().is_ok() {
-                break;
-            }
+            // A calibration over part of the duration is no good for saving, so cancelling leaves the current one as it is
+            anyhow::ensure!(!cancellation.is_cancelled(), "Calibration cancelled.");
 
-            let (sample, sampling_instant) = self.wait_for_sample(i2c, Some(interrupt_timeout));
+            let (sample, sampling_instant) = self.wait_for_sample(bus, Some(interrupt_timeout));
 
             match sample {
                 Ok(sample) => {
@@ -728,14 +1296,13 @@ impl GY521 {
                     }
                 }
                 Err(error) => {
-                    errors.push((error, sampling_instant));
+                    observer.on_sample_rejected(calibration::RejectedSample::ReadError(&error));
                 }
             }
 
-            if clock.elapsed().as_nanos() / status_period.as_nanos() >= status_count {
-                status_action();
-                status_count += 1;
-            }
+            observer.on_progress(
+                (clock.elapsed().as_secs_f64() / calibration_duration.as_secs_f64()).min(1.0),
+            );
 
             if clock.elapsed() >= calibration_duration {
                 break; // Let's have a look at the samples
@@ -743,12 +1310,22 @@ impl GY521 {
         }
 
         // 2.: Compute offsets
-        let sum = samples.data.iter().fold(
-            SensorSample::<Vec3D, f64>::default(),
-            |sum, (sample, _time)| sum + *sample,
+        anyhow::ensure!(!samples.is_empty(), "No samples collected for calibration.");
+        let (report, samples, outliers) = calibration::CalibrationReport::new(
+            samples.make_contiguous(),
+            calibration::CalibrationReport::OUTLIER_THRESHOLD,
         );
+        for outlier in &outliers {
+            observer.on_sample_rejected(calibration::RejectedSample::Outlier(outlier));
+        }
+        let sum = samples
+            .iter()
+            .fold(SensorSample::<Vec3D, f64>::default(), |sum, sample| {
+                sum + *sample
+            });
 
         let mut offsets = -sum / samples.len() as f64;
+        let temperature = offsets.temperature.map(|temperature| -temperature);
         println!("Offsets: {:#?}", offsets);
         offsets.acceleration.z = 1.0 + offsets.acceleration.z; // 1g expected for z acceleration
         self.gyroscope_configuration.calibration_offset += offsets.angular_velocity;
@@ -761,33 +1338,126 @@ impl GY521 {
             "Accelerometer offsets: {:#?}",
             self.accelerometer_configuration.calibration_offset
         );
+
+        let calibration = calibration::CalibrationData {
+            report: Some(report),
+            ..self.calibration(temperature)
+        };
+        observer.on_complete(&calibration);
+        Ok(calibration)
+    }
+
+    /// Sets the sample rate as close to {sample_rate} as the sample rate divider allows, i.e., to the gyroscope output rate divided by 1 to 256, and returns the sample rate set.
+    /// Interrupt timing starts over at the new rate.
+    pub fn set_sample_rate(&mut self, sample_rate: f64, bus: &mut impl Transport) -> Result<f64> {
+        anyhow::ensure!(
+            sample_rate > 0.0 && sample_rate.is_finite(),
+            "Invalid sample rate: {sample_rate} Hz."
+        );
+        let output_rate = self.gyroscope_configuration.output_rate;
+        let divider = sample_rate_divider(output_rate, sample_rate);
+        self.write_register(bus, self.settings_registers.smplrt_div.address, divider)?;
+        self.settings_registers.smplrt_div.value = divider;
+        self.sample_rate_divider = divider;
+        self.sample_rate = output_rate / (1.0 + divider as f64);
+        if let Some(timing) = &mut self.interrupt_timing {
+            timing.period = 1.0 / self.sample_rate;
+            timing.previous = None;
+        }
+        Ok(self.sample_rate)
     }
 
     /// Set the power settings' clock source.
-    pub fn set_clock_source(&mut self, clock_source: ClockSource, i2c: &mut I2c) -> Result<()> {
+    pub fn set_clock_source(
+        &mut self,
+        clock_source: ClockSource,
+        bus: &mut impl Transport,
+    ) -> Result<()> {
+        anyhow::ensure!(
+            self.power_state != PowerState::Resetting,
+            "Cannot change clock source while the sensor is resetting."
+        );
         let mut pwr_mgmt_1 = self.settings_registers.pwr_mgmt_1.value;
         pwr_mgmt_1 &= u8::MAX << 2; // Reset clock source settings
         pwr_mgmt_1 |= clock_source as u8;
-        i2c.smbus_write_byte(self.settings_registers.pwr_mgmt_1.address, pwr_mgmt_1)?;
+        self.write_register(bus, self.settings_registers.pwr_mgmt_1.address, pwr_mgmt_1)?;
         self.power_settings.clock_source = clock_source;
         self.settings_registers.pwr_mgmt_1.value = pwr_mgmt_1;
         Ok(())
     }
 
-    pub fn sleep(&mut self, i2c: &mut I2c) -> Result<()> {
+    pub fn sleep(&mut self, bus: &mut impl Transport) -> Result<()> {
+        self.set_power_mode(PowerMode::Sleep, bus)
+    }
+
+    pub fn wake(&mut self, bus: &mut impl Transport) -> Result<()> {
+        self.set_power_mode(PowerMode::Active, bus)
+    }
+
+    /// Moves the sensor into power mode {mode}, if that is a valid transition from the current power state.
+    pub fn set_power_mode(&mut self, mode: PowerMode, bus: &mut impl Transport) -> Result<()> {
+        let power_state = PowerState::from(mode);
+        self.power_state.ensure_transition(power_state)?;
+
         let mut pwr_mgmt_1 = self.settings_registers.pwr_mgmt_1.value;
-        pwr_mgmt_1 |= 1 << 6;
-        i2c.smbus_write_byte(self.settings_registers.pwr_mgmt_1.address, pwr_mgmt_1)?;
+        let mut pwr_mgmt_2 = self.settings_registers.pwr_mgmt_2.value;
+        pwr_mgmt_1 &= !(1 << 6 | 1 << 5); // Reset sleep and cycle settings
+        match power_state {
+            PowerState::Active => (),
+            PowerState::Cycle(wake_up_frequency) => {
+                pwr_mgmt_1 |= 1 << 5;
+                pwr_mgmt_2 &= !(0b11 << 6); // Reset wake up frequency settings
+                pwr_mgmt_2 |= (wake_up_frequency as u8) << 6;
+            }
+            PowerState::Sleep => pwr_mgmt_1 |= 1 << 6,
+            PowerState::Resetting => return self.reset(bus),
+        }
+
+        self.write_register(bus, self.settings_registers.pwr_mgmt_1.address, pwr_mgmt_1)?;
         self.settings_registers.pwr_mgmt_1.value = pwr_mgmt_1;
+        self.write_register(bus, self.settings_registers.pwr_mgmt_2.address, pwr_mgmt_2)?;
+        self.settings_registers.pwr_mgmt_2.value = pwr_mgmt_2;
+        self.power_settings.mode = mode;
+        self.power_state = power_state;
         Ok(())
     }
 
+    /// Resets all of the sensor's registers to their default values and waits for the reset to finish.
+    /// The sensor is asleep afterwards, so it needs to be initialized again before use.
+    pub fn reset(&mut self, bus: &mut impl Transport) -> Result<()> {
+        const RESET_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(100);
+
+        self.power_state.ensure_transition(PowerState::Resetting)?;
+        self.write_register(bus, self.settings_registers.pwr_mgmt_1.address, 1 << 7)?;
+        self.power_state = PowerState::Resetting;
+
+        // The reset bit clears itself once the reset has finished
+        let clock = Instant::now();
+        loop {
+            // The sensor may not respond while it is busy resetting, so errors here are not fatal until the timeout is up
+            if let Ok(pwr_mgmt_1) = bus.read_register(self.settings_registers.pwr_mgmt_1.address) {
+                if pwr_mgmt_1 & (1 << 7) == 0 {
+                    self.settings_registers.reset_values(pwr_mgmt_1);
+                    self.power_state = PowerState::Sleep;
+                    self.fifo = None; // Disabled by the reset
+                    return Ok(());
+                }
+            }
+
+            (clock.elapsed() < RESET_TIMEOUT)
+                .then_some(())
+                .context("Sensor did not finish resetting in time.")?;
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
     pub fn wait_for_interrupt(
         &mut self,
-        i2c: &mut I2c,
+        bus: &mut impl Transport,
         reset: bool,
         timeout: Option<std::time::Duration>,
     ) -> Result<Option<InterruptStatus>> {
+        self.power_state.ensure_sampling("wait for interrupts")?;
         assert!(self.interrupt_configuration.interrupt_pin.is_some());
         let interrupt = self
             .interrupt_configuration
@@ -796,17 +1466,27 @@ impl GY521 {
             .unwrap()
             .poll_interrupt(reset, timeout)
             .context("Unable to poll interrupt.")?;
+        let interrupt_instant = Instant::now();
 
         Ok(match interrupt {
             Some(_) => {
-                let interrupt_byte = i2c
-                    .smbus_read_byte(self.settings_registers.int_status.address)
+                let interrupt_byte = self
+                    .read_register(bus, self.settings_registers.int_status.address)
                     .context("Unable to read interrupt status.")?;
-                Some(InterruptStatus {
+                let interrupt_status = InterruptStatus {
                     fifo_buffer_overflow: (interrupt_byte & (1 << 4)) != 0,
                     i2c_master_interrupt: (interrupt_byte & (1 << 3)) != 0,
                     data_ready: (interrupt_byte & (1 << 0)) != 0,
-                })
+                };
+                if interrupt_status.fifo_buffer_overflow {
+                    self.fifo_overflows += 1;
+                }
+                if let Some(interrupt_timing) = &mut self.interrupt_timing {
+                    if interrupt_status.data_ready {
+                        interrupt_timing.record(interrupt_instant);
+                    }
+                }
+                Some(interrupt_status)
             }
             None => None, // Timeout waiting for interrupt, I think
         })
@@ -814,18 +1494,18 @@ impl GY521 {
 
     pub fn wait_for_sample(
         &mut self,
-        i2c: &mut I2c,
+        bus: &mut impl Transport,
         timeout: Option<std::time::Duration>,
     ) -> (Result<Option<SensorSample<Vec3D, f64>>>, Instant) {
         let interrupt = self
-            .wait_for_interrupt(i2c, true, timeout)
+            .wait_for_interrupt(bus, true, timeout)
             .context("Cannot poll for interrupt.");
 
         match interrupt {
             Ok(interrupt) => match interrupt {
                 Some(interrupt_status) if interrupt_status.data_ready => {
                     let sampling_instant = Instant::now();
-                    let sample = self.read(i2c).context("Unable to read sensors.");
+                    let sample = self.read(bus).context("Unable to read sensors.");
                     match sample {
                         Ok(sample) => (Ok(Some(sample)), sampling_instant),
                         Err(error) => (Err(error), sampling_instant),
@@ -839,10 +1519,87 @@ impl GY521 {
     }
 }
 
+/// Where samples come from: The sensor itself, via `LiveSource`, or, e.g., a recording played back by `logging::replay::Replay`, so that processing code runs the same on either.
+/// Other sensors provide their own kind of sample {T} the same way, e.g., fixes of `gps::GpsReceiver`
+pub trait SampleSource<T = SensorSample<Vec3D, f64>> {
+    /// Waits up to {timeout} for the next sample, like `GY521::wait_for_sample`, and returns it with the instant it was taken at. Ok(None) if no sample came in time
+    fn next_sample(&mut self, timeout: Option<std::time::Duration>)
+        -> (Result<Option<T>>, Instant);
+
+    /// True once no more samples will come. The sensor never runs out
+    fn is_finished(&self) -> bool {
+        false
+    }
+}
+
+/// A sensor, this one by default, as a `SampleSource`, reading over {i2c}, or another bus {B}
+pub struct LiveSource<'a, S = GY521, B = I2c> {
+    pub sensor: &'a mut S,
+    pub i2c: &'a mut B,
+}
+
+impl<S: Sensor<B>, B> SampleSource<S::Sample> for LiveSource<'_, S, B> {
+    fn next_sample(
+        &mut self,
+        timeout: Option<std::time::Duration>,
+    ) -> (Result<Option<S::Sample>>, Instant) {
+        self.sensor.wait_for_sample(self.i2c, timeout)
+    }
+}
+
+impl Sensor for GY521 {
+    type Sample = SensorSample<Vec3D, f64>;
+
+    fn initialize(&mut self, i2c: &mut I2c) -> Result<()> {
+        GY521::initialize(self, i2c)
+    }
+
+    fn sample(&mut self, i2c: &I2c) -> Result<Self::Sample> {
+        self.read(i2c)
+    }
+
+    fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    fn wait_for_sample(
+        &mut self,
+        i2c: &mut I2c,
+        timeout: Option<std::time::Duration>,
+    ) -> (Result<Option<Self::Sample>>, Instant) {
+        GY521::wait_for_sample(self, i2c, timeout)
+    }
+}
+
+impl ImuSensor for GY521 {
+    fn calibrate(
+        &mut self,
+        i2c: &mut I2c,
+        sample_size: usize,
+        sampling_period: std::time::Duration,
+        duration: std::time::Duration,
+        cancellation: &utilites::CancellationToken,
+        observer: &mut impl calibration::CalibrationObserver,
+    ) -> Result<calibration::CalibrationData> {
+        GY521::calibrate(
+            self,
+            sample_size,
+            sampling_period,
+            duration,
+            i2c,
+            cancellation,
+            observer,
+        )
+    }
+
+    fn apply_calibration(&mut self, calibration: &calibration::CalibrationData) {
+        GY521::apply_calibration(self, calibration)
+    }
+}
+
 impl Default for GY521 {
     fn default() -> Self {
         Self::new(
-            Default::default(),
             Default::default(),
             Default::default(),
             0x68, // I2C default slave address
@@ -850,9 +1607,241 @@ impl Default for GY521 {
             Default::default(),
             Default::default(),
             Default::default(),
-            Default::default(),
             0,
             Default::default(),
         )
+        .expect("Default register map is valid.")
+    }
+}
+
+// Divider that brings {output_rate} closest to {sample_rate}, i.e., sample rate = output rate / (1 + divider)
+fn sample_rate_divider(output_rate: f64, sample_rate: f64) -> u8 {
+    ((output_rate / sample_rate).round() - 1.0).clamp(0.0, u8::MAX as f64) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        collections::{HashMap, VecDeque},
+    };
+
+    use super::*;
+
+    // Registers of an MPU-6050 that keep what is written to them, with a FIFO filled by the test instead of by sampling
+    #[derive(Default)]
+    pub(crate) struct FakeSensor {
+        pub registers: RefCell<HashMap<u8, u8>>,
+        pub fifo: RefCell<VecDeque<u8>>,
+    }
+
+    impl Transport for FakeSensor {
+        type Error = std::io::Error;
+
+        fn interface(&self) -> Interface {
+            Interface::I2c
+        }
+
+        fn select(&mut self, _i2c_address: u16) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn read_register(&self, register: u8) -> Result<u8, Self::Error> {
+            Ok(match register {
+                0x75 => 0x68, // WHO_AM_I of the MPU-6050
+                _ => self.registers.borrow().get(&register).copied().unwrap_or(0),
+            })
+        }
+
+        fn write_register(&self, register: u8, value: u8) -> Result<(), Self::Error> {
+            if register == USER_CTRL && value & fifo::FIFO_RESET != 0 {
+                self.fifo.borrow_mut().clear();
+            }
+            self.registers.borrow_mut().insert(register, value);
+            Ok(())
+        }
+
+        fn read_registers(&self, register: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+            match register {
+                fifo::FIFO_COUNT => {
+                    buffer.copy_from_slice(&(self.fifo.borrow().len() as u16).to_be_bytes())
+                }
+                fifo::FIFO_R_W => {
+                    let mut fifo = self.fifo.borrow_mut();
+                    for byte in buffer {
+                        *byte = fifo.pop_front().unwrap_or(0);
+                    }
+                }
+                _ => buffer.fill(0),
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_interrupt_timing() {
+        let mut timing = InterruptTiming::new(100.0, 10);
+        let start = Instant::now();
+        for milliseconds in [0, 10, 21, 29, 40, 70] {
+            timing.record(start + std::time::Duration::from_millis(milliseconds));
+        }
+
+        let statistics = timing.statistics().unwrap();
+        assert_eq!(statistics.count, 5);
+        assert_eq!(statistics.missed, 2);
+        assert!((statistics.min - -2e-3).abs() < 1e-9);
+        assert!((statistics.max - 1e-3).abs() < 1e-9);
+        assert!(statistics.mean.abs() < 1e-9);
+        assert!(statistics.p50.abs() < 1e-9);
+        assert!((statistics.p99 - 1e-3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_sample_rate_divider() {
+        assert_eq!(sample_rate_divider(1e3, 1e3), 0);
+        assert_eq!(sample_rate_divider(1e3, 200.0), 4);
+        assert_eq!(sample_rate_divider(1e3, 300.0), 2); // 333 Hz
+        assert_eq!(sample_rate_divider(8e3, 1.0), 255); // 31 Hz, as low as it goes
+        assert_eq!(sample_rate_divider(1e3, 5e3), 0);
+    }
+
+    #[test]
+    fn test_sample_cast() {
+        let sample = SensorSample::new(Vec3D::new(0.5, -1, 2), Vec3D::new(10, 0, 0), Some(25.5));
+        let single: SensorSample<Vec3D<f32>, f32> = sample.cast();
+        assert_eq!(
+            single.acceleration(),
+            Vec3D::from_components(0.5, -1.0, 2.0)
+        );
+        assert_eq!(single.temperature(), Some(25.5));
+        assert_eq!(
+            single.cast::<f64>().angular_velocity(),
+            sample.angular_velocity()
+        );
+    }
+
+    #[test]
+    fn test_power_state_transitions() {
+        use PowerState::*;
+        let cycle = Cycle(WakeFrequency::Freq5Hz);
+
+        // (from, to, allowed)
+        for (from, to, allowed) in [
+            (Sleep, Active, true),
+            (Sleep, cycle, true),
+            (Sleep, Sleep, true),
+            (Sleep, Resetting, true),
+            (Active, Sleep, true),
+            (Active, cycle, true),
+            (Active, Resetting, true),
+            (cycle, Active, true),
+            (cycle, Sleep, true),
+            (cycle, Resetting, true),
+            (Resetting, Resetting, true), // Retrying
+            (Resetting, Active, false),
+            (Resetting, cycle, false),
+            (Resetting, Sleep, false), // Only once the sensor reports the reset as finished
+        ] {
+            assert_eq!(from.can_transition_to(to), allowed, "{from:?} -> {to:?}");
+        }
+
+        // (state, sampling allowed)
+        for (state, sampling) in [
+            (Active, true),
+            (cycle, true),
+            (Sleep, false),
+            (Resetting, false),
+        ] {
+            assert_eq!(
+                state.ensure_sampling("sample").is_ok(),
+                sampling,
+                "{state:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_power_state_guards() {
+        let mut bus = FakeSensor::default();
+        let mut sensor = GY521::default();
+
+        // Asleep until initialized
+        assert_eq!(sensor.power_state(), PowerState::Sleep);
+        assert!(sensor.read(&bus).is_err());
+        assert!(sensor.wait_for_interrupt(&mut bus, true, None).is_err());
+        assert!(bus.registers.borrow().is_empty());
+        sensor.initialize(&mut bus).unwrap();
+        assert_eq!(sensor.power_state(), PowerState::Active);
+        assert!(sensor.read(&bus).is_ok());
+
+        // The reset bit never clears on the fake sensor, so the reset doesn't finish
+        assert!(sensor.reset(&mut bus).is_err());
+        assert_eq!(sensor.power_state(), PowerState::Resetting);
+        bus.registers.borrow_mut().clear();
+        assert!(sensor.read(&bus).is_err());
+        assert!(sensor.wake(&mut bus).is_err());
+        assert!(sensor.sleep(&mut bus).is_err());
+        assert!(sensor
+            .set_clock_source(ClockSource::GyroX, &mut bus)
+            .is_err());
+        assert!(bus.registers.borrow().is_empty()); // Nothing was sent
+        assert_eq!(sensor.power_state(), PowerState::Resetting);
+    }
+
+    #[test]
+    fn test_register_map() {
+        let path =
+            std::env::temp_dir().join(format!("njord_register_map_{}.yaml", std::process::id()));
+        let mut register_map = Variant::MPU6500.register_map();
+        register_map.name = "Clone".to_string();
+        register_map.accepted_who_am_i.push(0x98);
+        register_map.thermometer_sensitivity = 340.0;
+        register_map.save(&path).unwrap();
+        let loaded = RegisterMap::load(&path);
+
+        // A gap between the thermometer and gyroscope registers would split the burst read
+        let mut gap = register_map.clone();
+        gap.thermometer = 0x41..=0x41;
+        gap.save(&path).unwrap();
+        let rejected = RegisterMap::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), register_map);
+        assert!(rejected.is_err());
+        let mut overlap = register_map.clone();
+        overlap.gyroscope = 0x42..=0x47;
+        assert!(DataRegisters::try_from(&overlap).is_err());
+    }
+
+    #[test]
+    fn test_variant_detection() {
+        for (who_am_i, variant) in [
+            (0x68, Some(Variant::MPU6050)),
+            (0x70, Some(Variant::MPU6500)),
+            (0x71, Some(Variant::MPU9250)),
+            (0x98, None),
+        ] {
+            assert_eq!(Variant::from_who_am_i(who_am_i), variant);
+            if let Some(variant) = variant {
+                assert_eq!(variant.who_am_i(), who_am_i);
+                assert_eq!(variant.register_map().accepted_who_am_i, [who_am_i]);
+            }
+        }
+
+        let mut bus = FakeSensor::default();
+        assert_eq!(
+            GY521::detect_variant(&mut bus, 0x68).unwrap(),
+            Variant::MPU6050
+        );
+    }
+
+    #[test]
+    fn test_thermometer_offset() {
+        let mut bus = FakeSensor::default();
+        let mut sensor = GY521::default();
+        sensor.initialize(&mut bus).unwrap();
+        sensor.set_thermometer_offset(-1.5);
+        let temperature = sensor.read(&bus).unwrap().temperature().unwrap();
+        assert!((temperature - (36.53 - 1.5)).abs() < 1e-12); // All registers read 0
     }
 }
@@ -0,0 +1,653 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+use super::{SensorSample, GY521};
+use crate::math::{allan::AllanDeviation, Mat3, Vec3D};
+use crate::{sensors::transport::Transport, utilites};
+
+/// Everything needed to restore a calibration without having to let the sensor sit around in peace for minutes again.
+/// Calibrated values are computed as `matrix * measurement + offset`. The matrix covers per-axis scale as well as misalignment between the axes.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CalibrationData {
+    pub gyroscope_offset: Vec3D,     // [degree/s]
+    pub gyroscope_matrix: Mat3,      // [1]
+    pub accelerometer_offset: Vec3D, // [g]
+    pub accelerometer_matrix: Mat3,  // [1]
+    pub temperature: Option<f64>, // [degree C] Mean temperature while calibrating, if the thermometer was active
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub report: Option<CalibrationReport>, // How well behaved the sensor was while calibrating
+}
+
+impl CalibrationData {
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let file = std::fs::File::open(path.as_ref()).with_context(|| {
+            format!(
+                "Unable to open calibration file: {}",
+                path.as_ref().display()
+            )
+        })?;
+        serde_yaml::from_reader(file).context("Unable to parse calibration data.")
+    }
+
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        let file = std::fs::File::create(path.as_ref()).with_context(|| {
+            format!(
+                "Unable to create calibration file: {}",
+                path.as_ref().display()
+            )
+        })?;
+        serde_yaml::to_writer(file, self).context("Unable to write calibration data.")
+    }
+}
+
+impl Default for CalibrationData {
+    // No correction at all
+    fn default() -> Self {
+        Self {
+            gyroscope_offset: Vec3D::new(0, 0, 0),
+            gyroscope_matrix: Mat3::IDENTITY,
+            accelerometer_offset: Vec3D::new(0, 0, 0),
+            accelerometer_matrix: Mat3::IDENTITY,
+            temperature: None,
+            report: None,
+        }
+    }
+}
+
+/// Per-axis statistics of one sensor over a calibration window
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub struct AxisStatistics {
+    pub mean: Vec3D,
+    pub standard_deviation: Vec3D,
+    pub drift: Vec3D, // Change of the least squares line fitted through the window, from its start to its end
+}
+
+impl AxisStatistics {
+    /// {values} are (time [s], value) pairs
+    pub fn new(values: &[(f64, Vec3D)]) -> Self {
+        if values.is_empty() {
+            return Self::default();
+        }
+        let count = values.len() as f64;
+        let mean_time = values.iter().map(|(time, _)| time).sum::<f64>() / count;
+        let mean = values
+            .iter()
+            .fold(Vec3D::default(), |sum, (_, value)| sum + *value)
+            / count;
+
+        let mut variance = Vec3D::default();
+        let mut covariance = Vec3D::default(); // Between time and value
+        let mut time_variance = 0.0;
+        for (time, value) in values {
+            let deviation = *value - mean;
+            variance += deviation.component_mul(&deviation);
+            covariance += (time - mean_time) * deviation;
+            time_variance += (time - mean_time).powi(2);
+        }
+        variance /= count;
+
+        let duration = values.last().unwrap().0 - values[0].0;
+        let drift = if time_variance > 0.0 {
+            covariance * (duration / time_variance)
+        } else {
+            Vec3D::default()
+        };
+
+        Self {
+            mean,
+            standard_deviation: Vec3D::new(variance.x.sqrt(), variance.y.sqrt(), variance.z.sqrt()),
+            drift,
+        }
+    }
+
+    /// Whether {value} deviates from the mean by more than {threshold} standard deviations on any axis
+    pub fn is_outlier(&self, value: &Vec3D, threshold: f64) -> bool {
+        let deviation = *value - self.mean;
+        [
+            (deviation.x, self.standard_deviation.x),
+            (deviation.y, self.standard_deviation.y),
+            (deviation.z, self.standard_deviation.z),
+        ]
+        .into_iter()
+        .any(|(deviation, standard_deviation)| deviation.abs() > threshold * standard_deviation)
+    }
+}
+
+type Samples = Vec<SensorSample<Vec3D, f64>>;
+
+/// Summary of a calibration run, so calibrations of different sensor units can be compared, and bad runs (someone bumped the table) stand out.
+/// Statistics are of the raw-ish readings, i.e., with the previous calibration applied, and without the outliers.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CalibrationReport {
+    pub sample_count: usize,                  // Samples used for the calibration
+    pub rejected_outliers: usize, // Samples discarded for deviating too far from the rest
+    pub duration: f64,            // [s]
+    pub gyroscope: AxisStatistics, // [degree/s]
+    pub accelerometer: AxisStatistics, // [g]
+    pub temperature_span: Option<(f64, f64)>, // [degree C] (min, max), if the thermometer was active
+}
+
+impl CalibrationReport {
+    pub const OUTLIER_THRESHOLD: f64 = 4.0; // [standard deviations]
+
+    /// Rejects outliers among {samples} and summarizes the rest. Returns the report together with the accepted and the rejected samples.
+    pub fn new(
+        samples: &[(SensorSample<Vec3D, f64>, Instant)],
+        outlier_threshold: f64,
+    ) -> (Self, Samples, Samples) {
+        let start = samples.first().map(|(_, instant)| *instant);
+        let timed = |sample: &(SensorSample<Vec3D, f64>, Instant)| {
+            start.map_or(0.0, |start| sample.1.duration_since(start).as_secs_f64())
+        };
+        let statistics = |samples: &[&(SensorSample<Vec3D, f64>, Instant)]| {
+            let (gyroscope, accelerometer): (Vec<_>, Vec<_>) = samples
+                .iter()
+                .map(|sample| {
+                    let time = timed(sample);
+                    (
+                        (time, sample.0.angular_velocity),
+                        (time, sample.0.acceleration),
+                    )
+                })
+                .unzip();
+            (
+                AxisStatistics::new(&gyroscope),
+                AxisStatistics::new(&accelerometer),
+            )
+        };
+
+        let all = samples.iter().collect::<Vec<_>>();
+        let (gyroscope, accelerometer) = statistics(&all);
+        let (accepted, rejected): (Vec<_>, Vec<_>) = all.into_iter().partition(|(sample, _)| {
+            !gyroscope.is_outlier(&sample.angular_velocity, outlier_threshold)
+                && !accelerometer.is_outlier(&sample.acceleration, outlier_threshold)
+        });
+        let (gyroscope, accelerometer) = statistics(&accepted);
+
+        let temperature_span = accepted
+            .iter()
+            .filter_map(|(sample, _)| sample.temperature)
+            .fold(None, |span: Option<(f64, f64)>, temperature| {
+                Some(span.map_or((temperature, temperature), |(min, max)| {
+                    (min.min(temperature), max.max(temperature))
+                }))
+            });
+
+        let report = Self {
+            sample_count: accepted.len(),
+            rejected_outliers: rejected.len(),
+            duration: samples.last().map_or(0.0, timed),
+            gyroscope,
+            accelerometer,
+            temperature_span,
+        };
+        (
+            report,
+            accepted.into_iter().map(|(sample, _)| *sample).collect(),
+            rejected.into_iter().map(|(sample, _)| *sample).collect(),
+        )
+    }
+}
+
+/// Keeps refining the gyroscope offset during operation, whenever the sensor sits still.
+/// The bias drifts (e.g., while the sensor warms up), and a bias fixed at startup accumulates into heading drift over long recordings.
+pub struct BiasEstimator {
+    window: utilites::Memory<(Vec3D, Vec3D)>, // (acceleration [g], angular velocity [degree/s]) with the current calibration applied
+    pub acceleration_tolerance: f64, // [g] Maximum deviation of the acceleration magnitude from 1 g, to count as stationary
+    pub gyroscope_noise_threshold: f64, // [degree/s] Maximum standard deviation of the angular velocity, to count as stationary
+    pub max_bias: f64, // [degree/s] A steady turn has low variance as well. Mean angular velocities larger than this are not taken for bias
+    pub gain: f64, // [1] Fraction of the measured bias corrected per stationary window. Small values average over many windows
+    pub updates: usize, // Number of corrections made so far
+}
+
+impl BiasEstimator {
+    /// Evaluates windows of {window_size} samples. About a second worth of samples works well.
+    pub fn new(window_size: usize) -> Self {
+        Self {
+            window: utilites::Memory::new(window_size),
+            acceleration_tolerance: 0.05,
+            gyroscope_noise_threshold: 0.2,
+            max_bias: 2.0,
+            gain: 0.1,
+            updates: 0,
+        }
+    }
+
+    /// Whether the samples currently in the window look like the sensor is at rest
+    pub fn is_stationary(&self) -> bool {
+        if self.window.is_empty() {
+            return false;
+        }
+        let (acceleration, angular_velocity): (Vec<_>, Vec<_>) = self
+            .window
+            .iter()
+            .map(|(acceleration, angular_velocity)| {
+                ((0.0, *acceleration), (0.0, *angular_velocity))
+            })
+            .unzip();
+        let (acceleration, angular_velocity) = (
+            AxisStatistics::new(&acceleration),
+            AxisStatistics::new(&angular_velocity),
+        );
+        let noise = angular_velocity.standard_deviation;
+
+        (acceleration.mean.magnitude() - 1.0).abs() <= self.acceleration_tolerance
+            && noise.x.max(noise.y).max(noise.z) <= self.gyroscope_noise_threshold
+            && angular_velocity.mean.magnitude() <= self.max_bias
+    }
+
+    /// Takes a calibrated sample. Once the window is full, returns the correction to add to the gyroscope offset, if the sensor was stationary.
+    pub fn update(&mut self, acceleration: Vec3D, angular_velocity: Vec3D) -> Option<Vec3D> {
+        self.window.push((acceleration, angular_velocity));
+        if self.window.len() < self.window.capacity() {
+            return None;
+        }
+
+        let correction = self.is_stationary().then(|| {
+            let mean = self
+                .window
+                .iter()
+                .fold(Vec3D::default(), |sum, (_, angular_velocity)| {
+                    sum + *angular_velocity
+                })
+                / self.window.len() as f64;
+            -mean * self.gain
+        });
+        // Samples from before a correction would distort the next window, so each window starts over
+        self.window.clear();
+        self.updates += correction.is_some() as usize;
+        correction
+    }
+}
+
+/// A sample that didn't make it into a calibration
+#[derive(Debug)]
+pub enum RejectedSample<'a> {
+    ReadError(&'a anyhow::Error),
+    Outlier(&'a SensorSample<Vec3D, f64>), // See `CalibrationReport::OUTLIER_THRESHOLD`
+}
+
+/// Hooks for following a calibration, e.g., from a GUI, a TUI, or a plain terminal. All methods do nothing by default.
+/// `()` is the observer for headless runs that don't care.
+pub trait CalibrationObserver {
+    /// Called repeatedly while collecting samples, with {progress} from 0 to 1. Rate limiting the output is up to the observer.
+    fn on_progress(&mut self, _progress: f64) {}
+
+    fn on_sample_rejected(&mut self, _sample: RejectedSample) {}
+
+    fn on_complete(&mut self, _calibration: &CalibrationData) {}
+}
+
+impl CalibrationObserver for () {}
+
+/// Positions of the six-position accelerometer calibration. Each one points an axis straight up or down.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Orientation {
+    PositiveX,
+    NegativeX,
+    PositiveY,
+    NegativeY,
+    PositiveZ,
+    NegativeZ,
+}
+
+impl Orientation {
+    pub const ALL: [Orientation; 6] = [
+        Orientation::PositiveX,
+        Orientation::NegativeX,
+        Orientation::PositiveY,
+        Orientation::NegativeY,
+        Orientation::PositiveZ,
+        Orientation::NegativeZ,
+    ];
+
+    /// Acceleration measured by a perfect accelerometer at rest in this orientation [g]
+    pub fn expected_acceleration(&self) -> Vec3D {
+        match self {
+            Orientation::PositiveX => Vec3D::new(1, 0, 0),
+            Orientation::NegativeX => Vec3D::new(-1, 0, 0),
+            Orientation::PositiveY => Vec3D::new(0, 1, 0),
+            Orientation::NegativeY => Vec3D::new(0, -1, 0),
+            Orientation::PositiveZ => Vec3D::new(0, 0, 1),
+            Orientation::NegativeZ => Vec3D::new(0, 0, -1),
+        }
+    }
+}
+
+impl std::fmt::Display for Orientation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (axis, direction) = match self {
+            Orientation::PositiveX => ("X", "up"),
+            Orientation::NegativeX => ("X", "down"),
+            Orientation::PositiveY => ("Y", "up"),
+            Orientation::NegativeY => ("Y", "down"),
+            Orientation::PositiveZ => ("Z", "up"),
+            Orientation::NegativeZ => ("Z", "down"),
+        };
+        write!(f, "{} axis pointing {}", axis, direction)
+    }
+}
+
+/// Least squares fit of (matrix, offset), such that `matrix * measured + offset` matches expected, for (measured, expected) pairs.
+/// The matrix covers scale as well as misalignment, e.g., of a board not mounted perfectly orthogonal to the hull.
+/// This needs at least four orientations that don't all lie in one plane, like the six of `Orientation::ALL`.
+pub fn fit_calibration_matrix(pairs: &[(Vec3D, Vec3D)]) -> Result<(Mat3, Vec3D)> {
+    anyhow::ensure!(
+        pairs.len() >= 4,
+        "At least four orientations are needed to fit a calibration matrix."
+    );
+
+    // With centered data, the offset drops out: matrix = (sum of expected * measured^T) * (sum of measured * measured^T)^-1
+    let count = pairs.len() as f64;
+    let (measured_mean, expected_mean) = pairs.iter().fold(
+        (Vec3D::default(), Vec3D::default()),
+        |(measured_sum, expected_sum), (measured, expected)| {
+            (measured_sum + *measured, expected_sum + *expected)
+        },
+    );
+    let (measured_mean, expected_mean) = (measured_mean / count, expected_mean / count);
+
+    let (mut cross_covariance, mut covariance) = (Mat3::ZERO, Mat3::ZERO);
+    for (measured, expected) in pairs {
+        let (measured, expected) = (*measured - measured_mean, *expected - expected_mean);
+        cross_covariance += Mat3::outer_product(&expected, &measured);
+        covariance += Mat3::outer_product(&measured, &measured);
+    }
+    let matrix = cross_covariance * covariance.inverse().context(
+        "Measurements don't span all three axes. Was the sensor turned to enough different orientations?",
+    )?;
+
+    Ok((matrix, expected_mean - matrix * measured_mean))
+}
+
+/// Allan deviation of each gyroscope and accelerometer axis, in the order x, y, z
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct NoiseCharacterization {
+    pub gyroscope: [AllanDeviation; 3],     // [degree/s]
+    pub accelerometer: [AllanDeviation; 3], // [g]
+}
+
+impl NoiseCharacterization {
+    /// Analyzes a recording of uninterrupted samples, taken at rest with {sample_period} [s] between them.
+    /// Works on recorded data just as well as on samples collected live. See `GY521::characterize_noise`.
+    pub fn from_samples<'a>(
+        samples: impl IntoIterator<Item = &'a SensorSample<Vec3D, f64>>,
+        sample_period: f64,
+        points: usize,
+    ) -> Self {
+        let mut gyroscope = [Vec::new(), Vec::new(), Vec::new()];
+        let mut accelerometer = [Vec::new(), Vec::new(), Vec::new()];
+        for sample in samples {
+            for (axis, value) in gyroscope.iter_mut().zip(sample.angular_velocity.to_array()) {
+                axis.push(value);
+            }
+            for (axis, value) in accelerometer.iter_mut().zip(sample.acceleration.to_array()) {
+                axis.push(value);
+            }
+        }
+
+        let analyze = |rates: &Vec<f64>| AllanDeviation::compute(rates, sample_period, points);
+        Self {
+            gyroscope: gyroscope.each_ref().map(analyze),
+            accelerometer: accelerometer.each_ref().map(analyze),
+        }
+    }
+
+    /// Angle random walk of each gyroscope axis [degree/sqrt(s)]
+    pub fn angle_random_walk(&self) -> [Option<f64>; 3] {
+        self.gyroscope.each_ref().map(AllanDeviation::random_walk)
+    }
+
+    /// Velocity random walk of each accelerometer axis [g*sqrt(s)]
+    pub fn velocity_random_walk(&self) -> [Option<f64>; 3] {
+        self.accelerometer
+            .each_ref()
+            .map(AllanDeviation::random_walk)
+    }
+
+    /// Bias instability of each gyroscope axis [degree/s]
+    pub fn gyroscope_bias_instability(&self) -> [Option<f64>; 3] {
+        self.gyroscope
+            .each_ref()
+            .map(AllanDeviation::bias_instability)
+    }
+
+    /// Bias instability of each accelerometer axis [g]
+    pub fn accelerometer_bias_instability(&self) -> [Option<f64>; 3] {
+        self.accelerometer
+            .each_ref()
+            .map(AllanDeviation::bias_instability)
+    }
+}
+
+impl GY521 {
+    /// Records every sample for {duration} while the sensor is at rest, and characterizes its noise.
+    /// Bias instability only shows at long averaging times, so this should run for an hour or more. All samples are kept in memory until the end.
+    pub fn characterize_noise(
+        &mut self,
+        bus: &mut impl Transport,
+        duration: Duration,
+        cancellation: &utilites::CancellationToken,
+    ) -> Result<NoiseCharacterization> {
+        self.power_state.ensure_sampling("characterize noise")?;
+        let interrupt_timeout = Duration::from_secs_f64(1.5 / self.sample_rate);
+
+        let mut samples = Vec::with_capacity((duration.as_secs_f64() * self.sample_rate) as usize);
+        let clock = Instant::now();
+        while clock.elapsed() < duration {
+            anyhow::ensure!(
+                !cancellation.is_cancelled(),
+                "Noise characterization cancelled."
+            );
+            // The analysis assumes uniform sampling, so a failed read can't just be skipped like during calibration
+            let (sample, _) = self.wait_for_sample(bus, Some(interrupt_timeout));
+            if let Some(sample) =
+                sample.context("Failed to read a sample during noise characterization.")?
+            {
+                samples.push(sample);
+            }
+        }
+
+        Ok(NoiseCharacterization::from_samples(
+            &samples,
+            1.0 / self.sample_rate,
+            50,
+        ))
+    }
+
+    /// Calibrates offset, scale, and misalignment of the accelerometer by measuring gravity with each axis pointing up and down.
+    /// Before each position, {prompt} is called with the orientation to place the sensor in, and it should return once the sensor is in place and at rest.
+    /// Each position is then measured for {duration_per_position}, while {observer} follows the progress through all six positions.
+    /// The gyroscope calibration is kept as it is.
+    pub fn calibrate_six_position<P>(
+        &mut self,
+        bus: &mut impl Transport,
+        duration_per_position: Duration,
+        cancellation: &utilites::CancellationToken,
+        observer: &mut impl CalibrationObserver,
+        mut prompt: P,
+    ) -> Result<CalibrationData>
+    where
+        P: FnMut(Orientation) -> Result<()>,
+    {
+        self.power_state.ensure_sampling("calibrate")?;
+        let interrupt_timeout = Duration::from_secs_f64(1.5 / self.sample_rate);
+
+        let mut measurements = [Vec3D::default(); 6];
+        let mut total = SensorSample::<Vec3D, f64>::default();
+        let mut total_count = 0;
+        for (position, (orientation, measurement)) in Orientation::ALL
+            .into_iter()
+            .zip(&mut measurements)
+            .enumerate()
+        {
+            prompt(orientation)?;
+
+            let mut sum = SensorSample::<Vec3D, f64>::default();
+            let mut count = 0;
+            let clock = Instant::now();
+            while clock.elapsed() < duration_per_position {
+                anyhow::ensure!(!cancellation.is_cancelled(), "Calibration cancelled.");
+                // Errors are rare bus hiccups. Skipping a sample doesn't hurt an average
+                match self.wait_for_sample(bus, Some(interrupt_timeout)) {
+                    (Ok(Some(sample)), _) => {
+                        sum += sample;
+                        count += 1;
+                    }
+                    (Err(error), _) => {
+                        observer.on_sample_rejected(RejectedSample::ReadError(&error))
+                    }
+                    _ => (),
+                }
+                let position_progress =
+                    (clock.elapsed().as_secs_f64() / duration_per_position.as_secs_f64()).min(1.0);
+                observer.on_progress(
+                    (position as f64 + position_progress) / Orientation::ALL.len() as f64,
+                );
+            }
+            anyhow::ensure!(count > 0, "No samples collected with the {}.", orientation);
+
+            *measurement = sum.acceleration / count as f64;
+            total += sum;
+            total_count += count;
+        }
+
+        // Measurements already have the current calibration applied, so the correction is applied on top of it
+        let pairs = Orientation::ALL
+            .iter()
+            .zip(measurements)
+            .map(|(orientation, measurement)| (measurement, orientation.expected_acceleration()))
+            .collect::<Vec<_>>();
+        let (matrix, offset) = fit_calibration_matrix(&pairs)?;
+        self.accelerometer_configuration.calibration_matrix =
+            matrix * self.accelerometer_configuration.calibration_matrix;
+        self.accelerometer_configuration.calibration_offset =
+            matrix * self.accelerometer_configuration.calibration_offset + offset;
+
+        let calibration = self.calibration((total / total_count as f64).temperature);
+        observer.on_complete(&calibration);
+        Ok(calibration)
+    }
+
+    /// Snapshot of the calibration currently applied to readings. {temperature} is the temperature the calibration was made at, if known.
+    pub fn calibration(&self, temperature: Option<f64>) -> CalibrationData {
+        CalibrationData {
+            gyroscope_offset: self.gyroscope_configuration.calibration_offset,
+            gyroscope_matrix: self.gyroscope_configuration.calibration_matrix,
+            accelerometer_offset: self.accelerometer_configuration.calibration_offset,
+            accelerometer_matrix: self.accelerometer_configuration.calibration_matrix,
+            temperature,
+            report: None,
+        }
+    }
+
+    /// Replaces the current calibration, e.g., with one loaded from a file.
+    pub fn apply_calibration(&mut self, calibration: &CalibrationData) {
+        self.gyroscope_configuration.calibration_offset = calibration.gyroscope_offset;
+        self.gyroscope_configuration.calibration_matrix = calibration.gyroscope_matrix;
+        self.accelerometer_configuration.calibration_offset = calibration.accelerometer_offset;
+        self.accelerometer_configuration.calibration_matrix = calibration.accelerometer_matrix;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_calibration_matrix() {
+        // Slightly rotated about z, scaled, and sheared
+        let matrix = Mat3::from_rows(
+            &Vec3D::new(1.02, -0.05, 0.01),
+            &Vec3D::new(0.04, 0.97, 0.0),
+            &Vec3D::new(-0.01, 0.02, 1.1),
+        );
+        let offset = Vec3D::new(0.05, -0.03, 0.2);
+        // Measurements of an accelerometer that is corrected by matrix * measured + offset
+        let inverse = matrix.inverse().unwrap();
+        let pairs = Orientation::ALL
+            .iter()
+            .map(|orientation| {
+                let expected = orientation.expected_acceleration();
+                (inverse * (expected - offset), expected)
+            })
+            .collect::<Vec<_>>();
+
+        let (fitted_matrix, fitted_offset) = fit_calibration_matrix(&pairs).unwrap();
+        assert!((0..3).all(|row| (fitted_matrix.row(row) - matrix.row(row)).near_zero()));
+        assert!((fitted_offset - offset).near_zero());
+        for (measured, expected) in pairs {
+            assert!((fitted_matrix * measured + fitted_offset - expected).near_zero());
+        }
+    }
+
+    #[test]
+    fn test_calibration_report() {
+        let start = Instant::now();
+        let mut samples = (0..100)
+            .map(|i| {
+                let sample = SensorSample {
+                    acceleration: Vec3D::new(0.0, 0.0, 1.0 + if i % 2 == 0 { 0.01 } else { -0.01 }),
+                    angular_velocity: Vec3D::new(0.5 + 0.001 * i as f64, 0.0, 0.0),
+                    temperature: Some(25.0 + 0.01 * i as f64),
+                    outlier: false,
+                };
+                (sample, start + Duration::from_millis(10 * i))
+            })
+            .collect::<Vec<_>>();
+        samples[50].0.acceleration.z = 3.0; // Bump
+
+        let (report, accepted, rejected) =
+            CalibrationReport::new(&samples, CalibrationReport::OUTLIER_THRESHOLD);
+        assert_eq!(report.rejected_outliers, 1);
+        assert_eq!(rejected[0].acceleration.z, 3.0);
+        assert_eq!(report.sample_count, 99);
+        assert_eq!(accepted.len(), 99);
+        assert!((report.accelerometer.mean - Vec3D::new(0, 0, 1)).magnitude() < 1e-3);
+        assert!((report.accelerometer.standard_deviation.z - 0.01).abs() < 1e-3);
+        // Gyroscope x rises by 0.001 degree/s per sample, i.e., by 0.099 degree/s over the window
+        assert!((report.gyroscope.drift.x - 0.099).abs() < 1e-3);
+        let (min, max) = report.temperature_span.unwrap();
+        assert!((min - 25.0).abs() < 1e-9 && (max - 25.99).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_bias_estimator() {
+        let bias = Vec3D::new(0.3, -0.2, 0.1);
+        let mut offset = Vec3D::default();
+        let mut estimator = BiasEstimator::new(100);
+        for i in 0..10_000 {
+            let noise = if i % 2 == 0 { 0.05 } else { -0.05 };
+            if let Some(correction) = estimator.update(
+                Vec3D::new(0, 0, 1),
+                bias + offset + Vec3D::new(noise, noise, noise),
+            ) {
+                offset += correction;
+            }
+        }
+        assert_eq!(estimator.updates, 100);
+        assert!((offset + bias).magnitude() < 1e-3);
+
+        // A bump, or turning the sensor, is not stationary
+        for _ in 0..100 {
+            assert!(estimator
+                .update(Vec3D::new(0, 0, 1.5), Vec3D::default())
+                .is_none());
+        }
+        for _ in 0..100 {
+            assert!(estimator
+                .update(Vec3D::new(0, 0, 1), Vec3D::new(0, 0, 10))
+                .is_none());
+        }
+        assert_eq!(estimator.updates, 100);
+    }
+
+    #[test]
+    fn test_fit_calibration_matrix_unturned() {
+        let pairs = Orientation::ALL
+            .map(|orientation| (Vec3D::new(0, 0, 1), orientation.expected_acceleration()));
+        assert!(fit_calibration_matrix(&pairs).is_err());
+        assert!(fit_calibration_matrix(&pairs[..3]).is_err());
+    }
+}
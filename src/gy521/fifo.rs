@@ -0,0 +1,460 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use rppal::i2c::I2c;
+
+use super::{SampleSource, SensorSample, GY521, I2C_IF_DIS, USER_CTRL};
+use crate::{
+    math::{Mat3, Vec3D},
+    sensors::transport::{Interface, Transport},
+};
+
+// Same addresses on all variants
+pub(super) const FIFO_EN: u8 = 0x23; // Which measurements go into the FIFO
+pub(super) const FIFO_COUNT: u8 = 0x72; // Number of bytes in the FIFO, high byte first
+pub(super) const FIFO_R_W: u8 = 0x74; // Reading it repeatedly pops bytes off the FIFO
+                                      // In USER_CTRL
+pub(super) const FIFO_ENABLE: u8 = 1 << 6;
+pub(super) const FIFO_RESET: u8 = 1 << 2; // Clears itself once the FIFO is empty
+
+const FIFO_BURST: usize = 1024; // [bytes] Read at a time. Well within the 4096 bytes spidev transfers by default
+
+/// Which measurements the sensor writes into its FIFO buffer (see FIFO_EN, register 35).
+/// Each frame holds them in register order: Acceleration, temperature, angular velocity, as big endian 16 bit values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FifoLayout {
+    pub accelerometer: bool,
+    pub thermometer: bool,
+    pub gyroscope: bool,
+}
+
+impl FifoLayout {
+    pub const ALL: Self = Self {
+        accelerometer: true,
+        thermometer: true,
+        gyroscope: true,
+    };
+
+    /// [bytes]
+    pub fn frame_size(&self) -> usize {
+        6 * self.accelerometer as usize
+            + 2 * self.thermometer as usize
+            + 6 * self.gyroscope as usize
+    }
+
+    // Value of FIFO_EN. The gyroscope axes are enabled separately, but always together here, to keep frames whole
+    fn fifo_en(&self) -> u8 {
+        (self.thermometer as u8) << 7
+            | ((self.gyroscope as u8) * 0b111) << 4
+            | (self.accelerometer as u8) << 3
+    }
+}
+
+/// How raw readings of one sensor become physical values: `matrix * (raw / scale_factor) + offset`, like `GY521::read` does it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scaling {
+    pub scale_factor: f64, // [LSB/unit]
+    pub matrix: Mat3,
+    pub offset: Vec3D, // [unit]
+}
+
+// Scaling folded into one multiply-add per component: rows = matrix / scale_factor
+#[derive(Debug, Clone, Copy)]
+struct Affine {
+    rows: [[f64; 3]; 3],
+    offset: [f64; 3],
+}
+
+impl Affine {
+    fn new(scaling: &Scaling) -> Self {
+        Self {
+            rows: (scaling.matrix * (1.0 / scaling.scale_factor)).rows,
+            offset: scaling.offset.to_array(),
+        }
+    }
+
+    #[inline(always)]
+    fn apply(&self, raw: &[f64]) -> Vec3D {
+        let [x, y, z] = self
+            .rows
+            .map(|row| row[0] * raw[0] + row[1] * raw[1] + row[2] * raw[2]);
+        Vec3D::new(x + self.offset[0], y + self.offset[1], z + self.offset[2])
+    }
+}
+
+/// Converts a whole FIFO drain into scaled samples in one pass, instead of one burst read at a time.
+/// All words are decoded first, in a loop simple enough for the compiler to vectorize, and the scale factors are folded into the calibration matrices, so there is no division per sample.
+/// The scaling is copied when the converter is made, so a new one is needed after changing ranges or calibration.
+#[derive(Debug, Clone)]
+pub struct BatchConverter {
+    pub layout: FifoLayout,
+    accelerometer: Affine,
+    gyroscope: Affine,
+    temperature_scale: f64,  // [degree C/LSB]
+    temperature_offset: f64, // [degree C]
+    words: Vec<f64>, // Decoded words of the latest drain, kept to avoid allocating every time
+}
+
+impl BatchConverter {
+    pub fn new(
+        layout: FifoLayout,
+        accelerometer: &Scaling,
+        gyroscope: &Scaling,
+        thermometer_sensitivity: f64, // [LSB/(degree C)]
+        thermometer_offset: f64,      // [degree C]
+    ) -> Self {
+        Self {
+            layout,
+            accelerometer: Affine::new(accelerometer),
+            gyroscope: Affine::new(gyroscope),
+            temperature_scale: 1.0 / thermometer_sensitivity,
+            temperature_offset: thermometer_offset,
+            words: Vec::new(),
+        }
+    }
+
+    /// Appends a sample per complete frame in {bytes} to {samples}, and returns the number of frames converted.
+    /// Bytes of a trailing incomplete frame are left alone, so they can be prepended to the next drain.
+    /// Measurements missing from the layout are zero (or None, for the temperature).
+    pub fn convert(&mut self, bytes: &[u8], samples: &mut Vec<SensorSample<Vec3D, f64>>) -> usize {
+        let frame_size = self.layout.frame_size();
+        if frame_size == 0 {
+            return 0;
+        }
+        let frames = bytes.len() / frame_size;
+
+        self.words.clear();
+        self.words.extend(
+            bytes[..frames * frame_size]
+                .chunks_exact(2)
+                .map(|word| i16::from_be_bytes([word[0], word[1]]) as f64),
+        );
+
+        samples.reserve(frames);
+        for frame in self.words.chunks_exact(frame_size / 2) {
+            let mut frame = frame;
+            let mut take = |words: usize| {
+                let (taken, rest) = frame.split_at(words);
+                frame = rest;
+                taken
+            };
+            let acceleration = if self.layout.accelerometer {
+                self.accelerometer.apply(take(3))
+            } else {
+                Vec3D::default()
+            };
+            let temperature = self
+                .layout
+                .thermometer
+                .then(|| take(1)[0] * self.temperature_scale + self.temperature_offset);
+            let angular_velocity = if self.layout.gyroscope {
+                self.gyroscope.apply(take(3))
+            } else {
+                Vec3D::default()
+            };
+            samples.push(SensorSample::new(
+                acceleration,
+                angular_velocity,
+                temperature,
+            ));
+        }
+        frames
+    }
+}
+
+/// An enabled FIFO, with what is needed for draining it. See `GY521::enable_fifo`
+#[derive(Debug, Clone)]
+pub struct Fifo {
+    pub converter: BatchConverter,
+    bytes: Vec<u8>, // Drained, but not converted yet, i.e., the start of an incomplete frame
+}
+
+impl GY521 {
+    /// Starts buffering samples of {layout} in the sensor's FIFO, to be collected in batches by `drain_fifo`, e.g., at sample rates too high for reading every sample on its own.
+    /// Anything buffered before is discarded. The conversion is set up from the current ranges and calibration, so this needs to be called again after changing them.
+    pub fn enable_fifo(&mut self, layout: FifoLayout, bus: &mut impl Transport) -> Result<()> {
+        self.power_state.ensure_sampling("enable the FIFO")?;
+        anyhow::ensure!(layout.frame_size() > 0, "Nothing to buffer in the FIFO.");
+        self.write_register(bus, FIFO_EN, layout.fifo_en())?;
+        self.restart_fifo(bus)?;
+        self.fifo = Some(Fifo {
+            converter: self.batch_converter(layout),
+            bytes: Vec::new(),
+        });
+        Ok(())
+    }
+
+    pub fn disable_fifo(&mut self, bus: &mut impl Transport) -> Result<()> {
+        self.write_register(bus, USER_CTRL, user_ctrl(bus.interface()))?;
+        self.write_register(bus, FIFO_EN, 0)?;
+        self.fifo = None;
+        Ok(())
+    }
+
+    // Empties the FIFO and starts filling it again
+    fn restart_fifo(&self, bus: &impl Transport) -> Result<()> {
+        let user_ctrl = user_ctrl(bus.interface());
+        self.write_register(bus, USER_CTRL, user_ctrl | FIFO_RESET)?;
+        self.write_register(bus, USER_CTRL, user_ctrl | FIFO_ENABLE)
+    }
+
+    /// Reads everything the FIFO has buffered since the last drain, and appends it to {samples} in one batch. Returns the number of samples appended.
+    /// Unlike `read`, the samples don't pass through outlier rejection or the bias estimator, and the sensor's latest values are left alone.
+    /// A full FIFO has lost samples, and a failed read leaves it somewhere in the middle of a frame, so in either case it is restarted and an error returned.
+    pub fn drain_fifo(
+        &mut self,
+        bus: &impl Transport,
+        samples: &mut Vec<SensorSample<Vec3D, f64>>,
+    ) -> Result<usize> {
+        self.power_state.ensure_sampling("drain the FIFO")?;
+        anyhow::ensure!(self.fifo.is_some(), "The FIFO is not enabled.");
+
+        let mut count = [0u8; 2];
+        self.retry_policy
+            .run(|| bus.read_registers(FIFO_COUNT, &mut count))
+            .context("Unable to read FIFO count.")?;
+        let count = u16::from_be_bytes(count) as usize;
+        if count >= self.register_map.fifo_size {
+            self.restart_fifo(bus)?;
+            self.fifo.as_mut().unwrap().bytes.clear();
+            anyhow::bail!("The FIFO overflowed, so it was restarted.");
+        }
+
+        // Retrying would pop further bytes off the FIFO, so reads are not retried
+        let fifo = self.fifo.as_mut().unwrap();
+        let start = fifo.bytes.len();
+        fifo.bytes.resize(start + count, 0);
+        let read = fifo.bytes[start..]
+            .chunks_mut(FIFO_BURST)
+            .try_for_each(|chunk| bus.read_registers(FIFO_R_W, chunk));
+        if let Err(error) = read {
+            fifo.bytes.clear();
+            self.restart_fifo(bus)?;
+            return Err(error).context("Unable to read the FIFO, so it was restarted.");
+        }
+
+        let frames = fifo.converter.convert(&fifo.bytes, samples);
+        fifo.bytes
+            .drain(..frames * fifo.converter.layout.frame_size());
+        Ok(frames)
+    }
+
+    /// Converter for FIFO data with the current ranges and calibration.
+    /// Unlike `read`, the converted samples don't pass through outlier rejection or the bias estimator.
+    pub fn batch_converter(&self, layout: FifoLayout) -> BatchConverter {
+        let accelerometer = Scaling {
+            scale_factor: self.accelerometer_configuration.scale_factor as f64,
+            matrix: self.accelerometer_configuration.calibration_matrix,
+            offset: self.accelerometer_configuration.calibration_offset,
+        };
+        let gyroscope = Scaling {
+            scale_factor: self.gyroscope_configuration.scale_factor,
+            matrix: self.gyroscope_configuration.calibration_matrix,
+            offset: self.gyroscope_configuration.calibration_offset,
+        };
+        BatchConverter::new(
+            layout,
+            &accelerometer,
+            &gyroscope,
+            self.thermometer_configuration.sensitivity,
+            self.thermometer_configuration.offset_celcius
+                + self.thermometer_configuration.calibration_offset,
+        )
+    }
+}
+
+// USER_CTRL with the FIFO disabled. SPI needs the I2C interface to stay disabled, see `GY521::initialize`
+fn user_ctrl(interface: Interface) -> u8 {
+    match interface {
+        Interface::I2c => 0,
+        Interface::Spi => I2C_IF_DIS,
+    }
+}
+
+/// A sensor, with its FIFO enabled, as a `SampleSource`, reading over {bus}.
+/// The FIFO is drained every {drain_period}, and the samples handed out one at a time. It holds 1024 bytes on the MPU-6050, i.e., 73 full samples, or 73 ms at 1 kHz, so the period needs to be shorter than that.
+/// The FIFO doesn't keep track of when samples were taken, so their instants are counted back from the drain at the sample rate.
+pub struct FifoSource<'a, B = I2c> {
+    pub sensor: &'a mut GY521,
+    pub bus: &'a mut B,
+    pub drain_period: Duration,
+    batch: Vec<SensorSample<Vec3D, f64>>,
+    samples: VecDeque<(SensorSample<Vec3D, f64>, Instant)>,
+}
+
+impl<'a, B> FifoSource<'a, B> {
+    pub fn new(sensor: &'a mut GY521, bus: &'a mut B, drain_period: Duration) -> Self {
+        Self {
+            sensor,
+            bus,
+            drain_period,
+            batch: Vec::new(),
+            samples: VecDeque::new(),
+        }
+    }
+}
+
+impl<B: Transport> SampleSource for FifoSource<'_, B> {
+    fn next_sample(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> (Result<Option<SensorSample<Vec3D, f64>>>, Instant) {
+        if self.samples.is_empty() {
+            std::thread::sleep(
+                timeout.map_or(self.drain_period, |timeout| timeout.min(self.drain_period)),
+            );
+            self.batch.clear();
+            let drain = self.sensor.drain_fifo(self.bus, &mut self.batch);
+            let drain_instant = Instant::now();
+            if let Err(error) = drain {
+                return (Err(error), drain_instant);
+            }
+            let period = Duration::from_secs_f64(1.0 / self.sensor.sample_rate);
+            let count = self.batch.len() as u32;
+            self.samples.extend(
+                self.batch
+                    .iter()
+                    .zip(1..)
+                    .map(|(sample, number)| (*sample, drain_instant - period * (count - number))),
+            );
+        }
+        match self.samples.pop_front() {
+            Some((sample, instant)) => (Ok(Some(sample)), instant),
+            None => (Ok(None), Instant::now()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gy521::tests::FakeSensor;
+
+    fn scaling(scale_factor: f64, offset: Vec3D) -> Scaling {
+        Scaling {
+            scale_factor,
+            matrix: Mat3::from_diagonal(&Vec3D::new(1.0, 2.0, 0.5)),
+            offset,
+        }
+    }
+
+    fn frame(words: &[i16]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_be_bytes()).collect()
+    }
+
+    #[test]
+    fn test_batch_conversion() {
+        let accelerometer = scaling(16_384.0, Vec3D::new(0.0, 0.0, 0.1));
+        let gyroscope = scaling(131.0, Vec3D::new(-1, 0, 0));
+        let mut converter =
+            BatchConverter::new(FifoLayout::ALL, &accelerometer, &gyroscope, 340.0, 36.53);
+
+        let mut bytes = frame(&[16_384, -8_192, 0, -340, 131, 0, -262]);
+        bytes.extend(frame(&[0, 0, -16_384, 0, 0, 0, 0]));
+        bytes.extend([0x12, 0x34, 0x56]); // Incomplete third frame
+
+        let mut samples = Vec::new();
+        assert_eq!(converter.convert(&bytes, &mut samples), 2);
+        assert_eq!(samples.len(), 2);
+        assert!(samples[0]
+            .acceleration()
+            .approx_eq(&Vec3D::new(1.0, -1.0, 0.1), 1e-12));
+        assert!((samples[0].temperature().unwrap() - 35.53).abs() < 1e-12);
+        assert!(samples[0]
+            .angular_velocity()
+            .approx_eq(&Vec3D::new(0, 0, -1), 1e-12));
+        assert!(samples[1]
+            .acceleration()
+            .approx_eq(&Vec3D::new(0.0, 0.0, -0.4), 1e-12));
+    }
+
+    #[test]
+    fn test_drain() {
+        let mut bus = FakeSensor::default();
+        let mut sensor = GY521::default();
+        let mut samples = Vec::new();
+        assert!(sensor.drain_fifo(&bus, &mut samples).is_err()); // Not enabled yet
+
+        sensor.initialize(&mut bus).unwrap();
+        bus.fifo.borrow_mut().extend([1, 2, 3]); // Left over from before enabling it
+        sensor.enable_fifo(FifoLayout::ALL, &mut bus).unwrap();
+        assert_eq!(bus.registers.borrow()[&FIFO_EN], 0b1111_1000);
+        assert_eq!(bus.registers.borrow()[&USER_CTRL], FIFO_ENABLE);
+        assert!(bus.fifo.borrow().is_empty());
+
+        let frame = frame(&[16_384, 0, 0, -340, 0, 131, 0]);
+        bus.fifo.borrow_mut().extend(&frame);
+        bus.fifo.borrow_mut().extend(&frame[..5]);
+        assert_eq!(sensor.drain_fifo(&bus, &mut samples).unwrap(), 1);
+        bus.fifo.borrow_mut().extend(&frame[5..]); // The rest of the second frame
+        assert_eq!(sensor.drain_fifo(&bus, &mut samples).unwrap(), 1);
+        assert_eq!(samples.len(), 2);
+        assert!(samples[1]
+            .acceleration()
+            .approx_eq(&Vec3D::new(1, 0, 0), 1e-12));
+        assert!(samples[1]
+            .angular_velocity()
+            .approx_eq(&Vec3D::new(0, 1, 0), 1e-12));
+
+        // Overflown
+        bus.fifo.borrow_mut().extend(vec![0; 1024]);
+        assert!(sensor.drain_fifo(&bus, &mut samples).is_err());
+        assert!(bus.fifo.borrow().is_empty());
+        assert_eq!(samples.len(), 2);
+
+        sensor.disable_fifo(&mut bus).unwrap();
+        assert_eq!(bus.registers.borrow()[&FIFO_EN], 0);
+        assert!(sensor.drain_fifo(&bus, &mut samples).is_err());
+    }
+
+    #[test]
+    fn test_fifo_source() {
+        let mut bus = FakeSensor::default();
+        let mut sensor = GY521::default();
+        sensor.initialize(&mut bus).unwrap();
+        sensor.enable_fifo(FifoLayout::ALL, &mut bus).unwrap();
+        let period = Duration::from_secs_f64(1.0 / sensor.sample_rate);
+        for gyroscope_x in 0..3 {
+            bus.fifo
+                .borrow_mut()
+                .extend(frame(&[0, 0, 0, 0, gyroscope_x * 131, 0, 0]));
+        }
+
+        let mut source = FifoSource::new(&mut sensor, &mut bus, Duration::ZERO);
+        let instants = (0..3)
+            .map(|gyroscope_x| {
+                let (sample, instant) = source.next_sample(None);
+                assert_eq!(
+                    sample.unwrap().unwrap().angular_velocity().x,
+                    gyroscope_x as f64
+                );
+                instant
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(instants[1] - instants[0], period);
+        assert_eq!(instants[2] - instants[1], period);
+        assert!(source.next_sample(None).0.unwrap().is_none()); // Drained
+    }
+
+    #[test]
+    fn test_partial_layout() {
+        let scaling = scaling(1.0, Vec3D::default());
+        let layout = FifoLayout {
+            accelerometer: false,
+            thermometer: false,
+            gyroscope: true,
+        };
+        assert_eq!(layout.frame_size(), 6);
+        let mut converter = BatchConverter::new(layout, &scaling, &scaling, 340.0, 36.53);
+
+        let mut samples = Vec::new();
+        converter.convert(&frame(&[1, 2, 3, 4, 5, 6]), &mut samples);
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[1].acceleration(), Vec3D::default());
+        assert_eq!(samples[1].temperature(), None);
+        assert_eq!(samples[1].angular_velocity(), Vec3D::new(4, 10, 3));
+    }
+}
@@ -0,0 +1,82 @@
+use crate::math::{filter::HampelFilter, Vec3D};
+
+/// What happens to a sample that was found to be an outlier. It is flagged either way (see `SensorSample::is_outlier`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutlierAction {
+    #[default]
+    Flag, // Keep the values as read
+    Replace, // Replace the values with the median of the recent samples
+}
+
+/// Catches impossible readings, e.g., from I2C glitches, before they reach downstream filters.
+/// Accelerometer and gyroscope are checked separately with Hampel filters. A sample is an outlier if either of them is.
+pub struct OutlierRejection {
+    pub acceleration: HampelFilter,     // [g]
+    pub angular_velocity: HampelFilter, // [degree/s]
+    pub action: OutlierAction,
+    pub outliers: usize, // Number of outliers found so far
+}
+
+impl OutlierRejection {
+    pub const THRESHOLD: f64 = 5.0; // [standard deviations] Generous, since genuine motion can be abrupt
+
+    pub fn new(window_size: usize) -> Self {
+        Self {
+            acceleration: HampelFilter::new(window_size, Self::THRESHOLD, 0.05),
+            angular_velocity: HampelFilter::new(window_size, Self::THRESHOLD, 5.0),
+            action: Default::default(),
+            outliers: 0,
+        }
+    }
+
+    /// Checks a sample, replacing its values if so configured. Returns true for an outlier.
+    pub fn check(&mut self, acceleration: &mut Vec3D, angular_velocity: &mut Vec3D) -> bool {
+        // Both filters need to see every sample, so no short circuiting
+        let acceleration_replacement = self.acceleration.check(*acceleration);
+        let angular_velocity_replacement = self.angular_velocity.check(*angular_velocity);
+        if acceleration_replacement.is_none() && angular_velocity_replacement.is_none() {
+            return false;
+        }
+
+        self.outliers += 1;
+        if self.action == OutlierAction::Replace {
+            *acceleration = acceleration_replacement.unwrap_or(*acceleration);
+            *angular_velocity = angular_velocity_replacement.unwrap_or(*angular_velocity);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let mut rejection = OutlierRejection::new(5);
+        let steady = |rejection: &mut OutlierRejection, n: i32| {
+            let noise = 0.01 * (n % 2) as f64;
+            let (mut acceleration, mut angular_velocity) =
+                (Vec3D::new(0, noise, 1), Vec3D::new(noise, 0, 0));
+            rejection.check(&mut acceleration, &mut angular_velocity)
+        };
+        assert!(!(0..10).any(|n| steady(&mut rejection, n)));
+
+        // A glitch of the accelerometer is flagged, but kept as read
+        let (mut acceleration, mut angular_velocity) = (Vec3D::new(0, 0, 8), Vec3D::default());
+        assert!(rejection.check(&mut acceleration, &mut angular_velocity));
+        assert_eq!(acceleration, Vec3D::new(0, 0, 8));
+        assert_eq!(rejection.outliers, 1);
+
+        // With replacing, a glitch of the gyroscope is replaced by the median, and the accelerometer is left alone
+        rejection.action = OutlierAction::Replace;
+        assert!(!steady(&mut rejection, 0));
+        let (mut acceleration, mut angular_velocity) =
+            (Vec3D::new(0, 0, 1), Vec3D::new(0, 0, -500));
+        assert!(rejection.check(&mut acceleration, &mut angular_velocity));
+        assert!(angular_velocity.near_zero());
+        assert_eq!(acceleration, Vec3D::new(0, 0, 1));
+        assert_eq!(rejection.outliers, 2);
+        assert!(!(0..10).any(|n| steady(&mut rejection, n)));
+    }
+}
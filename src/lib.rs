@@ -1,5 +1,15 @@
 #![feature(bool_to_option)]
 #![feature(stmt_expr_attributes)]
+pub mod actuators;
+pub mod clock;
+pub mod display;
+pub mod fusion;
+pub mod gps;
 pub mod gy521;
+pub mod logging;
 pub mod math;
+pub mod power;
+pub mod sensors;
+pub mod telemetry;
+pub mod units;
 pub mod utilites;
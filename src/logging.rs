@@ -0,0 +1,138 @@
+// Writing recorded samples to files, in whichever format suits the analysis afterwards.
+// Sinks take samples in chunks, e.g., from `streaming::StreamingWriter`, so a recording doesn't have to be held in RAM until the end.
+
+pub mod binary;
+pub mod black_box;
+pub mod compression;
+pub mod convert;
+pub mod csv;
+pub mod hub;
+#[cfg(feature = "influxdb")]
+pub mod influxdb;
+pub mod markers;
+#[cfg(feature = "msgpack")]
+pub mod msgpack;
+pub mod ndjson;
+#[cfg(feature = "parquet-export")]
+pub mod parquet;
+pub mod raw;
+pub mod replay;
+pub mod rotation;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod streaming;
+pub mod yaml;
+
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::Result;
+
+use crate::{
+    gy521::{calibration::CalibrationData, Filter, SensorSample, GY521},
+    math::Vec3D,
+};
+
+/// Maps the monotonic instants that samples are taken at to time since the start of a recording, and to wall-clock time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timebase {
+    pub start: Instant,
+    pub start_time: SystemTime, // Wall-clock time at {start}
+}
+
+impl Timebase {
+    /// For a recording that starts now
+    pub fn now() -> Self {
+        Self {
+            start: Instant::now(),
+            start_time: SystemTime::now(),
+        }
+    }
+
+    /// Time from the start to {instant}, or zero for instants before the start
+    pub fn elapsed(&self, instant: Instant) -> Duration {
+        instant.saturating_duration_since(self.start)
+    }
+
+    pub fn system_time(&self, instant: Instant) -> SystemTime {
+        self.start_time + self.elapsed(instant)
+    }
+}
+
+/// Everything needed to interpret a recording without knowing how the sensor was set up at the time, written at the start of every recording
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Metadata {
+    pub start_time: SystemTime, // Wall-clock time that sample times count from
+    pub sample_rate: f64,       // [Hz]
+    pub accelerometer_range: isize, // [g] Full scale, e.g., 2 for +-2 g
+    pub gyroscope_range: isize, // [degree/s] Full scale
+    pub filter: Filter,
+    pub calibration: CalibrationData,
+    // Recordings from before these were added lack them
+    #[serde(default)]
+    pub crate_version: String, // Of the Njord that made the recording
+    #[serde(default)]
+    pub device_model: Option<String>, // E.g., "Raspberry Pi 3 Model B". None if it couldn't be determined
+}
+
+impl Metadata {
+    /// Describes recordings of {sensor}, as it is set up now, that start at {timebase}
+    pub fn new(sensor: &GY521, timebase: &Timebase) -> Self {
+        Self {
+            start_time: timebase.start_time,
+            sample_rate: sensor.sample_rate,
+            accelerometer_range: *sensor.accelerometer_configuration.range().end(),
+            gyroscope_range: *sensor.gyroscope_configuration.range().end(),
+            filter: sensor.configuration.filter,
+            calibration: sensor.calibration(None),
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            device_model: rppal::system::DeviceInfo::new()
+                .ok()
+                .map(|info| info.model().to_string()),
+        }
+    }
+
+    /// Metadata of a 1 kHz recording that starts at {timebase}, without a sensor, for tests. Tests set whatever they depend on themselves
+    #[cfg(test)]
+    pub(crate) fn for_tests(timebase: &Timebase) -> Self {
+        Self {
+            start_time: timebase.start_time,
+            sample_rate: 1e3,
+            accelerometer_range: 2,
+            gyroscope_range: 250,
+            filter: Filter::BwAc184HzBwGy188Hz,
+            calibration: CalibrationData::default(),
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            device_model: None,
+        }
+    }
+}
+
+/// Destination for recorded samples, like a CSV, YAML, NDJSON, or binary file.
+/// Samples are calibrated ones by default. Sinks for other kinds of samples, like `raw::RawCsvSink` for raw readings, name them as {T}
+pub trait Sink<T = SensorSample<Vec3D, f64>> {
+    /// Appends {samples}, given with the instants they were taken at, oldest to newest
+    fn write(&mut self, samples: &[(T, Instant)]) -> Result<()>;
+
+    /// Pushes everything written so far on to the underlying writer
+    fn flush(&mut self) -> Result<()>;
+
+    /// Makes sure that everything written so far is on disk, for sinks that manage their files themselves, like `rotation::RotatingSink`.
+    /// Other sinks can't tell which file they write to, so this only flushes them
+    fn sync(&mut self) -> Result<()> {
+        self.flush()
+    }
+}
+
+impl<T, S: Sink<T> + ?Sized> Sink<T> for Box<S> {
+    fn write(&mut self, samples: &[(T, Instant)]) -> Result<()> {
+        (**self).write(samples)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        (**self).sync()
+    }
+}
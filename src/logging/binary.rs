@@ -0,0 +1,475 @@
+use std::{
+    io::{Read, Write},
+    time::{Duration, Instant},
+};
+
+use anyhow::{ensure, Context, Result};
+
+use super::{Metadata, Sink, Timebase};
+use crate::{gy521::SensorSample, math::Vec3D};
+
+// File layout, little-endian:
+// Magic number (8), version (4), header length (4), header as YAML, i.e., the `Metadata`, CRC-32 of the header (4).
+// Then blocks of up to BLOCK_FRAMES frames: BLOCK_MARKER (4), frame count (4), CRC-32 of the frames (4), frames.
+// The marker lets readers find the next block after a damaged one. Version 1 lacks it, so reading those stops at the first damage.
+// Frame: time since the start [ns] (8), acceleration [g] (3 * 4), angular velocity [degree/s] (3 * 4), temperature [degree C] (4, NaN if the thermometer is disabled).
+// Values are stored as f32, which resolves far finer than the sensor's 16 bit readings, at a fraction of the size of YAML.
+
+const MAGIC: &[u8; 8] = b"NJORDLOG";
+const VERSION: u32 = 2;
+const BLOCK_MARKER: &[u8; 4] = b"\xf0NJB";
+pub(crate) const FRAME_SIZE: usize = 36; // [bytes]
+const BLOCK_FRAMES: usize = 256; // Frames per block at most
+const MAX_HEADER_SIZE: usize = 1 << 20; // [bytes] Far beyond any metadata, so that a damaged header length doesn't allocate gigabytes
+
+// CRC-32 (IEEE), as used by zip and PNG
+fn crc32(bytes: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut index = 0;
+        while index < 256 {
+            let mut value = index as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                value = if value & 1 == 1 {
+                    (value >> 1) ^ 0xedb8_8320
+                } else {
+                    value >> 1
+                };
+                bit += 1;
+            }
+            table[index] = value;
+            index += 1;
+        }
+        table
+    };
+
+    !bytes.iter().fold(!0, |crc, byte| {
+        TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Appends the FRAME_SIZE bytes of one frame to {bytes}, for {sample} taken {time} after the start
+pub(crate) fn encode_frame(bytes: &mut Vec<u8>, sample: &SensorSample<Vec3D, f64>, time: Duration) {
+    bytes.extend_from_slice(&(time.as_nanos() as u64).to_le_bytes());
+    let (acceleration, angular_velocity) = (sample.acceleration(), sample.angular_velocity());
+    for value in [
+        acceleration.x,
+        acceleration.y,
+        acceleration.z,
+        angular_velocity.x,
+        angular_velocity.y,
+        angular_velocity.z,
+        sample.temperature().unwrap_or(f64::NAN),
+    ] {
+        bytes.extend_from_slice(&(value as f32).to_le_bytes());
+    }
+}
+
+/// Sample and time since the start from the first FRAME_SIZE bytes of {frame}
+pub(crate) fn decode_frame(frame: &[u8]) -> (SensorSample<Vec3D, f64>, Duration) {
+    let time = u64::from_le_bytes(frame[..8].try_into().unwrap());
+    let value = |index: usize| {
+        let start = 8 + 4 * index;
+        f32::from_le_bytes(frame[start..start + 4].try_into().unwrap()) as f64
+    };
+    let temperature = value(6);
+    (
+        SensorSample::new(
+            Vec3D::new(value(0), value(1), value(2)),
+            Vec3D::new(value(3), value(4), value(5)),
+            (!temperature.is_nan()).then_some(temperature),
+        ),
+        Duration::from_nanos(time),
+    )
+}
+
+/// Writes samples in the compact binary format, in blocks with a checksum each, after a header describing the sensor
+pub struct BinaryWriter<W: Write> {
+    writer: W,
+    timebase: Timebase,
+    block: Vec<u8>, // Reused for encoding, to not allocate for every block
+}
+
+impl<W: Write> BinaryWriter<W> {
+    /// Writes {metadata} as header right away. Frame times count from the start of {timebase}, which should match the start time in {metadata}
+    pub fn new(mut writer: W, metadata: &Metadata, timebase: Timebase) -> Result<Self> {
+        let yaml = serde_yaml::to_string(metadata)?;
+        writer
+            .write_all(MAGIC)
+            .and_then(|_| writer.write_all(&VERSION.to_le_bytes()))
+            .and_then(|_| writer.write_all(&(yaml.len() as u32).to_le_bytes()))
+            .and_then(|_| writer.write_all(yaml.as_bytes()))
+            .and_then(|_| writer.write_all(&crc32(yaml.as_bytes()).to_le_bytes()))
+            .context("Unable to write binary log header.")?;
+        Ok(Self {
+            writer,
+            timebase,
+            block: Vec::with_capacity(BLOCK_FRAMES * FRAME_SIZE),
+        })
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Sink for BinaryWriter<W> {
+    fn write(&mut self, samples: &[(SensorSample<Vec3D, f64>, Instant)]) -> Result<()> {
+        for block in samples.chunks(BLOCK_FRAMES) {
+            self.block.clear();
+            for (sample, instant) in block {
+                encode_frame(&mut self.block, sample, self.timebase.elapsed(*instant));
+            }
+            self.writer
+                .write_all(BLOCK_MARKER)
+                .and_then(|_| self.writer.write_all(&(block.len() as u32).to_le_bytes()))
+                .and_then(|_| self.writer.write_all(&crc32(&self.block).to_le_bytes()))
+                .and_then(|_| self.writer.write_all(&self.block))
+                .context("Unable to write binary log block.")?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush().context("Unable to flush binary log.")
+    }
+}
+
+/// Stretch of a binary log that couldn't be read, e.g., because of a bit flipped on an SD card, or a block cut short by power loss
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptedRegion {
+    pub start: u64, // [bytes] From the start of the file
+    pub end: u64,   // [bytes] Exclusive. Reading carries on from the block starting here, if any
+    pub reason: String,
+}
+
+impl std::fmt::Display for CorruptedRegion {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            formatter,
+            "Binary log is damaged from byte {} to {}: {}",
+            self.start, self.end, self.reason
+        )
+    }
+}
+
+impl std::error::Error for CorruptedRegion {}
+
+/// What `verify` found
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Verification {
+    pub blocks: usize, // Intact ones
+    pub frames: usize, // In intact blocks
+    pub corrupted: Vec<CorruptedRegion>,
+}
+
+impl Verification {
+    pub fn is_intact(&self) -> bool {
+        self.corrupted.is_empty()
+    }
+}
+
+/// Checks the checksums of a whole binary log, and reports the damaged regions instead of stopping at the first one. Fails only if the header is damaged, or reading fails
+pub fn verify<R: Read>(reader: R) -> Result<Verification> {
+    let mut reader = BinaryReader::new(reader)?;
+    let mut verification = Verification::default();
+    while let Some(block) = reader.next_block()? {
+        match block {
+            Ok(()) => {
+                verification.blocks += 1;
+                verification.frames += reader.block.len() / FRAME_SIZE;
+            }
+            Err(region) => verification.corrupted.push(region),
+        }
+    }
+    Ok(verification)
+}
+
+/// Reads a binary log back, as (sample, time since the start) pairs.
+/// A damaged region comes as a `CorruptedRegion` error, after which reading carries on with the next intact block. Logs of version 1 can't be read past damage, so they end there
+pub struct BinaryReader<R: Read> {
+    reader: R,
+    metadata: Metadata,
+    version: u32,
+    buffer: Vec<u8>, // Read from {reader}, but not taken apart yet
+    offset: u64,     // Of the start of {buffer} in the file [bytes]
+    block: Vec<u8>,
+    position: usize, // Of the next frame in block [bytes]
+    failed: bool,
+}
+
+impl<R: Read> BinaryReader<R> {
+    /// Reads and checks the header
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut prefix = [0; 16];
+        reader
+            .read_exact(&mut prefix)
+            .context("Binary log is too short.")?;
+        ensure!(&prefix[..8] == MAGIC, "Not a binary log.");
+        let version = u32::from_le_bytes(prefix[8..12].try_into()?);
+        ensure!(
+            (1..=VERSION).contains(&version),
+            "Binary log has version {version}, but only versions up to {VERSION} are supported."
+        );
+
+        let header_size = u32::from_le_bytes(prefix[12..16].try_into()?) as usize;
+        ensure!(
+            header_size <= MAX_HEADER_SIZE,
+            "Binary log header is damaged, it claims to be {header_size} bytes long."
+        );
+        let mut yaml = vec![0; header_size];
+        let mut checksum = [0; 4];
+        reader
+            .read_exact(&mut yaml)
+            .and_then(|_| reader.read_exact(&mut checksum))
+            .context("Binary log header is incomplete.")?;
+        ensure!(
+            u32::from_le_bytes(checksum) == crc32(&yaml),
+            "Binary log header is damaged."
+        );
+        let metadata =
+            serde_yaml::from_slice(&yaml).context("Unable to parse binary log header.")?;
+
+        Ok(Self {
+            reader,
+            metadata,
+            version,
+            buffer: Vec::new(),
+            offset: (prefix.len() + yaml.len() + checksum.len()) as u64,
+            block: Vec::new(),
+            position: 0,
+            failed: false,
+        })
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    // Reads until {buffer} holds {length} bytes. False if the log ends before that
+    fn fill(&mut self, length: usize) -> Result<bool> {
+        let mut chunk = [0; 4096];
+        while self.buffer.len() < length {
+            match self.reader.read(&mut chunk) {
+                Ok(0) => return Ok(false),
+                Ok(count) => self.buffer.extend_from_slice(&chunk[..count]),
+                Err(error) if error.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(error) => return Err(error).context("Unable to read binary log."),
+            }
+        }
+        Ok(true)
+    }
+
+    fn consume(&mut self, length: usize) {
+        self.buffer.drain(..length);
+        self.offset += length as u64;
+    }
+
+    // The next block, decoded into {block}, or the damaged region in front of the next intact one. None at the end of the log
+    fn next_block(&mut self) -> Result<Option<std::result::Result<(), CorruptedRegion>>> {
+        if !self.fill(1)? {
+            return Ok(None);
+        }
+        let start = self.offset;
+        let reason = match self.check_block()? {
+            Ok(frames) => {
+                self.block.clear();
+                self.block.extend_from_slice(&self.buffer[frames.clone()]);
+                self.consume(frames.end);
+                self.position = 0;
+                return Ok(Some(Ok(())));
+            }
+            Err(reason) => reason,
+        };
+        self.block.clear();
+        self.position = 0;
+        self.skip_damage()?;
+        Ok(Some(Err(CorruptedRegion {
+            start,
+            end: self.offset,
+            reason,
+        })))
+    }
+
+    // Where the frames of the intact block at the start of {buffer} are, or why it is damaged
+    fn check_block(&mut self) -> Result<std::result::Result<std::ops::Range<usize>, String>> {
+        let prefix = if self.version == 1 { 8 } else { 12 };
+        if !self.fill(prefix)? {
+            return Ok(Err("Log ends in the middle of a block.".to_string()));
+        }
+        if self.version > 1 && &self.buffer[..4] != BLOCK_MARKER {
+            return Ok(Err("Block start is missing.".to_string()));
+        }
+        let fields = &self.buffer[prefix - 8..prefix];
+        let frame_count = u32::from_le_bytes(fields[..4].try_into()?) as usize;
+        let checksum = u32::from_le_bytes(fields[4..].try_into()?);
+        if frame_count > BLOCK_FRAMES {
+            return Ok(Err(format!(
+                "Block claims {frame_count} frames, but blocks hold at most {BLOCK_FRAMES}."
+            )));
+        }
+        let length = prefix + frame_count * FRAME_SIZE;
+        if !self.fill(length)? {
+            return Ok(Err("Log ends in the middle of a block.".to_string()));
+        }
+        if checksum != crc32(&self.buffer[prefix..length]) {
+            return Ok(Err("Checksum doesn't match.".to_string()));
+        }
+        Ok(Ok(prefix..length))
+    }
+
+    // Skips to the next block marker after the damaged block at the start of {buffer}, or to the end of the log
+    fn skip_damage(&mut self) -> Result<()> {
+        // Version 1 has no markers to find the next block by
+        if self.version == 1 {
+            loop {
+                self.consume(self.buffer.len());
+                if !self.fill(1)? {
+                    return Ok(());
+                }
+            }
+        }
+        self.consume(1); // The damaged block may start with a marker itself
+        loop {
+            if let Some(index) = self
+                .buffer
+                .windows(BLOCK_MARKER.len())
+                .position(|bytes| bytes == BLOCK_MARKER)
+            {
+                self.consume(index);
+                return Ok(());
+            }
+            // The last few bytes might be the start of a marker
+            let keep = self.buffer.len().min(BLOCK_MARKER.len() - 1);
+            self.consume(self.buffer.len() - keep);
+            if !self.fill(keep + 1)? {
+                self.consume(self.buffer.len());
+                return Ok(());
+            }
+        }
+    }
+
+    fn frame(&mut self) -> (SensorSample<Vec3D, f64>, Duration) {
+        let frame = decode_frame(&self.block[self.position..self.position + FRAME_SIZE]);
+        self.position += FRAME_SIZE;
+        frame
+    }
+}
+
+impl<R: Read> Iterator for BinaryReader<R> {
+    type Item = Result<(SensorSample<Vec3D, f64>, Duration)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.failed && self.position >= self.block.len() {
+            match self.next_block() {
+                Ok(Some(Ok(()))) => {}
+                Ok(Some(Err(region))) => return Some(Err(region.into())),
+                Ok(None) => return None,
+                Err(error) => {
+                    self.failed = true;
+                    return Some(Err(error));
+                }
+            }
+        }
+        (!self.failed).then(|| Ok(self.frame()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let timebase = Timebase::now();
+        let samples = (0..300)
+            .map(|n| {
+                let temperature = (n % 2 == 0).then_some(25.5);
+                (
+                    SensorSample::new(Vec3D::new(n, 0, 1), Vec3D::new(0.25, -n, 0), temperature),
+                    timebase.start + Duration::from_millis(n as u64),
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut writer =
+            BinaryWriter::new(Vec::new(), &Metadata::for_tests(&timebase), timebase).unwrap();
+        writer.write(&samples[..100]).unwrap();
+        writer.write(&samples[100..]).unwrap(); // Two blocks
+        let bytes = writer.into_inner();
+
+        let reader = BinaryReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.metadata(), &Metadata::for_tests(&timebase));
+        let read = reader.collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(read.len(), samples.len());
+        for ((sample, time), (original, instant)) in read.iter().zip(&samples) {
+            assert_eq!(*time, timebase.elapsed(*instant));
+            assert!(sample
+                .acceleration()
+                .approx_eq(&original.acceleration(), 1e-5));
+            assert!(sample
+                .angular_velocity()
+                .approx_eq(&original.angular_velocity(), 1e-4));
+            assert_eq!(sample.temperature(), original.temperature());
+        }
+    }
+
+    #[test]
+    fn test_damage() {
+        let timebase = Timebase::now();
+        let sample = SensorSample::new(Vec3D::new(0, 0, 1), Vec3D::default(), None);
+        let mut writer =
+            BinaryWriter::new(Vec::new(), &Metadata::for_tests(&timebase), timebase).unwrap();
+        writer.write(&[(sample, timebase.start); 3]).unwrap();
+        let first_end = writer.get_ref().len();
+        writer.write(&[(sample, timebase.start); 2]).unwrap();
+        let second_end = writer.get_ref().len();
+        writer.write(&[(sample, timebase.start); 4]).unwrap();
+        let mut bytes = writer.into_inner();
+        let intact = verify(bytes.as_slice()).unwrap();
+        assert!(intact.is_intact());
+        assert_eq!((intact.blocks, intact.frames), (3, 9));
+
+        // A flipped bit in the second block only loses that block
+        bytes[second_end - FRAME_SIZE] ^= 0x01;
+        let read = BinaryReader::new(bytes.as_slice())
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(read.len(), 3 + 1 + 4);
+        assert!(read
+            .iter()
+            .enumerate()
+            .all(|(index, result)| result.is_err() == (index == 3)));
+        let region = read[3]
+            .as_ref()
+            .unwrap_err()
+            .downcast_ref::<CorruptedRegion>()
+            .unwrap();
+        assert_eq!(
+            (region.start, region.end),
+            (first_end as u64, second_end as u64)
+        );
+
+        // Then a block cut short by power loss
+        let end = bytes.len();
+        bytes.truncate(end - 1);
+        let damaged = verify(bytes.as_slice()).unwrap();
+        assert_eq!((damaged.blocks, damaged.frames), (1, 3));
+        assert_eq!(damaged.corrupted.len(), 2);
+        assert_eq!(
+            (damaged.corrupted[1].start, damaged.corrupted[1].end),
+            (second_end as u64, end as u64 - 1)
+        );
+
+        let mut header_size = bytes.clone();
+        header_size[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(BinaryReader::new(header_size.as_slice()).is_err());
+        bytes[0] = b'X';
+        assert!(BinaryReader::new(bytes.as_slice()).is_err());
+        assert!(verify(bytes.as_slice()).is_err());
+    }
+}
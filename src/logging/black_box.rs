@@ -0,0 +1,188 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, TryLockError},
+    time::{Instant, SystemTime},
+};
+
+use anyhow::{Context, Result};
+
+use super::{rotation, Metadata, Timebase};
+use crate::{gy521::SensorSample, math::Vec3D, utilites::Memory};
+
+/// What a black box file holds: the latest samples and errors before the recording ended unexpectedly, and how the sensor was set up
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BlackBox {
+    pub reason: String,   // E.g., the panic message and where it happened
+    pub time: SystemTime, // Of the dump
+    pub metadata: Metadata,
+    pub samples: Memory<(SensorSample<Vec3D, f64>, SystemTime)>, // Oldest to newest, like in `yaml::YamlSink`
+    pub errors: Memory<(String, SystemTime)>,
+}
+
+impl BlackBox {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Unable to open black box {}.", path.display()))?;
+        serde_yaml::from_reader(file)
+            .with_context(|| format!("Unable to read black box {}.", path.display()))
+    }
+}
+
+/// Keeps the latest samples and errors of a recording in memory, to dump them to a "black box" file when the program panics, so the samples that led up to a crash aren't lost with it.
+/// Clones share the same record, so one can stay with the panic hook while another records
+#[derive(Clone)]
+pub struct FlightRecorder {
+    record: Arc<Mutex<BlackBox>>, // Reason and time are filled in when dumping
+    timebase: Timebase,
+}
+
+impl FlightRecorder {
+    /// Keeps the latest {capacity} samples and errors of a recording that starts at {timebase}
+    pub fn new(capacity: usize, metadata: Metadata, timebase: Timebase) -> Self {
+        let record = BlackBox {
+            reason: String::new(),
+            time: timebase.start_time,
+            metadata,
+            samples: Memory::new(capacity),
+            errors: Memory::new(capacity),
+        };
+        Self {
+            record: Arc::new(Mutex::new(record)),
+            timebase,
+        }
+    }
+
+    pub fn record(&self, sample: SensorSample<Vec3D, f64>, instant: Instant) {
+        let time = self.timebase.system_time(instant);
+        self.lock().samples.push((sample, time));
+    }
+
+    pub fn record_error(&self, error: &impl Display, instant: Instant) {
+        let time = self.timebase.system_time(instant);
+        self.lock().errors.push((format!("{error:#}"), time));
+    }
+
+    /// Replaces the sensor setup to dump, e.g., after recalibrating
+    pub fn set_metadata(&self, metadata: Metadata) {
+        self.lock().metadata = metadata;
+    }
+
+    /// What would be dumped now
+    pub fn black_box(&self, reason: &str) -> BlackBox {
+        black_box(&self.lock(), reason)
+    }
+
+    /// Writes the record to "{directory}/Black box {timestamp}.yaml", and returns the path
+    pub fn dump(&self, directory: impl AsRef<Path>, reason: &str) -> Result<PathBuf> {
+        write(directory.as_ref(), &self.black_box(reason))
+    }
+
+    /// Dumps the record to {directory} whenever a thread panics, before the previous panic hook runs, e.g., the one that prints the panic message.
+    /// Ctrl-C isn't a crash, so it doesn't dump anything. Install this only once, since every call adds another dump
+    pub fn install(&self, directory: impl Into<PathBuf>) {
+        let record = self.record.clone();
+        let directory = directory.into();
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            // A panic while the record is locked, e.g., in `record` itself, would deadlock on it
+            let black_box = match record.try_lock() {
+                Ok(record) => Some(black_box(&record, &info.to_string())),
+                Err(TryLockError::Poisoned(poisoned)) => {
+                    Some(black_box(&poisoned.into_inner(), &info.to_string()))
+                }
+                Err(TryLockError::WouldBlock) => None,
+            };
+            match black_box.map(|black_box| write(&directory, &black_box)) {
+                Some(Ok(path)) => eprintln!("Black box written to {}", path.display()),
+                Some(Err(error)) => eprintln!("Unable to write black box: {error:#}"),
+                None => eprintln!("Unable to write black box, since it was being recorded to."),
+            }
+            previous(info);
+        }));
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BlackBox> {
+        // Poisoning only means that some thread panicked while recording, and the record is still worth keeping
+        self.record
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn black_box(record: &BlackBox, reason: &str) -> BlackBox {
+    BlackBox {
+        reason: reason.to_string(),
+        time: SystemTime::now(),
+        ..record.clone()
+    }
+}
+
+// Never overwrites earlier black boxes, even from the same second
+fn write(directory: &Path, black_box: &BlackBox) -> Result<PathBuf> {
+    std::fs::create_dir_all(directory)
+        .with_context(|| format!("Unable to create {}.", directory.display()))?;
+    let timestamp = rotation::timestamp(black_box.time);
+    let mut counter = 0;
+    loop {
+        let name = match counter {
+            0 => format!("Black box {timestamp}.yaml"),
+            _ => format!("Black box {timestamp} {counter}.yaml"),
+        };
+        let path = directory.join(name);
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => {
+                serde_yaml::to_writer(std::io::BufWriter::new(&file), black_box)
+                    .with_context(|| format!("Unable to write black box {}.", path.display()))?;
+                file.sync_all()
+                    .with_context(|| format!("Unable to sync black box {}.", path.display()))?;
+                return Ok(path);
+            }
+            Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => counter += 1,
+            Err(error) => {
+                return Err(error)
+                    .with_context(|| format!("Unable to create black box {}.", path.display()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dump() {
+        let timebase = Timebase::now();
+        let metadata = Metadata {
+            sample_rate: 100.0,
+            ..Metadata::for_tests(&timebase)
+        };
+        let recorder = FlightRecorder::new(3, metadata.clone(), timebase);
+        for n in 0..5 {
+            let sample = SensorSample::new(Vec3D::new(0, 0, n), Vec3D::default(), None);
+            recorder.record(sample, timebase.start);
+        }
+        recorder.record_error(&anyhow::anyhow!("I2C timeout"), timebase.start);
+
+        let directory =
+            std::env::temp_dir().join(format!("njord_black_box_{}", std::process::id()));
+        let first = recorder.dump(&directory, "Test").unwrap();
+        let second = recorder.dump(&directory, "Test").unwrap();
+        assert_ne!(first, second);
+
+        let black_box = BlackBox::load(&first).unwrap();
+        assert_eq!(black_box.reason, "Test");
+        assert_eq!(black_box.metadata, metadata);
+        assert_eq!(black_box.samples.count(), 5);
+        assert_eq!(black_box.samples.len(), 3); // Only the latest
+        assert_eq!(black_box.samples.last().unwrap().0.acceleration().z, 4.0);
+        assert_eq!(black_box.errors[0].0, "I2C timeout");
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
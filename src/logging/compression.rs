@@ -0,0 +1,235 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(10); // Default
+
+/// How log files are compressed. IMU samples compress to a fraction of their size, which saves SD card writes as well as space
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    #[cfg(feature = "gzip")]
+    Gzip(u32), // Level from 0 (fastest) to 9 (smallest). 6 is the usual default
+    #[cfg(feature = "zstd")]
+    Zstd(i32), // Level from 1 (fastest) to 22 (smallest). 3 is the usual default, and compresses better and faster than gzip
+}
+
+impl Compression {
+    /// Extension to append to file names, e.g., "gz" for "Calibrated data.csv.gz"
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            Self::None => None,
+            #[cfg(feature = "gzip")]
+            Self::Gzip(_) => Some("gz"),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(_) => Some("zst"),
+        }
+    }
+}
+
+enum Encoder<W: Write> {
+    None(W),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Self::None(writer) => writer,
+            #[cfg(feature = "gzip")]
+            Self::Gzip(encoder) => encoder,
+            #[cfg(feature = "zstd")]
+            Self::Zstd(encoder) => encoder,
+        }
+    }
+
+    fn get_ref(&self) -> &W {
+        match self {
+            Self::None(writer) => writer,
+            #[cfg(feature = "gzip")]
+            Self::Gzip(encoder) => encoder.get_ref(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(encoder) => encoder.get_ref(),
+        }
+    }
+
+    fn finish(self) -> std::io::Result<W> {
+        match self {
+            Self::None(writer) => Ok(writer),
+            #[cfg(feature = "gzip")]
+            Self::Gzip(encoder) => encoder.finish(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/// Compresses everything written to it, for wrapping the writer of any sink, e.g., `CsvSink::new(CompressedWriter::new(file, Compression::Zstd(3))?, ...)`.
+/// Flushing a compressor ends its current block, which costs compression, and sinks flush after every few samples. So flushing only pushes the compressed data on if {flush_interval} has passed since the last time, and a crash can lose the samples of that interval.
+/// Compressed files are only complete once finished, so call `finish` at the end, which dropping does as well, ignoring errors. Samples up to the last flush can still be read from unfinished files
+pub struct CompressedWriter<W: Write> {
+    encoder: Option<Encoder<W>>, // None once finished
+    flush_interval: Duration,
+    last_flush: Instant,
+}
+
+impl<W: Write> CompressedWriter<W> {
+    pub fn new(writer: W, compression: Compression) -> Result<Self> {
+        let encoder = match compression {
+            Compression::None => Encoder::None(writer),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip(level) => Encoder::Gzip(flate2::write::GzEncoder::new(
+                writer,
+                flate2::Compression::new(level.min(9)),
+            )),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(level) => Encoder::Zstd(
+                zstd::stream::write::Encoder::new(writer, level)
+                    .context("Unable to start zstd compression.")?,
+            ),
+        };
+        Ok(Self {
+            encoder: Some(encoder),
+            flush_interval: FLUSH_INTERVAL,
+            last_flush: Instant::now(),
+        })
+    }
+
+    /// Zero flushes every time, like an uncompressed writer, at the cost of compression
+    pub fn set_flush_interval(&mut self, flush_interval: Duration) {
+        self.flush_interval = flush_interval;
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.encoder
+            .as_ref()
+            .expect("Compressed writer is only taken apart when finishing")
+            .get_ref()
+    }
+
+    /// Writes the end of the compressed data, and returns the underlying writer
+    pub fn finish(mut self) -> Result<W> {
+        let encoder = self
+            .encoder
+            .take()
+            .context("Compressed writer is finished.")?;
+        let mut writer = encoder.finish().context("Unable to finish compression.")?;
+        writer.flush()?;
+        Ok(writer)
+    }
+
+    fn encoder(&mut self) -> std::io::Result<&mut dyn Write> {
+        match &mut self.encoder {
+            Some(encoder) => Ok(encoder.writer()),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "Compressed writer is finished.",
+            )),
+        }
+    }
+}
+
+impl<W: Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.encoder()?.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let uncompressed = matches!(self.encoder, Some(Encoder::None(_)));
+        if uncompressed || self.last_flush.elapsed() >= self.flush_interval {
+            self.encoder()?.flush()?;
+            self.last_flush = Instant::now();
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for CompressedWriter<W> {
+    fn drop(&mut self) {
+        if let Some(encoder) = self.encoder.take() {
+            let _ = encoder.finish().and_then(|mut writer| writer.flush());
+        }
+    }
+}
+
+/// Decompresses {reader} if it holds gzip or zstd data, telling them apart by their magic numbers, and passes anything else through as it is.
+/// E.g., `BinaryReader::new(decompress(File::open("Calibrated data.njord.zst")?)?)`
+pub fn decompress<R: Read + 'static>(reader: R) -> Result<Box<dyn Read>> {
+    let mut reader = BufReader::new(reader);
+    let start = reader.fill_buf().context("Unable to read log.")?;
+    if start.starts_with(&[0x1f, 0x8b]) {
+        #[cfg(feature = "gzip")]
+        return Ok(Box::new(flate2::bufread::MultiGzDecoder::new(reader)));
+        #[cfg(not(feature = "gzip"))]
+        anyhow::bail!("Log is compressed with gzip. Enable the gzip feature to read it.");
+    }
+    if start.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        #[cfg(feature = "zstd")]
+        return Ok(Box::new(
+            zstd::stream::read::Decoder::with_buffer(reader)
+                .context("Unable to start zstd decompression.")?,
+        ));
+        #[cfg(not(feature = "zstd"))]
+        anyhow::bail!("Log is compressed with zstd. Enable the zstd feature to read it.");
+    }
+    Ok(Box::new(reader))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gy521::SensorSample,
+        logging::{
+            csv::{Column, CsvSink},
+            Sink, Timebase,
+        },
+        math::Vec3D,
+    };
+
+    #[test]
+    fn test_round_trip() {
+        let timebase = Timebase::now();
+        let samples = (0..1000)
+            .map(|n| {
+                let sample = SensorSample::new(Vec3D::new(0, 0, 1), Vec3D::default(), Some(20.0));
+                (sample, timebase.start + Duration::from_millis(10 * n))
+            })
+            .collect::<Vec<_>>();
+        let compressions = [
+            Compression::None,
+            #[cfg(feature = "gzip")]
+            Compression::Gzip(6),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(3),
+        ];
+
+        let mut sizes = Vec::new();
+        for compression in compressions {
+            let writer = CompressedWriter::new(Vec::new(), compression).unwrap();
+            let mut sink = CsvSink::new(writer, &Column::ALL, timebase).unwrap();
+            for chunk in samples.chunks(10) {
+                sink.write(chunk).unwrap();
+                sink.flush().unwrap();
+            }
+            let bytes = sink.into_inner().finish().unwrap();
+            sizes.push(bytes.len());
+
+            let mut text = String::new();
+            decompress(std::io::Cursor::new(bytes))
+                .unwrap()
+                .read_to_string(&mut text)
+                .unwrap();
+            assert_eq!(text.lines().count(), 1 + samples.len());
+        }
+        // Repetitive samples shrink to a small fraction
+        assert!(sizes[1..].iter().all(|size| size * 5 < sizes[0]));
+    }
+}
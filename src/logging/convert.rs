@@ -0,0 +1,354 @@
+// Converting recordings between formats, so that older recordings stay usable with tools that want another format, e.g., YAML archives for pandas as CSV.
+
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::Path,
+    time::{Instant, SystemTime},
+};
+
+use anyhow::{bail, Context, Result};
+
+use super::{
+    binary::BinaryWriter,
+    compression::{CompressedWriter, Compression},
+    csv::{Column, CsvSink},
+    ndjson::NdjsonSink,
+    replay::{self, Recording},
+    yaml::YamlSink,
+    Metadata, Sink, Timebase,
+};
+
+const CHUNK: usize = 1000; // Samples handed to the sink at a time
+
+/// What `convert` did
+#[derive(Debug, Default)]
+pub struct Conversion {
+    pub samples: usize,              // Written
+    pub skipped: Vec<anyhow::Error>, // Records that couldn't be read, e.g., damaged regions of a binary log. The rest was converted anyway
+}
+
+/// Writes the recording at {input} to {output}, each in the format its extension names, along with the metadata. See `replay::Recording::open` for the formats, all of which can be written as well.
+/// Ending {output} in ".gz" or ".zst" compresses it. Times are kept as they are. Formats that need metadata, i.e., binary, MessagePack, and SQLite, can't be written from recordings without it.
+/// {output} must not exist yet, so that nothing is overwritten by accident, and is removed again if the conversion fails
+pub fn convert<P: AsRef<Path>, Q: AsRef<Path>>(input: P, output: Q) -> Result<Conversion> {
+    let (input, output) = (input.as_ref(), output.as_ref());
+    let recording = Recording::open(input)?;
+    // Instants only need to be as far apart as the recorded times, so any instant serves as the start
+    let timebase = Timebase {
+        start: Instant::now(),
+        start_time: recording.start_time.unwrap_or(SystemTime::UNIX_EPOCH),
+    };
+    let sink = create_sink(output, &recording, timebase)?;
+    let conversion = copy(recording.records, sink, timebase);
+    if conversion.is_err() {
+        // Don't leave a partial file behind, which would look like a complete conversion
+        let _ = std::fs::remove_file(output);
+    }
+    conversion
+}
+
+// Writes {records} to {sink} in chunks, and finishes it
+fn copy(
+    records: replay::Records,
+    mut sink: Box<dyn Output>,
+    timebase: Timebase,
+) -> Result<Conversion> {
+    let mut conversion = Conversion::default();
+    let mut chunk = Vec::with_capacity(CHUNK);
+    for record in records {
+        match record {
+            Ok((sample, time)) => chunk.push((sample, timebase.start + time)),
+            Err(error) => conversion.skipped.push(error),
+        }
+        if chunk.len() >= CHUNK {
+            sink.write(&chunk)?;
+            conversion.samples += chunk.len();
+            chunk.clear();
+        }
+    }
+    sink.write(&chunk)?;
+    conversion.samples += chunk.len();
+    sink.flush()?;
+    sink.finish()?;
+    Ok(conversion)
+}
+
+// The file that sinks write to, compressed as its name says
+type Writer = CompressedWriter<BufWriter<File>>;
+
+// A sink that is finished explicitly, rather than when dropped, so that errors writing the end of the file are noticed, e.g., the Parquet footer or the end of the compressed data
+trait Output: Sink {
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+macro_rules! impl_output {
+    ($($sink:ty),*) => {
+        $(
+            impl Output for $sink {
+                fn finish(self: Box<Self>) -> Result<()> {
+                    self.into_inner().finish()?;
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+impl_output!(
+    YamlSink<Writer>,
+    CsvSink<Writer>,
+    NdjsonSink<Writer>,
+    BinaryWriter<Writer>
+);
+#[cfg(feature = "msgpack")]
+impl_output!(super::msgpack::MessagePackSink<Writer>);
+
+#[cfg(feature = "parquet-export")]
+impl Output for super::parquet::ParquetSink<Writer> {
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.close()?.finish()?;
+        Ok(())
+    }
+}
+
+// Every chunk is committed right away
+#[cfg(feature = "sqlite")]
+impl Output for super::sqlite::SqliteSink {
+    fn finish(self: Box<Self>) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn create_sink(path: &Path, recording: &Recording, timebase: Timebase) -> Result<Box<dyn Output>> {
+    let format = replay::format(path).context("Output has no extension to tell the format by.")?;
+    let metadata = recording.metadata.as_ref();
+
+    #[cfg(feature = "sqlite")]
+    if format == "sqlite" {
+        anyhow::ensure!(!path.exists(), "{} exists already.", path.display());
+        let metadata = metadata.context("Recording has no metadata, which sqlite needs.")?;
+        return Ok(Box::new(super::sqlite::SqliteSink::open(
+            path, metadata, timebase,
+        )?));
+    }
+
+    let name = path.to_string_lossy();
+    let compression = if name.ends_with(".gz") {
+        #[cfg(feature = "gzip")]
+        {
+            Compression::Gzip(6)
+        }
+        #[cfg(not(feature = "gzip"))]
+        bail!("Enable the gzip feature to write gzip files.")
+    } else if name.ends_with(".zst") {
+        #[cfg(feature = "zstd")]
+        {
+            Compression::Zstd(3)
+        }
+        #[cfg(not(feature = "zstd"))]
+        bail!("Enable the zstd feature to write zstd files.")
+    } else {
+        Compression::None
+    };
+    let file = File::options()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .with_context(|| format!("Unable to create {}.", path.display()))?;
+    let writer = CompressedWriter::new(BufWriter::new(file), compression)?;
+    let sink = format_sink(format, writer, metadata, timebase);
+    if sink.is_err() {
+        // Don't leave an empty file behind
+        let _ = std::fs::remove_file(path);
+    }
+    sink
+}
+
+fn format_sink(
+    format: &str,
+    writer: Writer,
+    metadata: Option<&Metadata>,
+    timebase: Timebase,
+) -> Result<Box<dyn Output>> {
+    let required_metadata =
+        || metadata.with_context(|| format!("Recording has no metadata, which {format} needs."));
+    Ok(match (format, metadata) {
+        ("yaml" | "yml", Some(metadata)) => {
+            Box::new(YamlSink::with_metadata(writer, metadata, timebase)?)
+        }
+        ("yaml" | "yml", None) => Box::new(YamlSink::new(writer, timebase)),
+        ("csv", Some(metadata)) => Box::new(CsvSink::with_metadata(
+            writer,
+            &Column::ALL,
+            metadata,
+            timebase,
+        )?),
+        ("csv", None) => Box::new(CsvSink::new(writer, &Column::ALL, timebase)?),
+        ("ndjson" | "jsonl", Some(metadata)) => {
+            Box::new(NdjsonSink::with_metadata(writer, metadata, timebase)?)
+        }
+        ("ndjson" | "jsonl", None) => Box::new(NdjsonSink::new(writer, timebase)),
+        ("njord", _) => Box::new(BinaryWriter::new(writer, required_metadata()?, timebase)?),
+        #[cfg(feature = "msgpack")]
+        ("msgpack", _) => Box::new(super::msgpack::MessagePackSink::new(
+            writer,
+            required_metadata()?,
+            timebase,
+        )?),
+        #[cfg(feature = "parquet-export")]
+        ("parquet", Some(metadata)) => Box::new(super::parquet::ParquetSink::with_metadata(
+            writer, metadata, timebase, 100_000,
+        )?),
+        #[cfg(feature = "parquet-export")]
+        ("parquet", None) => Box::new(super::parquet::ParquetSink::new(writer, timebase, 100_000)?),
+        _ => bail!("Unknown log format {format}."),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{gy521::SensorSample, math::Vec3D};
+
+    #[test]
+    fn test_round_trip() {
+        let directory = std::env::temp_dir().join(format!("njord_convert_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let timebase = Timebase::now();
+
+        // A YAML archive from before metadata was recorded
+        let samples = (0..1500)
+            .map(|n| {
+                let sample = SensorSample::new(Vec3D::new(0, n, 1), Vec3D::default(), Some(20.0));
+                (
+                    sample,
+                    timebase.start + std::time::Duration::from_millis(n as u64),
+                )
+            })
+            .collect::<Vec<_>>();
+        let yaml = directory.join("Archive.yaml");
+        let mut sink = YamlSink::new(File::create(&yaml).unwrap(), timebase);
+        sink.write(&samples).unwrap();
+        drop(sink);
+
+        let csv = directory.join("Archive.csv");
+        let conversion = convert(&yaml, &csv).unwrap();
+        assert_eq!(conversion.samples, samples.len());
+        assert!(conversion.skipped.is_empty());
+        assert!(convert(&yaml, &csv).is_err()); // Exists already
+        assert!(convert(&yaml, directory.join("Archive.njord")).is_err()); // Needs metadata
+        assert!(convert(&yaml, directory.join("Archive.txt")).is_err());
+        assert!(
+            !directory.join("Archive.njord").exists() && !directory.join("Archive.txt").exists()
+        );
+
+        let recording = Recording::open(&csv).unwrap();
+        let start_time = recording.start_time;
+        let records = recording.records.collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(records.len(), samples.len());
+        assert_eq!(records[1499].0.acceleration().y, 1499.0);
+        assert_eq!(records[1499].1, std::time::Duration::from_millis(1499));
+        assert_eq!(start_time, None); // No metadata, but the wall-clock times in the file are kept
+        let text = std::fs::read_to_string(&csv).unwrap();
+        let unix_time = timebase
+            .start_time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs_f64();
+        let first = text.lines().nth(1).unwrap().split(',').nth(1).unwrap();
+        assert!((first.parse::<f64>().unwrap() - unix_time).abs() < 1e-3);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[cfg(all(feature = "msgpack", feature = "sqlite", feature = "parquet-export"))]
+    #[test]
+    fn test_formats() {
+        use crate::gy521::Filter;
+
+        let directory =
+            std::env::temp_dir().join(format!("njord_convert_formats_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let timebase = Timebase::now();
+        let metadata = Metadata {
+            accelerometer_range: 4,
+            gyroscope_range: 500,
+            filter: Filter::BwAc44HzBwGy42Hz,
+            device_model: Some("Raspberry Pi 3 Model B".to_string()),
+            ..Metadata::for_tests(&timebase)
+        };
+        let samples = (0..2500)
+            .map(|n| {
+                let temperature = (n % 2 == 0).then_some(20.0);
+                let sample =
+                    SensorSample::new(Vec3D::new(0, n, 1), Vec3D::new(0, 0, -n), temperature);
+                (
+                    sample,
+                    timebase.start + std::time::Duration::from_millis(n as u64),
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut input = directory.join("Recording.yaml");
+        let mut sink =
+            YamlSink::with_metadata(File::create(&input).unwrap(), &metadata, timebase).unwrap();
+        sink.write(&samples).unwrap();
+        drop(sink);
+
+        // Each format is converted from the previous one, so that each is read as well as written
+        for extension in ["msgpack", "sqlite", "parquet", "ndjson"] {
+            let output = directory.join(format!("Recording.{extension}"));
+            let conversion = convert(&input, &output).unwrap();
+            assert_eq!(conversion.samples, samples.len());
+
+            let recording = Recording::open(&output).unwrap();
+            let mut read = recording.metadata.unwrap();
+            // SQLite stores the start time in seconds as a float
+            let difference = read
+                .start_time
+                .duration_since(metadata.start_time)
+                .unwrap_or_else(|error| error.duration());
+            assert!(difference < std::time::Duration::from_micros(1));
+            read.start_time = metadata.start_time;
+            assert_eq!(read, metadata, "{extension}");
+            let records = recording.records.collect::<Result<Vec<_>>>().unwrap();
+            assert_eq!(records.len(), samples.len());
+            let (sample, time) = &records[1999];
+            assert_eq!(sample.acceleration().y, 1999.0);
+            assert_eq!(sample.angular_velocity().z, -1999.0);
+            assert_eq!(sample.temperature(), None);
+            assert_eq!(records[1998].0.temperature(), Some(20.0));
+            assert!((time.as_secs_f64() - 1.999).abs() < 1e-6);
+            input = output;
+        }
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    // Like a full disk when writing the end of the file
+    struct Unfinished;
+
+    impl Sink for Unfinished {
+        fn write(&mut self, _samples: &[(SensorSample<Vec3D, f64>, Instant)]) -> Result<()> {
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Output for Unfinished {
+        fn finish(self: Box<Self>) -> Result<()> {
+            bail!("No space left on device.")
+        }
+    }
+
+    #[test]
+    fn test_finish_error() {
+        let records = (0..10).map(|n| {
+            let sample = SensorSample::new(Vec3D::new(0, 0, 1), Vec3D::default(), None);
+            Ok((sample, std::time::Duration::from_millis(n)))
+        });
+        let conversion = copy(Box::new(records), Box::new(Unfinished), Timebase::now());
+        assert!(conversion.is_err());
+    }
+}
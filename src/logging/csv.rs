@@ -0,0 +1,192 @@
+use std::{fmt::Write as _, io::Write, time::Instant};
+
+use anyhow::{ensure, Context, Result};
+
+use super::{Metadata, Sink, Timebase};
+use crate::{gy521::SensorSample, math::Vec3D};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    Time,          // [s] Since the start of the recording
+    UnixTime,      // [s] Wall-clock time since 1970-01-01 00:00 UTC
+    AccelerationX, // [g]
+    AccelerationY,
+    AccelerationZ,
+    AngularVelocityX, // [degree/s]
+    AngularVelocityY,
+    AngularVelocityZ,
+    Temperature, // [degree C] Empty if the thermometer is disabled
+}
+
+impl Column {
+    pub const ALL: [Column; 9] = [
+        Column::Time,
+        Column::UnixTime,
+        Column::AccelerationX,
+        Column::AccelerationY,
+        Column::AccelerationZ,
+        Column::AngularVelocityX,
+        Column::AngularVelocityY,
+        Column::AngularVelocityZ,
+        Column::Temperature,
+    ];
+
+    /// Name in the header line, with the unit as suffix, so it can be used as is in pandas or Excel
+    pub fn name(&self) -> &'static str {
+        match self {
+            Column::Time => "time_s",
+            Column::UnixTime => "unix_time_s",
+            Column::AccelerationX => "acceleration_x_g",
+            Column::AccelerationY => "acceleration_y_g",
+            Column::AccelerationZ => "acceleration_z_g",
+            Column::AngularVelocityX => "angular_velocity_x_dps",
+            Column::AngularVelocityY => "angular_velocity_y_dps",
+            Column::AngularVelocityZ => "angular_velocity_z_dps",
+            Column::Temperature => "temperature_c",
+        }
+    }
+
+    fn value(
+        &self,
+        sample: &SensorSample<Vec3D, f64>,
+        instant: Instant,
+        timebase: &Timebase,
+    ) -> Option<f64> {
+        Some(match self {
+            Column::Time => timebase.elapsed(instant).as_secs_f64(),
+            Column::UnixTime => timebase
+                .system_time(instant)
+                .duration_since(std::time::UNIX_EPOCH)
+                .ok()?
+                .as_secs_f64(),
+            Column::AccelerationX => sample.acceleration().x,
+            Column::AccelerationY => sample.acceleration().y,
+            Column::AccelerationZ => sample.acceleration().z,
+            Column::AngularVelocityX => sample.angular_velocity().x,
+            Column::AngularVelocityY => sample.angular_velocity().y,
+            Column::AngularVelocityZ => sample.angular_velocity().z,
+            Column::Temperature => sample.temperature()?,
+        })
+    }
+}
+
+/// Writes samples as comma-separated values, one line per sample, below a header line naming the columns
+pub struct CsvSink<W: Write> {
+    writer: W,
+    columns: Vec<Column>,
+    timebase: Timebase,
+    line: String, // Reused for formatting, to not allocate for every sample
+}
+
+impl<W: Write> CsvSink<W> {
+    /// Writes the header line for {columns} right away
+    pub fn new(mut writer: W, columns: &[Column], timebase: Timebase) -> Result<Self> {
+        ensure!(!columns.is_empty(), "CSV output needs at least one column.");
+        let header = columns
+            .iter()
+            .map(Column::name)
+            .collect::<Vec<_>>()
+            .join(",");
+        writeln!(writer, "{header}").context("Unable to write CSV header.")?;
+        Ok(Self {
+            writer,
+            columns: columns.to_vec(),
+            timebase,
+            line: String::new(),
+        })
+    }
+
+    /// Like `new`, with {metadata} as comment lines starting with "#" above the header line. pandas skips them with `read_csv(..., comment="#")`
+    pub fn with_metadata(
+        mut writer: W,
+        columns: &[Column],
+        metadata: &Metadata,
+        timebase: Timebase,
+    ) -> Result<Self> {
+        write_metadata(&mut writer, metadata)?;
+        Self::new(writer, columns, timebase)
+    }
+
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+// As YAML, with every line commented out
+pub(super) fn write_metadata<W: Write>(writer: &mut W, metadata: &Metadata) -> Result<()> {
+    let yaml = serde_yaml::to_string(metadata)?;
+    for line in yaml.trim_start_matches("---\n").lines() {
+        writeln!(writer, "# {line}").context("Unable to write CSV metadata.")?;
+    }
+    Ok(())
+}
+
+impl<W: Write> Sink for CsvSink<W> {
+    fn write(&mut self, samples: &[(SensorSample<Vec3D, f64>, Instant)]) -> Result<()> {
+        for (sample, instant) in samples {
+            self.line.clear();
+            for (index, column) in self.columns.iter().enumerate() {
+                if index > 0 {
+                    self.line.push(',');
+                }
+                if let Some(value) = column.value(sample, *instant, &self.timebase) {
+                    write!(self.line, "{value}")?;
+                }
+            }
+            self.line.push('\n');
+            self.writer
+                .write_all(self.line.as_bytes())
+                .context("Unable to write CSV line.")?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush().context("Unable to flush CSV output.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_csv_sink() {
+        let timebase = Timebase::now();
+        let samples = [
+            (
+                SensorSample::new(Vec3D::new(0, 0, 1), Vec3D::new(0.5, -2, 0), Some(25.25)),
+                timebase.start,
+            ),
+            (
+                SensorSample::new(Vec3D::new(0.1, 0, 1), Vec3D::default(), None),
+                timebase.start + Duration::from_millis(10),
+            ),
+        ];
+        let columns = [
+            Column::Time,
+            Column::AccelerationX,
+            Column::AngularVelocityY,
+            Column::Temperature,
+        ];
+        let mut sink = CsvSink::new(Vec::new(), &columns, timebase).unwrap();
+        sink.write(&samples).unwrap();
+        assert_eq!(
+            String::from_utf8(sink.into_inner()).unwrap(),
+            "time_s,acceleration_x_g,angular_velocity_y_dps,temperature_c\n\
+             0,0,-2,25.25\n\
+             0.01,0.1,0,\n"
+        );
+        assert!(CsvSink::new(Vec::new(), &[], timebase).is_err());
+    }
+}
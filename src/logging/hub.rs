@@ -0,0 +1,254 @@
+use std::{
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+
+use super::Sink;
+use crate::{
+    gy521::SensorSample,
+    math::Vec3D,
+    utilites::{self, MemoryConsumer, MemoryProducer, OverflowPolicy},
+};
+
+/// How a sink of a `Hub` gets its samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RouteConfig {
+    pub queue_capacity: usize, // Samples held while the sink is busy. A capacity of 0 counts as 1
+    pub policy: OverflowPolicy, // What happens once the queue is full: Overwrite loses the oldest samples of this sink only, Block holds up whoever writes to the hub
+    pub batch_size: usize, // Most samples written to the sink at once. Samples are written as soon as they come either way
+}
+
+impl RouteConfig {
+    /// Up to {queue_capacity} samples, in batches of up to 100
+    pub fn new(queue_capacity: usize, policy: OverflowPolicy) -> Self {
+        Self {
+            queue_capacity,
+            policy,
+            batch_size: 100,
+        }
+    }
+}
+
+/// How one sink of a `Hub` has done so far
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RouteStatus {
+    pub name: String,
+    pub written: usize, // Samples handed to the sink, whether it succeeded or not
+    pub dropped: usize, // Samples lost to a full queue, with `OverflowPolicy::Overwrite`
+    pub errors: usize,  // Failed writes and flushes
+    pub last_error: Option<String>,
+}
+
+struct Route<T> {
+    queue: MemoryProducer<(T, Instant)>,
+    status: Arc<Mutex<RouteStatus>>,
+    samples: Arc<MemoryConsumer<(T, Instant)>>, // The other end of {queue}, shared with the thread, for counting what was dropped
+    writer: JoinHandle<()>,
+}
+
+/// Feeds every sample written to it to any number of sinks, e.g., a file, a network stream, and a live filter, each on its own thread, with its own bounded queue.
+/// A slow or failing sink never holds up the others: Its errors are counted in `status` instead of failing `write`, and, with `OverflowPolicy::Overwrite`, it only loses its own oldest samples.
+/// Dropping the hub writes what is queued, flushes every sink, and waits for their threads to end
+pub struct Hub<T = SensorSample<Vec3D, f64>> {
+    routes: Vec<Route<T>>,
+}
+
+impl<T: Clone + Send + 'static> Hub<T> {
+    pub fn new() -> Self {
+        Self { routes: Vec::new() }
+    }
+
+    /// Starts feeding {sink}, known as {name} in `status`, with every sample written from now on
+    pub fn add<S: Sink<T> + Send + 'static>(
+        &mut self,
+        name: &str,
+        sink: S,
+        config: RouteConfig,
+    ) -> Result<()> {
+        let (queue, samples) = utilites::shared_memory(config.queue_capacity.max(1), config.policy);
+        let samples = Arc::new(samples);
+        let status = Arc::new(Mutex::new(RouteStatus {
+            name: name.to_string(),
+            ..Default::default()
+        }));
+        let writer = {
+            let (samples, status) = (samples.clone(), status.clone());
+            let batch_size = config.batch_size.max(1);
+            std::thread::Builder::new()
+                .name(format!("hub-{name}"))
+                .spawn(move || feed(sink, &samples, &status, batch_size))
+                .with_context(|| format!("Unable to start writing to {name}."))?
+        };
+        self.routes.push(Route {
+            queue,
+            status,
+            samples,
+            writer,
+        });
+        Ok(())
+    }
+
+    /// Of every sink, in the order they were added
+    pub fn status(&self) -> Vec<RouteStatus> {
+        self.routes
+            .iter()
+            .map(|route| RouteStatus {
+                dropped: route.samples.overwritten(),
+                ..route.status.lock().unwrap().clone()
+            })
+            .collect()
+    }
+}
+
+impl<T: Clone + Send + 'static> Default for Hub<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Writes what arrives in {samples} to {sink}, until the hub is gone and everything is written
+fn feed<T, S: Sink<T>>(
+    mut sink: S,
+    samples: &MemoryConsumer<(T, Instant)>,
+    status: &Mutex<RouteStatus>,
+    batch_size: usize,
+) {
+    let record = |result: Result<()>| {
+        if let Err(error) = result {
+            let mut status = status.lock().unwrap();
+            status.errors += 1;
+            status.last_error = Some(format!("{error:#}"));
+        }
+    };
+    let mut batch = Vec::with_capacity(batch_size);
+    loop {
+        // Waits in steps, to notice the hub being dropped
+        match samples.pop(Some(Duration::from_millis(100))) {
+            Some(sample) => batch.push(sample),
+            None if samples.is_disconnected() && samples.is_empty() => break,
+            None => continue,
+        }
+        batch.extend(samples.drain().take(batch_size - 1));
+        record(sink.write(&batch));
+        status.lock().unwrap().written += batch.len();
+        batch.clear();
+        // Only once the queue is empty, since flushing may be slow
+        if samples.is_empty() {
+            record(sink.flush());
+        }
+    }
+    record(sink.flush());
+}
+
+impl<T: Clone> Sink<T> for Hub<T> {
+    fn write(&mut self, samples: &[(T, Instant)]) -> Result<()> {
+        for sample in samples {
+            for route in &self.routes {
+                // Only fails if the thread of the route is gone, which only happens when it panicked
+                let _ = route.queue.push(sample.clone());
+            }
+        }
+        Ok(())
+    }
+
+    // Every sink is flushed on its own thread whenever it has caught up
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<T> Drop for Hub<T> {
+    fn drop(&mut self) {
+        for route in self.routes.drain(..) {
+            // Without the queue, the thread writes what is left and ends
+            drop(route.queue);
+            let _ = route.writer.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Collects what it is given, optionally taking its time
+    struct Collector {
+        samples: Arc<Mutex<Vec<usize>>>,
+        delay: Duration,
+        flushes: Arc<Mutex<usize>>,
+    }
+
+    impl Sink<usize> for Collector {
+        fn write(&mut self, samples: &[(usize, Instant)]) -> Result<()> {
+            std::thread::sleep(self.delay);
+            self.samples
+                .lock()
+                .unwrap()
+                .extend(samples.iter().map(|(sample, _)| sample));
+            anyhow::ensure!(
+                !samples.iter().any(|(sample, _)| *sample == 13),
+                "Unlucky sample."
+            );
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            *self.flushes.lock().unwrap() += 1;
+            Ok(())
+        }
+    }
+
+    fn collector(delay: Duration) -> Collector {
+        Collector {
+            samples: Arc::default(),
+            delay,
+            flushes: Arc::default(),
+        }
+    }
+
+    #[test]
+    fn test_fan_out() {
+        let mut hub = Hub::new();
+        let file = collector(Duration::ZERO);
+        let (file_samples, file_flushes) = (file.samples.clone(), file.flushes.clone());
+        hub.add("file", file, RouteConfig::new(10, OverflowPolicy::Block))
+            .unwrap();
+        // Busy with its first sample while the rest arrive
+        let stream = collector(Duration::from_millis(200));
+        let stream_samples = stream.samples.clone();
+        let mut config = RouteConfig::new(5, OverflowPolicy::Overwrite);
+        config.batch_size = 5;
+        hub.add("stream", stream, config).unwrap();
+
+        let instant = Instant::now();
+        hub.write(&[(0, instant)]).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        let samples = (1..100).map(|n| (n, instant)).collect::<Vec<_>>();
+        hub.write(&samples).unwrap();
+        let start = Instant::now();
+        while hub
+            .status()
+            .iter()
+            .map(|route| route.written)
+            .sum::<usize>()
+            < 100 + 6
+        {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let status = hub.status();
+        drop(hub);
+
+        assert_eq!(*file_samples.lock().unwrap(), (0..100).collect::<Vec<_>>());
+        assert!(*file_flushes.lock().unwrap() >= 1);
+        assert_eq!(*stream_samples.lock().unwrap(), [0, 95, 96, 97, 98, 99]);
+        assert_eq!(status[1].name, "stream");
+        assert_eq!(status[1].dropped, 94);
+        assert_eq!(status[0].dropped, 0);
+        assert_eq!(status[0].errors, 1);
+        assert_eq!(status[0].last_error.as_deref(), Some("Unlucky sample."));
+    }
+}
@@ -0,0 +1,340 @@
+use std::{
+    fmt::Write as _,
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use anyhow::{ensure, Context, Result};
+
+use super::{Sink, Timebase};
+use crate::{
+    gy521::SensorSample,
+    math::Vec3D,
+    utilites::{self, Backoff, MemoryConsumer, MemoryProducer, OverflowPolicy},
+};
+
+/// Where and how `InfluxDbSink` sends samples
+pub struct InfluxDbConfig {
+    pub url: String, // Write endpoint, e.g., "https://example.com:8086/api/v2/write?org=boat&bucket=imu" for InfluxDB 2, or "http://raspberrypi.local:8086/write?db=imu" for InfluxDB 1
+    pub token: Option<String>, // API token, sent as "Authorization: Token {token}"
+    pub measurement: String,
+    pub tags: Vec<(String, String)>, // Added to every sample, e.g., ("device", "njord-1") and ("session", "2022-03-14")
+    pub batch_size: usize,           // Samples per request
+    pub queue_capacity: usize, // Batches held while the server is unreachable. The oldest ones are dropped beyond that
+    pub backoff: Backoff,      // Between attempts at sending a batch
+    pub timeout: Duration,     // Per request
+}
+
+impl InfluxDbConfig {
+    /// Sends to {url}, in batches of 50 samples, retrying every 1 s to 60 s
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            token: None,
+            measurement: "imu".to_string(),
+            tags: Vec::new(),
+            batch_size: 50,
+            queue_capacity: 1000,
+            backoff: Backoff::Exponential {
+                initial: Duration::from_secs(1),
+                factor: 2.0,
+                max: Duration::from_secs(60),
+            },
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// How sending has gone so far
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InfluxDbStatus {
+    pub sent: usize,                // Samples the server accepted
+    pub failures: usize, // Failed requests, including ones that were retried successfully
+    pub dropped: usize, // Batches lost to a full queue or shutting down while the server was unreachable
+    pub last_error: Option<String>, // Of the latest failed request
+}
+
+/// Sends samples to InfluxDB in its line protocol, e.g., for Grafana dashboards of heel and pitch while sailing.
+/// Every sample becomes a point with acceleration [g], angular velocity [degree/s], temperature [degree C], and roll and pitch [degree] from `SensorSample::tilt`, at its wall-clock time.
+/// Requests go out on a background thread, so a slow or unreachable server never holds up sampling. Failed batches are retried with {backoff}, and network errors don't fail `write`, since losing the connection at sea shouldn't end a recording. See `status` instead.
+/// Flushing sends the pending samples right away, without waiting for a full batch. Dropping sends what is left, unless the server is unreachable by then
+pub struct InfluxDbSink {
+    timebase: Timebase,
+    prefix: String, // Measurement and tags, the same for every line
+    batch: String,
+    batch_count: usize, // Samples in {batch}
+    batch_size: usize,
+    producer: Option<MemoryProducer<(String, usize)>>, // None once dropped, to let the sender finish
+    status: Arc<Mutex<InfluxDbStatus>>,
+    sender: Option<JoinHandle<()>>,
+}
+
+impl InfluxDbSink {
+    pub fn new(config: InfluxDbConfig, timebase: Timebase) -> Result<Self> {
+        ensure!(
+            !config.measurement.is_empty(),
+            "InfluxDB measurement name is empty."
+        );
+        let mut prefix = escape(&config.measurement, &[',', ' ']);
+        for (key, value) in &config.tags {
+            // Empty tag values aren't allowed in the line protocol
+            if !key.is_empty() && !value.is_empty() {
+                let escape_tag = |text| escape(text, &[',', '=', ' ']);
+                write!(prefix, ",{}={}", escape_tag(key), escape_tag(value))?;
+            }
+        }
+
+        let (producer, consumer) =
+            utilites::shared_memory(config.queue_capacity.max(1), OverflowPolicy::Overwrite);
+        let status = Arc::new(Mutex::new(InfluxDbStatus::default()));
+        let batch_size = config.batch_size.max(1);
+        let sender = {
+            let status = status.clone();
+            std::thread::Builder::new()
+                .name("influxdb".to_string())
+                .spawn(move || send(config, consumer, status))
+                .context("Unable to start InfluxDB sender.")?
+        };
+        Ok(Self {
+            timebase,
+            prefix,
+            batch: String::new(),
+            batch_count: 0,
+            batch_size,
+            producer: Some(producer),
+            status,
+            sender: Some(sender),
+        })
+    }
+
+    pub fn status(&self) -> InfluxDbStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    fn hand_over(&mut self) -> Result<()> {
+        if self.batch_count == 0 {
+            return Ok(());
+        }
+        let batch = std::mem::take(&mut self.batch);
+        let count = std::mem::take(&mut self.batch_count);
+        self.producer
+            .as_ref()
+            .context("InfluxDB sink is shut down.")?
+            .push((batch, count))
+            .context("InfluxDB sender stopped.")
+    }
+}
+
+impl Sink for InfluxDbSink {
+    fn write(&mut self, samples: &[(SensorSample<Vec3D, f64>, Instant)]) -> Result<()> {
+        for (sample, instant) in samples {
+            let time = self
+                .timebase
+                .system_time(*instant)
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos();
+            write_line(&mut self.batch, &self.prefix, sample, time)?;
+            self.batch_count += 1;
+            if self.batch_count >= self.batch_size {
+                self.hand_over()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.hand_over()
+    }
+}
+
+impl Drop for InfluxDbSink {
+    fn drop(&mut self) {
+        let _ = self.hand_over();
+        self.producer = None;
+        if let Some(sender) = self.sender.take() {
+            let _ = sender.join();
+        }
+    }
+}
+
+// Backslashes before {special} characters, as the line protocol needs in names, tags, and field keys
+fn escape(text: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        if character == '\\' || special.contains(&character) {
+            escaped.push('\\');
+        }
+        escaped.push(character);
+    }
+    escaped
+}
+
+// One point, e.g., "imu,device=njord acceleration_x=0,...,outlier=false 1647249013000000000". Values that aren't finite can't be written, so they are left out
+fn write_line(
+    line: &mut String,
+    prefix: &str,
+    sample: &SensorSample<Vec3D, f64>,
+    time: u128, // [ns] Since 1970-01-01 00:00 UTC
+) -> Result<()> {
+    let (acceleration, angular_velocity) = (sample.acceleration(), sample.angular_velocity());
+    let (roll, pitch) = sample.tilt();
+    let fields = [
+        ("acceleration_x", Some(acceleration.x)),
+        ("acceleration_y", Some(acceleration.y)),
+        ("acceleration_z", Some(acceleration.z)),
+        ("angular_velocity_x", Some(angular_velocity.x)),
+        ("angular_velocity_y", Some(angular_velocity.y)),
+        ("angular_velocity_z", Some(angular_velocity.z)),
+        ("temperature", sample.temperature()),
+        ("roll", Some(roll.to_degrees())),
+        ("pitch", Some(pitch.to_degrees())),
+    ];
+    write!(line, "{prefix} ")?;
+    for (key, value) in fields {
+        if let Some(value) = value.filter(|value| value.is_finite()) {
+            write!(line, "{key}={value},")?;
+        }
+    }
+    writeln!(line, "outlier={} {time}", sample.is_outlier())?;
+    Ok(())
+}
+
+// Runs on the background thread until the sink is dropped and the queue is empty
+fn send(
+    config: InfluxDbConfig,
+    consumer: MemoryConsumer<(String, usize)>,
+    status: Arc<Mutex<InfluxDbStatus>>,
+) {
+    let agent = ureq::AgentBuilder::new().timeout(config.timeout).build();
+    let post = |body: &str| -> Result<()> {
+        let mut request = agent
+            .post(&config.url)
+            .set("Content-Type", "text/plain; charset=utf-8");
+        if let Some(token) = &config.token {
+            request = request.set("Authorization", &format!("Token {token}"));
+        }
+        request
+            .send_string(body)
+            .map(|_| ())
+            .context("Unable to send samples to InfluxDB.")
+    };
+
+    let mut abandoned = 0; // Batches given up on after the sink was dropped
+    loop {
+        let Some((batch, count)) = consumer.pop(Some(Duration::from_millis(100))) else {
+            if consumer.is_disconnected() && consumer.is_empty() {
+                break;
+            }
+            continue;
+        };
+        // Once the sink is gone, nobody waits for the server to come back
+        if abandoned > 0 {
+            abandoned += 1;
+            status.lock().unwrap().dropped = consumer.overwritten() + abandoned;
+            continue;
+        }
+        let mut attempt = 1;
+        loop {
+            let result = post(&batch);
+            let mut status = status.lock().unwrap();
+            let sent = result.is_ok();
+            match result {
+                Ok(()) => status.sent += count,
+                Err(error) => {
+                    status.failures += 1;
+                    status.last_error = Some(format!("{error:#}"));
+                    if consumer.is_disconnected() {
+                        abandoned += 1;
+                    }
+                }
+            }
+            status.dropped = consumer.overwritten() + abandoned;
+            if sent || abandoned > 0 {
+                break;
+            }
+            drop(status);
+            std::thread::sleep(config.backoff.delay(attempt));
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_line_protocol() {
+        let mut line = String::new();
+        let sample = SensorSample::new(Vec3D::new(0, 0, 1), Vec3D::new(0.5, 0, 0), None);
+        write_line(&mut line, &escape("imu data", &[',', ' ']), &sample, 42).unwrap();
+        assert_eq!(
+            line,
+            "imu\\ data acceleration_x=0,acceleration_y=0,acceleration_z=1,angular_velocity_x=0.5,angular_velocity_y=0,angular_velocity_z=0,roll=0,pitch=-0,outlier=false 42\n"
+        );
+        assert_eq!(escape("a=b,c d", &[',', '=', ' ']), "a\\=b\\,c\\ d");
+    }
+
+    #[test]
+    fn test_retry() {
+        // Answers the first request with an error, and accepts the rest
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for (index, stream) in listener.incoming().take(2).enumerate() {
+                let mut stream = BufReader::new(stream.unwrap());
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    stream.read_line(&mut line).unwrap();
+                    if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0; length];
+                stream.read_exact(&mut body).unwrap();
+                bodies.push(String::from_utf8(body).unwrap());
+                let status = if index == 0 {
+                    "503 Service Unavailable"
+                } else {
+                    "204 No Content"
+                };
+                write!(
+                    stream.get_mut(),
+                    "HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                )
+                .unwrap();
+            }
+            bodies
+        });
+
+        let mut config = InfluxDbConfig::new(&format!("http://{address}/write?db=test"));
+        config.tags = vec![("device".to_string(), "njord 1".to_string())];
+        config.batch_size = 3;
+        config.backoff = Backoff::Constant(Duration::from_millis(10));
+        let timebase = Timebase::now();
+        let mut sink = InfluxDbSink::new(config, timebase).unwrap();
+        let sample = SensorSample::new(Vec3D::new(0, 0, 1), Vec3D::default(), Some(20.0));
+        sink.write(&[(sample, timebase.start); 3]).unwrap();
+        let bodies = server.join().unwrap();
+        drop(sink);
+
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(bodies[0], bodies[1]);
+        let lines = bodies[1].lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("imu,device=njord\\ 1 acceleration_x=0,"));
+        assert!(lines[0].contains(",temperature=20,"));
+    }
+}
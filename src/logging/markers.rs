@@ -0,0 +1,182 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+
+use super::{Metadata, Sink, Timebase};
+
+/// Something that happened during a recording, like "tack", "engine start", or a button press, for finding maneuvers in the samples afterwards
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Marker {
+    pub tag: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<serde_json::Value>, // Anything else worth noting, e.g., {"heading": 270}
+}
+
+impl Marker {
+    pub fn new(tag: &str) -> Self {
+        Self {
+            tag: tag.to_string(),
+            payload: None,
+        }
+    }
+
+    pub fn with_payload(tag: &str, payload: serde_json::Value) -> Self {
+        Self {
+            payload: Some(payload),
+            ..Self::new(tag)
+        }
+    }
+}
+
+/// Hands markers over from other threads, e.g., from a GPIO interrupt or from reading the console, stamped with when they happened. See `channel`
+#[derive(Clone)]
+pub struct MarkerSender(crossbeam_channel::Sender<(Marker, Instant)>);
+
+impl MarkerSender {
+    /// Stamps {marker} with the current instant
+    pub fn mark(&self, marker: Marker) -> Result<()> {
+        self.0
+            .send((marker, Instant::now()))
+            .context("Markers aren't recorded anymore.")
+    }
+}
+
+/// The receiving end gets the markers in the order they were sent, to push into a `MarkerSink`, e.g., through a `streaming::StreamingWriter`, along with the samples
+pub fn channel() -> (MarkerSender, crossbeam_channel::Receiver<(Marker, Instant)>) {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    (MarkerSender(sender), receiver)
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Record {
+    time: f64, // [s] Since the start of the recording
+    #[serde(default)]
+    unix_time: f64, // [s] Wall-clock time since 1970-01-01 00:00 UTC
+    #[serde(flatten)]
+    marker: Marker,
+}
+
+/// Writes markers as newline-delimited JSON, like `ndjson::NdjsonSink` does with samples, with times on the same timebase as the samples of the recording
+pub struct MarkerSink<W: Write> {
+    writer: W,
+    timebase: Timebase,
+}
+
+impl<W: Write> MarkerSink<W> {
+    /// Writes a first line holding {metadata}, as an object with only the key "metadata", right away
+    pub fn new(mut writer: W, metadata: &Metadata, timebase: Timebase) -> Result<Self> {
+        serde_json::to_writer(&mut writer, &serde_json::json!({ "metadata": metadata }))?;
+        writer
+            .write_all(b"\n")
+            .context("Unable to write JSON metadata.")?;
+        Ok(Self { writer, timebase })
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Sink<Marker> for MarkerSink<W> {
+    fn write(&mut self, markers: &[(Marker, Instant)]) -> Result<()> {
+        for (marker, instant) in markers {
+            let record = Record {
+                time: self.timebase.elapsed(*instant).as_secs_f64(),
+                unix_time: self
+                    .timebase
+                    .system_time(*instant)
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs_f64(),
+                marker: marker.clone(),
+            };
+            serde_json::to_writer(&mut self.writer, &record)?;
+            self.writer
+                .write_all(b"\n")
+                .context("Unable to write marker.")?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush().context("Unable to flush markers.")
+    }
+}
+
+/// Markers as read from a file
+pub struct MarkerLog {
+    pub metadata: Option<Metadata>,
+    pub markers: Vec<(Marker, Duration)>, // With the time since the start of the recording, for lining them up with the samples of a `replay::Recording`
+}
+
+/// Reads what `MarkerSink` writes
+pub fn read_markers<R: Read>(reader: R) -> Result<MarkerLog> {
+    #[derive(serde::Deserialize)]
+    struct Start {
+        metadata: Metadata,
+    }
+
+    let mut lines = BufReader::new(reader).lines().peekable();
+    let metadata = match lines.peek() {
+        Some(Ok(line)) if line.starts_with("{\"metadata\"") => {
+            let start: Start =
+                serde_json::from_str(line).context("Unable to parse marker metadata.")?;
+            lines.next();
+            Some(start.metadata)
+        }
+        _ => None,
+    };
+    let mut markers = Vec::new();
+    for line in lines {
+        let line = line.context("Unable to read markers.")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Record = serde_json::from_str(&line).context("Unable to parse marker.")?;
+        markers.push((record.marker, Duration::from_secs_f64(record.time.max(0.0))));
+    }
+    Ok(MarkerLog { metadata, markers })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let timebase = Timebase::now();
+        let metadata = Metadata::for_tests(&timebase);
+        let (sender, receiver) = channel();
+        let marker = std::thread::spawn({
+            let sender = sender.clone();
+            move || sender.mark(Marker::new("engine start"))
+        });
+        marker.join().unwrap().unwrap();
+        sender
+            .mark(Marker::with_payload(
+                "tack",
+                serde_json::json!({ "heading": 270 }),
+            ))
+            .unwrap();
+        drop(sender);
+
+        let mut sink = MarkerSink::new(Vec::new(), &metadata, timebase).unwrap();
+        sink.write(&receiver.iter().collect::<Vec<_>>()).unwrap();
+        let MarkerLog {
+            metadata: read_metadata,
+            markers,
+        } = read_markers(sink.into_inner().as_slice()).unwrap();
+        assert_eq!(read_metadata, Some(metadata));
+        assert_eq!(markers.len(), 2);
+        assert_eq!(markers[0].0, Marker::new("engine start"));
+        assert_eq!(markers[1].0.payload.as_ref().unwrap()["heading"], 270);
+        assert!(markers[0].1 <= markers[1].1);
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+
+use super::{Metadata, Sink, Timebase};
+use crate::{gy521::SensorSample, math::Vec3D};
+
+// A stream of MessagePack values: The `Metadata` first, then one (time since the start [ns], sample) pair per sample.
+// Structs are encoded as arrays, without field names, which takes under 80 bytes per sample instead of several hundred for YAML or JSON.
+// Every value stands on its own, so the stream can be cut off anywhere between samples, e.g., when a connection drops
+
+/// Writes samples as MessagePack, e.g., to a file or a TCP connection
+pub struct MessagePackSink<W: Write> {
+    writer: W,
+    timebase: Timebase,
+}
+
+impl<W: Write> MessagePackSink<W> {
+    /// Writes {metadata} right away. Sample times count from the start of {timebase}
+    pub fn new(mut writer: W, metadata: &Metadata, timebase: Timebase) -> Result<Self> {
+        rmp_serde::encode::write(&mut writer, metadata)
+            .context("Unable to write MessagePack metadata.")?;
+        Ok(Self { writer, timebase })
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Sink for MessagePackSink<W> {
+    fn write(&mut self, samples: &[(SensorSample<Vec3D, f64>, Instant)]) -> Result<()> {
+        for (sample, instant) in samples {
+            let time = self.timebase.elapsed(*instant).as_nanos() as u64;
+            rmp_serde::encode::write(&mut self.writer, &(time, sample))
+                .context("Unable to write MessagePack sample.")?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer
+            .flush()
+            .context("Unable to flush MessagePack output.")
+    }
+}
+
+/// Reads MessagePack samples back, as (sample, time since the start) pairs. Stops at the first value that can't be read, after returning an error for it
+pub struct MessagePackReader<R: Read> {
+    reader: R,
+    metadata: Metadata,
+    failed: bool,
+}
+
+impl<R: Read> MessagePackReader<R> {
+    /// Reads the metadata
+    pub fn new(mut reader: R) -> Result<Self> {
+        let metadata =
+            rmp_serde::from_read(&mut reader).context("Unable to read MessagePack metadata.")?;
+        Ok(Self {
+            reader,
+            metadata,
+            failed: false,
+        })
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+}
+
+impl<R: Read> Iterator for MessagePackReader<R> {
+    type Item = Result<(SensorSample<Vec3D, f64>, Duration)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match rmp_serde::from_read::<_, (u64, SensorSample<Vec3D, f64>)>(&mut self.reader) {
+            Ok((time, sample)) => Some(Ok((sample, Duration::from_nanos(time)))),
+            // Nothing left where the next value would start
+            Err(rmp_serde::decode::Error::InvalidMarkerRead(error))
+                if error.kind() == ErrorKind::UnexpectedEof =>
+            {
+                None
+            }
+            Err(error) => {
+                self.failed = true;
+                Some(Err(error).context("Unable to read MessagePack sample."))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gy521::Filter;
+
+    #[test]
+    fn test_round_trip() {
+        let timebase = Timebase::now();
+        let metadata = Metadata {
+            sample_rate: 100.0,
+            filter: Filter::Disabled,
+            device_model: Some("Raspberry Pi 3 Model B".to_string()),
+            ..Metadata::for_tests(&timebase)
+        };
+        let samples = (0..10_i32)
+            .map(|n| {
+                let temperature = (n % 2 == 0).then_some(21.5);
+                (
+                    SensorSample::new(Vec3D::new(0.1, n, 1), Vec3D::new(n, 0, -n), temperature),
+                    timebase.start + Duration::from_millis(10 * n as u64),
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut sink = MessagePackSink::new(Vec::new(), &metadata, timebase).unwrap();
+        let header_size = sink.get_ref().len();
+        sink.write(&samples).unwrap();
+        let mut bytes = sink.into_inner();
+        assert!((bytes.len() - header_size) / samples.len() < 80);
+
+        let reader = MessagePackReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.metadata(), &metadata);
+        let read = reader.collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(read.len(), samples.len());
+        for ((sample, time), (original, instant)) in read.iter().zip(&samples) {
+            assert_eq!(*time, timebase.elapsed(*instant));
+            assert_eq!(sample.acceleration(), original.acceleration());
+            assert_eq!(sample.temperature(), original.temperature());
+        }
+
+        // Cut off in the middle of the last sample
+        bytes.truncate(bytes.len() - 3);
+        let read = MessagePackReader::new(bytes.as_slice())
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(read.len(), samples.len());
+        assert!(read.last().unwrap().is_err());
+    }
+}
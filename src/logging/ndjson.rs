@@ -0,0 +1,89 @@
+use std::{io::Write, time::Instant};
+
+use anyhow::{Context, Result};
+
+use super::{Metadata, Sink, Timebase};
+use crate::{gy521::SensorSample, math::Vec3D};
+
+#[derive(serde::Serialize)]
+struct Record<'a> {
+    time: f64,      // [s] Since the start of the recording
+    unix_time: f64, // [s] Wall-clock time since 1970-01-01 00:00 UTC
+    #[serde(flatten)]
+    sample: &'a SensorSample<Vec3D, f64>,
+}
+
+/// Writes samples as newline-delimited JSON, one object per line, so that every line is complete on its own and a file cut short by a crash stays readable
+pub struct NdjsonSink<W: Write> {
+    writer: W,
+    timebase: Timebase,
+}
+
+impl<W: Write> NdjsonSink<W> {
+    pub fn new(writer: W, timebase: Timebase) -> Self {
+        Self { writer, timebase }
+    }
+
+    /// Like `new`, with a first line holding {metadata}, as an object with only the key "metadata"
+    pub fn with_metadata(mut writer: W, metadata: &Metadata, timebase: Timebase) -> Result<Self> {
+        serde_json::to_writer(&mut writer, &serde_json::json!({ "metadata": metadata }))?;
+        writer
+            .write_all(b"\n")
+            .context("Unable to write JSON metadata.")?;
+        Ok(Self::new(writer, timebase))
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Sink for NdjsonSink<W> {
+    fn write(&mut self, samples: &[(SensorSample<Vec3D, f64>, Instant)]) -> Result<()> {
+        for (sample, instant) in samples {
+            let record = Record {
+                time: self.timebase.elapsed(*instant).as_secs_f64(),
+                unix_time: self
+                    .timebase
+                    .system_time(*instant)
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs_f64(),
+                sample,
+            };
+            serde_json::to_writer(&mut self.writer, &record)?;
+            self.writer
+                .write_all(b"\n")
+                .context("Unable to write JSON line.")?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush().context("Unable to flush JSON output.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_one_object_per_line() {
+        let timebase = Timebase::now();
+        let sample = SensorSample::new(Vec3D::new(0, 0, 1), Vec3D::default(), Some(20.0));
+        let mut sink = NdjsonSink::new(Vec::new(), timebase);
+        sink.write(&[(sample, timebase.start); 2]).unwrap();
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let record: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(record["time"], 0.0);
+        assert_eq!(record["acceleration"]["z"], 1.0);
+        assert_eq!(record["temperature"], 20.0);
+    }
+}
@@ -0,0 +1,257 @@
+use std::{io::Write, sync::Arc, time::Instant};
+
+use anyhow::{Context, Result};
+use parquet::{
+    data_type::{BoolType, DoubleType, Int64Type},
+    file::{metadata::KeyValue, properties::WriterProperties, writer::SerializedFileWriter},
+    schema::parser::parse_message_type,
+};
+
+use super::{Metadata, Sink, Timebase};
+use crate::{gy521::SensorSample, math::Vec3D};
+
+// Same columns and units as `csv::Column::ALL`, plus the outlier flag. Wall-clock time is a proper timestamp, so Polars and pandas parse it as such
+const SCHEMA: &str = "
+    message sample {
+        REQUIRED DOUBLE time_s;
+        REQUIRED INT64 unix_time (TIMESTAMP(MICROS, true));
+        REQUIRED DOUBLE acceleration_x_g;
+        REQUIRED DOUBLE acceleration_y_g;
+        REQUIRED DOUBLE acceleration_z_g;
+        REQUIRED DOUBLE angular_velocity_x_dps;
+        REQUIRED DOUBLE angular_velocity_y_dps;
+        REQUIRED DOUBLE angular_velocity_z_dps;
+        OPTIONAL DOUBLE temperature_c;
+        REQUIRED BOOLEAN outlier;
+    }
+";
+
+pub const ROW_GROUP_SIZE: usize = 65_536; // [samples] Default, about 11 minutes at 100 Hz
+pub const METADATA_KEY: &str = "njord.metadata"; // Key of the `Metadata`, as JSON, in the file's key-value metadata
+
+// Samples of the next row group, column by column
+#[derive(Default)]
+struct Columns {
+    time: Vec<f64>,
+    unix_time: Vec<i64>,
+    values: [Vec<f64>; 6],        // Acceleration, then angular velocity
+    temperature: Vec<f64>,        // Only the samples that have one
+    temperature_levels: Vec<i16>, // 1 where a sample has a temperature, 0 where it doesn't
+    outlier: Vec<bool>,
+}
+
+/// Writes samples as a Parquet file, with one typed column per value, for loading straight into Polars or pandas.
+/// Parquet files are only readable once they are complete, so call `close` at the end, which dropping does as well, ignoring errors.
+/// Samples are written in row groups of {row_group_size}. A crash loses the current row group along with the footer, so for long recordings, rather stream to another format and convert afterwards
+pub struct ParquetSink<W: Write + Send> {
+    writer: Option<SerializedFileWriter<W>>, // None once closed
+    timebase: Timebase,
+    row_group_size: usize,
+    columns: Columns,
+}
+
+impl<W: Write + Send> ParquetSink<W> {
+    pub fn new(writer: W, timebase: Timebase, row_group_size: usize) -> Result<Self> {
+        Self::create(writer, None, timebase, row_group_size)
+    }
+
+    /// Like `new`, with {metadata} stored in the file's key-value metadata under `METADATA_KEY`
+    pub fn with_metadata(
+        writer: W,
+        metadata: &Metadata,
+        timebase: Timebase,
+        row_group_size: usize,
+    ) -> Result<Self> {
+        let metadata = KeyValue::new(METADATA_KEY.to_string(), serde_json::to_string(metadata)?);
+        Self::create(writer, Some(metadata), timebase, row_group_size)
+    }
+
+    fn create(
+        writer: W,
+        metadata: Option<KeyValue>,
+        timebase: Timebase,
+        row_group_size: usize,
+    ) -> Result<Self> {
+        let schema = Arc::new(parse_message_type(SCHEMA)?);
+        let properties = Arc::new(
+            WriterProperties::builder()
+                .set_key_value_metadata(metadata.map(|metadata| vec![metadata]))
+                .build(),
+        );
+        Ok(Self {
+            writer: Some(
+                SerializedFileWriter::new(writer, schema, properties)
+                    .context("Unable to start Parquet file.")?,
+            ),
+            timebase,
+            row_group_size: row_group_size.max(1),
+            columns: Columns::default(),
+        })
+    }
+
+    /// Writes the remaining samples and the footer, and returns the underlying writer
+    pub fn close(mut self) -> Result<W> {
+        self.write_row_group()?;
+        let writer = self.writer.take().context("Parquet file is closed.")?;
+        writer
+            .into_inner()
+            .context("Unable to finish Parquet file.")
+    }
+
+    fn write_row_group(&mut self) -> Result<()> {
+        let writer = self.writer.as_mut().context("Parquet file is closed.")?;
+        if self.columns.time.is_empty() {
+            return Ok(());
+        }
+        let columns = std::mem::take(&mut self.columns);
+        let mut row_group = writer.next_row_group()?;
+        let mut index = 0;
+        while let Some(mut column) = row_group.next_column()? {
+            match index {
+                0 => column
+                    .typed::<DoubleType>()
+                    .write_batch(&columns.time, None, None)?,
+                1 => column
+                    .typed::<Int64Type>()
+                    .write_batch(&columns.unix_time, None, None)?,
+                2..=7 => column.typed::<DoubleType>().write_batch(
+                    &columns.values[index - 2],
+                    None,
+                    None,
+                )?,
+                8 => column.typed::<DoubleType>().write_batch(
+                    &columns.temperature,
+                    Some(&columns.temperature_levels),
+                    None,
+                )?,
+                _ => column
+                    .typed::<BoolType>()
+                    .write_batch(&columns.outlier, None, None)?,
+            };
+            column.close()?;
+            index += 1;
+        }
+        row_group
+            .close()
+            .context("Unable to write Parquet row group.")?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> Sink for ParquetSink<W> {
+    fn write(&mut self, samples: &[(SensorSample<Vec3D, f64>, Instant)]) -> Result<()> {
+        for (sample, instant) in samples {
+            let columns = &mut self.columns;
+            columns
+                .time
+                .push(self.timebase.elapsed(*instant).as_secs_f64());
+            columns.unix_time.push(
+                self.timebase
+                    .system_time(*instant)
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_micros() as i64,
+            );
+            let (acceleration, angular_velocity) =
+                (sample.acceleration(), sample.angular_velocity());
+            for (column, value) in columns.values.iter_mut().zip([
+                acceleration.x,
+                acceleration.y,
+                acceleration.z,
+                angular_velocity.x,
+                angular_velocity.y,
+                angular_velocity.z,
+            ]) {
+                column.push(value);
+            }
+            columns.temperature.extend(sample.temperature());
+            columns
+                .temperature_levels
+                .push(sample.temperature().is_some() as i16);
+            columns.outlier.push(sample.is_outlier());
+
+            if columns.time.len() >= self.row_group_size {
+                self.write_row_group()?;
+            }
+        }
+        Ok(())
+    }
+
+    // Only complete row groups are written before closing, since every row group is stored on its own with its metadata
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> Drop for ParquetSink<W> {
+    fn drop(&mut self) {
+        if self.writer.is_some() {
+            let _ = self.write_row_group();
+            if let Some(mut writer) = self.writer.take() {
+                let _ = writer.finish();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use parquet::{
+        file::reader::{FileReader, SerializedFileReader},
+        record::RowAccessor,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_parquet_sink() {
+        let timebase = Timebase::now();
+        let samples = (0..25_i32)
+            .map(|n| {
+                let temperature = (n % 5 != 0).then_some(20.0);
+                (
+                    SensorSample::new(Vec3D::new(0, 0, n), Vec3D::default(), temperature),
+                    timebase.start + Duration::from_millis(10 * n as u64),
+                )
+            })
+            .collect::<Vec<_>>();
+        let path = std::env::temp_dir().join(format!("njord_parquet_{}", std::process::id()));
+        let metadata = Metadata {
+            sample_rate: 100.0,
+            filter: Default::default(),
+            ..Metadata::for_tests(&timebase)
+        };
+        let file = std::fs::File::create(&path).unwrap();
+        let mut sink = ParquetSink::with_metadata(file, &metadata, timebase, 10).unwrap();
+        sink.write(&samples).unwrap();
+        sink.close().unwrap();
+
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let file_metadata = reader.metadata();
+        assert_eq!(file_metadata.file_metadata().num_rows(), 25);
+        assert_eq!(file_metadata.num_row_groups(), 3);
+        let stored = file_metadata
+            .file_metadata()
+            .key_value_metadata()
+            .and_then(|pairs| pairs.iter().find(|pair| pair.key == METADATA_KEY))
+            .and_then(|pair| pair.value.as_deref())
+            .unwrap();
+        assert_eq!(
+            serde_json::from_str::<Metadata>(stored)
+                .unwrap()
+                .sample_rate,
+            100.0
+        );
+        let rows = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(rows[7].get_double(4).unwrap(), 7.0); // acceleration_z_g
+        assert!(rows[5].get_double(8).is_err()); // No temperature
+        assert_eq!(rows[6].get_double(8).unwrap(), 20.0);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
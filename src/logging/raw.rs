@@ -0,0 +1,117 @@
+use std::{fmt::Write as _, io::Write, time::Instant};
+
+use anyhow::{Context, Result};
+
+use super::{csv::write_metadata, Metadata, Sink, Timebase};
+use crate::gy521::RawSample;
+
+const HEADER: &str = "time_s,unix_time_s,acceleration_x,acceleration_y,acceleration_z,angular_velocity_x,angular_velocity_y,angular_velocity_z,temperature";
+
+/// Writes raw readings, in counts as the sensor reports them, as comma-separated values, for redoing the calibration offline.
+/// Times are the same as for the calibrated samples from the same reads, so both files line up. Scale factors follow from the ranges in the metadata, e.g., 16384 counts per g for +-2 g
+pub struct RawCsvSink<W: Write> {
+    writer: W,
+    timebase: Timebase,
+    line: String, // Reused for formatting, to not allocate for every sample
+}
+
+impl<W: Write> RawCsvSink<W> {
+    /// Writes {metadata} as comment lines starting with "#", and the header line, right away
+    pub fn new(mut writer: W, metadata: &Metadata, timebase: Timebase) -> Result<Self> {
+        write_metadata(&mut writer, metadata)?;
+        writeln!(writer, "{HEADER}").context("Unable to write CSV header.")?;
+        Ok(Self {
+            writer,
+            timebase,
+            line: String::new(),
+        })
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Sink<RawSample> for RawCsvSink<W> {
+    fn write(&mut self, samples: &[(RawSample, Instant)]) -> Result<()> {
+        for (sample, instant) in samples {
+            let [ax, ay, az] = sample.acceleration();
+            let [gx, gy, gz] = sample.angular_velocity();
+            self.line.clear();
+            write!(
+                self.line,
+                "{},{},{ax},{ay},{az},{gx},{gy},{gz},",
+                self.timebase.elapsed(*instant).as_secs_f64(),
+                self.timebase
+                    .system_time(*instant)
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs_f64(),
+            )?;
+            if let Some(temperature) = sample.temperature() {
+                write!(self.line, "{temperature}")?;
+            }
+            self.line.push('\n');
+            self.writer
+                .write_all(self.line.as_bytes())
+                .context("Unable to write CSV line.")?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush().context("Unable to flush CSV output.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{gy521::SensorSample, logging::streaming::StreamingWriter};
+
+    #[test]
+    fn test_raw_csv_sink() {
+        let timebase = Timebase::now();
+        let metadata = Metadata {
+            sample_rate: 100.0,
+            filter: Default::default(),
+            ..Metadata::for_tests(&timebase)
+        };
+        let samples = [
+            (
+                SensorSample::new([0, -12, 16384], [131, 0, -1], Some(-521)),
+                timebase.start,
+            ),
+            (
+                SensorSample::new([1, 2, 3], [4, 5, 6], None),
+                timebase.start + Duration::from_millis(10),
+            ),
+        ];
+        let mut writer = StreamingWriter::new(
+            RawCsvSink::new(Vec::new(), &metadata, timebase).unwrap(),
+            10,
+        );
+        for (sample, instant) in samples {
+            writer.push(sample, instant).unwrap();
+        }
+        writer.flush().unwrap();
+        let output = String::from_utf8(writer.sink().get_ref().clone()).unwrap();
+        let lines = output
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect::<Vec<_>>();
+        assert_eq!(lines[0], HEADER);
+        assert!(lines[1].starts_with("0,") && lines[1].ends_with(",0,-12,16384,131,0,-1,-521"));
+        assert!(lines[2].starts_with("0.01,") && lines[2].ends_with(",1,2,3,4,5,6,"));
+        // Same comments as calibrated CSV files
+        let mut comments = Vec::new();
+        write_metadata(&mut comments, &metadata).unwrap();
+        assert!(output.starts_with(std::str::from_utf8(&comments).unwrap()));
+    }
+}
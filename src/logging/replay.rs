@@ -0,0 +1,568 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::Path,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{bail, Context, Result};
+
+use super::{binary::BinaryReader, compression, Metadata};
+use crate::{
+    gy521::{SampleSource, SensorSample},
+    math::Vec3D,
+};
+
+/// Recorded samples, with their time since the start of the recording, oldest to newest
+pub type Records = Box<dyn Iterator<Item = Result<(SensorSample<Vec3D, f64>, Duration)>>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pace {
+    RealTime,         // Samples come as far apart as they were recorded
+    AsFastAsPossible, // Samples come right away, but their instants are still as far apart as they were recorded
+}
+
+/// Plays a recording back as a `SampleSource`, for developing processing code, like attitude filters, away from the Pi.
+/// Samples come with instants as far apart as they were recorded, counting from when the replay was created
+pub struct Replay {
+    records: Records,
+    pace: Pace,
+    start: Instant, // Corresponds to the start of the recording
+    pending: Option<(SensorSample<Vec3D, f64>, Duration)>, // Next sample, if it wasn't due yet when waiting for it timed out
+    finished: bool,
+    metadata: Option<Metadata>,
+}
+
+impl Replay {
+    pub fn new(records: Records, pace: Pace) -> Self {
+        Self {
+            records,
+            pace,
+            start: Instant::now(),
+            pending: None,
+            finished: false,
+            metadata: None,
+        }
+    }
+
+    /// Replays the recording at {path}. See `Recording::open`
+    pub fn open<P: AsRef<Path>>(path: P, pace: Pace) -> Result<Self> {
+        let recording = Recording::open(path)?;
+        Ok(Self {
+            metadata: recording.metadata,
+            ..Self::new(recording.records, pace)
+        })
+    }
+
+    /// How the sensor was set up for the recording, if the file says
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    /// Instant that corresponds to the start of the recording
+    pub fn start(&self) -> Instant {
+        self.start
+    }
+}
+
+impl SampleSource for Replay {
+    fn next_sample(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> (Result<Option<SensorSample<Vec3D, f64>>>, Instant) {
+        let (sample, time) = match self.pending.take() {
+            Some(record) => record,
+            None => match self.records.next() {
+                Some(Ok(record)) => record,
+                Some(Err(error)) => return (Err(error), Instant::now()),
+                None => {
+                    self.finished = true;
+                    return (Ok(None), Instant::now());
+                }
+            },
+        };
+
+        let instant = self.start + time;
+        if self.pace == Pace::RealTime {
+            let wait = instant.saturating_duration_since(Instant::now());
+            if let Some(timeout) = timeout.filter(|timeout| wait > *timeout) {
+                std::thread::sleep(timeout);
+                self.pending = Some((sample, time));
+                return (Ok(None), Instant::now());
+            }
+            std::thread::sleep(wait);
+        }
+        (Ok(Some(sample)), instant)
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+impl Iterator for Replay {
+    type Item = Result<(SensorSample<Vec3D, f64>, Instant)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_sample(None) {
+            (Ok(Some(sample)), instant) => Some(Ok((sample, instant))),
+            (Err(error), _) => Some(Err(error)),
+            (Ok(None), _) => None,
+        }
+    }
+}
+
+/// A recording as read from a file, before replaying it
+pub struct Recording {
+    pub metadata: Option<Metadata>, // None for files written without it
+    pub start_time: Option<SystemTime>, // Wall-clock time that the times of the records count from. None if the file doesn't say, e.g., a CSV file without metadata
+    pub records: Records,
+}
+
+impl Recording {
+    /// Reads the recording at {path}, in the format its extension names, i.e., "yaml", "csv", "ndjson", "njord" for the binary format, and, if the respective feature is enabled, "msgpack", "sqlite", or "parquet".
+    /// Files compressed with gzip or zstd, like "Calibrated data.csv.zst", are decompressed on the fly. SQLite and Parquet files are read in place, so they can't be compressed
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).with_context(|| format!("Unable to open {}.", path.display()))?;
+        match format(path) {
+            Some("sqlite") => {
+                #[cfg(feature = "sqlite")]
+                return read_sqlite(path);
+                #[cfg(not(feature = "sqlite"))]
+                bail!("Enable the sqlite feature to read SQLite files.")
+            }
+            Some("parquet") => {
+                #[cfg(feature = "parquet-export")]
+                return read_parquet(file);
+                #[cfg(not(feature = "parquet-export"))]
+                bail!("Enable the parquet-export feature to read Parquet files.")
+            }
+            _ => (),
+        }
+        let reader = compression::decompress(file)?;
+        match format(path) {
+            Some("yaml" | "yml") => read_yaml(reader),
+            Some("csv") => read_csv(reader),
+            Some("ndjson" | "jsonl") => read_ndjson(reader),
+            Some("njord") => read_binary(reader),
+            #[cfg(feature = "msgpack")]
+            Some("msgpack") => read_msgpack(reader),
+            #[cfg(not(feature = "msgpack"))]
+            Some("msgpack") => bail!("Enable the msgpack feature to read MessagePack files."),
+            _ => bail!("Unknown log format of {}.", path.display()),
+        }
+    }
+}
+
+/// Extension of {path} that names the format, without the one for compression, e.g., "csv" for "Calibrated data.csv.zst"
+pub fn format(path: &Path) -> Option<&str> {
+    let name = path.file_name()?.to_str()?;
+    let name = [".gz", ".zst"].iter().fold(name, |name, suffix| {
+        name.strip_suffix(suffix).unwrap_or(name)
+    });
+    name.rsplit_once('.').map(|(_, extension)| extension)
+}
+
+/// Reads what `yaml::YamlSink` writes. YAML can't be parsed piece by piece, so the whole file is read at once. Times count from the start of the recording if there is metadata, and from the first sample otherwise
+pub fn read_yaml<R: Read>(reader: R) -> Result<Recording> {
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum File {
+        Samples(Vec<(SensorSample<Vec3D, f64>, SystemTime)>),
+        WithMetadata {
+            metadata: Box<Metadata>,
+            samples: Option<Vec<(SensorSample<Vec3D, f64>, SystemTime)>>, // None if no samples were written
+        },
+    }
+
+    let (metadata, samples) = match serde_yaml::from_reader(reader)
+        .context("Unable to parse YAML log.")?
+    {
+        File::Samples(samples) => (None, samples),
+        File::WithMetadata { metadata, samples } => (Some(*metadata), samples.unwrap_or_default()),
+    };
+    let start = metadata
+        .as_ref()
+        .map(|metadata: &Metadata| metadata.start_time)
+        .or_else(|| samples.first().map(|(_, time)| *time));
+    Ok(Recording {
+        start_time: start,
+        metadata,
+        records: Box::new(samples.into_iter().map(move |(sample, time)| {
+            let start = start.unwrap_or(time);
+            Ok((sample, time.duration_since(start).unwrap_or_default()))
+        })),
+    })
+}
+
+/// Reads what `csv::CsvSink` writes, with any columns, as long as there are all of acceleration and angular velocity, and a time.
+/// Times count from the start of the recording if there is a "time_s" column, and from the first sample otherwise
+pub fn read_csv<R: Read + 'static>(reader: R) -> Result<Recording> {
+    use super::csv::Column;
+
+    let mut lines = BufReader::new(reader).lines();
+    // Metadata comes as comment lines above the header line
+    let mut comments = String::new();
+    let header = loop {
+        let line = lines.next().context("CSV log is empty.")??;
+        match line.strip_prefix('#') {
+            Some(comment) => {
+                comments.push_str(comment.strip_prefix(' ').unwrap_or(comment));
+                comments.push('\n');
+            }
+            None => break line,
+        }
+    };
+    let metadata = (!comments.is_empty())
+        .then(|| serde_yaml::from_str(&comments).context("Unable to parse CSV log metadata."))
+        .transpose()?;
+    let names = header.trim().split(',').collect::<Vec<_>>();
+    let index = |column: Column| names.iter().position(|name| *name == column.name());
+    let values = [
+        Column::AccelerationX,
+        Column::AccelerationY,
+        Column::AccelerationZ,
+        Column::AngularVelocityX,
+        Column::AngularVelocityY,
+        Column::AngularVelocityZ,
+    ]
+    .map(|column| {
+        index(column).with_context(|| format!("CSV log has no {} column.", column.name()))
+    });
+    let values = values.into_iter().collect::<Result<Vec<_>>>()?;
+    let time = match (index(Column::Time), index(Column::UnixTime)) {
+        (Some(time), _) => (time, false),
+        (None, Some(unix_time)) => (unix_time, true),
+        (None, None) => bail!("CSV log has no time column."),
+    };
+    let temperature = index(Column::Temperature);
+
+    let mut start = None;
+    let records = Box::new(
+        lines
+            .filter(|line| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+            .map(move |line| {
+                let line = line.context("Unable to read CSV log.")?;
+                let fields = line.trim().split(',').collect::<Vec<_>>();
+                let field = |index: usize| -> Result<Option<f64>> {
+                    match fields.get(index).map(|field| field.trim()) {
+                        None | Some("") => Ok(None),
+                        Some(field) => Ok(Some(field.parse().with_context(|| {
+                            format!("Invalid number {field} in CSV line: {line}")
+                        })?)),
+                    }
+                };
+                let value = |index: usize| -> Result<f64> {
+                    field(index)?.with_context(|| format!("Missing value in CSV line: {line}"))
+                };
+
+                let sample = SensorSample::new(
+                    Vec3D::new(value(values[0])?, value(values[1])?, value(values[2])?),
+                    Vec3D::new(value(values[3])?, value(values[4])?, value(values[5])?),
+                    temperature.map(field).transpose()?.flatten(),
+                );
+                let mut seconds = value(time.0)?;
+                if time.1 {
+                    seconds -= *start.get_or_insert(seconds);
+                }
+                Ok((sample, Duration::from_secs_f64(seconds.max(0.0))))
+            }),
+    );
+    Ok(Recording {
+        start_time: metadata
+            .as_ref()
+            .map(|metadata: &Metadata| metadata.start_time),
+        metadata,
+        records,
+    })
+}
+
+/// Reads what `ndjson::NdjsonSink` writes. A damaged line is reported as an error, and reading goes on after it
+pub fn read_ndjson<R: Read + 'static>(reader: R) -> Result<Recording> {
+    #[derive(serde::Deserialize)]
+    struct Start {
+        metadata: Metadata,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct Record {
+        time: f64, // [s] Since the start of the recording
+        #[serde(flatten)]
+        sample: SensorSample<Vec3D, f64>,
+    }
+
+    let mut lines = BufReader::new(reader).lines().peekable();
+    let metadata = match lines.peek() {
+        Some(Ok(line)) if line.starts_with("{\"metadata\"") => {
+            let start: Start =
+                serde_json::from_str(line).context("Unable to parse JSON log metadata.")?;
+            lines.next();
+            Some(start.metadata)
+        }
+        _ => None,
+    };
+    Ok(Recording {
+        start_time: metadata
+            .as_ref()
+            .map(|metadata: &Metadata| metadata.start_time),
+        metadata,
+        records: Box::new(
+            lines
+                .filter(|line| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+                .map(|line| {
+                    let line = line.context("Unable to read JSON log.")?;
+                    let record: Record = serde_json::from_str(&line)
+                        .with_context(|| format!("Unable to parse JSON line: {line}"))?;
+                    Ok((record.sample, Duration::from_secs_f64(record.time.max(0.0))))
+                }),
+        ),
+    })
+}
+
+/// Reads what `binary::BinaryWriter` writes. Damaged regions come as errors, and replaying carries on after them
+pub fn read_binary<R: Read + 'static>(reader: R) -> Result<Recording> {
+    let reader = BinaryReader::new(reader)?;
+    Ok(Recording {
+        metadata: Some(reader.metadata().clone()),
+        start_time: Some(reader.metadata().start_time),
+        records: Box::new(reader),
+    })
+}
+
+/// Reads what `msgpack::MessagePackSink` writes. Damaged records come as errors, and replaying carries on after them
+#[cfg(feature = "msgpack")]
+pub fn read_msgpack<R: Read + 'static>(reader: R) -> Result<Recording> {
+    let reader = super::msgpack::MessagePackReader::new(reader)?;
+    Ok(Recording {
+        metadata: Some(reader.metadata().clone()),
+        start_time: Some(reader.metadata().start_time),
+        records: Box::new(reader),
+    })
+}
+
+/// Reads the latest session of the database at {path}, as `sqlite::SqliteSink` writes it. The samples are read at once, with times counting from the start of the session
+#[cfg(feature = "sqlite")]
+pub fn read_sqlite(path: &Path) -> Result<Recording> {
+    use rusqlite::{Connection, OpenFlags};
+
+    let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("Unable to open database at {}.", path.display()))?;
+    let (session, mut metadata, filter, calibration) = connection
+        .query_row(
+            "SELECT id, start_time, sample_rate, accelerometer_range, gyroscope_range, filter, calibration, crate_version, device_model FROM sessions ORDER BY id DESC LIMIT 1",
+            [],
+            |row| {
+                let metadata = Metadata {
+                    start_time: SystemTime::UNIX_EPOCH
+                        + Duration::from_secs_f64(row.get::<_, f64>(1)?.max(0.0)),
+                    sample_rate: row.get(2)?,
+                    accelerometer_range: row.get::<_, i64>(3)? as isize,
+                    gyroscope_range: row.get::<_, i64>(4)? as isize,
+                    filter: Default::default(),
+                    calibration: Default::default(),
+                    crate_version: row.get::<_, Option<String>>(7)?.unwrap_or_default(),
+                    device_model: row.get(8)?,
+                };
+                let (filter, calibration) = (row.get::<_, String>(5)?, row.get::<_, String>(6)?);
+                Ok((row.get::<_, i64>(0)?, metadata, filter, calibration))
+            },
+        )
+        .context("Database has no session to read.")?;
+    // Stored by name, which is how the filter is serialized as well
+    metadata.filter = serde_json::from_value(serde_json::Value::String(filter))
+        .context("Unable to parse filter of session.")?;
+    metadata.calibration =
+        serde_json::from_str(&calibration).context("Unable to parse calibration of session.")?;
+    let start = metadata
+        .start_time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+
+    let mut statement = connection.prepare(
+        "SELECT time, acceleration_x, acceleration_y, acceleration_z, angular_velocity_x, angular_velocity_y, angular_velocity_z, temperature FROM samples WHERE session = ? ORDER BY time",
+    )?;
+    let records = statement
+        .query_map([session], |row| {
+            let sample = SensorSample::new(
+                Vec3D::new(
+                    row.get::<_, f64>(1)?,
+                    row.get::<_, f64>(2)?,
+                    row.get::<_, f64>(3)?,
+                ),
+                Vec3D::new(
+                    row.get::<_, f64>(4)?,
+                    row.get::<_, f64>(5)?,
+                    row.get::<_, f64>(6)?,
+                ),
+                row.get(7)?,
+            );
+            let time = row.get::<_, f64>(0)? - start;
+            Ok((sample, Duration::from_secs_f64(time.max(0.0))))
+        })?
+        .map(|record| record.context("Unable to read sample from database."))
+        .collect::<Vec<_>>();
+    Ok(Recording {
+        start_time: Some(metadata.start_time),
+        metadata: Some(metadata),
+        records: Box::new(records.into_iter()),
+    })
+}
+
+/// Reads what `parquet::ParquetSink` writes. Times count from the start of the recording, and the metadata is read from the file's key-value metadata, if it is there
+#[cfg(feature = "parquet-export")]
+pub fn read_parquet(file: File) -> Result<Recording> {
+    use parquet::{
+        file::reader::{FileReader, SerializedFileReader},
+        record::RowAccessor,
+    };
+
+    let reader = SerializedFileReader::new(file).context("Unable to open Parquet file.")?;
+    let metadata = reader
+        .metadata()
+        .file_metadata()
+        .key_value_metadata()
+        .and_then(|pairs| {
+            pairs
+                .iter()
+                .find(|pair| pair.key == super::parquet::METADATA_KEY)
+        })
+        .and_then(|pair| pair.value.as_deref())
+        .map(|metadata| {
+            serde_json::from_str::<Metadata>(metadata).context("Unable to parse Parquet metadata.")
+        })
+        .transpose()?;
+    let records = reader.into_iter().map(|row| {
+        let row = row.context("Unable to read Parquet row.")?;
+        let value = |index: usize| row.get_double(index).context("Invalid Parquet row.");
+        let sample = SensorSample::new(
+            Vec3D::new(value(2)?, value(3)?, value(4)?),
+            Vec3D::new(value(5)?, value(6)?, value(7)?),
+            row.get_double(8).ok(), // Null without a temperature
+        );
+        Ok((sample, Duration::from_secs_f64(value(0)?.max(0.0))))
+    });
+    Ok(Recording {
+        start_time: metadata
+            .as_ref()
+            .map(|metadata: &Metadata| metadata.start_time),
+        metadata,
+        records: Box::new(records),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::{
+        binary::BinaryWriter,
+        csv::{Column, CsvSink},
+        ndjson::NdjsonSink,
+        yaml::YamlSink,
+        Metadata, Sink, Timebase,
+    };
+
+    fn samples(timebase: &Timebase) -> Vec<(SensorSample<Vec3D, f64>, Instant)> {
+        (0..5)
+            .map(|n| {
+                let temperature = (n % 2 == 0).then_some(21.5);
+                (
+                    SensorSample::new(Vec3D::new(0, n, 1), Vec3D::new(0.5, 0, -n), temperature),
+                    timebase.start + Duration::from_millis(20 * n as u64),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_formats() {
+        let timebase = Timebase::now();
+        let samples = samples(&timebase);
+        let metadata = Metadata {
+            sample_rate: 50.0,
+            filter: Default::default(),
+            ..Metadata::for_tests(&timebase)
+        };
+
+        for with_metadata in [false, true] {
+            let (mut yaml, mut csv, mut ndjson) = if with_metadata {
+                (
+                    YamlSink::with_metadata(Vec::new(), &metadata, timebase).unwrap(),
+                    CsvSink::with_metadata(Vec::new(), &Column::ALL, &metadata, timebase).unwrap(),
+                    NdjsonSink::with_metadata(Vec::new(), &metadata, timebase).unwrap(),
+                )
+            } else {
+                (
+                    YamlSink::new(Vec::new(), timebase),
+                    CsvSink::new(Vec::new(), &Column::ALL, timebase).unwrap(),
+                    NdjsonSink::new(Vec::new(), timebase),
+                )
+            };
+            let mut binary = BinaryWriter::new(Vec::new(), &metadata, timebase).unwrap();
+            for chunk in samples.chunks(2) {
+                yaml.write(chunk).unwrap();
+                csv.write(chunk).unwrap();
+                ndjson.write(chunk).unwrap();
+                binary.write(chunk).unwrap();
+            }
+            let cursor = std::io::Cursor::new;
+            for (recording, has_metadata) in [
+                (read_yaml(cursor(yaml.into_inner())).unwrap(), with_metadata),
+                (read_csv(cursor(csv.into_inner())).unwrap(), with_metadata),
+                (
+                    read_ndjson(cursor(ndjson.into_inner())).unwrap(),
+                    with_metadata,
+                ),
+                (read_binary(cursor(binary.into_inner())).unwrap(), true),
+            ] {
+                match has_metadata {
+                    true => assert_eq!(recording.metadata.as_ref(), Some(&metadata)),
+                    false => assert!(recording.metadata.is_none()),
+                }
+                let replay = Replay::new(recording.records, Pace::AsFastAsPossible);
+                let start = replay.start();
+                let read = replay.collect::<Result<Vec<_>>>().unwrap();
+                assert_eq!(read.len(), samples.len());
+                for ((sample, instant), (original, original_instant)) in read.iter().zip(&samples) {
+                    let time = instant.duration_since(start).as_secs_f64();
+                    assert!(
+                        (time - timebase.elapsed(*original_instant).as_secs_f64()).abs() < 1e-6
+                    );
+                    assert!(sample
+                        .acceleration()
+                        .approx_eq(&original.acceleration(), 1e-6));
+                    assert!(sample
+                        .angular_velocity()
+                        .approx_eq(&original.angular_velocity(), 1e-6));
+                    assert_eq!(sample.temperature(), original.temperature());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_real_time() {
+        let timebase = Timebase::now();
+        let records = samples(&timebase)
+            .into_iter()
+            .map(move |(sample, instant)| Ok((sample, timebase.elapsed(instant))));
+        let mut replay = Replay::new(Box::new(records), Pace::RealTime);
+
+        let (first, _) = replay.next_sample(None);
+        assert!(first.unwrap().is_some());
+        // The next sample is 20 ms later
+        let (timeout, _) = replay.next_sample(Some(Duration::from_millis(1)));
+        assert!(timeout.unwrap().is_none());
+        let mut count = 1;
+        while !replay.is_finished() {
+            if let (Ok(Some(_)), instant) = replay.next_sample(None) {
+                assert!(Instant::now() >= instant);
+                count += 1;
+            }
+        }
+        assert_eq!(count, 5);
+        assert!(replay.start().elapsed() >= Duration::from_millis(80));
+    }
+}
@@ -0,0 +1,246 @@
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{Context, Result};
+
+use super::Sink;
+use crate::clock;
+
+/// When `RotatingSink` starts a new file, and how many it keeps. Without limits, everything goes into one file
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RotationPolicy {
+    pub max_size: Option<u64>, // [bytes] Start a new file once the current one is this large
+    pub max_age: Option<Duration>, // Start a new file once the current one is this old
+    pub retention: Option<usize>, // Number of files to keep, deleting the oldest ones. Files from earlier runs count as well
+}
+
+/// Spreads samples over several files, numbered in the order they were started, so that recordings that run for days don't end up in one huge file.
+/// A new file is started before writing a chunk of samples, so file sizes can exceed {max_size} by one chunk, plus what the sink buffers
+pub struct RotatingSink<S> {
+    directory: PathBuf,
+    prefix: String,
+    extension: String,
+    policy: RotationPolicy,
+    create: Box<dyn FnMut(File) -> Result<S>>,
+    sink: S,
+    file: File, // Shares the current file with {sink}, for checking its size and syncing it
+    path: PathBuf,
+    opened: Instant,
+}
+
+impl<S> RotatingSink<S> {
+    /// Writes to files named "{prefix}_{sequence number}_{UTC time}.{extension}" in {directory}, e.g., "Calibrated data_000042_2022-03-14_09-26-53.csv".
+    /// Retention goes by the sequence number, which carries on from the files there are already, since the wall-clock time may be off
+    /// {create} makes the sink for every new file, which then writes its own header, if it has one
+    pub fn new<P: AsRef<Path>>(
+        directory: P,
+        prefix: &str,
+        extension: &str,
+        policy: RotationPolicy,
+        mut create: impl FnMut(File) -> Result<S> + 'static,
+    ) -> Result<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        let (file, path) = new_file(&directory, prefix, extension)?;
+        let sink = create(file.try_clone()?)?;
+        let rotating = Self {
+            directory,
+            prefix: prefix.to_string(),
+            extension: extension.to_string(),
+            policy,
+            create: Box::new(create),
+            sink,
+            file,
+            path,
+            opened: Instant::now(),
+        };
+        rotating.apply_retention()?;
+        Ok(rotating)
+    }
+
+    /// File that samples are currently written to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn due(&self) -> Result<bool> {
+        let too_old = self
+            .policy
+            .max_age
+            .is_some_and(|max_age| self.opened.elapsed() >= max_age);
+        let too_large = match self.policy.max_size {
+            Some(max_size) => self.file.metadata()?.len() >= max_size,
+            None => false,
+        };
+        Ok(too_old || too_large)
+    }
+
+    fn rotate<T>(&mut self) -> Result<()>
+    where
+        S: Sink<T>,
+    {
+        Sink::<T>::sync(self)?;
+        let (file, path) = new_file(&self.directory, &self.prefix, &self.extension)?;
+        // The previous sink is dropped here, which lets it finish its file, e.g., the footer of a Parquet file
+        self.sink = (self.create)(file.try_clone()?)?;
+        self.file = file;
+        self.path = path;
+        self.opened = Instant::now();
+        self.apply_retention()
+    }
+
+    fn apply_retention(&self) -> Result<()> {
+        let Some(retention) = self.policy.retention else {
+            return Ok(());
+        };
+        let files = log_files(&self.directory, &self.prefix, &self.extension)?;
+        let excess = files.len().saturating_sub(retention.max(1));
+        for (_, path) in &files[..excess] {
+            std::fs::remove_file(path)
+                .with_context(|| format!("Unable to delete old log file {}.", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+impl<T, S: Sink<T>> Sink<T> for RotatingSink<S> {
+    fn write(&mut self, samples: &[(T, Instant)]) -> Result<()> {
+        if self.due()? {
+            self.rotate::<T>()?;
+        }
+        self.sink.write(samples)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.sink.flush()
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.sink.flush()?;
+        self.file
+            .sync_data()
+            .with_context(|| format!("Unable to sync {}.", self.path.display()))
+    }
+}
+
+// Files named like the ones `RotatingSink` creates, oldest first, with their sequence number.
+// The wall clock can't tell which is older, since it may be off until NTP has synchronized it, e.g., at 1970 after booting without a real-time clock
+fn log_files(directory: &Path, prefix: &str, extension: &str) -> Result<Vec<(u64, PathBuf)>> {
+    let mut files = std::fs::read_dir(directory)
+        .with_context(|| format!("Unable to list log files in {}.", directory.display()))?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = path.file_name()?.to_str()?;
+            let (sequence, _timestamp) = name
+                .strip_prefix(prefix)?
+                .strip_prefix('_')?
+                .strip_suffix(extension)?
+                .strip_suffix('.')?
+                .split_once('_')?;
+            Some((sequence.parse().ok()?, path))
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    Ok(files)
+}
+
+// Creates a file with the next sequence number, after the highest one in {directory}
+fn new_file(directory: &Path, prefix: &str, extension: &str) -> Result<(File, PathBuf)> {
+    let timestamp = timestamp(SystemTime::now());
+    let mut sequence = log_files(directory, prefix, extension)?
+        .last()
+        .map_or(0, |(sequence, _)| sequence + 1);
+    loop {
+        let path = directory.join(format!("{prefix}_{sequence:06}_{timestamp}.{extension}"));
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => return Ok((file, path)),
+            Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => sequence += 1,
+            Err(error) => {
+                return Err(error)
+                    .with_context(|| format!("Unable to create log file {}.", path.display()))
+            }
+        }
+    }
+}
+
+// UTC as "YYYY-MM-DD_hh-mm-ss", which is valid in file names everywhere
+pub(super) fn timestamp(time: SystemTime) -> String {
+    let ((year, month, day), _) = clock::to_civil(time);
+    let seconds = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        % 86_400;
+    format!(
+        "{year:04}-{month:02}-{day:02}_{:02}-{:02}-{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gy521::SensorSample,
+        logging::{
+            csv::{Column, CsvSink},
+            Timebase,
+        },
+        math::Vec3D,
+    };
+
+    #[test]
+    fn test_timestamp() {
+        let time = |seconds| SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+        assert_eq!(timestamp(time(0)), "1970-01-01_00-00-00");
+        assert_eq!(timestamp(time(951_825_599)), "2000-02-29_11-59-59");
+        assert_eq!(timestamp(time(1_647_249_013)), "2022-03-14_09-10-13");
+    }
+
+    #[test]
+    fn test_rotation() {
+        let directory = std::env::temp_dir().join(format!("njord_rotation_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let timebase = Timebase::now();
+        let policy = RotationPolicy {
+            max_size: Some(100),
+            retention: Some(2),
+            ..Default::default()
+        };
+        // From an earlier run, with the wall clock ahead, which doesn't make it any newer
+        let earlier = directory.join("test_000007_2099-01-01_00-00-00.csv");
+        std::fs::write(&earlier, "").unwrap();
+        let mut sink = RotatingSink::new(&directory, "test", "csv", policy, move |file| {
+            CsvSink::new(file, &Column::ALL, timebase)
+        })
+        .unwrap();
+        let sample = SensorSample::new(Vec3D::new(0, 0, 1), Vec3D::default(), Some(20.0));
+        let first = sink.path().to_path_buf();
+        for _ in 0..4 {
+            sink.write(&[(sample, timebase.start)]).unwrap(); // Every line is longer than 100 bytes with the header
+        }
+        sink.sync().unwrap();
+
+        let mut files = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(files.len(), 2);
+        assert!(!files.contains(&first) && !files.contains(&earlier));
+        assert!(first.to_string_lossy().contains("test_000008_"));
+        assert_eq!(files.last(), Some(&sink.path().to_path_buf()));
+        // Every file starts with its own header
+        let contents = std::fs::read_to_string(sink.path()).unwrap();
+        assert!(contents.starts_with("time_s,"));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
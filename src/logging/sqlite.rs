@@ -0,0 +1,224 @@
+use std::{path::Path, time::Instant};
+
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection};
+
+use super::{Metadata, Sink, Timebase};
+use crate::{gy521::SensorSample, math::Vec3D};
+
+// Times are wall-clock times in [s] since 1970-01-01 00:00 UTC, so that they can be queried with SQLite's date and time functions, e.g., `datetime(time, 'unixepoch')`.
+// Every recording is a session. Samples and errors refer to the session they were recorded in
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS sessions (
+        id INTEGER PRIMARY KEY,
+        start_time REAL NOT NULL,
+        sample_rate REAL NOT NULL,
+        accelerometer_range INTEGER NOT NULL,
+        gyroscope_range INTEGER NOT NULL,
+        filter TEXT NOT NULL,
+        calibration TEXT NOT NULL,
+        crate_version TEXT,
+        device_model TEXT
+    );
+    CREATE TABLE IF NOT EXISTS samples (
+        session INTEGER NOT NULL REFERENCES sessions (id),
+        time REAL NOT NULL,
+        acceleration_x REAL NOT NULL,
+        acceleration_y REAL NOT NULL,
+        acceleration_z REAL NOT NULL,
+        angular_velocity_x REAL NOT NULL,
+        angular_velocity_y REAL NOT NULL,
+        angular_velocity_z REAL NOT NULL,
+        temperature REAL,
+        outlier INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS samples_time ON samples (time);
+    CREATE TABLE IF NOT EXISTS errors (
+        session INTEGER NOT NULL REFERENCES sessions (id),
+        time REAL NOT NULL,
+        message TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS errors_time ON errors (time);
+";
+
+/// Writes samples and errors into a SQLite database, for querying long recordings with SQL, e.g., the largest acceleration within an hour.
+/// Acceleration is in [g], angular velocity in [degree/s], and temperature in [degree C]. The calibration is stored as JSON
+pub struct SqliteSink {
+    connection: Connection,
+    timebase: Timebase,
+    session: i64,
+}
+
+impl SqliteSink {
+    /// Opens the database at {path}, creating it if needed, and starts a new session in it, described by {metadata}
+    pub fn open<P: AsRef<Path>>(path: P, metadata: &Metadata, timebase: Timebase) -> Result<Self> {
+        let path = path.as_ref();
+        let connection = Connection::open(path)
+            .with_context(|| format!("Unable to open database at {}.", path.display()))?;
+        Self::new(connection, metadata, timebase)
+    }
+
+    /// Like `open`, with an existing connection, e.g., to an in-memory database
+    pub fn new(connection: Connection, metadata: &Metadata, timebase: Timebase) -> Result<Self> {
+        connection
+            .execute_batch(SCHEMA)
+            .context("Unable to create database tables.")?;
+        // Databases from before the crate version and device model were recorded lack their columns
+        for column in ["crate_version", "device_model"] {
+            let exists = connection
+                .prepare("SELECT 1 FROM pragma_table_info('sessions') WHERE name = ?")?
+                .exists([column])?;
+            if !exists {
+                connection
+                    .execute(
+                        &format!("ALTER TABLE sessions ADD COLUMN {column} TEXT"),
+                        [],
+                    )
+                    .context("Unable to update database tables.")?;
+            }
+        }
+        connection
+            .execute(
+                "INSERT INTO sessions (start_time, sample_rate, accelerometer_range, gyroscope_range, filter, calibration, crate_version, device_model) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+                params![
+                    unix_time(&timebase, timebase.start),
+                    metadata.sample_rate,
+                    metadata.accelerometer_range as i64,
+                    metadata.gyroscope_range as i64,
+                    format!("{:?}", metadata.filter),
+                    serde_json::to_string(&metadata.calibration)?,
+                    metadata.crate_version,
+                    metadata.device_model,
+                ],
+            )
+            .context("Unable to start session.")?;
+        let session = connection.last_insert_rowid();
+        Ok(Self {
+            connection,
+            timebase,
+            session,
+        })
+    }
+
+    pub fn session(&self) -> i64 {
+        self.session
+    }
+
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Records that reading a sample at {instant} failed with {error}
+    pub fn write_error(&mut self, error: &anyhow::Error, instant: Instant) -> Result<()> {
+        self.connection
+            .prepare_cached("INSERT INTO errors (session, time, message) VALUES (?, ?, ?)")?
+            .execute(params![
+                self.session,
+                unix_time(&self.timebase, instant),
+                format!("{error:#}"),
+            ])
+            .context("Unable to write error to database.")?;
+        Ok(())
+    }
+}
+
+fn unix_time(timebase: &Timebase, instant: Instant) -> f64 {
+    timebase
+        .system_time(instant)
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+impl Sink for SqliteSink {
+    fn write(&mut self, samples: &[(SensorSample<Vec3D, f64>, Instant)]) -> Result<()> {
+        // One transaction per chunk. Committing every sample on its own is orders of magnitude slower
+        let transaction = self.connection.transaction()?;
+        {
+            let mut statement = transaction
+                .prepare_cached("INSERT INTO samples VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")?;
+            for (sample, instant) in samples {
+                let (acceleration, angular_velocity) =
+                    (sample.acceleration(), sample.angular_velocity());
+                statement.execute(params![
+                    self.session,
+                    unix_time(&self.timebase, *instant),
+                    acceleration.x,
+                    acceleration.y,
+                    acceleration.z,
+                    angular_velocity.x,
+                    angular_velocity.y,
+                    angular_velocity.z,
+                    sample.temperature(),
+                    sample.is_outlier(),
+                ])?;
+            }
+        }
+        transaction
+            .commit()
+            .context("Unable to write samples to database.")
+    }
+
+    // Every chunk is committed right away
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::gy521::Filter;
+
+    #[test]
+    fn test_sqlite_sink() {
+        let timebase = Timebase::now();
+        let metadata = Metadata {
+            sample_rate: 100.0,
+            filter: Filter::BwAc44HzBwGy42Hz,
+            device_model: Some("Raspberry Pi 3 Model B".to_string()),
+            ..Metadata::for_tests(&timebase)
+        };
+        let mut sink =
+            SqliteSink::new(Connection::open_in_memory().unwrap(), &metadata, timebase).unwrap();
+        let samples = (0..10)
+            .map(|n| {
+                (
+                    SensorSample::new(Vec3D::new(0, 0, n), Vec3D::default(), None),
+                    timebase.start + Duration::from_millis(10 * n as u64),
+                )
+            })
+            .collect::<Vec<_>>();
+        sink.write(&samples).unwrap();
+        sink.write_error(&anyhow::anyhow!("Timeout"), timebase.start)
+            .unwrap();
+
+        let start = unix_time(&timebase, timebase.start);
+        let (count, maximum): (i64, f64) = sink
+            .connection()
+            .query_row(
+                "SELECT COUNT(*), MAX(acceleration_z) FROM samples WHERE session = ? AND time < ?",
+                params![sink.session(), start + 0.045],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((count, maximum), (5, 4.0));
+        let filter: String = sink
+            .connection()
+            .query_row("SELECT filter FROM sessions", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(filter, "BwAc44HzBwGy42Hz");
+        let device_model: String = sink
+            .connection()
+            .query_row("SELECT device_model FROM sessions", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(device_model, "Raspberry Pi 3 Model B");
+        let errors: i64 = sink
+            .connection()
+            .query_row("SELECT COUNT(*) FROM errors", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(errors, 1);
+    }
+}
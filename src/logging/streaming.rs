@@ -0,0 +1,137 @@
+use std::{
+    fs::File,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+
+use super::Sink;
+use crate::{gy521::SensorSample, math::Vec3D};
+
+/// Hands samples to a sink while recording, instead of holding all of them until the end, so that a crash only loses the latest few.
+/// Samples are written in batches of {batch_size}, and each batch is flushed to the operating system right away.
+/// With `enable_sync`, the file is also synced to disk periodically, so that samples survive power loss too. Call `flush` at the end, which dropping does as well, ignoring errors
+pub struct StreamingWriter<S: Sink<T>, T = SensorSample<Vec3D, f64>> {
+    sink: S,
+    batch: Vec<(T, Instant)>,
+    batch_size: usize,
+    count: usize,                           // Samples pushed in total
+    sync: Option<(Option<File>, Duration)>, // File that {sink} writes to, if the sink doesn't sync by itself, and how often to sync
+    last_sync: Instant,
+}
+
+impl<T, S: Sink<T>> StreamingWriter<S, T> {
+    /// A {batch_size} of 0 counts as 1
+    pub fn new(sink: S, batch_size: usize) -> Self {
+        let batch_size = batch_size.max(1);
+        Self {
+            sink,
+            batch: Vec::with_capacity(batch_size),
+            batch_size,
+            count: 0,
+            sync: None,
+            last_sync: Instant::now(),
+        }
+    }
+
+    /// Starts syncing to disk at least every {interval}. Syncing wears SD cards, so the interval shouldn't be too short.
+    /// {file} is the one that the sink writes to. Sinks that manage their files themselves, like `rotation::RotatingSink`, sync without it. See `Sink::sync`
+    pub fn enable_sync(&mut self, file: Option<&File>, interval: Duration) -> Result<()> {
+        let file = file
+            .map(File::try_clone)
+            .transpose()
+            .context("Unable to share log file for syncing.")?;
+        self.sync = Some((file, interval));
+        Ok(())
+    }
+
+    pub fn push(&mut self, sample: T, instant: Instant) -> Result<()> {
+        self.batch.push((sample, instant));
+        self.count += 1;
+        if self.batch.len() >= self.batch_size {
+            self.write_batch()?;
+        }
+        if self
+            .sync
+            .as_ref()
+            .is_some_and(|(_, interval)| self.last_sync.elapsed() >= *interval)
+        {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes everything pushed so far, and syncs it to disk if syncing is enabled
+    pub fn flush(&mut self) -> Result<()> {
+        self.write_batch()?;
+        if let Some((file, _)) = &self.sync {
+            self.sink.sync()?;
+            if let Some(file) = file {
+                file.sync_data().context("Unable to sync log file.")?;
+            }
+            self.last_sync = Instant::now();
+        }
+        Ok(())
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    fn write_batch(&mut self) -> Result<()> {
+        if !self.batch.is_empty() {
+            self.sink.write(&self.batch)?;
+            self.batch.clear();
+        }
+        self.sink.flush()
+    }
+}
+
+impl<T, S: Sink<T>> Drop for StreamingWriter<S, T> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::{csv::Column, csv::CsvSink, Timebase};
+
+    #[test]
+    fn test_batches() {
+        let timebase = Timebase::now();
+        let sink = CsvSink::new(Vec::new(), &[Column::AccelerationZ], timebase).unwrap();
+        let mut writer = StreamingWriter::new(sink, 3);
+        let sample = SensorSample::new(Vec3D::new(0, 0, 1), Vec3D::default(), None);
+        let lines = |writer: &StreamingWriter<CsvSink<Vec<u8>>>| {
+            writer
+                .sink()
+                .get_ref()
+                .iter()
+                .filter(|&&byte| byte == b'\n')
+                .count()
+        };
+        for _ in 0..4 {
+            writer.push(sample, timebase.start).unwrap();
+        }
+        assert_eq!(lines(&writer), 1 + 3); // Header and the first batch
+        writer.flush().unwrap();
+        assert_eq!(lines(&writer), 1 + 4);
+        assert_eq!(writer.count(), 4);
+
+        let path = std::env::temp_dir().join(format!("njord_streaming_{}", std::process::id()));
+        let file = File::create(&path).unwrap();
+        let sink = CsvSink::new(file.try_clone().unwrap(), &Column::ALL, timebase).unwrap();
+        let mut writer = StreamingWriter::new(sink, 100);
+        writer.enable_sync(Some(&file), Duration::ZERO).unwrap();
+        writer.push(sample, Instant::now()).unwrap(); // Synced right away, despite the batch not being full
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+        drop(writer);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,82 @@
+use std::{io::Write, time::Instant};
+
+use anyhow::{Context, Result};
+
+use super::{Metadata, Sink, Timebase};
+use crate::{gy521::SensorSample, math::Vec3D};
+
+/// Writes samples as one YAML sequence of (sample, wall-clock time) pairs, the same as serializing all of them at once
+pub struct YamlSink<W: Write> {
+    writer: W,
+    timebase: Timebase,
+}
+
+impl<W: Write> YamlSink<W> {
+    pub fn new(writer: W, timebase: Timebase) -> Self {
+        Self { writer, timebase }
+    }
+
+    /// Like `new`, with {metadata} in front. The file is then a mapping of "metadata" and "samples", instead of a sequence of samples
+    pub fn with_metadata(mut writer: W, metadata: &Metadata, timebase: Timebase) -> Result<Self> {
+        #[derive(serde::Serialize)]
+        struct Start<'a> {
+            metadata: &'a Metadata,
+        }
+
+        let yaml = serde_yaml::to_string(&Start { metadata })?;
+        // The samples follow as a sequence at the same indentation, which YAML allows for mapping values
+        writer
+            .write_all(yaml.as_bytes())
+            .and_then(|_| writer.write_all(b"\nsamples:\n"))
+            .context("Unable to write YAML metadata.")?;
+        Ok(Self::new(writer, timebase))
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Sink for YamlSink<W> {
+    fn write(&mut self, samples: &[(SensorSample<Vec3D, f64>, Instant)]) -> Result<()> {
+        // An empty chunk would be written as [], which breaks up the sequence
+        if samples.is_empty() {
+            return Ok(());
+        }
+        let samples = samples
+            .iter()
+            .map(|(sample, instant)| (sample, self.timebase.system_time(*instant)))
+            .collect::<Vec<_>>();
+        let yaml = serde_yaml::to_string(&samples)?;
+        // Without the document start marker, chunks written one after another form one sequence
+        self.writer
+            .write_all(yaml.trim_start_matches("---\n").as_bytes())
+            .context("Unable to write YAML.")
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush().context("Unable to flush YAML output.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunks_form_one_sequence() {
+        let timebase = Timebase::now();
+        let sample = SensorSample::new(Vec3D::new(0, 0, 1), Vec3D::default(), Some(20.0));
+        let mut sink = YamlSink::new(Vec::new(), timebase);
+        sink.write(&[(sample, timebase.start)]).unwrap();
+        sink.write(&[]).unwrap();
+        sink.write(&[(sample, timebase.start), (sample, timebase.start)])
+            .unwrap();
+        let samples: Vec<serde_yaml::Value> = serde_yaml::from_slice(&sink.into_inner()).unwrap();
+        assert_eq!(samples.len(), 3);
+    }
+}
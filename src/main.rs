@@ -5,36 +5,104 @@ use std::{
     time::{Duration, Instant},
 };
 
-use anyhow::Result;
-use njord::{gy521, utilites};
-use rppal::{gpio::Gpio, i2c::I2c, system::DeviceInfo};
+use anyhow::{Context, Result};
+use njord::{
+    actuators::led::{Pattern, StatusLed},
+    clock::ds3231,
+    display,
+    gy521::{self, SampleSource},
+    logging::{self, Sink},
+    power,
+    sensors::{self, ImuSensor},
+    telemetry, utilites,
+};
+use rppal::{
+    gpio::{Gpio, Trigger},
+    i2c::I2c,
+    system::DeviceInfo,
+};
 
 // BCM pin numbering
 const GPIO_LED: u8 = 21;
 const GPIO_INTERRUPT: u8 = 4;
+const GPIO_MARKER_BUTTON: u8 = 17; // Pressing it connects the pin to ground
+
+const LED_CODE_SENSOR: u8 = 2; // Flashes of the status LED while reading the sensor fails
+const LED_ERROR_HOLD: Duration = Duration::from_secs(5); // How long the status LED keeps showing an error after the last one
+
+const CALIBRATION_FILE: &str = "Data/Calibration.yaml";
 
 fn main() -> Result<()> {
+    // "njord convert <input> <output>" converts a recording to another format, e.g., a YAML archive to CSV, without touching the sensor
+    let arguments = std::env::args().collect::<Vec<_>>();
+    if let [_, command, input, output] = arguments.as_slice() {
+        if command == "convert" {
+            let conversion = logging::convert::convert(input, output)?;
+            println!("Converted {} samples.", conversion.samples);
+            for error in &conversion.skipped {
+                println!("Skipped: {error:#}");
+            }
+            return Ok(());
+        }
+    }
+
     /*********
      * Setup *
      *********/
-    let (tx, kill_signal) = crossbeam_channel::unbounded();
-    ctrlc::set_handler(move || tx.send(()).expect("Unable to send signal on channel."))
+    let cancellation = utilites::CancellationToken::default();
+    let handler_cancellation = cancellation.clone();
+    ctrlc::set_handler(move || handler_cancellation.cancel())
         .expect("Unable to set Ctrl-C handler");
 
     let mut i2c = I2c::new()?;
+    // Steady while starting up
+    let mut led = StatusLed::new(Gpio::new()?.get(GPIO_LED)?.into_output(), Pattern::On)?;
+
+    // With "--rtc", the wall clock is set from a DS3231 real-time clock before anything is timestamped, unless NTP has set it already, in which case the real-time clock is set from it instead
+    let rtc = std::env::args().any(|argument| argument == "--rtc");
+    // A GY-521 with AD0 pulled high answers at "--imu-address=0x69" instead. That is needed next to the real-time clock, which is fixed at 0x68
+    let imu_address = match std::env::args().find_map(|argument| {
+        argument
+            .strip_prefix("--imu-address=")
+            .map(|address| address.to_string())
+    }) {
+        Some(address) => u16::from_str_radix(address.trim_start_matches("0x"), 16)
+            .with_context(|| format!("Invalid IMU address: {address}"))?,
+        None => 0x68, // AD0 low
+    };
+    anyhow::ensure!(
+        !rtc || imu_address != ds3231::I2C_ADDRESS,
+        "The real-time clock takes address {:#04x}, so the IMU needs AD0 pulled high, and \"--imu-address=0x69\".",
+        ds3231::I2C_ADDRESS
+    );
+    if rtc {
+        let mut rtc_i2c = I2c::new()?;
+        let mut real_time_clock = ds3231::DS3231::default();
+        let synchronization = real_time_clock.initialize(&mut rtc_i2c).and_then(|_| {
+            let ntp_synchronized = njord::clock::ntp_synchronized().unwrap_or(false);
+            real_time_clock.synchronize(&rtc_i2c, ntp_synchronized)
+        });
+        match synchronization {
+            Ok(ds3231::Synchronization::RtcFromSystem) => {
+                println!("Set the real-time clock from the NTP synchronized wall clock.")
+            }
+            Ok(ds3231::Synchronization::SystemFromRtc { offset }) => {
+                println!("Set the wall clock from the real-time clock, by {offset:.1} s.")
+            }
+            Err(error) => println!("Unable to synchronize with the real-time clock: {error:#}"),
+        }
+    }
 
     let mut sensor = gy521::GY521::new(
-        Default::default(),
-        Default::default(),
+        gy521::Variant::MPU6050.register_map(),
         gy521::PowerSettings {
             clock_source: gy521::ClockSource::GyroX, // Use gyroscope as clock source for higher accuracy
             ..Default::default()
         },
-        0x68,
+        imu_address,
         4e5,
         Default::default(),
         Default::default(),
-        Default::default(),
         gy521::Configuration {
             filter: gy521::Filter::BwAc184HzBwGy188Hz,
             ..Default::default()
@@ -48,120 +116,981 @@ fn main() -> Result<()> {
             open: false, // Something with internal pull-push stuff for the sensor?
             ..Default::default()
         },
-    );
+    )?;
 
     sensor.initialize(&mut i2c)?;
     thread::sleep(Duration::SECOND); // Let stuff start up
-    let mut calibration_status_count = 0;
-    let calibration_duration = Duration::from_secs(5 * 60);
-    let status_period = Duration::from_secs(30);
-    sensor.calibrate(
-        10_000,
-        Duration::from_millis(100),
-        calibration_duration,
-        &mut i2c,
-        &kill_signal,
-        status_period,
-        || {
-            let expected_status_updates =
-                calibration_duration.as_micros() / status_period.as_micros();
-            println!("Status update: \t{calibration_status_count}/{expected_status_updates}");
-            calibration_status_count += 1;
-        },
-    );
 
-    let mut led = Gpio::new()?.get(GPIO_LED)?.into_output();
-    let mut blink_count = 0;
-    let blink_period = Duration::from_millis(800);
+    // Calibrating takes minutes, so a previous calibration is reused if there is one. Delete the file to recalibrate
+    if std::path::Path::new(CALIBRATION_FILE).exists() {
+        println!("Using calibration from {CALIBRATION_FILE}");
+        sensor.apply_calibration(&gy521::calibration::CalibrationData::load(
+            CALIBRATION_FILE,
+        )?);
+    } else {
+        led.set(Pattern::Calibrating);
+        let calibration = sensor.calibrate(
+            10_000,
+            Duration::from_millis(100),
+            Duration::from_secs(5 * 60),
+            &mut i2c,
+            &cancellation,
+            &mut ConsoleObserver::new(10),
+        );
+        // Ctrl-C during calibration stops before the recording even starts, with nothing saved, so the next start calibrates anew
+        if cancellation.is_cancelled() {
+            println!("Calibration cancelled.");
+            return Ok(());
+        }
+        let calibration = calibration?;
+        println!("Calibration report: {:#?}", calibration.report);
+        calibration.save(CALIBRATION_FILE)?; // The report is saved along with it, for comparing sensor units later on
+    }
+
+    // The default calibration assumes the sensor to be lying flat. Turning it to all six sides calibrates the accelerometer's scale as well
+    if std::env::args().any(|argument| argument == "--six-position") {
+        led.set(Pattern::Calibrating);
+        let calibration = sensor.calibrate_six_position(
+            &mut i2c,
+            Duration::from_secs(10),
+            &cancellation,
+            &mut ConsoleObserver::new(6),
+            |orientation| {
+                println!(
+                    "Place the sensor with its {orientation}, keep it still, and press enter."
+                );
+                std::io::stdin().read_line(&mut String::new())?;
+                Ok(())
+            },
+        )?;
+        calibration.save(CALIBRATION_FILE)?;
+    }
+
+    led.set(Pattern::Heartbeat);
+    let mut last_error: Option<Instant> = None;
+    let mut report_count = 0;
+    let report_period = Duration::from_millis(800);
 
     let memory_capacity = 5000;
-    let mut samples =
-        utilites::Memory::<(gy521::SensorSample<njord::math::Vec3D, f64>, Instant)>::new(
-            memory_capacity,
-        );
     let mut errors = utilites::Memory::new(memory_capacity);
 
-    println!("Blinking an LED on a {}.", DeviceInfo::new()?.model());
+    println!("Running on a {}.", DeviceInfo::new()?.model());
     println!("I2C clock frequency: {} Hz", i2c.clock_speed().unwrap());
 
-    let sampling_period = Duration::from_millis(100); // Time between stored samples
-    let interrupt_timeout = Duration::from_secs_f64(1.5 / sensor.sample_rate); // Timeout of more than one sampling period (in case of minor delay?), but less than two sampling periods
-    let mut sample_count = 0;
-    let sampling_begin = std::time::SystemTime::now();
-    let clock = Instant::now();
+    sensor.enable_interrupt_timing(memory_capacity);
+    sensor.enable_bias_estimation(sensor.sample_rate as usize); // Stationary windows of one second
+
+    let mut interrupt_timeout = Duration::from_secs_f64(1.5 / sensor.sample_rate); // Timeout of more than one sampling period (in case of minor delay?), but less than two sampling periods
+    let timebase = logging::Timebase::now();
+    let clock = timebase.start;
+    // A stored sample every 100 ms
+    let mut sampler = sensors::Sampler::new(Duration::from_millis(100), clock);
+    // Without a real-time clock, the Pi's wall clock is only right once NTP has synchronized it, possibly in the middle of the recording. Steps of it are marked, for lining up recordings of several devices
+    let mut clock_sync = njord::clock::ClockSync::new(timebase, Default::default());
+    match njord::clock::ntp_synchronized() {
+        Ok(true) => {}
+        Ok(false) => println!("The wall clock isn't synchronized by NTP yet."),
+        Err(error) => println!("Unable to check wall clock synchronization: {error:#}"),
+    }
+    // YAML by default. CSV loads more easily into pandas or Excel, NDJSON stays readable when cut short, and the binary format is the most compact
+    let format = std::env::args()
+        .find(|argument| ["--csv", "--ndjson", "--binary"].contains(&argument.as_str()))
+        .unwrap_or_default();
+    let extension = match format.as_str() {
+        "--csv" => "csv",
+        "--ndjson" => "ndjson",
+        "--binary" => "njord",
+        _ => "yaml",
+    };
+    // Compressed files take a fraction of the space, and wear the SD card less
+    let compression = match std::env::args()
+        .find(|argument| ["--gzip", "--zstd"].contains(&argument.as_str()))
+        .as_deref()
+    {
+        #[cfg(feature = "gzip")]
+        Some("--gzip") => logging::compression::Compression::Gzip(6),
+        #[cfg(feature = "zstd")]
+        Some("--zstd") => logging::compression::Compression::Zstd(3),
+        Some(flag) => anyhow::bail!("{flag} needs the {} feature.", &flag[2..]),
+        None => logging::compression::Compression::None,
+    };
+    let extension = match compression.extension() {
+        Some(compressed) => format!("{extension}.{compressed}"),
+        None => extension.to_string(),
+    };
+    // Every file starts with how the sensor was set up, so that it can still be interpreted later
+    let metadata = logging::Metadata::new(&sensor, &timebase);
+    let raw_metadata = metadata.clone();
+    let marker_metadata = metadata.clone();
+    let telemetry_metadata = metadata.clone();
+    // The latest samples and errors, with the sensor setup, are dumped to "Data/Black box {time}.yaml" if anything panics
+    let recorder =
+        logging::black_box::FlightRecorder::new(memory_capacity, metadata.clone(), timebase);
+    recorder.install("Data");
+    let create_sink = move |file: std::fs::File| -> Result<Box<dyn Sink>> {
+        let writer = logging::compression::CompressedWriter::new(
+            std::io::BufWriter::new(file),
+            compression,
+        )?;
+        Ok(match format.as_str() {
+            "--csv" => Box::new(logging::csv::CsvSink::with_metadata(
+                writer,
+                &logging::csv::Column::ALL,
+                &metadata,
+                timebase,
+            )?),
+            "--ndjson" => Box::new(logging::ndjson::NdjsonSink::with_metadata(
+                writer, &metadata, timebase,
+            )?),
+            "--binary" => Box::new(logging::binary::BinaryWriter::new(
+                writer, &metadata, timebase,
+            )?),
+            _ => Box::new(logging::yaml::YamlSink::with_metadata(
+                writer, &metadata, timebase,
+            )?),
+        })
+    };
+    // Live copy for dashboards, e.g., "--influxdb=http://raspberrypi.local:8086/write?db=imu", with the token in INFLUXDB_TOKEN
+    #[cfg(feature = "influxdb")]
+    let mut influxdb = match std::env::args().find_map(|argument| {
+        argument
+            .strip_prefix("--influxdb=")
+            .map(|url| url.to_string())
+    }) {
+        Some(url) => {
+            let mut config = logging::influxdb::InfluxDbConfig::new(&url);
+            config.token = std::env::var("INFLUXDB_TOKEN").ok();
+            let session = timebase
+                .start_time
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_secs();
+            config.tags = vec![
+                (
+                    "device".to_string(),
+                    std::fs::read_to_string("/etc/hostname")
+                        .map(|name| name.trim().to_string())
+                        .unwrap_or_default(),
+                ),
+                ("session".to_string(), session.to_string()),
+            ];
+            Some(logging::influxdb::InfluxDbSink::new(config, timebase)?)
+        }
+        None => None,
+    };
+    // Commands from other devices, e.g., "--control=0.0.0.0:5002" and then "echo '{"command": "stop_recording"}' | nc raspberrypi.local 5002". Add "--remote-control" to take the same commands over MQTT and WebSocket. See `telemetry::control::Command` for what can be done
+    let (command_sender, command_receiver) = telemetry::control::channel();
+    let _control_server = match std::env::args().find_map(|argument| {
+        argument
+            .strip_prefix("--control=")
+            .map(|address| address.to_string())
+    }) {
+        Some(address) => {
+            let server = telemetry::control::ControlServer::bind(address, command_sender.clone())?;
+            println!("Taking commands on {}", server.local_address());
+            Some(server)
+        }
+        None => None,
+    };
+    // Live samples for watching on another machine, e.g., "--tcp=0.0.0.0:5000". See `telemetry::tcp::TcpServer` for the protocol
+    let tcp_server = match std::env::args().find_map(|argument| {
+        argument
+            .strip_prefix("--tcp=")
+            .map(|address| address.to_string())
+    }) {
+        Some(address) => {
+            let config = telemetry::tcp::TcpConfig::new(telemetry::Encoding::Json);
+            let server =
+                telemetry::tcp::TcpServer::bind(address, config, &telemetry_metadata, timebase)?;
+            println!("Streaming samples on {}", server.local_address());
+            Some(server)
+        }
+        None => None,
+    };
+    // Quick checks from another machine, e.g., "--http=0.0.0.0:8000" and then "curl raspberrypi.local:8000/status", and metrics for Prometheus. See `telemetry::http::HttpServer` for the endpoints
+    let mut http_server = match std::env::args().find_map(|argument| {
+        argument
+            .strip_prefix("--http=")
+            .map(|address| address.to_string())
+    }) {
+        Some(address) => {
+            let server = telemetry::http::HttpServer::bind(address, &telemetry_metadata, timebase)?;
+            println!("Serving HTTP on {}", server.local_address());
+            Some(server)
+        }
+        None => None,
+    };
+    // Publishing to a broker, e.g., "--mqtt=raspberrypi.local" or "--mqtt=192.168.1.2:1883", with the credentials in MQTT_USERNAME and MQTT_PASSWORD. Add "--heel-alarm=30" for an alarm beyond 30 degrees of heel
+    #[cfg(feature = "mqtt")]
+    let mut mqtt = match std::env::args().find_map(|argument| {
+        argument
+            .strip_prefix("--mqtt=")
+            .map(|address| address.to_string())
+    }) {
+        Some(address) => {
+            let (host, port) = match address.rsplit_once(':') {
+                Some((host, port)) => (host.to_string(), port.parse()?),
+                None => (address, 1883),
+            };
+            let mut config = telemetry::mqtt::MqttConfig::new(&host, port);
+            let hostname = std::fs::read_to_string("/etc/hostname")
+                .map(|name| name.trim().to_string())
+                .unwrap_or_default();
+            config.client_id = format!("njord-{hostname}");
+            config.credentials = std::env::var("MQTT_USERNAME")
+                .ok()
+                .zip(std::env::var("MQTT_PASSWORD").ok());
+            config.attitude_filter = Some(Box::new(njord::fusion::Madgwick::new(
+                0.1,
+                1.0 / sensor.sample_rate,
+            )));
+            config.heel_alarm = std::env::args()
+                .find_map(|argument| {
+                    argument
+                        .strip_prefix("--heel-alarm=")
+                        .map(|degrees| degrees.parse())
+                })
+                .transpose()?;
+            if std::env::args().any(|argument| argument == "--remote-control") {
+                config.commands = Some(("njord/commands".to_string(), command_sender.clone()));
+            }
+            Some(telemetry::mqtt::MqttSink::new(config, timebase)?)
+        }
+        None => None,
+    };
+    // Live dashboards in the browser, e.g., "--websocket=0.0.0.0:8080". See `telemetry::websocket::WebSocketServer` for the protocol
+    #[cfg(feature = "websocket")]
+    let websocket = match std::env::args().find_map(|argument| {
+        argument
+            .strip_prefix("--websocket=")
+            .map(|address| address.to_string())
+    }) {
+        Some(address) => {
+            let mut config = telemetry::websocket::WebSocketConfig::new();
+            if std::env::args().any(|argument| argument == "--remote-control") {
+                config.commands = Some(command_sender.clone());
+            }
+            let server = telemetry::websocket::WebSocketServer::bind(
+                address,
+                config,
+                &telemetry_metadata,
+                timebase,
+            )?;
+            println!("Serving WebSocket clients on {}", server.local_address());
+            Some(server)
+        }
+        None => None,
+    };
+    // Remote control and live samples for tools with a gRPC client, e.g., "--grpc=0.0.0.0:50051". See proto/njord.proto for the API
+    #[cfg(feature = "grpc")]
+    let mut grpc = match std::env::args()
+        .find_map(|argument| argument.strip_prefix("--grpc=").map(|a| a.to_string()))
+    {
+        Some(address) => {
+            let server = telemetry::grpc::GrpcServer::bind(address, &telemetry_metadata, timebase)?;
+            println!("Serving gRPC on {}", server.local_address());
+            Some(server)
+        }
+        None => None,
+    };
+    // Every sample for local analysis processes, which subscribe to "njord.samples" on, e.g., "--zeromq=ipc:///tmp/njord" or "--zeromq=tcp://*:5556"
+    #[cfg(feature = "zeromq")]
+    let zeromq = match std::env::args().find_map(|argument| {
+        argument
+            .strip_prefix("--zeromq=")
+            .map(|endpoint| endpoint.to_string())
+    }) {
+        Some(endpoint) => {
+            let config = telemetry::zeromq::ZmqConfig::new(&endpoint, telemetry::Encoding::Json);
+            let publisher =
+                telemetry::zeromq::ZmqPublisher::bind(config, &telemetry_metadata, timebase)?;
+            println!("Publishing on ZeroMQ at {}", publisher.endpoint());
+            Some(publisher)
+        }
+        None => None,
+    };
+    // Attitude and heading for a boat's Signal K server, e.g., "--signalk=ws://raspberrypi.local:3000/signalk/v1/stream?subscribe=none", with the device's access token in SIGNALK_TOKEN
+    #[cfg(feature = "signalk")]
+    let signalk = match std::env::args().find_map(|argument| {
+        argument
+            .strip_prefix("--signalk=")
+            .map(|url| url.to_string())
+    }) {
+        Some(url) => {
+            let mut config = telemetry::signalk::SignalKConfig::new(&url);
+            config.token = std::env::var("SIGNALK_TOKEN").ok();
+            config.attitude_filter = Some(Box::new(njord::fusion::Madgwick::new(
+                0.1,
+                1.0 / sensor.sample_rate,
+            )));
+            Some(telemetry::signalk::SignalKSink::new(config, timebase)?)
+        }
+        None => None,
+    };
+    // Attitude for ground stations, e.g., "--mavlink=udp:192.168.1.10:14550" for QGroundControl on that machine, or "--mavlink=/dev/serial0" for a telemetry radio, with the baud rate set by stty
+    let mavlink: Option<Box<dyn Sink + Send>> = match std::env::args().find_map(|argument| {
+        argument
+            .strip_prefix("--mavlink=")
+            .map(|destination| destination.to_string())
+    }) {
+        Some(destination) => {
+            let mut config = telemetry::mavlink::MavlinkConfig::new();
+            config.attitude_filter = Some(Box::new(njord::fusion::Madgwick::new(
+                0.1,
+                1.0 / sensor.sample_rate,
+            )));
+            Some(match destination.strip_prefix("udp:") {
+                Some(address) => Box::new(telemetry::mavlink::MavlinkSink::udp(
+                    address,
+                    config,
+                    &telemetry_metadata,
+                    timebase,
+                )?),
+                None => {
+                    let serial = std::fs::OpenOptions::new().write(true).open(&destination)?;
+                    Box::new(telemetry::mavlink::MavlinkSink::new(
+                        serial,
+                        config,
+                        &telemetry_metadata,
+                        timebase,
+                    )?)
+                }
+            })
+        }
+        None => None,
+    };
+    // Attitude for chart plotters, e.g., "--nmea=/dev/ttyUSB0" with the baud rate set by stty, or "--nmea=tcp:0.0.0.0:10110" for OpenCPN. Add "--compass" for the heading from a magnetometer next to the GY-521, and "--declination=2.5" for true headings besides
+    let nmea: Option<Box<dyn Sink + Send>> = match std::env::args().find_map(|argument| {
+        argument
+            .strip_prefix("--nmea=")
+            .map(|destination| destination.to_string())
+    }) {
+        Some(destination) => {
+            let mut config = telemetry::nmea::NmeaConfig::new();
+            config.attitude_filter = Some(Box::new(njord::fusion::Madgwick::new(
+                0.1,
+                1.0 / sensor.sample_rate,
+            )));
+            config.declination = std::env::args()
+                .find_map(|argument| {
+                    argument
+                        .strip_prefix("--declination=")
+                        .map(|degrees| degrees.parse())
+                })
+                .transpose()?;
+            if std::env::args().any(|argument| argument == "--compass") {
+                let mut compass_i2c = I2c::new()?;
+                let mut magnetometer =
+                    sensors::magnetometer::Magnetometer::detect(&mut compass_i2c)?;
+                magnetometer.initialize(&mut compass_i2c)?;
+                println!("Heading from the {}", magnetometer.name());
+                let (sender, receiver) = std::sync::mpsc::channel();
+                config.magnetometer = Some(receiver);
+                let compass_cancellation = cancellation.clone();
+                thread::Builder::new()
+                    .name("compass".to_string())
+                    .spawn(move || {
+                        while !compass_cancellation.is_cancelled() {
+                            match magnetometer
+                                .wait_for_sample(&compass_i2c, Some(Duration::from_millis(100)))
+                            {
+                                // Stops along with the NMEA sink
+                                (Ok(Some(field)), _) if sender.send(field).is_err() => break,
+                                (Err(error), _) => println!("{error:#}"),
+                                _ => {}
+                            }
+                        }
+                    })?;
+            }
+            Some(match destination.strip_prefix("tcp:") {
+                Some(address) => {
+                    let tcp_config = telemetry::tcp::TcpConfig::new(telemetry::Encoding::Json);
+                    let broadcaster =
+                        telemetry::tcp::TcpBroadcaster::bind(address, &tcp_config, &[])?;
+                    println!("Serving NMEA on {}", broadcaster.local_address());
+                    Box::new(telemetry::nmea::NmeaSink::new(
+                        broadcaster,
+                        config,
+                        timebase,
+                    )?)
+                }
+                None => {
+                    let serial = std::fs::OpenOptions::new().write(true).open(&destination)?;
+                    Box::new(telemetry::nmea::NmeaSink::new(serial, config, timebase)?)
+                }
+            })
+        }
+        None => None,
+    };
+    // Live attitude for displays on the local network, e.g., "--udp=192.168.1.255:5001". Every sensor sample goes out, up to 50 Hz, even between the stored ones
+    let udp_sender = match std::env::args().find_map(|argument| {
+        argument
+            .strip_prefix("--udp=")
+            .map(|address| address.to_string())
+    }) {
+        Some(address) => {
+            let config = telemetry::udp::UdpConfig::new(address, telemetry::Encoding::Json)?;
+            println!("Sending samples to {}", config.destination);
+            Some(telemetry::udp::UdpSender::new(config, timebase)?)
+        }
+        None => None,
+    };
+    // Roll, pitch, and heading on an SSD1306 OLED at address 0x3C with "--display", e.g., in the cockpit, with the sample and error counts
+    let mut display = if std::env::args().any(|argument| argument == "--display") {
+        let mut display_i2c = I2c::new()?;
+        let mut ssd1306 = display::ssd1306::SSD1306::default();
+        ssd1306.initialize(&mut display_i2c)?;
+        let attitude_tracker = telemetry::AttitudeTracker::new(Some(Box::new(
+            njord::fusion::Madgwick::new(0.1, 1.0 / sensor.sample_rate),
+        )));
+        Some((
+            display::Display::new(ssd1306, display_i2c, Duration::from_millis(250))?,
+            attitude_tracker,
+        ))
+    } else {
+        None
+    };
+    let rotate = std::env::args().any(|argument| argument == "--rotate");
+    let mut data_file = open_stream("Calibrated data", &extension, rotate, create_sink)?;
+    // Raw readings from the same reads, for redoing the calibration offline once it improves
+    let mut raw_file = if std::env::args().any(|argument| argument == "--raw") {
+        let extension = match compression.extension() {
+            Some(compressed) => format!("csv.{compressed}"),
+            None => "csv".to_string(),
+        };
+        let create_sink = move |file: std::fs::File| {
+            let writer = logging::compression::CompressedWriter::new(
+                std::io::BufWriter::new(file),
+                compression,
+            )?;
+            logging::raw::RawCsvSink::new(writer, &raw_metadata, timebase)
+        };
+        Some(open_stream("Raw data", &extension, rotate, create_sink)?)
+    } else {
+        None
+    };
+    // Events like "tack" or "engine start", typed into the console or marked with the button, for finding maneuvers in the samples afterwards
+    let (marker_sender, marker_receiver) = logging::markers::channel();
+    let command_markers = marker_sender.clone();
+    let clock_markers = marker_sender.clone();
+    let power_markers = marker_sender.clone();
+    let mut marker_file = if std::env::args().any(|argument| argument == "--markers") {
+        let create_sink = move |file: std::fs::File| {
+            logging::markers::MarkerSink::new(
+                std::io::BufWriter::new(file),
+                &marker_metadata,
+                timebase,
+            )
+        };
+        let stream = open_stream("Markers", "ndjson", rotate, create_sink)?;
+
+        let console = marker_sender.clone();
+        thread::spawn(move || {
+            for line in std::io::stdin().lines().map_while(|line| line.ok()) {
+                let tag = line.trim();
+                if !tag.is_empty() && console.mark(logging::markers::Marker::new(tag)).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut button = Gpio::new()?.get(GPIO_MARKER_BUTTON)?.into_input_pullup();
+        let mut last_press: Option<Instant> = None;
+        button.set_async_interrupt(Trigger::FallingEdge, move |_| {
+            // The contacts bounce for a few milliseconds
+            if last_press.is_none_or(|last| last.elapsed() >= Duration::from_millis(200)) {
+                last_press = Some(Instant::now());
+                let _ = marker_sender.mark(logging::markers::Marker::new("button"));
+            }
+        })?;
+        Some((stream, button)) // The button only interrupts as long as it is kept
+    } else {
+        None
+    };
+    // Battery powered, e.g., "--battery=11.5" watches the supply through an INA219, and stops the recording, writing everything, once it stays below 11.5 V, with a warning from 0.3 V above that. Add "--poweroff" to shut the Pi down then as well
+    let power_monitor = match std::env::args()
+        .find_map(|argument| {
+            argument
+                .strip_prefix("--battery=")
+                .map(|voltage| voltage.parse::<f64>())
+        })
+        .transpose()?
+    {
+        Some(shutdown_voltage) => {
+            let mut power_i2c = I2c::new()?;
+            let mut ina219 = sensors::ina219::INA219::default();
+            ina219.initialize(&mut power_i2c)?;
+            let mut monitor = power::PowerMonitor::new(
+                power::PowerInput::Ina219 {
+                    monitor: ina219,
+                    i2c: power_i2c,
+                },
+                power::PowerConfig::new(shutdown_voltage + 0.3, shutdown_voltage),
+            );
+            monitor.shutdown = Some(cancellation.clone());
+            let power_cancellation = cancellation.clone();
+            let monitor = thread::Builder::new()
+                .name("power-monitor".to_string())
+                .spawn(move || {
+                    let mut state = power::SupplyState::Normal;
+                    while !power_cancellation.is_cancelled() {
+                        match monitor.next_sample(Some(Duration::from_millis(100))) {
+                            (Ok(Some(sample)), _) if sample.state != state => {
+                                state = sample.state;
+                                println!("Supply: {:?} at {:.2} V", state, sample.voltage);
+                                if let Ok(payload) = serde_json::to_value(sample) {
+                                    let _ = power_markers.mark(
+                                        logging::markers::Marker::with_payload("supply", payload),
+                                    );
+                                }
+                            }
+                            (Err(error), _) => println!("{error:#}"),
+                            _ => {}
+                        }
+                    }
+                    state
+                })?;
+            Some(monitor)
+        }
+        None => None,
+    };
+    // Streams nothing else needs go through hubs, each fed on its own thread, with its own queue, so that one failing or stalling, e.g., on an unplugged serial port, neither stops the recording nor holds up the others
+    let route = logging::hub::RouteConfig::new(1000, utilites::OverflowPolicy::Overwrite);
+    let mut live = logging::hub::Hub::new(); // Every sample
+    let mut stored = logging::hub::Hub::new(); // The stored samples
+    if let Some(udp_sender) = udp_sender {
+        live.add("UDP", udp_sender, route)?;
+    }
+    if let Some(mavlink) = mavlink {
+        live.add("MAVLink", mavlink, route)?;
+    }
+    if let Some(nmea) = nmea {
+        live.add("NMEA", nmea, route)?;
+    }
+    #[cfg(feature = "websocket")]
+    if let Some(websocket) = websocket {
+        live.add("WebSocket", websocket, route)?;
+    }
+    #[cfg(feature = "signalk")]
+    if let Some(signalk) = signalk {
+        live.add("Signal K", signalk, route)?;
+    }
+    #[cfg(feature = "zeromq")]
+    if let Some(zeromq) = zeromq {
+        live.add("ZeroMQ", zeromq, route)?;
+    }
+    if let Some(tcp_server) = tcp_server {
+        stored.add("TCP", tcp_server, route)?;
+    }
+    // Errors of the other streams, which are needed for more than samples, e.g., for their status at the end. Like those of the hubs, they are counted rather than stopping the recording
+    let mut sink_errors: Vec<logging::hub::RouteStatus> = Vec::new();
+
+    let mut recording = true; // Stopped and started with `telemetry::control::Command`s
     loop {
-        if kill_signal.try_recv().is_ok() {
+        if cancellation.is_cancelled() {
             break;
         }
 
-        let (sample, sampling_instant) = sensor.wait_for_sample(&mut i2c, Some(interrupt_timeout));
+        #[cfg(feature = "grpc")]
+        if let Some(grpc) = &grpc {
+            for command in grpc.commands() {
+                match command {
+                    telemetry::grpc::Command::StartSampling(reply) => {
+                        reply.send(sensor.wake(&mut i2c));
+                        // Stored samples pick up from now, rather than catching up on the pause
+                        sampler.resume();
+                    }
+                    telemetry::grpc::Command::StopSampling(reply) => {
+                        reply.send(sensor.sleep(&mut i2c))
+                    }
+                    telemetry::grpc::Command::Calibrate {
+                        duration,
+                        sampling_period: calibration_period,
+                        sample_size,
+                        reply,
+                    } => {
+                        let calibration = recalibrate(
+                            &mut sensor,
+                            &mut i2c,
+                            &mut led,
+                            &cancellation,
+                            sample_size,
+                            calibration_period,
+                            duration,
+                        );
+                        if calibration.is_ok() {
+                            grpc.set_metadata(&logging::Metadata::new(&sensor, &timebase));
+                        }
+                        reply.send(calibration);
+                        sampler.resume();
+                    }
+                }
+            }
+        }
+        for command in command_receiver.try_iter() {
+            println!("Command: {command:?}");
+            match command {
+                telemetry::control::Command::StartRecording => recording = true,
+                telemetry::control::Command::StopRecording => recording = false,
+                telemetry::control::Command::Calibrate => {
+                    match recalibrate(
+                        &mut sensor,
+                        &mut i2c,
+                        &mut led,
+                        &cancellation,
+                        10_000,
+                        Duration::from_millis(100),
+                        Duration::from_secs(5 * 60),
+                    ) {
+                        Ok(_) =>
+                        {
+                            #[cfg(feature = "grpc")]
+                            if let Some(grpc) = &grpc {
+                                grpc.set_metadata(&logging::Metadata::new(&sensor, &timebase));
+                            }
+                        }
+                        Err(error) => println!("Calibration failed: {error:#}"),
+                    }
+                    // Stored samples pick up from now, rather than catching up on the calibration
+                    sampler.resume();
+                }
+                telemetry::control::Command::SetSampleRate { rate } => {
+                    match sensor.set_sample_rate(rate, &mut i2c) {
+                        Ok(rate) => {
+                            interrupt_timeout = Duration::from_secs_f64(1.5 / rate);
+                            // Files state the sample rate of their start, so the change is marked
+                            let _ = command_markers.mark(logging::markers::Marker::with_payload(
+                                "sample rate",
+                                serde_json::json!({ "rate": rate }),
+                            ));
+                            #[cfg(feature = "grpc")]
+                            if let Some(grpc) = &grpc {
+                                grpc.set_metadata(&logging::Metadata::new(&sensor, &timebase));
+                            }
+                        }
+                        Err(error) => println!("Unable to set sample rate: {error:#}"),
+                    }
+                }
+                telemetry::control::Command::MarkEvent { tag, payload } => {
+                    let _ = command_markers.mark(logging::markers::Marker { tag, payload });
+                }
+                telemetry::control::Command::Shutdown => cancellation.cancel(),
+            }
+        }
+        // Stopped remotely
+        #[cfg(feature = "grpc")]
+        if sensor.power_state() == gy521::PowerState::Sleep {
+            thread::sleep(Duration::from_millis(50));
+            continue;
+        }
+
+        let (sample, sampling_instant) =
+            sampler.sample(&mut sensor, &mut i2c, Some(interrupt_timeout));
 
         match sample {
             Ok(sample) => {
-                if let Some(sample) = sample {
-                    let id = samples.len();
-                    if id > 0 {
-                        if sampling_instant.duration_since(clock).as_nanos()
-                            >= sample_count * sampling_period.as_nanos()
-                        {
-                            samples.push((sample, sampling_instant));
-                            sample_count += 1;
+                if last_error.is_none_or(|last_error| last_error.elapsed() >= LED_ERROR_HOLD) {
+                    led.set(Pattern::Heartbeat);
+                }
+                if let Some(sensors::Sampled { sample, due }) = sample {
+                    live.write(&[(sample, sampling_instant)])?;
+                    if let Some(http_server) = &mut http_server {
+                        if let Err(error) = http_server.write(&[(sample, sampling_instant)]) {
+                            record_sink_error(&mut sink_errors, "HTTP", error);
+                        }
+                    }
+                    #[cfg(feature = "mqtt")]
+                    if let Some(mqtt) = &mut mqtt {
+                        if let Err(error) = mqtt.write(&[(sample, sampling_instant)]) {
+                            record_sink_error(&mut sink_errors, "MQTT", error);
+                        }
+                    }
+                    #[cfg(feature = "grpc")]
+                    if let Some(grpc) = &mut grpc {
+                        if let Err(error) = grpc.write(&[(sample, sampling_instant)]) {
+                            record_sink_error(&mut sink_errors, "gRPC", error);
+                        }
+                    }
+                    if let Some((display, attitude_tracker)) = &mut display {
+                        display.show(display::DisplayStatus {
+                            attitude: Some(attitude_tracker.update(
+                                &sample,
+                                sampling_instant,
+                                &timebase,
+                            )),
+                            samples: data_file.count(),
+                            errors: errors.len(),
+                            recording,
+                        });
+                    }
+                    if due {
+                        #[cfg(feature = "influxdb")]
+                        if let Some(influxdb) = &mut influxdb {
+                            if let Err(error) = influxdb.write(&[(sample, sampling_instant)]) {
+                                record_sink_error(&mut sink_errors, "InfluxDB", error);
+                            }
+                        }
+                        stored.write(&[(sample, sampling_instant)])?;
+                        recorder.record(sample, sampling_instant);
+                        if recording {
+                            data_file.push(sample, sampling_instant)?;
+                            if let (Some(raw_file), Some(raw)) = (&mut raw_file, sensor.raw) {
+                                raw_file.push(raw, sampling_instant)?;
+                            }
                         }
-                    } else {
-                        samples.push((sample, sampling_instant));
                     }
+                    if let Some(http_server) = &http_server {
+                        http_server.record_latency(sampling_instant.elapsed());
+                    }
+                } else if let Some(http_server) = &http_server {
+                    http_server.record_timeout();
                 }
             }
             Err(error) => {
+                led.set(Pattern::ErrorCode(LED_CODE_SENSOR));
+                last_error = Some(sampling_instant);
+                recorder.record_error(&error, sampling_instant);
+                if let Some(http_server) = &http_server {
+                    http_server.record_error(&error);
+                }
+                #[cfg(feature = "mqtt")]
+                if let Some(mqtt) = &mqtt {
+                    if let Err(error) = mqtt.alarm("sensor", &error.to_string(), sampling_instant) {
+                        record_sink_error(&mut sink_errors, "MQTT", error);
+                    }
+                }
                 errors.push((error, sampling_instant));
             }
         }
 
-        if (clock.elapsed().as_micros() as u128 / blink_period.as_micros()) > blink_count {
-            led.toggle();
-            blink_count += 1;
+        if let Some(http_server) = &http_server {
+            http_server.set_fifo_overflows(sensor.fifo_overflows);
+        }
+
+        if let Some(step) = clock_sync.poll() {
+            println!("Wall clock stepped by {:.3} s", step.offset);
+            let _ = clock_markers.mark(logging::markers::Marker::with_payload(
+                "clock step",
+                serde_json::to_value(step)?,
+            ));
+        }
+
+        if let Some((marker_file, _)) = &mut marker_file {
+            let mut marked = false;
+            for (marker, instant) in marker_receiver.try_iter() {
+                println!("Marker: {}", marker.tag);
+                marker_file.push(marker, instant)?;
+                marked = true;
+            }
+            // Markers are rare, and each is worth keeping
+            if marked {
+                marker_file.flush()?;
+            }
+        }
+
+        if (clock.elapsed().as_micros() as u128 / report_period.as_micros()) > report_count {
+            report_count += 1;
             println!(
                 "Samples: {} | Elapsed time: {}",
-                samples.count(),
+                data_file.count(),
                 clock.elapsed().as_micros(),
             );
         }
-
-        if samples.len() == memory_capacity {
-            break; // Let's have a look at the samples
-        }
     }
 
-    led.set_low();
+    led.set(Pattern::Off);
     sensor.sleep(&mut i2c)?;
+    let supply_state = power_monitor.map(|monitor| monitor.join().unwrap_or_default());
 
     println!("Writing data.");
 
-    let data_file = std::fs::File::create("Data/Calibrated data.yaml")?;
-    serde_yaml::to_writer(
-        data_file,
-        &samples
-            .data
-            .iter()
-            .map(|(sample, instant)| (sample, sampling_begin + instant.duration_since(clock)))
-            .collect::<Vec<_>>(),
-    )?;
+    data_file.flush()?;
+    if let Some(raw_file) = &mut raw_file {
+        raw_file.flush()?;
+    }
+    if let Some((marker_file, _)) = &mut marker_file {
+        // Those since the last round of the loop, e.g., of the supply turning critical, which ended it
+        for (marker, instant) in marker_receiver.try_iter() {
+            marker_file.push(marker, instant)?;
+        }
+        marker_file.flush()?;
+    }
+    let routes = live.status().into_iter().chain(stored.status());
+    drop((live, stored)); // Writes the rest
+    for status in routes.chain(sink_errors) {
+        if status.errors > 0 || status.dropped > 0 {
+            println!(
+                "{}: {} samples dropped, {} errors, last error: {}",
+                status.name,
+                status.dropped,
+                status.errors,
+                status.last_error.unwrap_or_default()
+            );
+        }
+    }
+    #[cfg(feature = "influxdb")]
+    if let Some(influxdb) = influxdb.take() {
+        let status = influxdb.status();
+        drop(influxdb); // Sends the rest
+        if let Some(error) = status.last_error {
+            println!(
+                "InfluxDB: {} samples sent, {} failed requests, last error: {error}",
+                status.sent, status.failures
+            );
+        }
+    }
+    #[cfg(feature = "mqtt")]
+    if let Some(mqtt) = mqtt.take() {
+        let status = mqtt.status();
+        drop(mqtt); // Publishes the rest
+        if status.dropped > 0 {
+            println!(
+                "MQTT: {} messages published, {} dropped, last error: {}",
+                status.published,
+                status.dropped,
+                status.last_error.unwrap_or_default()
+            );
+        }
+    }
     let error_file = std::fs::File::create("Data/Calibrated errors.yaml")?;
     serde_yaml::to_writer(
         error_file,
         &errors
-            .data
             .iter()
-            .map(|(error, instant)| {
-                (
-                    error.to_string(),
-                    sampling_begin + instant.duration_since(clock),
-                )
-            })
+            .map(|(error, instant)| (error.to_string(), timebase.system_time(*instant)))
             .collect::<Vec<_>>(),
     )?;
 
     println!("Errors encountered: {}", errors.len());
+    if !clock_sync.steps().is_empty() {
+        println!(
+            "Wall clock steps: {:#?}\nCorrected start time: {}",
+            clock_sync.steps(),
+            njord::clock::iso_timestamp(clock_sync.corrected_start_time())
+        );
+    }
+    if let Some(statistics) = sensor
+        .interrupt_timing
+        .as_ref()
+        .and_then(|timing| timing.statistics())
+    {
+        println!("Interrupt timing: {:#?}", statistics);
+    }
+    if supply_state == Some(power::SupplyState::Critical) {
+        println!("Stopped, since the supply is critical.");
+        if std::env::args().any(|argument| argument == "--poweroff") {
+            std::process::Command::new("systemctl")
+                .arg("poweroff")
+                .status()?;
+        }
+    }
 
     Ok(())
 }
+
+/// Counts a failed write to the stream {name} in {statuses}. Only the first error of each stream is printed, since a stream that fails once tends to fail with every sample
+fn record_sink_error(
+    statuses: &mut Vec<logging::hub::RouteStatus>,
+    name: &str,
+    error: anyhow::Error,
+) {
+    let index = match statuses.iter().position(|status| status.name == name) {
+        Some(index) => index,
+        None => {
+            println!("{name}: {error:#}");
+            statuses.push(logging::hub::RouteStatus {
+                name: name.to_string(),
+                ..Default::default()
+            });
+            statuses.len() - 1
+        }
+    };
+    statuses[index].errors += 1;
+    statuses[index].last_error = Some(format!("{error:#}"));
+}
+
+/// Streams samples to "Data/{name}.{extension}", or, with {rotate}, for recordings over days, to a new file every hour or 50 MB, named after the time it was started at.
+/// Samples are written as they come in, and synced every 10 s, so a crash only loses the latest few
+fn open_stream<T, S: Sink<T> + 'static>(
+    name: &str,
+    extension: &str,
+    rotate: bool,
+    mut create_sink: impl FnMut(std::fs::File) -> Result<S> + 'static,
+) -> Result<logging::streaming::StreamingWriter<Box<dyn Sink<T>>, T>> {
+    let (sink, file): (Box<dyn Sink<T>>, _) = if rotate {
+        let policy = logging::rotation::RotationPolicy {
+            max_size: Some(50_000_000),
+            max_age: Some(Duration::from_secs(60 * 60)),
+            retention: None,
+        };
+        let sink =
+            logging::rotation::RotatingSink::new("Data", name, extension, policy, create_sink)?;
+        (Box::new(sink), None) // Syncs its files itself
+    } else {
+        let file = std::fs::File::create(format!("Data/{name}.{extension}"))?;
+        (Box::new(create_sink(file.try_clone()?)?), Some(file))
+    };
+    let mut stream = logging::streaming::StreamingWriter::new(sink, 10);
+    stream.enable_sync(file.as_ref(), Duration::from_secs(10))?;
+    Ok(stream)
+}
+
+/// Calibrates {sensor} like at the start, with the latest {sample_size} of samples taken every {sampling_period} over {duration}, and saves the calibration for the next start. {led} shows the calibration pattern meanwhile
+fn recalibrate<S: ImuSensor>(
+    sensor: &mut S,
+    i2c: &mut I2c,
+    led: &mut StatusLed,
+    cancellation: &utilites::CancellationToken,
+    sample_size: usize,
+    sampling_period: Duration,
+    duration: Duration,
+) -> Result<gy521::calibration::CalibrationData> {
+    led.set(Pattern::Calibrating);
+    let calibration = sensor.calibrate(
+        i2c,
+        sample_size,
+        sampling_period,
+        duration,
+        cancellation,
+        &mut ConsoleObserver::new(10),
+    );
+    led.set(Pattern::Heartbeat);
+    let calibration = calibration?;
+    calibration.save(CALIBRATION_FILE)?;
+    Ok(calibration)
+}
+
+/// Prints {updates} status updates over the course of a calibration
+struct ConsoleObserver {
+    updates: usize,
+    status_count: usize,
+    rejected_count: usize,
+}
+
+impl ConsoleObserver {
+    fn new(updates: usize) -> Self {
+        Self {
+            updates,
+            status_count: 0,
+            rejected_count: 0,
+        }
+    }
+}
+
+impl gy521::calibration::CalibrationObserver for ConsoleObserver {
+    fn on_progress(&mut self, progress: f64) {
+        if (progress * self.updates as f64) as usize >= self.status_count {
+            println!("Status update: \t{}/{}", self.status_count, self.updates);
+            self.status_count += 1;
+        }
+    }
+
+    fn on_sample_rejected(&mut self, _sample: gy521::calibration::RejectedSample) {
+        self.rejected_count += 1;
+    }
+
+    fn on_complete(&mut self, _calibration: &gy521::calibration::CalibrationData) {
+        println!(
+            "Calibration complete. Rejected samples: {}",
+            self.rejected_count
+        );
+    }
+}
@@ -1,12 +1,96 @@
-use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+use std::ops::{
+    Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Neg, Sub, SubAssign,
+};
+
+pub mod allan;
+pub mod angle;
+mod euler;
+pub mod filter;
+pub mod frames;
+pub mod integration;
+mod kalman;
+mod mat3;
+mod matrix;
+mod quaternion;
+pub mod resample;
+pub mod spectrum;
+mod statistics;
+
+pub use euler::{EulerAngles, RotationOrder};
+pub use kalman::KalmanFilter;
+pub use mat3::Mat3;
+pub use matrix::Matrix;
+pub use quaternion::Quaternion;
+pub use statistics::{Components, RollingStatistics, Statistics};
+
+/// Number type of the components of `Vec3D`. Implemented for f64 (the default everywhere) and f32 (for memory constrained targets).
+/// Multiplying and dividing vectors by a scalar is only implemented for these two, since a generic implementation would overlap with the one for any number that converts into f64
+pub trait Scalar:
+    Copy
+    + Default
+    + PartialEq
+    + PartialOrd
+    + std::fmt::Debug
+    + Add<Output = Self>
+    + AddAssign
+    + Sub<Output = Self>
+    + SubAssign
+    + Mul<Output = Self>
+    + MulAssign
+    + Div<Output = Self>
+    + DivAssign
+    + Neg<Output = Self>
+{
+    fn from_f64(value: f64) -> Self;
+    fn to_f64(self) -> f64;
+    fn sqrt(self) -> Self;
+    fn abs(self) -> Self;
+    fn min(self, other: Self) -> Self;
+    fn max(self, other: Self) -> Self;
+}
+
+macro_rules! impl_scalar {
+    ($($float:ty),*) => {
+        $(
+            impl Scalar for $float {
+                fn from_f64(value: f64) -> Self {
+                    value as $float
+                }
+
+                fn to_f64(self) -> f64 {
+                    self as f64
+                }
+
+                fn sqrt(self) -> Self {
+                    <$float>::sqrt(self)
+                }
+
+                fn abs(self) -> Self {
+                    <$float>::abs(self)
+                }
+
+                fn min(self, other: Self) -> Self {
+                    <$float>::min(self, other)
+                }
+
+                fn max(self, other: Self) -> Self {
+                    <$float>::max(self, other)
+                }
+            }
+        )*
+    };
+}
+
+impl_scalar!(f32, f64);
 
-#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize)]
-pub struct Vec3D {
-    pub x: f64,
-    pub y: f64,
-    pub z: f64,
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Vec3D<S = f64> {
+    pub x: S,
+    pub y: S,
+    pub z: S,
 }
 
+// Constructors and scalar arithmetic with mixed number types are only implemented for f64, so that `Vec3D::new(0, 0, 1)` stays unambiguous
 impl Vec3D {
     pub fn new<A: Into<f64>, B: Into<f64>, C: Into<f64>>(x: A, y: B, z: C) -> Self {
         Self {
@@ -16,35 +100,137 @@ impl Vec3D {
         }
     }
 
+    pub fn near_zero(&self) -> bool {
+        (self.x.abs() < Vec3D::THRESHOLD)
+            && (self.y.abs() < Vec3D::THRESHOLD)
+            && (self.z.abs() < Vec3D::THRESHOLD)
+    }
+
+    /// `approx_eq` with `Vec3D::THRESHOLD`
+    pub fn near(&self, rhs: &Vec3D) -> bool {
+        self.approx_eq(rhs, Vec3D::THRESHOLD)
+    }
+
+    pub const THRESHOLD: f64 = 1e-8;
+}
+
+impl<S: Scalar> Vec3D<S> {
+    pub fn from_components(x: S, y: S, z: S) -> Self {
+        Self { x, y, z }
+    }
+
+    /// Converts each component, e.g., from f64 to f32
+    pub fn cast<U: Scalar>(&self) -> Vec3D<U> {
+        Vec3D::from_components(
+            U::from_f64(self.x.to_f64()),
+            U::from_f64(self.y.to_f64()),
+            U::from_f64(self.z.to_f64()),
+        )
+    }
+
     #[must_use]
-    pub fn cross_product(&self, rhs: &Vec3D) -> Self {
+    pub fn cross_product(&self, rhs: &Vec3D<S>) -> Self {
         let x = self.y * rhs.z - self.z * rhs.y;
         let y = self.z * rhs.x - self.x * rhs.z;
         let z = self.x * rhs.y - self.y * rhs.x;
 
-        Self::new(x, y, z)
+        Self::from_components(x, y, z)
     }
 
-    pub fn magnitude(&self) -> f64 {
-        (self.x.powi(2) + self.y.powi(2) + self.z.powi(2)).sqrt()
+    /// Multiplies the vectors component by component (Hadamard product)
+    #[must_use]
+    pub fn component_mul(&self, rhs: &Vec3D<S>) -> Self {
+        Self::from_components(self.x * rhs.x, self.y * rhs.y, self.z * rhs.z)
     }
 
-    pub fn near_zero(&self) -> bool {
-        (self.x.abs() < Vec3D::THRESHOLD)
-            && (self.y.abs() < Vec3D::THRESHOLD)
-            && (self.z.abs() < Vec3D::THRESHOLD)
+    /// Same as `self * rhs`, but easier to spot
+    pub fn dot(&self, rhs: &Vec3D<S>) -> S {
+        self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
+    }
+
+    /// Smallest value of each component
+    #[must_use]
+    pub fn component_min(&self, rhs: &Vec3D<S>) -> Self {
+        Self::from_components(self.x.min(rhs.x), self.y.min(rhs.y), self.z.min(rhs.z))
+    }
+
+    /// Largest value of each component
+    #[must_use]
+    pub fn component_max(&self, rhs: &Vec3D<S>) -> Self {
+        Self::from_components(self.x.max(rhs.x), self.y.max(rhs.y), self.z.max(rhs.z))
+    }
+
+    pub fn magnitude(&self) -> S {
+        self.norm_squared().sqrt()
+    }
+
+    /// Euclidean norm. Same as `magnitude`
+    pub fn norm(&self) -> S {
+        self.magnitude()
+    }
+
+    /// Cheaper than `norm` for comparisons
+    pub fn norm_squared(&self) -> S {
+        self.dot(self)
+    }
+
+    /// Whether each component differs by less than {tolerance}
+    pub fn approx_eq(&self, rhs: &Vec3D<S>, tolerance: S) -> bool {
+        (self.x - rhs.x).abs() < tolerance
+            && (self.y - rhs.y).abs() < tolerance
+            && (self.z - rhs.z).abs() < tolerance
+    }
+
+    pub fn to_array(&self) -> [S; 3] {
+        [self.x, self.y, self.z]
     }
 
     pub fn normalize(&mut self) {
-        *self /= self.magnitude()
+        *self = self.normalized()
     }
 
     #[must_use]
     pub fn normalized(&self) -> Self {
-        *self / self.magnitude()
+        let magnitude = self.magnitude();
+        Self::from_components(self.x / magnitude, self.y / magnitude, self.z / magnitude)
     }
+}
 
-    pub const THRESHOLD: f64 = 1e-8;
+impl<S: Scalar> From<[S; 3]> for Vec3D<S> {
+    fn from([x, y, z]: [S; 3]) -> Self {
+        Self::from_components(x, y, z)
+    }
+}
+
+impl<S: Scalar> From<Vec3D<S>> for [S; 3] {
+    fn from(vector: Vec3D<S>) -> Self {
+        vector.to_array()
+    }
+}
+
+impl<S> Index<usize> for Vec3D<S> {
+    type Output = S;
+
+    /// 0: x, 1: y, 2: z
+    fn index(&self, index: usize) -> &Self::Output {
+        match index {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Index out of bounds for Vec3D: {}", index),
+        }
+    }
+}
+
+impl<S> IndexMut<usize> for Vec3D<S> {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        match index {
+            0 => &mut self.x,
+            1 => &mut self.y,
+            2 => &mut self.z,
+            _ => panic!("Index out of bounds for Vec3D: {}", index),
+        }
+    }
 }
 
 impl<T: Into<f64>> Add<T> for Vec3D {
@@ -56,18 +242,18 @@ impl<T: Into<f64>> Add<T> for Vec3D {
     }
 }
 
-impl Add<Vec3D> for Vec3D {
-    type Output = Vec3D;
+impl<S: Scalar> Add<Vec3D<S>> for Vec3D<S> {
+    type Output = Vec3D<S>;
 
-    fn add(self, rhs: Vec3D) -> Self::Output {
-        Self::new(self.x + rhs.x, self.y + rhs.y, self.z + rhs.z)
+    fn add(self, rhs: Vec3D<S>) -> Self::Output {
+        Self::from_components(self.x + rhs.x, self.y + rhs.y, self.z + rhs.z)
     }
 }
 
-impl Add<Vec3D> for &Vec3D {
-    type Output = Vec3D;
+impl<S: Scalar> Add<Vec3D<S>> for &Vec3D<S> {
+    type Output = Vec3D<S>;
 
-    fn add(self, rhs: Vec3D) -> Self::Output {
+    fn add(self, rhs: Vec3D<S>) -> Self::Output {
         // Self::new(self.x + rhs.x, self.y + rhs.y, self.z + rhs.z)
         *self + rhs
     }
@@ -98,8 +284,8 @@ impl<T: Into<f64>> AddAssign<T> for Vec3D {
     }
 }
 
-impl AddAssign<Vec3D> for Vec3D {
-    fn add_assign(&mut self, rhs: Vec3D) {
+impl<S: Scalar> AddAssign<Vec3D<S>> for Vec3D<S> {
+    fn add_assign(&mut self, rhs: Vec3D<S>) {
         self.x += rhs.x;
         self.y += rhs.y;
         self.z += rhs.z;
@@ -114,18 +300,18 @@ impl<T: Into<f64>> Sub<T> for Vec3D {
     }
 }
 
-impl Sub<Vec3D> for Vec3D {
-    type Output = Vec3D;
+impl<S: Scalar> Sub<Vec3D<S>> for Vec3D<S> {
+    type Output = Vec3D<S>;
 
-    fn sub(self, rhs: Vec3D) -> Self::Output {
+    fn sub(self, rhs: Vec3D<S>) -> Self::Output {
         self.add(-rhs)
     }
 }
 
-impl Sub<Vec3D> for &Vec3D {
-    type Output = Vec3D;
+impl<S: Scalar> Sub<Vec3D<S>> for &Vec3D<S> {
+    type Output = Vec3D<S>;
 
-    fn sub(self, rhs: Vec3D) -> Self::Output {
+    fn sub(self, rhs: Vec3D<S>) -> Self::Output {
         self.add(-rhs)
     }
 }
@@ -154,8 +340,8 @@ impl<T: Into<f64>> SubAssign<T> for Vec3D {
     }
 }
 
-impl SubAssign<Vec3D> for Vec3D {
-    fn sub_assign(&mut self, rhs: Vec3D) {
+impl<S: Scalar> SubAssign<Vec3D<S>> for Vec3D<S> {
+    fn sub_assign(&mut self, rhs: Vec3D<S>) {
         self.add_assign(-rhs)
     }
 }
@@ -178,36 +364,36 @@ impl<T: Into<f64>> MulAssign<T> for Vec3D {
     }
 }
 
-impl Mul<&Vec3D> for Vec3D {
-    type Output = f64;
+impl<S: Scalar> Mul<&Vec3D<S>> for Vec3D<S> {
+    type Output = S;
 
-    fn mul(self, rhs: &Vec3D) -> Self::Output {
-        self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
+    fn mul(self, rhs: &Vec3D<S>) -> Self::Output {
+        self.dot(rhs)
     }
 }
 
 #[allow(clippy::op_ref)]
-impl Mul<Vec3D> for Vec3D {
-    type Output = f64;
+impl<S: Scalar> Mul<Vec3D<S>> for Vec3D<S> {
+    type Output = S;
 
-    fn mul(self, rhs: Vec3D) -> Self::Output {
+    fn mul(self, rhs: Vec3D<S>) -> Self::Output {
         self * &rhs
     }
 }
 
 #[allow(clippy::op_ref)]
-impl Mul<Vec3D> for &Vec3D {
-    type Output = f64;
+impl<S: Scalar> Mul<Vec3D<S>> for &Vec3D<S> {
+    type Output = S;
 
-    fn mul(self, rhs: Vec3D) -> Self::Output {
+    fn mul(self, rhs: Vec3D<S>) -> Self::Output {
         *self * &rhs
     }
 }
 
-impl Mul<&Vec3D> for &Vec3D {
-    type Output = f64;
+impl<S: Scalar> Mul<&Vec3D<S>> for &Vec3D<S> {
+    type Output = S;
 
-    fn mul(self, rhs: &Vec3D) -> Self::Output {
+    fn mul(self, rhs: &Vec3D<S>) -> Self::Output {
         *self * rhs
     }
 }
@@ -254,22 +440,58 @@ impl<T: Into<f64>> DivAssign<T> for Vec3D {
     }
 }
 
-impl Neg for &Vec3D {
-    type Output = Vec3D;
+impl<S: Scalar> Neg for &Vec3D<S> {
+    type Output = Vec3D<S>;
 
     fn neg(self) -> Self::Output {
-        Self::Output::new(-self.x, -self.y, -self.z)
+        Vec3D::from_components(-self.x, -self.y, -self.z)
     }
 }
 
-impl Neg for Vec3D {
-    type Output = Vec3D;
+impl<S: Scalar> Neg for Vec3D<S> {
+    type Output = Vec3D<S>;
 
     fn neg(self) -> Self::Output {
         -&self
     }
 }
 
+impl Mul<f32> for Vec3D<f32> {
+    type Output = Vec3D<f32>;
+
+    fn mul(self, rhs: f32) -> Self::Output {
+        Self::from_components(self.x * rhs, self.y * rhs, self.z * rhs)
+    }
+}
+
+impl Mul<Vec3D<f32>> for f32 {
+    type Output = Vec3D<f32>;
+
+    fn mul(self, rhs: Vec3D<f32>) -> Self::Output {
+        rhs * self
+    }
+}
+
+impl MulAssign<f32> for Vec3D<f32> {
+    fn mul_assign(&mut self, rhs: f32) {
+        *self = *self * rhs;
+    }
+}
+
+impl Div<f32> for Vec3D<f32> {
+    type Output = Vec3D<f32>;
+
+    fn div(self, rhs: f32) -> Self::Output {
+        Self::from_components(self.x / rhs, self.y / rhs, self.z / rhs)
+    }
+}
+
+impl DivAssign<f32> for Vec3D<f32> {
+    fn div_assign(&mut self, rhs: f32) {
+        *self = *self / rhs;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -361,6 +583,56 @@ mod tests {
         assert_eq!(a, b);
     }
 
+    #[test]
+    fn test_component_mul() {
+        let a = Vec3D::new(1, 2, 3);
+        let b = Vec3D::new(-2, 0.5, 3);
+        assert_eq!(a.component_mul(&b), Vec3D::new(-2, 1, 9));
+    }
+
+    #[test]
+    fn test_dot() {
+        let a = Vec3D::new(1, 2, 3);
+        let b = Vec3D::new(4, -5, 6);
+        assert_eq!(a.dot(&b), 12.0);
+        assert_eq!(a.dot(&b), a * b);
+        assert_eq!(a.norm_squared(), 14.0);
+        assert_eq!(Vec3D::new(1, 2, 2).norm(), 3.0);
+    }
+
+    #[test]
+    fn test_component_min_max() {
+        let a = Vec3D::new(1, -2, 3);
+        let b = Vec3D::new(0, 5, 3);
+        assert_eq!(a.component_min(&b), Vec3D::new(0, -2, 3));
+        assert_eq!(a.component_max(&b), Vec3D::new(1, 5, 3));
+    }
+
+    #[test]
+    fn test_conversion_and_index() {
+        let mut a = Vec3D::from([1.0, 2.0, 3.0]);
+        assert_eq!(a, Vec3D::new(1, 2, 3));
+        assert_eq!(<[f64; 3]>::from(a), [1.0, 2.0, 3.0]);
+        assert_eq!((a[0], a[1], a[2]), (1.0, 2.0, 3.0));
+        a[2] = -1.0;
+        assert_eq!(a.z, -1.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_index_out_of_bounds() {
+        let _: f64 = Vec3D::default()[3];
+    }
+
+    #[test]
+    fn test_approx_eq() {
+        let a = Vec3D::new(1, 2, 3);
+        assert!(a.approx_eq(&(a + 0.05), 0.1));
+        assert!(!a.approx_eq(&(a + 0.2), 0.1));
+        assert!(a.near(&(a + 1e-10)));
+        assert!(!a.near(&(a + 1e-6)));
+    }
+
     #[test]
     fn test_magnitude() {
         assert_eq!(Vec3D::new(1, 2, 2).magnitude(), 3.0);
@@ -397,4 +669,17 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn test_single_precision() {
+        let a = Vec3D::from_components(1.0_f32, 2.0, 2.0);
+        assert_eq!(a.norm(), 3.0_f32);
+        assert_eq!(a * 2.0 - a, a);
+        assert_eq!(
+            (a / 3.0).cast::<f64>(),
+            Vec3D::new(1.0 / 3.0_f32, 2.0 / 3.0_f32, 2.0 / 3.0_f32)
+        );
+        assert_eq!(Vec3D::new(1, 2, 2).cast::<f32>(), a);
+        assert_eq!(a.cross_product(&a), Vec3D::default());
+    }
 }
@@ -0,0 +1,110 @@
+/// Overlapping Allan deviation of a rate signal (e.g. angular velocity or acceleration) over a range of averaging times.
+/// See: https://www.mathworks.com/help/nav/ug/inertial-sensor-noise-analysis-using-allan-variance.html
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct AllanDeviation {
+    pub tau: Vec<f64>,       // Averaging times [s]
+    pub deviation: Vec<f64>, // [unit of the rate signal]
+}
+
+impl AllanDeviation {
+    /// Computes the deviation for up to {points} logarithmically spaced averaging times, from one sample period up to half the recording.
+    /// {rates} need to be sampled uniformly with {sample_period} [s].
+    pub fn compute(rates: &[f64], sample_period: f64, points: usize) -> Self {
+        // Integrated signal, e.g. angle from angular velocity
+        let mut theta = Vec::with_capacity(rates.len() + 1);
+        theta.push(0.0);
+        for rate in rates {
+            theta.push(theta.last().unwrap() + rate * sample_period);
+        }
+
+        let max_cluster_size = (theta.len().saturating_sub(1)) / 2;
+        let mut cluster_sizes = (0..points)
+            .map(|point| {
+                let exponent = point as f64 / (points.max(2) - 1) as f64;
+                (max_cluster_size as f64).powf(exponent).round() as usize
+            })
+            .filter(|&m| m >= 1)
+            .collect::<Vec<_>>();
+        cluster_sizes.dedup();
+
+        let (tau, deviation) = cluster_sizes
+            .into_iter()
+            .map(|m| {
+                let tau = m as f64 * sample_period;
+                let terms = theta.len() - 2 * m;
+                let sum = (0..terms)
+                    .map(|k| (theta[k + 2 * m] - 2.0 * theta[k + m] + theta[k]).powi(2))
+                    .sum::<f64>();
+                (tau, (sum / (2.0 * tau.powi(2) * terms as f64)).sqrt())
+            })
+            .unzip();
+
+        Self { tau, deviation }
+    }
+
+    // Index where the log-log slope of the curve is closest to {slope}, and the slope is actually measured there
+    fn closest_slope(&self, slope: f64) -> Option<usize> {
+        (0..self.tau.len().saturating_sub(1))
+            .filter(|&i| self.deviation[i] > 0.0 && self.deviation[i + 1] > 0.0)
+            .map(|i| {
+                let local_slope = (self.deviation[i + 1].ln() - self.deviation[i].ln())
+                    / (self.tau[i + 1].ln() - self.tau[i].ln());
+                (i, (local_slope - slope).abs())
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(i, _)| i)
+    }
+
+    /// Random walk coefficient: The -1/2 slope line evaluated at tau = 1 s [unit*sqrt(s)]
+    /// For a gyroscope in degree/s, this is the angle random walk in degree/sqrt(s). Multiply by 60 for degree/sqrt(h).
+    pub fn random_walk(&self) -> Option<f64> {
+        self.closest_slope(-0.5)
+            .map(|i| self.deviation[i] * self.tau[i].sqrt())
+    }
+
+    /// Bias instability: The flat bottom of the curve, scaled by sqrt(2 ln(2) / pi) [unit]
+    pub fn bias_instability(&self) -> Option<f64> {
+        let scale = (2.0 * 2.0_f64.ln() / std::f64::consts::PI).sqrt();
+        self.closest_slope(0.0).map(|i| self.deviation[i] / scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Deterministic, roughly Gaussian noise (sum of uniform linear congruential draws), so the tests don't need a random crate
+    fn white_noise(count: usize, standard_deviation: f64) -> Vec<f64> {
+        let mut state = 0x2545_F491_4F6C_DD1D_u64;
+        let mut uniform = move || {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (state >> 11) as f64 / (1u64 << 53) as f64
+        };
+        (0..count)
+            .map(|_| ((0..12).map(|_| uniform()).sum::<f64>() - 6.0) * standard_deviation)
+            .collect()
+    }
+
+    #[test]
+    fn test_constant_signal() {
+        let allan = AllanDeviation::compute(&[0.3; 1000], 0.01, 20);
+        assert!(!allan.tau.is_empty());
+        assert!(allan.deviation.iter().all(|deviation| *deviation < 1e-9));
+    }
+
+    #[test]
+    fn test_white_noise_random_walk() {
+        let sample_period = 0.01;
+        let standard_deviation = 0.5;
+        let allan =
+            AllanDeviation::compute(&white_noise(100_000, standard_deviation), sample_period, 30);
+
+        // White rate noise has an Allan deviation of standard_deviation * sqrt(sample_period / tau)
+        assert!((allan.deviation[0] - standard_deviation).abs() / standard_deviation < 0.05);
+        let expected = standard_deviation * sample_period.sqrt();
+        let random_walk = allan.random_walk().unwrap();
+        assert!((random_walk - expected).abs() / expected < 0.1);
+    }
+}
@@ -0,0 +1,99 @@
+use std::f64::consts::{PI, TAU};
+
+// Angles in [rad], unless the name says degrees
+
+/// {angle} in [-pi, pi)
+pub fn wrap_to_pi(angle: f64) -> f64 {
+    wrap_to_two_pi(angle + PI) - PI
+}
+
+/// {angle} in [0, 2 pi)
+pub fn wrap_to_two_pi(angle: f64) -> f64 {
+    // rem_euclid can round up to exactly 2 pi for tiny negative angles
+    let wrapped = angle.rem_euclid(TAU);
+    if wrapped < TAU {
+        wrapped
+    } else {
+        0.0
+    }
+}
+
+/// {angle} [degree] in [0, 360), e.g., for compass headings
+pub fn wrap_to_360(angle: f64) -> f64 {
+    let wrapped = angle.rem_euclid(360.0);
+    if wrapped < 360.0 {
+        wrapped
+    } else {
+        0.0
+    }
+}
+
+/// Smallest rotation from {from} to {to}, in [-pi, pi). Positive means counterclockwise, e.g., from 350 to 10 degrees is +20 degrees.
+pub fn difference(from: f64, to: f64) -> f64 {
+    wrap_to_pi(to - from)
+}
+
+/// Turns a wrapped angle, e.g., a heading in [0, 2 pi), back into a continuous one, so that crossing north doesn't jump by a full turn.
+/// Assumes that consecutive angles are less than half a turn apart.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Unwrapper {
+    angle: Option<f64>, // Continuous angle so far
+}
+
+impl Unwrapper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Continuous version of {angle}. The first angle is passed through unchanged
+    pub fn push(&mut self, angle: f64) -> f64 {
+        let unwrapped = match self.angle {
+            Some(previous) => previous + difference(previous, angle),
+            None => angle,
+        };
+        self.angle = Some(unwrapped);
+        unwrapped
+    }
+
+    pub fn reset(&mut self) {
+        self.angle = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrapping() {
+        assert_eq!(wrap_to_pi(PI), -PI);
+        assert_eq!(wrap_to_pi(-PI), -PI);
+        assert!((wrap_to_pi(3.0 * PI / 2.0) + PI / 2.0).abs() < 1e-12);
+        assert!((wrap_to_pi(-7.0 * TAU + 0.5) - 0.5).abs() < 1e-9);
+        assert!((wrap_to_two_pi(-PI / 2.0) - 3.0 * PI / 2.0).abs() < 1e-12);
+        assert_eq!(wrap_to_two_pi(-1e-17), 0.0);
+        assert_eq!(wrap_to_360(-90.0), 270.0);
+        assert_eq!(wrap_to_360(720.0), 0.0);
+        assert_eq!(wrap_to_360(-1e-15), 0.0);
+    }
+
+    #[test]
+    fn test_difference_and_unwrapping() {
+        let degrees = |angle: f64| angle.to_radians();
+        assert!((difference(degrees(350.0), degrees(10.0)) - degrees(20.0)).abs() < 1e-12);
+        assert!((difference(degrees(10.0), degrees(350.0)) + degrees(20.0)).abs() < 1e-12);
+
+        // Turning counterclockwise for two full turns, in steps of 50 degrees
+        let mut unwrapper = Unwrapper::new();
+        let mut last = 0.0;
+        for step in 0..=15 {
+            let angle = degrees(50.0 * step as f64);
+            last = unwrapper.push(wrap_to_pi(angle));
+            assert!((last - angle).abs() < 1e-9);
+        }
+        assert!(last > 2.0 * TAU);
+
+        unwrapper.reset();
+        assert_eq!(unwrapper.push(3.0), 3.0);
+    }
+}
@@ -0,0 +1,190 @@
+use super::{Mat3, Quaternion, Vec3D};
+
+/// Order in which the elementary rotations of Euler angles are applied. All rotations are intrinsic, i.e., about the axes of the already rotated frame, and follow the right hand rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum RotationOrder {
+    #[default]
+    ZYX, // Yaw about z, then pitch about y', then roll about x''. R = Rz(yaw) * Ry(pitch) * Rx(roll). The aerospace/nautical convention
+    XYZ, // Roll about x, then pitch about y', then yaw about z''. R = Rx(roll) * Ry(pitch) * Rz(yaw)
+}
+
+/// Orientation as roll (about x), pitch (about y), and yaw (about z) [rad].
+/// At a pitch of +-90 degrees (gimbal lock), roll and yaw rotate about the same axis, and only a combination of them is defined: For ZYX, yaw - roll at +90 degrees, and yaw + roll at -90 degrees. For XYZ, it is the other way around. Roll is then reported as 0, with the combination as yaw.
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct EulerAngles {
+    pub roll: f64,  // [rad]
+    pub pitch: f64, // [rad]
+    pub yaw: f64,   // [rad]
+    pub order: RotationOrder,
+}
+
+impl EulerAngles {
+    pub fn new(roll: f64, pitch: f64, yaw: f64, order: RotationOrder) -> Self {
+        Self {
+            roll,
+            pitch,
+            yaw,
+            order,
+        }
+    }
+
+    /// Same as `new`, but with angles in degrees
+    pub fn from_degrees(roll: f64, pitch: f64, yaw: f64, order: RotationOrder) -> Self {
+        Self::new(
+            roll.to_radians(),
+            pitch.to_radians(),
+            yaw.to_radians(),
+            order,
+        )
+    }
+
+    /// (roll, pitch, yaw) [degree]
+    pub fn to_degrees(&self) -> (f64, f64, f64) {
+        (
+            self.roll.to_degrees(),
+            self.pitch.to_degrees(),
+            self.yaw.to_degrees(),
+        )
+    }
+
+    pub fn from_matrix(matrix: &Mat3, order: RotationOrder) -> Self {
+        let m = matrix.rows;
+        let gimbal_lock = |sin_pitch: f64| (sin_pitch.abs() - 1.0).abs() < Vec3D::THRESHOLD;
+        let (roll, pitch, yaw) = match order {
+            RotationOrder::ZYX => {
+                let sin_pitch = (-m[2][0]).clamp(-1.0, 1.0);
+                if gimbal_lock(sin_pitch) {
+                    (0.0, sin_pitch.asin(), (-m[0][1]).atan2(m[1][1]))
+                } else {
+                    (
+                        m[2][1].atan2(m[2][2]),
+                        sin_pitch.asin(),
+                        m[1][0].atan2(m[0][0]),
+                    )
+                }
+            }
+            RotationOrder::XYZ => {
+                let sin_pitch = m[0][2].clamp(-1.0, 1.0);
+                if gimbal_lock(sin_pitch) {
+                    (0.0, sin_pitch.asin(), m[1][0].atan2(m[1][1]))
+                } else {
+                    (
+                        (-m[1][2]).atan2(m[2][2]),
+                        sin_pitch.asin(),
+                        (-m[0][1]).atan2(m[0][0]),
+                    )
+                }
+            }
+        };
+        Self::new(roll, pitch, yaw, order)
+    }
+
+    pub fn from_quaternion(quaternion: &Quaternion, order: RotationOrder) -> Self {
+        Self::from_matrix(&Mat3::from(*quaternion), order)
+    }
+
+    pub fn to_matrix(&self) -> Mat3 {
+        self.to_quaternion().into()
+    }
+
+    pub fn to_quaternion(&self) -> Quaternion {
+        let roll = Quaternion::from_axis_angle(&Vec3D::new(1, 0, 0), self.roll);
+        let pitch = Quaternion::from_axis_angle(&Vec3D::new(0, 1, 0), self.pitch);
+        let yaw = Quaternion::from_axis_angle(&Vec3D::new(0, 0, 1), self.yaw);
+        match self.order {
+            RotationOrder::ZYX => yaw * pitch * roll,
+            RotationOrder::XYZ => roll * pitch * yaw,
+        }
+    }
+
+    /// The same orientation, expressed in another rotation order
+    #[must_use]
+    pub fn with_order(&self, order: RotationOrder) -> Self {
+        Self::from_matrix(&self.to_matrix(), order)
+    }
+}
+
+impl From<EulerAngles> for Quaternion {
+    fn from(angles: EulerAngles) -> Self {
+        angles.to_quaternion()
+    }
+}
+
+impl From<EulerAngles> for Mat3 {
+    fn from(angles: EulerAngles) -> Self {
+        angles.to_matrix()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn near(a: &EulerAngles, b: &EulerAngles) -> bool {
+        a.order == b.order
+            && (Vec3D::new(a.roll, a.pitch, a.yaw) - Vec3D::new(b.roll, b.pitch, b.yaw)).near_zero()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for order in [RotationOrder::ZYX, RotationOrder::XYZ] {
+            let angles = EulerAngles::new(0.4, -1.1, 2.9, order);
+            assert!(near(
+                &EulerAngles::from_quaternion(&angles.to_quaternion(), order),
+                &angles
+            ));
+            assert!(near(
+                &EulerAngles::from_matrix(&angles.to_matrix(), order),
+                &angles
+            ));
+        }
+    }
+
+    #[test]
+    fn test_orders() {
+        let zyx = EulerAngles::new(0.2, 0.3, -0.5, RotationOrder::ZYX);
+        assert!(near(
+            &EulerAngles::from_quaternion(
+                &Quaternion::from_euler_angles(0.2, 0.3, -0.5),
+                RotationOrder::ZYX
+            ),
+            &zyx
+        ));
+
+        // Same orientation, different angles
+        let xyz = zyx.with_order(RotationOrder::XYZ);
+        assert!(!near(
+            &EulerAngles {
+                order: RotationOrder::ZYX,
+                ..xyz
+            },
+            &zyx
+        ));
+        let v = Vec3D::new(1, 2, 3);
+        assert!((xyz.to_matrix() * v - zyx.to_matrix() * v).near_zero());
+    }
+
+    #[test]
+    fn test_gimbal_lock() {
+        for order in [RotationOrder::ZYX, RotationOrder::XYZ] {
+            let angles = EulerAngles::from_degrees(0.0, 90.0, 30.0, order);
+            let result = EulerAngles::from_matrix(&angles.to_matrix(), order);
+            assert!(near(&result, &angles));
+        }
+
+        // With roll, only the combination of roll and yaw comes back, as yaw: (order, pitch, yaw) [degree]
+        for (order, pitch, yaw) in [
+            (RotationOrder::ZYX, 90.0, 10.0),  // yaw - roll
+            (RotationOrder::ZYX, -90.0, 50.0), // yaw + roll
+            (RotationOrder::XYZ, 90.0, 50.0),
+            (RotationOrder::XYZ, -90.0, 10.0),
+        ] {
+            let angles = EulerAngles::from_degrees(20.0, pitch, 30.0, order);
+            let result = EulerAngles::from_matrix(&angles.to_matrix(), order);
+            assert!(near(
+                &result,
+                &EulerAngles::from_degrees(0.0, pitch, yaw, order)
+            ));
+        }
+    }
+}
@@ -0,0 +1,497 @@
+use std::{
+    f64::consts::PI,
+    ops::{Add, Mul, Sub},
+};
+
+use anyhow::{ensure, Result};
+
+use super::Vec3D;
+use crate::utilites::Memory;
+
+/// Anything that can be filtered sample by sample, e.g., `f64` or `Vec3D` (each axis separately)
+pub trait Signal:
+    Copy + Default + Add<Output = Self> + Sub<Output = Self> + Mul<f64, Output = Self>
+{
+}
+
+impl<T: Copy + Default + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T>> Signal for T {}
+
+/// Coefficients of a second order section, normalized such that a0 = 1:
+/// H(z) = (b0 + b1 z^-1 + b2 z^-2) / (1 + a1 z^-1 + a2 z^-2)
+/// First order sections have b2 = a2 = 0.
+/// Designs follow: https://www.w3.org/TR/audio-eq-cookbook/
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Biquad {
+    pub b: [f64; 3],
+    pub a: [f64; 2],
+}
+
+impl Biquad {
+    /// Second order low-pass with {cutoff} and {sample_rate} in [Hz], and quality factor {q}. 1/sqrt(2) gives a Butterworth response.
+    pub fn lowpass(cutoff: f64, sample_rate: f64, q: f64) -> Self {
+        let (sin, cos) = (2.0 * PI * cutoff / sample_rate).sin_cos();
+        let alpha = sin / (2.0 * q);
+        Self::normalized(
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// Second order high-pass. See `lowpass`
+    pub fn highpass(cutoff: f64, sample_rate: f64, q: f64) -> Self {
+        let (sin, cos) = (2.0 * PI * cutoff / sample_rate).sin_cos();
+        let alpha = sin / (2.0 * q);
+        Self::normalized(
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// First order low-pass, from the bilinear transform with prewarping
+    pub fn first_order_lowpass(cutoff: f64, sample_rate: f64) -> Self {
+        let k = (PI * cutoff / sample_rate).tan();
+        Self::normalized([k, k, 0.0], [1.0 + k, k - 1.0, 0.0])
+    }
+
+    /// First order high-pass, from the bilinear transform with prewarping
+    pub fn first_order_highpass(cutoff: f64, sample_rate: f64) -> Self {
+        let k = (PI * cutoff / sample_rate).tan();
+        Self::normalized([1.0, -1.0, 0.0], [1.0 + k, k - 1.0, 0.0])
+    }
+
+    fn normalized(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self {
+            b: b.map(|coefficient| coefficient / a[0]),
+            a: [a[1] / a[0], a[2] / a[0]],
+        }
+    }
+
+    /// Magnitude of the frequency response at {frequency} [Hz]
+    pub fn gain(&self, frequency: f64, sample_rate: f64) -> f64 {
+        let omega = 2.0 * PI * frequency / sample_rate;
+        // |c0 + c1 e^-jw + c2 e^-2jw|
+        let magnitude = |[c0, c1, c2]: [f64; 3]| {
+            let real = c0 + c1 * omega.cos() + c2 * (2.0 * omega).cos();
+            let imaginary = c1 * omega.sin() + c2 * (2.0 * omega).sin();
+            real.hypot(imaginary)
+        };
+        magnitude(self.b) / magnitude([1.0, self.a[0], self.a[1]])
+    }
+
+    // Transposed direct form II, which needs only two state values per section
+    fn process<T: Signal>(&self, input: T, state: &mut [T; 2]) -> T {
+        let output = input * self.b[0] + state[0];
+        state[0] = input * self.b[1] - output * self.a[0] + state[1];
+        state[1] = input * self.b[2] - output * self.a[1];
+        output
+    }
+}
+
+/// Cascade of biquad sections, applied to a stream of samples of type T.
+pub struct IirFilter<T: Signal> {
+    pub sections: Vec<Biquad>,
+    state: Vec<[T; 2]>,
+}
+
+impl<T: Signal> IirFilter<T> {
+    pub fn new(sections: Vec<Biquad>) -> Self {
+        let state = vec![[T::default(); 2]; sections.len()];
+        Self { sections, state }
+    }
+
+    /// Butterworth low-pass of order {order}, with the -3 dB point at {cutoff} [Hz].
+    pub fn butterworth_lowpass(order: usize, cutoff: f64, sample_rate: f64) -> Result<Self> {
+        validate(order, cutoff, sample_rate)?;
+        Ok(Self::new(butterworth(
+            order,
+            |q| Biquad::lowpass(cutoff, sample_rate, q),
+            || Biquad::first_order_lowpass(cutoff, sample_rate),
+        )))
+    }
+
+    /// Butterworth high-pass of order {order}, with the -3 dB point at {cutoff} [Hz].
+    pub fn butterworth_highpass(order: usize, cutoff: f64, sample_rate: f64) -> Result<Self> {
+        validate(order, cutoff, sample_rate)?;
+        Ok(Self::new(butterworth(
+            order,
+            |q| Biquad::highpass(cutoff, sample_rate, q),
+            || Biquad::first_order_highpass(cutoff, sample_rate),
+        )))
+    }
+
+    /// Band-pass from {low} to {high} [Hz], as a Butterworth high-pass followed by a Butterworth low-pass, each of order {order}.
+    /// For narrow bands, the corners overlap and the gain in the middle of the band drops below 1.
+    pub fn butterworth_bandpass(
+        order: usize,
+        low: f64,
+        high: f64,
+        sample_rate: f64,
+    ) -> Result<Self> {
+        ensure!(
+            low < high,
+            "Lower corner frequency ({low} Hz) needs to be below the upper one ({high} Hz)."
+        );
+        let mut sections = Self::butterworth_highpass(order, low, sample_rate)?.sections;
+        sections.extend(Self::butterworth_lowpass(order, high, sample_rate)?.sections);
+        Ok(Self::new(sections))
+    }
+
+    /// Filters the next sample of the stream.
+    pub fn filter(&mut self, input: T) -> T {
+        self.sections
+            .iter()
+            .zip(&mut self.state)
+            .fold(input, |signal, (section, state)| {
+                section.process(signal, state)
+            })
+    }
+
+    /// Magnitude of the frequency response at {frequency} [Hz]
+    pub fn gain(&self, frequency: f64, sample_rate: f64) -> f64 {
+        self.sections
+            .iter()
+            .map(|section| section.gain(frequency, sample_rate))
+            .product()
+    }
+
+    /// Forgets previous samples
+    pub fn reset(&mut self) {
+        self.state.fill([T::default(); 2]);
+    }
+}
+
+fn validate(order: usize, cutoff: f64, sample_rate: f64) -> Result<()> {
+    ensure!(order > 0, "Filter order needs to be at least 1.");
+    ensure!(
+        cutoff > 0.0 && cutoff < sample_rate / 2.0,
+        "Cutoff frequency ({cutoff} Hz) needs to be between 0 Hz and the Nyquist frequency ({} Hz).",
+        sample_rate / 2.0
+    );
+    Ok(())
+}
+
+// Second order sections with the quality factors of the Butterworth poles, plus one first order section for odd orders
+fn butterworth(
+    order: usize,
+    second_order: impl Fn(f64) -> Biquad,
+    first_order: impl Fn() -> Biquad,
+) -> Vec<Biquad> {
+    let mut sections = (0..order / 2)
+        .map(|k| {
+            // Angle of the pole pair from the negative real axis. Odd orders have an additional real pole
+            let angle = PI * (2 * k + 1 + order % 2) as f64 / (2 * order) as f64;
+            second_order(1.0 / (2.0 * angle.cos()))
+        })
+        .collect::<Vec<_>>();
+    if order % 2 == 1 {
+        sections.push(first_order());
+    }
+    sections
+}
+
+/// Mean over the last {window_size} samples. Until the window is full, the mean is over the samples seen so far.
+pub struct MovingAverage<T: Signal> {
+    window: Memory<T>,
+    sum: T,
+}
+
+impl<T: Signal> MovingAverage<T> {
+    pub fn new(window_size: usize) -> Self {
+        Self {
+            window: Memory::new(window_size.max(1)),
+            sum: T::default(),
+        }
+    }
+
+    pub fn filter(&mut self, input: T) -> T {
+        match self.window.push(input) {
+            Some(oldest) => self.sum = self.sum + input - oldest,
+            None => self.sum = self.sum + input,
+        }
+        // Rounding errors of the running sum would pile up forever, so it's recomputed once per window
+        if self.window.count().is_multiple_of(self.window.capacity()) {
+            self.sum = self
+                .window
+                .iter()
+                .fold(T::default(), |sum, &value| sum + value);
+        }
+        self.sum * (1.0 / self.window.len() as f64)
+    }
+
+    pub fn reset(&mut self) {
+        self.window = Memory::new(self.window.capacity());
+        self.sum = T::default();
+    }
+}
+
+/// First order smoothing: output = alpha * input + (1 - alpha) * previous output.
+pub struct ExponentialMovingAverage<T: Signal> {
+    pub alpha: f64, // [1] Weight of the newest sample
+    value: Option<T>,
+}
+
+impl<T: Signal> ExponentialMovingAverage<T> {
+    pub fn new(alpha: f64) -> Self {
+        Self { alpha, value: None }
+    }
+
+    /// Chooses alpha such that the output settles with {time_constant} [s].
+    pub fn from_time_constant(time_constant: f64, sample_period: f64) -> Self {
+        Self::new(sample_period / (time_constant + sample_period))
+    }
+
+    /// The first sample is taken as it is.
+    pub fn filter(&mut self, input: T) -> T {
+        let value = match self.value {
+            Some(value) => input * self.alpha + value * (1.0 - self.alpha),
+            None => input,
+        };
+        self.value = Some(value);
+        value
+    }
+
+    /// Latest output. None before the first sample
+    pub fn value(&self) -> Option<T> {
+        self.value
+    }
+
+    pub fn reset(&mut self) {
+        self.value = None;
+    }
+}
+
+/// Signals with a median, e.g., for removing spikes. Vectors take the median of each axis separately.
+pub trait Median: Signal {
+    fn median<'a>(values: impl Iterator<Item = &'a Self>) -> Self
+    where
+        Self: 'a;
+}
+
+impl Median for f64 {
+    fn median<'a>(values: impl Iterator<Item = &'a Self>) -> Self {
+        let mut values = values.copied().collect::<Vec<_>>();
+        if values.is_empty() {
+            return 0.0;
+        }
+        values.sort_unstable_by(f64::total_cmp);
+        let middle = values.len() / 2;
+        match values.len() % 2 {
+            0 => (values[middle - 1] + values[middle]) / 2.0,
+            _ => values[middle],
+        }
+    }
+}
+
+impl Median for Vec3D {
+    fn median<'a>(values: impl Iterator<Item = &'a Self>) -> Self {
+        let values = values.collect::<Vec<_>>();
+        let axis = |axis| f64::median(values.iter().map(|value| &value[axis]));
+        Vec3D::new(axis(0), axis(1), axis(2))
+    }
+}
+
+/// Median over the last {window_size} samples. Removes spikes shorter than half the window, while keeping steps sharp.
+pub struct MedianFilter<T: Median> {
+    window: Memory<T>,
+}
+
+impl<T: Median> MedianFilter<T> {
+    /// Odd window sizes avoid averaging the two middle values.
+    pub fn new(window_size: usize) -> Self {
+        Self {
+            window: Memory::new(window_size.max(1)),
+        }
+    }
+
+    pub fn filter(&mut self, input: T) -> T {
+        self.window.push(input);
+        T::median(self.window.iter())
+    }
+
+    pub fn reset(&mut self) {
+        self.window = Memory::new(self.window.capacity());
+    }
+}
+
+/// Hampel filter: A sample is an outlier, if any axis deviates from the median of the window by more than {threshold} standard deviations.
+/// The standard deviation is estimated robustly from the median absolute deviation (MAD), so the spikes themselves barely affect it.
+/// Every sample enters the window, so genuine steps are accepted once they fill half of it.
+pub struct HampelFilter {
+    pub threshold: f64,         // [standard deviations] 3 is common
+    pub minimum_deviation: f64, // [unit of the signal] Floor for the standard deviation, so that a perfectly steady signal doesn't turn every bit of noise into an outlier
+    window: Memory<Vec3D>,
+}
+
+impl HampelFilter {
+    const MAD_TO_STANDARD_DEVIATION: f64 = 1.4826; // For normally distributed noise
+
+    pub fn new(window_size: usize, threshold: f64, minimum_deviation: f64) -> Self {
+        Self {
+            threshold,
+            minimum_deviation,
+            window: Memory::new(window_size.max(3)),
+        }
+    }
+
+    /// Adds {input} to the window. Returns the median of the window as a replacement if {input} is an outlier, None otherwise.
+    /// Nothing is flagged until the window is full.
+    pub fn check(&mut self, input: Vec3D) -> Option<Vec3D> {
+        self.window.push(input);
+        if self.window.len() < self.window.capacity() {
+            return None;
+        }
+
+        let median = Vec3D::median(self.window.iter());
+        let deviations = self
+            .window
+            .iter()
+            .map(|value| {
+                let deviation = *value - median;
+                Vec3D::new(deviation.x.abs(), deviation.y.abs(), deviation.z.abs())
+            })
+            .collect::<Vec<_>>();
+        let mad = Vec3D::median(deviations.iter());
+
+        let outlier = (0..3).any(|axis| {
+            let standard_deviation =
+                (Self::MAD_TO_STANDARD_DEVIATION * mad[axis]).max(self.minimum_deviation);
+            (input[axis] - median[axis]).abs() > self.threshold * standard_deviation
+        });
+        outlier.then_some(median)
+    }
+
+    pub fn reset(&mut self) {
+        self.window = Memory::new(self.window.capacity());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Vec3D;
+
+    #[test]
+    fn test_butterworth_gain() {
+        let sample_rate = 100.0;
+        for order in 1..=5 {
+            let lowpass = IirFilter::<f64>::butterworth_lowpass(order, 10.0, sample_rate).unwrap();
+            assert_eq!(lowpass.sections.len(), order.div_ceil(2));
+            assert!((lowpass.gain(0.0, sample_rate) - 1.0).abs() < 1e-12);
+            assert!((lowpass.gain(10.0, sample_rate) - 0.5_f64.sqrt()).abs() < 1e-9);
+            assert!(lowpass.gain(50.0, sample_rate) < 1e-9);
+
+            let highpass =
+                IirFilter::<f64>::butterworth_highpass(order, 10.0, sample_rate).unwrap();
+            assert!(highpass.gain(0.0, sample_rate) < 1e-12);
+            assert!((highpass.gain(10.0, sample_rate) - 0.5_f64.sqrt()).abs() < 1e-9);
+            assert!((highpass.gain(50.0, sample_rate) - 1.0).abs() < 1e-9);
+        }
+        // Steeper with higher order
+        let gain = |order| {
+            IirFilter::<f64>::butterworth_lowpass(order, 10.0, sample_rate)
+                .unwrap()
+                .gain(20.0, sample_rate)
+        };
+        assert!(gain(4) < gain(2) && gain(2) < gain(1));
+    }
+
+    #[test]
+    fn test_invalid_design() {
+        assert!(IirFilter::<f64>::butterworth_lowpass(0, 1.0, 10.0).is_err());
+        assert!(IirFilter::<f64>::butterworth_lowpass(2, 5.0, 10.0).is_err());
+        assert!(IirFilter::<f64>::butterworth_bandpass(2, 2.0, 1.0, 10.0).is_err());
+    }
+
+    #[test]
+    fn test_wave_band() {
+        // Waves at 0.5 Hz, riding on an offset and engine vibration at 8 Hz
+        let sample_rate = 50.0;
+        let mut filter = IirFilter::butterworth_bandpass(2, 0.2, 1.0, sample_rate).unwrap();
+        let signal = |t: f64| (2.0 * PI * 0.5 * t).sin();
+        let mut error: f64 = 0.0;
+        for n in 0..3000 {
+            let t = n as f64 / sample_rate;
+            let output = filter.filter(0.3 + signal(t) + 0.5 * (2.0 * PI * 8.0 * t).sin());
+            if n >= 2500 {
+                // The band-pass shifts the phase, but passes the amplitude almost unchanged
+                error = error.max((output.abs() - 1.0).max(0.0));
+            }
+        }
+        assert!(error < 0.1);
+        assert!(filter.gain(0.5, sample_rate) > 0.9);
+        assert!(filter.gain(8.0, sample_rate) < 0.02);
+    }
+
+    #[test]
+    fn test_vector_step_response() {
+        let mut filter = IirFilter::<Vec3D>::butterworth_lowpass(2, 1.0, 100.0).unwrap();
+        let step = Vec3D::new(1.0, -2.0, 0.5);
+        let mut output = Vec3D::default();
+        for _ in 0..1000 {
+            output = filter.filter(step);
+        }
+        assert!(output.approx_eq(&step, 1e-9));
+
+        filter.reset();
+        assert!(filter.filter(step).norm() < step.norm() / 100.0);
+    }
+
+    #[test]
+    fn test_moving_average() {
+        let mut filter = MovingAverage::new(4);
+        let outputs = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0].map(|input| filter.filter(input));
+        assert_eq!(outputs, [1.0, 1.5, 2.0, 2.5, 3.5, 4.5]);
+
+        filter.reset();
+        assert_eq!(filter.filter(10.0), 10.0);
+    }
+
+    #[test]
+    fn test_exponential_moving_average() {
+        let mut filter = ExponentialMovingAverage::from_time_constant(0.1, 0.001);
+        assert_eq!(filter.value(), None);
+        filter.filter(Vec3D::default());
+        for _ in 0..100 {
+            filter.filter(Vec3D::new(1, 1, 1));
+        }
+        // After one time constant, about 1 - 1/e of a step is reached
+        let value = filter.value().unwrap();
+        assert!((value.x - (1.0 - (-1.0_f64).exp())).abs() < 5e-3);
+    }
+
+    #[test]
+    fn test_median() {
+        let mut filter = MedianFilter::new(5);
+        let outputs = [1.0, 1.0, 50.0, 1.0, 1.0, 2.0, 2.0, 2.0].map(|input| filter.filter(input));
+        assert!(outputs.iter().all(|&output| output <= 2.0)); // Spike removed
+        assert_eq!(outputs[7], 2.0); // Step kept
+
+        let mut filter = MedianFilter::new(3);
+        filter.filter(Vec3D::new(1, 9, 0));
+        filter.filter(Vec3D::new(2, -9, 0));
+        let output = filter.filter(Vec3D::new(3, 0, 100));
+        assert_eq!(output, Vec3D::new(2, 0, 0));
+        assert_eq!(f64::median([1.0, 4.0].iter()), 2.5);
+    }
+
+    #[test]
+    fn test_hampel() {
+        let mut filter = HampelFilter::new(7, 3.0, 0.01);
+        let mut flagged = Vec::new();
+        for n in 0..40 {
+            let mut input = Vec3D::new(0.0, 0.0, 1.0 + 0.005 * (n % 3) as f64);
+            if n == 20 {
+                input.y = -2.0; // I2C glitch
+            }
+            if n >= 30 {
+                input.x = 0.5; // Genuine step
+            }
+            if let Some(replacement) = filter.check(input) {
+                flagged.push(n);
+                assert!(replacement.y.abs() < 1e-12);
+            }
+        }
+        // The step is only flagged until it fills half of the window
+        assert_eq!(flagged, [20, 30, 31, 32]);
+    }
+}
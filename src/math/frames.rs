@@ -0,0 +1,196 @@
+use std::marker::PhantomData;
+
+use anyhow::{ensure, Result};
+
+use super::{Mat3, Quaternion, Vec3D};
+
+// Coordinate frames, as markers for `Vector`, so that mixing up frames is a type error instead of a sign error.
+// The attitude filters in `fusion` rotate from the sensor frame into North-West-Up (x north, y west, z up).
+// The body frame is the vessel's: x forward, y to port (left), z up. Level and heading north, it coincides with North-West-Up.
+
+pub trait Frame {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sensor; // The sensor's own axes, as printed on the board
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Body;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Nwu;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ned;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Enu;
+
+impl Frame for Sensor {}
+impl Frame for Body {}
+impl Frame for Nwu {}
+impl Frame for Ned {}
+impl Frame for Enu {}
+
+/// Vector with components in frame F
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vector<F: Frame> {
+    pub value: Vec3D,
+    frame: PhantomData<F>,
+}
+
+impl<F: Frame> Vector<F> {
+    pub fn new(value: Vec3D) -> Self {
+        Self {
+            value,
+            frame: PhantomData,
+        }
+    }
+}
+
+impl Vector<Body> {
+    /// Components in North-West-Up, with the body's {orientation} (body to North-West-Up, see `Mounting::body_orientation`)
+    pub fn to_earth(&self, orientation: &Quaternion) -> Vector<Nwu> {
+        Vector::new(orientation.rotate(&self.value))
+    }
+}
+
+impl Vector<Nwu> {
+    /// Components in the body frame, with the body's {orientation} (body to North-West-Up)
+    pub fn to_body(&self, orientation: &Quaternion) -> Vector<Body> {
+        Vector::new(orientation.conjugate().rotate(&self.value))
+    }
+}
+
+impl From<Vector<Nwu>> for Vector<Ned> {
+    fn from(vector: Vector<Nwu>) -> Self {
+        let Vec3D { x, y, z } = vector.value;
+        Vector::new(Vec3D::new(x, -y, -z))
+    }
+}
+
+impl From<Vector<Ned>> for Vector<Nwu> {
+    fn from(vector: Vector<Ned>) -> Self {
+        let Vec3D { x, y, z } = vector.value;
+        Vector::new(Vec3D::new(x, -y, -z))
+    }
+}
+
+impl From<Vector<Nwu>> for Vector<Enu> {
+    fn from(vector: Vector<Nwu>) -> Self {
+        let Vec3D { x, y, z } = vector.value;
+        Vector::new(Vec3D::new(-y, x, z))
+    }
+}
+
+impl From<Vector<Enu>> for Vector<Nwu> {
+    fn from(vector: Vector<Enu>) -> Self {
+        let Vec3D { x, y, z } = vector.value;
+        Vector::new(Vec3D::new(y, -x, z))
+    }
+}
+
+impl From<Vector<Ned>> for Vector<Enu> {
+    fn from(vector: Vector<Ned>) -> Self {
+        let Vec3D { x, y, z } = vector.value;
+        Vector::new(Vec3D::new(y, x, -z))
+    }
+}
+
+impl From<Vector<Enu>> for Vector<Ned> {
+    fn from(vector: Vector<Enu>) -> Self {
+        let Vec3D { x, y, z } = vector.value;
+        Vector::new(Vec3D::new(y, x, -z))
+    }
+}
+
+/// How the sensor is mounted in the vessel: A fixed rotation from the sensor frame to the body frame.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Mounting {
+    pub rotation: Quaternion, // Sensor to body
+}
+
+impl Default for Mounting {
+    fn default() -> Self {
+        Self {
+            rotation: Quaternion::IDENTITY,
+        }
+    }
+}
+
+impl Mounting {
+    /// Mounting described by where the sensor's {x} and {y} axes point in the body frame, e.g., x to starboard is (0, -1, 0).
+    /// The axes need to be perpendicular unit vectors.
+    pub fn from_axes(x: &Vec3D, y: &Vec3D) -> Result<Self> {
+        ensure!(
+            (x.norm() - 1.0).abs() < 1e-6 && (y.norm() - 1.0).abs() < 1e-6,
+            "Mounting axes need to be unit vectors, but are {:?} and {:?}.",
+            x,
+            y
+        );
+        ensure!(
+            x.dot(y).abs() < 1e-6,
+            "Mounting axes need to be perpendicular, but are {:?} and {:?}.",
+            x,
+            y
+        );
+        // Columns are the sensor's axes in the body frame
+        let matrix = Mat3::from_rows(x, y, &x.cross_product(y)).transpose();
+        Ok(Self {
+            rotation: Quaternion::from(matrix),
+        })
+    }
+
+    pub fn to_body(&self, vector: &Vector<Sensor>) -> Vector<Body> {
+        Vector::new(self.rotation.rotate(&vector.value))
+    }
+
+    pub fn to_sensor(&self, vector: &Vector<Body>) -> Vector<Sensor> {
+        Vector::new(self.rotation.conjugate().rotate(&vector.value))
+    }
+
+    /// Orientation of the body (body to North-West-Up), from the {sensor_orientation} an attitude filter estimated (sensor to North-West-Up)
+    pub fn body_orientation(&self, sensor_orientation: &Quaternion) -> Quaternion {
+        *sensor_orientation * self.rotation.conjugate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_earth_frames() {
+        // North, west, and up
+        let nwu = Vector::<Nwu>::new(Vec3D::new(1, 2, 3));
+        let ned = Vector::<Ned>::from(nwu);
+        let enu = Vector::<Enu>::from(nwu);
+        assert_eq!(ned.value, Vec3D::new(1, -2, -3));
+        assert_eq!(enu.value, Vec3D::new(-2, 1, 3));
+        assert_eq!(Vector::<Enu>::from(ned), enu);
+        assert_eq!(Vector::<Ned>::from(enu), ned);
+        assert_eq!(Vector::<Nwu>::from(ned), nwu);
+        assert_eq!(Vector::<Nwu>::from(enu), nwu);
+    }
+
+    #[test]
+    fn test_sideways_mounting() {
+        // Sensor x to starboard, sensor y forward. Sensor z still points up
+        let mounting = Mounting::from_axes(&Vec3D::new(0, -1, 0), &Vec3D::new(1, 0, 0)).unwrap();
+        let forward = mounting.to_body(&Vector::new(Vec3D::new(0, 1, 0)));
+        assert!(forward.value.approx_eq(&Vec3D::new(1, 0, 0), 1e-12));
+        let up = mounting.to_body(&Vector::new(Vec3D::new(0, 0, 1)));
+        assert!(up.value.approx_eq(&Vec3D::new(0, 0, 1), 1e-12));
+        let starboard = mounting.to_sensor(&Vector::new(Vec3D::new(0, -1, 0)));
+        assert!(starboard.value.approx_eq(&Vec3D::new(1, 0, 0), 1e-12));
+
+        // Vessel level and heading north: The sensor is yawed by -90 degrees, but the body isn't
+        let sensor_orientation =
+            Quaternion::from_axis_angle(&Vec3D::new(0, 0, 1), -std::f64::consts::FRAC_PI_2);
+        let body_orientation = mounting.body_orientation(&sensor_orientation);
+        assert!((body_orientation.dot(&Quaternion::IDENTITY).abs() - 1.0).abs() < 1e-12);
+        let north = Vector::<Body>::new(Vec3D::new(1, 0, 0)).to_earth(&body_orientation);
+        assert!(north.value.approx_eq(&Vec3D::new(1, 0, 0), 1e-12));
+        let north_ned =
+            Vector::<Ned>::from(north.to_body(&body_orientation).to_earth(&body_orientation));
+        assert!(north_ned.value.approx_eq(&Vec3D::new(1, 0, 0), 1e-12));
+
+        assert!(Mounting::from_axes(&Vec3D::new(1, 0, 0), &Vec3D::new(1, 0, 0)).is_err());
+        assert!(Mounting::from_axes(&Vec3D::new(2, 0, 0), &Vec3D::new(0, 1, 0)).is_err());
+    }
+}
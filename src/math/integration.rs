@@ -0,0 +1,150 @@
+use std::time::Instant;
+
+use super::filter::Signal;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntegrationMethod {
+    #[default]
+    Trapezoidal, // Straight line between two samples. Exact for linearly changing values
+    Quadratic, // Parabola through the latest three samples, like Simpson's rule. Exact for quadratically changing values. The first step is trapezoidal
+}
+
+/// Integrates a stream of timestamped values, e.g., angular velocity to angle, or acceleration to velocity.
+/// Each step uses the actual time between samples, so timing jitter doesn't turn into integration error.
+pub struct Integrator<T: Signal> {
+    pub method: IntegrationMethod,
+    pub integral: T, // [unit of the values * s]
+    previous: Option<(T, Instant)>,
+    before_previous: Option<(T, Instant)>,
+}
+
+impl<T: Signal> Integrator<T> {
+    pub fn new(method: IntegrationMethod) -> Self {
+        Self {
+            method,
+            integral: T::default(),
+            previous: None,
+            before_previous: None,
+        }
+    }
+
+    /// Adds the area since the previous sample, and returns the integral so far.
+    /// A sample with the same instant as the previous one replaces it. Samples from before the previous one are ignored.
+    pub fn push(&mut self, value: T, instant: Instant) -> T {
+        let (previous_value, previous_instant) = match self.previous {
+            Some(previous) => previous,
+            None => {
+                self.previous = Some((value, instant));
+                return self.integral;
+            }
+        };
+        if instant < previous_instant {
+            return self.integral;
+        }
+        if instant == previous_instant {
+            self.previous = Some((value, instant));
+            return self.integral;
+        }
+
+        let step = (instant - previous_instant).as_secs_f64();
+        let area = match (self.method, self.before_previous) {
+            (
+                IntegrationMethod::Quadratic,
+                Some((before_previous_value, before_previous_instant)),
+            ) => {
+                let previous_step = (previous_instant - before_previous_instant).as_secs_f64();
+                let span = previous_step + step;
+                before_previous_value * (-step.powi(3) / (6.0 * previous_step * span))
+                    + previous_value * (step * (step + 3.0 * previous_step) / (6.0 * previous_step))
+                    + value * (step * (3.0 * previous_step + 2.0 * step) / (6.0 * span))
+            }
+            _ => (previous_value + value) * (0.5 * step),
+        };
+        self.integral = self.integral + area;
+        self.before_previous = self.previous.replace((value, instant));
+        self.integral
+    }
+
+    /// Starts over from zero, forgetting previous samples
+    pub fn reset(&mut self) {
+        self.integral = T::default();
+        self.previous = None;
+        self.before_previous = None;
+    }
+}
+
+/// Integral over all {samples}, given as (value, instant) pairs in chronological order.
+pub fn integrate<T: Signal>(samples: &[(T, Instant)], method: IntegrationMethod) -> T {
+    let mut integrator = Integrator::new(method);
+    for &(value, instant) in samples {
+        integrator.push(value, instant);
+    }
+    integrator.integral
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::math::Vec3D;
+
+    // Irregularly spaced instants over one second
+    fn instants() -> Vec<Instant> {
+        let start = Instant::now();
+        let mut milliseconds = 0;
+        let mut instants = vec![start];
+        for step in [3, 17, 10, 25, 5].iter().cycle() {
+            milliseconds += step;
+            if milliseconds > 1000 {
+                break;
+            }
+            instants.push(start + Duration::from_millis(milliseconds));
+        }
+        instants
+    }
+
+    #[test]
+    fn test_irregular_steps() {
+        let instants = instants();
+        let start = instants[0];
+        let end = (*instants.last().unwrap() - start).as_secs_f64();
+        let samples = |f: fn(f64) -> f64| {
+            instants
+                .iter()
+                .map(|&instant| (f((instant - start).as_secs_f64()), instant))
+                .collect::<Vec<_>>()
+        };
+
+        // Linear: Both are exact
+        for method in [IntegrationMethod::Trapezoidal, IntegrationMethod::Quadratic] {
+            let integral = integrate(&samples(|t| 2.0 * t + 1.0), method);
+            assert!((integral - (end.powi(2) + end)).abs() < 1e-12);
+        }
+
+        // Quadratic: Only the quadratic method is exact, after its trapezoidal first step
+        let first_step = (instants[1] - start).as_secs_f64();
+        let first_step_error = first_step.powi(3) / 2.0; // Trapezoid minus exact integral of 3 t^2 over the first step
+        let quadratic = integrate(&samples(|t| 3.0 * t * t), IntegrationMethod::Quadratic);
+        assert!((quadratic - first_step_error - end.powi(3)).abs() < 1e-12);
+        let trapezoidal = integrate(&samples(|t| 3.0 * t * t), IntegrationMethod::Trapezoidal);
+        assert!((trapezoidal - end.powi(3)).abs() > 1e-5);
+    }
+
+    #[test]
+    fn test_vectors_and_edge_cases() {
+        let start = Instant::now();
+        let mut integrator = Integrator::new(IntegrationMethod::default());
+        let acceleration = Vec3D::new(1, -2, 0); // [m/s^2]
+        assert_eq!(integrator.push(acceleration, start), Vec3D::default());
+        integrator.push(Vec3D::new(100, 100, 100), start); // Same instant: Replaces the previous sample
+        integrator.push(acceleration, start);
+        integrator.push(acceleration, start + Duration::from_millis(500));
+        integrator.push(Vec3D::new(100, 100, 100), start); // From the past: Ignored
+        let velocity = integrator.push(acceleration, start + Duration::from_secs(2));
+        assert!(velocity.approx_eq(&Vec3D::new(2, -4, 0), 1e-12));
+
+        integrator.reset();
+        assert_eq!(integrator.integral, Vec3D::default());
+    }
+}
@@ -0,0 +1,168 @@
+use anyhow::{Context, Result};
+
+use super::Matrix;
+
+/// Linear Kalman filter with STATES state variables and MEASUREMENTS measured quantities. Everything lives on the stack.
+/// See: https://en.wikipedia.org/wiki/Kalman_filter
+pub struct KalmanFilter<const STATES: usize, const MEASUREMENTS: usize> {
+    pub state: Matrix<STATES, 1>,                              // x
+    pub covariance: Matrix<STATES, STATES>,                    // P: Uncertainty of the state
+    pub transition: Matrix<STATES, STATES>, // F: State at the next step, from the current one
+    pub process_noise: Matrix<STATES, STATES>, // Q: Uncertainty added per step
+    pub observation: Matrix<MEASUREMENTS, STATES>, // H: Measurement expected from the state
+    pub measurement_noise: Matrix<MEASUREMENTS, MEASUREMENTS>, // R
+}
+
+impl<const STATES: usize, const MEASUREMENTS: usize> KalmanFilter<STATES, MEASUREMENTS> {
+    pub fn new(
+        state: [f64; STATES],
+        covariance: Matrix<STATES, STATES>,
+        transition: Matrix<STATES, STATES>,
+        process_noise: Matrix<STATES, STATES>,
+        observation: Matrix<MEASUREMENTS, STATES>,
+        measurement_noise: Matrix<MEASUREMENTS, MEASUREMENTS>,
+    ) -> Self {
+        Self {
+            state: Matrix::from_column(state),
+            covariance,
+            transition,
+            process_noise,
+            observation,
+            measurement_noise,
+        }
+    }
+
+    pub fn state(&self) -> [f64; STATES] {
+        self.state.to_column()
+    }
+
+    /// Propagates state and covariance by one step.
+    pub fn predict(&mut self) {
+        self.state = self.transition * self.state;
+        self.propagate_covariance();
+    }
+
+    /// Propagates state and covariance by one step, with a known input {control}, e.g., a gyroscope rate, entering through {control_matrix} (B).
+    pub fn predict_with_control<const CONTROLS: usize>(
+        &mut self,
+        control_matrix: &Matrix<STATES, CONTROLS>,
+        control: [f64; CONTROLS],
+    ) {
+        self.state = self.transition * self.state + *control_matrix * Matrix::from_column(control);
+        self.propagate_covariance();
+    }
+
+    fn propagate_covariance(&mut self) {
+        self.covariance =
+            self.transition * self.covariance * self.transition.transpose() + self.process_noise;
+    }
+
+    /// Corrects the state with a {measurement}, using the filter's own observation model.
+    pub fn update(&mut self, measurement: [f64; MEASUREMENTS]) -> Result<()> {
+        let (observation, measurement_noise) = (self.observation, self.measurement_noise);
+        self.update_with(measurement, &observation, &measurement_noise)
+    }
+
+    /// Corrects the state with a {measurement} of a different kind, described by {observation} (H) and {measurement_noise} (R).
+    /// Useful for sensors that report at different rates.
+    pub fn update_with<const K: usize>(
+        &mut self,
+        measurement: [f64; K],
+        observation: &Matrix<K, STATES>,
+        measurement_noise: &Matrix<K, K>,
+    ) -> Result<()> {
+        let innovation = Matrix::from_column(measurement) - *observation * self.state;
+        let innovation_covariance =
+            *observation * self.covariance * observation.transpose() + *measurement_noise;
+        let gain = self.covariance
+            * observation.transpose()
+            * innovation_covariance
+                .inverse()
+                .context("Innovation covariance is singular. Is the measurement noise zero?")?;
+
+        self.state = self.state + gain * innovation;
+        // Joseph form. Keeps the covariance symmetric and positive definite despite rounding
+        let correction = Matrix::<STATES, STATES>::identity() - gain * *observation;
+        self.covariance = correction * self.covariance * correction.transpose()
+            + gain * *measurement_noise * gain.transpose();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Deterministic noise in [-amplitude, amplitude]
+    fn noise(amplitude: f64) -> impl FnMut() -> f64 {
+        let mut state = 0x853C_49E6_748F_EA9B_u64;
+        move || {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            ((state >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0) * amplitude
+        }
+    }
+
+    #[test]
+    fn test_tilt_with_gyroscope_bias() {
+        // State: [angle [rad], gyroscope bias [rad/s]]. The gyroscope rate is the control input, the accelerometer's tilt is measured
+        let dt = 0.01;
+        let mut filter = KalmanFilter::<2, 1>::new(
+            [0.0, 0.0],
+            Matrix::from_diagonal([1.0, 1.0]),
+            Matrix::new([[1.0, -dt], [0.0, 1.0]]),
+            Matrix::from_diagonal([1e-6, 1e-8]),
+            Matrix::new([[1.0, 0.0]]),
+            Matrix::new([[0.03_f64.powi(2)]]),
+        );
+        let control_matrix = Matrix::new([[dt], [0.0]]);
+
+        let (angle, bias) = (0.3, 0.05);
+        let mut accelerometer_noise = noise(0.05);
+        for _ in 0..5000 {
+            filter.predict_with_control(&control_matrix, [bias]); // At rest, the gyroscope only reads its bias
+            filter.update([angle + accelerometer_noise()]).unwrap();
+        }
+
+        let [estimated_angle, estimated_bias] = filter.state();
+        assert!((estimated_angle - angle).abs() < 5e-3);
+        assert!((estimated_bias - bias).abs() < 5e-3);
+        assert!(filter.covariance[(0, 0)] < 0.03_f64.powi(2));
+    }
+
+    #[test]
+    fn test_constant_velocity() {
+        // State: [position [m], velocity [m/s]], measuring position only
+        let dt = 0.1;
+        let mut filter = KalmanFilter::<2, 1>::new(
+            [0.0, 0.0],
+            Matrix::from_diagonal([10.0, 10.0]),
+            Matrix::new([[1.0, dt], [0.0, 1.0]]),
+            Matrix::from_diagonal([1e-6, 1e-6]),
+            Matrix::new([[1.0, 0.0]]),
+            Matrix::new([[0.1]]),
+        );
+
+        let mut measurement_noise = noise(0.2);
+        for step in 1..=500 {
+            filter.predict();
+            let position = 2.0 * step as f64 * dt;
+            filter.update([position + measurement_noise()]).unwrap();
+        }
+        assert!((filter.state()[1] - 2.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_singular_innovation() {
+        let mut filter = KalmanFilter::<1, 1>::new(
+            [0.0],
+            Matrix::ZERO,
+            Matrix::identity(),
+            Matrix::ZERO,
+            Matrix::identity(),
+            Matrix::ZERO,
+        );
+        assert!(filter.update([1.0]).is_err());
+    }
+}
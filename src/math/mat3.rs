@@ -0,0 +1,343 @@
+use std::ops::{Add, AddAssign, Index, IndexMut, Mul, Neg, Sub, SubAssign};
+
+use super::{Quaternion, Vec3D};
+
+/// 3x3 matrix of f64, stored row by row
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Mat3 {
+    pub rows: [[f64; 3]; 3],
+}
+
+impl Mat3 {
+    pub const IDENTITY: Self = Self {
+        rows: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+    };
+    pub const ZERO: Self = Self {
+        rows: [[0.0; 3]; 3],
+    };
+
+    pub fn from_rows(x: &Vec3D, y: &Vec3D, z: &Vec3D) -> Self {
+        Self {
+            rows: [[x.x, x.y, x.z], [y.x, y.y, y.z], [z.x, z.y, z.z]],
+        }
+    }
+
+    pub fn from_diagonal(diagonal: &Vec3D) -> Self {
+        Self {
+            rows: [
+                [diagonal.x, 0.0, 0.0],
+                [0.0, diagonal.y, 0.0],
+                [0.0, 0.0, diagonal.z],
+            ],
+        }
+    }
+
+    /// Rotation matrix for {angle} [rad] about {axis}. See `Quaternion::from_axis_angle`
+    pub fn from_axis_angle(axis: &Vec3D, angle: f64) -> Self {
+        Quaternion::from_axis_angle(axis, angle).into()
+    }
+
+    /// Rotation matrix in the convention of `Quaternion::from_euler_angles`, i.e., R = Rz(yaw) * Ry(pitch) * Rx(roll)
+    pub fn from_euler_angles(roll: f64, pitch: f64, yaw: f64) -> Self {
+        Quaternion::from_euler_angles(roll, pitch, yaw).into()
+    }
+
+    /// (roll, pitch, yaw) [rad] of a rotation matrix, in the convention of `from_euler_angles`
+    pub fn to_euler_angles(&self) -> (f64, f64, f64) {
+        let roll = self.rows[2][1].atan2(self.rows[2][2]);
+        let pitch = (-self.rows[2][0]).clamp(-1.0, 1.0).asin();
+        let yaw = self.rows[1][0].atan2(self.rows[0][0]);
+        (roll, pitch, yaw)
+    }
+
+    /// Unit quaternion of a rotation matrix
+    pub fn to_quaternion(&self) -> Quaternion {
+        Quaternion::from(*self)
+    }
+
+    /// {a} * {b}^T
+    pub fn outer_product(a: &Vec3D, b: &Vec3D) -> Self {
+        Self::from_rows(&(a.x * b), &(a.y * b), &(a.z * b))
+    }
+
+    pub fn row(&self, row: usize) -> Vec3D {
+        let [x, y, z] = self.rows[row];
+        Vec3D::new(x, y, z)
+    }
+
+    pub fn column(&self, column: usize) -> Vec3D {
+        Vec3D::new(
+            self.rows[0][column],
+            self.rows[1][column],
+            self.rows[2][column],
+        )
+    }
+
+    #[must_use]
+    pub fn transpose(&self) -> Self {
+        Self::from_rows(&self.column(0), &self.column(1), &self.column(2))
+    }
+
+    pub fn determinant(&self) -> f64 {
+        self.row(0) * self.row(1).cross_product(&self.row(2))
+    }
+
+    /// None if the matrix is (nearly) singular
+    pub fn inverse(&self) -> Option<Self> {
+        let determinant = self.determinant();
+        if determinant.abs() < Vec3D::THRESHOLD {
+            return None;
+        }
+
+        // The columns of the inverse are the cross products of the rows, divided by the determinant
+        let (a, b, c) = (self.row(0), self.row(1), self.row(2));
+        let adjugate = Self::from_rows(
+            &b.cross_product(&c),
+            &c.cross_product(&a),
+            &a.cross_product(&b),
+        )
+        .transpose();
+        Some(adjugate * (1.0 / determinant))
+    }
+}
+
+impl Index<(usize, usize)> for Mat3 {
+    type Output = f64;
+
+    fn index(&self, (row, column): (usize, usize)) -> &Self::Output {
+        &self.rows[row][column]
+    }
+}
+
+impl IndexMut<(usize, usize)> for Mat3 {
+    fn index_mut(&mut self, (row, column): (usize, usize)) -> &mut Self::Output {
+        &mut self.rows[row][column]
+    }
+}
+
+impl Add<Mat3> for Mat3 {
+    type Output = Mat3;
+
+    fn add(mut self, rhs: Mat3) -> Self::Output {
+        self += rhs;
+        self
+    }
+}
+
+impl AddAssign<Mat3> for Mat3 {
+    fn add_assign(&mut self, rhs: Mat3) {
+        for (row, rhs_row) in self.rows.iter_mut().zip(rhs.rows) {
+            for (element, rhs_element) in row.iter_mut().zip(rhs_row) {
+                *element += rhs_element;
+            }
+        }
+    }
+}
+
+impl Sub<Mat3> for Mat3 {
+    type Output = Mat3;
+
+    fn sub(self, rhs: Mat3) -> Self::Output {
+        self + -rhs
+    }
+}
+
+impl SubAssign<Mat3> for Mat3 {
+    fn sub_assign(&mut self, rhs: Mat3) {
+        *self += -rhs;
+    }
+}
+
+impl Neg for Mat3 {
+    type Output = Mat3;
+
+    fn neg(self) -> Self::Output {
+        self * -1.0
+    }
+}
+
+impl Mul<f64> for Mat3 {
+    type Output = Mat3;
+
+    fn mul(self, rhs: f64) -> Self::Output {
+        Self {
+            rows: self.rows.map(|row| row.map(|element| element * rhs)),
+        }
+    }
+}
+
+impl Mul<Vec3D> for Mat3 {
+    type Output = Vec3D;
+
+    fn mul(self, rhs: Vec3D) -> Self::Output {
+        Vec3D::new(self.row(0) * rhs, self.row(1) * rhs, self.row(2) * rhs)
+    }
+}
+
+impl Mul<Vec3D> for &Mat3 {
+    type Output = Vec3D;
+
+    fn mul(self, rhs: Vec3D) -> Self::Output {
+        *self * rhs
+    }
+}
+
+impl Mul<Mat3> for Mat3 {
+    type Output = Mat3;
+
+    fn mul(self, rhs: Mat3) -> Self::Output {
+        let columns = [rhs.column(0), rhs.column(1), rhs.column(2)];
+        Self {
+            rows: [0, 1, 2].map(|row| columns.map(|column| self.row(row) * column)),
+        }
+    }
+}
+
+impl From<Quaternion> for Mat3 {
+    /// Rotation matrix of a (unit) quaternion, such that `Mat3::from(q) * v == q.rotate(&v)`
+    fn from(q: Quaternion) -> Self {
+        let Quaternion { w, x, y, z } = q.normalized();
+        Self {
+            rows: [
+                [
+                    1.0 - 2.0 * (y * y + z * z),
+                    2.0 * (x * y - w * z),
+                    2.0 * (x * z + w * y),
+                ],
+                [
+                    2.0 * (x * y + w * z),
+                    1.0 - 2.0 * (x * x + z * z),
+                    2.0 * (y * z - w * x),
+                ],
+                [
+                    2.0 * (x * z - w * y),
+                    2.0 * (y * z + w * x),
+                    1.0 - 2.0 * (x * x + y * y),
+                ],
+            ],
+        }
+    }
+}
+
+impl From<Mat3> for Quaternion {
+    /// Quaternion of a rotation matrix. See: https://www.euclideanspace.com/maths/geometry/rotations/conversions/matrixToQuaternion/
+    fn from(matrix: Mat3) -> Self {
+        let m = matrix.rows;
+        let trace = m[0][0] + m[1][1] + m[2][2];
+        // Dividing by the largest of the candidates keeps the result accurate for any rotation
+        let q = if trace > 0.0 {
+            let s = 2.0 * (trace + 1.0).sqrt(); // 4w
+            Quaternion::new(
+                s / 4.0,
+                (m[2][1] - m[1][2]) / s,
+                (m[0][2] - m[2][0]) / s,
+                (m[1][0] - m[0][1]) / s,
+            )
+        } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+            let s = 2.0 * (1.0 + m[0][0] - m[1][1] - m[2][2]).sqrt(); // 4x
+            Quaternion::new(
+                (m[2][1] - m[1][2]) / s,
+                s / 4.0,
+                (m[0][1] + m[1][0]) / s,
+                (m[0][2] + m[2][0]) / s,
+            )
+        } else if m[1][1] > m[2][2] {
+            let s = 2.0 * (1.0 + m[1][1] - m[0][0] - m[2][2]).sqrt(); // 4y
+            Quaternion::new(
+                (m[0][2] - m[2][0]) / s,
+                (m[0][1] + m[1][0]) / s,
+                s / 4.0,
+                (m[1][2] + m[2][1]) / s,
+            )
+        } else {
+            let s = 2.0 * (1.0 + m[2][2] - m[0][0] - m[1][1]).sqrt(); // 4z
+            Quaternion::new(
+                (m[1][0] - m[0][1]) / s,
+                (m[0][2] + m[2][0]) / s,
+                (m[1][2] + m[2][1]) / s,
+                s / 4.0,
+            )
+        };
+        q.normalized()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn near(a: &Mat3, b: &Mat3) -> bool {
+        (0..3).all(|row| (a.row(row) - b.row(row)).near_zero())
+    }
+
+    #[test]
+    fn test_mul() {
+        let a = Mat3::from_rows(
+            &Vec3D::new(1, 2, 3),
+            &Vec3D::new(4, 5, 6),
+            &Vec3D::new(7, 8, 9),
+        );
+        assert_eq!(a * Vec3D::new(1, 0, -1), Vec3D::new(-2, -2, -2));
+        assert_eq!(a * Mat3::IDENTITY, a);
+        assert_eq!(
+            a * Mat3::from_diagonal(&Vec3D::new(2, 1, 0)),
+            Mat3::from_rows(
+                &Vec3D::new(2, 2, 0),
+                &Vec3D::new(8, 5, 0),
+                &Vec3D::new(14, 8, 0)
+            )
+        );
+        assert_eq!(a.transpose().row(0), a.column(0));
+    }
+
+    #[test]
+    fn test_inverse() {
+        let a = Mat3::from_rows(
+            &Vec3D::new(2, 0, 1),
+            &Vec3D::new(1, 3, 0),
+            &Vec3D::new(0, 1, 4),
+        );
+        assert_eq!(a.determinant(), 25.0);
+        assert!(near(&(a * a.inverse().unwrap()), &Mat3::IDENTITY));
+        assert!(near(&(a.inverse().unwrap() * a), &Mat3::IDENTITY));
+
+        let singular = Mat3::outer_product(&Vec3D::new(1, 2, 3), &Vec3D::new(4, 5, 6));
+        assert!(singular.inverse().is_none());
+    }
+
+    #[test]
+    fn test_quaternion_conversion() {
+        let v = Vec3D::new(0.3, -1.2, 2.0);
+        // Includes rotations by (almost) 180 degrees, where the trace is negative
+        for (axis, angle) in [
+            (Vec3D::new(1, 2, 3), 0.4),
+            (Vec3D::new(1, 0, 0), 3.1),
+            (Vec3D::new(0, 1, 0), -3.1),
+            (Vec3D::new(0, 0, 1), std::f64::consts::PI),
+        ] {
+            let q = Quaternion::from_axis_angle(&axis, angle);
+            let matrix = Mat3::from(q);
+            assert!((matrix * v - q.rotate(&v)).near_zero());
+            assert!((matrix.determinant() - 1.0).abs() < Vec3D::THRESHOLD);
+            assert!(near(&(matrix * matrix.transpose()), &Mat3::IDENTITY));
+
+            let result = matrix.to_quaternion();
+            assert!((result.dot(&q).abs() - 1.0).abs() < Vec3D::THRESHOLD);
+        }
+    }
+
+    #[test]
+    fn test_euler_angles() {
+        let (roll, pitch, yaw) = (-0.3, 0.8, -2.0);
+        let matrix = Mat3::from_euler_angles(roll, pitch, yaw);
+        let expected = Mat3::from_axis_angle(&Vec3D::new(0, 0, 1), yaw)
+            * Mat3::from_axis_angle(&Vec3D::new(0, 1, 0), pitch)
+            * Mat3::from_axis_angle(&Vec3D::new(1, 0, 0), roll);
+        assert!(near(&matrix, &expected));
+
+        let (result_roll, result_pitch, result_yaw) = matrix.to_euler_angles();
+        assert!((result_roll - roll).abs() < Vec3D::THRESHOLD);
+        assert!((result_pitch - pitch).abs() < Vec3D::THRESHOLD);
+        assert!((result_yaw - yaw).abs() < Vec3D::THRESHOLD);
+    }
+}
@@ -0,0 +1,190 @@
+use std::ops::{Add, Index, IndexMut, Mul, Sub};
+
+/// Fixed size ROWS x COLUMNS matrix, stored row by row on the stack. Column vectors are `Matrix<N, 1>`.
+/// For rotations and calibration in 3D, `Mat3` is more convenient.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Matrix<const ROWS: usize, const COLUMNS: usize> {
+    pub data: [[f64; COLUMNS]; ROWS],
+}
+
+impl<const ROWS: usize, const COLUMNS: usize> Matrix<ROWS, COLUMNS> {
+    pub const ZERO: Self = Self {
+        data: [[0.0; COLUMNS]; ROWS],
+    };
+
+    pub fn new(data: [[f64; COLUMNS]; ROWS]) -> Self {
+        Self { data }
+    }
+
+    #[must_use]
+    pub fn transpose(&self) -> Matrix<COLUMNS, ROWS> {
+        let mut result = Matrix::<COLUMNS, ROWS>::ZERO;
+        for (row, values) in self.data.iter().enumerate() {
+            for (column, value) in values.iter().enumerate() {
+                result.data[column][row] = *value;
+            }
+        }
+        result
+    }
+}
+
+impl<const N: usize> Matrix<N, 1> {
+    pub fn from_column(column: [f64; N]) -> Self {
+        Self {
+            data: column.map(|value| [value]),
+        }
+    }
+
+    pub fn to_column(&self) -> [f64; N] {
+        self.data.map(|[value]| value)
+    }
+}
+
+impl<const N: usize> Matrix<N, N> {
+    pub fn identity() -> Self {
+        Self::from_diagonal([1.0; N])
+    }
+
+    pub fn from_diagonal(diagonal: [f64; N]) -> Self {
+        let mut result = Self::ZERO;
+        for (i, value) in diagonal.into_iter().enumerate() {
+            result.data[i][i] = value;
+        }
+        result
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting. None if the matrix is (nearly) singular
+    pub fn inverse(&self) -> Option<Self> {
+        let mut matrix = self.data;
+        let mut inverse = Self::identity().data;
+
+        for column in 0..N {
+            let pivot = (column..N)
+                .max_by(|&a, &b| matrix[a][column].abs().total_cmp(&matrix[b][column].abs()))?;
+            if matrix[pivot][column].abs() < 1e-12 {
+                return None;
+            }
+            matrix.swap(column, pivot);
+            inverse.swap(column, pivot);
+
+            let scale = 1.0 / matrix[column][column];
+            matrix[column].iter_mut().for_each(|value| *value *= scale);
+            inverse[column].iter_mut().for_each(|value| *value *= scale);
+
+            for row in (0..N).filter(|&row| row != column) {
+                let factor = matrix[row][column];
+                let (pivot_row, pivot_inverse_row) = (matrix[column], inverse[column]);
+                for (value, pivot_value) in matrix[row].iter_mut().zip(pivot_row) {
+                    *value -= factor * pivot_value;
+                }
+                for (value, pivot_value) in inverse[row].iter_mut().zip(pivot_inverse_row) {
+                    *value -= factor * pivot_value;
+                }
+            }
+        }
+        Some(Self::new(inverse))
+    }
+}
+
+impl<const ROWS: usize, const COLUMNS: usize> Index<(usize, usize)> for Matrix<ROWS, COLUMNS> {
+    type Output = f64;
+
+    fn index(&self, (row, column): (usize, usize)) -> &Self::Output {
+        &self.data[row][column]
+    }
+}
+
+impl<const ROWS: usize, const COLUMNS: usize> IndexMut<(usize, usize)> for Matrix<ROWS, COLUMNS> {
+    fn index_mut(&mut self, (row, column): (usize, usize)) -> &mut Self::Output {
+        &mut self.data[row][column]
+    }
+}
+
+impl<const ROWS: usize, const COLUMNS: usize> Add for Matrix<ROWS, COLUMNS> {
+    type Output = Self;
+
+    fn add(mut self, rhs: Self) -> Self::Output {
+        for (row, rhs_row) in self.data.iter_mut().zip(rhs.data) {
+            for (value, rhs_value) in row.iter_mut().zip(rhs_row) {
+                *value += rhs_value;
+            }
+        }
+        self
+    }
+}
+
+impl<const ROWS: usize, const COLUMNS: usize> Sub for Matrix<ROWS, COLUMNS> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        self + rhs * -1.0
+    }
+}
+
+impl<const ROWS: usize, const COLUMNS: usize> Mul<f64> for Matrix<ROWS, COLUMNS> {
+    type Output = Self;
+
+    fn mul(self, rhs: f64) -> Self::Output {
+        Self {
+            data: self.data.map(|row| row.map(|value| value * rhs)),
+        }
+    }
+}
+
+impl<const ROWS: usize, const INNER: usize, const COLUMNS: usize> Mul<Matrix<INNER, COLUMNS>>
+    for Matrix<ROWS, INNER>
+{
+    type Output = Matrix<ROWS, COLUMNS>;
+
+    fn mul(self, rhs: Matrix<INNER, COLUMNS>) -> Self::Output {
+        let mut result = Matrix::<ROWS, COLUMNS>::ZERO;
+        for (result_row, row) in result.data.iter_mut().zip(self.data) {
+            for (value, rhs_row) in row.iter().zip(rhs.data) {
+                for (result_value, rhs_value) in result_row.iter_mut().zip(rhs_row) {
+                    *result_value += value * rhs_value;
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mul() {
+        let a = Matrix::new([[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]]);
+        let b = Matrix::new([[1.0, 0.0], [0.0, 1.0], [1.0, -1.0]]);
+        assert_eq!(a * b, Matrix::new([[4.0, -1.0], [10.0, -1.0]]));
+        assert_eq!(a * Matrix::<3, 3>::identity(), a);
+        assert_eq!(
+            a * Matrix::from_column([1.0, 1.0, 1.0]),
+            Matrix::from_column([6.0, 15.0])
+        );
+        assert_eq!(a.transpose().transpose(), a);
+        assert_eq!(a.transpose()[(2, 1)], 6.0);
+    }
+
+    #[test]
+    fn test_inverse() {
+        // Needs pivoting, as the first diagonal element is 0
+        let a = Matrix::new([
+            [0.0, 2.0, 1.0, 0.0],
+            [1.0, 0.0, 0.0, 3.0],
+            [0.0, 1.0, 4.0, 0.0],
+            [2.0, 0.0, 1.0, 1.0],
+        ]);
+        let product = a * a.inverse().unwrap();
+        let difference = product - Matrix::identity();
+        assert!(difference
+            .data
+            .iter()
+            .flatten()
+            .all(|value| value.abs() < 1e-12));
+
+        let singular = Matrix::new([[1.0, 2.0], [2.0, 4.0]]);
+        assert!(singular.inverse().is_none());
+    }
+}
@@ -0,0 +1,231 @@
+use std::ops::{Mul, MulAssign};
+
+use super::Vec3D;
+
+/// Quaternion w + xi + yj + zk. Rotations are represented by unit quaternions, and rotate vectors actively, i.e., `q.rotate(v)` turns {v} itself.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Quaternion {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Quaternion {
+    pub const IDENTITY: Self = Self {
+        w: 1.0,
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+
+    pub fn new<W: Into<f64>, X: Into<f64>, Y: Into<f64>, Z: Into<f64>>(
+        w: W,
+        x: X,
+        y: Y,
+        z: Z,
+    ) -> Self {
+        Self {
+            w: w.into(),
+            x: x.into(),
+            y: y.into(),
+            z: z.into(),
+        }
+    }
+
+    /// Rotation by {angle} [rad] about {axis}, counterclockwise when looking against the axis (right hand rule)
+    pub fn from_axis_angle(axis: &Vec3D, angle: f64) -> Self {
+        let axis = axis.normalized();
+        let (sin, cos) = (angle / 2.0).sin_cos();
+        Self::new(cos, axis.x * sin, axis.y * sin, axis.z * sin)
+    }
+
+    /// Rotation from roll, pitch, and yaw [rad], applied in the order yaw (z), pitch (y), roll (x) about the rotating axes (aerospace convention)
+    /// Shorthand for `RotationOrder::ZYX`. See `EulerAngles` for other orders
+    pub fn from_euler_angles(roll: f64, pitch: f64, yaw: f64) -> Self {
+        Self::from_axis_angle(&Vec3D::new(0, 0, 1), yaw)
+            * Self::from_axis_angle(&Vec3D::new(0, 1, 0), pitch)
+            * Self::from_axis_angle(&Vec3D::new(1, 0, 0), roll)
+    }
+
+    /// (axis, angle [rad]) with the angle in [0, 2 pi]. The axis is arbitrary for the identity
+    pub fn to_axis_angle(&self) -> (Vec3D, f64) {
+        let q = self.normalized();
+        let sin = Vec3D::new(q.x, q.y, q.z).magnitude();
+        let angle = 2.0 * sin.atan2(q.w);
+        if sin < Vec3D::THRESHOLD {
+            return (Vec3D::new(1, 0, 0), angle);
+        }
+        (Vec3D::new(q.x, q.y, q.z) / sin, angle)
+    }
+
+    /// (roll, pitch, yaw) [rad] in the convention of `from_euler_angles`
+    pub fn to_euler_angles(&self) -> (f64, f64, f64) {
+        let q = self.normalized();
+        let roll = (2.0 * (q.w * q.x + q.y * q.z)).atan2(1.0 - 2.0 * (q.x.powi(2) + q.y.powi(2)));
+        let pitch = (2.0 * (q.w * q.y - q.z * q.x)).clamp(-1.0, 1.0).asin(); // Clamped against rounding errors at +-90 degrees
+        let yaw = (2.0 * (q.w * q.z + q.x * q.y)).atan2(1.0 - 2.0 * (q.y.powi(2) + q.z.powi(2)));
+        (roll, pitch, yaw)
+    }
+
+    pub fn norm(&self) -> f64 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn dot(&self, rhs: &Quaternion) -> f64 {
+        self.w * rhs.w + self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
+    }
+
+    pub fn normalize(&mut self) {
+        *self = self.normalized();
+    }
+
+    #[must_use]
+    pub fn normalized(&self) -> Self {
+        let norm = self.norm();
+        Self::new(self.w / norm, self.x / norm, self.y / norm, self.z / norm)
+    }
+
+    /// The inverse rotation, for unit quaternions
+    #[must_use]
+    pub fn conjugate(&self) -> Self {
+        Self::new(self.w, -self.x, -self.y, -self.z)
+    }
+
+    /// Rotates {vector} by this (unit) quaternion
+    pub fn rotate(&self, vector: &Vec3D) -> Vec3D {
+        // v' = v + 2w(u x v) + 2u x (u x v), with u being the vector part. Cheaper than q * v * q^-1
+        let u = Vec3D::new(self.x, self.y, self.z);
+        let t = 2.0 * u.cross_product(vector);
+        *vector + self.w * t + u.cross_product(&t)
+    }
+
+    /// Spherical linear interpolation from this rotation ({t} = 0) to {target} ({t} = 1) along the shorter arc
+    pub fn slerp(&self, target: &Quaternion, t: f64) -> Self {
+        let (a, mut b) = (self.normalized(), target.normalized());
+        let mut cos = a.dot(&b);
+        if cos < 0.0 {
+            // q and -q are the same rotation. Flipping one takes the shorter way around
+            b = Self::new(-b.w, -b.x, -b.y, -b.z);
+            cos = -cos;
+        }
+
+        let (weight_a, weight_b) = if cos > 1.0 - Vec3D::THRESHOLD {
+            (1.0 - t, t) // Nearly identical. Linear interpolation avoids dividing by sin(0)
+        } else {
+            let angle = cos.acos();
+            let sin = angle.sin();
+            (((1.0 - t) * angle).sin() / sin, (t * angle).sin() / sin)
+        };
+
+        Self::new(
+            weight_a * a.w + weight_b * b.w,
+            weight_a * a.x + weight_b * b.x,
+            weight_a * a.y + weight_b * b.y,
+            weight_a * a.z + weight_b * b.z,
+        )
+        .normalized()
+    }
+}
+
+impl Default for Quaternion {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+/// Hamilton product. `a * b` rotates by b first, then by a
+impl Mul<Quaternion> for Quaternion {
+    type Output = Quaternion;
+
+    fn mul(self, rhs: Quaternion) -> Self::Output {
+        Self::new(
+            self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z,
+            self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+            self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+            self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w,
+        )
+    }
+}
+
+impl MulAssign<Quaternion> for Quaternion {
+    fn mul_assign(&mut self, rhs: Quaternion) {
+        *self = *self * rhs;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+    use super::*;
+
+    fn near(a: &Quaternion, b: &Quaternion) -> bool {
+        // q and -q are the same rotation
+        (a.dot(b).abs() - 1.0).abs() < Vec3D::THRESHOLD
+    }
+
+    #[test]
+    fn test_rotate() {
+        let q = Quaternion::from_axis_angle(&Vec3D::new(0, 0, 1), FRAC_PI_2);
+        assert!((q.rotate(&Vec3D::new(1, 0, 0)) - Vec3D::new(0, 1, 0)).near_zero());
+        assert!((q.conjugate().rotate(&Vec3D::new(0, 1, 0)) - Vec3D::new(1, 0, 0)).near_zero());
+        assert!((q.rotate(&Vec3D::new(0, 0, 2)) - Vec3D::new(0, 0, 2)).near_zero());
+    }
+
+    #[test]
+    fn test_mul() {
+        let x = Quaternion::from_axis_angle(&Vec3D::new(1, 0, 0), FRAC_PI_2);
+        let z = Quaternion::from_axis_angle(&Vec3D::new(0, 0, 1), FRAC_PI_2);
+        // x first: y -> z, then z: z stays z
+        assert!(((z * x).rotate(&Vec3D::new(0, 1, 0)) - Vec3D::new(0, 0, 1)).near_zero());
+        // z first: y -> -x, then x: -x stays -x
+        assert!(((x * z).rotate(&Vec3D::new(0, 1, 0)) - Vec3D::new(-1, 0, 0)).near_zero());
+        assert!(near(&(x * x.conjugate()), &Quaternion::IDENTITY));
+        assert!(near(&(z * z * z * z), &Quaternion::IDENTITY));
+    }
+
+    #[test]
+    fn test_axis_angle() {
+        let axis = Vec3D::new(1, 2, 3).normalized();
+        let (result_axis, angle) = Quaternion::from_axis_angle(&axis, 0.7).to_axis_angle();
+        assert!((result_axis - axis).near_zero());
+        assert!((angle - 0.7).abs() < Vec3D::THRESHOLD);
+    }
+
+    #[test]
+    fn test_euler_angles() {
+        let (roll, pitch, yaw) = (0.1, -0.4, 2.5);
+        let q = Quaternion::from_euler_angles(roll, pitch, yaw);
+        let (result_roll, result_pitch, result_yaw) = q.to_euler_angles();
+        assert!((result_roll - roll).abs() < Vec3D::THRESHOLD);
+        assert!((result_pitch - pitch).abs() < Vec3D::THRESHOLD);
+        assert!((result_yaw - yaw).abs() < Vec3D::THRESHOLD);
+
+        // Pitching up by 90 degrees turns the nose (x) up, which is -z in a z-down body frame
+        let nose = Quaternion::from_euler_angles(0.0, FRAC_PI_2, 0.0).rotate(&Vec3D::new(1, 0, 0));
+        assert!((nose - Vec3D::new(0, 0, -1)).near_zero());
+    }
+
+    #[test]
+    fn test_slerp() {
+        let a = Quaternion::IDENTITY;
+        let b = Quaternion::from_axis_angle(&Vec3D::new(0, 0, 1), FRAC_PI_2);
+        assert!(near(&a.slerp(&b, 0.0), &a));
+        assert!(near(&a.slerp(&b, 1.0), &b));
+        let halfway = Quaternion::from_axis_angle(&Vec3D::new(0, 0, 1), FRAC_PI_4);
+        assert!(near(&a.slerp(&b, 0.5), &halfway));
+
+        // Takes the shorter way around, even with the sign of the target flipped
+        let c = Quaternion::from_axis_angle(&Vec3D::new(0, 0, 1), 1.5 * PI);
+        let short_halfway = Quaternion::from_axis_angle(&Vec3D::new(0, 0, 1), -FRAC_PI_4);
+        assert!(near(&a.slerp(&c, 0.5), &short_halfway));
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut q = Quaternion::new(1, 2, 3, 4);
+        q.normalize();
+        assert!((q.norm() - 1.0).abs() < Vec3D::THRESHOLD);
+    }
+}
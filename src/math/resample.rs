@@ -0,0 +1,219 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use anyhow::{ensure, Result};
+
+use super::filter::Signal;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interpolation {
+    #[default]
+    Linear, // Straight line between two samples
+    Cubic, // Cubic Hermite spline, with slopes from the neighbouring samples. Smoother, but one sample behind
+}
+
+/// Turns a stream of irregularly timed samples, like the ones from `GY521::wait_for_sample`, into one with a fixed sample rate, for spectra and filters that assume a constant sample period.
+/// Output samples are at multiples of the sample period after the first input sample. Timing jitter of the input would otherwise show up as noise in a spectrum.
+pub struct Resampler<T: Signal> {
+    pub interpolation: Interpolation,
+    period: f64, // [s]
+    start: Option<Instant>,
+    index: u64,                  // Of the next output sample
+    samples: VecDeque<(T, f64)>, // Latest input samples, with their time since start [s]
+}
+
+impl<T: Signal> Resampler<T> {
+    pub fn new(sample_rate: f64, interpolation: Interpolation) -> Result<Self> {
+        ensure!(
+            sample_rate > 0.0 && sample_rate.is_finite(),
+            "Sample rate needs to be positive, but is {sample_rate}."
+        );
+        Ok(Self {
+            interpolation,
+            period: 1.0 / sample_rate,
+            start: None,
+            index: 0,
+            samples: VecDeque::with_capacity(4),
+        })
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        1.0 / self.period
+    }
+
+    /// Adds an input sample, and returns the output samples that are due, as (value, instant) pairs.
+    /// Output goes up to, but not including, the newest sample (with cubic interpolation: the one before). Samples that aren't newer than the previous one are ignored.
+    pub fn push(&mut self, value: T, instant: Instant) -> Vec<(T, Instant)> {
+        let start = *self.start.get_or_insert(instant);
+        let time = instant.saturating_duration_since(start).as_secs_f64();
+        if self
+            .samples
+            .back()
+            .is_some_and(|&(_, previous)| time <= previous)
+        {
+            return Vec::new();
+        }
+        self.samples.push_back((value, time));
+        if self.samples.len() > 4 {
+            self.samples.pop_front();
+        }
+
+        // Cubic interpolation needs the sample after an interval for the slope at its end
+        let length = self.samples.len();
+        match self.interpolation {
+            Interpolation::Linear if length >= 2 => self.interpolate(length - 2, start),
+            Interpolation::Cubic if length >= 3 => self.interpolate(length - 3, start),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Output samples up to the newest input sample, which cubic interpolation holds back while waiting for the next one. Starts over afterwards.
+    pub fn finish(&mut self) -> Vec<(T, Instant)> {
+        let output = match (self.interpolation, self.start) {
+            (Interpolation::Cubic, Some(start)) if self.samples.len() >= 2 => {
+                self.interpolate(self.samples.len() - 2, start)
+            }
+            _ => Vec::new(),
+        };
+        self.reset();
+        output
+    }
+
+    pub fn reset(&mut self) {
+        self.start = None;
+        self.index = 0;
+        self.samples.clear();
+    }
+
+    // Output samples between input samples {first} and {first} + 1
+    fn interpolate(&mut self, first: usize, start: Instant) -> Vec<(T, Instant)> {
+        let (value_a, time_a) = self.samples[first];
+        let (value_b, time_b) = self.samples[first + 1];
+        let step = time_b - time_a;
+        let slopes = (self.interpolation == Interpolation::Cubic)
+            .then(|| (self.slope(first), self.slope(first + 1)));
+
+        let mut output = Vec::new();
+        loop {
+            let time = self.index as f64 * self.period;
+            if time >= time_b {
+                break;
+            }
+            let s = (time - time_a) / step; // [0, 1)
+            let value = match slopes {
+                Some((slope_a, slope_b)) => {
+                    let (s2, s3) = (s * s, s * s * s);
+                    value_a * (2.0 * s3 - 3.0 * s2 + 1.0)
+                        + slope_a * ((s3 - 2.0 * s2 + s) * step)
+                        + value_b * (3.0 * s2 - 2.0 * s3)
+                        + slope_b * ((s3 - s2) * step)
+                }
+                None => value_a + (value_b - value_a) * s,
+            };
+            output.push((value, start + Duration::from_secs_f64(time)));
+            self.index += 1;
+        }
+        output
+    }
+
+    // Slope at input sample {index}, from a parabola through it and its neighbours, which is exact for uneven steps too. One-sided at the ends
+    fn slope(&self, index: usize) -> T {
+        let (value, time) = self.samples[index];
+        let before = index.checked_sub(1).map(|before| self.samples[before]);
+        let after = self.samples.get(index + 1).copied();
+        match (before, after) {
+            (Some((before, before_time)), Some((after, after_time))) => {
+                let (step_before, step_after) = (time - before_time, after_time - time);
+                (after - value) * (step_before / (step_after * (step_before + step_after)))
+                    + (value - before) * (step_after / (step_before * (step_before + step_after)))
+            }
+            (Some((before, before_time)), None) => (value - before) * (1.0 / (time - before_time)),
+            (None, Some((after, after_time))) => (after - value) * (1.0 / (after_time - time)),
+            (None, None) => T::default(),
+        }
+    }
+}
+
+/// All of {samples}, given as (value, instant) pairs in chronological order, resampled to {sample_rate} [Hz]
+pub fn resample<T: Signal>(
+    samples: &[(T, Instant)],
+    sample_rate: f64,
+    interpolation: Interpolation,
+) -> Result<Vec<T>> {
+    let mut resampler = Resampler::new(sample_rate, interpolation)?;
+    let mut output = samples
+        .iter()
+        .flat_map(|&(value, instant)| resampler.push(value, instant))
+        .collect::<Vec<_>>();
+    output.extend(resampler.finish());
+    Ok(output.into_iter().map(|(value, _)| value).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+    use crate::math::Vec3D;
+
+    // About 100 Hz, with up to +-3 ms of jitter, over one second
+    fn jittery_instants(start: Instant) -> Vec<Instant> {
+        (0..=100)
+            .map(|n| {
+                let jitter = [0, 3, -2, 1, -3, 2][n % 6];
+                start + Duration::from_millis((10 * n as i64 + jitter).max(0) as u64)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_linear() {
+        let start = Instant::now();
+        let samples = jittery_instants(start)
+            .into_iter()
+            .map(|instant| {
+                let t = (instant - start).as_secs_f64();
+                (Vec3D::new(t, -2.0 * t, 1.0), instant)
+            })
+            .collect::<Vec<_>>();
+
+        let mut resampler = Resampler::new(50.0, Interpolation::Linear).unwrap();
+        let output = samples
+            .iter()
+            .flat_map(|&(value, instant)| resampler.push(value, instant))
+            .collect::<Vec<_>>();
+        assert_eq!(output.len(), 50); // Up to, but not including, the last sample at 1 s
+        for (n, (value, instant)) in output.iter().enumerate() {
+            let t = n as f64 / 50.0;
+            assert!(((*instant - start).as_secs_f64() - t).abs() < 1e-6);
+            assert!(value.approx_eq(&Vec3D::new(t, -2.0 * t, 1.0), 1e-9));
+        }
+        assert!(Resampler::<f64>::new(0.0, Interpolation::Linear).is_err());
+    }
+
+    #[test]
+    fn test_cubic() {
+        let start = Instant::now();
+        let signal = |t: f64| (2.0 * PI * 5.0 * t).sin();
+        let samples = jittery_instants(start)
+            .into_iter()
+            .map(|instant| (signal((instant - start).as_secs_f64()), instant))
+            .collect::<Vec<_>>();
+
+        let error = |interpolation| {
+            let output = resample(&samples, 200.0, interpolation).unwrap();
+            assert_eq!(output.len(), 200);
+            output
+                .iter()
+                .enumerate()
+                .map(|(n, value)| (value - signal(n as f64 / 200.0)).abs())
+                .fold(0.0, f64::max)
+        };
+        let linear = error(Interpolation::Linear);
+        let cubic = error(Interpolation::Cubic);
+        assert!(cubic < 0.01);
+        assert!(cubic < linear / 3.0);
+    }
+}
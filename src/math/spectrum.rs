@@ -0,0 +1,243 @@
+use std::f64::consts::PI;
+
+use anyhow::{ensure, Result};
+
+use super::Vec3D;
+use crate::utilites::Memory;
+
+/// Amplitude spectrum of each axis of a vector signal, e.g., acceleration for vibration monitoring.
+/// Samples are weighted with a Hann window, so a sine shows up with its amplitude, spread over about two neighbouring bins.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Spectrum {
+    pub resolution: f64,        // [Hz] Bin k is at k * resolution
+    pub amplitudes: Vec<Vec3D>, // [unit of the signal] Bins from 0 Hz up to the Nyquist frequency
+}
+
+impl Spectrum {
+    /// Spectrum of {samples} taken at {sample_rate} [Hz]. The number of samples needs to be a power of two.
+    pub fn compute(samples: &[Vec3D], sample_rate: f64) -> Result<Self> {
+        let size = samples.len();
+        ensure!(
+            size >= 2 && size.is_power_of_two(),
+            "Spectrum needs a power of two of samples, but got {size}."
+        );
+
+        let window = (0..size)
+            .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f64 / size as f64).cos())
+            .collect::<Vec<_>>();
+        let window_sum = window.iter().sum::<f64>();
+
+        let mut amplitudes = vec![Vec3D::default(); size / 2 + 1];
+        for axis in 0..3 {
+            let mut real = samples
+                .iter()
+                .zip(&window)
+                .map(|(sample, weight)| sample[axis] * weight)
+                .collect::<Vec<_>>();
+            let mut imaginary = vec![0.0; size];
+            fft(&mut real, &mut imaginary);
+
+            for (bin, amplitude) in amplitudes.iter_mut().enumerate() {
+                // Energy of all bins but DC and Nyquist is split between positive and negative frequencies
+                let scale = if bin == 0 || bin == size / 2 {
+                    1.0
+                } else {
+                    2.0
+                };
+                amplitude[axis] = scale * real[bin].hypot(imaginary[bin]) / window_sum;
+            }
+        }
+
+        Ok(Self {
+            resolution: sample_rate / size as f64,
+            amplitudes,
+        })
+    }
+
+    pub fn frequencies(&self) -> impl Iterator<Item = f64> + '_ {
+        (0..self.amplitudes.len()).map(|bin| bin as f64 * self.resolution)
+    }
+
+    /// (frequency [Hz], amplitude) of the strongest bin of {axis}, ignoring DC (e.g. gravity), which the window also spreads into the first bin
+    pub fn peak(&self, axis: usize) -> (f64, f64) {
+        self.amplitudes
+            .iter()
+            .enumerate()
+            .skip(2)
+            .map(|(bin, amplitude)| (bin as f64 * self.resolution, amplitude[axis]))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .unwrap_or((0.0, 0.0))
+    }
+
+    /// Root sum square of the amplitudes from {low} to {high} [Hz], per axis, e.g., for watching the band around a propeller's rotation rate
+    pub fn band_amplitude(&self, low: f64, high: f64) -> Vec3D {
+        let sum = self
+            .frequencies()
+            .zip(&self.amplitudes)
+            .filter(|(frequency, _)| (low..=high).contains(frequency))
+            .fold(Vec3D::default(), |sum, (_, amplitude)| {
+                sum + amplitude.component_mul(amplitude)
+            });
+        Vec3D::new(sum.x.sqrt(), sum.y.sqrt(), sum.z.sqrt())
+    }
+}
+
+/// Computes spectra over a sliding window of the latest {size} samples, every {hop} samples.
+pub struct SpectrumAnalyzer {
+    pub sample_rate: f64, // [Hz]
+    pub hop: usize, // Samples between two spectra. Less than the window size gives overlapping windows
+    window: Memory<Vec3D>,
+    since_spectrum: usize,
+}
+
+impl SpectrumAnalyzer {
+    pub fn new(size: usize, hop: usize, sample_rate: f64) -> Result<Self> {
+        ensure!(
+            size >= 2 && size.is_power_of_two(),
+            "Window size needs to be a power of two, but is {size}."
+        );
+        ensure!(hop > 0, "Hop size needs to be at least 1.");
+        Ok(Self {
+            sample_rate,
+            hop,
+            window: Memory::new(size),
+            since_spectrum: 0,
+        })
+    }
+
+    /// Adds a sample. Returns a new spectrum once the window is full and {hop} samples have passed since the previous one.
+    pub fn push(&mut self, sample: Vec3D) -> Option<Spectrum> {
+        self.window.push(sample);
+        self.since_spectrum += 1;
+        if self.window.len() < self.window.capacity() || self.since_spectrum < self.hop {
+            return None;
+        }
+        self.since_spectrum = 0;
+        let samples = self.window.iter().copied().collect::<Vec<_>>();
+        Spectrum::compute(&samples, self.sample_rate).ok()
+    }
+}
+
+/// Amplitude of a single {frequency} [Hz] in {samples} taken at {sample_rate} [Hz] (Goertzel algorithm).
+/// Cheaper than a full spectrum when only a few frequencies matter. The frequency is rounded to the nearest bin.
+pub fn goertzel(samples: &[f64], frequency: f64, sample_rate: f64) -> f64 {
+    let size = samples.len();
+    if size == 0 {
+        return 0.0;
+    }
+    let bin = (frequency * size as f64 / sample_rate).round();
+    let coefficient = 2.0 * (2.0 * PI * bin / size as f64).cos();
+
+    let (mut previous, mut before_previous) = (0.0, 0.0);
+    for sample in samples {
+        let current = sample + coefficient * previous - before_previous;
+        before_previous = previous;
+        previous = current;
+    }
+    let power =
+        previous.powi(2) + before_previous.powi(2) - coefficient * previous * before_previous;
+    let scale = if bin == 0.0 || 2.0 * bin == size as f64 {
+        1.0
+    } else {
+        2.0
+    };
+    scale * power.max(0.0).sqrt() / size as f64
+}
+
+// In place radix 2 FFT (Cooley-Tukey). The length needs to be a power of two
+fn fft(real: &mut [f64], imaginary: &mut [f64]) {
+    let size = real.len();
+
+    // Bit reversal permutation
+    let mut j = 0;
+    for i in 1..size {
+        let mut bit = size >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            real.swap(i, j);
+            imaginary.swap(i, j);
+        }
+    }
+
+    let mut length = 2;
+    while length <= size {
+        let angle = -2.0 * PI / length as f64;
+        for start in (0..size).step_by(length) {
+            for k in 0..length / 2 {
+                let (sin, cos) = (angle * k as f64).sin_cos();
+                let (even, odd) = (start + k, start + k + length / 2);
+                let odd_real = real[odd] * cos - imaginary[odd] * sin;
+                let odd_imaginary = real[odd] * sin + imaginary[odd] * cos;
+                real[odd] = real[even] - odd_real;
+                imaginary[odd] = imaginary[even] - odd_imaginary;
+                real[even] += odd_real;
+                imaginary[even] += odd_imaginary;
+            }
+        }
+        length <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Engine vibration at 25 Hz on x, and propeller imbalance at 12.5 Hz on z
+    fn vibration(n: usize, sample_rate: f64) -> Vec3D {
+        let t = n as f64 / sample_rate;
+        Vec3D::new(
+            0.3 * (2.0 * PI * 25.0 * t).sin(),
+            0.0,
+            1.0 + 0.1 * (2.0 * PI * 12.5 * t).cos(),
+        )
+    }
+
+    #[test]
+    fn test_spectrum() {
+        let sample_rate = 200.0;
+        let samples = (0..256)
+            .map(|n| vibration(n, sample_rate))
+            .collect::<Vec<_>>();
+        let spectrum = Spectrum::compute(&samples, sample_rate).unwrap();
+
+        assert_eq!(spectrum.amplitudes.len(), 129);
+        assert_eq!(spectrum.frequencies().nth(32), Some(25.0));
+        let (frequency, amplitude) = spectrum.peak(0);
+        assert_eq!(frequency, 25.0);
+        assert!((amplitude - 0.3).abs() < 1e-9);
+        assert_eq!(spectrum.peak(2).0, 12.5);
+        assert!((spectrum.amplitudes[0].z - 1.0).abs() < 1e-9);
+        assert!(spectrum
+            .amplitudes
+            .iter()
+            .all(|amplitude| amplitude.y == 0.0));
+        assert!(spectrum.band_amplitude(20.0, 30.0).x > 0.3);
+        assert!(spectrum.band_amplitude(40.0, 100.0).x < 1e-9);
+
+        assert!(Spectrum::compute(&samples[..100], sample_rate).is_err());
+    }
+
+    #[test]
+    fn test_goertzel() {
+        let sample_rate = 200.0;
+        let samples = (0..200)
+            .map(|n| vibration(n, sample_rate).x)
+            .collect::<Vec<_>>();
+        assert!((goertzel(&samples, 25.0, sample_rate) - 0.3).abs() < 1e-9);
+        assert!(goertzel(&samples, 40.0, sample_rate) < 1e-9);
+    }
+
+    #[test]
+    fn test_sliding_window() {
+        let mut analyzer = SpectrumAnalyzer::new(64, 16, 200.0).unwrap();
+        let spectra = (0..128)
+            .filter_map(|n| analyzer.push(vibration(n, 200.0)))
+            .count();
+        assert_eq!(spectra, 5); // After 64, 80, 96, 112, and 128 samples
+        assert!(SpectrumAnalyzer::new(100, 16, 200.0).is_err());
+    }
+}
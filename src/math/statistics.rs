@@ -0,0 +1,420 @@
+use std::collections::VecDeque;
+
+use super::Vec3D;
+use crate::utilites::Memory;
+
+/// Running statistics of each axis of a stream of vectors, e.g., accelerations. Samples are added one at a time, without being stored.
+/// Mean and variance use Welford's algorithm, which stays accurate for long streams with a large offset (like gravity).
+/// Without samples, everything is zero.
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct Statistics {
+    count: usize,
+    mean: Vec3D,
+    squared_deviations: Vec3D, // Sum of squared deviations from the mean
+    range: Option<(Vec3D, Vec3D)>, // (min, max)
+}
+
+impl Statistics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, sample: Vec3D) {
+        self.count += 1;
+        let delta = sample - self.mean;
+        self.mean += delta / self.count as f64;
+        self.squared_deviations += delta.component_mul(&(sample - self.mean));
+        self.range = Some(match self.range {
+            Some((min, max)) => (min.component_min(&sample), max.component_max(&sample)),
+            None => (sample, sample),
+        });
+    }
+
+    /// Combines the statistics of two streams, as if all samples had been pushed into one
+    #[must_use]
+    pub fn merge(&self, other: &Statistics) -> Self {
+        if other.count == 0 {
+            return *self;
+        }
+        if self.count == 0 {
+            return *other;
+        }
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        let weight = (self.count * other.count) as f64 / count as f64;
+        Self {
+            count,
+            mean: self.mean + delta * (other.count as f64 / count as f64),
+            squared_deviations: self.squared_deviations
+                + other.squared_deviations
+                + delta.component_mul(&delta) * weight,
+            range: self
+                .range
+                .zip(other.range)
+                .map(|((min, max), (other_min, other_max))| {
+                    (min.component_min(&other_min), max.component_max(&other_max))
+                }),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn mean(&self) -> Vec3D {
+        self.mean
+    }
+
+    /// Sample variance (divided by count - 1)
+    pub fn variance(&self) -> Vec3D {
+        match self.count {
+            0 | 1 => Vec3D::default(),
+            count => self.squared_deviations / (count - 1) as f64,
+        }
+    }
+
+    pub fn standard_deviation(&self) -> Vec3D {
+        let variance = self.variance();
+        Vec3D::new(variance.x.sqrt(), variance.y.sqrt(), variance.z.sqrt())
+    }
+
+    /// Root mean square, i.e., including the mean
+    pub fn rms(&self) -> Vec3D {
+        if self.count == 0 {
+            return Vec3D::default();
+        }
+        let mean_square =
+            self.mean.component_mul(&self.mean) + self.squared_deviations / self.count as f64;
+        Vec3D::new(
+            mean_square.x.sqrt(),
+            mean_square.y.sqrt(),
+            mean_square.z.sqrt(),
+        )
+    }
+
+    pub fn min(&self) -> Vec3D {
+        self.range.map_or_else(Vec3D::default, |(min, _)| min)
+    }
+
+    pub fn max(&self) -> Vec3D {
+        self.range.map_or_else(Vec3D::default, |(_, max)| max)
+    }
+
+    pub fn peak_to_peak(&self) -> Vec3D {
+        self.max() - self.min()
+    }
+}
+
+impl Extend<Vec3D> for Statistics {
+    fn extend<I: IntoIterator<Item = Vec3D>>(&mut self, samples: I) {
+        for sample in samples {
+            self.push(sample);
+        }
+    }
+}
+
+impl FromIterator<Vec3D> for Statistics {
+    fn from_iter<I: IntoIterator<Item = Vec3D>>(samples: I) -> Self {
+        let mut statistics = Self::new();
+        statistics.extend(samples);
+        statistics
+    }
+}
+
+/// Statistics over the samples currently in a window
+impl From<&Memory<Vec3D>> for Statistics {
+    fn from(window: &Memory<Vec3D>) -> Self {
+        window.iter().copied().collect()
+    }
+}
+
+/// Numbers that statistics can be computed for, component by component
+pub trait Components: Copy + Default {
+    const COUNT: usize;
+
+    fn component(&self, index: usize) -> f64;
+    fn from_components(component: impl FnMut(usize) -> f64) -> Self;
+}
+
+impl Components for f64 {
+    const COUNT: usize = 1;
+
+    fn component(&self, _index: usize) -> f64 {
+        *self
+    }
+
+    fn from_components(mut component: impl FnMut(usize) -> f64) -> Self {
+        component(0)
+    }
+}
+
+impl Components for Vec3D {
+    const COUNT: usize = 3;
+
+    fn component(&self, index: usize) -> f64 {
+        self[index]
+    }
+
+    fn from_components(mut component: impl FnMut(usize) -> f64) -> Self {
+        Vec3D::new(component(0), component(1), component(2))
+    }
+}
+
+// Running values of one component over the window
+#[derive(Debug, Clone, Default)]
+struct ComponentWindow {
+    shift: f64, // Sums are of values minus this, so that a large offset (like gravity) doesn't eat up the precision of the variance
+    sum: f64,
+    squared_sum: f64,
+    minima: VecDeque<(usize, f64)>, // (push number, value), increasing values. The front is the minimum
+    maxima: VecDeque<(usize, f64)>, // (push number, value), decreasing values. The front is the maximum
+}
+
+/// Min, max, mean, and standard deviation of the latest {capacity} values, per component.
+/// Updated as values are pushed and evicted, so asking for them doesn't scan the window. Min and max use monotonic queues.
+/// Sums are recomputed once per window, so rounding errors from removing values don't pile up.
+pub struct RollingStatistics<T: Components> {
+    window: Memory<T>,
+    components: Vec<ComponentWindow>,
+}
+
+impl<T: Components> RollingStatistics<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            window: Memory::new(capacity),
+            components: vec![ComponentWindow::default(); T::COUNT],
+        }
+    }
+
+    /// Adds a value, and returns the one that got evicted to make room, if any
+    pub fn push(&mut self, value: T) -> Option<T> {
+        let number = self.window.count();
+        let evicted = self.window.push(value);
+        let oldest = self.window.count() - self.window.len(); // Push number of the oldest value still in the window
+
+        for (index, component) in self.components.iter_mut().enumerate() {
+            let x = value.component(index);
+            if let Some(evicted) = evicted {
+                let y = evicted.component(index) - component.shift;
+                component.sum -= y;
+                component.squared_sum -= y * y;
+            }
+            let y = x - component.shift;
+            component.sum += y;
+            component.squared_sum += y * y;
+
+            while component
+                .minima
+                .back()
+                .is_some_and(|(_, minimum)| *minimum >= x)
+            {
+                component.minima.pop_back();
+            }
+            component.minima.push_back((number, x));
+            while component
+                .minima
+                .front()
+                .is_some_and(|(number, _)| *number < oldest)
+            {
+                component.minima.pop_front();
+            }
+            while component
+                .maxima
+                .back()
+                .is_some_and(|(_, maximum)| *maximum <= x)
+            {
+                component.maxima.pop_back();
+            }
+            component.maxima.push_back((number, x));
+            while component
+                .maxima
+                .front()
+                .is_some_and(|(number, _)| *number < oldest)
+            {
+                component.maxima.pop_front();
+            }
+        }
+
+        if self
+            .window
+            .count()
+            .is_multiple_of(self.window.capacity().max(1))
+        {
+            self.recompute();
+        }
+        evicted
+    }
+
+    pub fn window(&self) -> &Memory<T> {
+        &self.window
+    }
+
+    pub fn len(&self) -> usize {
+        self.window.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.window.is_empty()
+    }
+
+    pub fn min(&self) -> Option<T> {
+        self.aggregate(|component| component.minima.front().map(|(_, minimum)| *minimum))
+    }
+
+    pub fn max(&self) -> Option<T> {
+        self.aggregate(|component| component.maxima.front().map(|(_, maximum)| *maximum))
+    }
+
+    pub fn mean(&self) -> Option<T> {
+        let count = self.window.len() as f64;
+        self.aggregate(|component| Some(component.shift + component.sum / count))
+    }
+
+    /// Sample variance (divided by count - 1). None with fewer than two values
+    pub fn variance(&self) -> Option<T> {
+        let count = self.window.len() as f64;
+        (self.window.len() >= 2).then_some(())?;
+        self.aggregate(|component| {
+            Some(
+                ((component.squared_sum - component.sum * component.sum / count) / (count - 1.0))
+                    .max(0.0),
+            )
+        })
+    }
+
+    pub fn standard_deviation(&self) -> Option<T> {
+        let variance = self.variance()?;
+        Some(T::from_components(|index| variance.component(index).sqrt()))
+    }
+
+    // None if the window is empty
+    fn aggregate(&self, statistic: impl Fn(&ComponentWindow) -> Option<f64>) -> Option<T> {
+        if self.window.is_empty() {
+            return None;
+        }
+        let mut missing = false;
+        let value = T::from_components(|index| {
+            statistic(&self.components[index]).unwrap_or_else(|| {
+                missing = true;
+                0.0
+            })
+        });
+        (!missing).then_some(value)
+    }
+
+    fn recompute(&mut self) {
+        for (index, component) in self.components.iter_mut().enumerate() {
+            component.shift = self
+                .window
+                .get(0)
+                .map_or(0.0, |value| value.component(index));
+            component.sum = 0.0;
+            component.squared_sum = 0.0;
+            for value in self.window.iter() {
+                let y = value.component(index) - component.shift;
+                component.sum += y;
+                component.squared_sum += y * y;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_statistics() {
+        let samples = [
+            Vec3D::new(1, -2, 1000),
+            Vec3D::new(2, -4, 1001),
+            Vec3D::new(3, -6, 1002),
+            Vec3D::new(4, -8, 1003),
+        ];
+        let statistics = samples.into_iter().collect::<Statistics>();
+        assert_eq!(statistics.count(), 4);
+        assert!(statistics
+            .mean()
+            .approx_eq(&Vec3D::new(2.5, -5.0, 1001.5), 1e-12));
+        assert!(statistics
+            .variance()
+            .approx_eq(&Vec3D::new(5.0 / 3.0, 20.0 / 3.0, 5.0 / 3.0), 1e-9));
+        assert!((statistics.rms().x - 7.5_f64.sqrt()).abs() < 1e-12);
+        assert_eq!(statistics.min(), Vec3D::new(1, -8, 1000));
+        assert_eq!(statistics.max(), Vec3D::new(4, -2, 1003));
+        assert_eq!(statistics.peak_to_peak(), Vec3D::new(3, 6, 3));
+
+        assert_eq!(Statistics::new().variance(), Vec3D::default());
+        assert_eq!(Statistics::new().peak_to_peak(), Vec3D::default());
+    }
+
+    #[test]
+    fn test_rolling_statistics() {
+        let values = (0..50)
+            .map(|n| {
+                Vec3D::new(
+                    (n * 7 % 11) as f64,
+                    1.0 + 1e-3 * (n % 5) as f64,
+                    -(n as f64),
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut rolling = RollingStatistics::new(8);
+        assert_eq!(rolling.mean(), None);
+        for (n, value) in values.iter().enumerate() {
+            rolling.push(*value);
+            let window = values[n.saturating_sub(7)..=n]
+                .iter()
+                .copied()
+                .collect::<Statistics>();
+            assert!(rolling.mean().unwrap().approx_eq(&window.mean(), 1e-9));
+            assert_eq!(rolling.min().unwrap(), window.min());
+            assert_eq!(rolling.max().unwrap(), window.max());
+            if n > 0 {
+                assert!(rolling
+                    .variance()
+                    .unwrap()
+                    .approx_eq(&window.variance(), 1e-9));
+                assert!(rolling
+                    .standard_deviation()
+                    .unwrap()
+                    .approx_eq(&window.standard_deviation(), 1e-6));
+            }
+        }
+        assert_eq!(rolling.len(), 8);
+
+        let mut rolling = RollingStatistics::<f64>::new(3);
+        rolling.push(2.0);
+        assert_eq!(rolling.variance(), None);
+        rolling.push(4.0);
+        assert_eq!(rolling.mean(), Some(3.0));
+        assert_eq!(rolling.variance(), Some(2.0));
+    }
+
+    #[test]
+    fn test_merge_and_window() {
+        let samples = (0..10)
+            .map(|n| Vec3D::new(n, n * n, -n))
+            .collect::<Vec<_>>();
+        let whole = samples.iter().copied().collect::<Statistics>();
+        let first = samples[..3].iter().copied().collect::<Statistics>();
+        let second = samples[3..].iter().copied().collect::<Statistics>();
+        let merged = first.merge(&second);
+        assert_eq!(merged.count(), whole.count());
+        assert!(merged.mean().approx_eq(&whole.mean(), 1e-12));
+        assert!(merged.variance().approx_eq(&whole.variance(), 1e-9));
+        assert_eq!(merged.min(), whole.min());
+        assert_eq!(first.merge(&Statistics::new()), first);
+
+        let mut window = Memory::new(3);
+        for sample in &samples {
+            window.push(*sample);
+        }
+        let statistics = Statistics::from(&window);
+        assert_eq!(statistics.count(), 3);
+        assert_eq!(
+            statistics.mean(),
+            Vec3D::new(8, (49 + 64 + 81) as f64 / 3.0, -8)
+        );
+    }
+}
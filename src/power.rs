@@ -0,0 +1,210 @@
+// Battery and supply monitoring for unattended deployments: The supply voltage is sampled like that of any other sensor, and once it stays below a threshold, the recording is stopped while there is still enough power to write everything, rather than the battery giving out in the middle of a write.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use rppal::i2c::I2c;
+
+use crate::{
+    gy521::SampleSource,
+    sensors::{ads1115::ADS1115, ina219::INA219},
+    utilites,
+};
+
+/// What measures the supply
+pub enum PowerInput {
+    Ina219 {
+        monitor: INA219, // Initialized
+        i2c: I2c,        // Of the monitor, apart from the GY-521's
+    },
+    Analog {
+        adc: ADS1115, // Initialized, and set up to measure the divided supply voltage, with {configuration.input}
+        i2c: I2c,     // Of the converter, apart from the GY-521's
+        divider: f64, // Supply voltage over the voltage at the input, e.g., 5.7 for a divider of 47 kOhm over 10 kOhm
+    },
+}
+
+impl PowerInput {
+    // Voltage [V], current [A], and power [W], as far as measured
+    fn read(&mut self) -> Result<(f64, Option<f64>, Option<f64>)> {
+        match self {
+            Self::Ina219 { monitor, i2c } => {
+                let sample = monitor.read(i2c)?;
+                Ok((sample.bus_voltage, Some(sample.current), Some(sample.power)))
+            }
+            Self::Analog { adc, i2c, divider } => {
+                let sample = adc.read(i2c)?;
+                Ok((sample.voltage * *divider, None, None))
+            }
+        }
+    }
+}
+
+/// How the supply is doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum SupplyState {
+    #[default]
+    Normal,
+    Low,      // Below the low voltage: Time to recharge
+    Critical, // Below the shutdown voltage: The recording is stopped. There is no way back from this, since a sagging battery recovers some voltage once the load is gone
+}
+
+/// Thresholds of the `SupplyState`s
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PowerConfig {
+    pub low_voltage: f64,      // [V]
+    pub shutdown_voltage: f64, // [V]
+    pub hysteresis: f64, // [V] Above the low voltage, before the state is back to normal, e.g., while charging
+    pub hold: Duration, // How long the voltage needs to stay below a threshold to count, so that it sagging while, e.g., a winch or the engine starter runs, doesn't stop the recording
+    pub sample_rate: f64, // [Hz] Of the samples as a `SampleSource`
+}
+
+impl PowerConfig {
+    /// 0.2 V of hysteresis, thresholds held for 30 s, sampled once a second. For a 12 V lead-acid battery, e.g., 11.8 V and 11.5 V
+    pub fn new(low_voltage: f64, shutdown_voltage: f64) -> Self {
+        Self {
+            low_voltage,
+            shutdown_voltage,
+            hysteresis: 0.2,
+            hold: Duration::from_secs(30),
+            sample_rate: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub struct SupplySample {
+    pub voltage: f64,         // [V]
+    pub current: Option<f64>, // [A] None without a current measurement, e.g., through the ADS1115
+    pub power: Option<f64>,   // [W]
+    pub state: SupplyState,   // As of this sample
+}
+
+// Tracks the state from the voltages over time
+#[derive(Debug, Clone, Default)]
+struct SupplyWatch {
+    state: SupplyState,
+    below_low: Option<Instant>, // Since when the voltage is below the low voltage
+    below_shutdown: Option<Instant>,
+}
+
+impl SupplyWatch {
+    fn update(&mut self, config: &PowerConfig, voltage: f64, instant: Instant) -> SupplyState {
+        let since = |below: &mut Option<Instant>, threshold: f64| {
+            if voltage < threshold {
+                let start = *below.get_or_insert(instant);
+                instant.saturating_duration_since(start) >= config.hold
+            } else {
+                *below = None;
+                false
+            }
+        };
+        let low = since(&mut self.below_low, config.low_voltage);
+        let critical = since(&mut self.below_shutdown, config.shutdown_voltage);
+
+        self.state = match self.state {
+            SupplyState::Critical => SupplyState::Critical,
+            _ if critical => SupplyState::Critical,
+            _ if low => SupplyState::Low,
+            SupplyState::Low if voltage < config.low_voltage + config.hysteresis => {
+                SupplyState::Low
+            }
+            _ => SupplyState::Normal,
+        };
+        self.state
+    }
+}
+
+/// Samples the supply through {input}, and cancels {shutdown} once it turns critical
+pub struct PowerMonitor {
+    pub config: PowerConfig,
+    pub shutdown: Option<utilites::CancellationToken>, // E.g., the one stopping the recording
+    input: PowerInput,
+    watch: SupplyWatch,
+    last_sample: Option<Instant>,
+}
+
+impl PowerMonitor {
+    pub fn new(input: PowerInput, config: PowerConfig) -> Self {
+        Self {
+            config,
+            shutdown: None,
+            input,
+            watch: SupplyWatch::default(),
+            last_sample: None,
+        }
+    }
+
+    pub fn state(&self) -> SupplyState {
+        self.watch.state
+    }
+
+    /// Measures the supply as of now
+    pub fn sample(&mut self) -> Result<SupplySample> {
+        let (voltage, current, power) = self.input.read().context("Unable to read supply.")?;
+        let now = Instant::now();
+        self.last_sample = Some(now);
+        let state = self.watch.update(&self.config, voltage, now);
+        if state == SupplyState::Critical {
+            if let Some(shutdown) = &self.shutdown {
+                shutdown.cancel();
+            }
+        }
+        Ok(SupplySample {
+            voltage,
+            current,
+            power,
+            state,
+        })
+    }
+}
+
+impl SampleSource<SupplySample> for PowerMonitor {
+    /// Waits until a sample period, by {config.sample_rate}, has passed since the latest sample, and samples. Ok(None) if that is beyond {timeout}
+    fn next_sample(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> (Result<Option<SupplySample>>, Instant) {
+        if let Some(last_sample) = self.last_sample {
+            let wait = (last_sample + Duration::from_secs_f64(1.0 / self.config.sample_rate))
+                .saturating_duration_since(Instant::now());
+            if let Some(timeout) = timeout.filter(|timeout| *timeout < wait) {
+                std::thread::sleep(timeout);
+                return (Ok(None), Instant::now());
+            }
+            std::thread::sleep(wait);
+        }
+        let sample = self.sample();
+        (sample.map(Some), Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_supply_watch() {
+        let start = Instant::now();
+        let at = |seconds: u64| start + Duration::from_secs(seconds);
+        let config = PowerConfig::new(11.8, 11.5);
+        let mut watch = SupplyWatch::default();
+
+        // A short sag, e.g., from the engine starter, doesn't count
+        assert_eq!(watch.update(&config, 12.4, at(0)), SupplyState::Normal);
+        assert_eq!(watch.update(&config, 10.9, at(1)), SupplyState::Normal);
+        assert_eq!(watch.update(&config, 12.3, at(5)), SupplyState::Normal);
+
+        // Low once it stays low for 30 s, and back to normal only above the hysteresis
+        assert_eq!(watch.update(&config, 11.7, at(10)), SupplyState::Normal);
+        assert_eq!(watch.update(&config, 11.7, at(40)), SupplyState::Low);
+        assert_eq!(watch.update(&config, 11.9, at(41)), SupplyState::Low);
+        assert_eq!(watch.update(&config, 12.1, at(42)), SupplyState::Normal);
+
+        // Critical for good
+        assert_eq!(watch.update(&config, 11.4, at(50)), SupplyState::Normal);
+        assert_eq!(watch.update(&config, 11.3, at(70)), SupplyState::Normal);
+        assert_eq!(watch.update(&config, 11.4, at(80)), SupplyState::Critical);
+        assert_eq!(watch.update(&config, 12.0, at(81)), SupplyState::Critical);
+    }
+}
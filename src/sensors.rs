@@ -0,0 +1,280 @@
+// Sensors behind one interface, `Sensor`, so that sampling code is written once for all of them, and drivers for sensors other than the GY-521, that are commonly wired to the same I2C bus, e.g., a magnetometer for the heading, or a barometer for heave and altitude.
+// The drivers follow the conventions of `gy521::GY521`: Configuration in pub fields, `initialize` before use, readings scaled to physical units, and `wait_for_sample` for the data ready pin.
+
+pub mod ads1115;
+pub mod bmp280;
+pub mod coordinator;
+pub mod encoder;
+pub mod hcsr04;
+pub mod hmc5883l;
+pub mod ina219;
+pub mod magnetometer;
+pub mod pulse;
+pub mod qmc5883l;
+pub mod rpm;
+pub mod transport;
+pub mod wind;
+
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use rppal::i2c::I2c;
+
+use crate::{
+    gy521::{
+        calibration::{CalibrationData, CalibrationObserver},
+        SensorSample,
+    },
+    math::Vec3D,
+    utilites::CancellationToken,
+};
+
+/// A sensor on the bus {B}, the I2C bus unless said otherwise, producing samples of type {Sample}, e.g., `gy521::SensorSample` or the magnetic field. See `gy521::LiveSource` to use one as a `gy521::SampleSource`
+pub trait Sensor<B = I2c> {
+    type Sample;
+
+    /// Sets the sensor up as configured. Needed before sampling
+    fn initialize(&mut self, bus: &mut B) -> Result<()>;
+
+    /// Reads the latest sample right away, whether it is new or not
+    fn sample(&mut self, bus: &B) -> Result<Self::Sample>;
+
+    /// [Hz] How often new samples are ready
+    fn sample_rate(&self) -> f64;
+
+    /// Waits up to {timeout} for a new sample, and reads it. Returns the instant it was taken at, and Ok(None) if none came in time
+    fn wait_for_sample(
+        &mut self,
+        bus: &mut B,
+        timeout: Option<Duration>,
+    ) -> (Result<Option<Self::Sample>>, Instant);
+}
+
+/// A sensor of acceleration and angular velocity, like the GY-521, for code that samples, fuses, or logs those, e.g., `Sampler` and attitude filters
+pub trait ImuSensor<B = I2c>: Sensor<B, Sample = SensorSample<Vec3D, f64>> {
+    /// Calibrates the sensor at rest from the latest {sample_size} of the samples taken every {sampling_period} over {duration}, like `gy521::GY521::calibrate`, and applies the calibration.
+    /// Fails once {cancellation} is cancelled, leaving the calibration as it was
+    fn calibrate(
+        &mut self,
+        bus: &mut B,
+        sample_size: usize,
+        sampling_period: Duration,
+        duration: Duration,
+        cancellation: &CancellationToken,
+        observer: &mut impl CalibrationObserver,
+    ) -> Result<CalibrationData>;
+
+    /// Applies {calibration}, e.g., one saved after an earlier `calibrate`
+    fn apply_calibration(&mut self, calibration: &CalibrationData);
+}
+
+/// A sample taken by `Sampler`
+#[derive(Debug, Clone, Copy)]
+pub struct Sampled {
+    pub sample: SensorSample<Vec3D, f64>,
+    pub due: bool, // For storing
+}
+
+/// The sampling loop's view of an `ImuSensor`: Every sample is passed on, e.g., for live streams, and one per {period} is marked as due for storing
+#[derive(Debug, Clone)]
+pub struct Sampler {
+    pub period: Duration, // Between stored samples
+    start: Instant,
+    count: u128, // Of the stored samples since the first one
+    first: bool, // Until the first sample is stored
+}
+
+impl Sampler {
+    /// Stores a sample every {period} from {start} on, e.g., from `logging::Timebase::start`
+    pub fn new(period: Duration, start: Instant) -> Self {
+        Self {
+            period,
+            start,
+            count: 0,
+            first: true,
+        }
+    }
+
+    /// Waits up to {timeout} for the next sample of {sensor}, like `Sensor::wait_for_sample`, and tells whether it is due for storing
+    pub fn sample<B, S: ImuSensor<B>>(
+        &mut self,
+        sensor: &mut S,
+        bus: &mut B,
+        timeout: Option<Duration>,
+    ) -> (Result<Option<Sampled>>, Instant) {
+        let (sample, instant) = sensor.wait_for_sample(bus, timeout);
+        let sample = sample.map(|sample| {
+            sample.map(|sample| {
+                let due = self.first
+                    || instant.saturating_duration_since(self.start).as_nanos()
+                        >= self.count * self.period.as_nanos();
+                if due && !std::mem::take(&mut self.first) {
+                    self.count += 1;
+                }
+                Sampled { sample, due }
+            })
+        });
+        (sample, instant)
+    }
+
+    /// Stored samples pick up from now, rather than catching up on the time missed, e.g., during a pause or a calibration
+    pub fn resume(&mut self) {
+        self.count = self.start.elapsed().as_nanos() / self.period.as_nanos();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Still on a table, sampling at 1 kHz on a bus that only counts how often it is used, and as much faster than real time as needed
+    struct MockImu {
+        start: Instant,
+        taken: u32,
+        offset: Vec3D,
+    }
+
+    impl Sensor<usize> for MockImu {
+        type Sample = SensorSample<Vec3D, f64>;
+
+        fn initialize(&mut self, bus: &mut usize) -> Result<()> {
+            *bus += 1;
+            Ok(())
+        }
+
+        fn sample(&mut self, bus: &usize) -> Result<Self::Sample> {
+            let _ = bus;
+            Ok(SensorSample::new(
+                Vec3D::new(0, 0, 1) + self.offset,
+                self.offset,
+                None,
+            ))
+        }
+
+        fn sample_rate(&self) -> f64 {
+            1e3
+        }
+
+        fn wait_for_sample(
+            &mut self,
+            bus: &mut usize,
+            _timeout: Option<Duration>,
+        ) -> (Result<Option<Self::Sample>>, Instant) {
+            *bus += 1;
+            self.taken += 1;
+            let instant = self.start + Duration::from_millis(self.taken as u64);
+            // Every 100th read fails, like a disturbed bus
+            match self.taken % 100 {
+                0 => (Err(anyhow::anyhow!("Remote I/O error")), instant),
+                _ => (self.sample(bus).map(Some), instant),
+            }
+        }
+    }
+
+    impl ImuSensor<usize> for MockImu {
+        fn calibrate(
+            &mut self,
+            bus: &mut usize,
+            _sample_size: usize,
+            _sampling_period: Duration,
+            _duration: Duration,
+            cancellation: &CancellationToken,
+            _observer: &mut impl CalibrationObserver,
+        ) -> Result<CalibrationData> {
+            anyhow::ensure!(!cancellation.is_cancelled(), "Calibration cancelled.");
+            let sample = self.sample(bus)?;
+            let calibration = CalibrationData {
+                accelerometer_offset: -sample.acceleration() + Vec3D::new(0, 0, 1),
+                gyroscope_offset: -sample.angular_velocity(),
+                ..Default::default()
+            };
+            self.apply_calibration(&calibration);
+            Ok(calibration)
+        }
+
+        fn apply_calibration(&mut self, calibration: &CalibrationData) {
+            self.offset += calibration.gyroscope_offset;
+        }
+    }
+
+    // Like the sampling loop does, for any `ImuSensor`
+    fn record<B, S: ImuSensor<B>>(
+        sensor: &mut S,
+        bus: &mut B,
+        sampler: &mut Sampler,
+        reads: usize,
+    ) -> (Vec<SensorSample<Vec3D, f64>>, usize, usize) {
+        let (mut stored, mut live, mut errors) = (Vec::new(), 0, 0);
+        for _ in 0..reads {
+            match sampler.sample(sensor, bus, Some(Duration::from_millis(2))) {
+                (Ok(Some(Sampled { sample, due })), _) => {
+                    live += 1;
+                    if due {
+                        stored.push(sample);
+                    }
+                }
+                (Ok(None), _) => {}
+                (Err(_), _) => errors += 1,
+            }
+        }
+        (stored, live, errors)
+    }
+
+    #[test]
+    fn test_sampler() {
+        let start = Instant::now();
+        let mut sensor = MockImu {
+            start,
+            taken: 0,
+            offset: Vec3D::new(0.0, 0.0, 2.0),
+        };
+        let mut bus = 0;
+        sensor.initialize(&mut bus).unwrap();
+
+        let calibration = sensor
+            .calibrate(
+                &mut bus,
+                100,
+                Duration::from_millis(10),
+                Duration::from_secs(1),
+                &CancellationToken::default(),
+                &mut (),
+            )
+            .unwrap();
+        assert_eq!(calibration.gyroscope_offset, Vec3D::new(0.0, 0.0, -2.0));
+        let cancelled = CancellationToken::default();
+        cancelled.cancel();
+        let cancellation = sensor.calibrate(
+            &mut bus,
+            100,
+            Duration::from_millis(10),
+            Duration::from_secs(1),
+            &cancelled,
+            &mut (),
+        );
+        assert!(cancellation.is_err());
+
+        // One second of samples, one in ten stored, with the first one stored right away
+        let mut sampler = Sampler::new(Duration::from_millis(10), start);
+        let (stored, live, errors) = record(&mut sensor, &mut bus, &mut sampler, 1000);
+        assert_eq!((live, errors), (990, 10));
+        assert_eq!(stored.len(), 101);
+        assert!(stored
+            .iter()
+            .all(|sample| sample.angular_velocity() == Vec3D::new(0.0, 0.0, 0.0)));
+        assert_eq!(bus, 1 + 1000); // Initialized, and read
+
+        // After a pause of 10 s, e.g., for a calibration, every sample is stored until caught up, unless picking up from now
+        let now = Instant::now();
+        let mut sampler = Sampler::new(
+            Duration::from_millis(10),
+            now.checked_sub(Duration::from_secs(10)).unwrap(),
+        );
+        (sensor.start, sensor.taken) = (now, 0);
+        let (stored, _, _) = record(&mut sensor, &mut bus, &mut sampler, 10);
+        assert_eq!(stored.len(), 10);
+        sampler.resume();
+        let (stored, _, _) = record(&mut sensor, &mut bus, &mut sampler, 100);
+        assert!((10..=12).contains(&stored.len())); // About one in ten
+    }
+}
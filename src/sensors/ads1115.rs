@@ -0,0 +1,426 @@
+// Texas Instruments ADS1115 16 bit analog to digital converter with four inputs, for analog signals, e.g., a rudder position potentiometer, or the voltage across a battery shunt.
+// Register map: https://www.ti.com/lit/ds/symlink/ads1115.pdf
+
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use rppal::{
+    gpio::{InputPin, Trigger},
+    i2c::I2c,
+};
+
+use super::Sensor;
+use crate::utilites;
+
+pub const I2C_ADDRESS: u16 = 0x48; // With ADDR connected to ground. 0x49 to VDD, 0x4A to SDA, 0x4B to SCL
+
+const CONVERSION: u8 = 0x00;
+const CONFIG: u8 = 0x01;
+const LO_THRESH: u8 = 0x02;
+const HI_THRESH: u8 = 0x03;
+const POLL_INTERVAL: Duration = Duration::from_micros(500); // Between reads of the config register, while a single-shot conversion runs
+
+// Config register bits
+const OS: u16 = 1 << 15; // Written: Starts a single-shot conversion. Read: No conversion running
+
+/// What is measured, either the difference of two inputs, or one input against ground. Config register, bits 14-12
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum Input {
+    Differential01 = 0b000, // AIN0 - AIN1
+    Differential03 = 0b001, // AIN0 - AIN3
+    Differential13 = 0b010, // AIN1 - AIN3
+    Differential23 = 0b011, // AIN2 - AIN3
+    #[default]
+    Single0 = 0b100,
+    Single1 = 0b101,
+    Single2 = 0b110,
+    Single3 = 0b111,
+}
+
+/// Full-scale range of the programmable gain amplifier. Inputs must stay between ground and the supply voltage regardless. Config register, bits 11-9
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Gain {
+    V6_144 = 0b000,
+    V4_096 = 0b001,
+    #[default]
+    V2_048 = 0b010,
+    V1_024 = 0b011,
+    V0_512 = 0b100,
+    V0_256 = 0b101,
+}
+
+impl Gain {
+    /// [V] Voltage of the largest reading
+    pub fn full_scale(&self) -> f64 {
+        match self {
+            Self::V6_144 => 6.144,
+            Self::V4_096 => 4.096,
+            Self::V2_048 => 2.048,
+            Self::V1_024 => 1.024,
+            Self::V0_512 => 0.512,
+            Self::V0_256 => 0.256,
+        }
+    }
+}
+
+/// Config register, bit 8
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    Continuous = 0,
+    #[default]
+    SingleShot = 1, // Powers down between conversions
+}
+
+/// Conversions per second. Slower ones average over more, and are less noisy. Config register, bits 7-5
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DataRate {
+    Sps8 = 0b000,
+    Sps16 = 0b001,
+    Sps32 = 0b010,
+    Sps64 = 0b011,
+    #[default]
+    Sps128 = 0b100,
+    Sps250 = 0b101,
+    Sps475 = 0b110,
+    Sps860 = 0b111,
+}
+
+impl DataRate {
+    /// [Hz]
+    pub fn frequency(&self) -> f64 {
+        match self {
+            Self::Sps8 => 8.0,
+            Self::Sps16 => 16.0,
+            Self::Sps32 => 32.0,
+            Self::Sps64 => 64.0,
+            Self::Sps128 => 128.0,
+            Self::Sps250 => 250.0,
+            Self::Sps475 => 475.0,
+            Self::Sps860 => 860.0,
+        }
+    }
+}
+
+/// How many conversions in a row have to be beyond a threshold for the alert pin to be asserted. Config register, bits 1-0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Queue {
+    #[default]
+    One = 0b00,
+    Two = 0b01,
+    Four = 0b10,
+}
+
+/// Threshold alert
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Comparator {
+    pub low_threshold: f64,  // [V]
+    pub high_threshold: f64, // [V]
+    pub window: bool, // false: Asserted above the high threshold, until below the low threshold | true: Asserted outside of the thresholds
+    pub latching: bool, // true: Stays asserted until the conversion register is read
+    pub queue: Queue,
+}
+
+/// What the ALERT/RDY pin signals. It is active low
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Alert {
+    #[default]
+    Disabled,
+    ConversionReady, // A short pulse after each conversion in continuous mode, and asserted once the conversion is done in single-shot mode
+    Comparator(Comparator),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Configuration {
+    pub input: Input,
+    pub gain: Gain,
+    pub mode: Mode,
+    pub data_rate: DataRate,
+    pub alert: Alert,
+}
+
+impl Configuration {
+    /// Contents of the config register, measuring {input}. Doesn't start a conversion
+    pub fn config(&self, input: Input) -> u16 {
+        let comparator = match self.alert {
+            Alert::Disabled => 0b11,
+            Alert::ConversionReady => Queue::One as u16,
+            Alert::Comparator(comparator) => {
+                ((comparator.window as u16) << 4)
+                    | ((comparator.latching as u16) << 2)
+                    | comparator.queue as u16
+            }
+        };
+        ((input as u16) << 12)
+            | ((self.gain as u16) << 9)
+            | ((self.mode as u16) << 8)
+            | ((self.data_rate as u16) << 5)
+            | comparator
+    }
+
+    /// Contents of the (low, high) threshold registers
+    pub fn thresholds(&self) -> (u16, u16) {
+        match self.alert {
+            // The most significant bit of the high threshold set, and that of the low one cleared, turns the pin into a conversion ready signal
+            Alert::Disabled | Alert::ConversionReady => (0x0000, 0x8000),
+            Alert::Comparator(comparator) => (
+                self.counts(comparator.low_threshold) as u16,
+                self.counts(comparator.high_threshold) as u16,
+            ),
+        }
+    }
+
+    /// Reading of {voltage} [V], saturated at full scale
+    pub fn counts(&self, voltage: f64) -> i16 {
+        (voltage / self.gain.full_scale() * 32768.0)
+            .round()
+            .clamp(i16::MIN as f64, i16::MAX as f64) as i16
+    }
+
+    /// [V] Voltage of a reading of {counts}
+    pub fn voltage(&self, counts: i16) -> f64 {
+        counts as f64 * self.gain.full_scale() / 32768.0
+    }
+}
+
+/// Voltage at one of the inputs
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct AnalogSample {
+    pub input: Input,
+    pub voltage: f64, // [V]
+}
+
+/// The converter, measuring voltages in [V]. It shares the bus with the GY-521, but needs its own `I2c`, since that is set to the GY-521's address once.
+/// In single-shot mode, each sample measures the next of the inputs in {scan}, so several signals can be logged from one converter. In continuous mode, only {configuration.input} is measured
+#[non_exhaustive]
+pub struct ADS1115 {
+    pub configuration: Configuration,
+    pub i2c_address: u16,
+    pub scan: Vec<Input>, // Inputs measured in turn, in single-shot mode. Empty for just {configuration.input}
+    pub sample: Option<AnalogSample>, // Latest
+    pub alert_pin: Option<InputPin>, // ALERT/RDY, signaling as set up in {configuration.alert}
+    pub retry_policy: utilites::RetryPolicy, // Applied to all register reads and writes
+    next_input: usize,    // Index into {scan}
+    last_sample: Option<Instant>,
+}
+
+impl ADS1115 {
+    pub fn new(configuration: Configuration, i2c_address: u16) -> Self {
+        Self {
+            configuration,
+            i2c_address,
+            scan: Vec::new(),
+            sample: None,
+            alert_pin: None,
+            retry_policy: Default::default(),
+            next_input: 0,
+            last_sample: None,
+        }
+    }
+
+    /// [Hz] Conversions per second, which are shared by the inputs in {scan}
+    pub fn sample_rate(&self) -> f64 {
+        self.configuration.data_rate.frequency()
+    }
+
+    // The registers are 16 bits wide, most significant byte first
+    fn write_register(&self, i2c: &I2c, address: u8, value: u16) -> Result<()> {
+        self.retry_policy
+            .run(|| i2c.block_write(address, &value.to_be_bytes()))
+            .with_context(|| format!("Unable to write register {:#04x}.", address))
+    }
+
+    fn read_register(&self, i2c: &I2c, address: u8) -> Result<u16> {
+        let mut value = [0u8; 2];
+        self.retry_policy
+            .run(|| i2c.block_read(address, &mut value))
+            .with_context(|| format!("Unable to read register {:#04x}.", address))?;
+        Ok(u16::from_be_bytes(value))
+    }
+
+    /// Sets the converter up. In continuous mode, it starts converting right away. The converter has no ID register, so unlike for the other sensors, this can't tell whether it is the right chip
+    pub fn initialize(&mut self, i2c: &mut I2c) -> Result<()> {
+        anyhow::ensure!(
+            self.scan.is_empty() || self.configuration.mode == Mode::SingleShot,
+            "Scanning several inputs needs single-shot mode."
+        );
+        i2c.set_slave_address(self.i2c_address)?;
+
+        let (low_threshold, high_threshold) = self.configuration.thresholds();
+        self.write_register(i2c, LO_THRESH, low_threshold)?;
+        self.write_register(i2c, HI_THRESH, high_threshold)?;
+        if let Some(pin) = &mut self.alert_pin {
+            pin.set_interrupt(Trigger::FallingEdge)
+                .context("Unable to set up alert interrupt.")?;
+        }
+        self.next_input = 0;
+        self.last_sample = None;
+        self.write_register(
+            i2c,
+            CONFIG,
+            self.configuration.config(self.configuration.input),
+        )
+    }
+
+    /// Measures {input} in a single-shot conversion, and waits for it. Takes one conversion period
+    pub fn read_input(&mut self, i2c: &I2c, input: Input) -> Result<AnalogSample> {
+        anyhow::ensure!(
+            self.configuration.mode == Mode::SingleShot,
+            "Measuring another input needs single-shot mode."
+        );
+        self.write_register(i2c, CONFIG, self.configuration.config(input) | OS)?;
+        // The data rate is accurate to 10 %
+        let timeout = Duration::from_secs_f64(1.5 / self.sample_rate());
+        let start = Instant::now();
+        while self.read_register(i2c, CONFIG)? & OS == 0 {
+            anyhow::ensure!(
+                start.elapsed() < timeout,
+                "Conversion did not finish in time."
+            );
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        self.read_conversion(i2c, input)
+    }
+
+    fn read_conversion(&mut self, i2c: &I2c, input: Input) -> Result<AnalogSample> {
+        let counts = self.read_register(i2c, CONVERSION)? as i16;
+        self.last_sample = Some(Instant::now());
+        let sample = AnalogSample {
+            input,
+            voltage: self.configuration.voltage(counts),
+        };
+        self.sample = Some(sample);
+        Ok(sample)
+    }
+
+    /// Reads the next voltage [V]: The latest conversion in continuous mode, or a new conversion of the next input in {scan} in single-shot mode
+    pub fn read(&mut self, i2c: &I2c) -> Result<AnalogSample> {
+        match self.configuration.mode {
+            Mode::Continuous => self.read_conversion(i2c, self.configuration.input),
+            Mode::SingleShot => {
+                let input = match self.scan.is_empty() {
+                    true => self.configuration.input,
+                    false => {
+                        let input = self.scan[self.next_input % self.scan.len()];
+                        self.next_input = (self.next_input + 1) % self.scan.len();
+                        input
+                    }
+                };
+                self.read_input(i2c, input)
+            }
+        }
+    }
+
+    /// Waits for the next conversion, up to {timeout}, and reads it. In continuous mode, that is on the alert pin if it signals conversion ready, or otherwise a conversion period, by `sample_rate`, since the latest read. Ok(None) if nothing came in time
+    pub fn wait_for_sample(
+        &mut self,
+        i2c: &I2c,
+        timeout: Option<Duration>,
+    ) -> (Result<Option<AnalogSample>>, Instant) {
+        if self.configuration.mode == Mode::Continuous {
+            match (&mut self.alert_pin, self.configuration.alert) {
+                (Some(pin), Alert::ConversionReady) => match pin.poll_interrupt(true, timeout) {
+                    Ok(Some(_)) => (),
+                    Ok(None) => return (Ok(None), Instant::now()),
+                    Err(error) => {
+                        return (
+                            Err(error).context("Unable to poll interrupt."),
+                            Instant::now(),
+                        )
+                    }
+                },
+                _ => {
+                    if let Some(last_sample) = self.last_sample {
+                        let wait = (last_sample
+                            + Duration::from_secs_f64(1.0 / self.sample_rate()))
+                        .saturating_duration_since(Instant::now());
+                        if let Some(timeout) = timeout.filter(|timeout| *timeout < wait) {
+                            std::thread::sleep(timeout);
+                            return (Ok(None), Instant::now());
+                        }
+                        std::thread::sleep(wait);
+                    }
+                }
+            }
+        }
+        let sampling_instant = Instant::now();
+        let sample = self.read(i2c).context("Unable to read ADC.");
+        (sample.map(Some), sampling_instant)
+    }
+
+    /// Waits up to {timeout} for the alert pin to signal a threshold being crossed. Ok(false) if it didn't in time
+    pub fn wait_for_alert(&mut self, timeout: Option<Duration>) -> Result<bool> {
+        anyhow::ensure!(
+            matches!(self.configuration.alert, Alert::Comparator(_)),
+            "The alert pin is not set up for thresholds."
+        );
+        let pin = self.alert_pin.as_mut().context("No alert pin set up.")?;
+        pin.poll_interrupt(true, timeout)
+            .map(|interrupt| interrupt.is_some())
+            .context("Unable to poll interrupt.")
+    }
+}
+
+impl Sensor for ADS1115 {
+    type Sample = AnalogSample;
+
+    fn initialize(&mut self, i2c: &mut I2c) -> Result<()> {
+        ADS1115::initialize(self, i2c)
+    }
+
+    fn sample(&mut self, i2c: &I2c) -> Result<Self::Sample> {
+        self.read(i2c)
+    }
+
+    fn sample_rate(&self) -> f64 {
+        ADS1115::sample_rate(self)
+    }
+
+    fn wait_for_sample(
+        &mut self,
+        i2c: &mut I2c,
+        timeout: Option<Duration>,
+    ) -> (Result<Option<Self::Sample>>, Instant) {
+        ADS1115::wait_for_sample(self, i2c, timeout)
+    }
+}
+
+impl Default for ADS1115 {
+    fn default() -> Self {
+        Self::new(Default::default(), I2C_ADDRESS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registers() {
+        // The power-on default, 0x8583, apart from the input, and with the conversion started
+        let configuration = Configuration {
+            input: Input::Differential01,
+            ..Default::default()
+        };
+        assert_eq!(configuration.config(configuration.input) | OS, 0x8583);
+        assert_eq!(configuration.thresholds(), (0x0000, 0x8000));
+
+        let configuration = Configuration {
+            gain: Gain::V4_096,
+            mode: Mode::Continuous,
+            data_rate: DataRate::Sps860,
+            alert: Alert::Comparator(Comparator {
+                low_threshold: 1.024,
+                high_threshold: 5.0,
+                window: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(configuration.config(Input::Single3), 0x72F0);
+        assert_eq!(configuration.thresholds(), (0x2000, 0x7FFF));
+
+        assert_eq!(configuration.voltage(0x4000), 2.048);
+        assert_eq!(configuration.voltage(i16::MIN), -4.096);
+        assert_eq!(configuration.counts(-1.0), -8000);
+    }
+}
@@ -0,0 +1,536 @@
+// Bosch BMP280 barometer, and the BME280, which measures humidity as well, but otherwise works the same.
+// Register maps: https://www.bosch-sensortec.com/media/boschsensortec/downloads/datasheets/bst-bmp280-ds001.pdf and https://www.bosch-sensortec.com/media/boschsensortec/downloads/datasheets/bst-bme280-ds002.pdf
+
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use rppal::i2c::I2c;
+
+use super::Sensor;
+use crate::utilites;
+
+pub const I2C_ADDRESS: u16 = 0x76; // With SDO connected to ground. 0x77 with SDO connected to VDDIO
+pub const SEA_LEVEL_PRESSURE: f64 = 101325.0; // [Pa] Standard atmosphere
+
+const CALIBRATION_TP: u8 = 0x88; // 24 bytes, temperature and pressure
+const CALIBRATION_H1: u8 = 0xA1;
+const CALIBRATION_H2: u8 = 0xE1; // 7 bytes, the rest of humidity
+const CHIP_ID: u8 = 0xD0;
+const RESET: u8 = 0xE0;
+const CTRL_HUM: u8 = 0xF2;
+const STATUS: u8 = 0xF3;
+const CTRL_MEAS: u8 = 0xF4;
+const CONFIG: u8 = 0xF5;
+const DATA: u8 = 0xF7; // Pressure, temperature, and, on the BME280, humidity
+const RESET_DURATION: Duration = Duration::from_millis(10); // Start-up time is 2 ms. See table 2
+const POLL_INTERVAL: Duration = Duration::from_millis(1); // Between reads of the status register, in forced mode
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    BMP280,
+    BME280, // Measures humidity as well
+}
+
+impl Variant {
+    pub fn from_chip_id(chip_id: u8) -> Option<Self> {
+        match chip_id {
+            0x56..=0x58 => Some(Self::BMP280), // 0x56 and 0x57 are engineering samples
+            0x60 => Some(Self::BME280),
+            _ => None,
+        }
+    }
+}
+
+/// Measurements per output, trading noise for time and current. Skipped leaves the quantity out, reading as None
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Oversampling {
+    Skipped = 0b000,
+    X1 = 0b001,
+    X2 = 0b010,
+    X4 = 0b011,
+    X8 = 0b100,
+    X16 = 0b101,
+}
+
+impl Oversampling {
+    pub fn factor(&self) -> u8 {
+        match self {
+            Self::Skipped => 0,
+            _ => 1 << (*self as u8 - 1),
+        }
+    }
+}
+
+/// Time constant of the IIR filter on pressure and temperature, which smooths out short changes, e.g., from a slamming door or gusts of wind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Off = 0b000,
+    X2 = 0b001,
+    X4 = 0b010,
+    X8 = 0b011,
+    X16 = 0b100,
+}
+
+/// Inactive time between measurements in normal mode, t_sb. The two longest differ between the variants
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Standby {
+    Ms0_5 = 0b000,
+    Ms62_5 = 0b001,
+    Ms125 = 0b010,
+    Ms250 = 0b011,
+    Ms500 = 0b100,
+    Ms1000 = 0b101,
+    Ms2000Or10 = 0b110, // 2000 ms on the BMP280, 10 ms on the BME280
+    Ms4000Or20 = 0b111, // 4000 ms on the BMP280, 20 ms on the BME280
+}
+
+impl Standby {
+    /// [ms]
+    pub fn duration(&self, variant: Variant) -> f64 {
+        match (self, variant) {
+            (Self::Ms0_5, _) => 0.5,
+            (Self::Ms62_5, _) => 62.5,
+            (Self::Ms125, _) => 125.0,
+            (Self::Ms250, _) => 250.0,
+            (Self::Ms500, _) => 500.0,
+            (Self::Ms1000, _) => 1000.0,
+            (Self::Ms2000Or10, Variant::BMP280) => 2000.0,
+            (Self::Ms2000Or10, Variant::BME280) => 10.0,
+            (Self::Ms4000Or20, Variant::BMP280) => 4000.0,
+            (Self::Ms4000Or20, Variant::BME280) => 20.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Sleep = 0b00,
+    Forced = 0b01, // Measures once per `read`, which then waits for the measurement
+    Normal = 0b11, // Measures continuously, with {standby} between measurements
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Configuration {
+    pub temperature_oversampling: Oversampling, // Temperature can't be skipped, since pressure and humidity are compensated with it
+    pub pressure_oversampling: Oversampling,
+    pub humidity_oversampling: Oversampling, // BME280 only
+    pub filter: Filter,
+    pub standby: Standby,
+    pub mode: Mode,
+}
+
+impl Default for Configuration {
+    /// For altitude, like the indoor navigation example of section 3.5 of the BMP280 datasheet: At least 23 Hz, with a noise of about 5 cm
+    fn default() -> Self {
+        Self {
+            temperature_oversampling: Oversampling::X2,
+            pressure_oversampling: Oversampling::X16,
+            humidity_oversampling: Oversampling::X1,
+            filter: Filter::X16,
+            standby: Standby::Ms0_5,
+            mode: Mode::Normal,
+        }
+    }
+}
+
+impl Configuration {
+    /// Contents of the ctrl_meas register
+    pub fn ctrl_meas(&self) -> u8 {
+        ((self.temperature_oversampling as u8) << 5)
+            | ((self.pressure_oversampling as u8) << 2)
+            | self.mode as u8
+    }
+
+    /// Contents of the config register
+    pub fn config(&self) -> u8 {
+        ((self.standby as u8) << 5) | ((self.filter as u8) << 2)
+    }
+
+    /// [ms] Longest a measurement takes. See section 9.1 of the BME280 datasheet
+    pub fn measurement_time(&self, variant: Variant) -> f64 {
+        let oversampling = |oversampling: Oversampling, overhead: f64| match oversampling {
+            Oversampling::Skipped => 0.0,
+            _ => 2.3 * oversampling.factor() as f64 + overhead,
+        };
+        let humidity = match variant {
+            Variant::BMP280 => 0.0,
+            Variant::BME280 => oversampling(self.humidity_oversampling, 0.575),
+        };
+        1.25 + oversampling(self.temperature_oversampling, 0.0)
+            + oversampling(self.pressure_oversampling, 0.575)
+            + humidity
+    }
+}
+
+/// Trimming parameters, individual to each sensor, that compensate the raw readings. Named like in the datasheets
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Calibration {
+    t1: f64,
+    t2: f64,
+    t3: f64,
+    p: [f64; 9],
+    h1: f64,
+    h2: f64,
+    h3: f64,
+    h4: f64,
+    h5: f64,
+    h6: f64,
+}
+
+impl Calibration {
+    /// From the 24 bytes starting at 0x88, and, for the BME280, the byte at 0xA1 and the 7 bytes starting at 0xE1
+    pub fn parse(temperature_pressure: &[u8; 24], humidity: Option<(u8, [u8; 7])>) -> Self {
+        let unsigned = |index: usize| {
+            u16::from_le_bytes([temperature_pressure[index], temperature_pressure[index + 1]])
+                as f64
+        };
+        let signed = |index: usize| {
+            i16::from_le_bytes([temperature_pressure[index], temperature_pressure[index + 1]])
+                as f64
+        };
+        let mut p = [unsigned(6); 9];
+        for (n, p) in p.iter_mut().enumerate().skip(1) {
+            *p = signed(6 + 2 * n);
+        }
+        let mut calibration = Self {
+            t1: unsigned(0),
+            t2: signed(2),
+            t3: signed(4),
+            p,
+            ..Default::default()
+        };
+        if let Some((h1, h)) = humidity {
+            calibration.h1 = h1 as f64;
+            calibration.h2 = i16::from_le_bytes([h[0], h[1]]) as f64;
+            calibration.h3 = h[2] as f64;
+            // Two 12 bit values in 3 bytes, sharing the middle one
+            calibration.h4 = (((h[3] as i8 as i16) << 4) | (h[4] & 0x0F) as i16) as f64;
+            calibration.h5 = (((h[5] as i8 as i16) << 4) | (h[4] >> 4) as i16) as f64;
+            calibration.h6 = h[6] as i8 as f64;
+        }
+        calibration
+    }
+
+    // Temperature [degree C], and t_fine, which the other quantities are compensated with. See section 3.11.3 and 8.1 of the BMP280 datasheet
+    fn temperature(&self, raw: f64) -> (f64, f64) {
+        let var1 = (raw / 16384.0 - self.t1 / 1024.0) * self.t2;
+        let var2 = (raw / 131072.0 - self.t1 / 8192.0).powi(2) * self.t3;
+        let t_fine = var1 + var2;
+        (t_fine / 5120.0, t_fine)
+    }
+
+    // [Pa]
+    fn pressure(&self, raw: f64, t_fine: f64) -> f64 {
+        let p = &self.p;
+        let var1 = t_fine / 2.0 - 64000.0;
+        let var2 = var1 * var1 * p[5] / 32768.0 + var1 * p[4] * 2.0;
+        let var2 = var2 / 4.0 + p[3] * 65536.0;
+        let var1 = (p[2] * var1 * var1 / 524288.0 + p[1] * var1) / 524288.0;
+        let var1 = (1.0 + var1 / 32768.0) * p[0];
+        if var1 == 0.0 {
+            return 0.0; // Avoids dividing by zero
+        }
+        let pressure = (1048576.0 - raw - var2 / 4096.0) * 6250.0 / var1;
+        let var1 = p[8] * pressure * pressure / 2147483648.0;
+        let var2 = pressure * p[7] / 32768.0;
+        pressure + (var1 + var2 + p[6]) / 16.0
+    }
+
+    // Relative humidity [%]. See section 4.2.3 of the BME280 datasheet
+    fn humidity(&self, raw: f64, t_fine: f64) -> f64 {
+        let h = t_fine - 76800.0;
+        let h = (raw - (self.h4 * 64.0 + self.h5 / 16384.0 * h))
+            * (self.h2 / 65536.0
+                * (1.0 + self.h6 / 67108864.0 * h * (1.0 + self.h3 / 67108864.0 * h)));
+        let h = h * (1.0 - self.h1 * h / 524288.0);
+        h.clamp(0.0, 100.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct BarometerSample {
+    pub pressure: Option<f64>, // [Pa] None if skipped
+    pub temperature: f64,      // [degree C] Of the sensor, which is warmer than the air around it
+    pub humidity: Option<f64>, // [%] Relative. None on the BMP280, or if skipped
+    pub altitude: Option<f64>, // [m] Above where the pressure is `reference_pressure`
+}
+
+/// The barometer, e.g., as the vertical reference for heave and altitude, which the accelerometer alone can only integrate with drift.
+/// Like the magnetometers, give it its own `I2c`, opened on the same bus as the GY-521
+#[non_exhaustive]
+pub struct BMP280 {
+    pub configuration: Configuration,
+    pub i2c_address: u16,
+    pub reference_pressure: f64, // [Pa] Where the altitude is 0. Sea level pressure gives the altitude above sea level, but only as accurately as the weather allows. See `set_reference`
+    pub sample: Option<BarometerSample>, // Latest
+    pub retry_policy: utilites::RetryPolicy, // Applied to all register reads and writes
+    variant: Option<Variant>,    // Known from `initialize` on
+    calibration: Calibration,
+    last_sample: Option<Instant>, // Of the latest read in normal mode, for `wait_for_sample` to wait for the next measurement
+}
+
+impl BMP280 {
+    pub fn new(configuration: Configuration, i2c_address: u16) -> Self {
+        Self {
+            configuration,
+            i2c_address,
+            reference_pressure: SEA_LEVEL_PRESSURE,
+            sample: None,
+            retry_policy: Default::default(),
+            variant: None,
+            calibration: Default::default(),
+            last_sample: None,
+        }
+    }
+
+    /// None before `initialize`
+    pub fn variant(&self) -> Option<Variant> {
+        self.variant
+    }
+
+    /// [Hz] How often new data is ready in normal mode. The worst case, since measurements mostly finish early
+    pub fn sample_rate(&self) -> f64 {
+        let variant = self.variant.unwrap_or(Variant::BMP280);
+        1e3 / (self.configuration.measurement_time(variant)
+            + self.configuration.standby.duration(variant))
+    }
+
+    fn write_register(&self, i2c: &I2c, address: u8, value: u8) -> Result<()> {
+        self.retry_policy
+            .run(|| i2c.smbus_write_byte(address, value))
+            .with_context(|| format!("Unable to write register {:#04x}.", address))
+    }
+
+    fn read_registers(&self, i2c: &I2c, address: u8, buffer: &mut [u8]) -> Result<()> {
+        self.retry_policy
+            .run(|| i2c.block_read(address, buffer))
+            .with_context(|| format!("Unable to read registers from {:#04x}.", address))
+    }
+
+    /// Identifies the sensor at {i2c_address} by its chip ID
+    pub fn detect_variant(i2c: &mut I2c, i2c_address: u16) -> Result<Variant> {
+        i2c.set_slave_address(i2c_address)?;
+        let chip_id = i2c
+            .smbus_read_byte(CHIP_ID)
+            .context("Unable to read chip ID register.")?;
+        Variant::from_chip_id(chip_id).with_context(|| format!("Unknown chip ID: {:#04x}", chip_id))
+    }
+
+    pub fn initialize(&mut self, i2c: &mut I2c) -> Result<()> {
+        let variant = Self::detect_variant(i2c, self.i2c_address)?;
+        anyhow::ensure!(
+            self.configuration.temperature_oversampling != Oversampling::Skipped,
+            "Temperature can't be skipped."
+        );
+
+        self.write_register(i2c, RESET, 0xB6)
+            .context("Unable to reset sensor.")?;
+        std::thread::sleep(RESET_DURATION);
+
+        let mut temperature_pressure = [0u8; 24];
+        self.read_registers(i2c, CALIBRATION_TP, &mut temperature_pressure)?;
+        let humidity = match variant {
+            Variant::BMP280 => None,
+            Variant::BME280 => {
+                let h1 = self
+                    .retry_policy
+                    .run(|| i2c.smbus_read_byte(CALIBRATION_H1))?;
+                let mut h = [0u8; 7];
+                self.read_registers(i2c, CALIBRATION_H2, &mut h)?;
+                // Only takes effect with the next write to ctrl_meas
+                self.write_register(
+                    i2c,
+                    CTRL_HUM,
+                    self.configuration.humidity_oversampling as u8,
+                )?;
+                Some((h1, h))
+            }
+        };
+        self.calibration = Calibration::parse(&temperature_pressure, humidity);
+        self.variant = Some(variant);
+
+        // The config register may be ignored in normal mode, so it goes first, while the sensor sleeps after the reset
+        self.write_register(i2c, CONFIG, self.configuration.config())?;
+        self.write_register(i2c, CTRL_MEAS, self.configuration.ctrl_meas())
+    }
+
+    /// Makes the current altitude 0, e.g., at the start of a recording, for heave and altitude changes from there
+    pub fn set_reference(&mut self, i2c: &I2c) -> Result<()> {
+        self.reference_pressure = self
+            .read(i2c)?
+            .pressure
+            .context("Pressure measurement is skipped.")?;
+        Ok(())
+    }
+
+    /// Reads pressure, temperature, and humidity, compensated with the sensor's trimming parameters. In forced mode, measures first, which takes up to `Configuration::measurement_time`
+    pub fn read(&mut self, i2c: &I2c) -> Result<BarometerSample> {
+        let variant = self
+            .variant
+            .context("Sensor needs to be initialized first.")?;
+        if self.configuration.mode == Mode::Forced {
+            self.write_register(i2c, CTRL_MEAS, self.configuration.ctrl_meas())?;
+            let timeout =
+                Duration::from_secs_f64(2.0 * self.configuration.measurement_time(variant) / 1e3);
+            let start = Instant::now();
+            // Bit 3 is set while measuring
+            while self.retry_policy.run(|| i2c.smbus_read_byte(STATUS))? & (1 << 3) != 0 {
+                anyhow::ensure!(
+                    start.elapsed() < timeout,
+                    "Measurement did not finish in time."
+                );
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        }
+
+        let mut data = [0u8; 8];
+        let length = match variant {
+            Variant::BMP280 => 6,
+            Variant::BME280 => 8,
+        };
+        self.read_registers(i2c, DATA, &mut data[..length])?;
+        self.last_sample = Some(Instant::now());
+        let sample = self.compensate(&data, variant);
+        self.sample = Some(sample);
+        Ok(sample)
+    }
+
+    fn compensate(&self, data: &[u8; 8], variant: Variant) -> BarometerSample {
+        // 20 bit values, most significant byte first
+        let raw_20 = |data: &[u8]| {
+            (((data[0] as u32) << 12) | ((data[1] as u32) << 4) | ((data[2] as u32) >> 4)) as f64
+        };
+        let (temperature, t_fine) = self.calibration.temperature(raw_20(&data[3..6]));
+        let pressure = (self.configuration.pressure_oversampling != Oversampling::Skipped)
+            .then(|| self.calibration.pressure(raw_20(&data[0..3]), t_fine));
+        let humidity = (variant == Variant::BME280
+            && self.configuration.humidity_oversampling != Oversampling::Skipped)
+            .then(|| {
+                let raw = u16::from_be_bytes([data[6], data[7]]) as f64;
+                self.calibration.humidity(raw, t_fine)
+            });
+        BarometerSample {
+            pressure,
+            temperature,
+            humidity,
+            altitude: pressure.map(|pressure| altitude(pressure, self.reference_pressure)),
+        }
+    }
+
+    /// Waits for the next measurement, up to {timeout}, and reads it. In normal mode, the sensor has no data ready signal, so this waits for a measurement period, by `sample_rate`, since the latest read. Ok(None) if that is beyond {timeout}
+    pub fn wait_for_sample(
+        &mut self,
+        i2c: &I2c,
+        timeout: Option<Duration>,
+    ) -> (Result<Option<BarometerSample>>, Instant) {
+        if self.configuration.mode == Mode::Normal {
+            if let Some(last_sample) = self.last_sample {
+                let wait = (last_sample + Duration::from_secs_f64(1.0 / self.sample_rate()))
+                    .saturating_duration_since(Instant::now());
+                if let Some(timeout) = timeout.filter(|timeout| *timeout < wait) {
+                    std::thread::sleep(timeout);
+                    return (Ok(None), Instant::now());
+                }
+                std::thread::sleep(wait);
+            }
+        }
+        let sample = self.read(i2c).context("Unable to read barometer.");
+        (sample.map(Some), Instant::now())
+    }
+}
+
+impl Sensor for BMP280 {
+    type Sample = BarometerSample;
+
+    fn initialize(&mut self, i2c: &mut I2c) -> Result<()> {
+        BMP280::initialize(self, i2c)
+    }
+
+    fn sample(&mut self, i2c: &I2c) -> Result<Self::Sample> {
+        self.read(i2c)
+    }
+
+    fn sample_rate(&self) -> f64 {
+        BMP280::sample_rate(self)
+    }
+
+    fn wait_for_sample(
+        &mut self,
+        i2c: &mut I2c,
+        timeout: Option<Duration>,
+    ) -> (Result<Option<Self::Sample>>, Instant) {
+        BMP280::wait_for_sample(self, i2c, timeout)
+    }
+}
+
+impl Default for BMP280 {
+    fn default() -> Self {
+        Self::new(Default::default(), I2C_ADDRESS)
+    }
+}
+
+/// [m] Above where the pressure is {reference_pressure} [Pa], at {pressure} [Pa], by the barometric formula of the standard atmosphere. Good to a few meters over the lowest few kilometers
+pub fn altitude(pressure: f64, reference_pressure: f64) -> f64 {
+    44330.0 * (1.0 - (pressure / reference_pressure).powf(1.0 / 5.255))
+}
+
+/// [Pa] Sea level pressure, from {pressure} [Pa] measured at a known {altitude} [m] above sea level, e.g., for `BMP280::reference_pressure`. The inverse of `altitude`
+pub fn sea_level_pressure(pressure: f64, altitude: f64) -> f64 {
+    pressure / (1.0 - altitude / 44330.0).powf(5.255)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compensation() {
+        // Example of section 3.12 of the BMP280 datasheet
+        let mut temperature_pressure = [0u8; 24];
+        for (n, value) in [
+            27504, 26435, -1000, 36477, -10685, 3024, 2855, 140, -7, 15500, -14600, 6000,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let bytes = match n {
+                0 | 3 => (value as u16).to_le_bytes(),
+                _ => (value as i16).to_le_bytes(),
+            };
+            temperature_pressure[2 * n..2 * n + 2].copy_from_slice(&bytes);
+        }
+        let calibration = Calibration::parse(&temperature_pressure, None);
+
+        let (temperature, t_fine) = calibration.temperature(519888.0);
+        assert!((temperature - 25.08).abs() < 0.01);
+        assert!((t_fine - 128422.0).abs() < 1.0);
+        let pressure = calibration.pressure(415148.0, t_fine);
+        assert!((pressure - 100653.27).abs() < 0.01);
+
+        // Humidity parameters pack two 12 bit values into 3 bytes
+        let calibration = Calibration::parse(
+            &temperature_pressure,
+            Some((75, [0x6A, 0x01, 0x00, 0x13, 0x25, 0x03, 0x1E])),
+        );
+        assert_eq!(calibration.h2, 362.0);
+        assert_eq!(calibration.h4, 309.0);
+        assert_eq!(calibration.h5, 50.0);
+        assert_eq!(calibration.h6, 30.0);
+    }
+
+    #[test]
+    fn test_altitude() {
+        assert_eq!(altitude(SEA_LEVEL_PRESSURE, SEA_LEVEL_PRESSURE), 0.0);
+        // Standard atmosphere at 1000 m
+        assert!((altitude(89874.6, SEA_LEVEL_PRESSURE) - 1000.0).abs() < 1.0);
+        // About 8 cm per Pa near sea level
+        assert!((altitude(101313.0, 101325.0) - 1.0).abs() < 0.02);
+        let pressure = sea_level_pressure(95000.0, 540.0);
+        assert!((altitude(95000.0, pressure) - 540.0).abs() < 1e-6);
+
+        let configuration = Configuration::default();
+        assert_eq!(configuration.ctrl_meas(), 0x57);
+        assert_eq!(configuration.config(), 0x10);
+        assert!((configuration.measurement_time(Variant::BMP280) - 43.2).abs() < 0.1);
+    }
+}
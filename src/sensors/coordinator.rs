@@ -0,0 +1,449 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use crossbeam_channel::{Receiver, RecvTimeoutError, SendTimeoutError, Sender};
+use rppal::i2c::I2c;
+
+use super::{
+    ads1115::AnalogSample, bmp280::BarometerSample, encoder::EncoderSample, hcsr04::DistanceSample,
+    ina219::PowerSample, rpm::RpmSample, wind::WindSample, Sensor,
+};
+use crate::{
+    gps::Fix,
+    gy521::{SampleSource, SensorSample},
+    logging::Timebase,
+    math::Vec3D,
+    power::SupplySample,
+    telemetry::RateLimit,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100); // Longest a source waits for a sample in one go, and the coordinator for sources to finish, so that either notices being stopped
+
+/// A sample of any of the sensors
+#[derive(Debug, Clone, Copy)]
+pub enum Reading {
+    Imu(SensorSample<Vec3D, f64>),
+    MagneticField(Vec3D), // [G]
+    Barometer(BarometerSample),
+    Gps(Fix),
+    Analog(AnalogSample),
+    Encoder(EncoderSample),
+    Distance(DistanceSample),
+    Wind(WindSample),
+    Rpm(RpmSample),
+    Power(PowerSample),
+    Supply(SupplySample),
+}
+
+impl From<SensorSample<Vec3D, f64>> for Reading {
+    fn from(sample: SensorSample<Vec3D, f64>) -> Self {
+        Self::Imu(sample)
+    }
+}
+
+impl From<Vec3D> for Reading {
+    fn from(magnetic_field: Vec3D) -> Self {
+        Self::MagneticField(magnetic_field)
+    }
+}
+
+impl From<BarometerSample> for Reading {
+    fn from(sample: BarometerSample) -> Self {
+        Self::Barometer(sample)
+    }
+}
+
+impl From<Fix> for Reading {
+    fn from(fix: Fix) -> Self {
+        Self::Gps(fix)
+    }
+}
+
+impl From<AnalogSample> for Reading {
+    fn from(sample: AnalogSample) -> Self {
+        Self::Analog(sample)
+    }
+}
+
+impl From<EncoderSample> for Reading {
+    fn from(sample: EncoderSample) -> Self {
+        Self::Encoder(sample)
+    }
+}
+
+impl From<DistanceSample> for Reading {
+    fn from(sample: DistanceSample) -> Self {
+        Self::Distance(sample)
+    }
+}
+
+impl From<WindSample> for Reading {
+    fn from(sample: WindSample) -> Self {
+        Self::Wind(sample)
+    }
+}
+
+impl From<RpmSample> for Reading {
+    fn from(sample: RpmSample) -> Self {
+        Self::Rpm(sample)
+    }
+}
+
+impl From<PowerSample> for Reading {
+    fn from(sample: PowerSample) -> Self {
+        Self::Power(sample)
+    }
+}
+
+impl From<SupplySample> for Reading {
+    fn from(sample: SupplySample) -> Self {
+        Self::Supply(sample)
+    }
+}
+
+/// A sample, with where and when it was taken
+#[derive(Debug, Clone)]
+pub struct TimedReading {
+    pub source: Arc<str>, // Name it was added with
+    pub instant: Instant,
+    pub time: Duration, // Since the start of the timebase
+    pub reading: Reading,
+}
+
+/// How one source of a `Coordinator` has done so far
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceStatus {
+    pub name: String,
+    pub samples: usize, // Passed on
+    pub skipped: usize, // Beyond the rate of the source
+    pub errors: usize,
+    pub last_error: Option<String>,
+    pub finished: bool, // E.g., the end of a recording. Sensors never finish
+}
+
+// Orders the samples waiting to be passed on by instant, and otherwise by arrival
+struct Pending {
+    instant: Instant,
+    sequence: u64,
+    reading: TimedReading,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        (self.instant, self.sequence) == (other.instant, other.sequence)
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.instant, self.sequence).cmp(&(other.instant, other.sequence))
+    }
+}
+
+/// A sensor with its own `I2c`, as a `SampleSource` that can go to another thread
+struct OwnedSource<S> {
+    sensor: S,
+    i2c: I2c,
+}
+
+impl<S: Sensor> SampleSource<S::Sample> for OwnedSource<S> {
+    fn next_sample(&mut self, timeout: Option<Duration>) -> (Result<Option<S::Sample>>, Instant) {
+        self.sensor.wait_for_sample(&mut self.i2c, timeout)
+    }
+}
+
+/// Samples several sensors at once, e.g., the GY-521, a magnetometer, a barometer, and a GPS receiver, each on its own thread, so that their blocking drivers don't hold up each other.
+/// Their samples come out of `next` as one stream, ordered by the instants they were taken at, and tagged with the time on a common timebase.
+/// Sources deliver their samples a little after taking them, e.g., GPS fixes after their last sentence arrived. So samples are held back for {latency} before they are passed on, to let the samples of other sources that were taken earlier catch up. Samples that come even later are passed on right away, out of order, and counted in `late`
+pub struct Coordinator {
+    timebase: Timebase,
+    latency: Duration,
+    sender: Sender<TimedReading>,
+    receiver: Receiver<TimedReading>,
+    pending: BinaryHeap<Reverse<Pending>>,
+    sequence: u64,           // Of the next sample to arrive
+    latest: Option<Instant>, // Of the latest sample passed on
+    late: usize,
+    sources: Vec<(Arc<Mutex<SourceStatus>>, JoinHandle<()>)>,
+    running: Arc<AtomicBool>,
+}
+
+impl Coordinator {
+    /// Queues up to {queue_capacity} samples that aren't taken out by `next` yet, after which the sources wait
+    pub fn new(timebase: Timebase, latency: Duration, queue_capacity: usize) -> Self {
+        let (sender, receiver) = crossbeam_channel::bounded(queue_capacity.max(1));
+        Self {
+            timebase,
+            latency,
+            sender,
+            receiver,
+            pending: BinaryHeap::new(),
+            sequence: 0,
+            latest: None,
+            late: 0,
+            sources: Vec::new(),
+            running: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    /// Starts sampling {source}, e.g., a `gps::GpsReceiver`, known as {name}, at up to {rate} [Hz]. None passes on every sample
+    pub fn add<T, S>(&mut self, name: &str, mut source: S, rate: Option<f64>) -> Result<()>
+    where
+        T: Into<Reading>,
+        S: SampleSource<T> + Send + 'static,
+    {
+        let mut rate_limit =
+            RateLimit::new(rate).with_context(|| format!("Invalid rate of {name}."))?;
+        let status = Arc::new(Mutex::new(SourceStatus {
+            name: name.to_string(),
+            ..Default::default()
+        }));
+        let thread = {
+            let (status, sender, running) =
+                (status.clone(), self.sender.clone(), self.running.clone());
+            let (source_name, timebase) = (Arc::<str>::from(name), self.timebase);
+            std::thread::Builder::new()
+                .name(format!("sensor-{name}"))
+                .spawn(move || {
+                    while running.load(Ordering::Relaxed) {
+                        match source.next_sample(Some(POLL_INTERVAL)) {
+                            (Ok(Some(sample)), instant) => {
+                                if !rate_limit.admit(instant) {
+                                    status.lock().unwrap().skipped += 1;
+                                    continue;
+                                }
+                                let mut reading = TimedReading {
+                                    source: source_name.clone(),
+                                    instant,
+                                    time: timebase.elapsed(instant),
+                                    reading: sample.into(),
+                                };
+                                // Waits in steps while the queue is full, to notice being stopped
+                                loop {
+                                    match sender.send_timeout(reading, POLL_INTERVAL) {
+                                        Ok(()) => break,
+                                        Err(SendTimeoutError::Timeout(unsent))
+                                            if running.load(Ordering::Relaxed) =>
+                                        {
+                                            reading = unsent
+                                        }
+                                        Err(_) => return,
+                                    }
+                                }
+                                status.lock().unwrap().samples += 1;
+                            }
+                            (Ok(None), _) if source.is_finished() => break,
+                            (Ok(None), _) => {}
+                            (Err(error), _) => {
+                                let mut status = status.lock().unwrap();
+                                status.errors += 1;
+                                status.last_error = Some(format!("{error:#}"));
+                            }
+                        }
+                    }
+                    status.lock().unwrap().finished = true;
+                })
+                .with_context(|| format!("Unable to start sampling {name}."))?
+        };
+        self.sources.push((status, thread));
+        Ok(())
+    }
+
+    /// Starts sampling {sensor} over {i2c}, known as {name}, at up to {rate} [Hz], like `add`. Give every sensor its own `I2c`, opened on the same bus, since each keeps its own slave address
+    pub fn add_sensor<S>(
+        &mut self,
+        name: &str,
+        sensor: S,
+        i2c: I2c,
+        rate: Option<f64>,
+    ) -> Result<()>
+    where
+        S: Sensor + Send + 'static,
+        S::Sample: Into<Reading>,
+    {
+        self.add(name, OwnedSource { sensor, i2c }, rate)
+    }
+
+    /// Waits up to {timeout} for the next sample, in the order they were taken. None if there is none in time, or all sources are finished, and everything is passed on
+    pub fn next(&mut self, timeout: Option<Duration>) -> Option<TimedReading> {
+        let start = Instant::now();
+        loop {
+            while let Ok(reading) = self.receiver.try_recv() {
+                self.push(reading);
+            }
+            let finished = self.is_finished();
+            let due = match self.pending.peek() {
+                Some(_) if finished => Instant::now(),
+                Some(Reverse(pending)) => pending.instant + self.latency,
+                None if finished => return None,
+                None => Instant::now() + POLL_INTERVAL,
+            };
+            if Instant::now() >= due {
+                return self.pop();
+            }
+
+            let mut wait = due.saturating_duration_since(Instant::now());
+            if let Some(timeout) = timeout {
+                let left = timeout.saturating_sub(start.elapsed());
+                if left.is_zero() {
+                    return None;
+                }
+                wait = wait.min(left);
+            }
+            match self.receiver.recv_timeout(wait) {
+                Ok(reading) => self.push(reading),
+                Err(RecvTimeoutError::Timeout) => {}
+                // The coordinator keeps a sender itself
+                Err(RecvTimeoutError::Disconnected) => unreachable!(),
+            }
+        }
+    }
+
+    fn push(&mut self, reading: TimedReading) {
+        self.pending.push(Reverse(Pending {
+            instant: reading.instant,
+            sequence: self.sequence,
+            reading,
+        }));
+        self.sequence += 1;
+    }
+
+    fn pop(&mut self) -> Option<TimedReading> {
+        let Reverse(pending) = self.pending.pop()?;
+        if self.latest.is_some_and(|latest| pending.instant < latest) {
+            self.late += 1;
+        } else {
+            self.latest = Some(pending.instant);
+        }
+        Some(pending.reading)
+    }
+
+    /// Whether all sources are finished, and all of their samples were taken out by `next`
+    pub fn is_finished(&self) -> bool {
+        self.sources
+            .iter()
+            .all(|(status, _)| status.lock().unwrap().finished)
+            && self.receiver.is_empty()
+            && self.pending.is_empty()
+    }
+
+    /// Samples that were passed on out of order, since they came in later than {latency}
+    pub fn late(&self) -> usize {
+        self.late
+    }
+
+    /// Of every source, in the order they were added
+    pub fn status(&self) -> Vec<SourceStatus> {
+        self.sources
+            .iter()
+            .map(|(status, _)| status.lock().unwrap().clone())
+            .collect()
+    }
+}
+
+impl Drop for Coordinator {
+    fn drop(&mut self) {
+        // Sources notice within their poll interval
+        self.running.store(false, Ordering::Relaxed);
+        for (_, thread) in self.sources.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Plays back samples at given instants, as fast as they are asked for
+    struct Playback<T> {
+        samples: std::vec::IntoIter<(T, Instant)>,
+        delay: Duration, // Before handing out each sample
+    }
+
+    impl<T> SampleSource<T> for Playback<T> {
+        fn next_sample(&mut self, _timeout: Option<Duration>) -> (Result<Option<T>>, Instant) {
+            std::thread::sleep(self.delay);
+            match self.samples.next() {
+                Some((sample, instant)) => (Ok(Some(sample)), instant),
+                None => (Ok(None), Instant::now()),
+            }
+        }
+
+        fn is_finished(&self) -> bool {
+            self.samples.len() == 0
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let timebase = Timebase::now();
+        let at = |milliseconds: u64| timebase.start + Duration::from_millis(milliseconds);
+        let mut coordinator = Coordinator::new(timebase, Duration::from_millis(200), 100);
+
+        // 100 Hz, thinned out to 50 Hz
+        let imu = (0..10)
+            .map(|n| {
+                let sample = SensorSample::new(Vec3D::new(0, 0, 1), Vec3D::new(n, 0, 0), None);
+                (sample, at(10 * n as u64))
+            })
+            .collect::<Vec<_>>();
+        let imu = Playback {
+            samples: imu.into_iter(),
+            delay: Duration::ZERO,
+        };
+        coordinator.add("imu", imu, Some(50.0)).unwrap();
+        // Slow to deliver, like a GPS receiver
+        let magnetometer = Playback {
+            samples: vec![
+                (Vec3D::new(0.2, 0, 0.4), at(15)),
+                (Vec3D::new(0.2, 0, 0.4), at(55)),
+            ]
+            .into_iter(),
+            delay: Duration::from_millis(50),
+        };
+        coordinator.add("magnetometer", magnetometer, None).unwrap();
+
+        let mut readings = Vec::new();
+        while let Some(reading) = coordinator.next(Some(Duration::from_secs(5))) {
+            readings.push(reading);
+        }
+        assert!(coordinator.is_finished());
+        assert_eq!(coordinator.late(), 0);
+
+        let times = readings
+            .iter()
+            .map(|reading| reading.time.as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(times, [0, 15, 20, 40, 55, 60, 80]);
+        assert_eq!(&*readings[1].source, "magnetometer");
+        let Reading::Imu(sample) = readings[2].reading else {
+            panic!("Not an IMU sample.");
+        };
+        assert_eq!(sample.angular_velocity(), Vec3D::new(2, 0, 0));
+
+        let status = coordinator.status();
+        assert_eq!((status[0].samples, status[0].skipped), (5, 5));
+        assert_eq!(status[1].samples, 2);
+        assert!(status
+            .iter()
+            .all(|status| status.finished && status.errors == 0));
+    }
+}
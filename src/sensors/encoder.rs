@@ -0,0 +1,334 @@
+// Quadrature encoders on two GPIO pins, e.g., a paddlewheel log for the speed through the water, or a rotary encoder on the rudder stock for the rudder angle.
+// Every edge on either pin counts, so an encoder with N pulses per revolution gives 4 N counts per revolution.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use rppal::gpio::{InputPin, Level, Trigger};
+
+use crate::gy521::SampleSource;
+
+/// How `Encoder` scales and filters the counts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EncoderConfig {
+    pub scale: f64, // [unit/count] E.g., [m] of water passing a paddlewheel, or [rad] of a rudder, per count. Negative to count the other way around
+    pub glitch_filter: Duration, // Shortest pulse taken for real. Edges following the previous one on the same pin sooner than this only count once the level has settled
+    pub sample_rate: f64,        // [Hz] Of the samples as a `SampleSource`
+}
+
+impl EncoderConfig {
+    /// Counts as they are, 10 times a second, filtering pulses shorter than 100 µs
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            glitch_filter: Duration::from_micros(100),
+            sample_rate: 10.0,
+        }
+    }
+}
+
+/// Position and velocity at one instant
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub struct EncoderSample {
+    pub count: i64,
+    pub position: f64, // [unit] {count} scaled
+    pub velocity: f64, // [unit/s]
+}
+
+/// How clean the signal has been so far
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EncoderStatus {
+    pub glitches: usize, // Edges within the glitch filter of the previous one on the same pin
+    pub missed: usize, // Edges to the level the pin was at already, meaning that one in between was missed, e.g., a pulse shorter than the interrupt latency
+}
+
+// Pins
+const A: usize = 0;
+const B: usize = 1;
+
+// Position of the pins' levels, (A, B), in the sequence 00, 10, 11, 01, which is forward with A leading
+fn phase(levels: [bool; 2]) -> i64 {
+    match levels {
+        [false, false] => 0,
+        [true, false] => 1,
+        [true, true] => 2,
+        [false, true] => 3,
+    }
+}
+
+// Counts the edges on both pins, as the interrupts report them
+#[derive(Debug, Clone)]
+struct Decoder {
+    levels: [bool; 2],               // Counted
+    raw_levels: [bool; 2],           // As of the latest edge, which may still be filtered
+    last_edge: [Option<Instant>; 2], // Latest edge on each pin, filtered or not
+    glitch_filter: Duration,
+    count: i64,
+    last_step: Option<Instant>, // When {count} last changed
+    status: EncoderStatus,
+}
+
+impl Decoder {
+    fn new(levels: [bool; 2], glitch_filter: Duration) -> Self {
+        Self {
+            levels,
+            raw_levels: levels,
+            last_edge: [None; 2],
+            glitch_filter,
+            count: 0,
+            last_step: None,
+            status: EncoderStatus::default(),
+        }
+    }
+
+    // Counts the step to {level} on {pin}
+    fn step(&mut self, pin: usize, level: bool, instant: Instant) {
+        if self.levels[pin] == level {
+            return;
+        }
+        let previous = phase(self.levels);
+        self.levels[pin] = level;
+        // One pin changing moves one step either way
+        self.count += match (phase(self.levels) - previous).rem_euclid(4) {
+            1 => 1,
+            _ => -1,
+        };
+        self.last_step = Some(instant);
+    }
+
+    // Takes in an edge to {level} on {pin} at {instant}
+    fn edge(&mut self, pin: usize, level: bool, instant: Instant) {
+        if self.raw_levels[pin] == level {
+            self.status.missed += 1;
+            return;
+        }
+        self.raw_levels[pin] = level;
+        let last_edge = self.last_edge[pin].replace(instant);
+        if last_edge.is_some_and(|last_edge| instant.duration_since(last_edge) < self.glitch_filter)
+        {
+            self.status.glitches += 1;
+            return;
+        }
+        // The other pin has settled by now, if it was still filtered
+        let other = 1 - pin;
+        self.step(other, self.raw_levels[other], instant);
+        self.step(pin, level, instant);
+    }
+
+    // Counts the levels that have been stable for the glitch filter by {now}
+    fn settle(&mut self, now: Instant) {
+        for pin in [A, B] {
+            let settled = self.last_edge[pin]
+                .is_none_or(|last_edge| now.duration_since(last_edge) >= self.glitch_filter);
+            if settled {
+                let last_edge = self.last_edge[pin].unwrap_or(now);
+                self.step(pin, self.raw_levels[pin], last_edge);
+            }
+        }
+    }
+}
+
+// [count/s] Velocity between the latest counts at {previous} and {current}, each (count, instant of the step to it). Without a step since, it is at most one count over the time since the latest step until {now}, so that it falls off towards 0 when the encoder stops
+fn velocity(
+    previous: (i64, Instant),
+    current: (i64, Instant),
+    now: Instant,
+    previous_velocity: f64,
+) -> f64 {
+    let (previous_count, previous_instant) = previous;
+    let (count, instant) = current;
+    let duration = instant
+        .saturating_duration_since(previous_instant)
+        .as_secs_f64();
+    if count != previous_count && duration > 0.0 {
+        (count - previous_count) as f64 / duration
+    } else {
+        let bound = 1.0 / now.saturating_duration_since(instant).as_secs_f64();
+        previous_velocity.clamp(-bound, bound)
+    }
+}
+
+/// A quadrature encoder with its A and B outputs on {pin_a} and {pin_b}, counted on interrupts, forward when A leads.
+/// The pins usually need pull-ups, e.g., `Pin::into_input_pullup`, since most encoders pull their outputs to ground only
+pub struct Encoder {
+    pub config: EncoderConfig,
+    pin_a: InputPin,
+    pin_b: InputPin,
+    decoder: Arc<Mutex<Decoder>>,
+    previous: Option<(i64, Instant)>, // Count and instant of its step, as of the latest sample
+    previous_velocity: f64,           // [count/s]
+    last_sample: Option<Instant>,
+}
+
+impl Encoder {
+    pub fn new(mut pin_a: InputPin, mut pin_b: InputPin, config: EncoderConfig) -> Result<Self> {
+        let levels = [pin_a.is_high(), pin_b.is_high()];
+        let decoder = Arc::new(Mutex::new(Decoder::new(levels, config.glitch_filter)));
+
+        for (pin, index) in [(&mut pin_a, A), (&mut pin_b, B)] {
+            let decoder = decoder.clone();
+            pin.set_async_interrupt(Trigger::Both, move |level| {
+                let instant = Instant::now();
+                if let Ok(mut decoder) = decoder.lock() {
+                    decoder.edge(index, level == Level::High, instant);
+                }
+            })
+            .context("Unable to set up encoder interrupt.")?;
+        }
+
+        Ok(Self {
+            config,
+            pin_a,
+            pin_b,
+            decoder,
+            previous: None,
+            previous_velocity: 0.0,
+            last_sample: None,
+        })
+    }
+
+    /// Sets the current position to 0, e.g., with the rudder amidships
+    pub fn zero(&mut self) {
+        let mut decoder = self.decoder.lock().unwrap();
+        decoder.count = 0;
+        if let Some(previous) = &mut self.previous {
+            previous.0 = 0;
+        }
+    }
+
+    pub fn status(&self) -> EncoderStatus {
+        self.decoder.lock().unwrap().status
+    }
+
+    /// Position and velocity as of now
+    pub fn sample(&mut self) -> EncoderSample {
+        let now = Instant::now();
+        let current = {
+            let mut decoder = self.decoder.lock().unwrap();
+            decoder.settle(now);
+            (decoder.count, decoder.last_step.unwrap_or(now))
+        };
+        let velocity = match self.previous {
+            Some(previous) => velocity(previous, current, now, self.previous_velocity),
+            None => 0.0,
+        };
+        self.previous = Some(current);
+        self.previous_velocity = velocity;
+        self.last_sample = Some(now);
+
+        EncoderSample {
+            count: current.0,
+            position: current.0 as f64 * self.config.scale,
+            velocity: velocity * self.config.scale,
+        }
+    }
+}
+
+impl SampleSource<EncoderSample> for Encoder {
+    /// Waits until a sample period, by {config.sample_rate}, has passed since the latest sample, and samples. Ok(None) if that is beyond {timeout}
+    fn next_sample(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> (Result<Option<EncoderSample>>, Instant) {
+        if let Some(last_sample) = self.last_sample {
+            let wait = (last_sample + Duration::from_secs_f64(1.0 / self.config.sample_rate))
+                .saturating_duration_since(Instant::now());
+            if let Some(timeout) = timeout.filter(|timeout| *timeout < wait) {
+                std::thread::sleep(timeout);
+                return (Ok(None), Instant::now());
+            }
+            std::thread::sleep(wait);
+        }
+        let sample = self.sample();
+        (Ok(Some(sample)), Instant::now())
+    }
+}
+
+impl Drop for Encoder {
+    fn drop(&mut self) {
+        // The callbacks hold on to the decoder otherwise
+        let _ = self.pin_a.clear_async_interrupt();
+        let _ = self.pin_b.clear_async_interrupt();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decoder() {
+        let start = Instant::now();
+        let at = |microseconds: u64| start + Duration::from_micros(microseconds);
+        let mut decoder = Decoder::new([false, false], Duration::from_micros(100));
+
+        // One cycle forward, with A leading, and half of one back
+        decoder.edge(A, true, at(0));
+        decoder.edge(B, true, at(1000));
+        decoder.edge(A, false, at(2000));
+        decoder.edge(B, false, at(3000));
+        assert_eq!(decoder.count, 4);
+        decoder.edge(A, true, at(4000));
+        decoder.edge(A, false, at(5000));
+        assert_eq!(decoder.count, 4);
+        decoder.edge(B, true, at(6000));
+        decoder.edge(A, true, at(7000));
+        assert_eq!(decoder.count, 2);
+        assert_eq!(decoder.last_step, Some(at(7000)));
+
+        // A bouncing on its way down counts once, and its bounces not at all
+        decoder.edge(A, false, at(8000));
+        decoder.edge(A, true, at(8010));
+        decoder.edge(A, false, at(8020));
+        assert_eq!(decoder.count, 3);
+        assert_eq!(decoder.status.glitches, 2);
+        // A spike on B is taken back once the level has been stable for the glitch filter
+        decoder.edge(B, false, at(8500));
+        decoder.edge(B, true, at(8510));
+        assert_eq!(decoder.count, 4);
+        decoder.settle(at(8550));
+        assert_eq!(decoder.count, 4);
+        decoder.settle(at(8700));
+        assert_eq!(decoder.count, 3);
+        assert_eq!(decoder.last_step, Some(at(8510)));
+
+        decoder.edge(B, true, at(9000));
+        assert_eq!(decoder.status.missed, 1);
+        assert_eq!(decoder.count, 3);
+    }
+
+    #[test]
+    fn test_velocity() {
+        let start = Instant::now();
+        let at = |milliseconds: u64| start + Duration::from_millis(milliseconds);
+
+        let near = |velocity: f64, expected: f64| (velocity - expected).abs() < 1e-9;
+        // 10 counts in 50 ms, timed by the steps rather than the samples
+        assert!(near(
+            velocity((0, at(0)), (10, at(50)), at(100), 0.0),
+            200.0
+        ));
+        assert!(near(
+            velocity((10, at(50)), (5, at(75)), at(100), 200.0),
+            -200.0
+        ));
+        // Stopped: No more than one count since the latest step
+        assert!(near(
+            velocity((5, at(75)), (5, at(75)), at(175), -200.0),
+            -10.0
+        ));
+        assert!(near(
+            velocity((5, at(75)), (5, at(75)), at(80), -20.0),
+            -20.0
+        ));
+    }
+}
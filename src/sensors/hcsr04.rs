@@ -0,0 +1,200 @@
+// HC-SR04 ultrasonic distance sensor, e.g., for the height of the boom above the deck, or the distance to obstacles ahead.
+// A 10 µs pulse on the trigger pin sends a ping, and the echo pin is then high for as long as the sound took to come back. Datasheet: https://cdn.sparkfun.com/datasheets/Sensors/Proximity/HCSR04.pdf
+// The echo pin is driven at 5 V, so it needs a voltage divider, e.g., 1 kΩ and 2 kΩ, before the 3.3 V GPIO of the Pi.
+
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use rppal::gpio::{InputPin, OutputPin};
+
+use super::pulse::{self, PulseTimer};
+use crate::gy521::SampleSource;
+
+const TRIGGER_PULSE: Duration = Duration::from_micros(10);
+const ECHO_TIMEOUT: Duration = Duration::from_millis(60); // Without an obstacle, the echo pin goes high for about 38 ms
+pub const MIN_PING_PERIOD: Duration = Duration::from_millis(60); // Recommended by the datasheet, so that echoes of one ping don't get taken for those of the next
+
+/// [m/s] Of sound in dry air at {temperature} [degree C]
+pub fn speed_of_sound(temperature: f64) -> f64 {
+    331.3 * (1.0 + temperature / 273.15).sqrt()
+}
+
+/// How `HCSR04` turns echoes into distances
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DistanceConfig {
+    pub temperature: f64, // [degree C] Of the air, for the speed of sound, e.g., from `bmp280::BMP280`. About 0.17 % of the distance per degree
+    pub range: (f64, f64), // [m] Distances outside of this read as None. The HC-SR04 measures from 2 cm to 4 m
+    pub sample_rate: f64,  // [Hz] Of the pings as a `SampleSource`, at most 1 / `MIN_PING_PERIOD`
+}
+
+impl DistanceConfig {
+    /// 20 °C, the full range of the HC-SR04, 10 pings per second
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // [m] Of an echo taking {round_trip}. None outside of {range}
+    fn distance(&self, round_trip: Duration) -> Option<f64> {
+        let distance = round_trip.as_secs_f64() * speed_of_sound(self.temperature) / 2.0;
+        (self.range.0..=self.range.1)
+            .contains(&distance)
+            .then_some(distance)
+    }
+}
+
+impl Default for DistanceConfig {
+    fn default() -> Self {
+        Self {
+            temperature: 20.0,
+            range: (0.02, 4.0),
+            sample_rate: 10.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub struct DistanceSample {
+    pub distance: Option<f64>,   // [m] None without an echo within range
+    pub round_trip: Option<f64>, // [s] Of the echo, whether within range or not
+}
+
+/// How the pings went so far
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DistanceStatus {
+    pub pings: usize,
+    pub no_echo: usize, // Pings without a complete echo pulse in time, e.g., with a missed edge
+    pub out_of_range: usize,
+}
+
+/// An HC-SR04 with its trigger on {trigger} and its echo on {echo}, timed on interrupts
+pub struct HCSR04 {
+    pub config: DistanceConfig,
+    trigger: OutputPin,
+    echo: InputPin,
+    edges: Receiver<(bool, Instant)>, // Of {echo}
+    status: DistanceStatus,
+    last_ping: Option<Instant>,
+}
+
+impl HCSR04 {
+    pub fn new(mut trigger: OutputPin, mut echo: InputPin, config: DistanceConfig) -> Result<Self> {
+        anyhow::ensure!(
+            config.sample_rate > 0.0 && config.sample_rate.is_finite(),
+            "Invalid sample rate: {} Hz.",
+            config.sample_rate
+        );
+        trigger.set_low();
+        let edges = pulse::watch_edges(&mut echo)?;
+        Ok(Self {
+            config,
+            trigger,
+            echo,
+            edges,
+            status: DistanceStatus::default(),
+            last_ping: None,
+        })
+    }
+
+    pub fn status(&self) -> DistanceStatus {
+        self.status
+    }
+
+    /// When the latest ping was sent
+    pub fn last_ping(&self) -> Option<Instant> {
+        self.last_ping
+    }
+
+    /// Sends a ping and waits for its echo, up to 60 ms. Pinging more often than every `MIN_PING_PERIOD` may pick up echoes of the previous ping
+    pub fn measure(&mut self) -> Result<DistanceSample> {
+        // Edges of earlier pings
+        while self.edges.try_recv().is_ok() {}
+
+        let mut timer = PulseTimer::default();
+        let start = Instant::now();
+        timer.arm(start);
+        self.trigger.set_high();
+        // Sleeping takes far longer than the pulse needs
+        while start.elapsed() < TRIGGER_PULSE {
+            std::hint::spin_loop();
+        }
+        self.trigger.set_low();
+        self.last_ping = Some(start);
+        self.status.pings += 1;
+
+        let deadline = start + ECHO_TIMEOUT;
+        let round_trip = loop {
+            match self.edges.recv_deadline(deadline) {
+                Ok((high, instant)) => {
+                    if let Some(width) = timer.edge(high, instant) {
+                        break Some(width);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => break None,
+                Err(RecvTimeoutError::Disconnected) => {
+                    anyhow::bail!("The echo interrupt is gone.")
+                }
+            }
+        };
+
+        let distance = round_trip.and_then(|round_trip| self.config.distance(round_trip));
+        match (round_trip, distance) {
+            (None, _) => self.status.no_echo += 1,
+            (Some(_), None) => self.status.out_of_range += 1,
+            _ => {}
+        }
+        Ok(DistanceSample {
+            distance,
+            round_trip: round_trip.map(|round_trip| round_trip.as_secs_f64()),
+        })
+    }
+}
+
+impl SampleSource<DistanceSample> for HCSR04 {
+    /// Waits until a sample period, by {config.sample_rate} and at least `MIN_PING_PERIOD`, has passed since the latest ping, and measures. Ok(None) if that is beyond {timeout}. The instant is that of the ping
+    fn next_sample(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> (Result<Option<DistanceSample>>, Instant) {
+        if let Some(last_ping) = self.last_ping {
+            let period =
+                Duration::from_secs_f64(1.0 / self.config.sample_rate).max(MIN_PING_PERIOD);
+            let wait = (last_ping + period).saturating_duration_since(Instant::now());
+            if let Some(timeout) = timeout.filter(|timeout| *timeout < wait) {
+                std::thread::sleep(timeout);
+                return (Ok(None), Instant::now());
+            }
+            std::thread::sleep(wait);
+        }
+        let sample = self.measure();
+        let instant = self.last_ping.unwrap_or_else(Instant::now);
+        (sample.map(Some), instant)
+    }
+}
+
+impl Drop for HCSR04 {
+    fn drop(&mut self) {
+        let _ = self.echo.clear_async_interrupt();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distance() {
+        assert!((speed_of_sound(20.0) - 343.2).abs() < 0.1);
+        assert!((speed_of_sound(0.0) - 331.3).abs() < 1e-9);
+
+        let config = DistanceConfig::new();
+        // 1 m there and back at 343 m/s
+        let distance = config
+            .distance(Duration::from_secs_f64(2.0 / 343.2))
+            .unwrap();
+        assert!((distance - 1.0).abs() < 1e-3);
+        // Without an obstacle
+        assert_eq!(config.distance(Duration::from_millis(38)), None);
+        assert_eq!(config.distance(Duration::from_micros(50)), None);
+    }
+}
@@ -0,0 +1,354 @@
+// Honeywell HMC5883L 3-axis magnetometer, e.g., on GY-271 and GY-273 boards.
+// Register map: https://cdn-shop.adafruit.com/datasheets/HMC5883L_3-Axis_Digital_Compass_IC.pdf
+
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use rppal::{
+    gpio::{InputPin, Trigger},
+    i2c::I2c,
+};
+
+use super::{
+    magnetometer::{self, IronCalibration},
+    Sensor,
+};
+use crate::{fusion, math::Vec3D, utilites};
+
+pub const I2C_ADDRESS: u16 = 0x1E; // Fixed, so only one HMC5883L fits on a bus
+
+const CONFIGURATION_A: u8 = 0x00;
+const CONFIGURATION_B: u8 = 0x01;
+const MODE: u8 = 0x02;
+const DATA: u8 = 0x03; // Six bytes, X, Z, Y, each big-endian
+const STATUS: u8 = 0x09;
+const IDENTIFICATION: u8 = 0x0A; // Three bytes
+const IDENTITY: [u8; 3] = *b"H43";
+const OVERFLOW: i16 = -4096; // Reading of an axis whose field is beyond the range of the gain. See section "Data Output X Registers A and B"
+const POLL_INTERVAL: Duration = Duration::from_millis(1); // Between reads of the status register, without a data ready pin
+
+/// Number of measurements averaged per output. Configuration register A, bits 6-5
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Averaging {
+    #[default]
+    One = 0b00,
+    Two = 0b01,
+    Four = 0b10,
+    Eight = 0b11,
+}
+
+/// Output rate in continuous measurement mode. Configuration register A, bits 4-2
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DataRate {
+    Hz0_75 = 0b000,
+    Hz1_5 = 0b001,
+    Hz3 = 0b010,
+    Hz7_5 = 0b011,
+    #[default]
+    Hz15 = 0b100,
+    Hz30 = 0b101,
+    Hz75 = 0b110,
+}
+
+impl DataRate {
+    /// [Hz]
+    pub fn frequency(&self) -> f64 {
+        match self {
+            Self::Hz0_75 => 0.75,
+            Self::Hz1_5 => 1.5,
+            Self::Hz3 => 3.0,
+            Self::Hz7_5 => 7.5,
+            Self::Hz15 => 15.0,
+            Self::Hz30 => 30.0,
+            Self::Hz75 => 75.0,
+        }
+    }
+}
+
+/// Configuration register A, bits 1-0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MeasurementMode {
+    #[default]
+    Normal = 0b00,
+    PositiveBias = 0b01, // Adds a self-test field of about 1.1 G to the x and y axes, and 0.4 G to the z axis
+    NegativeBias = 0b10, // Like PositiveBias, but subtracted
+}
+
+/// Full-scale range. Configuration register B, bits 7-5
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Gain {
+    Gauss0_88 = 0b000,
+    #[default]
+    Gauss1_3 = 0b001,
+    Gauss1_9 = 0b010,
+    Gauss2_5 = 0b011,
+    Gauss4_0 = 0b100,
+    Gauss4_7 = 0b101,
+    Gauss5_6 = 0b110,
+    Gauss8_1 = 0b111,
+}
+
+impl Gain {
+    /// [LSB/G]
+    pub fn scale_factor(&self) -> f64 {
+        match self {
+            Self::Gauss0_88 => 1370.0,
+            Self::Gauss1_3 => 1090.0,
+            Self::Gauss1_9 => 820.0,
+            Self::Gauss2_5 => 660.0,
+            Self::Gauss4_0 => 440.0,
+            Self::Gauss4_7 => 390.0,
+            Self::Gauss5_6 => 330.0,
+            Self::Gauss8_1 => 230.0,
+        }
+    }
+
+    /// [G] Largest field that can be measured
+    pub fn range(&self) -> f64 {
+        match self {
+            Self::Gauss0_88 => 0.88,
+            Self::Gauss1_3 => 1.3,
+            Self::Gauss1_9 => 1.9,
+            Self::Gauss2_5 => 2.5,
+            Self::Gauss4_0 => 4.0,
+            Self::Gauss4_7 => 4.7,
+            Self::Gauss5_6 => 5.6,
+            Self::Gauss8_1 => 8.1,
+        }
+    }
+}
+
+/// Mode register, bits 1-0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OperatingMode {
+    #[default]
+    Continuous = 0b00, // Measures at the data rate
+    Single = 0b01, // Measures once, then idles. `read` starts the next measurement, so samples come as fast as they are read, up to 160 Hz
+    Idle = 0b10,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Configuration {
+    pub averaging: Averaging,
+    pub data_rate: DataRate,
+    pub measurement_mode: MeasurementMode,
+    pub gain: Gain,
+    pub operating_mode: OperatingMode,
+}
+
+impl Configuration {
+    /// Contents of configuration register A
+    pub fn register_a(&self) -> u8 {
+        ((self.averaging as u8) << 5) | ((self.data_rate as u8) << 2) | self.measurement_mode as u8
+    }
+
+    /// Contents of configuration register B
+    pub fn register_b(&self) -> u8 {
+        (self.gain as u8) << 5
+    }
+}
+
+/// The magnetometer, measuring the magnetic field in [G], e.g., for the heading with `heading`.
+/// The GY-521 sets its slave address only once, in `initialize`, so give this its own `I2c`, opened on the same bus. Mount it with its axes along the axes of the GY-521, or rotate the readings accordingly, for tilt compensation to work
+#[non_exhaustive]
+pub struct HMC5883L {
+    pub configuration: Configuration,
+    pub i2c_address: u16,
+    pub magnetic_field: Vec3D, // [G] Latest, calibrated
+    pub calibration: IronCalibration,
+    pub data_ready_pin: Option<InputPin>, // DRDY, pulled low for 250 us whenever new data is ready. None polls the status register instead
+    pub retry_policy: utilites::RetryPolicy, // Applied to all register reads and writes
+    pub overflows: usize, // Readings rejected so far, because the field was beyond the range of the gain
+}
+
+impl HMC5883L {
+    pub fn new(configuration: Configuration, i2c_address: u16) -> Self {
+        Self {
+            configuration,
+            i2c_address,
+            magnetic_field: Default::default(),
+            calibration: Default::default(),
+            data_ready_pin: None,
+            retry_policy: Default::default(),
+            overflows: 0,
+        }
+    }
+
+    /// [Hz] How often new data is ready. In single measurement mode, that is up to how fast it is read
+    pub fn sample_rate(&self) -> f64 {
+        match self.configuration.operating_mode {
+            OperatingMode::Single => 160.0,
+            _ => self.configuration.data_rate.frequency(),
+        }
+    }
+
+    fn write_register(&self, i2c: &I2c, address: u8, value: u8) -> Result<()> {
+        self.retry_policy
+            .run(|| i2c.smbus_write_byte(address, value))
+            .with_context(|| format!("Unable to write register {:#04x}.", address))
+    }
+
+    /// Whether the sensor at {i2c_address} identifies as an HMC5883L
+    pub fn detect(i2c: &mut I2c, i2c_address: u16) -> Result<bool> {
+        i2c.set_slave_address(i2c_address)?;
+        let mut identity = [0u8; 3];
+        i2c.block_read(IDENTIFICATION, &mut identity)
+            .context("Unable to read identification registers.")?;
+        Ok(identity == IDENTITY)
+    }
+
+    pub fn initialize(&mut self, i2c: &mut I2c) -> Result<()> {
+        Self::detect(i2c, self.i2c_address)?
+            .then_some(())
+            .with_context(|| {
+                format!(
+                    "No HMC5883L found at {:#04x}. Boards sold as HMC5883L often carry a QMC5883L, which `magnetometer::Magnetometer::detect` finds as well.",
+                    self.i2c_address
+                )
+            })?;
+
+        self.write_register(i2c, CONFIGURATION_A, self.configuration.register_a())?;
+        self.write_register(i2c, CONFIGURATION_B, self.configuration.register_b())?;
+        // The pin only pulses, so every falling edge counts
+        if let Some(pin) = &mut self.data_ready_pin {
+            pin.set_interrupt(Trigger::FallingEdge)
+                .context("Unable to set up data ready interrupt.")?;
+        }
+        self.write_register(i2c, MODE, self.configuration.operating_mode as u8)
+    }
+
+    /// Whether new data is in the data registers. Cleared by reading them
+    pub fn data_ready(&self, i2c: &I2c) -> Result<bool> {
+        data_ready(&self.retry_policy, i2c)
+    }
+
+    /// Reads the magnetic field [G], calibrated with {calibration}. Errors if the field is beyond the range of the gain on any axis
+    pub fn read(&mut self, i2c: &I2c) -> Result<Vec3D> {
+        let mut data = [0u8; 6];
+        self.retry_policy
+            .run(|| i2c.block_read(DATA, &mut data))
+            .context("Unable to read data registers.")?;
+        if self.configuration.operating_mode == OperatingMode::Single {
+            self.write_register(i2c, MODE, OperatingMode::Single as u8)?;
+        }
+
+        let field = parse_data(&data);
+        if field.contains(&OVERFLOW) {
+            self.overflows += 1;
+            anyhow::bail!(
+                "Magnetic field beyond {} G. Choose a larger gain.",
+                self.configuration.gain.range()
+            );
+        }
+        let field =
+            Vec3D::new(field[0], field[1], field[2]) / self.configuration.gain.scale_factor();
+        self.magnetic_field = self.calibration.apply(field);
+        Ok(self.magnetic_field)
+    }
+
+    /// Waits up to {timeout} for new data, on the data ready pin if there is one, and reads it. Ok(None) if nothing came in time
+    pub fn wait_for_sample(
+        &mut self,
+        i2c: &I2c,
+        timeout: Option<Duration>,
+    ) -> (Result<Option<Vec3D>>, Instant) {
+        let ready = magnetometer::wait_for_data(
+            self.data_ready_pin.as_mut(),
+            timeout,
+            POLL_INTERVAL,
+            || data_ready(&self.retry_policy, i2c),
+        );
+        let sampling_instant = Instant::now();
+        match ready {
+            Ok(true) => (
+                self.read(i2c)
+                    .map(Some)
+                    .context("Unable to read magnetometer."),
+                sampling_instant,
+            ),
+            Ok(false) => (Ok(None), sampling_instant),
+            Err(error) => (Err(error), sampling_instant),
+        }
+    }
+
+    /// Compass heading [rad] of the x axis, clockwise from true north, from the latest reading and {acceleration} [g] of the GY-521 at rest. See `fusion::tilt_compensated_heading` for {declination}
+    pub fn heading(&self, acceleration: &Vec3D, declination: f64) -> f64 {
+        fusion::tilt_compensated_heading(&self.magnetic_field, acceleration, declination)
+    }
+}
+
+impl Sensor for HMC5883L {
+    type Sample = Vec3D;
+
+    fn initialize(&mut self, i2c: &mut I2c) -> Result<()> {
+        HMC5883L::initialize(self, i2c)
+    }
+
+    fn sample(&mut self, i2c: &I2c) -> Result<Self::Sample> {
+        self.read(i2c)
+    }
+
+    fn sample_rate(&self) -> f64 {
+        HMC5883L::sample_rate(self)
+    }
+
+    fn wait_for_sample(
+        &mut self,
+        i2c: &mut I2c,
+        timeout: Option<Duration>,
+    ) -> (Result<Option<Self::Sample>>, Instant) {
+        HMC5883L::wait_for_sample(self, i2c, timeout)
+    }
+}
+
+impl Default for HMC5883L {
+    fn default() -> Self {
+        Self::new(Default::default(), I2C_ADDRESS)
+    }
+}
+
+// Separate from `HMC5883L::data_ready`, for waiting on it while the data ready pin is borrowed
+fn data_ready(retry_policy: &utilites::RetryPolicy, i2c: &I2c) -> Result<bool> {
+    let status = retry_policy
+        .run(|| i2c.smbus_read_byte(STATUS))
+        .context("Unable to read status register.")?;
+    Ok(status & 1 != 0)
+}
+
+// Readings of the data registers, in the order x, y, z. The sensor sends them as x, z, y
+fn parse_data(data: &[u8; 6]) -> [i16; 3] {
+    let x = i16::from_be_bytes([data[0], data[1]]);
+    let z = i16::from_be_bytes([data[2], data[3]]);
+    let y = i16::from_be_bytes([data[4], data[5]]);
+    [x, y, z]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registers() {
+        let configuration = Configuration::default();
+        // Defaults of the sensor. See table 3 and 5
+        assert_eq!(configuration.register_a(), 0x10);
+        assert_eq!(configuration.register_b(), 0x20);
+
+        let configuration = Configuration {
+            averaging: Averaging::Eight,
+            data_rate: DataRate::Hz75,
+            measurement_mode: MeasurementMode::PositiveBias,
+            gain: Gain::Gauss8_1,
+            operating_mode: OperatingMode::Single,
+        };
+        assert_eq!(configuration.register_a(), 0b0111_1001);
+        assert_eq!(configuration.register_b(), 0b1110_0000);
+    }
+
+    #[test]
+    fn test_parse_data() {
+        let data = [0x01, 0x2C, 0xFE, 0x0C, 0xF0, 0x00];
+        assert_eq!(parse_data(&data), [300, -4096, -500]);
+        assert!(parse_data(&data).contains(&OVERFLOW));
+    }
+}
@@ -0,0 +1,327 @@
+// Texas Instruments INA219 current and power monitor, measuring the voltage across a shunt resistor in the supply line, and the voltage of the supply itself, e.g., of the battery powering the Pi on the boat.
+// Register map: https://www.ti.com/lit/ds/symlink/ina219.pdf
+
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use rppal::i2c::I2c;
+
+use super::Sensor;
+use crate::utilites;
+
+pub const I2C_ADDRESS: u16 = 0x40; // With A0 and A1 connected to ground. See table 1 for the other 15
+
+const CONFIG: u8 = 0x00;
+const SHUNT_VOLTAGE: u8 = 0x01;
+const BUS_VOLTAGE: u8 = 0x02;
+const POWER: u8 = 0x03;
+const CURRENT: u8 = 0x04;
+const CALIBRATION: u8 = 0x05;
+const RESET_DURATION: Duration = Duration::from_millis(1); // Not specified, but takes well under this
+
+// Config register bits
+const RST: u16 = 1 << 15;
+const MODE_CONTINUOUS: u16 = 0b111; // Shunt and bus voltage, continuously
+
+// Bus voltage register bits
+const CNVR: u16 = 1 << 1; // A conversion finished since the power register was last read
+const OVF: u16 = 1 << 0; // Current or power out of range
+
+const SHUNT_VOLTAGE_LSB: f64 = 10e-6; // [V]
+const BUS_VOLTAGE_LSB: f64 = 4e-3; // [V]
+
+/// Full scale of the bus voltage. Config register, bit 13
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BusVoltageRange {
+    V16 = 0,
+    #[default]
+    V32 = 1, // The input takes at most 26 V
+}
+
+/// Full scale of the shunt voltage. Config register, bits 12-11
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShuntRange {
+    Mv40 = 0b00,
+    Mv80 = 0b01,
+    Mv160 = 0b10,
+    #[default]
+    Mv320 = 0b11,
+}
+
+impl ShuntRange {
+    /// [V]
+    pub fn full_scale(&self) -> f64 {
+        0.04 * (1 << *self as u8) as f64
+    }
+}
+
+/// Resolution, or number of 12 bit samples averaged, per conversion. Config register, bits 10-7 for the bus, and 6-3 for the shunt
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Adc {
+    Bits9 = 0b0000,
+    Bits10 = 0b0001,
+    Bits11 = 0b0010,
+    #[default]
+    Bits12 = 0b0011,
+    Samples2 = 0b1001,
+    Samples4 = 0b1010,
+    Samples8 = 0b1011,
+    Samples16 = 0b1100,
+    Samples32 = 0b1101,
+    Samples64 = 0b1110,
+    Samples128 = 0b1111,
+}
+
+impl Adc {
+    /// Time a conversion takes. See table 5
+    pub fn conversion_time(&self) -> Duration {
+        match self {
+            Self::Bits9 => Duration::from_micros(84),
+            Self::Bits10 => Duration::from_micros(148),
+            Self::Bits11 => Duration::from_micros(276),
+            Self::Bits12 => Duration::from_micros(532),
+            // Whole 12 bit conversions, averaged
+            averaged => Duration::from_micros(532) * (1 << (*averaged as u8 & 0b0111)),
+        }
+    }
+}
+
+/// Setup of the monitor, with the shunt it measures across
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Configuration {
+    pub bus_voltage_range: BusVoltageRange,
+    pub shunt_range: ShuntRange,
+    pub bus_adc: Adc,
+    pub shunt_adc: Adc,
+    pub shunt_resistance: f64, // [Ohm] 0.1 on most breakout boards
+    pub max_current: f64, // [A] Expected at most, setting the resolution of the current to 1/32768 of it. At most {shunt_range} over {shunt_resistance}
+}
+
+impl Configuration {
+    /// Config register
+    pub fn config(&self) -> u16 {
+        (self.bus_voltage_range as u16) << 13
+            | (self.shunt_range as u16) << 11
+            | (self.bus_adc as u16) << 7
+            | (self.shunt_adc as u16) << 3
+            | MODE_CONTINUOUS
+    }
+
+    /// [A] Per count of the current register
+    pub fn current_lsb(&self) -> f64 {
+        self.max_current / 32768.0
+    }
+
+    /// Calibration register, scaling the shunt voltage to the current. See equation 1
+    pub fn calibration(&self) -> u16 {
+        (0.04096 / (self.current_lsb() * self.shunt_resistance)) as u16 & !1 // Bit 0 is read-only
+    }
+
+    /// Time until both voltages are converted anew
+    pub fn conversion_time(&self) -> Duration {
+        self.bus_adc.conversion_time() + self.shunt_adc.conversion_time()
+    }
+}
+
+impl Default for Configuration {
+    /// The power-on default, 0x399F, with the 0.1 Ohm shunt of most breakout boards, measuring up to 3.2 A
+    fn default() -> Self {
+        Self {
+            bus_voltage_range: BusVoltageRange::default(),
+            shunt_range: ShuntRange::default(),
+            bus_adc: Adc::default(),
+            shunt_adc: Adc::default(),
+            shunt_resistance: 0.1,
+            max_current: 3.2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub struct PowerSample {
+    pub bus_voltage: f64, // [V] Of the supply, against ground, on the load side of the shunt
+    pub shunt_voltage: f64, // [V] Across the shunt
+    pub current: f64,     // [A] Positive from IN+ to IN-
+    pub power: f64,       // [W] Drawn by the load
+}
+
+/// The monitor. It shares the bus with the GY-521, but needs its own `I2c`, since that is set to the GY-521's address once
+#[non_exhaustive]
+pub struct INA219 {
+    pub configuration: Configuration,
+    pub i2c_address: u16,
+    pub sample: Option<PowerSample>,         // Latest
+    pub retry_policy: utilites::RetryPolicy, // Applied to all register reads and writes
+    last_sample: Option<Instant>,
+}
+
+impl INA219 {
+    pub fn new(configuration: Configuration, i2c_address: u16) -> Self {
+        Self {
+            configuration,
+            i2c_address,
+            sample: None,
+            retry_policy: Default::default(),
+            last_sample: None,
+        }
+    }
+
+    /// [Hz] Conversions of both voltages per second
+    pub fn sample_rate(&self) -> f64 {
+        1.0 / self.configuration.conversion_time().as_secs_f64()
+    }
+
+    // The registers are 16 bits wide, most significant byte first
+    fn write_register(&self, i2c: &I2c, address: u8, value: u16) -> Result<()> {
+        self.retry_policy
+            .run(|| i2c.block_write(address, &value.to_be_bytes()))
+            .with_context(|| format!("Unable to write register {:#04x}.", address))
+    }
+
+    fn read_register(&self, i2c: &I2c, address: u8) -> Result<u16> {
+        let mut value = [0u8; 2];
+        self.retry_policy
+            .run(|| i2c.block_read(address, &mut value))
+            .with_context(|| format!("Unable to read register {:#04x}.", address))?;
+        Ok(u16::from_be_bytes(value))
+    }
+
+    /// Resets the monitor, and sets it up to convert continuously. It has no ID register, so the configuration is read back instead, to tell whether it took
+    pub fn initialize(&mut self, i2c: &mut I2c) -> Result<()> {
+        anyhow::ensure!(
+            self.configuration.max_current
+                <= self.configuration.shunt_range.full_scale()
+                    / self.configuration.shunt_resistance,
+            "The maximum current is beyond the shunt range."
+        );
+        i2c.set_slave_address(self.i2c_address)?;
+
+        self.write_register(i2c, CONFIG, RST)?;
+        std::thread::sleep(RESET_DURATION);
+        self.write_register(i2c, CALIBRATION, self.configuration.calibration())?;
+        self.write_register(i2c, CONFIG, self.configuration.config())?;
+        let config = self.read_register(i2c, CONFIG)?;
+        anyhow::ensure!(
+            config == self.configuration.config(),
+            "No INA219 found. Config register reads {config:#06x}."
+        );
+        self.last_sample = None;
+        Ok(())
+    }
+
+    /// Reads the latest conversion of both voltages, and the current and power computed from them
+    pub fn read(&mut self, i2c: &I2c) -> Result<PowerSample> {
+        let bus = self.read_register(i2c, BUS_VOLTAGE)?;
+        anyhow::ensure!(
+            bus & OVF == 0,
+            "Current or power out of range. Check the shunt range and the maximum current."
+        );
+        let shunt = self.read_register(i2c, SHUNT_VOLTAGE)? as i16;
+        let current = self.read_register(i2c, CURRENT)? as i16;
+        // Reading the power register clears {CNVR}
+        let power = self.read_register(i2c, POWER)?;
+        let current_lsb = self.configuration.current_lsb();
+
+        self.last_sample = Some(Instant::now());
+        let sample = PowerSample {
+            bus_voltage: (bus >> 3) as f64 * BUS_VOLTAGE_LSB,
+            shunt_voltage: shunt as f64 * SHUNT_VOLTAGE_LSB,
+            current: current as f64 * current_lsb,
+            power: power as f64 * 20.0 * current_lsb,
+        };
+        self.sample = Some(sample);
+        Ok(sample)
+    }
+
+    /// Waits up to {timeout} for a new conversion, polling the conversion ready bit once a conversion period has passed since the latest read, and reads it. Ok(None) if nothing came in time
+    pub fn wait_for_sample(
+        &mut self,
+        i2c: &I2c,
+        timeout: Option<Duration>,
+    ) -> (Result<Option<PowerSample>>, Instant) {
+        let start = Instant::now();
+        if let Some(last_sample) = self.last_sample {
+            let wait = (last_sample + self.configuration.conversion_time())
+                .saturating_duration_since(start);
+            if let Some(timeout) = timeout.filter(|timeout| *timeout < wait) {
+                std::thread::sleep(timeout);
+                return (Ok(None), Instant::now());
+            }
+            std::thread::sleep(wait);
+        }
+        loop {
+            match self.read_register(i2c, BUS_VOLTAGE) {
+                Ok(bus) if bus & CNVR != 0 => break,
+                Ok(_) => {}
+                Err(error) => return (Err(error), Instant::now()),
+            }
+            if timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+                return (Ok(None), Instant::now());
+            }
+            std::thread::sleep(self.configuration.conversion_time() / 4);
+        }
+        let sampling_instant = Instant::now();
+        let sample = self.read(i2c).context("Unable to read power monitor.");
+        (sample.map(Some), sampling_instant)
+    }
+}
+
+impl Sensor for INA219 {
+    type Sample = PowerSample;
+
+    fn initialize(&mut self, i2c: &mut I2c) -> Result<()> {
+        INA219::initialize(self, i2c)
+    }
+
+    fn sample(&mut self, i2c: &I2c) -> Result<Self::Sample> {
+        self.read(i2c)
+    }
+
+    fn sample_rate(&self) -> f64 {
+        INA219::sample_rate(self)
+    }
+
+    fn wait_for_sample(
+        &mut self,
+        i2c: &mut I2c,
+        timeout: Option<Duration>,
+    ) -> (Result<Option<Self::Sample>>, Instant) {
+        INA219::wait_for_sample(self, i2c, timeout)
+    }
+}
+
+impl Default for INA219 {
+    fn default() -> Self {
+        Self::new(Default::default(), I2C_ADDRESS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registers() {
+        let configuration = Configuration::default();
+        assert_eq!(configuration.config(), 0x399F);
+        // 0.1 Ohm and 3.2 A: About 0.1 mA per count. See 8.5.1
+        assert!((configuration.current_lsb() - 97.65625e-6).abs() < 1e-12);
+        assert_eq!(configuration.calibration(), 4194);
+        assert_eq!(configuration.conversion_time(), Duration::from_micros(1064));
+
+        let configuration = Configuration {
+            bus_voltage_range: BusVoltageRange::V16,
+            shunt_range: ShuntRange::Mv40,
+            bus_adc: Adc::Samples128,
+            shunt_adc: Adc::Bits9,
+            ..Default::default()
+        };
+        assert_eq!(configuration.config(), 0x0787);
+        assert_eq!(
+            Adc::Samples128.conversion_time(),
+            Duration::from_micros(68_096)
+        );
+        assert!((ShuntRange::Mv40.full_scale() - 0.04).abs() < 1e-12);
+        assert!((ShuntRange::Mv320.full_scale() - 0.32).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,213 @@
+// Magnetometers, whichever chip the board carries.
+// Boards sold as HMC5883L, e.g., GY-271, mostly carry the QMC5883L nowadays, which looks the same, but has another address and register map. `Magnetometer::detect` finds out which one is there.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use rppal::{gpio::InputPin, i2c::I2c};
+
+use super::{hmc5883l, hmc5883l::HMC5883L, qmc5883l, qmc5883l::QMC5883L, Sensor};
+use crate::{
+    fusion,
+    math::{Mat3, Vec3D},
+};
+
+/// Corrections of the magnetic field for what is mounted along with the sensor, applied as `soft_iron_matrix * (field - hard_iron_offset)`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IronCalibration {
+    pub hard_iron_offset: Vec3D, // [G] Field of magnetized parts, e.g., a speaker or steel screws
+    pub soft_iron_matrix: Mat3,  // Distortion of the field by iron near the sensor
+}
+
+impl IronCalibration {
+    pub fn apply(&self, field: Vec3D) -> Vec3D {
+        self.soft_iron_matrix * (field - self.hard_iron_offset)
+    }
+}
+
+impl Default for IronCalibration {
+    fn default() -> Self {
+        Self {
+            hard_iron_offset: Default::default(),
+            soft_iron_matrix: Mat3::IDENTITY,
+        }
+    }
+}
+
+/// Either of the supported magnetometers, with their default configuration unless set up otherwise before `initialize`
+pub enum Magnetometer {
+    HMC5883L(HMC5883L),
+    QMC5883L(QMC5883L),
+}
+
+impl Magnetometer {
+    /// Looks for either chip at its default address, the HMC5883L first
+    pub fn detect(i2c: &mut I2c) -> Result<Self> {
+        // Nobody answering an address is an error as well, which only means to look further
+        if HMC5883L::detect(i2c, hmc5883l::I2C_ADDRESS).unwrap_or(false) {
+            return Ok(Self::HMC5883L(Default::default()));
+        }
+        if QMC5883L::detect(i2c, qmc5883l::I2C_ADDRESS).unwrap_or(false) {
+            return Ok(Self::QMC5883L(Default::default()));
+        }
+        anyhow::bail!(
+            "No magnetometer found at {:#04x} (HMC5883L) or {:#04x} (QMC5883L).",
+            hmc5883l::I2C_ADDRESS,
+            qmc5883l::I2C_ADDRESS
+        )
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::HMC5883L(_) => "HMC5883L",
+            Self::QMC5883L(_) => "QMC5883L",
+        }
+    }
+
+    pub fn initialize(&mut self, i2c: &mut I2c) -> Result<()> {
+        match self {
+            Self::HMC5883L(sensor) => sensor.initialize(i2c),
+            Self::QMC5883L(sensor) => sensor.initialize(i2c),
+        }
+    }
+
+    /// Set before `initialize`. See the {data_ready_pin} of either chip
+    pub fn set_data_ready_pin(&mut self, pin: Option<InputPin>) {
+        match self {
+            Self::HMC5883L(sensor) => sensor.data_ready_pin = pin,
+            Self::QMC5883L(sensor) => sensor.data_ready_pin = pin,
+        }
+    }
+
+    /// [Hz]
+    pub fn sample_rate(&self) -> f64 {
+        match self {
+            Self::HMC5883L(sensor) => sensor.sample_rate(),
+            Self::QMC5883L(sensor) => sensor.sample_rate(),
+        }
+    }
+
+    pub fn calibration(&mut self) -> &mut IronCalibration {
+        match self {
+            Self::HMC5883L(sensor) => &mut sensor.calibration,
+            Self::QMC5883L(sensor) => &mut sensor.calibration,
+        }
+    }
+
+    /// [G] Latest, calibrated
+    pub fn magnetic_field(&self) -> Vec3D {
+        match self {
+            Self::HMC5883L(sensor) => sensor.magnetic_field,
+            Self::QMC5883L(sensor) => sensor.magnetic_field,
+        }
+    }
+
+    /// Reads the magnetic field [G]
+    pub fn read(&mut self, i2c: &I2c) -> Result<Vec3D> {
+        match self {
+            Self::HMC5883L(sensor) => sensor.read(i2c),
+            Self::QMC5883L(sensor) => sensor.read(i2c),
+        }
+    }
+
+    /// Waits up to {timeout} for new data, and reads it. Ok(None) if nothing came in time
+    pub fn wait_for_sample(
+        &mut self,
+        i2c: &I2c,
+        timeout: Option<Duration>,
+    ) -> (Result<Option<Vec3D>>, Instant) {
+        match self {
+            Self::HMC5883L(sensor) => sensor.wait_for_sample(i2c, timeout),
+            Self::QMC5883L(sensor) => sensor.wait_for_sample(i2c, timeout),
+        }
+    }
+
+    /// Compass heading [rad] of the x axis, clockwise from true north, from the latest reading and {acceleration} [g] of the GY-521 at rest. See `fusion::tilt_compensated_heading` for {declination}
+    pub fn heading(&self, acceleration: &Vec3D, declination: f64) -> f64 {
+        fusion::tilt_compensated_heading(&self.magnetic_field(), acceleration, declination)
+    }
+}
+
+impl Sensor for Magnetometer {
+    type Sample = Vec3D;
+
+    fn initialize(&mut self, i2c: &mut I2c) -> Result<()> {
+        Magnetometer::initialize(self, i2c)
+    }
+
+    fn sample(&mut self, i2c: &I2c) -> Result<Self::Sample> {
+        self.read(i2c)
+    }
+
+    fn sample_rate(&self) -> f64 {
+        Magnetometer::sample_rate(self)
+    }
+
+    fn wait_for_sample(
+        &mut self,
+        i2c: &mut I2c,
+        timeout: Option<Duration>,
+    ) -> (Result<Option<Self::Sample>>, Instant) {
+        Magnetometer::wait_for_sample(self, i2c, timeout)
+    }
+}
+
+// Waits up to {timeout} for an interrupt on {pin}, or, without one, for {data_ready} to be true, checking it every {poll_interval}
+pub(super) fn wait_for_data(
+    pin: Option<&mut InputPin>,
+    timeout: Option<Duration>,
+    poll_interval: Duration,
+    mut data_ready: impl FnMut() -> Result<bool>,
+) -> Result<bool> {
+    match pin {
+        Some(pin) => pin
+            .poll_interrupt(true, timeout)
+            .map(|interrupt| interrupt.is_some())
+            .context("Unable to poll interrupt."),
+        None => {
+            let start = Instant::now();
+            loop {
+                match data_ready() {
+                    Ok(false) if timeout.is_none_or(|timeout| start.elapsed() < timeout) => {
+                        std::thread::sleep(poll_interval)
+                    }
+                    ready => return ready,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_iron_calibration() {
+        let calibration = IronCalibration {
+            hard_iron_offset: Vec3D::new(0.1, -0.2, 0),
+            soft_iron_matrix: Mat3::from_diagonal(&Vec3D::new(2, 1, 0.5)),
+        };
+        let field = calibration.apply(Vec3D::new(0.35, 0.3, 0.4));
+        assert!(field.near(&Vec3D::new(0.5, 0.5, 0.2)));
+        assert_eq!(
+            IronCalibration::default().apply(Vec3D::new(1, 2, 3)),
+            Vec3D::new(1, 2, 3)
+        );
+
+        let mut polls = 0;
+        let ready = wait_for_data(None, None, Duration::ZERO, || {
+            polls += 1;
+            Ok(polls == 3)
+        });
+        assert!(ready.unwrap());
+        assert_eq!(polls, 3);
+        let ready = wait_for_data(
+            None,
+            Some(Duration::from_millis(5)),
+            Duration::from_millis(1),
+            || Ok(false),
+        );
+        assert!(!ready.unwrap());
+    }
+}
@@ -0,0 +1,228 @@
+// Timing of pulses on GPIO pins, from the instants of their edges as interrupts report them, for sensors that answer with the width of a pulse, e.g., the echo of an ultrasonic distance sensor, or with the rate of pulses, e.g., a cup anemometer.
+// Kept apart from the pins, so that the timing is tested without hardware.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use crossbeam_channel::Receiver;
+use rppal::gpio::{InputPin, Level, Trigger};
+
+/// Measures the width of the first high pulse starting after `arm`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PulseTimer {
+    armed: Option<Instant>,
+    start: Option<Instant>, // Of the pulse being measured
+}
+
+impl PulseTimer {
+    /// Waits for a pulse starting at or after {instant}, forgetting the one being measured
+    pub fn arm(&mut self, instant: Instant) {
+        self.armed = Some(instant);
+        self.start = None;
+    }
+
+    /// When the pulse being measured started, if it did
+    pub fn start(&self) -> Option<Instant> {
+        self.start
+    }
+
+    /// Takes in an edge to {high} at {instant}. Returns the width of the pulse if this ends it, which disarms the timer
+    pub fn edge(&mut self, high: bool, instant: Instant) -> Option<Duration> {
+        let armed = self.armed?;
+        if instant < armed {
+            return None;
+        }
+        match (high, self.start) {
+            // Another rising edge means that the falling one in between was missed, so the pulse is timed from the latest
+            (true, _) => {
+                self.start = Some(instant);
+                None
+            }
+            (false, Some(start)) => {
+                self.armed = None;
+                self.start = None;
+                Some(instant.duration_since(start))
+            }
+            // The end of a pulse that started before arming
+            (false, None) => None,
+        }
+    }
+}
+
+/// Counts pulses, and measures their rate from the instants of the latest ones
+#[derive(Debug, Clone)]
+pub struct PulseCounter {
+    pub debounce: Duration, // Pulses following the previous one sooner than this are taken for bounces, e.g., of a reed switch
+    pub window: Duration,   // Over which the rate is averaged
+    pub count: u64,         // Since the start
+    pub bounces: u64,
+    pulses: VecDeque<Instant>, // Within {window} of the latest one
+}
+
+impl PulseCounter {
+    pub fn new(debounce: Duration, window: Duration) -> Self {
+        Self {
+            debounce,
+            window,
+            count: 0,
+            bounces: 0,
+            pulses: VecDeque::new(),
+        }
+    }
+
+    /// Takes in a pulse at {instant}
+    pub fn pulse(&mut self, instant: Instant) {
+        if let Some(last) = self.pulses.back() {
+            if instant.saturating_duration_since(*last) < self.debounce {
+                self.bounces += 1;
+                return;
+            }
+        }
+        self.count += 1;
+        self.pulses.push_back(instant);
+        while let Some(first) = self.pulses.front() {
+            if instant.saturating_duration_since(*first) > self.window {
+                self.pulses.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// [Hz] Pulses per second, from the periods between the pulses within the window. Without a pulse since, it is at most one pulse over the time since the latest one until {now}, so that it falls off towards 0 when the pulses stop
+    pub fn frequency(&self, now: Instant) -> f64 {
+        let (Some(first), Some(last)) = (self.pulses.front(), self.pulses.back()) else {
+            return 0.0;
+        };
+        let since_last = now.saturating_duration_since(*last).as_secs_f64();
+        if since_last > self.window.as_secs_f64() {
+            return 0.0;
+        }
+        let bound = 1.0 / since_last;
+        let span = last.duration_since(*first).as_secs_f64();
+        if self.pulses.len() < 2 || span <= 0.0 {
+            return 0.0;
+        }
+        ((self.pulses.len() - 1) as f64 / span).min(bound)
+    }
+}
+
+/// A `PulseCounter` counting the edges of {trigger} on a pin, on interrupts
+pub struct PulseInput {
+    pin: InputPin,
+    counter: Arc<Mutex<PulseCounter>>,
+}
+
+impl PulseInput {
+    /// Counts on {trigger} edges of {pin}, e.g., `Trigger::FallingEdge` for a switch to ground with a pull-up
+    pub fn new(mut pin: InputPin, trigger: Trigger, counter: PulseCounter) -> Result<Self> {
+        let counter = Arc::new(Mutex::new(counter));
+        let callback_counter = counter.clone();
+        pin.set_async_interrupt(trigger, move |_| {
+            let instant = Instant::now();
+            if let Ok(mut counter) = callback_counter.lock() {
+                counter.pulse(instant);
+            }
+        })
+        .context("Unable to set up pulse interrupt.")?;
+        Ok(Self { pin, counter })
+    }
+
+    /// [Hz] As of now
+    pub fn frequency(&self) -> f64 {
+        self.counter.lock().unwrap().frequency(Instant::now())
+    }
+
+    /// Pulses since the start, and bounces filtered
+    pub fn count(&self) -> (u64, u64) {
+        let counter = self.counter.lock().unwrap();
+        (counter.count, counter.bounces)
+    }
+}
+
+impl Drop for PulseInput {
+    fn drop(&mut self) {
+        // The callback holds on to the counter otherwise
+        let _ = self.pin.clear_async_interrupt();
+    }
+}
+
+/// Reports the edges on {pin} with their instants, taken in the interrupt callback, over the returned channel. `InputPin::clear_async_interrupt` stops it
+pub fn watch_edges(pin: &mut InputPin) -> Result<Receiver<(bool, Instant)>> {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    pin.set_async_interrupt(Trigger::Both, move |level| {
+        let _ = sender.send((level == Level::High, Instant::now()));
+    })
+    .context("Unable to set up interrupt.")?;
+    Ok(receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pulse_timer() {
+        let start = Instant::now();
+        let at = |microseconds: u64| start + Duration::from_micros(microseconds);
+        let mut timer = PulseTimer::default();
+
+        // Nothing counts before arming
+        assert_eq!(timer.edge(true, at(0)), None);
+        assert_eq!(timer.edge(false, at(100)), None);
+
+        timer.arm(at(200));
+        // The end of a pulse that started earlier, and edges from before arming, that came in late
+        assert_eq!(timer.edge(false, at(150)), None);
+        assert_eq!(timer.edge(false, at(250)), None);
+        assert_eq!(timer.start(), None);
+        assert_eq!(timer.edge(true, at(700)), None);
+        assert_eq!(timer.start(), Some(at(700)));
+        assert_eq!(
+            timer.edge(false, at(6500)),
+            Some(Duration::from_micros(5800))
+        );
+        // Disarmed by the end of the pulse
+        assert_eq!(timer.edge(true, at(7000)), None);
+        assert_eq!(timer.edge(false, at(8000)), None);
+
+        // A missed falling edge: Timed from the latest rising edge
+        timer.arm(at(10_000));
+        timer.edge(true, at(10_500));
+        timer.edge(true, at(11_000));
+        assert_eq!(
+            timer.edge(false, at(12_000)),
+            Some(Duration::from_micros(1000))
+        );
+    }
+
+    #[test]
+    fn test_pulse_counter() {
+        let start = Instant::now();
+        let at = |milliseconds: u64| start + Duration::from_millis(milliseconds);
+        let near = |frequency: f64, expected: f64| (frequency - expected).abs() < 1e-9;
+        let mut counter = PulseCounter::new(Duration::from_millis(5), Duration::from_secs(2));
+        assert_eq!(counter.frequency(at(0)), 0.0);
+
+        // 4 Hz, with a bounce
+        for milliseconds in [0, 250, 252, 500, 750, 1000] {
+            counter.pulse(at(milliseconds));
+        }
+        assert_eq!((counter.count, counter.bounces), (5, 1));
+        assert!(near(counter.frequency(at(1100)), 4.0));
+        // Stopped: No more than one pulse since the latest
+        assert!(near(counter.frequency(at(1500)), 2.0));
+        assert_eq!(counter.frequency(at(3500)), 0.0);
+
+        // The pulses before the window don't count towards the rate anymore
+        for milliseconds in [3000, 3500, 4000, 4500, 5000] {
+            counter.pulse(at(milliseconds));
+        }
+        assert_eq!(counter.pulses.len(), 5);
+        assert!(near(counter.frequency(at(5000)), 2.0));
+    }
+}
@@ -0,0 +1,306 @@
+// QST QMC5883L 3-axis magnetometer, which most boards sold as HMC5883L carry nowadays, e.g., GY-271.
+// Register map: https://www.filipeflop.com/img/files/download/Datasheet-QMC5883L-1.0%20.pdf
+
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use rppal::{
+    gpio::{InputPin, Trigger},
+    i2c::I2c,
+};
+
+use super::{
+    magnetometer::{self, IronCalibration},
+    Sensor,
+};
+use crate::{fusion, math::Vec3D, utilites};
+
+pub const I2C_ADDRESS: u16 = 0x0D; // Fixed
+
+const DATA: u8 = 0x00; // Six bytes, X, Y, Z, each little-endian, followed by the status register
+const STATUS: u8 = 0x06;
+const CONTROL_1: u8 = 0x09;
+const CONTROL_2: u8 = 0x0A;
+const SET_RESET_PERIOD: u8 = 0x0B;
+const CHIP_ID: u8 = 0x0D;
+const IDENTITY: u8 = 0xFF;
+const POLL_INTERVAL: Duration = Duration::from_millis(1); // Between reads of the status register, without a data ready pin
+const RESET_DURATION: Duration = Duration::from_millis(10); // Not given in the datasheet. Generous
+
+// Status register bits
+const DRDY: u8 = 1 << 0; // New data is ready. Cleared by reading the data registers
+const OVL: u8 = 1 << 1; // Field beyond the range on some axis
+
+/// Measurements per output, trading noise for current. Control register 1, bits 7-6
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverSampling {
+    #[default]
+    X512 = 0b00,
+    X256 = 0b01,
+    X128 = 0b10,
+    X64 = 0b11,
+}
+
+/// Full-scale range. Control register 1, bits 5-4
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Range {
+    #[default]
+    Gauss2 = 0b00,
+    Gauss8 = 0b01,
+}
+
+impl Range {
+    /// [LSB/G]
+    pub fn scale_factor(&self) -> f64 {
+        match self {
+            Self::Gauss2 => 12000.0,
+            Self::Gauss8 => 3000.0,
+        }
+    }
+
+    /// [G] Largest field that can be measured
+    pub fn range(&self) -> f64 {
+        match self {
+            Self::Gauss2 => 2.0,
+            Self::Gauss8 => 8.0,
+        }
+    }
+}
+
+/// Output rate in continuous mode. Control register 1, bits 3-2
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DataRate {
+    #[default]
+    Hz10 = 0b00,
+    Hz50 = 0b01,
+    Hz100 = 0b10,
+    Hz200 = 0b11,
+}
+
+impl DataRate {
+    /// [Hz]
+    pub fn frequency(&self) -> f64 {
+        match self {
+            Self::Hz10 => 10.0,
+            Self::Hz50 => 50.0,
+            Self::Hz100 => 100.0,
+            Self::Hz200 => 200.0,
+        }
+    }
+}
+
+/// Control register 1, bits 1-0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OperatingMode {
+    Standby = 0b00,
+    #[default]
+    Continuous = 0b01,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Configuration {
+    pub over_sampling: OverSampling,
+    pub range: Range,
+    pub data_rate: DataRate,
+    pub operating_mode: OperatingMode,
+}
+
+impl Configuration {
+    /// Contents of control register 1
+    pub fn control_1(&self) -> u8 {
+        ((self.over_sampling as u8) << 6)
+            | ((self.range as u8) << 4)
+            | ((self.data_rate as u8) << 2)
+            | self.operating_mode as u8
+    }
+}
+
+/// The magnetometer, measuring the magnetic field in [G], like `hmc5883l::HMC5883L`, whose notes on sharing the bus with the GY-521 and mounting apply here as well
+#[non_exhaustive]
+pub struct QMC5883L {
+    pub configuration: Configuration,
+    pub i2c_address: u16,
+    pub magnetic_field: Vec3D, // [G] Latest, calibrated
+    pub calibration: IronCalibration,
+    pub data_ready_pin: Option<InputPin>, // DRDY, high from when new data is ready until it is read. None polls the status register instead
+    pub retry_policy: utilites::RetryPolicy, // Applied to all register reads and writes
+    pub overflows: usize, // Readings rejected so far, because the field was beyond the range
+}
+
+impl QMC5883L {
+    pub fn new(configuration: Configuration, i2c_address: u16) -> Self {
+        Self {
+            configuration,
+            i2c_address,
+            magnetic_field: Default::default(),
+            calibration: Default::default(),
+            data_ready_pin: None,
+            retry_policy: Default::default(),
+            overflows: 0,
+        }
+    }
+
+    /// [Hz] How often new data is ready
+    pub fn sample_rate(&self) -> f64 {
+        self.configuration.data_rate.frequency()
+    }
+
+    fn write_register(&self, i2c: &I2c, address: u8, value: u8) -> Result<()> {
+        self.retry_policy
+            .run(|| i2c.smbus_write_byte(address, value))
+            .with_context(|| format!("Unable to write register {:#04x}.", address))
+    }
+
+    /// Whether the sensor at {i2c_address} identifies as a QMC5883L. Its chip ID is 0xFF, which is what some other chips read as well, so this is a hint more than proof
+    pub fn detect(i2c: &mut I2c, i2c_address: u16) -> Result<bool> {
+        i2c.set_slave_address(i2c_address)?;
+        let chip_id = i2c
+            .smbus_read_byte(CHIP_ID)
+            .context("Unable to read chip ID register.")?;
+        Ok(chip_id == IDENTITY)
+    }
+
+    pub fn initialize(&mut self, i2c: &mut I2c) -> Result<()> {
+        Self::detect(i2c, self.i2c_address)?
+            .then_some(())
+            .with_context(|| format!("No QMC5883L found at {:#04x}.", self.i2c_address))?;
+
+        self.write_register(i2c, CONTROL_2, 1 << 7)
+            .context("Unable to reset sensor.")?;
+        std::thread::sleep(RESET_DURATION);
+        // Recommended by the datasheet, without further explanation
+        self.write_register(i2c, SET_RESET_PERIOD, 0x01)?;
+        // Bit 0 disables the data ready pin
+        let disable_interrupt = self.data_ready_pin.is_none() as u8;
+        self.write_register(i2c, CONTROL_2, disable_interrupt)?;
+        // The pin stays high until the data is read, so only rising edges count
+        if let Some(pin) = &mut self.data_ready_pin {
+            pin.set_interrupt(Trigger::RisingEdge)
+                .context("Unable to set up data ready interrupt.")?;
+        }
+        self.write_register(i2c, CONTROL_1, self.configuration.control_1())
+    }
+
+    /// Whether new data is in the data registers. Cleared by reading them
+    pub fn data_ready(&self, i2c: &I2c) -> Result<bool> {
+        data_ready(&self.retry_policy, i2c)
+    }
+
+    /// Reads the magnetic field [G], calibrated with {calibration}. Errors if the field is beyond the range on any axis
+    pub fn read(&mut self, i2c: &I2c) -> Result<Vec3D> {
+        // The status register follows the data registers, so it is read along, and tells whether this data overflowed
+        let mut data = [0u8; 7];
+        self.retry_policy
+            .run(|| i2c.block_read(DATA, &mut data))
+            .context("Unable to read data registers.")?;
+
+        if data[6] & OVL != 0 {
+            self.overflows += 1;
+            anyhow::bail!(
+                "Magnetic field beyond {} G. Choose a larger range.",
+                self.configuration.range.range()
+            );
+        }
+        let field = parse_data(&data);
+        let field =
+            Vec3D::new(field[0], field[1], field[2]) / self.configuration.range.scale_factor();
+        self.magnetic_field = self.calibration.apply(field);
+        Ok(self.magnetic_field)
+    }
+
+    /// Waits up to {timeout} for new data, on the data ready pin if there is one, and reads it. Ok(None) if nothing came in time
+    pub fn wait_for_sample(
+        &mut self,
+        i2c: &I2c,
+        timeout: Option<Duration>,
+    ) -> (Result<Option<Vec3D>>, Instant) {
+        let ready = magnetometer::wait_for_data(
+            self.data_ready_pin.as_mut(),
+            timeout,
+            POLL_INTERVAL,
+            || data_ready(&self.retry_policy, i2c),
+        );
+        let sampling_instant = Instant::now();
+        match ready {
+            Ok(true) => (
+                self.read(i2c)
+                    .map(Some)
+                    .context("Unable to read magnetometer."),
+                sampling_instant,
+            ),
+            Ok(false) => (Ok(None), sampling_instant),
+            Err(error) => (Err(error), sampling_instant),
+        }
+    }
+
+    /// Compass heading [rad] of the x axis, clockwise from true north, from the latest reading and {acceleration} [g] of the GY-521 at rest. See `fusion::tilt_compensated_heading` for {declination}
+    pub fn heading(&self, acceleration: &Vec3D, declination: f64) -> f64 {
+        fusion::tilt_compensated_heading(&self.magnetic_field, acceleration, declination)
+    }
+}
+
+impl Sensor for QMC5883L {
+    type Sample = Vec3D;
+
+    fn initialize(&mut self, i2c: &mut I2c) -> Result<()> {
+        QMC5883L::initialize(self, i2c)
+    }
+
+    fn sample(&mut self, i2c: &I2c) -> Result<Self::Sample> {
+        self.read(i2c)
+    }
+
+    fn sample_rate(&self) -> f64 {
+        QMC5883L::sample_rate(self)
+    }
+
+    fn wait_for_sample(
+        &mut self,
+        i2c: &mut I2c,
+        timeout: Option<Duration>,
+    ) -> (Result<Option<Self::Sample>>, Instant) {
+        QMC5883L::wait_for_sample(self, i2c, timeout)
+    }
+}
+
+impl Default for QMC5883L {
+    fn default() -> Self {
+        Self::new(Default::default(), I2C_ADDRESS)
+    }
+}
+
+// Separate from `QMC5883L::data_ready`, for waiting on it while the data ready pin is borrowed
+fn data_ready(retry_policy: &utilites::RetryPolicy, i2c: &I2c) -> Result<bool> {
+    let status = retry_policy
+        .run(|| i2c.smbus_read_byte(STATUS))
+        .context("Unable to read status register.")?;
+    Ok(status & DRDY != 0)
+}
+
+// Readings of the data registers, in the order x, y, z
+fn parse_data(data: &[u8]) -> [i16; 3] {
+    [
+        i16::from_le_bytes([data[0], data[1]]),
+        i16::from_le_bytes([data[2], data[3]]),
+        i16::from_le_bytes([data[4], data[5]]),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registers() {
+        // 200 Hz, 8 G, oversampling 512, continuous, as in section 9.2.1 of the datasheet
+        let configuration = Configuration {
+            range: Range::Gauss8,
+            data_rate: DataRate::Hz200,
+            ..Default::default()
+        };
+        assert_eq!(configuration.control_1(), 0x1D);
+
+        let data = [0x2C, 0x01, 0x0C, 0xFE, 0x00, 0x80, DRDY];
+        assert_eq!(parse_data(&data), [300, -500, i16::MIN]);
+    }
+}
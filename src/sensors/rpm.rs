@@ -0,0 +1,163 @@
+// Hall-effect sensors counting magnets passing on a shaft or flywheel, for the engine or propeller shaft speed.
+// Logged next to the acceleration, the shaft speed tells which peaks of the vibration spectrum come from the engine, see `RpmSample::order_amplitudes`.
+
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use rppal::gpio::{InputPin, Trigger};
+
+use super::pulse::{PulseCounter, PulseInput};
+use crate::{
+    gy521::SampleSource,
+    math::{spectrum::Spectrum, Vec3D},
+};
+
+/// How `RpmSensor` counts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RpmConfig {
+    pub pulses_per_revolution: f64, // E.g., the number of magnets on the shaft
+    pub trigger: Trigger, // Edge of a pulse to count. Most Hall-effect switches pull their output low while a magnet passes, which needs a pull-up
+    pub debounce: Duration, // Pulses following the previous one sooner than this are dropped. Keep it below the period at the highest speed
+    pub window: Duration,   // Over which the speed is averaged
+    pub sample_rate: f64,   // [Hz] Of the samples as a `SampleSource`
+}
+
+impl RpmConfig {
+    /// One magnet, counted on falling edges, averaged over 1 s, 10 samples per second
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Default for RpmConfig {
+    fn default() -> Self {
+        Self {
+            pulses_per_revolution: 1.0,
+            trigger: Trigger::FallingEdge,
+            debounce: Duration::from_micros(500), // Allows for up to 120000 pulses per minute
+            window: Duration::from_secs(1),
+            sample_rate: 10.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub struct RpmSample {
+    pub rpm: f64,         // [1/min]
+    pub revolutions: f64, // Since the start, e.g., for engine hours
+}
+
+impl RpmSample {
+    /// [Hz] Revolutions per second
+    pub fn frequency(&self) -> f64 {
+        self.rpm / 60.0
+    }
+
+    /// Amplitudes in {spectrum} at the first {orders} multiples of the shaft frequency, each over the bins within one bin of it, since the window spreads a sine over about two bins.
+    /// The first order shows imbalance, and the order of the number of cylinders or propeller blades shows firing or blade pass
+    pub fn order_amplitudes(&self, spectrum: &Spectrum, orders: usize) -> Vec<Vec3D> {
+        (1..=orders)
+            .map(|order| {
+                let frequency = order as f64 * self.frequency();
+                spectrum.band_amplitude(
+                    frequency - spectrum.resolution,
+                    frequency + spectrum.resolution,
+                )
+            })
+            .collect()
+    }
+}
+
+/// A Hall-effect sensor on {pin}, counted on interrupts
+pub struct RpmSensor {
+    pub config: RpmConfig,
+    input: PulseInput,
+    last_sample: Option<Instant>,
+}
+
+impl RpmSensor {
+    pub fn new(pin: InputPin, config: RpmConfig) -> Result<Self> {
+        anyhow::ensure!(
+            config.pulses_per_revolution > 0.0 && config.pulses_per_revolution.is_finite(),
+            "Invalid number of pulses per revolution: {}.",
+            config.pulses_per_revolution
+        );
+        anyhow::ensure!(
+            config.sample_rate > 0.0 && config.sample_rate.is_finite(),
+            "Invalid sample rate: {} Hz.",
+            config.sample_rate
+        );
+        let counter = PulseCounter::new(config.debounce, config.window);
+        Ok(Self {
+            config,
+            input: PulseInput::new(pin, config.trigger, counter)?,
+            last_sample: None,
+        })
+    }
+
+    /// Pulses since the start, and those dropped by the debounce
+    pub fn count(&self) -> (u64, u64) {
+        self.input.count()
+    }
+
+    /// Speed as of now
+    pub fn sample(&mut self) -> RpmSample {
+        self.last_sample = Some(Instant::now());
+        let (count, _) = self.input.count();
+        RpmSample {
+            rpm: self.input.frequency() * 60.0 / self.config.pulses_per_revolution,
+            revolutions: count as f64 / self.config.pulses_per_revolution,
+        }
+    }
+}
+
+impl SampleSource<RpmSample> for RpmSensor {
+    /// Waits until a sample period, by {config.sample_rate}, has passed since the latest sample, and samples. Ok(None) if that is beyond {timeout}
+    fn next_sample(&mut self, timeout: Option<Duration>) -> (Result<Option<RpmSample>>, Instant) {
+        if let Some(last_sample) = self.last_sample {
+            let wait = (last_sample + Duration::from_secs_f64(1.0 / self.config.sample_rate))
+                .saturating_duration_since(Instant::now());
+            if let Some(timeout) = timeout.filter(|timeout| *timeout < wait) {
+                std::thread::sleep(timeout);
+                return (Ok(None), Instant::now());
+            }
+            std::thread::sleep(wait);
+        }
+        let sample = self.sample();
+        (Ok(Some(sample)), Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+
+    #[test]
+    fn test_order_amplitudes() {
+        // 1800 rpm, with vibration at the shaft frequency and the double of it, on different axes
+        let sample = RpmSample {
+            rpm: 1800.0,
+            revolutions: 0.0,
+        };
+        assert_eq!(sample.frequency(), 30.0);
+        let sample_rate = 256.0;
+        let samples = (0..256)
+            .map(|index| {
+                let time = index as f64 / sample_rate;
+                Vec3D::new(
+                    (2.0 * PI * 30.0 * time).sin(),
+                    0.5 * (2.0 * PI * 60.0 * time).sin(),
+                    0.0,
+                )
+            })
+            .collect::<Vec<_>>();
+        let spectrum = Spectrum::compute(&samples, sample_rate).unwrap();
+        let orders = sample.order_amplitudes(&spectrum, 3);
+        assert_eq!(orders.len(), 3);
+        assert!(orders[0].x > 0.9 && orders[0].y < 0.01);
+        assert!(orders[1].y > 0.45 && orders[1].x < 0.01);
+        assert!(orders[2].x < 0.01 && orders[2].y < 0.01);
+    }
+}
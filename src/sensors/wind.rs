@@ -0,0 +1,224 @@
+// Masthead wind instruments: A cup anemometer closing a switch once or more per revolution for the wind speed, and a wind vane for the direction, either read as a voltage through the ADS1115, or as an angle from a quadrature encoder.
+// Both measure the apparent wind, relative to the boat, in the plane perpendicular to the mast, so with the boat heeled, they see less of the wind across it. `WindSample::leveled` corrects for that with roll and pitch from the IMU.
+
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use rppal::{
+    gpio::{InputPin, Trigger},
+    i2c::I2c,
+};
+
+use super::{
+    ads1115::ADS1115,
+    encoder::Encoder,
+    pulse::{PulseCounter, PulseInput},
+};
+use crate::{gy521::SampleSource, math::angle};
+
+/// How the vane's voltage maps to its angle
+#[derive(Debug, Clone, PartialEq)]
+pub enum VaneResponse {
+    Linear { voltages: (f64, f64) }, // [V] At 0 and at a full turn, e.g., of a potentiometer vane across the supply
+    Positions(Vec<(f64, f64)>), // ([V], [rad]) Of vanes switching between resistors, e.g., 16 positions. The closest voltage wins
+}
+
+impl VaneResponse {
+    /// [rad] Angle of the vane at {voltage}, in [0, 2 pi)
+    pub fn angle(&self, voltage: f64) -> Option<f64> {
+        match self {
+            Self::Linear {
+                voltages: (zero, full),
+            } => {
+                let fraction = (voltage - zero) / (full - zero);
+                fraction
+                    .is_finite()
+                    .then(|| angle::wrap_to_two_pi(fraction * std::f64::consts::TAU))
+            }
+            Self::Positions(positions) => positions
+                .iter()
+                .min_by(|a, b| (a.0 - voltage).abs().total_cmp(&(b.0 - voltage).abs()))
+                .map(|(_, angle)| angle::wrap_to_two_pi(*angle)),
+        }
+    }
+}
+
+/// What turns with the vane
+pub enum WindVane {
+    Analog {
+        adc: ADS1115, // Set up to measure the vane's input, with {configuration.input}
+        i2c: I2c,     // Of the converter, apart from the GY-521's
+        response: VaneResponse,
+    },
+    Encoder(Encoder), // Scaled to [rad], and zeroed with the vane pointing ahead, since it only counts from where it started
+}
+
+impl WindVane {
+    // [rad] As of now
+    fn angle(&mut self) -> Result<Option<f64>> {
+        match self {
+            Self::Analog { adc, i2c, response } => {
+                let sample = adc.read(i2c)?;
+                Ok(response.angle(sample.voltage))
+            }
+            Self::Encoder(encoder) => Ok(Some(angle::wrap_to_two_pi(encoder.sample().position))),
+        }
+    }
+}
+
+/// How `WindSensor` scales its inputs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindConfig {
+    pub speed_factor: f64, // [m/s per Hz] Wind speed per pulse per second. 0.667 for the common cup anemometers giving a pulse per second at 2.4 km/h
+    pub debounce: Duration, // Of the anemometer's switch
+    pub window: Duration,  // Over which the speed is averaged
+    pub direction_offset: f64, // [rad] Added to the vane's angle, for a vane not mounted to read 0 with the wind from ahead
+    pub sample_rate: f64,      // [Hz] Of the samples as a `SampleSource`
+}
+
+impl WindConfig {
+    /// For the common cup anemometers, averaged over 3 s, sampled once a second
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Default for WindConfig {
+    fn default() -> Self {
+        Self {
+            speed_factor: 0.667,
+            debounce: Duration::from_millis(2),
+            window: Duration::from_secs(3),
+            direction_offset: 0.0,
+            sample_rate: 1.0,
+        }
+    }
+}
+
+/// The apparent wind, in the plane perpendicular to the mast
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub struct WindSample {
+    pub speed: f64,             // [m/s]
+    pub direction: Option<f64>, // [rad] Where the wind comes from, clockwise from ahead, in [0, 2 pi). None without a vane, or with its voltage out of range
+}
+
+impl WindSample {
+    /// The wind in the horizontal plane, from that seen by instruments on a mast heeled by {roll} and {pitch} [rad], e.g., from `fusion`. Without a direction, the speed is taken as coming from across the boat, which is the worst case.
+    /// The instruments see the wind across the boat scaled by cos(roll), and that along it by cos(pitch), which this undoes, as long as the angles are below 90 degrees
+    pub fn leveled(&self, roll: f64, pitch: f64) -> Self {
+        let direction = self.direction.unwrap_or(std::f64::consts::FRAC_PI_2);
+        let along = self.speed * direction.cos() / pitch.cos();
+        let across = self.speed * direction.sin() / roll.cos();
+        Self {
+            speed: along.hypot(across),
+            direction: self
+                .direction
+                .map(|_| angle::wrap_to_two_pi(across.atan2(along))),
+        }
+    }
+}
+
+/// A cup anemometer on {anemometer}, counted on interrupts, and a {vane}, if any
+pub struct WindSensor {
+    pub config: WindConfig,
+    anemometer: PulseInput,
+    vane: Option<WindVane>,
+    last_sample: Option<Instant>,
+}
+
+impl WindSensor {
+    /// Counts falling edges on {anemometer}, for a switch to ground, which needs a pull-up, e.g., `Pin::into_input_pullup`
+    pub fn new(anemometer: InputPin, vane: Option<WindVane>, config: WindConfig) -> Result<Self> {
+        anyhow::ensure!(
+            config.sample_rate > 0.0 && config.sample_rate.is_finite(),
+            "Invalid sample rate: {} Hz.",
+            config.sample_rate
+        );
+        let counter = PulseCounter::new(config.debounce, config.window);
+        Ok(Self {
+            config,
+            anemometer: PulseInput::new(anemometer, Trigger::FallingEdge, counter)?,
+            vane,
+            last_sample: None,
+        })
+    }
+
+    /// Anemometer pulses since the start, and bounces filtered
+    pub fn count(&self) -> (u64, u64) {
+        self.anemometer.count()
+    }
+
+    /// Speed and direction as of now
+    pub fn sample(&mut self) -> Result<WindSample> {
+        let speed = self.anemometer.frequency() * self.config.speed_factor;
+        let direction = match &mut self.vane {
+            Some(vane) => vane
+                .angle()?
+                .map(|angle| angle::wrap_to_two_pi(angle + self.config.direction_offset)),
+            None => None,
+        };
+        self.last_sample = Some(Instant::now());
+        Ok(WindSample { speed, direction })
+    }
+}
+
+impl SampleSource<WindSample> for WindSensor {
+    /// Waits until a sample period, by {config.sample_rate}, has passed since the latest sample, and samples. Ok(None) if that is beyond {timeout}
+    fn next_sample(&mut self, timeout: Option<Duration>) -> (Result<Option<WindSample>>, Instant) {
+        if let Some(last_sample) = self.last_sample {
+            let wait = (last_sample + Duration::from_secs_f64(1.0 / self.config.sample_rate))
+                .saturating_duration_since(Instant::now());
+            if let Some(timeout) = timeout.filter(|timeout| *timeout < wait) {
+                std::thread::sleep(timeout);
+                return (Ok(None), Instant::now());
+            }
+            std::thread::sleep(wait);
+        }
+        let sample = self.sample();
+        (sample.map(Some), Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::{FRAC_PI_2, FRAC_PI_3, PI};
+
+    use super::*;
+
+    #[test]
+    fn test_wind() {
+        let near = |a: f64, b: f64| (a - b).abs() < 1e-9;
+
+        let linear = VaneResponse::Linear {
+            voltages: (0.0, 3.3),
+        };
+        assert!(near(linear.angle(1.65).unwrap(), PI));
+        assert!(near(linear.angle(3.3).unwrap(), 0.0));
+        let positions = VaneResponse::Positions(vec![(0.5, 0.0), (1.5, FRAC_PI_2), (2.5, PI)]);
+        assert_eq!(positions.angle(1.7), Some(FRAC_PI_2));
+        assert_eq!(VaneResponse::Positions(Vec::new()).angle(1.0), None);
+
+        // Heeled by 60 degrees, the wind from abeam looks half as strong
+        let abeam = WindSample {
+            speed: 5.0,
+            direction: Some(FRAC_PI_2),
+        };
+        let leveled = abeam.leveled(FRAC_PI_3, 0.0);
+        assert!(near(leveled.speed, 10.0));
+        assert!(near(leveled.direction.unwrap(), FRAC_PI_2));
+        // Wind from ahead isn't affected by heel
+        let ahead = WindSample {
+            speed: 5.0,
+            direction: Some(0.0),
+        };
+        assert!(near(ahead.leveled(FRAC_PI_3, 0.0).speed, 5.0));
+        // Across and along are scaled differently, which turns the direction aft
+        let quarter = WindSample {
+            speed: 2.0_f64.sqrt(),
+            direction: Some(FRAC_PI_2 / 2.0),
+        };
+        let leveled = quarter.leveled(FRAC_PI_3, 0.0);
+        assert!(near(leveled.speed, 5.0_f64.sqrt()));
+        assert!(near(leveled.direction.unwrap(), 2.0_f64.atan()));
+    }
+}
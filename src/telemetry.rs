@@ -0,0 +1,264 @@
+// Streaming samples live to other devices, e.g., to a laptop showing the attitude while the Pi sits in the bilge.
+// Senders take samples like the sinks in `logging` do, so they plug into the same pipeline, and share the encoding of samples into messages.
+
+pub mod control;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod http;
+pub mod mavlink;
+pub mod metrics;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod nmea;
+#[cfg(feature = "signalk")]
+pub mod signalk;
+pub mod tcp;
+pub mod udp;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+#[cfg(feature = "zeromq")]
+pub mod zeromq;
+
+use std::{
+    io::Read,
+    time::{Duration, Instant},
+};
+
+use anyhow::{ensure, Context, Result};
+
+use crate::{
+    fusion::AttitudeFilter,
+    gy521::SensorSample,
+    logging::{binary, Timebase},
+    math::{RotationOrder, Vec3D},
+};
+
+const BINARY_MESSAGE_SIZE: usize = binary::FRAME_SIZE + 2 * 4; // [bytes]
+
+/// One sample as sent to clients, with the attitude derived from it
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct Message {
+    pub time: f64, // [s] Since the start of the recording. The wall-clock start is in the metadata
+    #[serde(flatten)]
+    pub sample: SensorSample<Vec3D, f64>,
+    pub roll: f64,  // [degree] From `SensorSample::tilt`
+    pub pitch: f64, // [degree]
+}
+
+impl Message {
+    pub fn new(sample: &SensorSample<Vec3D, f64>, time: Duration) -> Self {
+        let (roll, pitch) = sample.tilt();
+        Self {
+            time: time.as_secs_f64(),
+            sample: *sample,
+            roll: roll.to_degrees(),
+            pitch: pitch.to_degrees(),
+        }
+    }
+}
+
+/// Just the attitude, for clients that don't need the whole sample
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct Attitude {
+    pub time: f64,  // [s] Since the start of the recording
+    pub roll: f64,  // [degree]
+    pub pitch: f64, // [degree]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub yaw: Option<f64>, // [degree] Only from an attitude filter. Drifts without a magnetometer
+}
+
+impl Attitude {
+    /// Roll and pitch from `SensorSample::tilt`, without yaw
+    pub fn from_tilt(sample: &SensorSample<Vec3D, f64>, time: Duration) -> Self {
+        let (roll, pitch) = sample.tilt();
+        Self {
+            time: time.as_secs_f64(),
+            roll: roll.to_degrees(),
+            pitch: pitch.to_degrees(),
+            yaw: None,
+        }
+    }
+}
+
+/// Tracks the attitude of sample after sample, with an attitude filter if there is one, or else from the tilt of each sample alone
+pub struct AttitudeTracker {
+    filter: Option<Box<dyn AttitudeFilter + Send>>,
+    previous: Option<Instant>, // Of the previous sample
+}
+
+impl AttitudeTracker {
+    pub fn new(filter: Option<Box<dyn AttitudeFilter + Send>>) -> Self {
+        Self {
+            filter,
+            previous: None,
+        }
+    }
+
+    /// Feeds {sample}, taken at {instant}, to the filter, and returns the attitude. Yaw is None without a filter
+    pub fn update(
+        &mut self,
+        sample: &SensorSample<Vec3D, f64>,
+        instant: Instant,
+        timebase: &Timebase,
+    ) -> Attitude {
+        let time = timebase.elapsed(instant);
+        let sample_period = self.previous.replace(instant).map_or(0.0, |previous| {
+            instant.saturating_duration_since(previous).as_secs_f64()
+        });
+        match &mut self.filter {
+            Some(filter) => {
+                filter.update_attitude(sample, sample_period);
+                let angles = filter.euler_angles(RotationOrder::ZYX);
+                Attitude {
+                    time: time.as_secs_f64(),
+                    roll: angles.roll.to_degrees(),
+                    pitch: angles.pitch.to_degrees(),
+                    yaw: Some(angles.yaw.to_degrees()),
+                }
+            }
+            None => Attitude::from_tilt(sample, time),
+        }
+    }
+}
+
+/// How messages are serialized
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Json,   // A `Message` as JSON object, for reading with anything
+    Binary, // A frame of `logging::binary` (time [ns], acceleration, angular velocity, temperature), then roll and pitch [degree] as f32, little-endian. 44 bytes
+}
+
+impl Encoding {
+    pub fn encode(&self, message: &Message) -> Result<Vec<u8>> {
+        match self {
+            Self::Json => Ok(serde_json::to_vec(message)?),
+            Self::Binary => {
+                let mut bytes = Vec::with_capacity(BINARY_MESSAGE_SIZE);
+                let time = Duration::from_secs_f64(message.time.max(0.0));
+                binary::encode_frame(&mut bytes, &message.sample, time);
+                for angle in [message.roll, message.pitch] {
+                    bytes.extend_from_slice(&(angle as f32).to_le_bytes());
+                }
+                Ok(bytes)
+            }
+        }
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> Result<Message> {
+        match self {
+            Self::Json => serde_json::from_slice(bytes).context("Unable to parse message."),
+            Self::Binary => {
+                ensure!(
+                    bytes.len() == BINARY_MESSAGE_SIZE,
+                    "Binary message has {} bytes instead of {BINARY_MESSAGE_SIZE}.",
+                    bytes.len()
+                );
+                let (sample, time) = binary::decode_frame(bytes);
+                let angle = |start: usize| {
+                    f32::from_le_bytes(bytes[start..start + 4].try_into().unwrap()) as f64
+                };
+                Ok(Message {
+                    time: time.as_secs_f64(),
+                    sample,
+                    roll: angle(binary::FRAME_SIZE),
+                    pitch: angle(binary::FRAME_SIZE + 4),
+                })
+            }
+        }
+    }
+
+    /// {sample} taken at {instant}, encoded and length-prefixed. See `frame`
+    pub fn sample_frame(
+        &self,
+        sample: &SensorSample<Vec3D, f64>,
+        instant: Instant,
+        timebase: &Timebase,
+    ) -> Result<Vec<u8>> {
+        Ok(frame(&self.encode(&Message::new(
+            sample,
+            timebase.elapsed(instant),
+        ))?))
+    }
+}
+
+/// Lets samples through at up to a rate, judged by the instants they were taken at, so that a burst of samples read late still thins out evenly
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    period: Duration,      // Between samples let through
+    last: Option<Instant>, // Of the latest sample let through
+}
+
+impl RateLimit {
+    /// Up to {rate} [Hz]. None lets every sample through
+    pub fn new(rate: Option<f64>) -> Result<Self> {
+        let period = match rate {
+            Some(rate) => {
+                ensure!(rate > 0.0, "Rate is {rate} Hz, but needs to be positive.");
+                Duration::from_secs_f64(1.0 / rate)
+            }
+            None => Duration::ZERO,
+        };
+        Ok(Self { period, last: None })
+    }
+
+    /// Whether the sample taken at {instant} goes through, which then counts for the next ones
+    pub fn admit(&mut self, instant: Instant) -> bool {
+        let due = self
+            .last
+            .is_none_or(|last| instant.saturating_duration_since(last) >= self.period);
+        if due {
+            self.last = Some(instant);
+        }
+        due
+    }
+}
+
+/// {payload} with its length in front, as u32, little-endian, so that messages can be told apart on a stream
+pub fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Payload of the next frame on {reader}, or None if the stream ends before it starts
+pub fn read_frame<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+    let mut length = [0; 4];
+    match reader.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error).context("Unable to read frame."),
+    }
+    let mut payload = vec![0; u32::from_le_bytes(length) as usize];
+    reader
+        .read_exact(&mut payload)
+        .context("Stream ends in the middle of a frame.")?;
+    Ok(Some(payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encodings() {
+        let sample = SensorSample::new(Vec3D::new(0, 0.5, 0.5), Vec3D::new(1, 2, 3), Some(21.5));
+        let message = Message::new(&sample, Duration::from_millis(1500));
+        assert!((message.roll - 45.0).abs() < 1e-9);
+        for encoding in [Encoding::Json, Encoding::Binary] {
+            let bytes = frame(&encoding.encode(&message).unwrap());
+            let payload = read_frame(&mut bytes.as_slice()).unwrap().unwrap();
+            let decoded = encoding.decode(&payload).unwrap();
+            assert_eq!(decoded.time, 1.5);
+            assert!(decoded
+                .sample
+                .angular_velocity()
+                .approx_eq(&sample.angular_velocity(), 1e-6));
+            assert_eq!(decoded.sample.temperature(), Some(21.5));
+            assert!((decoded.roll - message.roll).abs() < 1e-4);
+        }
+        assert_eq!(Encoding::Binary.encode(&message).unwrap().len(), 44);
+        assert!(read_frame(&mut [0u8; 0].as_slice()).unwrap().is_none());
+        assert!(read_frame(&mut [5u8, 0, 0, 0, 1].as_slice()).is_err());
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+
+/// What can be asked of a running recording from another device, as JSON, e.g., {"command": "set_sample_rate", "rate": 200}
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    StartRecording, // Stores samples again after stop_recording
+    StopRecording,  // Keeps sampling and streaming, but stops storing samples
+    Calibrate, // With the defaults. The sensor needs to lie flat and still meanwhile, and sampling pauses until it is done
+    SetSampleRate {
+        rate: f64, // [Hz] Rounded to what the sensor supports
+    },
+    MarkEvent {
+        tag: String, // E.g., "tack", like markers typed into the console
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        payload: Option<serde_json::Value>,
+    },
+    Shutdown, // Stops like Ctrl-C does, writing everything out first
+}
+
+/// What every command is answered with. Accepted only means that the command was understood and queued, the outcome shows in the console and the recording
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Acknowledgement {
+    pub accepted: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>, // Why the command wasn't accepted
+}
+
+impl Acknowledgement {
+    pub fn accepted() -> Self {
+        Self {
+            accepted: true,
+            error: None,
+        }
+    }
+
+    pub fn rejected(error: &anyhow::Error) -> Self {
+        Self {
+            accepted: false,
+            error: Some(format!("{error:#}")),
+        }
+    }
+}
+
+/// Hands commands over from the threads of the transports, e.g., `ControlServer`, to the sampling loop. See `channel`
+#[derive(Clone)]
+pub struct CommandSender(crossbeam_channel::Sender<Command>);
+
+impl CommandSender {
+    pub fn send(&self, command: Command) -> Result<()> {
+        self.0
+            .send(command)
+            .context("Commands aren't taken anymore.")
+    }
+
+    /// Parses {text} as a JSON `Command` and sends it, for transports to answer with the result
+    pub fn submit(&self, text: &[u8]) -> Acknowledgement {
+        let command = serde_json::from_slice(text).context("Unable to parse command.");
+        match command.and_then(|command| self.send(command)) {
+            Ok(()) => Acknowledgement::accepted(),
+            Err(error) => Acknowledgement::rejected(&error),
+        }
+    }
+}
+
+/// The receiving end gets the commands in the order they were sent, for the sampling loop to carry out between samples
+pub fn channel() -> (CommandSender, crossbeam_channel::Receiver<Command>) {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    (CommandSender(sender), receiver)
+}
+
+/// Takes commands over TCP, one JSON `Command` per line, and answers each with a line of JSON `Acknowledgement`, e.g., with "nc raspberrypi.local 5002".
+/// Anyone who can connect can stop the recording, so this is meant for trusted networks only
+pub struct ControlServer {
+    address: SocketAddr,
+    running: Arc<AtomicBool>,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+impl ControlServer {
+    /// Listens on {address}, e.g., "0.0.0.0:5002" for all network interfaces, and passes commands on to {commands}. Port 0 picks a free port, see `local_address`
+    pub fn bind<A: ToSocketAddrs>(address: A, commands: CommandSender) -> Result<Self> {
+        let listener = TcpListener::bind(address).context("Unable to start control server.")?;
+        // Accepting polls, so that the server can be stopped
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+
+        let running = Arc::new(AtomicBool::new(true));
+        let acceptor = {
+            let running = running.clone();
+            std::thread::Builder::new()
+                .name("telemetry-control".to_string())
+                .spawn(move || {
+                    while running.load(Ordering::Relaxed) {
+                        match listener.accept() {
+                            Ok((stream, address)) => {
+                                let (commands, running) = (commands.clone(), running.clone());
+                                let _ = std::thread::Builder::new()
+                                    .name(format!("telemetry-control-{address}"))
+                                    .spawn(move || {
+                                        // Whatever goes wrong only concerns this client
+                                        let _ = serve(stream, &commands, &running);
+                                    });
+                            }
+                            // Mostly nobody connecting right now
+                            Err(_) => std::thread::sleep(Duration::from_millis(50)),
+                        }
+                    }
+                })
+                .context("Unable to start control server.")?
+        };
+
+        Ok(Self {
+            address,
+            running,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    pub fn local_address(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        // Client threads notice within their read timeout
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+    }
+}
+
+// Answers the commands of one client until it disconnects, or the server is gone
+fn serve(stream: TcpStream, commands: &CommandSender, running: &AtomicBool) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+    // Reading waits in steps, so that the server can be stopped
+    stream.set_read_timeout(Some(Duration::from_millis(100)))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut line = Vec::new();
+    while running.load(Ordering::Relaxed) {
+        // Lines cut by the timeout are continued on the next read
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => return Ok(()),
+            Ok(_) if line.ends_with(b"\n") => {
+                if !line.trim_ascii().is_empty() {
+                    let acknowledgement = commands.submit(&line);
+                    writer.write_all(&serde_json::to_vec(&acknowledgement)?)?;
+                    writer.write_all(b"\n")?;
+                }
+                line.clear();
+            }
+            Ok(_) => return Ok(()), // Closed in the middle of a line
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) => {}
+            Err(error) => return Err(error.into()),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_commands() {
+        let (sender, receiver) = channel();
+        let server = ControlServer::bind("127.0.0.1:0", sender).unwrap();
+        let mut stream = TcpStream::connect(server.local_address()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+            .write_all(
+                concat!(
+                    "{\"command\": \"set_sample_rate\", \"rate\": 200}\n",
+                    "\n",
+                    "{\"command\": \"mark_event\", \"tag\": \"tack\"}\n",
+                    "{\"command\": \"self_destruct\"}\n",
+                    "{\"command\": \"shutdown\"}\n",
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut answers = String::new();
+        stream.read_to_string(&mut answers).unwrap();
+
+        let acknowledgements = answers
+            .lines()
+            .map(|line| serde_json::from_str::<Acknowledgement>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(acknowledgements.len(), 4);
+        assert!(acknowledgements[0].accepted && acknowledgements[1].accepted);
+        assert!(!acknowledgements[2].accepted);
+        assert!(acknowledgements[2].error.is_some());
+        assert!(acknowledgements[3].accepted);
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            [
+                Command::SetSampleRate { rate: 200.0 },
+                Command::MarkEvent {
+                    tag: "tack".to_string(),
+                    payload: None
+                },
+                Command::Shutdown,
+            ]
+        );
+    }
+}
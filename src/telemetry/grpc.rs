@@ -0,0 +1,409 @@
+use std::{
+    net::{SocketAddr, TcpListener, ToSocketAddrs},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use tokio::sync::{broadcast, oneshot};
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream, TcpListenerStream},
+    Stream, StreamExt,
+};
+use tonic::Status;
+
+use super::RateLimit;
+use crate::{
+    gy521::{calibration::CalibrationData, SensorSample},
+    logging::{Metadata, Sink, Timebase},
+    math::Vec3D,
+};
+
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Messages and service generated from proto/njord.proto, including `njord_client::NjordClient` for connecting from Rust
+pub mod proto {
+    #![allow(clippy::all)]
+    tonic::include_proto!("njord.v1");
+}
+
+/// Something a client asked for, to be carried out by the sampling loop, which owns the sensor. Answer it through its `Reply`
+#[derive(Debug)]
+pub enum Command {
+    StartSampling(Reply<()>),
+    StopSampling(Reply<()>),
+    Calibrate {
+        duration: Duration,        // Of sampling
+        sampling_period: Duration, // Between the samples used
+        sample_size: usize,        // Only the latest this many samples are used
+        reply: Reply<CalibrationData>,
+    },
+}
+
+/// The client's end of a `Command`, waiting for the outcome
+#[derive(Debug)]
+pub struct Reply<T>(oneshot::Sender<Result<T, String>>);
+
+impl<T> Reply<T> {
+    /// Hands {result} to the client, which gets errors as an INTERNAL status
+    pub fn send(self, result: Result<T>) {
+        // The client may have given up waiting
+        let _ = self.0.send(result.map_err(|error| format!("{error:#}")));
+    }
+}
+
+/// Serves the API in proto/njord.proto to remote tools, on a background thread: starting and stopping sampling, calibrating, the configuration, and samples as they come in.
+/// Commands end up in `commands`, for the sampling loop to carry out. Samples written to it go out to every client streaming them, and clients that fall behind miss samples rather than hold up the rest
+pub struct GrpcServer {
+    local_address: SocketAddr,
+    timebase: Timebase,
+    samples: Option<broadcast::Sender<(proto::Sample, Instant)>>, // None once dropped, to end the streams
+    commands: crossbeam_channel::Receiver<Command>,
+    configuration: Arc<Mutex<proto::Configuration>>,
+    shutdown: Option<oneshot::Sender<()>>,
+    server: Option<JoinHandle<Result<()>>>,
+}
+
+impl GrpcServer {
+    /// Listens on {address}, e.g., "0.0.0.0:50051", with {metadata} as the configuration until `set_metadata`
+    pub fn bind(
+        address: impl ToSocketAddrs,
+        metadata: &Metadata,
+        timebase: Timebase,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind(address).context("Unable to bind gRPC server.")?;
+        listener.set_nonblocking(true)?;
+        let local_address = listener.local_addr()?;
+
+        let (samples, receiver) = broadcast::channel(1000);
+        let (command_sender, commands) = crossbeam_channel::unbounded();
+        let configuration = Arc::new(Mutex::new(configuration(metadata)));
+        let service = Service {
+            samples: receiver,
+            commands: command_sender,
+            configuration: configuration.clone(),
+        };
+        let (shutdown, shutdown_receiver) = oneshot::channel();
+        let server = std::thread::Builder::new()
+            .name("grpc".to_string())
+            .spawn(move || {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()?;
+                runtime.block_on(async {
+                    let listener = tokio::net::TcpListener::from_std(listener)?;
+                    let (stop, stopped) = oneshot::channel::<()>();
+                    let server = tokio::spawn(
+                        tonic::transport::Server::builder()
+                            .add_service(proto::njord_server::NjordServer::new(service))
+                            .serve_with_incoming_shutdown(
+                                TcpListenerStream::new(listener),
+                                async {
+                                    let _ = stopped.await;
+                                },
+                            ),
+                    );
+                    let _ = shutdown_receiver.await;
+                    let _ = stop.send(());
+                    // Clients get a moment to be told, but one that doesn't answer doesn't hold up stopping
+                    match tokio::time::timeout(SHUTDOWN_TIMEOUT, server).await {
+                        Ok(result) => result?.context("gRPC server failed."),
+                        Err(_) => Ok(()),
+                    }
+                })
+            })
+            .context("Unable to start gRPC server.")?;
+
+        Ok(Self {
+            local_address,
+            timebase,
+            samples: Some(samples),
+            commands,
+            configuration,
+            shutdown: Some(shutdown),
+            server: Some(server),
+        })
+    }
+
+    pub fn local_address(&self) -> SocketAddr {
+        self.local_address
+    }
+
+    /// Commands that came in since the last call, oldest first, without waiting
+    pub fn commands(&self) -> crossbeam_channel::TryIter<'_, Command> {
+        self.commands.try_iter()
+    }
+
+    /// Makes GetConfiguration answer with {metadata}, e.g., after calibrating
+    pub fn set_metadata(&self, metadata: &Metadata) {
+        *self.configuration.lock().unwrap() = configuration(metadata);
+    }
+}
+
+impl Sink for GrpcServer {
+    fn write(&mut self, samples: &[(SensorSample<Vec3D, f64>, Instant)]) -> Result<()> {
+        for (sample, instant) in samples {
+            let message = proto::Sample {
+                time: self.timebase.elapsed(*instant).as_secs_f64(),
+                acceleration: Some(sample.acceleration().into()),
+                angular_velocity: Some(sample.angular_velocity().into()),
+                temperature: sample.temperature(),
+            };
+            // Fails only without any client streaming samples
+            if let Some(samples) = &self.samples {
+                let _ = samples.send((message, *instant));
+            }
+        }
+        Ok(())
+    }
+
+    // Samples go out as they are written
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for GrpcServer {
+    fn drop(&mut self) {
+        // The server only shuts down once no responses are in progress
+        self.samples = None;
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(server) = self.server.take() {
+            let _ = server.join();
+        }
+    }
+}
+
+struct Service {
+    samples: broadcast::Receiver<(proto::Sample, Instant)>, // Only for subscribing, so that streams end along with the `GrpcServer`
+    commands: crossbeam_channel::Sender<Command>,
+    configuration: Arc<Mutex<proto::Configuration>>,
+}
+
+impl Service {
+    // Hands the command made from a new reply to the sampling loop, and waits for the outcome
+    async fn command<T>(&self, command: impl FnOnce(Reply<T>) -> Command) -> Result<T, Status> {
+        let (sender, receiver) = oneshot::channel();
+        // Either end is only gone once the sampling loop has stopped
+        self.commands
+            .send(command(Reply(sender)))
+            .map_err(|_| shutting_down())?;
+        receiver
+            .await
+            .map_err(|_| shutting_down())?
+            .map_err(Status::internal)
+    }
+}
+
+#[tonic::async_trait]
+impl proto::njord_server::Njord for Service {
+    async fn start_sampling(
+        &self,
+        _request: tonic::Request<proto::StartSamplingRequest>,
+    ) -> Result<tonic::Response<proto::SamplingState>, Status> {
+        self.command(Command::StartSampling).await?;
+        Ok(tonic::Response::new(proto::SamplingState {
+            sampling: true,
+        }))
+    }
+
+    async fn stop_sampling(
+        &self,
+        _request: tonic::Request<proto::StopSamplingRequest>,
+    ) -> Result<tonic::Response<proto::SamplingState>, Status> {
+        self.command(Command::StopSampling).await?;
+        Ok(tonic::Response::new(proto::SamplingState {
+            sampling: false,
+        }))
+    }
+
+    async fn calibrate(
+        &self,
+        request: tonic::Request<proto::CalibrateRequest>,
+    ) -> Result<tonic::Response<proto::Calibration>, Status> {
+        let request = request.into_inner();
+        // Zero, the default of every field, stands for the defaults of `njord`
+        let seconds = |seconds: f64, default| match seconds {
+            0.0 => Ok(default),
+            seconds => Duration::try_from_secs_f64(seconds),
+        };
+        let invalid = |error: std::time::TryFromFloatSecsError| {
+            Status::invalid_argument(format!("Invalid duration: {error}"))
+        };
+        let duration = seconds(request.duration, Duration::from_secs(5 * 60)).map_err(invalid)?;
+        let sampling_period =
+            seconds(request.sampling_period, Duration::from_millis(100)).map_err(invalid)?;
+        let sample_size = match request.sample_size {
+            0 => 10_000,
+            sample_size => sample_size as usize,
+        };
+        let calibration = self
+            .command(|reply| Command::Calibrate {
+                duration,
+                sampling_period,
+                sample_size,
+                reply,
+            })
+            .await?;
+        Ok(tonic::Response::new((&calibration).into()))
+    }
+
+    async fn get_configuration(
+        &self,
+        _request: tonic::Request<proto::GetConfigurationRequest>,
+    ) -> Result<tonic::Response<proto::Configuration>, Status> {
+        Ok(tonic::Response::new(
+            self.configuration.lock().unwrap().clone(),
+        ))
+    }
+
+    type SamplesStream = Pin<Box<dyn Stream<Item = Result<proto::Sample, Status>> + Send>>;
+
+    async fn samples(
+        &self,
+        request: tonic::Request<proto::SamplesRequest>,
+    ) -> Result<tonic::Response<Self::SamplesStream>, Status> {
+        let rate = request.into_inner().rate;
+        let mut rate_limit = RateLimit::new((rate != 0.0).then_some(rate))
+            .map_err(|error| Status::invalid_argument(error.to_string()))?;
+        let samples =
+            BroadcastStream::new(self.samples.resubscribe()).filter_map(move |item| match item {
+                Ok((sample, instant)) => rate_limit.admit(instant).then_some(Ok(sample)),
+                Err(BroadcastStreamRecvError::Lagged(_)) => None,
+            });
+        Ok(tonic::Response::new(Box::pin(samples)))
+    }
+}
+
+impl From<Vec3D> for proto::Vector {
+    fn from(vector: Vec3D) -> Self {
+        Self {
+            x: vector.x,
+            y: vector.y,
+            z: vector.z,
+        }
+    }
+}
+
+impl From<&CalibrationData> for proto::Calibration {
+    fn from(calibration: &CalibrationData) -> Self {
+        let report = calibration.report.as_ref();
+        Self {
+            gyroscope_offset: Some(calibration.gyroscope_offset.into()),
+            accelerometer_offset: Some(calibration.accelerometer_offset.into()),
+            temperature: calibration.temperature,
+            sample_count: report.map_or(0, |report| report.sample_count as u32),
+            rejected_outliers: report.map_or(0, |report| report.rejected_outliers as u32),
+            duration: report.map_or(0.0, |report| report.duration),
+        }
+    }
+}
+
+fn shutting_down() -> Status {
+    Status::unavailable("Njord is shutting down.")
+}
+
+fn configuration(metadata: &Metadata) -> proto::Configuration {
+    proto::Configuration {
+        start_time: metadata
+            .start_time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64(),
+        sample_rate: metadata.sample_rate,
+        accelerometer_range: metadata.accelerometer_range as i32,
+        gyroscope_range: metadata.gyroscope_range as i32,
+        filter: format!("{:?}", metadata.filter),
+        calibration: Some((&metadata.calibration).into()),
+        crate_version: metadata.crate_version.clone(),
+        device_model: metadata.device_model.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::njord_client::NjordClient;
+
+    #[test]
+    fn test_service() {
+        let timebase = Timebase::now();
+        let metadata = Metadata::for_tests(&timebase);
+        let mut server = GrpcServer::bind("127.0.0.1:0", &metadata, timebase).unwrap();
+        let url = format!("http://{}", server.local_address());
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let (mut client, mut samples) = runtime.block_on(async {
+            let mut client = NjordClient::connect(url).await.unwrap();
+            let samples = client
+                .samples(proto::SamplesRequest { rate: 0.0 })
+                .await
+                .unwrap()
+                .into_inner();
+            (client, samples)
+        });
+
+        let sample = SensorSample::new(Vec3D::new(0, 0, 1), Vec3D::new(1, 2, 3), Some(25.0));
+        server
+            .write(&[(sample, timebase.start + Duration::from_millis(5))])
+            .unwrap();
+        let received = runtime.block_on(samples.message()).unwrap().unwrap();
+        assert_eq!(received.time, 0.005);
+        assert_eq!(received.angular_velocity.unwrap().y, 2.0);
+        assert_eq!(received.temperature, Some(25.0));
+
+        // The sampling loop carries out commands, here on another thread
+        let (calibration, server) = runtime.block_on(async {
+            let configuration = client
+                .get_configuration(proto::GetConfigurationRequest {})
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(configuration.filter, "BwAc184HzBwGy188Hz");
+            assert_eq!(configuration.sample_rate, 1e3);
+
+            let calibrate = client.calibrate(proto::CalibrateRequest {
+                duration: 0.0,
+                sampling_period: 0.0,
+                sample_size: 0,
+            });
+            let command = tokio::task::spawn_blocking(move || {
+                let command = loop {
+                    if let Some(command) = server.commands().next() {
+                        break command;
+                    }
+                    std::thread::sleep(Duration::from_millis(1));
+                };
+                match command {
+                    Command::Calibrate {
+                        duration,
+                        sample_size,
+                        reply,
+                        ..
+                    } => {
+                        assert_eq!(duration, Duration::from_secs(5 * 60));
+                        assert_eq!(sample_size, 10_000);
+                        reply.send(Err(anyhow::anyhow!("Sensor is asleep.")));
+                    }
+                    command => panic!("Unexpected command: {command:?}"),
+                }
+                server
+            });
+            (calibrate.await, command.await.unwrap())
+        });
+        let status = calibration.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Internal);
+        assert_eq!(status.message(), "Sensor is asleep.");
+
+        // Streams end along with the server
+        drop(server);
+        assert!(!matches!(runtime.block_on(samples.message()), Ok(Some(_))));
+    }
+}
@@ -0,0 +1,314 @@
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+
+use super::{metrics::Metrics, Message};
+use crate::{
+    gy521::SensorSample,
+    logging::{Metadata, Sink, Timebase},
+    math::Vec3D,
+};
+
+const MAX_REQUEST_SIZE: usize = 8192; // [bytes] Of the request line and headers
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1); // Clients are served one after the other, so a stalled one doesn't get to hold up the rest for long
+
+/// What `/status` answers with
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DeviceStatus {
+    pub uptime: f64,                // [s] Since the start of the recording
+    pub sample_rate: f64,           // [Hz] As set up
+    pub measured_rate: Option<f64>, // [Hz] Over the latest full second. None before that
+    pub samples: usize,
+    pub errors: usize,
+    pub last_error: Option<String>,
+}
+
+// Kept up to date by the sampling loop, and read by the server
+struct State {
+    latest: Option<Message>,
+    metrics: Metrics,
+    last_error: Option<String>,
+    window: (Instant, usize), // Start of the current second, and samples in it so far
+}
+
+/// Answers HTTP GET requests with JSON, for quick checks from another machine, e.g., "curl raspberrypi.local:8000/status", and with metrics for Prometheus to scrape:
+/// - `/sample`, the latest sample, with roll and pitch, as `telemetry::Message`. 503 before the first one.
+/// - `/status`, the sample rate, error counts, and uptime, as `DeviceStatus`.
+/// - `/config`, how the sensor is set up, as `logging::Metadata`.
+/// - `/metrics`, counters and gauges for Prometheus, as `metrics::Metrics::render` gives them.
+///
+/// Samples written to it and errors passed to `record_error` make up the answers. Requests are served one after the other on a background thread
+pub struct HttpServer {
+    address: SocketAddr,
+    timebase: Timebase,
+    state: Arc<Mutex<State>>,
+    running: Arc<AtomicBool>,
+    server: Option<JoinHandle<()>>,
+}
+
+impl HttpServer {
+    /// Listens on {address}, e.g., "0.0.0.0:8000" for all network interfaces. Port 0 picks a free port, see `local_address`
+    pub fn bind<A: ToSocketAddrs>(
+        address: A,
+        metadata: &Metadata,
+        timebase: Timebase,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind(address).context("Unable to start HTTP server.")?;
+        // Accepting polls, so that the server can be stopped
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+
+        let state = Arc::new(Mutex::new(State {
+            latest: None,
+            metrics: Metrics::new(metadata.start_time),
+            last_error: None,
+            window: (timebase.start, 0),
+        }));
+        let running = Arc::new(AtomicBool::new(true));
+        let config = serde_json::to_string_pretty(metadata)?;
+        let server = {
+            let (state, running) = (state.clone(), running.clone());
+            let sample_rate = metadata.sample_rate;
+            std::thread::Builder::new()
+                .name("telemetry-http".to_string())
+                .spawn(move || {
+                    while running.load(Ordering::Relaxed) {
+                        match listener.accept() {
+                            Ok((stream, _)) => {
+                                // Whatever goes wrong only concerns this client
+                                let _ = serve(stream, |path| match path {
+                                    "/sample" => match state.lock().unwrap().latest {
+                                        Some(message) => json(&message),
+                                        None => (503, "No sample yet.\n".to_string()),
+                                    },
+                                    "/status" => json(&status(
+                                        &state.lock().unwrap(),
+                                        sample_rate,
+                                        &timebase,
+                                    )),
+                                    "/config" => (200, format!("{config}\n")),
+                                    "/metrics" => (200, state.lock().unwrap().metrics.render()),
+                                    _ => (
+                                        404,
+                                        "Try /sample, /status, /config, or /metrics.\n".to_string(),
+                                    ),
+                                });
+                            }
+                            // Mostly nobody connecting right now
+                            Err(_) => std::thread::sleep(Duration::from_millis(50)),
+                        }
+                    }
+                })
+                .context("Unable to start HTTP server.")?
+        };
+
+        Ok(Self {
+            address,
+            timebase,
+            state,
+            running,
+            server: Some(server),
+        })
+    }
+
+    pub fn local_address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Counts {error} from reading the sensor in `/status`, as the latest one, and in `/metrics`
+    pub fn record_error(&self, error: &anyhow::Error) {
+        let mut state = self.state.lock().unwrap();
+        state.metrics.i2c_errors += 1;
+        state.last_error = Some(format!("{error:#}"));
+    }
+
+    /// Counts a wait for a sample that ran out of time in `/metrics`
+    pub fn record_timeout(&self) {
+        self.state.lock().unwrap().metrics.interrupt_timeouts += 1;
+    }
+
+    /// Adds {latency}, from taking a sample until every sink has it, to the histogram in `/metrics`
+    pub fn record_latency(&self, latency: Duration) {
+        self.state
+            .lock()
+            .unwrap()
+            .metrics
+            .observe_loop_latency(latency);
+    }
+
+    /// Makes `/metrics` report {count} FIFO overflows so far, as counted by `GY521::fifo_overflows`
+    pub fn set_fifo_overflows(&self, count: usize) {
+        self.state.lock().unwrap().metrics.fifo_overflows = count as u64;
+    }
+}
+
+impl Sink for HttpServer {
+    fn write(&mut self, samples: &[(SensorSample<Vec3D, f64>, Instant)]) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        for (sample, instant) in samples {
+            state.latest = Some(Message::new(sample, self.timebase.elapsed(*instant)));
+            state.metrics.samples += 1;
+            state.metrics.temperature = sample.temperature();
+
+            let (start, count) = state.window;
+            let elapsed = instant.saturating_duration_since(start);
+            if elapsed >= Duration::from_secs(1) {
+                state.metrics.measured_rate = Some(count as f64 / elapsed.as_secs_f64());
+                state.window = (*instant, 1);
+            } else {
+                state.window.1 += 1;
+            }
+        }
+        Ok(())
+    }
+
+    // Answers always use the latest samples
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for HttpServer {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(server) = self.server.take() {
+            let _ = server.join();
+        }
+    }
+}
+
+fn status(state: &State, sample_rate: f64, timebase: &Timebase) -> DeviceStatus {
+    DeviceStatus {
+        uptime: timebase.start.elapsed().as_secs_f64(),
+        sample_rate,
+        measured_rate: state.metrics.measured_rate,
+        samples: state.metrics.samples as usize,
+        errors: state.metrics.i2c_errors as usize,
+        last_error: state.last_error.clone(),
+    }
+}
+
+// Status code 200, and {value} as pretty JSON, which reads well in a terminal
+fn json(value: &impl serde::Serialize) -> (u16, String) {
+    match serde_json::to_string_pretty(value) {
+        Ok(body) => (200, format!("{body}\n")),
+        Err(error) => (500, format!("{error}\n")),
+    }
+}
+
+// Reads one request from {stream}, and answers it with the status code and body {route} gives for its path
+fn serve(mut stream: TcpStream, route: impl FnOnce(&str) -> (u16, String)) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+    // Only the request line matters, but the headers are read too, so that closing the connection doesn't reset it under the client
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let count = stream.read(&mut buffer)?;
+        anyhow::ensure!(
+            count > 0,
+            "Connection closed before the end of the request."
+        );
+        request.extend_from_slice(&buffer[..count]);
+        anyhow::ensure!(request.len() <= MAX_REQUEST_SIZE, "Request too large.");
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut words = request.lines().next().unwrap_or_default().split(' ');
+    let (method, target) = (words.next(), words.next().unwrap_or_default());
+    // Query strings, like from cache busting, don't change the answer
+    let path = target.split('?').next().unwrap_or_default();
+
+    let (code, body) = match method {
+        Some("GET") => route(path),
+        _ => (405, "Only GET is supported.\n".to_string()),
+    };
+    let (reason, content_type) = match code {
+        200 if path == "/metrics" => ("OK", "text/plain; version=0.0.4"),
+        200 => ("OK", "application/json"),
+        404 => ("Not Found", "text/plain"),
+        405 => ("Method Not Allowed", "text/plain"),
+        503 => ("Service Unavailable", "text/plain"),
+        _ => ("Internal Server Error", "text/plain"),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {code} {reason}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\nAccess-Control-Allow-Origin: *\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The status code and body of GET {path}
+    fn get(address: SocketAddr, path: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "GET {path} HTTP/1.1\r\nHost: njord\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let code = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (code, body.to_string())
+    }
+
+    #[test]
+    fn test_endpoints() {
+        let timebase = Timebase::now();
+        let metadata = Metadata {
+            sample_rate: 100.0,
+            ..Metadata::for_tests(&timebase)
+        };
+        let mut server = HttpServer::bind("127.0.0.1:0", &metadata, timebase).unwrap();
+        let address = server.local_address();
+        assert_eq!(get(address, "/sample").0, 503);
+
+        // 150 samples at 100 Hz
+        let sample = SensorSample::new(Vec3D::new(0, 0, 1), Vec3D::new(0, 0, 0), Some(21.0));
+        let samples = (0..150)
+            .map(|n| (sample, timebase.start + Duration::from_millis(n * 10)))
+            .collect::<Vec<_>>();
+        server.write(&samples).unwrap();
+        server.record_error(&anyhow::anyhow!("Interrupt timed out."));
+
+        let (code, body) = get(address, "/sample?now");
+        assert_eq!(code, 200);
+        let message: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(message["time"], 1.49);
+        assert_eq!(message["roll"], 0.0);
+
+        let (code, body) = get(address, "/status");
+        assert_eq!(code, 200);
+        let status: DeviceStatus = serde_json::from_str(&body).unwrap();
+        assert_eq!(status.samples, 150);
+        assert!((status.measured_rate.unwrap() - 100.0).abs() < 1e-9);
+        assert_eq!(status.errors, 1);
+        assert_eq!(status.last_error.unwrap(), "Interrupt timed out.");
+
+        let (code, body) = get(address, "/config");
+        assert_eq!(code, 200);
+        assert_eq!(serde_json::from_str::<Metadata>(&body).unwrap(), metadata);
+
+        let (code, body) = get(address, "/metrics");
+        assert_eq!(code, 200);
+        assert!(body.lines().any(|line| line == "njord_samples_total 150"));
+        assert!(body
+            .lines()
+            .any(|line| line == "njord_temperature_celsius 21"));
+
+        assert_eq!(get(address, "/").0, 404);
+    }
+}
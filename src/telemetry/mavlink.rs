@@ -0,0 +1,413 @@
+use std::{
+    io::Write,
+    net::{ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+
+use super::{AttitudeTracker, RateLimit};
+use crate::{
+    fusion::AttitudeFilter,
+    gy521::SensorSample,
+    logging::{Metadata, Sink, Timebase},
+    math::Vec3D,
+};
+
+const MAGIC: u8 = 0xfd; // MAVLink 2
+const MAVLINK_VERSION: u8 = 3;
+const MAV_AUTOPILOT_GENERIC: u8 = 0;
+const MAV_STATE_ACTIVE: u8 = 4;
+
+// Message ids with their CRC_EXTRA, the checksum seed derived from the message definition
+const HEARTBEAT: (u32, u8) = (0, 50);
+const SCALED_IMU: (u32, u8) = (26, 170);
+const RAW_IMU: (u32, u8) = (27, 144);
+const ATTITUDE: (u32, u8) = (30, 39);
+
+/// How `MavlinkSink` presents the sensor
+pub struct MavlinkConfig {
+    pub system_id: u8,
+    pub component_id: u8,           // 200 is MAV_COMP_ID_IMU
+    pub vehicle_type: u8, // MAV_TYPE in the heartbeat, e.g., 11 for a surface boat or 2 for a quadrotor
+    pub attitude_rate: Option<f64>, // [Hz] Of ATTITUDE. None sends it for every sample
+    pub imu_rate: Option<f64>, // [Hz] Of RAW_IMU and SCALED_IMU
+    pub heartbeat: Duration, // Between heartbeats, which ground stations need to show the system at all
+    pub attitude_filter: Option<Box<dyn AttitudeFilter + Send>>, // Estimates the attitude from every sample. None takes roll and pitch from `SensorSample::tilt`, with a yaw of 0
+}
+
+impl MavlinkConfig {
+    /// Appears as system 1, a boat, with the attitude and IMU messages at 50 Hz
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Default for MavlinkConfig {
+    fn default() -> Self {
+        Self {
+            system_id: 1,
+            component_id: 200,
+            vehicle_type: 11,
+            attitude_rate: Some(50.0),
+            imu_rate: Some(50.0),
+            heartbeat: Duration::from_secs(1),
+            attitude_filter: None,
+        }
+    }
+}
+
+/// How sending has gone so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MavlinkStatus {
+    pub sent: usize,    // Messages
+    pub dropped: usize, // Messages that couldn't be written, e.g., with the network unreachable or the radio unplugged
+}
+
+/// Sends samples as MAVLink 2 messages, so that ground stations like QGroundControl or Mission Planner show the sensor like any autopilot, e.g., over UDP to port 14550, or over a serial telemetry radio.
+/// Every sample can become an ATTITUDE, a RAW_IMU (in counts of the configured ranges), and a SCALED_IMU message, besides a HEARTBEAT every second. Each message is written to {writer} in one go, see `udp`.
+/// Like a radio link might lose them anyway, messages that can't be written are dropped and counted in `status`, rather than failing the write
+pub struct MavlinkSink<W: Write> {
+    writer: W,
+    timebase: Timebase,
+    config: MavlinkConfig,
+    accelerometer_scale: f64, // [1/g] Counts per g
+    gyroscope_scale: f64,     // [s/degree] Counts per degree/s
+    attitude_rate_limit: RateLimit,
+    imu_rate_limit: RateLimit,
+    attitude_tracker: AttitudeTracker,
+    heartbeat: Option<Instant>, // Of the latest sample a heartbeat was sent with
+    sequence: u8,
+    frame: Vec<u8>,
+    status: MavlinkStatus,
+}
+
+impl<W: Write> MavlinkSink<W> {
+    /// The ranges in {metadata} are used for RAW_IMU
+    pub fn new(
+        writer: W,
+        mut config: MavlinkConfig,
+        metadata: &Metadata,
+        timebase: Timebase,
+    ) -> Result<Self> {
+        Ok(Self {
+            writer,
+            timebase,
+            accelerometer_scale: 32768.0 / metadata.accelerometer_range as f64,
+            gyroscope_scale: 32768.0 / metadata.gyroscope_range as f64,
+            attitude_rate_limit: RateLimit::new(config.attitude_rate)
+                .context("Invalid ATTITUDE rate.")?,
+            imu_rate_limit: RateLimit::new(config.imu_rate).context("Invalid IMU rate.")?,
+            attitude_tracker: AttitudeTracker::new(config.attitude_filter.take()),
+            config,
+            heartbeat: None,
+            sequence: 0,
+            frame: Vec::new(),
+            status: MavlinkStatus::default(),
+        })
+    }
+
+    pub fn status(&self) -> MavlinkStatus {
+        self.status
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    // Writes one message with {payload} as a frame
+    fn send(&mut self, (id, crc_extra): (u32, u8), payload: &[u8]) {
+        frame(
+            &mut self.frame,
+            self.sequence,
+            (self.config.system_id, self.config.component_id),
+            (id, crc_extra),
+            payload,
+        );
+        self.sequence = self.sequence.wrapping_add(1);
+        match self.writer.write_all(&self.frame) {
+            Ok(()) => self.status.sent += 1,
+            Err(_) => self.status.dropped += 1,
+        }
+    }
+}
+
+impl MavlinkSink<UdpWriter> {
+    /// Sends to {address}, e.g., "192.168.1.10:14550" for QGroundControl on that machine
+    pub fn udp<A: ToSocketAddrs>(
+        address: A,
+        config: MavlinkConfig,
+        metadata: &Metadata,
+        timebase: Timebase,
+    ) -> Result<Self> {
+        Self::new(UdpWriter::connect(address)?, config, metadata, timebase)
+    }
+}
+
+impl<W: Write> Sink for MavlinkSink<W> {
+    fn write(&mut self, samples: &[(SensorSample<Vec3D, f64>, Instant)]) -> Result<()> {
+        for (sample, instant) in samples {
+            let elapsed = self.timebase.elapsed(*instant);
+            let time_boot_ms = (elapsed.as_millis() as u32).to_le_bytes();
+
+            if self.heartbeat.is_none_or(|heartbeat| {
+                instant.saturating_duration_since(heartbeat) >= self.config.heartbeat
+            }) {
+                let mut payload = [0; 9];
+                // custom_mode (0), type, autopilot, base_mode (0), system_status, mavlink_version
+                payload[4..].copy_from_slice(&[
+                    self.config.vehicle_type,
+                    MAV_AUTOPILOT_GENERIC,
+                    0,
+                    MAV_STATE_ACTIVE,
+                    MAVLINK_VERSION,
+                ]);
+                self.send(HEARTBEAT, &payload);
+                self.heartbeat = Some(*instant);
+            }
+
+            // Kept up to date with every sample, if it is a filter
+            let attitude = self
+                .attitude_tracker
+                .update(sample, *instant, &self.timebase);
+            let angular_velocity = sample.angular_velocity();
+            if self.attitude_rate_limit.admit(*instant) {
+                let mut payload = Vec::with_capacity(28);
+                payload.extend_from_slice(&time_boot_ms);
+                let angles = [
+                    attitude.roll.to_radians(),
+                    attitude.pitch.to_radians(),
+                    attitude.yaw.unwrap_or(0.0).to_radians(),
+                    angular_velocity.x.to_radians(),
+                    angular_velocity.y.to_radians(),
+                    angular_velocity.z.to_radians(),
+                ];
+                for angle in angles {
+                    payload.extend_from_slice(&(angle as f32).to_le_bytes());
+                }
+                self.send(ATTITUDE, &payload);
+            }
+
+            if self.imu_rate_limit.admit(*instant) {
+                let acceleration = sample.acceleration();
+                // Magnetometer fields stay 0. A temperature of 0 means none, so 0 degree C is sent as 0.01
+                let temperature = sample.temperature().map_or(0, |temperature| {
+                    match saturate(temperature * 100.0) {
+                        0 => 1,
+                        centidegrees => centidegrees,
+                    }
+                });
+
+                let mut payload = Vec::with_capacity(29);
+                payload.extend_from_slice(&(elapsed.as_micros() as u64).to_le_bytes());
+                let counts = [
+                    acceleration.x * self.accelerometer_scale,
+                    acceleration.y * self.accelerometer_scale,
+                    acceleration.z * self.accelerometer_scale,
+                    angular_velocity.x * self.gyroscope_scale,
+                    angular_velocity.y * self.gyroscope_scale,
+                    angular_velocity.z * self.gyroscope_scale,
+                    0.0,
+                    0.0,
+                    0.0,
+                ];
+                for count in counts {
+                    payload.extend_from_slice(&saturate(count).to_le_bytes());
+                }
+                payload.push(0); // id of the IMU
+                payload.extend_from_slice(&temperature.to_le_bytes());
+                self.send(RAW_IMU, &payload);
+
+                let mut payload = Vec::with_capacity(24);
+                payload.extend_from_slice(&time_boot_ms);
+                let scaled = [
+                    acceleration.x * 1e3, // [mg]
+                    acceleration.y * 1e3,
+                    acceleration.z * 1e3,
+                    angular_velocity.x.to_radians() * 1e3, // [mrad/s]
+                    angular_velocity.y.to_radians() * 1e3,
+                    angular_velocity.z.to_radians() * 1e3,
+                    0.0,
+                    0.0,
+                    0.0,
+                ];
+                for value in scaled {
+                    payload.extend_from_slice(&saturate(value).to_le_bytes());
+                }
+                payload.extend_from_slice(&temperature.to_le_bytes());
+                self.send(SCALED_IMU, &payload);
+            }
+        }
+        Ok(())
+    }
+
+    // Like failed writes, a failed flush only loses messages
+    fn flush(&mut self) -> Result<()> {
+        let _ = self.writer.flush();
+        Ok(())
+    }
+}
+
+// Rounds to the nearest i16, clamping values out of range
+fn saturate(value: f64) -> i16 {
+    value.round().clamp(i16::MIN as f64, i16::MAX as f64) as i16
+}
+
+/// Writes each write as one UDP packet, so that every MAVLink message is a datagram of its own
+pub struct UdpWriter(UdpSocket);
+
+impl UdpWriter {
+    pub fn connect<A: ToSocketAddrs>(address: A) -> Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0").context("Unable to open UDP socket.")?;
+        socket
+            .connect(address)
+            .context("Unable to reach MAVLink destination.")?;
+        Ok(Self(socket))
+    }
+}
+
+impl Write for UdpWriter {
+    fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+        self.0.send(buffer)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// A MAVLink 2 frame of {payload} into {frame}, without trailing zeros in the payload, as MAVLink 2 wants it
+fn frame(
+    frame: &mut Vec<u8>,
+    sequence: u8,
+    (system_id, component_id): (u8, u8),
+    (id, crc_extra): (u32, u8),
+    payload: &[u8],
+) {
+    let length = payload
+        .iter()
+        .rposition(|&byte| byte != 0)
+        .map_or(1, |last| last + 1);
+    frame.clear();
+    frame.extend_from_slice(&[MAGIC, length as u8, 0, 0, sequence, system_id, component_id]);
+    frame.extend_from_slice(&id.to_le_bytes()[..3]);
+    frame.extend_from_slice(&payload[..length]);
+    let checksum = crc(crc(0xffff, &frame[1..]), &[crc_extra]);
+    frame.extend_from_slice(&checksum.to_le_bytes());
+}
+
+// CRC-16/MCRF4XX, as MAVLink uses it, continuing from {checksum}. Checksums start at 0xffff
+fn crc(mut checksum: u16, bytes: &[u8]) -> u16 {
+    for byte in bytes {
+        let mut temporary = byte ^ (checksum as u8);
+        temporary ^= temporary << 4;
+        let temporary = temporary as u16;
+        checksum = (checksum >> 8) ^ (temporary << 8) ^ (temporary << 3) ^ (temporary >> 4);
+    }
+    checksum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Message id and payload of each frame in {bytes}, checking the checksums
+    fn parse(mut bytes: &[u8]) -> Vec<(u32, Vec<u8>)> {
+        let crc_extra = |id| {
+            [HEARTBEAT, SCALED_IMU, RAW_IMU, ATTITUDE]
+                .into_iter()
+                .find(|(known, _)| *known == id)
+                .unwrap()
+                .1
+        };
+        let mut messages = Vec::new();
+        while !bytes.is_empty() {
+            assert_eq!(bytes[0], MAGIC);
+            let length = bytes[1] as usize;
+            let id = u32::from_le_bytes([bytes[7], bytes[8], bytes[9], 0]);
+            let end = 10 + length;
+            let checksum = u16::from_le_bytes([bytes[end], bytes[end + 1]]);
+            let expected = crc(crc(0xffff, &bytes[1..end]), &[crc_extra(id)]);
+            assert_eq!(checksum, expected);
+            messages.push((id, bytes[10..end].to_vec()));
+            bytes = &bytes[end + 2..];
+        }
+        messages
+    }
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(crc(0xffff, b"123456789"), 0x6f91);
+    }
+
+    #[test]
+    fn test_messages() {
+        let timebase = Timebase::now();
+        let metadata = Metadata::for_tests(&timebase);
+        let mut config = MavlinkConfig::new();
+        config.imu_rate = Some(100.0); // Every 10 ms
+        let mut sink = MavlinkSink::new(Vec::new(), config, &metadata, timebase).unwrap();
+        let sample = SensorSample::new(Vec3D::new(0, 0.5, 0.5), Vec3D::new(0, 0, 10), Some(0.0));
+        let samples = (0..20)
+            .map(|n| (sample, timebase.start + Duration::from_millis(n)))
+            .collect::<Vec<_>>();
+        sink.write(&samples).unwrap();
+
+        let messages = parse(sink.get_ref());
+        let count = |id| messages.iter().filter(|(known, _)| *known == id).count();
+        assert_eq!(count(HEARTBEAT.0), 1);
+        assert_eq!(count(ATTITUDE.0), 1); // 50 Hz
+        assert_eq!(count(RAW_IMU.0), 2);
+        assert_eq!(count(SCALED_IMU.0), 2);
+        assert_eq!(sink.get_ref()[4..6], [0, 1]); // Sequence, system
+        assert_eq!(sink.get_ref()[6], 200);
+
+        // Payloads come without trailing zeros, which receivers fill back in
+        let payload = |id, length| {
+            let (_, payload) = messages.iter().find(|(known, _)| *known == id).unwrap();
+            let mut payload = payload.clone();
+            payload.resize(length, 0);
+            payload
+        };
+        let attitude = payload(ATTITUDE.0, 28);
+        let float =
+            |start: usize| f32::from_le_bytes(attitude[start..start + 4].try_into().unwrap());
+        assert!((float(4).to_degrees() - 45.0).abs() < 1e-4); // Roll
+        assert!((float(24).to_degrees() - 10.0).abs() < 1e-4); // Yaw speed
+
+        let raw = payload(RAW_IMU.0, 29);
+        let y = i16::from_le_bytes([raw[10], raw[11]]);
+        assert_eq!(y, (0.5 * 16384.0) as i16);
+        assert_eq!(i16::from_le_bytes([raw[27], raw[28]]), 1); // 0 degree C, not missing
+        let scaled = payload(SCALED_IMU.0, 24);
+        assert_eq!(i16::from_le_bytes([scaled[6], scaled[7]]), 500); // [mg]
+        assert_eq!(sink.status().sent, messages.len());
+
+        // A link going down, e.g., the Wi-Fi, only drops messages
+        struct Unreachable;
+        impl Write for Unreachable {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::NetworkUnreachable.into())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Err(std::io::ErrorKind::NetworkUnreachable.into())
+            }
+        }
+        let mut sink =
+            MavlinkSink::new(Unreachable, MavlinkConfig::new(), &metadata, timebase).unwrap();
+        sink.write(&samples[..1]).unwrap();
+        sink.flush().unwrap();
+        assert_eq!(
+            sink.status(),
+            MavlinkStatus {
+                sent: 0,
+                dropped: 4
+            }
+        );
+    }
+}
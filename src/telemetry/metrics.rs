@@ -0,0 +1,191 @@
+use std::{fmt::Write, time::Duration};
+
+/// Upper bounds [s] of the buckets of `Metrics::loop_latency`, from well within one sampling period at 1 kHz to far beyond it
+pub const LATENCY_BUCKETS: [f64; 10] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1,
+];
+
+/// Counts of how often values fell into each bucket, for the distribution of, e.g., latencies, as Prometheus histograms have them
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    bounds: Vec<f64>, // Upper bounds, ascending. Above the last, values only count towards `count`
+    counts: Vec<u64>, // Per bucket, not cumulative
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    /// With buckets up to each of {bounds}, which need to be ascending
+    pub fn new(bounds: &[f64]) -> Self {
+        Self {
+            bounds: bounds.to_vec(),
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        if let Some(bucket) = self.bounds.iter().position(|&bound| value <= bound) {
+            self.counts[bucket] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    /// Observations up to {bound}, for one of the bounds given to `new`. None for any other bound
+    pub fn cumulative_count(&self, bound: f64) -> Option<u64> {
+        let bucket = self.bounds.iter().position(|&other| other == bound)?;
+        Some(self.counts[..=bucket].iter().sum())
+    }
+}
+
+/// What happened on the way to the samples so far, for monitoring with Prometheus, see `render` and `http::HttpServer`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metrics {
+    pub start_time: f64,            // [s] Unix time of the start of the recording
+    pub samples: u64,               // Read from the sensor
+    pub i2c_errors: u64,            // Failed reads of the sensor and its interrupt
+    pub interrupt_timeouts: u64,    // Waits for a sample that ran out of time
+    pub fifo_overflows: u64,        // As counted by `GY521::fifo_overflows`
+    pub loop_latency: Histogram,    // [s] From taking a sample until every sink has it
+    pub temperature: Option<f64>,   // [degree C] Of the latest sample, if the thermometer is active
+    pub measured_rate: Option<f64>, // [Hz] Of samples, over the latest full second
+}
+
+impl Metrics {
+    /// Counting from a recording that started at {start_time}
+    pub fn new(start_time: std::time::SystemTime) -> Self {
+        Self {
+            start_time: start_time
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+            samples: 0,
+            i2c_errors: 0,
+            interrupt_timeouts: 0,
+            fifo_overflows: 0,
+            loop_latency: Histogram::new(&LATENCY_BUCKETS),
+            temperature: None,
+            measured_rate: None,
+        }
+    }
+
+    pub fn observe_loop_latency(&mut self, latency: Duration) {
+        self.loop_latency.observe(latency.as_secs_f64());
+    }
+
+    /// All metrics in the Prometheus text exposition format, named "njord_*". Gauges without a value yet are left out
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: Option<f64>| {
+            if let Some(value) = value {
+                // Writing to a string doesn't fail
+                let _ = write!(
+                    text,
+                    "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"
+                );
+            }
+        };
+        metric(
+            "njord_start_time_seconds",
+            "gauge",
+            "Unix time of the start of the recording.",
+            Some(self.start_time),
+        );
+        metric(
+            "njord_samples_total",
+            "counter",
+            "Samples read from the sensor.",
+            Some(self.samples as f64),
+        );
+        metric(
+            "njord_i2c_errors_total",
+            "counter",
+            "Failed reads of the sensor and its interrupt.",
+            Some(self.i2c_errors as f64),
+        );
+        metric(
+            "njord_interrupt_timeouts_total",
+            "counter",
+            "Waits for a sample that ran out of time.",
+            Some(self.interrupt_timeouts as f64),
+        );
+        metric(
+            "njord_fifo_overflows_total",
+            "counter",
+            "FIFO buffer overflows reported by the sensor.",
+            Some(self.fifo_overflows as f64),
+        );
+        metric(
+            "njord_temperature_celsius",
+            "gauge",
+            "Temperature of the sensor.",
+            self.temperature,
+        );
+        metric(
+            "njord_sample_rate_hertz",
+            "gauge",
+            "Samples per second, over the latest full second.",
+            self.measured_rate,
+        );
+
+        let name = "njord_loop_latency_seconds";
+        let _ = write!(
+            text,
+            "# HELP {name} Time from taking a sample until every sink has it.\n# TYPE {name} histogram\n"
+        );
+        let histogram = &self.loop_latency;
+        let mut cumulative = 0;
+        for (bound, count) in histogram.bounds.iter().zip(&histogram.counts) {
+            cumulative += count;
+            let _ = writeln!(text, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        let _ = write!(
+            text,
+            "{name}_bucket{{le=\"+Inf\"}} {}\n{name}_sum {}\n{name}_count {}\n",
+            histogram.count, histogram.sum, histogram.count
+        );
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut metrics = Metrics::new(std::time::UNIX_EPOCH + Duration::from_secs(1647249013));
+        metrics.samples = 3;
+        metrics.i2c_errors = 1;
+        for latency in [50, 300, 300, 200_000] {
+            metrics.observe_loop_latency(Duration::from_micros(latency));
+        }
+        assert_eq!(metrics.loop_latency.cumulative_count(0.0005), Some(3));
+        assert_eq!(metrics.loop_latency.cumulative_count(0.0003), None);
+
+        let text = metrics.render();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert!(lines.contains(&"njord_start_time_seconds 1647249013"));
+        assert!(lines.contains(&"# TYPE njord_samples_total counter"));
+        assert!(lines.contains(&"njord_samples_total 3"));
+        assert!(lines.contains(&"njord_i2c_errors_total 1"));
+        // Without a thermometer reading yet
+        assert!(!text.contains("njord_temperature_celsius"));
+        assert!(lines.contains(&"njord_loop_latency_seconds_bucket{le=\"0.0001\"} 1"));
+        assert!(lines.contains(&"njord_loop_latency_seconds_bucket{le=\"0.00025\"} 1"));
+        assert!(lines.contains(&"njord_loop_latency_seconds_bucket{le=\"0.1\"} 3"));
+        assert!(lines.contains(&"njord_loop_latency_seconds_bucket{le=\"+Inf\"} 4"));
+        assert!(lines.contains(&"njord_loop_latency_seconds_count 4"));
+        assert!(text.ends_with('\n'));
+    }
+}
@@ -0,0 +1,504 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use rumqttc::{Client, Connection, Event, MqttOptions, Outgoing, Packet};
+
+pub use rumqttc::QoS;
+
+use super::{control::CommandSender, Attitude, AttitudeTracker, Encoding, Message, RateLimit};
+use crate::{
+    fusion::AttitudeFilter,
+    gy521::SensorSample,
+    logging::{Sink, Timebase},
+    math::Vec3D,
+    utilites::Backoff,
+};
+
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(1); // For sending what is queued when dropped
+const HEEL_HYSTERESIS: f64 = 2.0; // [degree] Below the limit, before a heel alarm clears, so that waves don't toggle it
+
+/// A topic `MqttSink` publishes to, and how
+#[derive(Debug, Clone)]
+pub struct Topic {
+    pub name: String,
+    pub qos: QoS,
+    pub retain: bool, // Whether the broker keeps the latest message for clients subscribing later
+    pub rate: Option<f64>, // [Hz] Samples that come faster are skipped. None publishes every sample. Not used for alarms
+}
+
+impl Topic {
+    /// At most once, not retained
+    pub fn new(name: &str, rate: Option<f64>) -> Self {
+        Self {
+            name: name.to_string(),
+            qos: QoS::AtMostOnce,
+            retain: false,
+            rate,
+        }
+    }
+}
+
+/// Where and what `MqttSink` publishes
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String, // Needs to be unique on the broker
+    pub credentials: Option<(String, String)>, // User name and password
+    pub encoding: Encoding, // Of samples
+    pub samples: Option<Topic>, // Every sample, as `telemetry::Message`. None doesn't publish them
+    pub decimated: Option<Topic>, // The same at a lower rate, for clients that don't need every sample
+    pub attitude: Option<Topic>,  // Roll, pitch, and yaw, as JSON `Attitude`
+    pub alarms: Option<Topic>,    // As JSON `Alarm`
+    pub attitude_filter: Option<Box<dyn AttitudeFilter + Send>>, // Estimates the attitude from every sample. None takes roll and pitch from `SensorSample::tilt`, without yaw
+    pub heel_alarm: Option<f64>, // [degree] Roll beyond which an alarm is raised. It clears once the roll is back below the limit
+    pub commands: Option<(String, CommandSender)>, // Topic to take `control::Command`s from, e.g., "njord/commands", and where they go. Each is answered on "{topic}/ack". None doesn't subscribe
+    pub queue_capacity: usize, // Messages held while the broker is unreachable. Newer ones are dropped beyond that
+    pub keep_alive: Duration,
+    pub backoff: Backoff, // Between attempts at reconnecting
+}
+
+impl MqttConfig {
+    /// Publishes decimated samples at 1 Hz to "njord/samples", the attitude at 10 Hz to "njord/attitude", and alarms at least once to "njord/alarms"
+    pub fn new(host: &str, port: u16) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            client_id: "njord".to_string(),
+            credentials: None,
+            encoding: Encoding::Json,
+            samples: None,
+            decimated: Some(Topic::new("njord/samples", Some(1.0))),
+            attitude: Some(Topic::new("njord/attitude", Some(10.0))),
+            alarms: Some(Topic {
+                qos: QoS::AtLeastOnce,
+                ..Topic::new("njord/alarms", None)
+            }),
+            attitude_filter: None,
+            heel_alarm: None,
+            commands: None,
+            queue_capacity: 1000,
+            keep_alive: Duration::from_secs(30),
+            backoff: Backoff::Exponential {
+                initial: Duration::from_secs(1),
+                factor: 2.0,
+                max: Duration::from_secs(60),
+            },
+        }
+    }
+}
+
+/// Message on the alarm topic
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Alarm {
+    pub time: f64,    // [s] Since the start of the recording
+    pub name: String, // E.g., "heel"
+    pub active: bool, // False once the alarm clears
+    pub message: String,
+}
+
+/// How publishing has gone so far
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MqttStatus {
+    pub connected: bool,
+    pub connections: usize,         // Including reconnections
+    pub published: usize,           // Messages handed to the connection
+    pub dropped: usize, // Messages lost to a full queue, e.g., while the broker was unreachable
+    pub last_error: Option<String>, // Of the connection
+}
+
+/// Publishes samples, the attitude, and alarms to an MQTT broker, e.g., for home automation or a boat's data network.
+/// The connection is kept on a background thread, which reconnects with {backoff} whenever it is lost, so losing the broker never holds up sampling or fails `write`. Messages queue up meanwhile, and are dropped once the queue is full. See `status`.
+/// Dropping publishes what is queued and disconnects, unless the broker is unreachable by then
+pub struct MqttSink {
+    client: Client,
+    timebase: Timebase,
+    encoding: Encoding,
+    samples: Option<(Topic, RateLimit)>,
+    decimated: Option<(Topic, RateLimit)>,
+    attitude: Option<(Topic, RateLimit)>,
+    alarms: Option<Topic>,
+    attitude_tracker: AttitudeTracker,
+    heel_alarm: Option<f64>,
+    heeling: bool, // Whether the heel alarm is active
+    status: Arc<Mutex<MqttStatus>>,
+    running: Arc<AtomicBool>,
+    connection: Option<JoinHandle<()>>,
+}
+
+impl MqttSink {
+    pub fn new(config: MqttConfig, timebase: Timebase) -> Result<Self> {
+        let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+        options.set_keep_alive(config.keep_alive);
+        if let Some((user_name, password)) = &config.credentials {
+            options.set_credentials(user_name, password);
+        }
+        let (client, connection) = Client::new(options, config.queue_capacity.max(1));
+
+        let with_rate_limit = |topic: Option<Topic>| -> Result<_> {
+            topic
+                .map(|topic| {
+                    let rate_limit = RateLimit::new(topic.rate)
+                        .with_context(|| format!("Invalid rate for MQTT topic {}.", topic.name))?;
+                    Ok((topic, rate_limit))
+                })
+                .transpose()
+        };
+        let samples = with_rate_limit(config.samples)?;
+        let decimated = with_rate_limit(config.decimated)?;
+        let attitude = with_rate_limit(config.attitude)?;
+
+        let status = Arc::new(Mutex::new(MqttStatus::default()));
+        let running = Arc::new(AtomicBool::new(true));
+        let thread = {
+            let (client, status, running) = (client.clone(), status.clone(), running.clone());
+            let (commands, backoff) = (config.commands, config.backoff);
+            std::thread::Builder::new()
+                .name("mqtt".to_string())
+                .spawn(move || {
+                    keep_connected(connection, client, commands, status, running, backoff)
+                })
+                .context("Unable to start MQTT connection.")?
+        };
+
+        Ok(Self {
+            client,
+            timebase,
+            encoding: config.encoding,
+            samples,
+            decimated,
+            attitude,
+            alarms: config.alarms,
+            attitude_tracker: AttitudeTracker::new(config.attitude_filter),
+            heel_alarm: config.heel_alarm,
+            heeling: false,
+            status,
+            running,
+            connection: Some(thread),
+        })
+    }
+
+    pub fn status(&self) -> MqttStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Publishes an active alarm right away, e.g., for sensor errors. Does nothing without an alarm topic
+    pub fn alarm(&self, name: &str, message: &str, instant: Instant) -> Result<()> {
+        let alarm = Alarm {
+            time: self.timebase.elapsed(instant).as_secs_f64(),
+            name: name.to_string(),
+            active: true,
+            message: message.to_string(),
+        };
+        self.publish_alarm(&alarm)
+    }
+
+    fn publish_alarm(&self, alarm: &Alarm) -> Result<()> {
+        if let Some(topic) = &self.alarms {
+            publish(
+                &self.client,
+                &self.status,
+                topic,
+                serde_json::to_vec(alarm)?,
+            );
+        }
+        Ok(())
+    }
+
+    // Raises or clears the heel alarm
+    fn check_heel(&mut self, attitude: &Attitude) -> Result<()> {
+        let Some(limit) = self.heel_alarm else {
+            return Ok(());
+        };
+        let heel = attitude.roll.abs();
+        let message = if !self.heeling && heel > limit {
+            format!("Heel of {heel:.1} degrees exceeds {limit} degrees.")
+        } else if self.heeling && heel < limit - HEEL_HYSTERESIS {
+            format!("Heel is back to {heel:.1} degrees.")
+        } else {
+            return Ok(());
+        };
+        self.heeling = !self.heeling;
+        self.publish_alarm(&Alarm {
+            time: attitude.time,
+            name: "heel".to_string(),
+            active: self.heeling,
+            message,
+        })
+    }
+}
+
+// Hands {payload} to the connection without waiting, counting it as dropped if the queue is full
+fn publish(client: &Client, status: &Mutex<MqttStatus>, topic: &Topic, payload: Vec<u8>) {
+    let result = client.try_publish(topic.name.clone(), topic.qos, topic.retain, payload);
+    let mut status = status.lock().unwrap();
+    match result {
+        Ok(()) => status.published += 1,
+        Err(_) => status.dropped += 1,
+    }
+}
+
+// Drives the connection until the sink disconnects, reconnecting after errors, and passes on {commands}
+fn keep_connected(
+    mut connection: Connection,
+    client: Client,
+    commands: Option<(String, CommandSender)>,
+    status: Arc<Mutex<MqttStatus>>,
+    running: Arc<AtomicBool>,
+    backoff: Backoff,
+) {
+    let acknowledgements = commands.as_ref().map(|(topic, _)| Topic {
+        qos: QoS::AtLeastOnce,
+        ..Topic::new(&format!("{topic}/ack"), None)
+    });
+    let mut failures = 0;
+    while let Ok(event) = connection.recv() {
+        match event {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                {
+                    let mut status = status.lock().unwrap();
+                    status.connected = true;
+                    status.connections += 1;
+                }
+                failures = 0;
+                // Subscriptions don't outlast the session, so every connection subscribes again
+                if let Some((topic, _)) = &commands {
+                    let _ = client.try_subscribe(topic, QoS::AtLeastOnce);
+                }
+            }
+            Ok(Event::Incoming(Packet::Publish(message))) => {
+                if let (Some((topic, commands)), Some(acknowledgements)) =
+                    (&commands, &acknowledgements)
+                {
+                    if message.topic == *topic {
+                        let acknowledgement = commands.submit(&message.payload);
+                        if let Ok(payload) = serde_json::to_vec(&acknowledgement) {
+                            publish(&client, &status, acknowledgements, payload);
+                        }
+                    }
+                }
+            }
+            Ok(Event::Outgoing(Outgoing::Disconnect)) => {
+                // Events run ahead of the network, so the connection is driven on until the broker hangs up, for what is queued to go out
+                let deadline = Instant::now() + DISCONNECT_TIMEOUT;
+                while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+                    if !matches!(connection.recv_timeout(remaining), Ok(Ok(_))) {
+                        break;
+                    }
+                }
+                break;
+            }
+            Ok(_) => {}
+            Err(error) => {
+                {
+                    let mut status = status.lock().unwrap();
+                    status.connected = false;
+                    status.last_error = Some(error.to_string());
+                }
+                failures += 1;
+                // In steps, to not hold up dropping the sink
+                let retry = Instant::now() + backoff.delay(failures);
+                while running.load(Ordering::Relaxed) && Instant::now() < retry {
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+                if !running.load(Ordering::Relaxed) {
+                    break;
+                }
+            }
+        }
+    }
+    status.lock().unwrap().connected = false;
+}
+
+impl Sink for MqttSink {
+    fn write(&mut self, samples: &[(SensorSample<Vec3D, f64>, Instant)]) -> Result<()> {
+        for (sample, instant) in samples {
+            let due = [&mut self.samples, &mut self.decimated]
+                .into_iter()
+                .flatten()
+                .filter_map(|(topic, rate_limit)| rate_limit.admit(*instant).then_some(&*topic))
+                .collect::<Vec<_>>();
+            if !due.is_empty() {
+                // Encoded once for both topics
+                let message = Message::new(sample, self.timebase.elapsed(*instant));
+                let payload = self.encoding.encode(&message)?;
+                for topic in due {
+                    publish(&self.client, &self.status, topic, payload.clone());
+                }
+            }
+
+            if self.attitude.is_some() || self.heel_alarm.is_some() {
+                let attitude = self
+                    .attitude_tracker
+                    .update(sample, *instant, &self.timebase);
+                if let Some((topic, rate_limit)) = &mut self.attitude {
+                    if rate_limit.admit(*instant) {
+                        let payload = serde_json::to_vec(&attitude)?;
+                        publish(&self.client, &self.status, topic, payload);
+                    }
+                }
+                self.check_heel(&attitude)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Messages go out as they are written
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for MqttSink {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        // Queued after the messages, so those go out first
+        let _ = self.client.try_disconnect();
+        if let Some(connection) = self.connection.take() {
+            let _ = connection.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+    };
+
+    // Reads one MQTT packet: type, flags, and everything after the fixed header
+    fn read_packet(stream: &mut impl Read) -> Option<(u8, Vec<u8>)> {
+        let mut header = [0];
+        stream.read_exact(&mut header).ok()?;
+        let (mut length, mut shift) = (0, 0);
+        loop {
+            let mut byte = [0];
+            stream.read_exact(&mut byte).ok()?;
+            length |= ((byte[0] & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).ok()?;
+        Some((header[0], body))
+    }
+
+    // Just enough of a broker to take a connection and collect what is published, until the client disconnects. Subscribing gets a shutdown command
+    fn broker(listener: TcpListener) -> Vec<(String, Vec<u8>)> {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut published = Vec::new();
+        while let Some((header, body)) = read_packet(&mut stream) {
+            match header >> 4 {
+                1 => stream.write_all(&[0x20, 2, 0, 0]).unwrap(), // CONNECT, accepted
+                3 => {
+                    let length = u16::from_be_bytes([body[0], body[1]]) as usize;
+                    let topic = String::from_utf8(body[2..2 + length].to_vec()).unwrap();
+                    let mut payload = &body[2 + length..];
+                    if (header >> 1) & 3 > 0 {
+                        // Acknowledge the packet id
+                        stream
+                            .write_all(&[0x40, 2, payload[0], payload[1]])
+                            .unwrap();
+                        payload = &payload[2..];
+                    }
+                    published.push((topic, payload.to_vec()));
+                }
+                8 => {
+                    // SUBSCRIBE, granted at least once
+                    stream.write_all(&[0x90, 3, body[0], body[1], 1]).unwrap();
+                    let length = u16::from_be_bytes([body[2], body[3]]) as usize;
+                    let topic = &body[2..4 + length];
+                    let payload = br#"{"command": "shutdown"}"#;
+                    stream
+                        .write_all(&[0x30, (topic.len() + payload.len()) as u8])
+                        .unwrap();
+                    stream.write_all(topic).unwrap();
+                    stream.write_all(payload).unwrap();
+                }
+                14 => break, // DISCONNECT
+                _ => {}
+            }
+        }
+        published
+    }
+
+    #[test]
+    fn test_publishing() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let broker = std::thread::spawn(move || broker(listener));
+
+        let mut config = MqttConfig::new("127.0.0.1", port);
+        config.samples = Some(Topic::new("test/samples", None));
+        config.decimated = Some(Topic::new("test/decimated", Some(200.0))); // Every 5 ms
+        config.attitude = Some(Topic::new("test/attitude", Some(100.0)));
+        config.alarms = Some(Topic::new("test/alarms", None));
+        config.heel_alarm = Some(30.0);
+        let (commands, received_commands) = crate::telemetry::control::channel();
+        config.commands = Some(("test/commands".to_string(), commands));
+        let timebase = Timebase::now();
+        let mut sink = MqttSink::new(config, timebase).unwrap();
+        assert_eq!(
+            received_commands
+                .recv_timeout(Duration::from_secs(5))
+                .unwrap(),
+            crate::telemetry::control::Command::Shutdown
+        );
+        // The acknowledgement
+        let start = Instant::now();
+        while sink.status().published == 0 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let level = SensorSample::new(Vec3D::new(0, 0, 1), Vec3D::default(), None);
+        let heeled = SensorSample::new(Vec3D::new(0, 0.5, 0.5), Vec3D::default(), None);
+        let samples = (0..11)
+            .map(|n| {
+                let sample = if n < 10 { level } else { heeled };
+                (sample, timebase.start + Duration::from_millis(n))
+            })
+            .collect::<Vec<_>>();
+        sink.write(&samples).unwrap();
+        drop(sink);
+
+        let published = broker.join().unwrap();
+        let on = |name: &str| {
+            published
+                .iter()
+                .filter(|(topic, _)| topic == name)
+                .map(|(_, payload)| payload.as_slice())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(on("test/samples").len(), 11);
+        let decimated = on("test/decimated")
+            .into_iter()
+            .map(|payload| Encoding::Json.decode(payload).unwrap().time)
+            .collect::<Vec<_>>();
+        assert_eq!(decimated, [0.0, 0.005, 0.01]);
+        let attitude = on("test/attitude");
+        assert_eq!(attitude.len(), 2);
+        let attitude: Attitude = serde_json::from_slice(attitude[1]).unwrap();
+        assert!((attitude.roll - 45.0).abs() < 1e-9);
+        assert_eq!(attitude.yaw, None);
+        let alarms = on("test/alarms");
+        assert_eq!(alarms.len(), 1);
+        let alarm: Alarm = serde_json::from_slice(alarms[0]).unwrap();
+        assert!(alarm.active && alarm.name == "heel");
+        let acknowledgements = on("test/commands/ack");
+        assert_eq!(acknowledgements.len(), 1);
+        let acknowledgement: crate::telemetry::control::Acknowledgement =
+            serde_json::from_slice(acknowledgements[0]).unwrap();
+        assert!(acknowledgement.accepted);
+    }
+}
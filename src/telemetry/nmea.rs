@@ -0,0 +1,255 @@
+use std::{fmt::Write as _, io::Write, sync::mpsc::Receiver, time::Instant};
+
+use anyhow::{ensure, Context, Result};
+
+use super::{AttitudeTracker, RateLimit};
+use crate::{
+    fusion::{self, AttitudeFilter},
+    gy521::SensorSample,
+    logging::{Sink, Timebase},
+    math::{angle, Vec3D},
+};
+
+/// What `NmeaSink` sends, and how often
+pub struct NmeaConfig {
+    pub talker: String, // Two characters naming the source in every sentence, e.g., "II" for integrated instrumentation, or "HE" for a gyro compass
+    pub rate: Option<f64>, // [Hz] Of the sentences. None sends them for every sample, which a serial port at 4800 baud can't keep up with
+    pub declination: Option<f64>, // [degree] From true to magnetic north, positive towards east. Given, HDT (true heading) is sent besides HDM
+    pub attitude_filter: Option<Box<dyn AttitudeFilter + Send>>, // For roll and pitch. None takes them from `SensorSample::tilt` of each sample
+    pub magnetometer: Option<Receiver<Vec3D>>, // Calibrated readings in the sensor frame, e.g., from a `sensors::magnetometer::Magnetometer` on another thread. Needed for the heading. None sends only XDR and ROT
+}
+
+impl NmeaConfig {
+    /// Sends as "II" once a second
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Default for NmeaConfig {
+    fn default() -> Self {
+        Self {
+            talker: "II".to_string(),
+            rate: Some(1.0),
+            declination: None,
+            attitude_filter: None,
+            magnetometer: None,
+        }
+    }
+}
+
+/// How sending has gone so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NmeaStatus {
+    pub sent: usize,    // Sentences
+    pub dropped: usize, // Sentences that couldn't be written, e.g., with the serial adapter unplugged
+}
+
+/// Sends NMEA 0183 sentences, so that chart plotters and navigation software like OpenCPN show the heading and attitude, e.g., over a serial port or a `tcp::TcpBroadcaster`:
+/// - HDM, the heading [degree] clockwise from magnetic north, from the latest magnetometer reading, tilt compensated with the sample's acceleration. Only with a magnetometer: The yaw of the gyroscope alone is relative to where the sensor pointed at the start, and drifts, which navigation equipment would take for a compass heading.
+/// - HDT, the same from true north, if the declination is known.
+/// - XDR, pitch (PTCH) and roll (ROLL) [degree], as in `telemetry::Attitude`.
+/// - ROT, the rate of turn [degree/min] about the sensor's z axis, negative when turning to port.
+///
+/// Every sentence is written to {writer} in one go. Sentences that can't be written are dropped and counted in `status`, rather than failing the write
+pub struct NmeaSink<W: Write> {
+    writer: W,
+    timebase: Timebase,
+    talker: String,
+    declination: Option<f64>,
+    magnetometer: Option<Receiver<Vec3D>>,
+    magnetic_field: Option<Vec3D>, // Latest reading
+    rate_limit: RateLimit,
+    attitude_tracker: AttitudeTracker,
+    status: NmeaStatus,
+}
+
+impl<W: Write> NmeaSink<W> {
+    pub fn new(writer: W, config: NmeaConfig, timebase: Timebase) -> Result<Self> {
+        ensure!(
+            config.talker.len() == 2
+                && config
+                    .talker
+                    .bytes()
+                    .all(|byte| byte.is_ascii_uppercase() || byte.is_ascii_digit()),
+            "NMEA talker ID needs to be two capital letters or digits, not \"{}\".",
+            config.talker
+        );
+        Ok(Self {
+            writer,
+            timebase,
+            talker: config.talker,
+            declination: config.declination,
+            magnetometer: config.magnetometer,
+            magnetic_field: None,
+            rate_limit: RateLimit::new(config.rate).context("Invalid NMEA rate.")?,
+            attitude_tracker: AttitudeTracker::new(config.attitude_filter),
+            status: NmeaStatus::default(),
+        })
+    }
+
+    pub fn status(&self) -> NmeaStatus {
+        self.status
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn send(&mut self, body: &str) {
+        match self
+            .writer
+            .write_all(sentence(&self.talker, body).as_bytes())
+        {
+            Ok(()) => self.status.sent += 1,
+            Err(_) => self.status.dropped += 1,
+        }
+    }
+}
+
+impl<W: Write> Sink for NmeaSink<W> {
+    fn write(&mut self, samples: &[(SensorSample<Vec3D, f64>, Instant)]) -> Result<()> {
+        if let Some(magnetometer) = &self.magnetometer {
+            if let Some(field) = magnetometer.try_iter().last() {
+                self.magnetic_field = Some(field);
+            }
+        }
+        for (sample, instant) in samples {
+            // Kept up to date with every sample, if it is a filter
+            let attitude = self
+                .attitude_tracker
+                .update(sample, *instant, &self.timebase);
+            if !self.rate_limit.admit(*instant) {
+                continue;
+            }
+
+            if let Some(field) = self.magnetic_field {
+                let heading = fusion::tilt_compensated_heading(&field, &sample.acceleration(), 0.0)
+                    .to_degrees();
+                self.send(&format!("HDM,{:.1},M", round_heading(heading)));
+                if let Some(declination) = self.declination {
+                    let heading = round_heading(heading + declination);
+                    self.send(&format!("HDT,{heading:.1},T"));
+                }
+            }
+            self.send(&format!(
+                "XDR,A,{:.1},D,PTCH,A,{:.1},D,ROLL",
+                attitude.pitch, attitude.roll
+            ));
+            let rate_of_turn = -sample.angular_velocity().z * 60.0;
+            self.send(&format!("ROT,{rate_of_turn:.1},A"));
+        }
+        Ok(())
+    }
+
+    // Like failed writes, a failed flush only loses sentences
+    fn flush(&mut self) -> Result<()> {
+        let _ = self.writer.flush();
+        Ok(())
+    }
+}
+
+// {heading} [degree] to the tenth of a degree sent, in [0, 360), so that, e.g., 359.96 is sent as 0.0 rather than 360.0. Adding 0 turns -0 into 0
+fn round_heading(heading: f64) -> f64 {
+    angle::wrap_to_360((angle::wrap_to_360(heading) * 10.0).round() / 10.0) + 0.0
+}
+
+/// A complete sentence from {talker} and {body}, e.g., "HDM,274.5,M", with checksum and line ending: "$IIHDM,274.5,M*26\r\n"
+pub fn sentence(talker: &str, body: &str) -> String {
+    let checksum = talker
+        .bytes()
+        .chain(body.bytes())
+        .fold(0, |sum, byte| sum ^ byte);
+    let mut sentence = String::with_capacity(body.len() + 11);
+    // Writing to a string doesn't fail
+    let _ = write!(sentence, "${talker}{body}*{checksum:02X}\r\n");
+    sentence
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fusion::Madgwick;
+    use std::time::Duration;
+
+    #[test]
+    fn test_sentences() {
+        assert_eq!(
+            sentence(
+                "GP",
+                "GGA,092750.000,5321.6802,N,00630.3372,W,1,8,1.03,61.7,M,55.2,M,,"
+            ),
+            "$GPGGA,092750.000,5321.6802,N,00630.3372,W,1,8,1.03,61.7,M,55.2,M,,*76\r\n"
+        );
+
+        let timebase = Timebase::now();
+        let mut config = NmeaConfig::new();
+        config.talker = "HE".to_string();
+        config.rate = Some(100.0);
+        config.declination = Some(2.5);
+        config.attitude_filter = Some(Box::new(Madgwick::new(0.1, 0.01)));
+        let mut sink = NmeaSink::new(Vec::new(), config, timebase).unwrap();
+        // Turning to starboard at 6 degree/s
+        let sample = SensorSample::new(Vec3D::new(0, 0, 1), Vec3D::new(0, 0, -6), None);
+        let samples = (0..=100)
+            .map(|n| (sample, timebase.start + Duration::from_millis(n * 10)))
+            .collect::<Vec<_>>();
+        sink.write(&samples).unwrap();
+
+        // Without a magnetometer, the yaw of the filter isn't sent as heading
+        let text = String::from_utf8(sink.into_inner()).unwrap();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2 * 101);
+        assert!(lines.iter().all(|line| line.starts_with("$HE")));
+        let field = |line: &str, index| -> f64 {
+            line.split(['*', ',']).nth(index).unwrap().parse().unwrap()
+        };
+        assert_eq!(
+            lines[lines.len() - 2],
+            sentence("HE", "XDR,A,0.0,D,PTCH,A,0.0,D,ROLL").trim_end()
+        );
+        assert_eq!(field(lines[lines.len() - 1], 1), 360.0);
+
+        let (magnetometer, readings) = std::sync::mpsc::channel();
+        let mut config = NmeaConfig::new();
+        config.rate = None;
+        config.declination = Some(2.5);
+        config.magnetometer = Some(readings);
+        let mut sink = NmeaSink::new(Vec::new(), config, timebase).unwrap();
+        // No reading yet
+        sink.write(&samples[..1]).unwrap();
+        // Pointing east, level: North is to port, and the field points down into the northern hemisphere
+        magnetometer.send(Vec3D::new(0.0, 0.2, -0.4)).unwrap();
+        sink.write(&samples[1..2]).unwrap();
+        let text = String::from_utf8(sink.into_inner()).unwrap();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2 + 4);
+        assert_eq!(lines[2], sentence("II", "HDM,90.0,M").trim_end());
+        assert_eq!(lines[3], sentence("II", "HDT,92.5,T").trim_end());
+
+        let mut config = NmeaConfig::new();
+        config.talker = "ii".to_string();
+        assert!(NmeaSink::new(Vec::new(), config, timebase).is_err());
+
+        assert_eq!(format!("{:.1}", round_heading(359.96)), "0.0");
+        assert_eq!(format!("{:.1}", round_heading(-0.0)), "0.0");
+        assert_eq!(format!("{:.1}", round_heading(-90.04)), "270.0");
+
+        // An unplugged serial adapter only drops sentences
+        let serial = std::fs::File::open("/dev/null").unwrap(); // Read only
+        let mut sink = NmeaSink::new(serial, NmeaConfig::new(), timebase).unwrap();
+        sink.write(&samples[..1]).unwrap();
+        sink.flush().unwrap();
+        assert_eq!(
+            sink.status(),
+            NmeaStatus {
+                sent: 0,
+                dropped: 2
+            }
+        );
+    }
+}
@@ -0,0 +1,354 @@
+use std::{
+    net::TcpStream,
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use tungstenite::{
+    client::IntoClientRequest, handshake::client::Request, stream::MaybeTlsStream, Message,
+    WebSocket,
+};
+
+use super::{Attitude, AttitudeTracker, RateLimit};
+use crate::{
+    clock,
+    fusion::AttitudeFilter,
+    gy521::SensorSample,
+    logging::{Sink, Timebase},
+    math::{angle, Vec3D},
+    utilites::{self, Backoff, MemoryConsumer, MemoryProducer, OverflowPolicy},
+};
+
+/// Where and how `SignalKSink` sends deltas
+pub struct SignalKConfig {
+    pub url: String, // Streaming endpoint of the server, e.g., "ws://raspberrypi.local:3000/signalk/v1/stream?subscribe=none"
+    pub token: Option<String>, // Access token of a device allowed to write, sent as "Authorization: Bearer {token}"
+    pub source: String,        // Label of the source in every update
+    pub rate: Option<f64>,     // [Hz] Of the deltas. None sends one for every sample
+    pub attitude_filter: Option<Box<dyn AttitudeFilter + Send>>, // Needed for yaw and heading. None sends the attitude with roll and pitch from `SensorSample::tilt` only
+    pub queue_capacity: usize, // Deltas held while the server is unreachable. The oldest ones are dropped beyond that
+    pub backoff: Backoff,      // Between attempts at reconnecting
+}
+
+impl SignalKConfig {
+    /// Sends as "njord" once a second, reconnecting every 1 s to 60 s
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            token: None,
+            source: "njord".to_string(),
+            rate: Some(1.0),
+            attitude_filter: None,
+            queue_capacity: 100,
+            backoff: Backoff::Exponential {
+                initial: Duration::from_secs(1),
+                factor: 2.0,
+                max: Duration::from_secs(60),
+            },
+        }
+    }
+}
+
+/// How sending has gone so far
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SignalKStatus {
+    pub connected: bool,
+    pub connections: usize,         // Including reconnections
+    pub sent: usize,                // Deltas
+    pub dropped: usize,             // Deltas lost to a full queue or to the connection
+    pub last_error: Option<String>, // Of the connection
+}
+
+/// Provides the attitude to a Signal K server as deltas over WebSocket, so that it shows up in the apps and instruments of the boat's data network:
+/// - navigation.attitude, roll, pitch, and yaw [rad], as in `telemetry::Attitude`.
+/// - navigation.headingMagnetic [rad], clockwise from magnetic north, from the yaw of the attitude filter. Without a magnetometer, this is relative to where the sensor pointed at the start, and drifts.
+/// - navigation.rateOfTurn [rad/s] about the sensor's z axis, positive when turning to starboard.
+///
+/// Deltas go out on a background thread, which reconnects with {backoff} whenever the connection is lost, so losing the server never holds up sampling or fails `write`. See `status`.
+/// Dropping sends what is queued, unless the server is unreachable by then
+pub struct SignalKSink {
+    timebase: Timebase,
+    source: String,
+    rate_limit: RateLimit,
+    attitude_tracker: AttitudeTracker,
+    producer: Option<MemoryProducer<String>>, // None once dropped, to let the sender finish
+    status: Arc<Mutex<SignalKStatus>>,
+    sender: Option<JoinHandle<()>>,
+}
+
+impl SignalKSink {
+    pub fn new(config: SignalKConfig, timebase: Timebase) -> Result<Self> {
+        // Checked here, to fail early on a bad URL or token
+        request(&config.url, config.token.as_deref())?;
+
+        let (producer, consumer) =
+            utilites::shared_memory(config.queue_capacity.max(1), OverflowPolicy::Overwrite);
+        let status = Arc::new(Mutex::new(SignalKStatus::default()));
+        let sender = {
+            let (status, backoff) = (status.clone(), config.backoff);
+            let (url, token) = (config.url, config.token);
+            std::thread::Builder::new()
+                .name("signalk".to_string())
+                .spawn(move || send(&url, token.as_deref(), consumer, status, backoff))
+                .context("Unable to start Signal K sender.")?
+        };
+        Ok(Self {
+            timebase,
+            source: config.source,
+            rate_limit: RateLimit::new(config.rate).context("Invalid Signal K rate.")?,
+            attitude_tracker: AttitudeTracker::new(config.attitude_filter),
+            producer: Some(producer),
+            status,
+            sender: Some(sender),
+        })
+    }
+
+    pub fn status(&self) -> SignalKStatus {
+        self.status.lock().unwrap().clone()
+    }
+}
+
+impl Sink for SignalKSink {
+    fn write(&mut self, samples: &[(SensorSample<Vec3D, f64>, Instant)]) -> Result<()> {
+        for (sample, instant) in samples {
+            // Kept up to date with every sample, if it is a filter
+            let attitude = self
+                .attitude_tracker
+                .update(sample, *instant, &self.timebase);
+            if !self.rate_limit.admit(*instant) {
+                continue;
+            }
+            let rate_of_turn = -sample.angular_velocity().z.to_radians();
+            let delta = delta(
+                &self.source,
+                &attitude,
+                rate_of_turn,
+                self.timebase.system_time(*instant),
+            );
+            self.producer
+                .as_ref()
+                .context("Signal K sink is shut down.")?
+                .push(delta)
+                .context("Signal K sender stopped.")?;
+        }
+        Ok(())
+    }
+
+    // Deltas go out as they are written
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SignalKSink {
+    fn drop(&mut self) {
+        self.producer = None;
+        if let Some(sender) = self.sender.take() {
+            let _ = sender.join();
+        }
+    }
+}
+
+/// A delta for "vessels.self" with the values of one sample
+pub fn delta(source: &str, attitude: &Attitude, rate_of_turn: f64, time: SystemTime) -> String {
+    let mut attitude_value = serde_json::json!({
+        "roll": attitude.roll.to_radians(),
+        "pitch": attitude.pitch.to_radians(),
+    });
+    let mut values = Vec::with_capacity(3);
+    if let Some(yaw) = attitude.yaw {
+        attitude_value["yaw"] = yaw.to_radians().into();
+        // Yaw turns counterclockwise about the z axis pointing up, while headings go clockwise
+        let heading = angle::wrap_to_360(-yaw).to_radians();
+        values.push(serde_json::json!({ "path": "navigation.headingMagnetic", "value": heading }));
+    }
+    values.insert(
+        0,
+        serde_json::json!({ "path": "navigation.attitude", "value": attitude_value }),
+    );
+    values.push(serde_json::json!({ "path": "navigation.rateOfTurn", "value": rate_of_turn }));
+    serde_json::json!({
+        "context": "vessels.self",
+        "updates": [{
+            "source": { "label": source },
+            "timestamp": clock::iso_timestamp(time),
+            "values": values,
+        }],
+    })
+    .to_string()
+}
+
+// The opening handshake for {url}, authorized with {token}, if given
+fn request(url: &str, token: Option<&str>) -> Result<Request> {
+    let mut request = url.into_client_request().context("Invalid Signal K URL.")?;
+    if let Some(token) = token {
+        request.headers_mut().insert(
+            "Authorization",
+            format!("Bearer {token}")
+                .parse()
+                .context("Invalid Signal K token.")?,
+        );
+    }
+    Ok(request)
+}
+
+// Keeps connecting and sending until the sink is dropped and everything is sent, or the server is unreachable by then
+fn send(
+    url: &str,
+    token: Option<&str>,
+    deltas: MemoryConsumer<String>,
+    status: Arc<Mutex<SignalKStatus>>,
+    backoff: Backoff,
+) {
+    let (mut failures, mut lost) = (0, 0);
+    loop {
+        let result = request(url, token)
+            .and_then(|request| {
+                tungstenite::connect(request).context("Unable to connect to Signal K server.")
+            })
+            .and_then(|(mut socket, _)| {
+                failures = 0;
+                {
+                    let mut status = status.lock().unwrap();
+                    status.connected = true;
+                    status.connections += 1;
+                }
+                let result = stream(&mut socket, &deltas, &status, &mut lost);
+                let _ = socket.close(None);
+                let _ = socket.write_pending();
+                result
+            });
+        let mut status_now = status.lock().unwrap();
+        status_now.connected = false;
+        status_now.dropped = deltas.overwritten() + lost;
+        match result {
+            Ok(()) => return,
+            Err(error) => status_now.last_error = Some(format!("{error:#}")),
+        }
+        if deltas.is_disconnected() {
+            // Nobody to wait for anymore
+            status_now.dropped += deltas.drain().count();
+            return;
+        }
+        drop(status_now);
+
+        failures += 1;
+        // In steps, to not hold up dropping the sink
+        let retry = Instant::now() + backoff.delay(failures);
+        while !deltas.is_disconnected() && Instant::now() < retry {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+// Sends deltas until the sink is dropped and all are sent, or the connection fails
+fn stream(
+    socket: &mut WebSocket<MaybeTlsStream<TcpStream>>,
+    deltas: &MemoryConsumer<String>,
+    status: &Mutex<SignalKStatus>,
+    lost: &mut usize,
+) -> Result<()> {
+    // Reading waits briefly for messages from the server, like pings, and then gets on with sending
+    if let MaybeTlsStream::Plain(stream) = socket.get_mut() {
+        stream.set_read_timeout(Some(Duration::from_millis(100)))?;
+    }
+    loop {
+        for delta in deltas.drain() {
+            if let Err(error) = socket.write_message(Message::Text(delta)) {
+                *lost += 1;
+                return Err(error).context("Signal K connection failed.");
+            }
+            status.lock().unwrap().sent += 1;
+        }
+        if deltas.is_disconnected() && deltas.is_empty() {
+            return Ok(());
+        }
+        match socket.read_message() {
+            Ok(_) => {} // The server's hello, and anything else it may say, isn't needed
+            Err(tungstenite::Error::Io(error))
+                if matches!(
+                    error.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) => {}
+            Err(error) => return Err(error).context("Signal K connection failed."),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use tungstenite::handshake::server::{ErrorResponse, Request};
+
+    #[test]
+    #[allow(clippy::result_large_err)] // The handshake callback's error is a whole HTTP response
+    fn test_deltas() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "ws://{}/signalk/v1/stream?subscribe=none",
+            listener.local_addr().unwrap()
+        );
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut authorization = None;
+            let mut socket = tungstenite::accept_hdr(stream, |request: &Request, response| {
+                authorization = request.headers().get("Authorization").cloned();
+                Ok::<_, ErrorResponse>(response)
+            })
+            .unwrap();
+            let mut deltas = Vec::new();
+            while let Ok(Message::Text(text)) = socket.read_message() {
+                deltas.push(serde_json::from_str::<serde_json::Value>(&text).unwrap());
+            }
+            (authorization, deltas)
+        });
+
+        let timebase = Timebase::now();
+        let mut config = SignalKConfig::new(&url);
+        config.token = Some("secret".to_string());
+        config.rate = Some(100.0);
+        config.attitude_filter = Some(Box::new(crate::fusion::Madgwick::new(0.1, 0.01)));
+        let mut sink = SignalKSink::new(config, timebase).unwrap();
+        let heeled = SensorSample::new(Vec3D::new(0, 0.5, 0.5), Vec3D::new(0, 0, -6), None);
+        let samples = (0..3)
+            .map(|n| (heeled, timebase.start + Duration::from_millis(n * 10)))
+            .collect::<Vec<_>>();
+        sink.write(&samples).unwrap();
+        drop(sink);
+
+        let (authorization, deltas) = server.join().unwrap();
+        assert_eq!(authorization.unwrap(), "Bearer secret");
+        assert_eq!(deltas.len(), 3);
+        let update = &deltas[2]["updates"][0];
+        assert_eq!(deltas[2]["context"], "vessels.self");
+        assert_eq!(update["source"]["label"], "njord");
+        assert!(update["timestamp"].as_str().unwrap().ends_with('Z'));
+        let values = update["values"].as_array().unwrap();
+        let paths = values
+            .iter()
+            .map(|value| value["path"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "navigation.attitude",
+                "navigation.headingMagnetic",
+                "navigation.rateOfTurn"
+            ]
+        );
+        assert!(values[0]["value"]["roll"].as_f64().unwrap() > 0.0);
+        assert!((values[2]["value"].as_f64().unwrap() - 6f64.to_radians()).abs() < 1e-9);
+
+        // Without yaw, there is no heading
+        let attitude = Attitude::from_tilt(&heeled, Duration::ZERO);
+        let delta: serde_json::Value =
+            serde_json::from_str(&delta("njord", &attitude, 0.0, SystemTime::UNIX_EPOCH)).unwrap();
+        let values = delta["updates"][0]["values"].as_array().unwrap();
+        assert_eq!(values.len(), 2);
+        assert!(values[0]["value"].get("yaw").is_none());
+        assert_eq!(delta["updates"][0]["timestamp"], "1970-01-01T00:00:00.000Z");
+    }
+}
@@ -0,0 +1,285 @@
+use std::{
+    io::Write,
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+
+use super::{frame, Encoding};
+use crate::{
+    gy521::SensorSample,
+    logging::{Metadata, Sink, Timebase},
+    math::Vec3D,
+    utilites::{self, MemoryProducer, OverflowPolicy},
+};
+
+/// How `TcpServer` treats its clients
+pub struct TcpConfig {
+    pub encoding: Encoding,
+    pub queue_capacity: usize, // Messages held per client that can't keep up. The oldest ones are dropped beyond that
+    pub write_timeout: Duration, // Clients that don't take any data for this long are disconnected
+}
+
+impl TcpConfig {
+    /// Holds up to 1000 messages per client, i.e., 10 s at 100 Hz
+    pub fn new(encoding: Encoding) -> Self {
+        Self {
+            encoding,
+            queue_capacity: 1000,
+            write_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+struct Client {
+    address: SocketAddr,
+    queue: MemoryProducer<Arc<[u8]>>,
+}
+
+/// Sends the same bytes to every client connected over TCP, e.g., NMEA sentences to chart plotters.
+/// Each client has its own queue and thread, so a slow client only loses its own oldest writes, and never holds up the others. Writing with `io::Write` broadcasts the bytes as they are
+pub struct TcpBroadcaster {
+    address: SocketAddr,
+    clients: Arc<Mutex<Vec<Client>>>,
+    running: Arc<AtomicBool>,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+impl TcpBroadcaster {
+    /// Listens on {address}, e.g., "0.0.0.0:10110" for all network interfaces, with {greeting} sent first on every connection. Of {config}, only the queue capacity and write timeout matter
+    pub fn bind<A: ToSocketAddrs>(address: A, config: &TcpConfig, greeting: &[u8]) -> Result<Self> {
+        let listener = TcpListener::bind(address).context("Unable to start TCP server.")?;
+        // Accepting polls, so that the server can be stopped
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+        let greeting: Arc<[u8]> = greeting.into();
+        let (queue_capacity, write_timeout) = (config.queue_capacity, config.write_timeout);
+
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let running = Arc::new(AtomicBool::new(true));
+        let acceptor = {
+            let (clients, running) = (clients.clone(), running.clone());
+            std::thread::Builder::new()
+                .name("telemetry-tcp".to_string())
+                .spawn(move || {
+                    while running.load(Ordering::Relaxed) {
+                        match listener.accept() {
+                            Ok((stream, address)) => {
+                                if let Ok(client) = connect(
+                                    stream,
+                                    address,
+                                    queue_capacity,
+                                    write_timeout,
+                                    &greeting,
+                                ) {
+                                    clients.lock().unwrap().push(client);
+                                }
+                            }
+                            // Mostly nobody connecting right now
+                            Err(_) => std::thread::sleep(Duration::from_millis(50)),
+                        }
+                    }
+                })
+                .context("Unable to start TCP server.")?
+        };
+
+        Ok(Self {
+            address,
+            clients,
+            running,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    pub fn local_address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Addresses of the clients connected right now
+    pub fn clients(&self) -> Vec<SocketAddr> {
+        self.clients
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|client| client.address)
+            .collect()
+    }
+
+    /// Queues {bytes} for every client, and forgets the clients that have disconnected
+    pub fn broadcast(&self, bytes: Arc<[u8]>) {
+        self.clients
+            .lock()
+            .unwrap()
+            .retain(|client| client.queue.push(bytes.clone()).is_ok());
+    }
+
+    fn is_idle(&self) -> bool {
+        self.clients.lock().unwrap().is_empty()
+    }
+}
+
+// Starts the thread that writes to a new client
+fn connect(
+    stream: TcpStream,
+    address: SocketAddr,
+    queue_capacity: usize,
+    write_timeout: Duration,
+    greeting: &Arc<[u8]>,
+) -> Result<Client> {
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?; // Messages are small, and should arrive right away
+    stream.set_write_timeout(Some(write_timeout))?;
+    let (queue, messages) =
+        utilites::shared_memory::<Arc<[u8]>>(queue_capacity.max(1), OverflowPolicy::Overwrite);
+    let greeting = greeting.clone();
+    std::thread::Builder::new()
+        .name(format!("telemetry-tcp-{address}"))
+        .spawn(move || {
+            let mut stream = stream;
+            if stream.write_all(&greeting).is_err() {
+                return;
+            }
+            // Ends when the client disconnects or stops reading, or the server is gone
+            loop {
+                match messages.pop(Some(Duration::from_millis(100))) {
+                    Some(bytes) => {
+                        if stream.write_all(&bytes).is_err() {
+                            return;
+                        }
+                    }
+                    None if messages.is_disconnected() => return,
+                    None => {}
+                }
+            }
+        })?;
+    Ok(Client { address, queue })
+}
+
+impl Write for TcpBroadcaster {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        if !self.is_idle() {
+            self.broadcast(bytes.into());
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for TcpBroadcaster {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+        // Without their queues, the client threads send what is left and end
+        self.clients.lock().unwrap().clear();
+    }
+}
+
+/// Streams samples to every client connected over TCP, as length-prefixed frames (see `telemetry::frame`), e.g., for watching the attitude live on a laptop.
+/// Every connection starts with a frame holding the metadata as JSON, and then gets the messages of the samples written from then on.
+/// Each client has its own queue and thread, so a slow client only loses its own oldest messages, and never holds up sampling or the other clients
+pub struct TcpServer {
+    broadcaster: TcpBroadcaster,
+    encoding: Encoding,
+    timebase: Timebase,
+}
+
+impl TcpServer {
+    /// Listens on {address}, e.g., "0.0.0.0:5000" for all network interfaces. Port 0 picks a free port, see `local_address`
+    pub fn bind<A: ToSocketAddrs>(
+        address: A,
+        config: TcpConfig,
+        metadata: &Metadata,
+        timebase: Timebase,
+    ) -> Result<Self> {
+        let greeting = frame(&serde_json::to_vec(metadata)?);
+        Ok(Self {
+            broadcaster: TcpBroadcaster::bind(address, &config, &greeting)?,
+            encoding: config.encoding,
+            timebase,
+        })
+    }
+
+    pub fn local_address(&self) -> SocketAddr {
+        self.broadcaster.local_address()
+    }
+
+    /// Addresses of the clients connected right now
+    pub fn clients(&self) -> Vec<SocketAddr> {
+        self.broadcaster.clients()
+    }
+}
+
+impl Sink for TcpServer {
+    fn write(&mut self, samples: &[(SensorSample<Vec3D, f64>, Instant)]) -> Result<()> {
+        if self.broadcaster.is_idle() {
+            return Ok(());
+        }
+        for (sample, instant) in samples {
+            let frame = self
+                .encoding
+                .sample_frame(sample, *instant, &self.timebase)?;
+            self.broadcaster.broadcast(frame.into());
+        }
+        Ok(())
+    }
+
+    // Frames go out as they are written
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::read_frame;
+
+    #[test]
+    fn test_streaming() {
+        let timebase = Timebase::now();
+        let metadata = Metadata::for_tests(&timebase);
+        let mut config = TcpConfig::new(Encoding::Binary);
+        config.queue_capacity = 10;
+        let mut server = TcpServer::bind("127.0.0.1:0", config, &metadata, timebase).unwrap();
+        let mut client = TcpStream::connect(server.local_address()).unwrap();
+        let greeting = read_frame(&mut client).unwrap().unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Metadata>(&greeting).unwrap(),
+            metadata
+        );
+        while server.clients().is_empty() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let sample = SensorSample::new(Vec3D::new(0, 0, 1), Vec3D::default(), None);
+        let instants = (0..3)
+            .map(|n| (sample, timebase.start + Duration::from_millis(n)))
+            .collect::<Vec<_>>();
+        server.write(&instants).unwrap();
+        for n in 0..3 {
+            let payload = read_frame(&mut client).unwrap().unwrap();
+            let message = Encoding::Binary.decode(&payload).unwrap();
+            assert_eq!(message.time, n as f64 / 1e3);
+        }
+
+        // Disconnected clients are forgotten
+        drop(client);
+        let start = Instant::now();
+        while !server.clients().is_empty() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            server.write(&instants).unwrap();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket},
+    time::Instant,
+};
+
+use anyhow::{ensure, Context, Result};
+
+use super::{Encoding, Message, RateLimit};
+use crate::{
+    gy521::SensorSample,
+    logging::{Sink, Timebase},
+    math::Vec3D,
+};
+
+/// Where and how often `UdpSender` sends
+pub struct UdpConfig {
+    pub destination: SocketAddr, // A broadcast address, like 192.168.1.255:5001 or 255.255.255.255:5001, a multicast group, like 239.0.0.1:5001, or a single receiver
+    pub encoding: Encoding,
+    pub rate: Option<f64>, // [Hz] Samples that come faster are skipped. None sends every sample
+    pub multicast_ttl: u32, // Routers a multicast packet may pass. 1 keeps it on the local network
+}
+
+impl UdpConfig {
+    /// Sends to {destination} at up to 50 Hz, which is plenty for a live display
+    pub fn new<A: ToSocketAddrs>(destination: A, encoding: Encoding) -> Result<Self> {
+        let destination = destination
+            .to_socket_addrs()?
+            .next()
+            .context("UDP destination has no address.")?;
+        Ok(Self {
+            destination,
+            encoding,
+            rate: Some(50.0),
+            multicast_ttl: 1,
+        })
+    }
+}
+
+/// How sending has gone so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UdpStatus {
+    pub sent: usize,    // Packets
+    pub skipped: usize, // Samples that came faster than the rate
+    pub dropped: usize, // Packets that couldn't be sent right away, e.g., with the send buffer full
+}
+
+/// Sends each sample as one UDP packet, for live displays on the local network, e.g., of the attitude.
+/// Unlike TCP, a lost packet doesn't hold up the ones after it, so the display always shows the latest sample. Receivers can spot lost packets by the sequence number.
+/// A packet is the sequence number (u32, little-endian, counting up from 0 and wrapping around), followed by the message in {encoding}. See `decode_packet`.
+/// Sending never waits. Packets that can't be sent right away are dropped, like the network might do anyway
+pub struct UdpSender {
+    socket: UdpSocket,
+    config: UdpConfig,
+    timebase: Timebase,
+    rate_limit: RateLimit,
+    sequence: u32,
+    packet: Vec<u8>, // Reused for encoding, to not allocate for every packet
+    status: UdpStatus,
+}
+
+impl UdpSender {
+    pub fn new(config: UdpConfig, timebase: Timebase) -> Result<Self> {
+        let local_address: SocketAddr = match config.destination.ip() {
+            IpAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            IpAddr::V6(_) => (std::net::Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local_address).context("Unable to open UDP socket.")?;
+        socket.set_nonblocking(true)?;
+        match config.destination.ip() {
+            IpAddr::V4(address) if address.is_multicast() => {
+                socket.set_multicast_ttl_v4(config.multicast_ttl)?
+            }
+            IpAddr::V4(_) => socket.set_broadcast(true)?, // Harmless for single receivers
+            IpAddr::V6(_) => {}
+        }
+        let rate_limit = RateLimit::new(config.rate).context("Invalid UDP rate.")?;
+        Ok(Self {
+            socket,
+            config,
+            timebase,
+            rate_limit,
+            sequence: 0,
+            packet: Vec::new(),
+            status: UdpStatus::default(),
+        })
+    }
+
+    pub fn status(&self) -> UdpStatus {
+        self.status
+    }
+}
+
+impl Sink for UdpSender {
+    fn write(&mut self, samples: &[(SensorSample<Vec3D, f64>, Instant)]) -> Result<()> {
+        for (sample, instant) in samples {
+            if !self.rate_limit.admit(*instant) {
+                self.status.skipped += 1;
+                continue;
+            }
+            let message = Message::new(sample, self.timebase.elapsed(*instant));
+            self.packet.clear();
+            self.packet.extend_from_slice(&self.sequence.to_le_bytes());
+            self.packet
+                .extend_from_slice(&self.config.encoding.encode(&message)?);
+            match self.socket.send_to(&self.packet, self.config.destination) {
+                Ok(_) => self.status.sent += 1,
+                // Nobody listening (ICMP port unreachable) or a full buffer just loses the packet
+                Err(_) => self.status.dropped += 1,
+            }
+            self.sequence = self.sequence.wrapping_add(1);
+        }
+        Ok(())
+    }
+
+    // Packets go out as they are written
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Sequence number and message of a packet from `UdpSender`
+pub fn decode_packet(encoding: Encoding, packet: &[u8]) -> Result<(u32, Message)> {
+    ensure!(packet.len() >= 4, "UDP packet is too short.");
+    let sequence = u32::from_le_bytes(packet[..4].try_into()?);
+    Ok((sequence, encoding.decode(&packet[4..])?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_rate() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut config = UdpConfig::new(receiver.local_addr().unwrap(), Encoding::Json).unwrap();
+        config.rate = Some(200.0); // Every 5 ms
+        let timebase = Timebase::now();
+        let mut sender = UdpSender::new(config, timebase).unwrap();
+
+        let sample = SensorSample::new(Vec3D::new(0, 0, 1), Vec3D::default(), None);
+        let samples = (0..10)
+            .map(|n| (sample, timebase.start + Duration::from_millis(n)))
+            .collect::<Vec<_>>();
+        sender.write(&samples).unwrap();
+        assert_eq!(sender.status().sent + sender.status().dropped, 2);
+        assert_eq!(sender.status().skipped, 8);
+
+        let mut buffer = [0; 2048];
+        for (sequence, time) in [(0, 0.0), (1, 0.005)] {
+            let length = receiver.recv(&mut buffer).unwrap();
+            let (received, message) = decode_packet(Encoding::Json, &buffer[..length]).unwrap();
+            assert_eq!(received, sequence);
+            assert_eq!(message.time, time);
+        }
+    }
+}
@@ -0,0 +1,512 @@
+use std::{
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use tungstenite::{Message as Frame, WebSocket};
+
+use super::{
+    control::{Acknowledgement, Command, CommandSender},
+    Attitude, Message, RateLimit,
+};
+use crate::{
+    gy521::SensorSample,
+    logging::{Metadata, Sink, Timebase},
+    math::Vec3D,
+    utilites::{self, MemoryConsumer, MemoryProducer, OverflowPolicy},
+};
+
+/// How `WebSocketServer` treats its clients
+pub struct WebSocketConfig {
+    pub decimated_rate: f64, // [Hz] Of the decimated and attitude streams, unless clients ask for less
+    pub max_rate: Option<f64>, // [Hz] Per client and stream, whatever clients ask for. None allows every sample
+    pub queue_capacity: usize, // Samples held per client that can't keep up. The oldest ones are dropped beyond that
+    pub heartbeat: Duration,   // Between pings to every client
+    pub timeout: Duration, // Clients that haven't sent anything, not even a pong, for this long are disconnected
+    pub write_timeout: Duration,
+    pub commands: Option<CommandSender>, // Where `Request::Command`s go. None rejects them
+}
+
+impl WebSocketConfig {
+    /// Decimates to 10 Hz, allows up to 100 Hz, and pings every 10 s
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self {
+            decimated_rate: 10.0,
+            max_rate: Some(100.0),
+            queue_capacity: 1000,
+            heartbeat: Duration::from_secs(10),
+            timeout: Duration::from_secs(30),
+            write_timeout: Duration::from_secs(10),
+            commands: None,
+        }
+    }
+}
+
+/// What clients can subscribe to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stream {
+    Raw,       // Every sample, as `telemetry::Message`, up to the maximum rate
+    Decimated, // The same, at the decimated rate
+    Attitude,  // Roll and pitch, as `telemetry::Attitude`, at the decimated rate
+}
+
+/// What clients send, as JSON text, e.g., {"type": "subscribe", "stream": "attitude", "rate": 5}
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Request {
+    Subscribe {
+        stream: Stream,
+        #[serde(default)]
+        rate: Option<f64>, // [Hz] Lower than the stream's own rate, if given
+    },
+    Unsubscribe {
+        stream: Stream,
+    },
+    // E.g., {"type": "command", "command": "mark_event", "tag": "tack"}
+    Command {
+        #[serde(flatten)]
+        command: Command,
+    },
+}
+
+/// What the server sends, as JSON text, e.g., {"type": "attitude", "time": 1.5, "roll": 3.2, "pitch": -0.4}
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Reply {
+    Metadata(Box<Metadata>), // First thing on every connection
+    Subscribed {
+        stream: Stream,
+        rate: Option<f64>, // [Hz] The rate the stream comes at, None for every sample
+    },
+    Unsubscribed {
+        stream: Stream,
+    },
+    Sample {
+        stream: Stream,
+        message: Message,
+    },
+    Attitude(Attitude),
+    Acknowledgement(Acknowledgement), // Of a command
+    Error {
+        message: String, // E.g., about a request that couldn't be parsed
+    },
+}
+
+struct Client {
+    address: SocketAddr,
+    queue: MemoryProducer<(SensorSample<Vec3D, f64>, Instant)>,
+}
+
+/// Streams samples to browsers and other WebSocket clients, e.g., for a live dashboard without any other services.
+/// Clients choose what they get by subscribing to streams, see `Request` and `Reply`. Nothing is sent but the metadata until they do.
+/// The server pings every client regularly, which browsers answer on their own, and disconnects the ones that have gone silent.
+/// Each client has its own queue and thread, so a slow client only loses its own oldest samples, and never holds up sampling or the other clients
+pub struct WebSocketServer {
+    address: SocketAddr,
+    clients: Arc<Mutex<Vec<Client>>>,
+    running: Arc<AtomicBool>,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+// What one client gets, shared by its thread
+#[derive(Clone)]
+struct Session {
+    greeting: Arc<String>,
+    timebase: Timebase,
+    decimated_rate: f64,
+    max_rate: Option<f64>,
+    heartbeat: Duration,
+    timeout: Duration,
+    write_timeout: Duration,
+    commands: Option<CommandSender>,
+}
+
+impl WebSocketServer {
+    /// Listens on {address}, e.g., "0.0.0.0:8080" for all network interfaces. Port 0 picks a free port, see `local_address`
+    pub fn bind<A: ToSocketAddrs>(
+        address: A,
+        config: WebSocketConfig,
+        metadata: &Metadata,
+        timebase: Timebase,
+    ) -> Result<Self> {
+        RateLimit::new(Some(config.decimated_rate)).context("Invalid decimated rate.")?;
+        RateLimit::new(config.max_rate).context("Invalid maximum rate.")?;
+        let listener = TcpListener::bind(address).context("Unable to start WebSocket server.")?;
+        // Accepting polls, so that the server can be stopped
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+        let session = Session {
+            greeting: Arc::new(serde_json::to_string(&Reply::Metadata(Box::new(
+                metadata.clone(),
+            )))?),
+            timebase,
+            decimated_rate: config.decimated_rate,
+            max_rate: config.max_rate,
+            heartbeat: config.heartbeat,
+            timeout: config.timeout,
+            write_timeout: config.write_timeout,
+            commands: config.commands,
+        };
+        let queue_capacity = config.queue_capacity.max(1);
+
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let running = Arc::new(AtomicBool::new(true));
+        let acceptor = {
+            let (clients, running) = (clients.clone(), running.clone());
+            std::thread::Builder::new()
+                .name("telemetry-websocket".to_string())
+                .spawn(move || {
+                    while running.load(Ordering::Relaxed) {
+                        match listener.accept() {
+                            Ok((stream, address)) => {
+                                let (queue, samples) = utilites::shared_memory(
+                                    queue_capacity,
+                                    OverflowPolicy::Overwrite,
+                                );
+                                let session = session.clone();
+                                // The handshake happens on the client's thread, so that slow clients don't hold up accepting others
+                                let spawned = std::thread::Builder::new()
+                                    .name(format!("telemetry-websocket-{address}"))
+                                    .spawn(move || {
+                                        let _ = serve(stream, samples, session);
+                                    });
+                                if spawned.is_ok() {
+                                    clients.lock().unwrap().push(Client { address, queue });
+                                }
+                            }
+                            // Mostly nobody connecting right now
+                            Err(_) => std::thread::sleep(Duration::from_millis(50)),
+                        }
+                    }
+                })
+                .context("Unable to start WebSocket server.")?
+        };
+
+        Ok(Self {
+            address,
+            clients,
+            running,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    pub fn local_address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Addresses of the clients connected right now
+    pub fn clients(&self) -> Vec<SocketAddr> {
+        self.clients
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|client| client.address)
+            .collect()
+    }
+}
+
+// A subscribed stream of a client
+struct Subscription {
+    stream: Stream,
+    rate_limit: RateLimit,
+}
+
+impl Session {
+    // The rate {stream} comes at when asked for {requested}, within the limits of the server
+    fn rate(&self, stream: Stream, requested: Option<f64>) -> Option<f64> {
+        let own = match stream {
+            Stream::Raw => self.max_rate,
+            Stream::Decimated | Stream::Attitude => Some(self.decimated_rate),
+        };
+        match (own, requested) {
+            (Some(own), Some(requested)) => Some(own.min(requested)),
+            (own, requested) => own.or(requested),
+        }
+    }
+
+    // Applies {text} from the client to {subscriptions}
+    fn handle(&self, text: &str, subscriptions: &mut Vec<Subscription>) -> Reply {
+        let request = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(error) => {
+                return Reply::Error {
+                    message: format!("Unable to parse request: {error}"),
+                }
+            }
+        };
+        match request {
+            Request::Subscribe { stream, rate } => {
+                let rate = self.rate(stream, rate);
+                let rate_limit = match RateLimit::new(rate) {
+                    Ok(rate_limit) => rate_limit,
+                    Err(error) => {
+                        return Reply::Error {
+                            message: error.to_string(),
+                        }
+                    }
+                };
+                subscriptions.retain(|subscription| subscription.stream != stream);
+                subscriptions.push(Subscription { stream, rate_limit });
+                Reply::Subscribed { stream, rate }
+            }
+            Request::Unsubscribe { stream } => {
+                subscriptions.retain(|subscription| subscription.stream != stream);
+                Reply::Unsubscribed { stream }
+            }
+            Request::Command { command } => match &self.commands {
+                Some(commands) => Reply::Acknowledgement(match commands.send(command) {
+                    Ok(()) => Acknowledgement::accepted(),
+                    Err(error) => Acknowledgement::rejected(&error),
+                }),
+                None => Reply::Error {
+                    message: "Commands aren't accepted here.".to_string(),
+                },
+            },
+        }
+    }
+}
+
+fn send(socket: &mut WebSocket<TcpStream>, reply: &Reply) -> Result<()> {
+    Ok(socket.write_message(Frame::Text(serde_json::to_string(reply)?))?)
+}
+
+// Talks to one client until it disconnects or goes silent, or the server is gone
+fn serve(
+    stream: TcpStream,
+    samples: MemoryConsumer<(SensorSample<Vec3D, f64>, Instant)>,
+    session: Session,
+) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+    stream.set_write_timeout(Some(session.write_timeout))?;
+    stream.set_read_timeout(Some(session.timeout))?; // For the handshake
+    let mut socket = tungstenite::accept(stream).context("WebSocket handshake failed.")?;
+    // Reading waits briefly for requests, and then gets on with sending samples
+    socket
+        .get_mut()
+        .set_read_timeout(Some(Duration::from_millis(10)))?;
+    socket.write_message(Frame::Text(session.greeting.to_string()))?;
+
+    let mut subscriptions = Vec::<Subscription>::new();
+    let (mut heard, mut pinged) = (Instant::now(), Instant::now());
+    loop {
+        match socket.read_message() {
+            Ok(Frame::Text(text)) => {
+                heard = Instant::now();
+                let reply = session.handle(&text, &mut subscriptions);
+                send(&mut socket, &reply)?;
+            }
+            Ok(Frame::Close(_)) => {
+                // Answered by tungstenite, which then reports the connection as closed
+                heard = Instant::now();
+            }
+            Ok(_) => heard = Instant::now(), // Pongs, pings (answered by tungstenite), and binary messages
+            Err(tungstenite::Error::Io(error))
+                if matches!(
+                    error.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) => {}
+            Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            Err(error) => return Err(error.into()),
+        }
+
+        for (sample, instant) in samples.drain() {
+            let time = session.timebase.elapsed(instant);
+            for subscription in &mut subscriptions {
+                if !subscription.rate_limit.admit(instant) {
+                    continue;
+                }
+                let reply = match subscription.stream {
+                    Stream::Raw | Stream::Decimated => Reply::Sample {
+                        stream: subscription.stream,
+                        message: Message::new(&sample, time),
+                    },
+                    Stream::Attitude => Reply::Attitude(Attitude::from_tilt(&sample, time)),
+                };
+                send(&mut socket, &reply)?;
+            }
+        }
+        if samples.is_disconnected() {
+            let _ = socket.close(None);
+            let _ = socket.write_pending();
+            return Ok(());
+        }
+
+        if heard.elapsed() > session.timeout {
+            let _ = socket.close(None);
+            return Ok(());
+        }
+        if pinged.elapsed() >= session.heartbeat {
+            socket.write_message(Frame::Ping(Vec::new()))?;
+            pinged = Instant::now();
+        }
+    }
+}
+
+impl Sink for WebSocketServer {
+    fn write(&mut self, samples: &[(SensorSample<Vec3D, f64>, Instant)]) -> Result<()> {
+        let mut clients = self.clients.lock().unwrap();
+        for sample in samples {
+            // Forgets the clients that have disconnected
+            clients.retain(|client| client.queue.push(*sample).is_ok());
+        }
+        Ok(())
+    }
+
+    // Samples go out as they are written
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for WebSocketServer {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+        // Without their queues, the client threads send what is left and close
+        self.clients.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receive(socket: &mut WebSocket<TcpStream>) -> Frame {
+        loop {
+            match socket.read_message().unwrap() {
+                Frame::Pong(_) => {}
+                frame => return frame,
+            }
+        }
+    }
+
+    fn reply(socket: &mut WebSocket<TcpStream>) -> Reply {
+        match receive(socket) {
+            Frame::Text(text) => serde_json::from_str(&text).unwrap(),
+            frame => panic!("Expected a reply, but got {frame:?}."),
+        }
+    }
+
+    fn request(socket: &mut WebSocket<TcpStream>, text: &str) {
+        socket.write_message(Frame::Text(text.to_string())).unwrap();
+    }
+
+    #[test]
+    fn test_subscriptions() {
+        let timebase = Timebase::now();
+        let metadata = Metadata::for_tests(&timebase);
+        let mut config = WebSocketConfig::new();
+        config.decimated_rate = 200.0; // Every 5 ms
+        config.max_rate = Some(500.0);
+        config.heartbeat = Duration::from_millis(50);
+        let (commands, received_commands) = crate::telemetry::control::channel();
+        config.commands = Some(commands);
+        let mut server = WebSocketServer::bind("127.0.0.1:0", config, &metadata, timebase).unwrap();
+
+        let stream = TcpStream::connect(server.local_address()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let url = format!("ws://{}/", server.local_address());
+        let (mut socket, _) = tungstenite::client(url.as_str(), stream).unwrap();
+        match reply(&mut socket) {
+            Reply::Metadata(received) => assert_eq!(*received, metadata),
+            reply => panic!("Expected metadata, but got {reply:?}."),
+        }
+
+        request(&mut socket, r#"{"type": "subscribe", "stream": "raw"}"#);
+        request(
+            &mut socket,
+            r#"{"type": "subscribe", "stream": "attitude", "rate": 1000}"#,
+        );
+        request(
+            &mut socket,
+            r#"{"type": "subscribe", "stream": "everything"}"#,
+        );
+        assert!(matches!(
+            reply(&mut socket),
+            Reply::Subscribed {
+                stream: Stream::Raw,
+                rate: Some(rate),
+            } if rate == 500.0
+        ));
+        assert!(matches!(
+            reply(&mut socket),
+            Reply::Subscribed {
+                stream: Stream::Attitude,
+                rate: Some(rate),
+            } if rate == 200.0
+        ));
+        assert!(matches!(reply(&mut socket), Reply::Error { .. }));
+
+        let heeled = SensorSample::new(Vec3D::new(0, 0.5, 0.5), Vec3D::default(), None);
+        let samples = (0..10)
+            .map(|n| (heeled, timebase.start + Duration::from_millis(n)))
+            .collect::<Vec<_>>();
+        server.write(&samples).unwrap();
+        let (mut raw, mut attitude) = (Vec::new(), Vec::new());
+        while raw.len() + attitude.len() < 7 {
+            match reply(&mut socket) {
+                Reply::Sample {
+                    stream: Stream::Raw,
+                    message,
+                } => raw.push(message.time),
+                Reply::Attitude(received) => attitude.push(received),
+                reply => panic!("Expected samples, but got {reply:?}."),
+            }
+        }
+        assert_eq!(raw, [0.0, 0.002, 0.004, 0.006, 0.008]);
+        assert_eq!(attitude.len(), 2);
+        assert!((attitude[1].roll - 45.0).abs() < 1e-9);
+
+        // Heartbeat
+        assert!(matches!(receive(&mut socket), Frame::Ping(_)));
+        request(&mut socket, r#"{"type": "unsubscribe", "stream": "raw"}"#);
+        assert!(matches!(
+            reply(&mut socket),
+            Reply::Unsubscribed {
+                stream: Stream::Raw
+            }
+        ));
+        request(
+            &mut socket,
+            r#"{"type": "command", "command": "mark_event", "tag": "tack"}"#,
+        );
+        assert!(matches!(
+            reply(&mut socket),
+            Reply::Acknowledgement(Acknowledgement { accepted: true, .. })
+        ));
+        assert_eq!(
+            received_commands.try_recv().unwrap(),
+            Command::MarkEvent {
+                tag: "tack".to_string(),
+                payload: None
+            }
+        );
+
+        // Disconnected clients are forgotten
+        socket.close(None).unwrap();
+        let start = Instant::now();
+        while !server.clients().is_empty() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            server.write(&samples).unwrap();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
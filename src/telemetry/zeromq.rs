@@ -0,0 +1,176 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+use super::{Encoding, Message, RateLimit};
+use crate::{
+    gy521::SensorSample,
+    logging::{Metadata, Sink, Timebase},
+    math::Vec3D,
+};
+
+/// Where and how `ZmqPublisher` publishes
+pub struct ZmqConfig {
+    pub endpoint: String, // E.g., "tcp://*:5556" for other machines too, or "ipc:///tmp/njord" for processes on the Pi only
+    pub topic: String, // First frame of every sample message, for subscribers to filter on by prefix
+    pub metadata_topic: String, // First frame of the metadata message. Shouldn't start with {topic}, so that subscribers get either one alone
+    pub metadata_interval: Duration, // The metadata is published again this often, for subscribers that join late
+    pub encoding: Encoding,
+    pub rate: Option<f64>, // [Hz] Samples that come faster are skipped. None publishes every sample
+    pub send_high_water_mark: i32, // Messages queued per subscriber that can't keep up. ZeroMQ drops new ones beyond that
+}
+
+impl ZmqConfig {
+    /// Publishes every sample on "njord.samples", and the metadata as JSON on "njord.metadata" every second
+    pub fn new(endpoint: &str, encoding: Encoding) -> Self {
+        Self {
+            endpoint: endpoint.to_string(),
+            topic: "njord.samples".to_string(),
+            metadata_topic: "njord.metadata".to_string(),
+            metadata_interval: Duration::from_secs(1),
+            encoding,
+            rate: None,
+            send_high_water_mark: 1000,
+        }
+    }
+}
+
+/// Publishes samples on a ZeroMQ PUB socket, so that any number of processes can subscribe with SUB sockets, without this keeping track of them.
+/// Every sample is a message of two frames: {topic}, and the `Message` in {encoding}. The metadata, as JSON, is published the same way on {metadata_topic}.
+/// Publishing never waits. Subscribers that fall behind lose messages, and nobody subscribing loses all of them
+pub struct ZmqPublisher {
+    socket: zmq::Socket,
+    endpoint: String,
+    topic: String,
+    encoding: Encoding,
+    metadata: (String, Vec<u8>), // Topic and message
+    metadata_interval: Duration,
+    metadata_published: Option<Instant>,
+    timebase: Timebase,
+    rate_limit: RateLimit,
+}
+
+impl ZmqPublisher {
+    pub fn bind(config: ZmqConfig, metadata: &Metadata, timebase: Timebase) -> Result<Self> {
+        let context = zmq::Context::new();
+        let socket = context
+            .socket(zmq::PUB)
+            .context("Unable to create ZeroMQ socket.")?;
+        socket.set_sndhwm(config.send_high_water_mark)?;
+        // Messages still queued on drop get a moment to go out, but never hold up stopping for long
+        socket.set_linger(1000)?;
+        socket
+            .bind(&config.endpoint)
+            .with_context(|| format!("Unable to bind ZeroMQ socket to {}.", config.endpoint))?;
+        // Wildcards, like a port of "*", are resolved now
+        let endpoint = socket
+            .get_last_endpoint()?
+            .unwrap_or_else(|_| config.endpoint.clone());
+        Ok(Self {
+            socket,
+            endpoint,
+            topic: config.topic,
+            encoding: config.encoding,
+            metadata: (config.metadata_topic, serde_json::to_vec(metadata)?),
+            metadata_interval: config.metadata_interval,
+            metadata_published: None,
+            timebase,
+            rate_limit: RateLimit::new(config.rate).context("Invalid ZeroMQ rate.")?,
+        })
+    }
+
+    /// Where subscribers connect to, e.g., "tcp://0.0.0.0:5556"
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    fn publish(&self, topic: &str, message: &[u8]) -> Result<()> {
+        self.socket
+            .send_multipart([topic.as_bytes(), message], zmq::DONTWAIT)
+            .context("Unable to publish ZeroMQ message.")
+    }
+}
+
+impl Sink for ZmqPublisher {
+    fn write(&mut self, samples: &[(SensorSample<Vec3D, f64>, Instant)]) -> Result<()> {
+        for (sample, instant) in samples {
+            if self.metadata_published.is_none_or(|published| {
+                instant.saturating_duration_since(published) >= self.metadata_interval
+            }) {
+                let (topic, metadata) = &self.metadata;
+                self.publish(topic, metadata)?;
+                self.metadata_published = Some(*instant);
+            }
+            if !self.rate_limit.admit(*instant) {
+                continue;
+            }
+            let message = Message::new(sample, self.timebase.elapsed(*instant));
+            self.publish(&self.topic, &self.encoding.encode(&message)?)?;
+        }
+        Ok(())
+    }
+
+    // Messages go out as they are written
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_publish() {
+        let timebase = Timebase::now();
+        let metadata = Metadata::for_tests(&timebase);
+        let mut publisher = ZmqPublisher::bind(
+            ZmqConfig::new("tcp://127.0.0.1:*", Encoding::Binary),
+            &metadata,
+            timebase,
+        )
+        .unwrap();
+
+        let context = zmq::Context::new();
+        let subscribe = |topic: &str| {
+            let socket = context.socket(zmq::SUB).unwrap();
+            socket.set_rcvtimeo(10).unwrap();
+            socket.connect(publisher.endpoint()).unwrap();
+            socket.set_subscribe(topic.as_bytes()).unwrap();
+            socket
+        };
+        let samples = subscribe("njord.samples");
+        let metadata_subscriber = subscribe("njord.metadata");
+
+        // Subscriptions take a moment to reach the publisher, and messages published before are lost
+        let sample = SensorSample::new(Vec3D::new(0, 0, 1), Vec3D::new(1, 2, 3), None);
+        let mut n = 0;
+        let frames = loop {
+            assert!(n < 500, "Nothing received.");
+            let instant = timebase.start + Duration::from_millis(n);
+            publisher.write(&[(sample, instant)]).unwrap();
+            if let Ok(frames) = samples.recv_multipart(0) {
+                break frames;
+            }
+            n += 1;
+        };
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0], b"njord.samples");
+        let message = Encoding::Binary.decode(&frames[1]).unwrap();
+        assert_eq!(message.sample.angular_velocity(), Vec3D::new(1, 2, 3));
+
+        // Republished every second
+        let instant = timebase.start + Duration::from_secs(n + 1);
+        publisher.write(&[(sample, instant)]).unwrap();
+        let frames = loop {
+            if let Ok(frames) = metadata_subscriber.recv_multipart(0) {
+                break frames;
+            }
+        };
+        assert_eq!(frames[0], b"njord.metadata");
+        assert_eq!(
+            serde_json::from_slice::<Metadata>(&frames[1]).unwrap(),
+            metadata
+        );
+    }
+}
@@ -0,0 +1,168 @@
+// Typed units for sensor outputs, so that g and m/s^2 (or degree/s and rad/s) can't be mixed up by accident.
+// The value is either a scalar or a Vec3D. Conversions between units of the same quantity are `From` impls.
+
+use std::{
+    f64::consts::PI,
+    ops::{Add, Div, Mul, Neg, Sub},
+};
+
+use crate::gy521::g;
+
+macro_rules! unit {
+    ($(#[$attribute:meta])* $name:ident) => {
+        $(#[$attribute])*
+        #[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+        #[serde(transparent)]
+        pub struct $name<T = f64>(pub T);
+
+        impl<T: Add<Output = T>> Add for $name<T> {
+            type Output = Self;
+
+            fn add(self, rhs: Self) -> Self {
+                Self(self.0 + rhs.0)
+            }
+        }
+
+        impl<T: Sub<Output = T>> Sub for $name<T> {
+            type Output = Self;
+
+            fn sub(self, rhs: Self) -> Self {
+                Self(self.0 - rhs.0)
+            }
+        }
+
+        impl<T: Neg<Output = T>> Neg for $name<T> {
+            type Output = Self;
+
+            fn neg(self) -> Self {
+                Self(-self.0)
+            }
+        }
+
+        impl<T: Mul<f64, Output = T>> Mul<f64> for $name<T> {
+            type Output = Self;
+
+            fn mul(self, rhs: f64) -> Self {
+                Self(self.0 * rhs)
+            }
+        }
+
+        impl<T: Div<f64, Output = T>> Div<f64> for $name<T> {
+            type Output = Self;
+
+            fn div(self, rhs: f64) -> Self {
+                Self(self.0 / rhs)
+            }
+        }
+    };
+}
+
+unit!(
+    /// Acceleration in multiples of standard gravity, like the accelerometer measures it
+    Gs
+);
+unit!(
+    /// Acceleration in [m/s^2]
+    MetersPerSecondSquared
+);
+unit!(
+    /// Angular velocity in [degree/s], like the gyroscope measures it
+    DegreesPerSecond
+);
+unit!(
+    /// Angular velocity in [rad/s], like the attitude filters use internally
+    RadiansPerSecond
+);
+unit!(
+    /// Temperature in [degree C]
+    Celsius
+);
+unit!(
+    /// Temperature in [K]
+    Kelvin
+);
+
+unit!(
+    /// Magnetic flux density in [G], like the magnetometers measure it
+    Gauss
+);
+unit!(
+    /// Magnetic flux density in [uT]
+    Microtesla
+);
+
+const ZERO_CELSIUS: f64 = 273.15; // [K]
+
+impl<T: Mul<f64, Output = T>> From<Gs<T>> for MetersPerSecondSquared<T> {
+    fn from(acceleration: Gs<T>) -> Self {
+        Self(acceleration.0 * g)
+    }
+}
+
+impl<T: Div<f64, Output = T>> From<MetersPerSecondSquared<T>> for Gs<T> {
+    fn from(acceleration: MetersPerSecondSquared<T>) -> Self {
+        Self(acceleration.0 / g)
+    }
+}
+
+impl<T: Mul<f64, Output = T>> From<DegreesPerSecond<T>> for RadiansPerSecond<T> {
+    fn from(angular_velocity: DegreesPerSecond<T>) -> Self {
+        Self(angular_velocity.0 * (PI / 180.0))
+    }
+}
+
+impl<T: Mul<f64, Output = T>> From<RadiansPerSecond<T>> for DegreesPerSecond<T> {
+    fn from(angular_velocity: RadiansPerSecond<T>) -> Self {
+        Self(angular_velocity.0 * (180.0 / PI))
+    }
+}
+
+impl<T: Mul<f64, Output = T>> From<Gauss<T>> for Microtesla<T> {
+    fn from(magnetic_field: Gauss<T>) -> Self {
+        Self(magnetic_field.0 * 100.0)
+    }
+}
+
+impl<T: Div<f64, Output = T>> From<Microtesla<T>> for Gauss<T> {
+    fn from(magnetic_field: Microtesla<T>) -> Self {
+        Self(magnetic_field.0 / 100.0)
+    }
+}
+
+impl From<Celsius> for Kelvin {
+    fn from(temperature: Celsius) -> Self {
+        Self(temperature.0 + ZERO_CELSIUS)
+    }
+}
+
+impl From<Kelvin> for Celsius {
+    fn from(temperature: Kelvin) -> Self {
+        Self(temperature.0 - ZERO_CELSIUS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Vec3D;
+
+    #[test]
+    fn test_conversions() {
+        let acceleration = MetersPerSecondSquared::from(Gs(Vec3D::new(0, 0, 1)));
+        assert_eq!(acceleration.0, Vec3D::new(0, 0, g));
+        assert_eq!(Gs::from(acceleration * 2.0), Gs(Vec3D::new(0, 0, 2)));
+
+        let angular_velocity = RadiansPerSecond::from(DegreesPerSecond(180.0));
+        assert!((angular_velocity.0 - PI).abs() < 1e-12);
+        let angular_velocity = DegreesPerSecond::from(-angular_velocity);
+        assert!((angular_velocity.0 + 180.0).abs() < 1e-12);
+
+        let magnetic_field = Microtesla::from(Gauss(Vec3D::new(0.25, 0, -0.5)));
+        assert_eq!(magnetic_field.0, Vec3D::new(25, 0, -50));
+        assert_eq!(Gauss::from(magnetic_field).0, Vec3D::new(0.25, 0, -0.5));
+
+        assert!((Kelvin::from(Celsius(25.0)).0 - 298.15).abs() < 1e-12);
+        assert_eq!(Celsius::from(Kelvin(ZERO_CELSIUS)), Celsius(0.0));
+        assert_eq!(Celsius(20.0) - Celsius(5.0), Celsius(15.0));
+    }
+}
@@ -1,27 +1,180 @@
+#[cfg(feature = "disk-memory")]
+pub mod disk_memory;
+
+/// How much a `Memory` keeps. Serializes as nothing, a number, or a duration, respectively
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum Capacity {
+    Unbounded,                // Keep everything, like a log
+    Items(usize),             // Keep the latest values, like a ring buffer
+    Age(std::time::Duration), // Keep values pushed up to this long before the newest one, like a rolling time window
+}
+
+/// Ring buffer of the latest {capacity} values, which also counts how many values have been pushed in total. See `Capacity` for growing forever or keeping a time window instead.
+/// Serializes as its capacity, count, and contents (oldest to newest), so recordings can be dumped and reloaded as they are.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(
+    try_from = "SerializedMemory<T>",
+    bound(deserialize = "T: serde::Deserialize<'de>") // Instead of T: Default, which serde infers from the skipped fields
+)]
 pub struct Memory<T> {
-    capacity: usize,
+    capacity: Capacity,
+    count: usize,
+    #[serde(rename = "contents")]
+    data: std::collections::VecDeque<T>,
+    #[serde(skip)]
+    arrivals: std::collections::VecDeque<std::time::Instant>, // When each value was pushed. Only kept with `Capacity::Age`
+    #[serde(skip)]
+    on_evict: Option<EvictionHook<T>>,
+}
+
+/// Called with every value that `Memory::push` evicts. See `Memory::set_on_evict`
+pub struct EvictionHook<T>(Box<dyn FnMut(T) + Send>);
+
+impl<T> std::fmt::Debug for EvictionHook<T> {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter.write_str("EvictionHook")
+    }
+}
+
+// Clones and comparisons are about the contents. Clones don't call the original's eviction hook
+impl<T: Clone> Clone for Memory<T> {
+    fn clone(&self) -> Self {
+        Self {
+            capacity: self.capacity,
+            count: self.count,
+            data: self.data.clone(),
+            arrivals: self.arrivals.clone(),
+            on_evict: None,
+        }
+    }
+}
+
+impl<T: PartialEq> PartialEq for Memory<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.capacity == other.capacity && self.count == other.count && self.data == other.data
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct SerializedMemory<T> {
+    capacity: Capacity,
     count: usize,
-    pub data: std::collections::VecDeque<T>,
+    contents: std::collections::VecDeque<T>,
+}
+
+impl<T> TryFrom<SerializedMemory<T>> for Memory<T> {
+    type Error = anyhow::Error;
+
+    fn try_from(memory: SerializedMemory<T>) -> anyhow::Result<Self> {
+        if let Capacity::Items(capacity) = memory.capacity {
+            anyhow::ensure!(
+                memory.contents.len() <= capacity,
+                "Memory holds {} values, but its capacity is {capacity}.",
+                memory.contents.len()
+            );
+        }
+        anyhow::ensure!(
+            memory.contents.len() <= memory.count,
+            "Memory holds {} values, but only {} have been pushed.",
+            memory.contents.len(),
+            memory.count
+        );
+        // Arrival times aren't serialized, so a reloaded time window starts over from now
+        let arrivals = match memory.capacity {
+            Capacity::Age(_) => {
+                std::iter::repeat_n(std::time::Instant::now(), memory.contents.len()).collect()
+            }
+            _ => std::collections::VecDeque::new(),
+        };
+        Ok(Self {
+            capacity: memory.capacity,
+            count: memory.count,
+            data: memory.contents,
+            arrivals,
+            on_evict: None,
+        })
+    }
 }
 
 impl<T> Memory<T> {
     pub fn new(capacity: usize) -> Self {
+        Self::with_limit(Capacity::Items(capacity))
+    }
+
+    /// Memory that keeps values as {capacity} says, e.g., `Capacity::Unbounded` for a log of a whole recording
+    pub fn with_limit(capacity: Capacity) -> Self {
         Self {
             capacity,
             count: 0,
             data: std::collections::VecDeque::new(),
+            arrivals: std::collections::VecDeque::new(),
+            on_evict: None,
         }
     }
 
-    pub fn push(&mut self, value: T) -> Option<T> {
-        let mut result = None;
-        if self.data.len() == self.capacity {
-            result = self.data.pop_front();
+    /// Like `new`, but allocates room for all {capacity} values up front, so that pushing never has to reallocate, e.g., in the middle of an acquisition
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            data: std::collections::VecDeque::with_capacity(capacity),
+            ..Self::new(capacity)
         }
+    }
 
+    /// Adds a value, and returns the oldest one if it had to make room. With an eviction hook, the hook gets the evicted value instead.
+    /// With `Capacity::Age`, several values can expire at once. Only the newest of them is returned, so use a hook to get all of them
+    pub fn push(&mut self, value: T) -> Option<T> {
+        // Making room first keeps a preallocated ring buffer from reallocating. Values only expire by age once the new one is in
+        let evicted = self.evict(1);
+        if let Capacity::Age(_) = self.capacity {
+            self.arrivals.push_back(std::time::Instant::now());
+        }
         self.data.push_back(value);
         self.count += 1;
-        result
+        self.evict(0).or(evicted)
+    }
+
+    // Removes the oldest values until {incoming} more fit, and hands them to the eviction hook. Returns the newest of them if there is no hook
+    fn evict(&mut self, incoming: usize) -> Option<T> {
+        let expired = match self.capacity {
+            Capacity::Unbounded => 0,
+            Capacity::Items(capacity) => (self.data.len() + incoming)
+                .saturating_sub(capacity)
+                .min(self.data.len()),
+            Capacity::Age(max_age) => match self.arrivals.back() {
+                Some(&newest) => self
+                    .arrivals
+                    .iter()
+                    .take_while(|&&arrival| newest.duration_since(arrival) > max_age)
+                    .count(),
+                None => 0,
+            },
+        };
+        self.remove_oldest(expired);
+        let evicted = self.data.drain(..expired);
+        match &mut self.on_evict {
+            Some(EvictionHook(on_evict)) => {
+                evicted.for_each(on_evict);
+                None
+            }
+            None => evicted.last(),
+        }
+    }
+
+    // Forgets the arrival times of the oldest {count} values, along with the values themselves
+    fn remove_oldest(&mut self, count: usize) {
+        let count = count.min(self.arrivals.len());
+        self.arrivals.drain(..count);
+    }
+
+    /// Hands every value evicted by `push` to {on_evict}, e.g., to stream it to disk or send it over a channel instead of losing it.
+    /// Values removed explicitly, with `drain`, `take_all`, or `clear`, don't pass through the hook
+    pub fn set_on_evict(&mut self, on_evict: impl FnMut(T) + Send + 'static) {
+        self.on_evict = Some(EvictionHook(Box::new(on_evict)));
+    }
+
+    pub fn remove_on_evict(&mut self) {
+        self.on_evict = None;
     }
 
     pub fn get(&self, index: usize) -> Option<&T> {
@@ -39,6 +192,216 @@ impl<T> Memory<T> {
     pub fn count(&self) -> usize {
         self.count
     }
+
+    /// Maximum number of values, which is unlimited (`usize::MAX`) unless the memory was created with `Capacity::Items`
+    pub fn capacity(&self) -> usize {
+        match self.capacity {
+            Capacity::Items(capacity) => capacity,
+            Capacity::Unbounded | Capacity::Age(_) => usize::MAX,
+        }
+    }
+
+    pub fn limit(&self) -> Capacity {
+        self.capacity
+    }
+
+    /// Most recently pushed value
+    pub fn last(&self) -> Option<&T> {
+        self.data.back()
+    }
+
+    /// Most recently pushed value, like `last`, for symmetry with `latest_n`
+    pub fn latest(&self) -> Option<&T> {
+        self.data.back()
+    }
+
+    /// The newest {n} values (or all, if there are fewer), oldest to newest
+    pub fn latest_n(&self, n: usize) -> std::collections::vec_deque::Iter<'_, T> {
+        self.data.range(self.data.len().saturating_sub(n)..)
+    }
+
+    /// Value {index} places before the newest one, e.g., 0 for the newest, 1 for the one before it
+    pub fn from_end(&self, index: usize) -> Option<&T> {
+        self.data
+            .len()
+            .checked_sub(index + 1)
+            .and_then(|index| self.data.get(index))
+    }
+
+    /// Oldest to newest
+    pub fn iter(&self) -> std::collections::vec_deque::Iter<'_, T> {
+        self.data.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::collections::vec_deque::IterMut<'_, T> {
+        self.data.iter_mut()
+    }
+
+    /// Contents as two slices, oldest to newest, since the ring buffer may wrap around. See `VecDeque::as_slices`
+    pub fn as_slices(&self) -> (&[T], &[T]) {
+        self.data.as_slices()
+    }
+
+    /// Contents as one slice, oldest to newest, moving them around if the ring buffer wraps around. See `VecDeque::make_contiguous`
+    pub fn make_contiguous(&mut self) -> &mut [T] {
+        self.data.make_contiguous()
+    }
+
+    /// Forgets the contents, but not how many values have been pushed in total
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.arrivals.clear();
+    }
+
+    /// Removes the values in {range} (indices from oldest to newest), and returns them in order. See `VecDeque::drain`
+    pub fn drain<R: std::ops::RangeBounds<usize>>(
+        &mut self,
+        range: R,
+    ) -> std::collections::vec_deque::Drain<'_, T> {
+        if !self.arrivals.is_empty() {
+            self.arrivals
+                .drain((range.start_bound().cloned(), range.end_bound().cloned()));
+        }
+        self.data.drain(range)
+    }
+
+    /// Writes the oldest {count} values (or all, if there are fewer) to {writer}, e.g., a file or a `logging::Sink`, in one batch with {serialize}, and removes them.
+    /// Values are only removed once they have been written, so nothing is lost if writing fails. Returns the number of values written
+    pub fn flush_to<W>(
+        &mut self,
+        writer: &mut W,
+        count: usize,
+        serialize: impl FnOnce(&mut W, &[T]) -> anyhow::Result<()>,
+    ) -> anyhow::Result<usize> {
+        let count = count.min(self.data.len());
+        serialize(writer, &self.data.make_contiguous()[..count])?;
+        self.remove_oldest(count);
+        self.data.drain(..count);
+        Ok(count)
+    }
+
+    /// Removes and returns all values, oldest to newest, e.g., for flushing them to disk. The total count is kept
+    pub fn take_all(&mut self) -> Vec<T> {
+        self.arrivals.clear();
+        self.data.drain(..).collect()
+    }
+}
+
+impl<T> Memory<T> {
+    /// Every {factor}th value, oldest to newest, starting with the oldest one, e.g., to show a 1 kHz recording at 10 Hz. A factor of 0 counts as 1
+    pub fn decimate(&self, factor: usize) -> impl Iterator<Item = &T> {
+        self.data.iter().step_by(factor.max(1))
+    }
+
+    /// Mean of every bucket of {factor} consecutive values, after extracting the part to average with {value}, e.g., the acceleration of recorded samples.
+    /// Averaging smooths out what simple decimation would alias. A trailing incomplete bucket is left out
+    pub fn decimate_mean_by<'a, U: crate::math::filter::Signal>(
+        &'a self,
+        factor: usize,
+        value: impl Fn(&T) -> U + 'a,
+    ) -> impl Iterator<Item = U> + 'a {
+        let factor = factor.max(1);
+        (0..self.data.len() / factor).map(move |bucket| {
+            self.data
+                .range(bucket * factor..(bucket + 1) * factor)
+                .fold(U::default(), |sum, element| sum + value(element))
+                * (1.0 / factor as f64)
+        })
+    }
+}
+
+impl<T: crate::math::filter::Signal> Memory<T> {
+    /// Mean of every bucket of {factor} consecutive values. See `decimate_mean_by`
+    pub fn decimate_mean(&self, factor: usize) -> impl Iterator<Item = T> + '_ {
+        self.decimate_mean_by(factor, |value| *value)
+    }
+}
+
+impl<T: Clone> Memory<T> {
+    /// Pushes all of {values} at once, e.g., a batch read from the sensor's FIFO. Evicted values go to the eviction hook, if there is one, and are dropped otherwise
+    pub fn push_slice(&mut self, values: &[T]) {
+        let Capacity::Items(capacity) = self.capacity else {
+            if let Capacity::Age(_) = self.capacity {
+                let now = std::time::Instant::now();
+                self.arrivals.extend(std::iter::repeat_n(now, values.len()));
+            }
+            self.data.extend(values.iter().cloned());
+            self.count += values.len();
+            self.evict(0);
+            return;
+        };
+
+        // Values that don't fit even into an empty memory are evicted right away, after everything that is already in it
+        let kept = values.len().min(capacity);
+        let (evicted, kept) = values.split_at(values.len() - kept);
+        let overflow = (self.data.len() + kept.len()).saturating_sub(capacity);
+        let overflow = self.data.drain(..overflow);
+        match &mut self.on_evict {
+            Some(EvictionHook(on_evict)) => {
+                overflow.chain(evicted.iter().cloned()).for_each(on_evict)
+            }
+            None => drop(overflow),
+        }
+        self.data.extend(kept.iter().cloned());
+        self.count += values.len();
+    }
+
+    /// Copy of the contents, oldest to newest, leaving the memory as it is
+    pub fn snapshot(&self) -> Vec<T> {
+        self.data.iter().cloned().collect()
+    }
+}
+
+/// Pushes every value, as if one after another
+impl<T> Extend<T> for Memory<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, values: I) {
+        for value in values {
+            self.push(value);
+        }
+    }
+}
+
+/// Memory with just enough capacity for all values
+impl<T> FromIterator<T> for Memory<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(values: I) -> Self {
+        let data = values
+            .into_iter()
+            .collect::<std::collections::VecDeque<_>>();
+        Self {
+            capacity: Capacity::Items(data.len()),
+            count: data.len(),
+            data,
+            arrivals: std::collections::VecDeque::new(),
+            on_evict: None,
+        }
+    }
+}
+
+impl<T> IntoIterator for Memory<T> {
+    type Item = T;
+    type IntoIter = std::collections::vec_deque::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.data.into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Memory<T> {
+    type Item = &'a T;
+    type IntoIter = std::collections::vec_deque::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.data.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut Memory<T> {
+    type Item = &'a mut T;
+    type IntoIter = std::collections::vec_deque::IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.data.iter_mut()
+    }
 }
 
 impl<T> std::ops::Index<usize> for Memory<T> {
@@ -54,3 +417,786 @@ impl<T> std::ops::IndexMut<usize> for Memory<T> {
         self.data.index_mut(index)
     }
 }
+
+/// Ring buffer of the latest {CAPACITY} values in a fixed-size array, without any heap allocation.
+/// Works like `Memory`, for small `Copy` values like samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FixedMemory<T: Copy + Default, const CAPACITY: usize> {
+    data: [T; CAPACITY],
+    start: usize, // Index of the oldest value in data
+    len: usize,
+    count: usize,
+}
+
+impl<T: Copy + Default, const CAPACITY: usize> Default for FixedMemory<T, CAPACITY> {
+    fn default() -> Self {
+        Self {
+            data: [T::default(); CAPACITY],
+            start: 0,
+            len: 0,
+            count: 0,
+        }
+    }
+}
+
+impl<T: Copy + Default, const CAPACITY: usize> FixedMemory<T, CAPACITY> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a value, and returns the oldest one if it had to make room
+    pub fn push(&mut self, value: T) -> Option<T> {
+        if CAPACITY == 0 {
+            return Some(value);
+        }
+        self.count += 1;
+        if self.len < CAPACITY {
+            self.data[(self.start + self.len) % CAPACITY] = value;
+            self.len += 1;
+            None
+        } else {
+            let evicted = std::mem::replace(&mut self.data[self.start], value);
+            self.start = (self.start + 1) % CAPACITY;
+            Some(evicted)
+        }
+    }
+
+    /// Value at {index}, counting from the oldest one
+    pub fn get(&self, index: usize) -> Option<&T> {
+        (index < self.len).then(|| &self.data[(self.start + index) % CAPACITY])
+    }
+
+    pub fn last(&self) -> Option<&T> {
+        self.len.checked_sub(1).and_then(|index| self.get(index))
+    }
+
+    /// Oldest to newest
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        (0..self.len).map(move |index| &self.data[(self.start + index) % CAPACITY])
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn capacity(&self) -> usize {
+        CAPACITY
+    }
+
+    /// Forgets the contents, but not how many values have been pushed in total
+    pub fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+    }
+}
+
+impl<T: Copy + Default, const CAPACITY: usize> std::ops::Index<usize> for FixedMemory<T, CAPACITY> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        self.get(index).expect("Index out of range")
+    }
+}
+
+/// When `TimedMemory` forgets its oldest values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eviction {
+    Count(usize),             // Keep the latest values, like `Memory`
+    Age(std::time::Duration), // Keep values up to this much older than the newest one
+}
+
+/// Values with the instant they were taken at, in chronological order, for queries like "everything from the last 2 seconds".
+pub struct TimedMemory<T> {
+    pub eviction: Eviction,
+    count: usize,
+    data: std::collections::VecDeque<(T, std::time::Instant)>,
+}
+
+impl<T> TimedMemory<T> {
+    pub fn new(eviction: Eviction) -> Self {
+        Self {
+            eviction,
+            count: 0,
+            data: std::collections::VecDeque::new(),
+        }
+    }
+
+    /// Adds {value}, taken at {instant}. Values pushed out of order are sorted in. Evicted values are dropped
+    pub fn push(&mut self, value: T, instant: std::time::Instant) {
+        let index = self.data.partition_point(|(_, other)| *other <= instant);
+        self.data.insert(index, (value, instant));
+        self.count += 1;
+
+        match self.eviction {
+            Eviction::Count(capacity) => {
+                while self.data.len() > capacity {
+                    self.data.pop_front();
+                }
+            }
+            Eviction::Age(max_age) => {
+                let newest = self.newest().unwrap_or(instant);
+                while self
+                    .data
+                    .front()
+                    .is_some_and(|(_, oldest)| newest.duration_since(*oldest) > max_age)
+                {
+                    self.data.pop_front();
+                }
+            }
+        }
+    }
+
+    /// Values taken from {since} up to and including {until}, oldest to newest
+    pub fn range(
+        &self,
+        since: std::time::Instant,
+        until: std::time::Instant,
+    ) -> impl Iterator<Item = &(T, std::time::Instant)> {
+        let start = self.data.partition_point(|(_, instant)| *instant < since);
+        let end = self.data.partition_point(|(_, instant)| *instant <= until);
+        self.data.range(start..end.max(start))
+    }
+
+    /// Values taken at most {duration} before the newest one
+    pub fn within(
+        &self,
+        duration: std::time::Duration,
+    ) -> impl Iterator<Item = &(T, std::time::Instant)> {
+        let start = self.newest().map_or(self.data.len(), |newest| {
+            self.data
+                .partition_point(|(_, instant)| newest.duration_since(*instant) > duration)
+        });
+        self.data.range(start..)
+    }
+
+    /// The latest {count} values, oldest to newest
+    pub fn latest(&self, count: usize) -> impl Iterator<Item = &(T, std::time::Instant)> {
+        self.data.range(self.data.len().saturating_sub(count)..)
+    }
+
+    /// Time between the oldest and newest value
+    pub fn duration(&self) -> std::time::Duration {
+        match (self.data.front(), self.data.back()) {
+            (Some((_, oldest)), Some((_, newest))) => newest.duration_since(*oldest),
+            _ => std::time::Duration::ZERO,
+        }
+    }
+
+    pub fn newest(&self) -> Option<std::time::Instant> {
+        self.data.back().map(|(_, instant)| *instant)
+    }
+
+    pub fn iter(&self) -> std::collections::vec_deque::Iter<'_, (T, std::time::Instant)> {
+        self.data.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Number of values pushed in total, including evicted ones
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+}
+
+/// What a `MemoryProducer` does when the consumer falls behind and the shared memory is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    #[default]
+    Overwrite, // Drop the oldest value, like `Memory::push`. The sampling thread never waits
+    Block, // Wait until the consumer makes room. No value is lost
+}
+
+/// Ring buffer of {capacity} values, shared between a producer thread (e.g., sampling) and a consumer thread (e.g., logging).
+/// Built on a bounded lock-free channel, so pushing and popping never lock the whole buffer.
+pub fn shared_memory<T>(
+    capacity: usize,
+    policy: OverflowPolicy,
+) -> (MemoryProducer<T>, MemoryConsumer<T>) {
+    let (sender, receiver) = crossbeam_channel::bounded(capacity);
+    let overwritten = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    (
+        MemoryProducer {
+            policy,
+            sender,
+            receiver: receiver.clone(),
+            overwritten: overwritten.clone(),
+        },
+        MemoryConsumer {
+            receiver,
+            overwritten,
+        },
+    )
+}
+
+pub struct MemoryProducer<T> {
+    pub policy: OverflowPolicy,
+    sender: crossbeam_channel::Sender<T>,
+    receiver: crossbeam_channel::Receiver<T>, // For dropping the oldest value when overwriting
+    overwritten: std::sync::Arc<std::sync::atomic::AtomicUsize>, // Shared with the consumer
+}
+
+impl<T> MemoryProducer<T> {
+    /// Adds a value, and wakes up the consumer if it is waiting. Fails if the consumer is gone.
+    pub fn push(&self, value: T) -> anyhow::Result<()> {
+        let mut value = value;
+        loop {
+            // Only the producer and the consumer hold the counter. The producer's own receiver keeps the channel connected, so this is the only way to notice
+            anyhow::ensure!(
+                std::sync::Arc::strong_count(&self.overwritten) > 1,
+                "Shared memory consumer has been dropped."
+            );
+            let rejected = match self.policy {
+                OverflowPolicy::Block => {
+                    match self
+                        .sender
+                        .send_timeout(value, std::time::Duration::from_millis(100))
+                    {
+                        Ok(()) => return Ok(()),
+                        Err(error) => error.into_inner(),
+                    }
+                }
+                OverflowPolicy::Overwrite => match self.sender.try_send(value) {
+                    Ok(()) => return Ok(()),
+                    Err(error) => {
+                        // The consumer may have made room in the meantime, so this can come up empty
+                        if self.receiver.try_recv().is_ok() {
+                            self.overwritten
+                                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        }
+                        error.into_inner()
+                    }
+                },
+            };
+            value = rejected;
+        }
+    }
+}
+
+pub struct MemoryConsumer<T> {
+    receiver: crossbeam_channel::Receiver<T>,
+    overwritten: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+}
+
+impl<T> MemoryConsumer<T> {
+    /// Oldest value, if there is one
+    pub fn try_pop(&self) -> Option<T> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Oldest value, waiting up to {timeout} (or forever) for one to arrive. None if the time runs out, or if the producer is gone and everything has been popped
+    pub fn pop(&self, timeout: Option<std::time::Duration>) -> Option<T> {
+        match timeout {
+            Some(timeout) => self.receiver.recv_timeout(timeout).ok(),
+            None => self.receiver.recv().ok(),
+        }
+    }
+
+    /// Everything available right now, oldest to newest, without waiting
+    pub fn drain(&self) -> impl Iterator<Item = T> + '_ {
+        self.receiver.try_iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.receiver.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.receiver.is_empty()
+    }
+
+    /// Number of values the producer has dropped to make room, with `OverflowPolicy::Overwrite`
+    pub fn overwritten(&self) -> usize {
+        self.overwritten.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// True once the producer is gone. Values pushed before can still be popped
+    pub fn is_disconnected(&self) -> bool {
+        std::sync::Arc::strong_count(&self.overwritten) == 1
+    }
+}
+
+pub enum Backoff {
+    None,
+    Constant(std::time::Duration),
+    Exponential {
+        initial: std::time::Duration,
+        factor: f64,
+        max: std::time::Duration,
+    },
+}
+
+impl Backoff {
+    /// Delays growing from {initial} by {factor} with every attempt, up to {max}. Err unless {factor} is finite and not negative
+    pub fn exponential(
+        initial: std::time::Duration,
+        factor: f64,
+        max: std::time::Duration,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            factor.is_finite() && factor >= 0.0,
+            "Invalid backoff factor {factor}."
+        );
+        Ok(Backoff::Exponential {
+            initial,
+            factor,
+            max,
+        })
+    }
+
+    /// Time to wait before retrying after failed attempt number {attempt} (starting at 1)
+    pub fn delay(&self, attempt: usize) -> std::time::Duration {
+        match self {
+            Backoff::None => std::time::Duration::ZERO,
+            Backoff::Constant(delay) => *delay,
+            Backoff::Exponential {
+                initial,
+                factor,
+                max,
+            } => {
+                // In seconds, since the delay outgrows a `Duration` after an hour or so of doubling. Beyond that, or with an invalid factor, it is {max}
+                let exponent = i32::try_from(attempt.saturating_sub(1)).unwrap_or(i32::MAX);
+                let delay = initial.as_secs_f64() * factor.powi(exponent);
+                std::time::Duration::try_from_secs_f64(delay).map_or(*max, |delay| delay.min(*max))
+            }
+        }
+    }
+}
+
+pub type ErrorCallback = Box<dyn Fn(&dyn std::error::Error, usize) + Send + Sync>;
+
+/// How often, and how patiently, to retry fallible operations like bus transfers.
+pub struct RetryPolicy {
+    pub attempts: usize, // Total number of attempts, including the first one
+    pub backoff: Backoff,
+    pub on_error: Option<ErrorCallback>, // Called with the error and attempt number of every failed attempt
+}
+
+impl RetryPolicy {
+    pub fn run<T, E: std::error::Error>(
+        &self,
+        mut operation: impl FnMut() -> Result<T, E>,
+    ) -> Result<T, E> {
+        let mut attempt = 1;
+        loop {
+            match operation() {
+                Ok(value) => return Ok(value),
+                Err(error) => {
+                    if let Some(on_error) = &self.on_error {
+                        on_error(&error, attempt);
+                    }
+                    if attempt >= self.attempts {
+                        return Err(error);
+                    }
+                    std::thread::sleep(self.backoff.delay(attempt));
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    // No retries, so errors surface just like without a policy
+    fn default() -> Self {
+        Self {
+            attempts: 1,
+            backoff: Backoff::None,
+            on_error: None,
+        }
+    }
+}
+
+/// Shared flag for asking long running operations, like calibration, to stop early. Clones share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(std::sync::Arc<std::sync::atomic::AtomicBool>);
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.0.store(true, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Makes the token usable again, for the next operation
+    pub fn reset(&self) {
+        self.0.store(false, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_memory_overwrite_and_disconnection() {
+        let (producer, consumer) = shared_memory(3, OverflowPolicy::Overwrite);
+        for value in 0..5 {
+            producer.push(value).unwrap();
+        }
+        assert_eq!(consumer.len(), 3);
+        assert_eq!(consumer.overwritten(), 2);
+        assert_eq!(consumer.drain().collect::<Vec<_>>(), [2, 3, 4]);
+        assert_eq!(consumer.try_pop(), None);
+
+        drop(consumer);
+        assert!(producer.push(5).is_err());
+
+        // A blocked producer notices too
+        let (producer, consumer) = shared_memory(1, OverflowPolicy::Block);
+        producer.push(0).unwrap();
+        let sampling = std::thread::spawn(move || producer.push(1));
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        drop(consumer);
+        assert!(sampling.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_shared_memory_block() {
+        let (producer, consumer) = shared_memory(2, OverflowPolicy::Block);
+        let sampling = std::thread::spawn(move || {
+            for value in 0..100 {
+                producer.push(value).unwrap();
+            }
+        });
+        let mut values = Vec::new();
+        while let Some(value) = consumer.pop(Some(std::time::Duration::from_secs(5))) {
+            values.push(value);
+        }
+        sampling.join().unwrap();
+        assert_eq!(values, (0..100).collect::<Vec<_>>());
+        assert_eq!(consumer.overwritten(), 0);
+        assert!(consumer.is_disconnected());
+    }
+
+    #[test]
+    fn test_drain_and_snapshot() {
+        let mut memory = Memory::new(4);
+        for value in 0..6 {
+            memory.push(value);
+        }
+        assert_eq!(memory.snapshot(), [2, 3, 4, 5]);
+        assert_eq!(memory.drain(..2).collect::<Vec<_>>(), [2, 3]);
+        assert_eq!(memory.len(), 2);
+        assert_eq!(memory.take_all(), [4, 5]);
+        assert!(memory.is_empty());
+        assert_eq!(memory.count(), 6);
+    }
+
+    #[test]
+    fn test_timed_memory() {
+        let start = std::time::Instant::now();
+        let at = |milliseconds| start + std::time::Duration::from_millis(milliseconds);
+
+        let mut memory = TimedMemory::new(Eviction::Age(std::time::Duration::from_secs(2)));
+        for (value, milliseconds) in [(0, 0), (1, 500), (3, 1500), (2, 1000), (4, 2200)] {
+            memory.push(value, at(milliseconds));
+        }
+        // The sample at 0 ms is more than 2 s older than the one at 2200 ms. The one at 1000 ms was sorted in
+        fn values<'a>(samples: impl Iterator<Item = &'a (i32, std::time::Instant)>) -> Vec<i32> {
+            samples.map(|(value, _)| *value).collect()
+        }
+        assert_eq!(values(memory.iter()), [1, 2, 3, 4]);
+        assert_eq!(memory.duration(), std::time::Duration::from_millis(1700));
+        assert_eq!(values(memory.range(at(1000), at(1500))), [2, 3]);
+        assert!(values(memory.range(at(1600), at(1000))).is_empty());
+        assert_eq!(
+            values(memory.within(std::time::Duration::from_millis(1200))),
+            [2, 3, 4]
+        );
+        assert_eq!(values(memory.latest(2)), [3, 4]);
+        assert_eq!(values(memory.latest(10)).len(), 4);
+        assert_eq!(memory.count(), 5);
+
+        let mut memory = TimedMemory::new(Eviction::Count(2));
+        for milliseconds in 0..5 {
+            memory.push(milliseconds, at(milliseconds));
+        }
+        assert_eq!(memory.len(), 2);
+        assert_eq!(memory.newest(), Some(at(4)));
+    }
+
+    #[test]
+    fn test_decimation() {
+        let mut memory = Memory::new(10);
+        for value in 0..12 {
+            memory.push(crate::math::Vec3D::new(value, -value, 0));
+        }
+        // Holds 2 to 11
+        assert_eq!(
+            memory.decimate(4).map(|value| value.x).collect::<Vec<_>>(),
+            [2.0, 6.0, 10.0]
+        );
+        assert_eq!(
+            memory.decimate_mean(4).collect::<Vec<_>>(),
+            [
+                crate::math::Vec3D::new(3.5, -3.5, 0.0),
+                crate::math::Vec3D::new(7.5, -7.5, 0.0)
+            ]
+        );
+        assert_eq!(
+            memory
+                .decimate_mean_by(5, |value| value.x)
+                .collect::<Vec<_>>(),
+            [4.0, 9.0]
+        );
+        assert_eq!(memory.decimate(0).count(), 10);
+    }
+
+    #[test]
+    fn test_eviction_hook() {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let mut memory = Memory::new(2);
+        memory.set_on_evict(move |value| sender.send(value).unwrap());
+        for value in 0..5 {
+            assert_eq!(memory.push(value), None);
+        }
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), [0, 1, 2]);
+        assert_eq!(memory.clone(), memory);
+
+        memory.remove_on_evict();
+        assert_eq!(memory.push(5), Some(3));
+        assert!(receiver.is_empty());
+    }
+
+    #[test]
+    fn test_flush_to() {
+        let mut memory = Memory::new(5);
+        for value in 0..5 {
+            memory.push(value);
+        }
+        let mut file = Vec::new();
+        let yaml = |writer: &mut Vec<u8>, values: &[i32]| {
+            serde_yaml::to_writer(writer, values)?;
+            Ok(())
+        };
+        assert_eq!(memory.flush_to(&mut file, 3, yaml).unwrap(), 3);
+        assert_eq!(memory.snapshot(), [3, 4]);
+        let values: Vec<i32> = serde_yaml::from_slice(&file).unwrap();
+        assert_eq!(values, [0, 1, 2]);
+
+        // Failing writes keep the values
+        assert!(memory
+            .flush_to(&mut file, 10, |_, _| Err(anyhow::anyhow!("Disk full")))
+            .is_err());
+        assert_eq!(memory.len(), 2);
+    }
+
+    #[test]
+    fn test_preallocation() {
+        let mut memory = Memory::with_capacity(100);
+        let allocated = memory.data.capacity();
+        assert!(allocated >= 100);
+        for value in 0..1000 {
+            memory.push(value);
+        }
+        assert_eq!(memory.data.capacity(), allocated);
+
+        let mut fixed = FixedMemory::<u32, 3>::new();
+        assert_eq!(fixed.last(), None);
+        for value in 0..5 {
+            assert_eq!(fixed.push(value), value.checked_sub(3));
+        }
+        assert_eq!(fixed.iter().copied().collect::<Vec<_>>(), [2, 3, 4]);
+        assert_eq!(fixed[0], 2);
+        assert_eq!(fixed.last(), Some(&4));
+        assert_eq!(fixed.get(3), None);
+        assert_eq!((fixed.len(), fixed.count(), fixed.capacity()), (3, 5, 3));
+        fixed.clear();
+        assert!(fixed.is_empty());
+    }
+
+    #[test]
+    fn test_bulk_insertion() {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let mut memory = Memory::new(4);
+        memory.set_on_evict(move |value| sender.send(value).unwrap());
+        memory.extend(0..3);
+        memory.push_slice(&[3, 4]);
+        assert_eq!(memory.snapshot(), [1, 2, 3, 4]);
+        memory.push_slice(&[5, 6, 7, 8, 9, 10]);
+        assert_eq!(memory.snapshot(), [7, 8, 9, 10]);
+        assert_eq!(memory.count(), 11);
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            [0, 1, 2, 3, 4, 5, 6]
+        );
+
+        let memory = (0..5).collect::<Memory<_>>();
+        assert_eq!((memory.len(), memory.capacity(), memory.count()), (5, 5, 5));
+    }
+
+    #[test]
+    fn test_latest() {
+        let mut memory = Memory::new(4);
+        assert_eq!(memory.latest(), None);
+        assert_eq!(memory.from_end(0), None);
+        memory.extend(0..6);
+        assert_eq!(memory.latest(), Some(&5));
+        assert_eq!(memory.from_end(1), Some(&4));
+        assert_eq!(memory.from_end(3), Some(&2));
+        assert_eq!(memory.from_end(4), None);
+        assert_eq!(memory.latest_n(2).copied().collect::<Vec<_>>(), [4, 5]);
+        assert_eq!(memory.latest_n(10).count(), 4);
+    }
+
+    #[test]
+    fn test_iteration() {
+        let mut memory = Memory::new(3);
+        assert_eq!(memory.last(), None);
+        memory.extend(1..=5);
+        assert_eq!(memory.last(), Some(&5));
+        assert_eq!(memory.iter().copied().collect::<Vec<_>>(), [3, 4, 5]);
+
+        for value in &mut memory {
+            *value *= 10;
+        }
+        memory.iter_mut().for_each(|value| *value += 1);
+        assert_eq!((&memory).into_iter().sum::<i32>(), 31 + 41 + 51);
+
+        // The ring buffer wraps around after the evictions, but reads oldest to newest either way
+        let (front, back) = memory.as_slices();
+        assert_eq!([front, back].concat(), [31, 41, 51]);
+        assert_eq!(memory.make_contiguous(), [31, 41, 51]);
+        assert_eq!(memory.clone().into_iter().collect::<Vec<_>>(), [31, 41, 51]);
+
+        memory.clear();
+        assert!(memory.is_empty() && memory.iter().next().is_none());
+        assert_eq!(memory.count(), 5);
+    }
+
+    #[test]
+    fn test_memory_serialization() {
+        let mut memory = Memory::new(3);
+        for value in 0..5 {
+            memory.push(value);
+        }
+        let yaml = serde_yaml::to_string(&memory).unwrap();
+        let reloaded: Memory<i32> = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(reloaded, memory);
+        assert_eq!(reloaded.count(), 5);
+        assert_eq!(reloaded.iter().copied().collect::<Vec<_>>(), [2, 3, 4]);
+
+        assert!(
+            serde_yaml::from_str::<Memory<i32>>("{capacity: 1, count: 2, contents: [1, 2]}")
+                .is_err()
+        );
+        assert!(
+            serde_yaml::from_str::<Memory<i32>>("{capacity: 3, count: 1, contents: [1, 2]}")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_backoff() {
+        let second = std::time::Duration::from_secs(1);
+        let minute = std::time::Duration::from_secs(60);
+        let backoff = Backoff::exponential(second, 2.0, minute).unwrap();
+        assert_eq!(backoff.delay(1), second);
+        assert_eq!(backoff.delay(3), 4 * second);
+        assert_eq!(backoff.delay(7), minute);
+        // Retrying for hours, long past what a `Duration` holds
+        assert_eq!(backoff.delay(65), minute);
+        assert_eq!(backoff.delay(usize::MAX), minute);
+
+        assert!(Backoff::exponential(second, f64::NAN, minute).is_err());
+        assert!(Backoff::exponential(second, f64::INFINITY, minute).is_err());
+        assert!(Backoff::exponential(second, -2.0, minute).is_err());
+        let invalid = Backoff::Exponential {
+            initial: second,
+            factor: -2.0,
+            max: minute,
+        };
+        assert_eq!(invalid.delay(2), minute);
+        assert_eq!(Backoff::Constant(second).delay(100), second);
+        assert_eq!(Backoff::None.delay(1), std::time::Duration::ZERO);
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        let failures = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = failures.clone();
+        let policy = RetryPolicy {
+            attempts: 3,
+            backoff: Backoff::Constant(std::time::Duration::from_millis(1)),
+            on_error: Some(Box::new(move |_, _| {
+                counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            })),
+        };
+
+        // Succeeding on the third attempt
+        let mut attempts = 0;
+        let result = policy.run(|| {
+            attempts += 1;
+            match attempts {
+                3 => Ok(attempts),
+                _ => Err(std::fmt::Error),
+            }
+        });
+        assert_eq!(result, Ok(3));
+        assert_eq!(failures.load(std::sync::atomic::Ordering::Relaxed), 2);
+
+        // Giving up after the last attempt, with its error
+        let mut attempts = 0;
+        let result: Result<(), _> = policy.run(|| {
+            attempts += 1;
+            Err(std::fmt::Error)
+        });
+        assert_eq!((result, attempts), (Err(std::fmt::Error), 3));
+        assert_eq!(failures.load(std::sync::atomic::Ordering::Relaxed), 5);
+
+        let mut attempts = 0;
+        let result: Result<(), _> = RetryPolicy::default().run(|| {
+            attempts += 1;
+            Err(std::fmt::Error)
+        });
+        assert!(result.is_err() && attempts == 1);
+    }
+
+    #[test]
+    fn test_capacity_modes() {
+        let mut log = Memory::with_limit(Capacity::Unbounded);
+        for value in 0..1000 {
+            assert_eq!(log.push(value), None);
+        }
+        log.push_slice(&[1000, 1001]);
+        assert_eq!((log.len(), log.capacity()), (1002, usize::MAX));
+
+        let mut window = Memory::with_limit(Capacity::Age(std::time::Duration::from_millis(50)));
+        let evicted = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = evicted.clone();
+        window.set_on_evict(move |value| sink.lock().unwrap().push(value));
+        window.push_slice(&[0, 1]);
+        window.push(2);
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        window.push(3);
+        assert_eq!(window.iter().copied().collect::<Vec<_>>(), [3]);
+        assert_eq!(*evicted.lock().unwrap(), [0, 1, 2]);
+        assert_eq!(window.drain(..).collect::<Vec<_>>(), [3]);
+
+        let yaml = serde_yaml::to_string(&Memory::<i32>::with_limit(Capacity::Unbounded)).unwrap();
+        let reloaded: Memory<i32> = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(reloaded.limit(), Capacity::Unbounded);
+        let mut ring = Memory::new(0);
+        assert_eq!(ring.push(1), Some(1));
+    }
+}
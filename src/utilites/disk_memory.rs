@@ -0,0 +1,277 @@
+use std::{fs::OpenOptions, marker::PhantomData, path::Path};
+
+use anyhow::{ensure, Context, Result};
+use memmap2::MmapMut;
+use serde::{de::DeserializeOwned, Serialize};
+
+// File layout: Two header slots, then {capacity} record slots.
+// Headers are written alternately, so a header torn by power loss still leaves the other one intact.
+// Every record carries its index and a checksum, so records that didn't make it to disk before power loss are recognized and dropped when reopening.
+
+const MAGIC: &[u8; 8] = b"NJORDMEM";
+const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 64;
+const RECORD_HEADER_SIZE: usize = 20; // Checksum (8), index (8), length (4)
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Header {
+    record_size: usize, // [bytes] Maximum size of a serialized value
+    capacity: usize,
+    count: usize, // Values pushed in total
+}
+
+impl Header {
+    fn write(&self, bytes: &mut [u8]) {
+        bytes[..8].copy_from_slice(MAGIC);
+        bytes[8..12].copy_from_slice(&VERSION.to_le_bytes());
+        bytes[12..16].copy_from_slice(&(self.record_size as u32).to_le_bytes());
+        bytes[16..24].copy_from_slice(&(self.capacity as u64).to_le_bytes());
+        bytes[24..32].copy_from_slice(&(self.count as u64).to_le_bytes());
+        let checksum = checksum(&bytes[..32]);
+        bytes[32..40].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    // None if the header is torn or was never written
+    fn read(bytes: &[u8]) -> Option<Self> {
+        let checksum_matches =
+            u64::from_le_bytes(bytes[32..40].try_into().ok()?) == checksum(&bytes[..32]);
+        let version = u32::from_le_bytes(bytes[8..12].try_into().ok()?);
+        (&bytes[..8] == MAGIC && version == VERSION && checksum_matches).then(|| Self {
+            record_size: u32::from_le_bytes(bytes[12..16].try_into().unwrap()) as usize,
+            capacity: u64::from_le_bytes(bytes[16..24].try_into().unwrap()) as usize,
+            count: u64::from_le_bytes(bytes[24..32].try_into().unwrap()) as usize,
+        })
+    }
+}
+
+// FNV-1a. Catches torn and stale writes, which is all this needs
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Ring buffer of the latest {capacity} values in a memory-mapped file, for recordings that outgrow RAM or need to survive power loss.
+/// Works like `Memory`, but values are serialized, so they are returned by value. Each serialized value may take up to {record_size} bytes.
+/// Pushed values reach the disk when the operating system gets around to it, or at the latest on `flush`. After a crash, reopening keeps everything up to the last value that was written completely.
+pub struct DiskMemory<T> {
+    map: MmapMut,
+    header: Header,
+    value: PhantomData<T>,
+}
+
+impl<T: Serialize + DeserializeOwned> DiskMemory<T> {
+    /// Creates an empty ring buffer at {path}, replacing any existing file
+    pub fn create<P: AsRef<Path>>(path: P, capacity: usize, record_size: usize) -> Result<Self> {
+        ensure!(capacity > 0, "Disk memory needs a capacity of at least 1.");
+        ensure!(
+            record_size > 0 && record_size <= u32::MAX as usize,
+            "Record size needs to be between 1 and {} bytes, but is {record_size}.",
+            u32::MAX
+        );
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .with_context(|| format!("Unable to create disk memory at {}.", path.display()))?;
+        let header = Header {
+            record_size,
+            capacity,
+            count: 0,
+        };
+        file.set_len((2 * HEADER_SIZE + capacity * (RECORD_HEADER_SIZE + record_size)) as u64)
+            .context("Unable to allocate disk memory.")?;
+        // Safety: The file is only modified through this mapping while it exists. Concurrent modification by other processes is not supported
+        let map = unsafe { MmapMut::map_mut(&file) }.context("Unable to map disk memory.")?;
+        let mut memory = Self {
+            map,
+            header,
+            value: PhantomData,
+        };
+        memory.write_header();
+        memory.flush()?;
+        Ok(memory)
+    }
+
+    /// Opens an existing ring buffer at {path}, dropping values at its end that weren't written completely
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("Unable to open disk memory at {}.", path.display()))?;
+        // Safety: See `create`
+        let map = unsafe { MmapMut::map_mut(&file) }.context("Unable to map disk memory.")?;
+        ensure!(
+            map.len() >= 2 * HEADER_SIZE,
+            "{} is too short to be a disk memory.",
+            path.display()
+        );
+
+        let header = [0, HEADER_SIZE]
+            .iter()
+            .filter_map(|&start| Header::read(&map[start..start + HEADER_SIZE]))
+            .max_by_key(|header| header.count)
+            .with_context(|| format!("{} has no valid disk memory header.", path.display()))?;
+        ensure!(
+            map.len()
+                >= 2 * HEADER_SIZE + header.capacity * (RECORD_HEADER_SIZE + header.record_size),
+            "{} is shorter than its header says.",
+            path.display()
+        );
+
+        let mut memory = Self {
+            map,
+            header,
+            value: PhantomData,
+        };
+        // Only the latest {capacity} records can be checked. Beyond that, slots hold newer values
+        let oldest = memory.header.count.saturating_sub(memory.header.capacity);
+        while memory.header.count > oldest && memory.record(memory.header.count - 1).is_none() {
+            memory.header.count -= 1;
+        }
+        Ok(memory)
+    }
+
+    /// Adds a value, overwriting the oldest one if full. Fails if {value} doesn't fit into a record
+    pub fn push(&mut self, value: T) -> Result<()> {
+        let payload = bincode::serialize(&value).context("Unable to serialize value.")?;
+        ensure!(
+            payload.len() <= self.header.record_size,
+            "Value takes {} bytes, but records only hold {}.",
+            payload.len(),
+            self.header.record_size
+        );
+
+        let index = self.header.count;
+        let slot = self.slot_mut(index);
+        slot[8..16].copy_from_slice(&(index as u64).to_le_bytes());
+        slot[16..20].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        slot[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + payload.len()].copy_from_slice(&payload);
+        let checksum = checksum(&slot[8..RECORD_HEADER_SIZE + payload.len()]);
+        slot[..8].copy_from_slice(&checksum.to_le_bytes());
+
+        self.header.count += 1;
+        self.write_header();
+        Ok(())
+    }
+
+    /// Value at {index}, counting from the oldest one. None if out of range, or if the record is damaged
+    pub fn get(&self, index: usize) -> Option<T> {
+        (index < self.len()).then_some(())?;
+        self.record(self.header.count - self.len() + index)
+    }
+
+    /// Oldest to newest, skipping damaged records
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        (0..self.len()).filter_map(|index| self.get(index))
+    }
+
+    pub fn len(&self) -> usize {
+        self.header.count.min(self.header.capacity)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.header.count == 0
+    }
+
+    pub fn count(&self) -> usize {
+        self.header.count
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.header.capacity
+    }
+
+    /// Waits until everything pushed so far is on disk
+    pub fn flush(&self) -> Result<()> {
+        self.map.flush().context("Unable to flush disk memory.")
+    }
+
+    fn slot_range(&self, index: usize) -> std::ops::Range<usize> {
+        let slot_size = RECORD_HEADER_SIZE + self.header.record_size;
+        let start = 2 * HEADER_SIZE + (index % self.header.capacity) * slot_size;
+        start..start + slot_size
+    }
+
+    fn slot_mut(&mut self, index: usize) -> &mut [u8] {
+        let range = self.slot_range(index);
+        &mut self.map[range]
+    }
+
+    // Value number {index} of all values pushed so far, if its record is intact
+    fn record(&self, index: usize) -> Option<T> {
+        let slot = &self.map[self.slot_range(index)];
+        let length = u32::from_le_bytes(slot[16..20].try_into().ok()?) as usize;
+        let stored_index = u64::from_le_bytes(slot[8..16].try_into().ok()?) as usize;
+        let stored_checksum = u64::from_le_bytes(slot[..8].try_into().ok()?);
+        (length <= self.header.record_size
+            && stored_index == index
+            && stored_checksum == checksum(&slot[8..RECORD_HEADER_SIZE + length]))
+        .then_some(())?;
+        bincode::deserialize(&slot[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + length]).ok()
+    }
+
+    fn write_header(&mut self) {
+        let start = (self.header.count % 2) * HEADER_SIZE;
+        let header = self.header;
+        header.write(&mut self.map[start..start + HEADER_SIZE]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Vec3D;
+
+    fn path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("njord_{}_{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_disk_memory() {
+        let path = path("disk_memory");
+        let mut memory = DiskMemory::create(&path, 3, 64).unwrap();
+        for n in 0..5 {
+            memory.push((n, Vec3D::new(n, -n, 0))).unwrap();
+        }
+        assert_eq!(memory.len(), 3);
+        assert_eq!(memory.get(0), Some((2, Vec3D::new(2, -2, 0))));
+        assert_eq!(memory.get(3), None);
+        assert!(memory.push((0, Vec3D::default())).is_ok());
+        memory.flush().unwrap();
+        drop(memory);
+
+        let memory = DiskMemory::<(i32, Vec3D)>::open(&path).unwrap();
+        assert_eq!(memory.count(), 6);
+        assert_eq!(memory.iter().map(|(n, _)| n).collect::<Vec<_>>(), [3, 4, 0]);
+        std::fs::remove_file(&path).unwrap();
+
+        let mut memory = DiskMemory::create(&path, 1, 16).unwrap();
+        assert!(memory.push(vec![0_u8; 100]).is_err()); // Doesn't fit into a record
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_power_loss() {
+        let path = path("power_loss");
+        let mut memory = DiskMemory::create(&path, 4, 16).unwrap();
+        for n in 0..3_u64 {
+            memory.push(n).unwrap();
+        }
+        // The newest record only made it to disk partially
+        let start = memory.slot_range(2).start;
+        memory.map[start + RECORD_HEADER_SIZE] ^= 0xff;
+        memory.flush().unwrap();
+        drop(memory);
+
+        let memory = DiskMemory::<u64>::open(&path).unwrap();
+        assert_eq!(memory.count(), 2);
+        assert_eq!(memory.iter().collect::<Vec<_>>(), [0, 1]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}